@@ -0,0 +1,202 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Data providers for point clouds in S3 and GCS buckets, selected by 's3://bucket/octree' and
+//! 'gs://bucket/octree' locations once registered with a 'DataProviderFactory'.
+//!
+//! Credentials are taken from the usual AWS environment variables or profile. GCS is accessed
+//! through its S3 compatible API, so it needs HMAC keys in AWS_ACCESS_KEY_ID and
+//! AWS_SECRET_ACCESS_KEY. The block cache is configured by the environment variables
+//! OBJECT_STORE_BLOCK_SIZE, OBJECT_STORE_READ_AHEAD_BLOCKS and OBJECT_STORE_CACHE_SIZE_BYTES.
+
+use point_viewer::data_provider::{
+    DataProviderFactory, DataProviderFactoryResult, ObjectStore, ObjectStoreConfig,
+    ObjectStoreDataProvider, RangeFuture,
+};
+use point_viewer::errors::*;
+use rusoto_core::{Region, RusotoError};
+use rusoto_s3::{GetObjectError, GetObjectRequest, S3Client, S3};
+use std::env;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::runtime::Runtime;
+
+pub const S3_PREFIX: &str = "s3://";
+pub const GCS_PREFIX: &str = "gs://";
+
+const GCS_ENDPOINT: &str = "https://storage.googleapis.com";
+
+/// A bucket accessed through the S3 API.
+pub struct S3Store {
+    client: S3Client,
+    bucket: String,
+    // Data providers are also used from synchronous code, so requests run on their own runtime.
+    runtime: Arc<Runtime>,
+}
+
+impl S3Store {
+    pub fn new(region: Region, bucket: impl Into<String>) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()?;
+        Ok(S3Store {
+            client: S3Client::new(region),
+            bucket: bucket.into(),
+            runtime: Arc::new(runtime),
+        })
+    }
+}
+
+async fn get_object(
+    client: S3Client,
+    request: GetObjectRequest,
+    description: String,
+) -> Result<Vec<u8>> {
+    let output = match client.get_object(request).await {
+        Ok(output) => output,
+        Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => {
+            return Err(ErrorKind::NodeNotFound.into());
+        }
+        Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => {
+            return Err(ErrorKind::NodeNotFound.into());
+        }
+        // The range starts after the end of the object.
+        Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 416 => {
+            return Ok(Vec::new());
+        }
+        Err(err) => return Err(format!("Could not get {}: {}", description, err).into()),
+    };
+    let mut data = Vec::new();
+    if let Some(body) = output.body {
+        body.into_async_read().read_to_end(&mut data).await?;
+    }
+    Ok(data)
+}
+
+impl S3Store {
+    /// Starts the request on the runtime right away and returns its result.
+    fn fetch(
+        &self,
+        key: &str,
+        start: u64,
+        len: u64,
+    ) -> impl Future<Output = Result<Vec<u8>>> + Send + 'static {
+        let handle = if len == 0 {
+            None
+        } else {
+            let request = GetObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                // The range is inclusive.
+                range: Some(format!("bytes={}-{}", start, start + len - 1)),
+                ..Default::default()
+            };
+            let description = format!("{}/{}", self.bucket, key);
+            Some(
+                self.runtime
+                    .spawn(get_object(self.client.clone(), request, description)),
+            )
+        };
+        async move {
+            match handle {
+                Some(handle) => handle
+                    .await
+                    .map_err(|err| Error::from(format!("Request failed: {}", err)))?,
+                None => Ok(Vec::new()),
+            }
+        }
+    }
+}
+
+impl ObjectStore for S3Store {
+    fn get_range(&self, key: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+        futures::executor::block_on(self.fetch(key, start, len))
+    }
+
+    fn get_range_async(&self, key: &str, start: u64, len: u64) -> RangeFuture<'_> {
+        Box::pin(self.fetch(key, start, len))
+    }
+}
+
+fn split_bucket(location: &str) -> (&str, &str) {
+    match location.find('/') {
+        Some(i) => (&location[..i], &location[i + 1..]),
+        None => (location, ""),
+    }
+}
+
+fn config_from_env() -> ObjectStoreConfig {
+    let mut config = ObjectStoreConfig::default();
+    let var = |name: &str| env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+    if let Some(block_size) = var("OBJECT_STORE_BLOCK_SIZE") {
+        config.block_size = block_size.max(1);
+    }
+    if let Some(read_ahead_blocks) = var("OBJECT_STORE_READ_AHEAD_BLOCKS") {
+        config.read_ahead_blocks = read_ahead_blocks;
+    }
+    if let Some(cache_size_bytes) = var("OBJECT_STORE_CACHE_SIZE_BYTES") {
+        config.cache_size_bytes = cache_size_bytes;
+    }
+    config
+}
+
+/// Creates the data provider for 's3://bucket/octree'. The region is taken from AWS_REGION or
+/// AWS_DEFAULT_REGION.
+pub fn s3_data_provider(location: &str) -> DataProviderFactoryResult {
+    let (bucket, prefix) = split_bucket(location.trim_start_matches(S3_PREFIX));
+    let store = S3Store::new(Region::default(), bucket)?;
+    Ok(Box::new(ObjectStoreDataProvider::new(
+        Box::new(store),
+        prefix,
+        config_from_env(),
+    )))
+}
+
+/// Creates the data provider for 'gs://bucket/octree'.
+pub fn gcs_data_provider(location: &str) -> DataProviderFactoryResult {
+    let (bucket, prefix) = split_bucket(location.trim_start_matches(GCS_PREFIX));
+    let region = Region::Custom {
+        name: "auto".to_string(),
+        endpoint: GCS_ENDPOINT.to_string(),
+    };
+    let store = S3Store::new(region, bucket)?;
+    Ok(Box::new(ObjectStoreDataProvider::new(
+        Box::new(store),
+        prefix,
+        config_from_env(),
+    )))
+}
+
+/// Lets `factory` create data providers for 's3://' and 'gs://' locations.
+pub fn register_object_stores(factory: DataProviderFactory) -> DataProviderFactory {
+    factory
+        .register(S3_PREFIX, s3_data_provider)
+        .register(GCS_PREFIX, gcs_data_provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_bucket() {
+        assert_eq!(
+            split_bucket("bucket/some/octree"),
+            ("bucket", "some/octree")
+        );
+        assert_eq!(split_bucket("bucket"), ("bucket", ""));
+    }
+}
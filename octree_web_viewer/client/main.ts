@@ -17,13 +17,14 @@
 import * as THREE from 'three';
 import { GUI } from 'dat.gui';
 import { FirstPersonController } from './control';
-import { OctreeViewer } from './octree_viewer';
+import { COLOR_MAPS, COLOR_MODES, OctreeViewer } from './octree_viewer';
 
 class App {
     private camera: THREE.PerspectiveCamera;
     private scene: THREE.Scene;
     private controller: FirstPersonController;
-    private viewer: OctreeViewer;
+    // One viewer per point cloud, all drawn into the same scene.
+    private viewers: OctreeViewer[];
     private renderer: THREE.WebGLRenderer;
     private lastFrustumUpdateTime: number;
     private lastMoveTime: number;
@@ -31,8 +32,11 @@ class App {
     private octreeIdControl: dat.GUIController;
     private gui: dat.GUI;
     private guiRenderControls: dat.GUI;
-    public octreeId: string;  // octree identifier
+    private guiCloudControls: dat.GUI;
+    public octreeId: string;  // comma separated octree identifiers
     private renderArea: HTMLElement;
+    private pickedPoint: string;
+    private mouseDownPosition: THREE.Vector2;
 
     private fetchDefaultOctreeId(): Promise<string> {
         const request = new Request(
@@ -50,40 +54,90 @@ class App {
         return result;
     }
 
-    private initOctreeViewer(octreeId: string) {
-        this.viewer = new OctreeViewer(this.scene, () => {
+    private initOctreeViewers(octreeIds: string[]) {
+        this.viewers = octreeIds.map((octreeId) => new OctreeViewer(this.scene, () => {
             this.needsRender = true;
-        }, octreeId);
+        }, octreeId));
     }
 
+    // The render controls change the first viewer, and the others follow.
     private addControls() {
+        const first = this.viewers[0];
+        const uniformChanged = (name: string) => () => {
+            for (const viewer of this.viewers) {
+                viewer.material.uniforms[name].value = first.material.uniforms[name].value;
+            }
+            this.needsRender = true;
+        };
+        const propertyChanged = (name: 'maxLevelToDisplay' | 'compression' | 'colorMode' | 'colorMap') => () => {
+            for (const viewer of this.viewers) {
+                (viewer as any)[name] = first[name];
+            }
+            this.needsRender = true;
+        };
         this.guiRenderControls = this.gui.addFolder('Render controls');
         this.guiRenderControls
-            .add(this.viewer.material.uniforms['size'], 'value')
+            .add(first.material.uniforms['size'], 'value')
             .name('Point size')
-            .onChange(() => {
-                this.needsRender = true;
-            });
+            .onChange(uniformChanged('size'));
         this.guiRenderControls
-            .add(this.viewer.material.uniforms['alpha'], 'value', 0, 1)
+            .add(first.material.uniforms['alpha'], 'value', 0, 1)
             .name('Transparency')
             .onChange(() => {
-                this.viewer.alphaChanged();
-                this.needsRender = true;
+                uniformChanged('alpha')();
+                this.viewers.forEach((viewer) => viewer.alphaChanged());
             });
         this.guiRenderControls
-            .add(this.viewer.material.uniforms['gamma'], 'value')
+            .add(first.material.uniforms['gamma'], 'value')
             .name('Gamma')
-            .onChange(() => {
-                this.needsRender = true;
-            });
+            .onChange(uniformChanged('gamma'));
         this.guiRenderControls
-            .add(this.viewer, 'maxLevelToDisplay', 0, 7)
+            .add(first, 'maxLevelToDisplay', 0, 7)
             .name('Moving details')
             .step(1)
+            .onChange(propertyChanged('maxLevelToDisplay'));
+        this.guiRenderControls
+            .add(first, 'compression', ['none', 'delta'])
+            .name('Compression')
+            .onChange(propertyChanged('compression'));
+        this.guiRenderControls
+            .add(first, 'colorMode', COLOR_MODES)
+            .name('Color by')
             .onChange(() => {
-                this.needsRender = true;
+                propertyChanged('colorMode')();
+                this.viewers.forEach((viewer) => viewer.colorMappingChanged());
             });
+        this.guiRenderControls
+            .add(first, 'colorMap', COLOR_MAPS)
+            .name('Color map')
+            .onChange(() => {
+                propertyChanged('colorMap')();
+                this.viewers.forEach((viewer) => viewer.colorMappingChanged());
+            });
+
+        // Every point cloud can be hidden and moved, e.g. to align it with the others.
+        this.guiCloudControls = this.gui.addFolder('Point clouds');
+        for (const viewer of this.viewers) {
+            const folder = this.guiCloudControls.addFolder(viewer.octreeId);
+            const transform = { x: 0, y: 0, z: 0, yaw: 0 };
+            const transformChanged = () => {
+                viewer.setTransform(
+                    new THREE.Vector3(transform.x, transform.y, transform.z),
+                    THREE.MathUtils.degToRad(transform.yaw)
+                );
+                this.needsRender = true;
+            };
+            folder
+                .add(viewer, 'visible')
+                .name('Visible')
+                .onChange(() => {
+                    this.needsRender = true;
+                });
+            folder.add(transform, 'x').name('x').onFinishChange(transformChanged);
+            folder.add(transform, 'y').name('y').onFinishChange(transformChanged);
+            folder.add(transform, 'z').name('z').onFinishChange(transformChanged);
+            folder.add(transform, 'yaw', -180, 180).name('Yaw (degrees)').onFinishChange(transformChanged);
+        }
     }
 
     private getViewPortSize(): [number, number] {
@@ -125,6 +179,45 @@ class App {
         this.needsRender = true;
         this.lastFrustumUpdateTime = 0;
         this.lastMoveTime = 0;
+        this.renderer.domElement.addEventListener('mousedown', (event) => {
+            this.mouseDownPosition = new THREE.Vector2(event.clientX, event.clientY);
+        });
+        this.renderer.domElement.addEventListener('mouseup', (event) => this.onClick(event));
+    }
+
+    // A click without dragging inspects the point under the mouse.
+    private onClick(event: MouseEvent) {
+        const PICK_RADIUS_PIXELS = 3;
+        if (event.button !== 0 || this.mouseDownPosition === undefined ||
+            this.mouseDownPosition.distanceTo(new THREE.Vector2(event.clientX, event.clientY)) > 2) {
+            return;
+        }
+        const rect = this.renderer.domElement.getBoundingClientRect();
+        const ndc = new THREE.Vector2(
+            (event.clientX - rect.left) / rect.width * 2 - 1,
+            -(event.clientY - rect.top) / rect.height * 2 + 1
+        );
+        const raycaster = new THREE.Raycaster();
+        raycaster.setFromCamera(ndc, this.camera);
+        const angle = PICK_RADIUS_PIXELS * THREE.MathUtils.degToRad(this.camera.fov) / rect.height;
+        const picks = this.viewers
+            .filter((viewer) => viewer.visible)
+            .map((viewer) => viewer.pick(raycaster.ray, angle));
+        Promise.all(picks).then((hits: any[]) => {
+            // The closest hit of all point clouds.
+            const hit = hits
+                .filter((hit) => hit !== null)
+                .reduce((closest, hit) => (closest === null || hit.distance < closest.distance ? hit : closest), null);
+            if (hit === null) {
+                this.pickedPoint = 'none';
+                return;
+            }
+            const p = hit.position.map((v: number) => v.toFixed(2)).join(', ');
+            const attributes = Object.keys(hit.attributes)
+                .map((name) => `${name}: ${hit.attributes[name]}`);
+            this.pickedPoint = [`(${p})`, ...attributes].join('; ');
+            console.log('Picked point', hit);
+        });
     }
 
     private cleanup() {
@@ -140,6 +233,9 @@ class App {
         if (this.guiRenderControls) {
             this.gui.removeFolder(this.guiRenderControls);
         }
+        if (this.guiCloudControls) {
+            this.gui.removeFolder(this.guiCloudControls);
+        }
     }
 
     private resetOctree() {
@@ -147,7 +243,9 @@ class App {
         this.initCamera();
         this.initScene();
         this.initRenderer();
-        this.initOctreeViewer(this.octreeId);
+        this.initOctreeViewers(
+            this.octreeId.split(',').map((id) => id.trim()).filter((id) => id.length > 0)
+        );
         this.addControls();
     }
 
@@ -163,13 +261,18 @@ class App {
     public init() {
         this.renderArea = document.getElementById('renderArea');
         this.octreeId = "loading...";
+        this.pickedPoint = '';
         this.gui = new GUI();
 
         this.octreeIdControl =
             this.gui
                 .add(this, 'octreeId')
-                .name('Point Cloud ID')
+                .name('Point Cloud IDs')
                 .onFinishChange(this.run);
+        this.gui
+            .add(this, 'pickedPoint')
+            .name('Picked point')
+            .listen();
 
         // TODO(negin-z): error handling
         this.fetchDefaultOctreeId()
@@ -194,11 +297,11 @@ class App {
         const time = performance.now();
         if (this.controller.update()) {
             this.lastMoveTime = time;
-            this.viewer.setMoving(true);
+            this.viewers.forEach((viewer) => viewer.setMoving(true));
             this.needsRender = true;
         }
         if (time - this.lastMoveTime > 250) {
-            this.viewer.setMoving(false);
+            this.viewers.forEach((viewer) => viewer.setMoving(false));
             this.needsRender = true;
         }
         if (this.lastFrustumUpdateTime <= this.lastMoveTime &&
@@ -209,11 +312,13 @@ class App {
                 this.camera.projectionMatrix,
                 this.camera.matrixWorldInverse
             );
-            this.viewer.frustumChanged(
-                matrix,
-                this.renderer.getContext().canvas.width,
-                this.renderer.getContext().canvas.height,
-            );
+            for (const viewer of this.viewers) {
+                viewer.frustumChanged(
+                    matrix,
+                    this.renderer.getContext().canvas.width,
+                    this.renderer.getContext().canvas.height,
+                );
+            }
         }
 
         if (this.needsRender) {
@@ -24,16 +24,73 @@ uniform float gamma;
 uniform float alpha;
 uniform float edgeLength;
 uniform vec3 min;
+// The index of the mode in COLOR_MODES.
+uniform int colorMode;
+// The index of the map in COLOR_MAPS.
+uniform int colorMap;
+// The values mapped to the ends of the color map.
+uniform vec2 valueRange;
 
 attribute vec3 color;
+// The attribute of the color mode, zero if the octree has none.
+attribute float value;
 
 varying vec4 v_color;
 
+// Polynomial approximations of the color maps, see
+// https://www.shadertoy.com/view/WlfXRN and
+// https://ai.googleblog.com/2019/08/turbo-improved-rainbow-colormap-for.html
+vec3 viridis(float t) {
+  const vec3 c0 = vec3(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+  const vec3 c1 = vec3(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+  const vec3 c2 = vec3(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+  const vec3 c3 = vec3(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+  const vec3 c4 = vec3(6.228269936347081, 14.17993336680509, 56.69055260068105);
+  const vec3 c5 = vec3(4.776384997670288, -13.74514537774601, -65.35303263337234);
+  const vec3 c6 = vec3(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+  return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+vec3 turbo(float t) {
+  const vec4 red4 = vec4(0.13572138, 4.61539260, -42.66032258, 132.13108234);
+  const vec4 green4 = vec4(0.09140261, 2.19418839, 4.84296658, -14.18503333);
+  const vec4 blue4 = vec4(0.10667330, 12.64194608, -60.58204836, 110.36276771);
+  const vec2 red2 = vec2(-152.94239396, 59.28637943);
+  const vec2 green2 = vec2(4.27729857, 2.82956604);
+  const vec2 blue2 = vec2(-89.90310912, 27.34824973);
+  vec4 v4 = vec4(1., t, t * t, t * t * t);
+  vec2 v2 = v4.zw * v4.z;
+  return vec3(dot(v4, red4) + dot(v2, red2), dot(v4, green4) + dot(v2, green2),
+              dot(v4, blue4) + dot(v2, blue2));
+}
+
+// Spreads the hues of neighboring labels by the golden ratio, so that they
+// are easy to tell apart.
+vec3 labelColor(float label) {
+  float hue = fract(label * 0.61803399);
+  vec3 rgb = clamp(abs(mod(hue * 6. + vec3(0., 4., 2.), 6.) - 3.) - 1., 0., 1.);
+  return mix(vec3(1.), rgb, 0.8);
+}
+
+float normalized(float v) {
+  return clamp((v - valueRange.x) / max(valueRange.y - valueRange.x, 1e-6), 0., 1.);
+}
+
 void main() {
-  vec3 corrected_color = pow(color / 255., vec3(1.0 / gamma));
+  vec3 worldPosition = position * edgeLength + min;
+  vec3 corrected_color;
+  if (colorMode == 1) {
+    corrected_color = vec3(pow(normalized(value), 1.0 / gamma));
+  } else if (colorMode == 2) {
+    float t = normalized(worldPosition.z);
+    corrected_color = colorMap == 0 ? viridis(t) : turbo(t);
+  } else if (colorMode == 3) {
+    corrected_color = labelColor(value);
+  } else {
+    corrected_color = pow(color / 255., vec3(1.0 / gamma));
+  }
   v_color = vec4(corrected_color, alpha);
-  gl_Position = projectionMatrix * modelViewMatrix *
-    vec4(position * edgeLength + min, 1.0);
+  gl_Position = projectionMatrix * modelViewMatrix * vec4(worldPosition, 1.0);
   gl_PointSize = size;
 }
 `;
@@ -68,22 +125,90 @@ function matrixToString(m: THREE.Matrix4): string {
     ].join(',');
 }
 
+// The ways to color points, in the order of the colorMode uniform.
+export const COLOR_MODES = ['rgb', 'intensity', 'height', 'classification'];
+export const COLOR_MAPS = ['viridis', 'turbo'];
+
+// The attribute each color mode needs in addition to position and color.
+const COLOR_MODE_ATTRIBUTES: { [colorMode: string]: string } = {
+    intensity: 'intensity',
+    classification: 'classification',
+};
+
 class NodeRenderData {
     constructor(
         public min: THREE.Vector3,
         public edgeLength: number,
         public position: Float32Array | Uint16Array | Uint8Array,
         public normalizePosition: boolean,
-        public color: Uint8Array
+        public color: Uint8Array,
+        public attributes: Map<string, Uint8Array>,
+        // The attributes with a single component, converted for drawing.
+        public values: Map<string, Float32Array>
     ) { }
 }
 
+// Bytes per point for each attribute data type, keyed by the values of the
+// AttributeDataType enum in proto.proto.
+const BYTES_PER_POINT: { [dataType: number]: number } = {
+    1: 1, 2: 2, 3: 4, 4: 8, 6: 1, 7: 2, 8: 4, 9: 8, 11: 4, 12: 8, 27: 3, 38: 24,
+};
+
+// Undoes the delta coding of the server: every value is stored as the
+// difference to the same component of the previous point. Typed arrays wrap
+// around just like the server does.
+function deltaDecode(values: Uint8Array | Uint16Array, numComponents: number) {
+    for (let i = numComponents; i < values.length; i++) {
+        values[i] += values[i - numComponents];
+    }
+}
+
+// Converts the values of an attribute with a single component, or returns
+// undefined for other attributes. 64 bit integers are not supported.
+function toFloat32(
+    data: ArrayBuffer,
+    offset: number,
+    numPoints: number,
+    dataType: number
+): Float32Array | undefined {
+    switch (dataType) {
+        case 1:
+            return Float32Array.from(new Uint8Array(data, offset, numPoints));
+        case 2:
+            return Float32Array.from(new Uint16Array(data, offset, numPoints));
+        case 3:
+            return Float32Array.from(new Uint32Array(data, offset, numPoints));
+        case 6:
+            return Float32Array.from(new Int8Array(data, offset, numPoints));
+        case 7:
+            return Float32Array.from(new Int16Array(data, offset, numPoints));
+        case 8:
+            return Float32Array.from(new Int32Array(data, offset, numPoints));
+        case 11:
+            return new Float32Array(data, offset, numPoints);
+        case 12:
+            return Float32Array.from(new Float64Array(data, offset, numPoints));
+        default:
+            return undefined;
+    }
+}
+
+function inflate(data: ArrayBuffer): Promise<ArrayBuffer> {
+    // The zlib format is called 'deflate' by the Compression Streams API.
+    const stream = new Blob([data])
+        .stream()
+        .pipeThrough(new (window as any).DecompressionStream('deflate'));
+    return new Response(stream).arrayBuffer();
+}
+
 class NodeLoader {
     public load(
-        scene: THREE.Scene,
+        scene: THREE.Object3D,
         material: THREE.ShaderMaterial,
         nodes: NodeData[],
-        octreeId: string
+        octreeId: string,
+        attributes: string[] = [],
+        compression: string = 'none'
     ): Promise<void> {
         let query: string[] = [];
 
@@ -92,7 +217,12 @@ class NodeLoader {
         }
         const headers = new Headers();
         headers.append('Content-Type', 'application/json; charset=UTF-8');
-        const request = new Request(`/nodes_data/${octreeId}/`, {
+        let parameters = [`compression=${compression}`];
+        if (attributes.length > 0) {
+            parameters.push(`attributes=${attributes.join(',')}`);
+        }
+        const url = `/nodes_data/${octreeId}/?${parameters.join('&')}`;
+        const request = new Request(url, {
             method: 'POST',
             body: '[' + query.join(',') + ']',
             headers: headers,
@@ -102,6 +232,7 @@ class NodeLoader {
         return window
             .fetch(request)
             .then((data) => data.arrayBuffer())
+            .then((data) => (compression === 'delta' ? inflate(data) : data))
             .then((data) => {
                 let view = new DataView(data);
                 let currentEntry = 0;
@@ -157,23 +288,51 @@ class NodeLoader {
                         default:
                             console.log('Invalid bytesPerCoordinate: ', bytesPerCoordinate);
                     }
+                    if (compression === 'delta' && bytesPerCoordinate <= 2) {
+                        deltaDecode(position as Uint8Array | Uint16Array, 3);
+                    }
                     numBytesRead += numPoints * bytesPerCoordinate * 3;
                     if (numBytesRead % 8 != 0) {
                         numBytesRead += 8 - numBytesRead % 8;
                     }
 
                     let color = new Uint8Array(data, numBytesRead, numPoints * 3);
+                    if (compression === 'delta') {
+                        deltaDecode(color, 3);
+                    }
                     numBytesRead += numPoints * 3;
                     if (numBytesRead % 8 != 0) {
                         numBytesRead += 8 - numBytesRead % 8;
                     }
 
+                    let attributeData = new Map<string, Uint8Array>();
+                    let attributeValues = new Map<string, Float32Array>();
+                    for (const attribute of attributes) {
+                        const dataType = view.getUint8(numBytesRead);
+                        numBytesRead += 1;
+                        if (numBytesRead % 8 != 0) {
+                            numBytesRead += 8 - numBytesRead % 8;
+                        }
+                        const numBytes = numPoints * BYTES_PER_POINT[dataType];
+                        attributeData.set(attribute, new Uint8Array(data, numBytesRead, numBytes));
+                        const values = toFloat32(data, numBytesRead, numPoints, dataType);
+                        if (values !== undefined) {
+                            attributeValues.set(attribute, values);
+                        }
+                        numBytesRead += numBytes;
+                        if (numBytesRead % 8 != 0) {
+                            numBytesRead += 8 - numBytesRead % 8;
+                        }
+                    }
+
                     let render_data = new NodeRenderData(
                         new THREE.Vector3(min_x, min_y, min_z),
                         edgeLength,
                         position,
                         normalizePosition,
-                        color
+                        color,
+                        attributeData,
+                        attributeValues
                     );
                     let node = nodes[currentEntry];
                     node.onDataLoaded(scene, material, render_data);
@@ -185,22 +344,39 @@ class NodeLoader {
 
 class NodeData {
     public threePoints: THREE.Points;
+    // Set when the node changed on the server. The old points stay visible
+    // until the new ones arrive.
+    public stale: boolean;
 
     constructor(public nodeName: string) {
         this.threePoints = undefined;
+        this.stale = false;
     }
 
     public isUpToDate(): boolean {
-        return this.threePoints !== undefined;
+        return this.threePoints !== undefined && !this.stale;
+    }
+
+    public clear(scene: THREE.Object3D) {
+        if (this.threePoints !== undefined) {
+            scene.remove(this.threePoints);
+            this.threePoints.geometry.dispose();
+            this.threePoints = undefined;
+        }
+        this.stale = false;
     }
 
     public onDataLoaded(
-        scene: THREE.Scene,
+        scene: THREE.Object3D,
         commonMaterial: THREE.ShaderMaterial,
         nodeRenderData: NodeRenderData
     ) {
+        if (this.isUpToDate()) {
+            return;
+        }
+        this.clear(scene);
         // If this node contains no points.
-        if (nodeRenderData.position.length === 0 || this.isUpToDate()) {
+        if (nodeRenderData.position.length === 0) {
             return;
         }
 
@@ -218,6 +394,10 @@ class NodeData {
             'color',
             new THREE.BufferAttribute(nodeRenderData.color, 3)
         );
+        // At most the attribute of the color mode was requested.
+        for (const values of nodeRenderData.values.values()) {
+            geometry.setAttribute('value', new THREE.BufferAttribute(values, 1));
+        }
 
         // THREE can no longer figure out the bounding box or the bounding sphere of
         // this node, since the 'position' attribute does not contain it. So we
@@ -247,6 +427,9 @@ class NodeData {
             size: commonMaterial.uniforms['size'],
             alpha: commonMaterial.uniforms['alpha'],
             gamma: commonMaterial.uniforms['gamma'],
+            colorMode: commonMaterial.uniforms['colorMode'],
+            colorMap: commonMaterial.uniforms['colorMap'],
+            valueRange: commonMaterial.uniforms['valueRange'],
         };
         this.threePoints = new THREE.Points(geometry, material);
         scene.add(this.threePoints);
@@ -258,36 +441,92 @@ export class OctreeViewer {
     // material.size. If DAT supports callbacks, we can encapsulate this nicer.
     public material: THREE.ShaderMaterial;
     public maxLevelToDisplay: number;
+    // 'delta' trades server and client time for less bandwidth, which pays
+    // off for remote viewers.
+    public compression: string;
+    // One of COLOR_MODES and COLOR_MAPS.
+    public colorMode: string;
+    public colorMap: string;
 
     private loadedData: { [key: string]: NodeData } = {};
     private nodeLoader: NodeLoader;
     private batches: NodeData[][] = [];
     private currentlyLoading: number;
     private useTransparency: boolean;
-
-
-    constructor(private scene: THREE.Scene, private onNewNodeData: () => void, private octreeId: string) {
+    private lastFrustum: { matrix: THREE.Matrix4, width: number, height: number };
+    // The points are added to this group, which places the octree in the scene.
+    private group: THREE.Group;
+    // The attribute loaded with the nodes for the color mode, if any.
+    private valueAttribute: string | undefined;
+    // The height range and the ranges of the attributes of the octree.
+    private ranges: { [name: string]: [number, number] } = {};
+
+    constructor(scene: THREE.Scene, private onNewNodeData: () => void, public octreeId: string) {
+        this.group = new THREE.Group();
+        scene.add(this.group);
         this.material = new THREE.ShaderMaterial({
             uniforms: {
                 size: { value: 2 },
                 alpha: { value: 1 },
                 gamma: { value: 1 },
+                colorMode: { value: 0 },
+                colorMap: { value: 0 },
+                valueRange: { value: new THREE.Vector2(0, 255) },
             },
             vertexShader: VERTEX_SHADER,
             fragmentShader: FRAGMENT_SHADER,
         });
         this.useTransparency = false;
         this.maxLevelToDisplay = 3;
+        this.compression = 'none';
+        this.colorMode = 'rgb';
+        this.colorMap = 'viridis';
+        this.valueAttribute = undefined;
 
         this.nodeLoader = new NodeLoader();
         this.currentlyLoading = 0;
+
+        // The server pushes the ids of the nodes that changed whenever the
+        // octree is updated on disk.
+        const updates = new EventSource(`/updates/${octreeId}/`);
+        updates.onmessage = (event) => this.nodesChanged(JSON.parse(event.data));
+
+        window
+            .fetch(new Request(`/attribute_ranges/${octreeId}/`, { credentials: 'same-origin' }))
+            .then((data) => data.json())
+            .then((ranges: any) => {
+                this.ranges = ranges.attributes;
+                this.ranges['height'] = [ranges.bounding_box.min[2], ranges.bounding_box.max[2]];
+                this.colorMappingChanged();
+            });
+    }
+
+    public colorMappingChanged() {
+        const uniforms = this.material.uniforms;
+        uniforms['colorMode'].value = COLOR_MODES.indexOf(this.colorMode);
+        uniforms['colorMap'].value = COLOR_MAPS.indexOf(this.colorMap);
+        const rangeName = COLOR_MODE_ATTRIBUTES[this.colorMode] || 'height';
+        // Without a known range, we assume 8 bit values.
+        const [min, max] = this.ranges[rangeName] || [0, 255];
+        uniforms['valueRange'].value.set(min, max);
+
+        // Attributes without a range were not in the input data, so there is nothing to load.
+        let valueAttribute = COLOR_MODE_ATTRIBUTES[this.colorMode];
+        if (this.ranges[valueAttribute] === undefined) {
+            valueAttribute = undefined;
+        }
+        if (valueAttribute !== this.valueAttribute) {
+            this.valueAttribute = valueAttribute;
+            // The loaded nodes stay visible until they arrive with the new attribute.
+            this.nodesChanged(Object.keys(this.loadedData));
+        }
     }
 
     public alphaChanged() {
         let newUseTransparency = this.material.uniforms['alpha'].value < 1;
         if (newUseTransparency != this.useTransparency) {
             this.material.transparent = newUseTransparency;
-            this.scene.traverse(function (node) {
+            this.group.traverse(function (node) {
                 if (node instanceof THREE.Points && node.material instanceof THREE.ShaderMaterial) {
                     node.material.transparent = newUseTransparency;
                 }
@@ -296,11 +535,43 @@ export class OctreeViewer {
         this.useTransparency = newUseTransparency;
     }
 
+    // Whether the points of this octree are shown.
+    public get visible(): boolean {
+        return this.group.visible;
+    }
+
+    public set visible(visible: boolean) {
+        this.group.visible = visible;
+        // The visible nodes are not updated while the octree is hidden.
+        this.transformChanged();
+    }
+
+    // Places the octree in the scene, rotated by 'yaw' radians around the z axis.
+    public setTransform(position: THREE.Vector3, yaw: number) {
+        this.group.position.copy(position);
+        this.group.rotation.set(0, 0, yaw);
+        this.transformChanged();
+    }
+
+    private transformChanged() {
+        this.group.updateMatrixWorld(true);
+        if (this.lastFrustum !== undefined) {
+            const { matrix, width, height } = this.lastFrustum;
+            this.frustumChanged(matrix, width, height);
+        }
+    }
+
+    // 'matrix' projects from the scene, the server expects a projection from the octree.
     public frustumChanged(matrix: THREE.Matrix4, width: number, height: number) {
+        this.lastFrustum = { matrix: matrix.clone(), width: width, height: height };
+        if (!this.group.visible) {
+            return;
+        }
+        const octreeMatrix = new THREE.Matrix4().multiplyMatrices(matrix, this.group.matrixWorld);
         // ThreeJS is column major.
         const request = new Request(
             `/visible_nodes/${this.octreeId}/?width=${width}&height=${height}&matrix=${matrixToString(
-                matrix
+                octreeMatrix
             )}`,
             {
                 method: 'GET',
@@ -316,6 +587,33 @@ export class OctreeViewer {
             });
     }
 
+    // Returns the point closest to the origin of the ray within 'angle' radians of it, or null.
+    public pick(ray: THREE.Ray, angle: number): Promise<any> {
+        const octreeFromScene = new THREE.Matrix4().getInverse(this.group.matrixWorld);
+        const octreeRay = ray.clone().applyMatrix4(octreeFromScene);
+        const o = octreeRay.origin;
+        const d = octreeRay.direction;
+        const request = new Request(
+            `/pick/${this.octreeId}/?origin=${o.x},${o.y},${o.z}&direction=${d.x},${d.y},${d.z}&angle=${angle}`,
+            {
+                method: 'GET',
+                credentials: 'same-origin',
+            }
+        );
+        return window
+            .fetch(request)
+            .then((data) => data.json())
+            .then((hit: any) => {
+                if (hit !== null) {
+                    const [x, y, z] = hit.position;
+                    const p = new THREE.Vector3(x, y, z).applyMatrix4(this.group.matrixWorld);
+                    // The transform is rigid, so the distances stay the same.
+                    hit.position = [p.x, p.y, p.z];
+                }
+                return hit;
+            });
+    }
+
     public setMoving(moving: boolean) {
         for (const nodeId of Object.keys(this.loadedData)) {
             const threePoints = this.loadedData[nodeId].threePoints;
@@ -330,8 +628,32 @@ export class OctreeViewer {
         }
     }
 
+    private nodesChanged(nodeIds: string[]) {
+        for (const nodeId of nodeIds) {
+            const node = this.loadedData[nodeId];
+            if (node !== undefined) {
+                node.stale = true;
+            }
+        }
+        // Nodes might have been added or removed, so we ask for the visible
+        // nodes again. Only the stale and new ones are fetched.
+        if (this.lastFrustum !== undefined) {
+            const { matrix, width, height } = this.lastFrustum;
+            this.frustumChanged(matrix, width, height);
+        }
+    }
+
     private nodesUpdate(nodeIds: string[]) {
         const start = performance.now();
+        // Stale nodes that are no longer visible, e.g. because they were
+        // deleted, would otherwise keep showing their old points.
+        const visible = new Set(nodeIds);
+        for (const nodeId of Object.keys(this.loadedData)) {
+            const node = this.loadedData[nodeId];
+            if (node.stale && !visible.has(nodeId)) {
+                node.clear(this.group);
+            }
+        }
         this.batches = [];
         let currentBatch: NodeData[] = [];
         for (let nodeId of nodeIds) {
@@ -359,7 +681,14 @@ export class OctreeViewer {
         }
         this.currentlyLoading += 1;
         this.nodeLoader
-            .load(this.scene, this.material, this.batches.shift(), this.octreeId)
+            .load(
+                this.group,
+                this.material,
+                this.batches.shift(),
+                this.octreeId,
+                this.valueAttribute === undefined ? [] : [this.valueAttribute],
+                this.compression
+            )
             .then(() => {
                 this.currentlyLoading -= 1;
                 this.onNewNodeData();
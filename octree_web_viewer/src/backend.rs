@@ -2,8 +2,13 @@ use crate::backend_error::PointsViewerError;
 use crate::state::AppState;
 use actix_web::{dev::BodyEncoding, http::ContentEncoding, web, HttpResponse};
 use byteorder::{LittleEndian, WriteBytesExt};
-use nalgebra::Matrix4;
-use point_viewer::octree::{self, Octree};
+use futures::StreamExt;
+use json::JsonValue;
+use nalgebra::{Matrix4, Point3, Vector3};
+use point_viewer::attributes::AttributeData;
+use point_viewer::geometry::{Frustum, PickRadius, Ray};
+use point_viewer::iterator::PointCloud;
+use point_viewer::octree::{self, NodeCompression, Octree, Viewport};
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -12,6 +17,58 @@ pub struct Info {
     matrix: String,
 }
 
+#[derive(Deserialize)]
+pub struct NodesForViewQuery {
+    /// Comma separated entries of the projection matrix, column major.
+    matrix: String,
+    /// The size of the viewport in pixels.
+    width: u32,
+    height: u32,
+    /// The maximum number of points to select, by default all visible points.
+    point_budget: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct NodesDataQuery {
+    /// Comma separated names of attributes to send in addition to position and color.
+    attributes: Option<String>,
+    /// How to compress the reply, 'none' (the default) or 'delta'.
+    compression: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PickQuery {
+    /// Comma separated coordinates of the origin of the ray.
+    origin: String,
+    /// Comma separated coordinates of the direction of the ray.
+    direction: String,
+    /// How far along the ray points are picked, by default the whole point cloud.
+    max_distance: Option<f64>,
+    /// The angle in radians around the ray within which points are picked.
+    angle: Option<f64>,
+    /// Comma separated names of attributes to return for the picked point.
+    attributes: Option<String>,
+}
+
+fn parse_matrix(s: &str) -> Result<Matrix4<f64>, PointsViewerError> {
+    // Entries are column major.
+    let e: Vec<f64> = s
+        .split(',')
+        .map(|s| s.parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| PointsViewerError::BadRequest(format!("Could not parse matrix '{}'.", s)))?;
+    // matrix size check
+    if e.len() != 16 {
+        return Err(PointsViewerError::BadRequest(
+            "Parsing Error: Expected matrix with 16 elements".to_string(),
+        ));
+    }
+    Ok(Matrix4::new(
+        e[0], e[1], e[2], e[3], e[4], e[5], e[6], e[7], e[8], e[9], e[10], e[11], e[12], e[13],
+        e[14], e[15],
+    ))
+}
+
 /// Method that returns visible nodes
 pub fn get_visible_nodes(
     (octree_id, state, matrix_query): (
@@ -23,27 +80,9 @@ pub fn get_visible_nodes(
     match get_octree_from_state(&octree_id.into_inner(), &state) {
         Err(err) => HttpResponse::from_error(err.into()),
         Ok(octree) => {
-            let matrix = {
-                // Entries are column major.
-                let e: Vec<f64> = matrix_query
-                    .matrix
-                    .split(',')
-                    .map(|s| s.parse::<f64>().unwrap())
-                    .collect();
-                // matrix size check
-                if 16 == e.len() {
-                    Matrix4::new(
-                        e[0], e[1], e[2], e[3], e[4], e[5], e[6], e[7], e[8], e[9], e[10], e[11],
-                        e[12], e[13], e[14], e[15],
-                    )
-                } else {
-                    return HttpResponse::from_error(
-                        PointsViewerError::BadRequest(
-                            "Parsing Error: Expected matrix with 16 elements".to_string(),
-                        )
-                        .into(),
-                    );
-                }
+            let matrix = match parse_matrix(&matrix_query.matrix) {
+                Ok(matrix) => matrix,
+                Err(err) => return HttpResponse::from_error(err.into()),
             };
 
             let visible_nodes = octree.get_visible_nodes(&matrix);
@@ -63,6 +102,157 @@ pub fn get_visible_nodes(
     }
 }
 
+/// Returns the nodes to render for a view within a point budget, the largest on screen first, as
+/// JSON objects with the node id, its number of points and the point size in pixels that closes
+/// the gaps between its points.
+pub fn get_nodes_for_view(
+    (octree_id, state, query): (
+        web::Path<String>,
+        web::Data<Arc<AppState>>,
+        web::Query<NodesForViewQuery>,
+    ),
+) -> HttpResponse {
+    let octree = match get_octree_from_state(&octree_id.into_inner(), &state) {
+        Ok(octree) => octree,
+        Err(err) => return HttpResponse::from_error(err.into()),
+    };
+    let frustum = match parse_matrix(&query.matrix).map(Frustum::from_matrix4) {
+        Ok(Some(frustum)) => frustum,
+        Ok(None) => {
+            return HttpResponse::from_error(
+                PointsViewerError::BadRequest("Invalid projection matrix.".to_string()).into(),
+            );
+        }
+        Err(err) => return HttpResponse::from_error(err.into()),
+    };
+    let viewport = Viewport::new(query.width, query.height);
+    let point_budget = query.point_budget.unwrap_or(usize::MAX);
+    let reply: Vec<JsonValue> = octree
+        .select_nodes_for_view(&frustum, viewport, point_budget)
+        .into_iter()
+        .map(|lod_node| {
+            let mut node = JsonValue::new_object();
+            node["id"] = lod_node.id.to_string().into();
+            node["num_points"] = lod_node.num_points.into();
+            node["point_size"] = lod_node.point_size.into();
+            node
+        })
+        .collect();
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(JsonValue::from(reply).dump())
+}
+
+/// Returns the bounding box of the octree and the ranges of its attributes as JSON, so that
+/// clients can scale color maps to them. Only attributes with a known range are listed.
+pub fn get_attribute_ranges(
+    (octree_id, state): (web::Path<String>, web::Data<Arc<AppState>>),
+) -> HttpResponse {
+    let octree = match get_octree_from_state(&octree_id.into_inner(), &state) {
+        Ok(octree) => octree,
+        Err(err) => return HttpResponse::from_error(err.into()),
+    };
+    let bounding_box = octree.bounding_box();
+    let (min, max) = (bounding_box.min(), bounding_box.max());
+    let mut reply = JsonValue::new_object();
+    reply["bounding_box"]["min"] = vec![min.x, min.y, min.z].into();
+    reply["bounding_box"]["max"] = vec![max.x, max.y, max.z].into();
+    let mut attributes = JsonValue::new_object();
+    for (name, range) in octree.attribute_ranges().iter() {
+        attributes[name] = vec![range.lower_bound(), range.upper_bound()].into();
+    }
+    reply["attributes"] = attributes;
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(reply.dump())
+}
+
+fn parse_vector(s: &str) -> Result<Vector3<f64>, PointsViewerError> {
+    let e: Vec<f64> = s
+        .split(',')
+        .map(|s| s.parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| PointsViewerError::BadRequest(format!("Could not parse vector '{}'.", s)))?;
+    if e.len() != 3 {
+        return Err(PointsViewerError::BadRequest(
+            "Parsing Error: Expected vector with 3 elements".to_string(),
+        ));
+    }
+    Ok(Vector3::new(e[0], e[1], e[2]))
+}
+
+/// Converts the single value of a picked point's attribute.
+fn attribute_to_json(data: &AttributeData) -> JsonValue {
+    match data {
+        AttributeData::U8(v) => v[0].into(),
+        AttributeData::U16(v) => v[0].into(),
+        AttributeData::U32(v) => v[0].into(),
+        AttributeData::U64(v) => v[0].into(),
+        AttributeData::I8(v) => v[0].into(),
+        AttributeData::I16(v) => v[0].into(),
+        AttributeData::I32(v) => v[0].into(),
+        AttributeData::I64(v) => v[0].into(),
+        AttributeData::F32(v) => v[0].into(),
+        AttributeData::F64(v) => v[0].into(),
+        AttributeData::U8Vec3(v) => vec![v[0].x, v[0].y, v[0].z].into(),
+        AttributeData::F64Vec3(v) => vec![v[0].x, v[0].y, v[0].z].into(),
+    }
+}
+
+/// Returns the point closest to the camera along a ray, e.g. through the pixel a user clicked
+/// on, as JSON with its position, distance and attributes. Returns null if no point is hit.
+pub fn pick(
+    (octree_id, state, query): (
+        web::Path<String>,
+        web::Data<Arc<AppState>>,
+        web::Query<PickQuery>,
+    ),
+) -> HttpResponse {
+    let octree = match get_octree_from_state(&octree_id.into_inner(), &state) {
+        Ok(octree) => octree,
+        Err(err) => return HttpResponse::from_error(err.into()),
+    };
+    let (origin, direction) = match (parse_vector(&query.origin), parse_vector(&query.direction)) {
+        (Ok(origin), Ok(direction)) => (Point3::from(origin), direction),
+        (Err(err), _) | (_, Err(err)) => return HttpResponse::from_error(err.into()),
+    };
+    let max_distance = query.max_distance.unwrap_or_else(|| {
+        let bounding_box = octree.bounding_box();
+        nalgebra::distance(&origin, &bounding_box.center()) + bounding_box.diag().norm()
+    });
+    // By default, about the size of a few pixels.
+    let radius = PickRadius::Angular(query.angle.unwrap_or(0.005));
+    let attributes: Vec<&str> = match &query.attributes {
+        Some(attributes) => attributes.split(',').filter(|a| !a.is_empty()).collect(),
+        None => Vec::new(),
+    };
+    let ray = Ray::new(origin, direction, max_distance);
+    let hit = match octree.pick(&ray, radius, &attributes) {
+        Ok(hit) => hit,
+        Err(err) => {
+            return HttpResponse::from_error(PointsViewerError::BadRequest(err.to_string()).into());
+        }
+    };
+    let reply = match hit {
+        None => JsonValue::Null,
+        Some(hit) => {
+            let mut reply = JsonValue::new_object();
+            let p = hit.position;
+            reply["position"] = vec![p.x, p.y, p.z].into();
+            reply["distance"] = hit.distance.into();
+            let mut attributes = JsonValue::new_object();
+            for (name, data) in &hit.attributes {
+                attributes[name.as_str()] = attribute_to_json(data);
+            }
+            reply["attributes"] = attributes;
+            reply
+        }
+    };
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(reply.dump())
+}
+
 // Javascript requires its arrays to be padded to 8 bytes.
 fn pad(input: &mut Vec<u8>) {
     let pad = input.len() % 8;
@@ -86,20 +276,22 @@ fn get_octree_from_state(
     })
 }
 
-/// Asynchronous Handler to get Node Data
-pub async fn get_nodes_data(
-    (octree_id, state, nodes): (
-        web::Path<String>,
-        web::Data<Arc<AppState>>,
-        web::Json<Vec<String>>,
-    ),
-) -> HttpResponse {
-    let start = time::Instant::now();
-    let data: Vec<String> = web::Json::into_inner(nodes);
-    let nodes_to_load = data
-        .into_iter()
-        .map(|e| octree::NodeId::from_str(e.as_str()).unwrap());
+/// The encoded nodes of 'get_nodes_data', with what was read for the log.
+struct NodesData {
+    reply_blob: Vec<u8>,
+    num_bytes_uncompressed: usize,
+    num_nodes_fetched: usize,
+    num_points: i64,
+}
 
+/// Reads the nodes and encodes them for the client. Reading blocks, e.g. on the requests to an
+/// object store, so 'get_nodes_data' runs this on the thread pool of 'web::block'.
+fn read_nodes_data(
+    octree: &Octree,
+    nodes_to_load: Vec<octree::NodeId>,
+    attributes: &[&str],
+    compression: NodeCompression,
+) -> Result<NodesData, PointsViewerError> {
     // So this is godawful: We need to get data to the GPU without JavaScript herp-derping with
     // it - because that will stall interaction. The straight forward approach would be to ship
     // json with base64 encoded values - unfortunately base64 decoding in JavaScript yields a
@@ -111,21 +303,13 @@ pub async fn get_nodes_data(
 
     let mut num_nodes_fetched = 0;
     let mut num_points = 0;
-    let octree: Arc<octree::Octree> =
-        get_octree_from_state(&octree_id.into_inner(), &state).unwrap();
     for node_id in nodes_to_load {
-        let mut node_data = match octree.get_node_data(&node_id) {
-            Ok(node_data) => node_data,
-            Err(_) => {
-                return HttpResponse::from_error(
-                    crate::backend_error::PointsViewerError::NotFound(format!(
-                        "Could not get node {}.",
-                        node_id
-                    ))
-                    .into(),
-                );
-            }
-        };
+        let mut node_data = octree
+            .get_node_data_with_attributes(&node_id, attributes)
+            .map_err(|_| PointsViewerError::NotFound(format!("Could not get node {}.", node_id)))?;
+        if compression == NodeCompression::Delta {
+            node_data.delta_encode();
+        }
 
         // Write the bounding box information.
         let min = node_data.meta.bounding_cube.min();
@@ -157,14 +341,90 @@ pub async fn get_nodes_data(
         reply_blob.append(&mut node_data.color);
         pad(&mut reply_blob);
 
+        // Requested attributes, in the order of the request, each prefixed by its data type.
+        for attribute in &mut node_data.attributes {
+            assert!(
+                node_data.meta.num_points as usize * attribute.data_type.size_of()
+                    == attribute.data.len()
+            );
+            reply_blob
+                .write_u8(attribute.data_type.to_proto() as u8)
+                .unwrap();
+            pad(&mut reply_blob);
+            reply_blob.append(&mut attribute.data);
+            pad(&mut reply_blob);
+        }
+
         num_nodes_fetched += 1;
         num_points += node_data.meta.num_points;
     }
 
+    let num_bytes_uncompressed = reply_blob.len();
+    if compression == NodeCompression::Delta {
+        reply_blob = octree::deflate(&reply_blob);
+    }
+    Ok(NodesData {
+        reply_blob,
+        num_bytes_uncompressed,
+        num_nodes_fetched,
+        num_points,
+    })
+}
+
+/// Asynchronous Handler to get Node Data
+pub async fn get_nodes_data(
+    (octree_id, state, nodes, query): (
+        web::Path<String>,
+        web::Data<Arc<AppState>>,
+        web::Json<Vec<String>>,
+        web::Query<NodesDataQuery>,
+    ),
+) -> HttpResponse {
+    let start = time::Instant::now();
+    let data: Vec<String> = web::Json::into_inner(nodes);
+    let attributes: Vec<String> = match &query.attributes {
+        Some(attributes) => attributes
+            .split(',')
+            .filter(|a| !a.is_empty())
+            .map(str::to_string)
+            .collect(),
+        None => Vec::new(),
+    };
+    let compression = match query.compression.as_deref().map(NodeCompression::from_str) {
+        Some(Ok(compression)) => compression,
+        Some(Err(err)) => {
+            return HttpResponse::from_error(
+                crate::backend_error::PointsViewerError::BadRequest(err.to_string()).into(),
+            );
+        }
+        None => NodeCompression::None,
+    };
+    let nodes_to_load: Vec<octree::NodeId> = data
+        .into_iter()
+        .map(|e| octree::NodeId::from_str(e.as_str()).unwrap())
+        .collect();
+
+    // Loading the octree and its nodes blocks, so it must not run on the workers of the server.
+    let octree_id = octree_id.into_inner();
+    let nodes_data = web::block(move || {
+        let octree = get_octree_from_state(&octree_id, &state)?;
+        let attributes: Vec<&str> = attributes.iter().map(String::as_str).collect();
+        read_nodes_data(&octree, nodes_to_load, &attributes, compression)
+    })
+    .await;
+    let nodes_data = match nodes_data {
+        Ok(nodes_data) => nodes_data,
+        Err(err) => return HttpResponse::from_error(PointsViewerError::from(err).into()),
+    };
+
     let duration_ms = start.elapsed().as_seconds_f64() * 1_000.;
     eprintln!(
-        "Got {} nodes with {} points ({}ms).",
-        num_nodes_fetched, num_points, duration_ms
+        "Got {} nodes with {} points, {} of {} bytes sent ({}ms).",
+        nodes_data.num_nodes_fetched,
+        nodes_data.num_points,
+        nodes_data.reply_blob.len(),
+        nodes_data.num_bytes_uncompressed,
+        duration_ms
     );
 
     HttpResponse::Ok()
@@ -173,5 +433,19 @@ pub async fn get_nodes_data(
         // Local test (same machine) default encoding doubles the computing time in that condition by saving only 10% of the data volume
         // TODO(catevita) tests are required to find the most meaningful option
         .encoding(ContentEncoding::Identity)
-        .body(reply_blob)
+        .body(nodes_data.reply_blob)
+}
+
+/// Server-sent events announcing the nodes that changed whenever the octree is updated on disk.
+/// Clients should re-fetch these nodes if they are displaying them.
+pub fn get_updates(
+    (octree_id, state): (web::Path<String>, web::Data<Arc<AppState>>),
+) -> HttpResponse {
+    match state.subscribe(octree_id.into_inner()) {
+        Err(err) => HttpResponse::from_error(err.into()),
+        Ok(receiver) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .encoding(ContentEncoding::Identity)
+            .streaming(receiver.map(Ok::<_, actix_web::Error>)),
+    }
 }
@@ -1,4 +1,4 @@
-use actix_web::{error::ResponseError, HttpResponse};
+use actix_web::{error::BlockingError, error::ResponseError, HttpResponse};
 use failure::Fail;
 
 #[derive(Fail, Debug)]
@@ -56,3 +56,14 @@ impl From<actix_web::error::Error> for PointsViewerError {
         PointsViewerError::InternalServerError(err.to_string())
     }
 }
+
+impl From<BlockingError<PointsViewerError>> for PointsViewerError {
+    fn from(err: BlockingError<PointsViewerError>) -> PointsViewerError {
+        match err {
+            BlockingError::Error(err) => err,
+            BlockingError::Canceled => {
+                PointsViewerError::InternalServerError("The request was canceled.".to_string())
+            }
+        }
+    }
+}
@@ -13,18 +13,21 @@
 // limitations under the License.
 
 use clap::Clap;
+use object_store_provider::register_object_stores;
 use octree_web_viewer::backend_error::PointsViewerError;
 use octree_web_viewer::state::AppState;
 use octree_web_viewer::utils::start_octree_server;
 use point_viewer::data_provider::DataProviderFactory;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 /// HTTP web viewer for 3d points stored in OnDiskOctrees
 #[derive(Clap, Debug)]
 #[clap(name = "points_web_viewer", about = "Visualizing points")]
 pub struct CommandLineArguments {
-    /// The octree directory to serve, including a trailing slash.
+    /// The octree directory to serve, including a trailing slash. Octrees in buckets can be
+    /// served with 's3://bucket/octree/' or 'gs://bucket/octree/'.
     #[clap(name = "DIR", parse(from_os_str))]
     octree_path: PathBuf,
     /// Port to listen on.
@@ -35,6 +38,9 @@ pub struct CommandLineArguments {
     ip: String,
     #[clap(default_value = "100")]
     cache_items: usize,
+    /// How often to check the served octrees for updates on disk, in seconds.
+    #[clap(long, default_value = "1")]
+    update_interval: u64,
 }
 
 /// init app state with command arguments
@@ -43,7 +49,7 @@ pub fn state_from(args: CommandLineArguments) -> Result<AppState, PointsViewerEr
     // initial implementation: suffix from args not yet supported
     let suffix = PathBuf::new();
     let prefix = args.octree_path.parent().unwrap_or_else(|| Path::new(""));
-    let data_provider_factory = DataProviderFactory::new();
+    let data_provider_factory = register_object_stores(DataProviderFactory::new());
     let octree_id = args.octree_path.strip_prefix(&prefix)?;
     Ok(AppState::new(
         args.cache_items,
@@ -58,9 +64,11 @@ fn main() {
     let args = CommandLineArguments::parse();
 
     let ip_port = format!("{}:{}", args.ip, args.port);
+    let update_interval = Duration::from_secs(args.update_interval);
 
     // initialize app state
     let app_state: Arc<AppState> = Arc::new(state_from(args).unwrap());
+    Arc::clone(&app_state).watch_for_updates(update_interval);
     // The actix-web framework handles requests asynchronously using actors. If we need multi-threaded
     // write access to the Octree, instead of using an RwLock we should use the actor system.
     // put octree arc in cache
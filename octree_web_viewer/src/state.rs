@@ -1,9 +1,14 @@
 use crate::backend_error::PointsViewerError;
+use actix_web::web::Bytes;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use point_viewer::data_provider;
 use point_viewer::octree;
+use point_viewer::META_FILENAME;
 use std::collections::HashMap;
+use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
 
 /// path information for the octrees
 #[derive(Clone)]
@@ -24,10 +29,25 @@ impl OctreeKeyParams {
     }
 }
 
+/// The meta file of a loaded octree on disk, used to detect updates.
+#[derive(Clone)]
+struct MetaFile {
+    path: PathBuf,
+    modified: SystemTime,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
 #[derive(Clone)]
 pub struct AppState {
     /// Hash Map for Octrees
     octree_map: Arc<RwLock<HashMap<String, Arc<octree::Octree>>>>,
+    /// Meta files of the loaded octrees that are on disk
+    meta_files: Arc<RwLock<HashMap<String, MetaFile>>>,
+    /// Clients to notify about changed nodes, per octree
+    subscribers: Arc<Mutex<HashMap<String, Vec<UnboundedSender<Bytes>>>>>,
     /// information for retieving octree path
     key_params: OctreeKeyParams,
     /// backward compatibility to input arguments
@@ -45,6 +65,8 @@ impl AppState {
     ) -> Self {
         AppState {
             octree_map: Arc::new(RwLock::new(HashMap::with_capacity(map_size))),
+            meta_files: Arc::new(RwLock::new(HashMap::new())),
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
             key_params: OctreeKeyParams {
                 prefix: prefix.into(),
                 suffix: suffix.into(),
@@ -80,10 +102,20 @@ impl AppState {
     ) -> Result<Arc<octree::Octree>, PointsViewerError> {
         let octree_key = octree_id.into();
         let addr = &self.key_params.get_octree_address(&octree_key);
-        let octree: Arc<octree::Octree> = Arc::from(octree::Octree::from_data_provider(
-            self.data_provider_factory
-                .generate_data_provider(addr.to_string_lossy())?,
-        )?);
+        let data_provider = self
+            .data_provider_factory
+            .generate_data_provider(addr.to_string_lossy())?;
+        let meta_path = data_provider
+            .directory()
+            .map(|directory| directory.join(META_FILENAME));
+        let octree: Arc<octree::Octree> =
+            Arc::from(octree::Octree::from_data_provider(data_provider)?);
+        if let Some(path) = meta_path {
+            if let Some(modified) = modified(&path) {
+                let mut wmeta_files = self.meta_files.write().unwrap();
+                wmeta_files.insert(octree_key.clone(), MetaFile { path, modified });
+            }
+        }
         {
             // write access to state
             let mut wmap = self.octree_map.write().unwrap();
@@ -92,7 +124,164 @@ impl AppState {
         Ok(octree)
     }
 
+    /// Returns a stream of server-sent events, each carrying a JSON list of the nodes that
+    /// changed when the octree was updated on disk.
+    pub fn subscribe(
+        &self,
+        octree_id: impl AsRef<str>,
+    ) -> Result<UnboundedReceiver<Bytes>, PointsViewerError> {
+        let octree_key = octree_id.as_ref();
+        self.load_octree(octree_key)?;
+        if !self.meta_files.read().unwrap().contains_key(octree_key) {
+            return Err(PointsViewerError::BadRequest(format!(
+                "Octree {} is not on disk and cannot be watched for updates.",
+                octree_key
+            )));
+        }
+        let (sender, receiver) = mpsc::unbounded();
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers
+            .entry(octree_key.to_string())
+            .or_default()
+            .push(sender);
+        Ok(receiver)
+    }
+
+    /// Reloads the octrees whose meta file changed on disk and returns their changed nodes.
+    fn reload_updated_octrees(&self) -> Vec<(String, Vec<octree::NodeId>)> {
+        let meta_files: Vec<(String, MetaFile)> = self
+            .meta_files
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, meta_file)| (key.clone(), meta_file.clone()))
+            .collect();
+        let mut updated = Vec::new();
+        for (octree_key, meta_file) in meta_files {
+            if modified(&meta_file.path).map_or(true, |m| m == meta_file.modified) {
+                continue;
+            }
+            let previous = match self.octree_map.read().unwrap().get(&octree_key) {
+                Some(octree) => Arc::clone(octree),
+                None => continue,
+            };
+            match self.insert_octree(octree_key.clone()) {
+                Ok(octree) => {
+                    let changed = octree.changed_nodes(&previous);
+                    updated.push((octree_key, changed));
+                }
+                Err(err) => eprintln!("Could not reload octree {}: {}", octree_key, err),
+            }
+        }
+        updated
+    }
+
+    fn notify_subscribers(&self, octree_key: &str, changed: &[octree::NodeId]) {
+        let node_ids = changed
+            .iter()
+            .map(|id| format!("\"{}\"", id))
+            .collect::<Vec<_>>()
+            .join(",");
+        let event = Bytes::from(format!("data: [{}]\n\n", node_ids));
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(senders) = subscribers.get_mut(octree_key) {
+            // Disconnected clients are dropped here.
+            senders.retain(|sender| sender.unbounded_send(event.clone()).is_ok());
+        }
+    }
+
+    /// Polls the meta files of the loaded octrees every `interval` in a background thread and
+    /// notifies the subscribers when an octree changed.
+    pub fn watch_for_updates(self: Arc<Self>, interval: Duration) {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            for (octree_key, changed) in self.reload_updated_octrees() {
+                eprintln!(
+                    "Octree {} changed, {} nodes updated.",
+                    octree_key,
+                    changed.len()
+                );
+                self.notify_subscribers(&octree_key, &changed);
+            }
+        });
+    }
+
     pub fn get_init_id(&self) -> String {
         self.init_octree_id.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point3, Vector3};
+    use point_viewer::geometry::Aabb;
+    use point_viewer::iterator::{AttributeUpdate, PointCloud, PointLocation, PointQuery};
+    use point_viewer::{AttributeData, PointsBatch};
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_subscribers_are_notified_about_rewritten_nodes() {
+        let tmp_dir = TempDir::new("octrees").unwrap();
+        let directory = tmp_dir.path().join("octree");
+        let num_points = 1000;
+        let position: Vec<_> = (0..num_points)
+            .map(|i| Point3::new(f64::from(i % 10), f64::from(i / 10), 0.))
+            .collect();
+        let batch = PointsBatch {
+            position,
+            attributes: vec![(
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points as usize]),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        octree::build_octree(
+            &directory,
+            1.,
+            Aabb::new(Point3::origin(), Point3::new(9., 99., 1.)),
+            vec![batch].into_iter(),
+            &["color"],
+        );
+
+        let state = AppState::new(
+            1,
+            tmp_dir.path(),
+            "",
+            "octree",
+            data_provider::DataProviderFactory::new(),
+        );
+        let mut receiver = state.subscribe("octree").unwrap();
+
+        // The rewritten meta file needs a newer modification time to be picked up.
+        std::thread::sleep(Duration::from_millis(10));
+        // Recoloring keeps the number of points of the node.
+        let mut octree =
+            octree::Octree::from_data_provider(Box::new(data_provider::OnDiskDataProvider {
+                directory,
+            }))
+            .unwrap();
+        let query = PointQuery {
+            location: PointLocation::Aabb(Aabb::new(
+                Point3::new(-0.5, -0.5, -1.),
+                Point3::new(9.5, 9.5, 1.),
+            )),
+            ..Default::default()
+        };
+        let num_updated = octree
+            .update_attribute(
+                &query,
+                "color",
+                AttributeUpdate::Constant(AttributeData::U8Vec3(vec![Vector3::new(0, 255, 0)])),
+            )
+            .unwrap();
+        assert_eq!(num_updated, 100);
+
+        for (octree_key, changed) in state.reload_updated_octrees() {
+            state.notify_subscribers(&octree_key, &changed);
+        }
+        let event = receiver.try_next().unwrap().unwrap();
+        assert_eq!(&event[..], &b"data: [\"r\"]\n\n"[..]);
+    }
+}
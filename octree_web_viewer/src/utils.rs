@@ -1,4 +1,6 @@
-use crate::backend::{get_nodes_data, get_visible_nodes};
+use crate::backend::{
+    get_attribute_ranges, get_nodes_data, get_nodes_for_view, get_updates, get_visible_nodes, pick,
+};
 use crate::backend_error::PointsViewerError;
 use crate::state::AppState;
 use actix_web::{web, HttpResponse, HttpServer};
@@ -47,7 +49,11 @@ pub fn start_octree_server(
             )
             .service(web::resource("/init_tree").to(get_init_tree))
             .service(web::resource("/visible_nodes/{octree_id}/").to(get_visible_nodes))
+            .service(web::resource("/nodes_for_view/{octree_id}/").to(get_nodes_for_view))
             .service(web::resource("/nodes_data/{octree_id}/").to(get_nodes_data))
+            .service(web::resource("/updates/{octree_id}/").to(get_updates))
+            .service(web::resource("/pick/{octree_id}/").to(pick))
+            .service(web::resource("/attribute_ranges/{octree_id}/").to(get_attribute_ranges))
     })
     .bind(&ip_port)
     .unwrap_or_else(|_| panic!("Can not bind to {}", &ip_port))
@@ -0,0 +1,279 @@
+use clap::Clap;
+use nalgebra::Point3;
+use object_store_provider::register_object_stores;
+use point_cloud_client::diff::{diff, write_colored_diff, Change};
+use point_cloud_client::ground::{classify_ground, GroundFilter};
+use point_cloud_client::raster::{HeightRaster, HeightStatistic};
+use point_cloud_client::PointCloudClientBuilder;
+use point_viewer::data_provider::DataProviderFactory;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::{PointCloud, PointLocation, PointQuery};
+use point_viewer::octree::Octree;
+use point_viewer::PointsBatch;
+use std::path::PathBuf;
+
+fn point3f64_from_str(s: &str) -> std::result::Result<Point3<f64>, &'static str> {
+    let coords: std::result::Result<Vec<f64>, &'static str> = s
+        .split(&[' ', ',', ';'][..])
+        .map(|s| s.parse::<f64>().map_err(|_| "Could not parse point."))
+        .collect();
+    let coords = coords?;
+    if coords.len() != 3 {
+        return Err("Wrong number of coordinates.");
+    }
+    Ok(Point3::new(coords[0], coords[1], coords[2]))
+}
+
+#[derive(Clap)]
+#[clap(about = "Tools working on point clouds through the point cloud client.")]
+struct CommandlineArguments {
+    /// The locations containing the point cloud data.
+    #[clap(parse(from_str), required = true)]
+    locations: Vec<String>,
+
+    /// The maximum number of threads to be running.
+    #[clap(long, default_value = "30")]
+    num_threads: usize,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clap)]
+enum Command {
+    /// Rasterizes the heights of the points in a box into a height map.
+    Rasterize(RasterizeArguments),
+    /// Compares the voxels occupied by the points in a box with those of later point clouds and
+    /// reports the added and removed regions.
+    Diff(DiffArguments),
+    /// Labels the points of an octree as ground or not with a progressive morphological filter
+    /// and writes the labels into its classification attribute.
+    ClassifyGround(ClassifyGroundArguments),
+}
+
+#[derive(Clap)]
+struct RasterizeArguments {
+    /// The file to write, a GeoTIFF for '.tif' or an ESRI ASCII grid for '.asc'.
+    #[clap(long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// The edge length of a raster cell.
+    #[clap(long)]
+    resolution: f64,
+
+    /// Which height of the points in a cell to write: 'min', 'max' or 'mean'.
+    #[clap(long, default_value = "mean")]
+    statistic: HeightStatistic,
+
+    /// The minimum of the box to rasterize. Defaults to the bounding box of the point clouds.
+    #[clap(long, parse(try_from_str = point3f64_from_str))]
+    min: Option<Point3<f64>>,
+
+    /// The maximum of the box to rasterize. Defaults to the bounding box of the point clouds.
+    #[clap(long, parse(try_from_str = point3f64_from_str))]
+    max: Option<Point3<f64>>,
+}
+
+#[derive(Clap)]
+struct DiffArguments {
+    /// The locations of the later point clouds. The point clouds in 'locations' are the earlier
+    /// ones.
+    #[clap(long, required = true)]
+    after: Vec<String>,
+
+    /// The edge length of the voxels that are compared.
+    #[clap(long, default_value = "0.5")]
+    voxel_size: f64,
+
+    /// Writes the points into this PLY file, green if added, red if removed and gray otherwise.
+    #[clap(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    /// The minimum of the box to compare. Defaults to the bounding box of all point clouds.
+    #[clap(long, parse(try_from_str = point3f64_from_str))]
+    min: Option<Point3<f64>>,
+
+    /// The maximum of the box to compare. Defaults to the bounding box of all point clouds.
+    #[clap(long, parse(try_from_str = point3f64_from_str))]
+    max: Option<Point3<f64>>,
+}
+
+#[derive(Clap)]
+struct ClassifyGroundArguments {
+    /// The edge length of the cells of the grid of lowest heights.
+    #[clap(long, default_value = "1")]
+    cell_size: f64,
+
+    /// The largest window of the filter. It should be larger than the largest building.
+    #[clap(long, default_value = "20")]
+    max_window_size: f64,
+
+    /// The steepest slope of the terrain, as height difference per distance.
+    #[clap(long, default_value = "1")]
+    slope: f64,
+
+    /// How far points may be above the ground surface of the smallest window.
+    #[clap(long, default_value = "0.5")]
+    initial_distance: f64,
+
+    /// How far points may be above the ground surface at most.
+    #[clap(long, default_value = "3")]
+    max_distance: f64,
+
+    /// The label of ground points.
+    #[clap(long, default_value = "ground")]
+    ground_label: String,
+
+    /// The label of the other points. They keep their label if not given.
+    #[clap(long)]
+    non_ground_label: Option<String>,
+
+    /// Writes the mean height of the ground points into this file, a GeoTIFF for '.tif' or an
+    /// ESRI ASCII grid for '.asc', with cells of 'cell_size'.
+    #[clap(long, parse(from_os_str))]
+    dem: Option<PathBuf>,
+
+    /// The minimum of the box to classify. Defaults to the bounding box of the octree.
+    #[clap(long, parse(try_from_str = point3f64_from_str))]
+    min: Option<Point3<f64>>,
+
+    /// The maximum of the box to classify. Defaults to the bounding box of the octree.
+    #[clap(long, parse(try_from_str = point3f64_from_str))]
+    max: Option<Point3<f64>>,
+}
+
+fn classify_ground_points(locations: &[String], args: &ClassifyGroundArguments) -> Result<()> {
+    if locations.len() != 1 {
+        return Err(ErrorKind::InvalidInput(
+            "Ground points can only be classified in a single octree.".to_string(),
+        )
+        .into());
+    }
+    let data_provider =
+        register_object_stores(DataProviderFactory::new()).generate_data_provider(&locations[0])?;
+    let mut octree = Octree::from_data_provider(data_provider)?;
+    let bounding_box = Aabb::new(
+        args.min.unwrap_or(*octree.bounding_box().min()),
+        args.max.unwrap_or(*octree.bounding_box().max()),
+    );
+    let filter = GroundFilter {
+        cell_size: args.cell_size,
+        max_window_size: args.max_window_size,
+        slope: args.slope,
+        initial_distance: args.initial_distance,
+        max_distance: args.max_distance,
+    };
+    let mut dem = match &args.dem {
+        Some(_) => Some(HeightRaster::new(
+            &bounding_box,
+            args.cell_size,
+            HeightStatistic::Mean,
+        )?),
+        None => None,
+    };
+    let num_ground = classify_ground(
+        &mut octree,
+        &bounding_box,
+        &filter,
+        &args.ground_label,
+        args.non_ground_label.as_deref(),
+        dem.as_mut(),
+    )?;
+    eprintln!("Labeled {} points as '{}'.", num_ground, args.ground_label);
+    if let (Some(dem), Some(path)) = (&dem, &args.dem) {
+        eprintln!("Writing {}.", path.display());
+        dem.write_to_file(path)?;
+    }
+    Ok(())
+}
+
+fn diff_point_clouds(locations: &[String], num_threads: usize, args: &DiffArguments) -> Result<()> {
+    let before = PointCloudClientBuilder::new(locations)
+        .num_threads(num_threads)
+        .build()?;
+    let after = PointCloudClientBuilder::new(&args.after)
+        .num_threads(num_threads)
+        .build()?;
+    let mut bounding_box = before.bounding_box().clone();
+    bounding_box.grow(*after.bounding_box().min());
+    bounding_box.grow(*after.bounding_box().max());
+    let query = PointQuery {
+        location: PointLocation::Aabb(Aabb::new(
+            args.min.unwrap_or(*bounding_box.min()),
+            args.max.unwrap_or(*bounding_box.max()),
+        )),
+        ..Default::default()
+    };
+    let occupancy_diff = diff(&before, &after, &query, args.voxel_size)?;
+    print!("{}", occupancy_diff.summary());
+    for region in occupancy_diff.regions() {
+        let change = match region.change {
+            Change::Added => "Added",
+            Change::Removed => "Removed",
+            Change::Unchanged => continue,
+        };
+        println!(
+            "{} {} voxels from {:?} to {:?}",
+            change,
+            region.num_voxels,
+            region.bounding_box.min().coords.as_slice(),
+            region.bounding_box.max().coords.as_slice()
+        );
+    }
+    if let Some(output) = &args.output {
+        eprintln!("Writing {}.", output.display());
+        write_colored_diff(&occupancy_diff, &before, &after, &query, output)?;
+    }
+    Ok(())
+}
+
+fn rasterize(locations: &[String], num_threads: usize, args: &RasterizeArguments) -> Result<()> {
+    let point_cloud_client = PointCloudClientBuilder::new(locations)
+        .num_threads(num_threads)
+        .build()?;
+    let bounding_box = point_cloud_client.bounding_box();
+    let bounding_box = Aabb::new(
+        args.min.unwrap_or(*bounding_box.min()),
+        args.max.unwrap_or(*bounding_box.max()),
+    );
+    let mut raster = HeightRaster::new(&bounding_box, args.resolution, args.statistic)?;
+    eprintln!(
+        "Rasterizing into {}x{} cells.",
+        raster.width(),
+        raster.height()
+    );
+    let query = PointQuery {
+        location: PointLocation::Aabb(bounding_box),
+        ..Default::default()
+    };
+    let mut num_points = 0;
+    point_cloud_client.for_each_point_data(&query, |points_batch: PointsBatch| {
+        raster.add_points(&points_batch.position);
+        num_points += points_batch.position.len();
+        Ok(())
+    })?;
+    eprintln!(
+        "Rasterized {} points, writing {}.",
+        num_points,
+        args.output.display()
+    );
+    raster.write_to_file(&args.output)
+}
+
+fn main() {
+    let args = CommandlineArguments::parse();
+    let result = match &args.command {
+        Command::Rasterize(rasterize_args) => {
+            rasterize(&args.locations, args.num_threads, rasterize_args)
+        }
+        Command::Diff(diff_args) => diff_point_clouds(&args.locations, args.num_threads, diff_args),
+        Command::ClassifyGround(classify_ground_args) => {
+            classify_ground_points(&args.locations, classify_ground_args)
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Encountered error:\n{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,197 @@
+//! Bounded query results, e.g. for interactive dashboards that need responses of a limited size.
+//! The levels of detail of an octree are taken from the root down while they fit into the
+//! budget. Point clouds without levels, and octrees whose root level alone exceeds the budget,
+//! return an evenly spread fraction of the points of each node instead.
+
+use point_viewer::coordinates::CoordinateSystem;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::{PointCloud, PointLocation, PointQuery};
+use point_viewer::labels::LabelDictionary;
+use point_viewer::metadata::Metadata;
+use point_viewer::read_write::{Encoding, NodeIterator};
+use point_viewer::PointsBatch;
+
+/// The most points that 'PointCloudClient::for_each_point_data_within_budget' returns.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointBudget {
+    pub max_points: usize,
+    /// If given, queries without 'PointQuery::downsample' are downsampled to roughly this many
+    /// points per unit volume, see 'VoxelSize::for_density'.
+    pub target_density: Option<f64>,
+}
+
+impl PointBudget {
+    pub fn new(max_points: usize) -> Self {
+        PointBudget {
+            max_points,
+            target_density: None,
+        }
+    }
+}
+
+/// The number of points in the nodes of `point_cloud` that `query` reads, an upper bound of the
+/// number of points it matches.
+pub fn num_points_for_query<C: PointCloud>(point_cloud: &C, query: &PointQuery) -> usize {
+    point_cloud
+        .nodes_for_query(query)
+        .into_iter()
+        .map(|node_id| point_cloud.num_points_in_node(node_id))
+        .sum()
+}
+
+/// A point cloud reduced to the nodes of a query that fit into a budget, which can be queried
+/// like the point cloud itself.
+pub struct BudgetedCloud<'a, C: PointCloud> {
+    point_cloud: &'a C,
+    node_ids: Vec<C::Id>,
+    /// The fraction of the matching points of each node that is returned.
+    keep_fraction: f64,
+}
+
+impl<'a, C: PointCloud> BudgetedCloud<'a, C> {
+    /// Selects the nodes of `point_cloud` for `query` that hold at most `max_points` points.
+    /// `level` is the level of detail of a node, 0 being the coarsest, e.g. always 0 for point
+    /// clouds without levels. Returns the selection and whether it has all nodes of the query.
+    pub fn new(
+        point_cloud: &'a C,
+        query: &PointQuery,
+        max_points: usize,
+        level: impl Fn(C::Id) -> u8,
+    ) -> (Self, bool) {
+        let mut nodes: Vec<(u8, C::Id, usize)> = point_cloud
+            .nodes_for_query(query)
+            .into_iter()
+            .map(|node_id| {
+                let num_points = point_cloud.num_points_in_node(node_id);
+                (level(node_id), node_id, num_points)
+            })
+            .collect();
+        nodes.sort_by_key(|(level, _, _)| *level);
+
+        // Whole levels are selected while they fit.
+        let mut num_selected = 0;
+        let mut num_points = 0;
+        let mut keep_fraction = 1.;
+        let mut is_exhaustive = true;
+        while num_selected < nodes.len() {
+            let level = nodes[num_selected].0;
+            let end = num_selected
+                + nodes[num_selected..]
+                    .iter()
+                    .take_while(|(node_level, _, _)| *node_level == level)
+                    .count();
+            let level_points: usize = nodes[num_selected..end]
+                .iter()
+                .map(|(_, _, num_points)| num_points)
+                .sum();
+            if num_points + level_points > max_points {
+                is_exhaustive = false;
+                if num_selected == 0 {
+                    // Even the coarsest level has too many points, so they are thinned out.
+                    num_selected = end;
+                    keep_fraction = max_points as f64 / level_points as f64;
+                }
+                break;
+            }
+            num_points += level_points;
+            num_selected = end;
+        }
+        let node_ids = nodes[..num_selected]
+            .iter()
+            .map(|(_, node_id, _)| *node_id)
+            .collect();
+        let budgeted_cloud = BudgetedCloud {
+            point_cloud,
+            node_ids,
+            keep_fraction,
+        };
+        (budgeted_cloud, is_exhaustive)
+    }
+}
+
+impl<'a, C> PointCloud for BudgetedCloud<'a, C>
+where
+    C: PointCloud,
+    C::Id: Sync,
+{
+    type Id = C::Id;
+
+    /// The selected nodes, which were selected for the location of the query already.
+    fn nodes_in_location(&self, _: &PointLocation) -> Vec<Self::Id> {
+        self.node_ids.clone()
+    }
+
+    fn nodes_for_query(&self, _: &PointQuery) -> Vec<Self::Id> {
+        self.node_ids.clone()
+    }
+
+    fn encoding_for_node(&self, id: Self::Id) -> Encoding {
+        self.point_cloud.encoding_for_node(id)
+    }
+
+    fn points_in_node(
+        &self,
+        attributes: &[&str],
+        node_id: Self::Id,
+        batch_size: usize,
+    ) -> Result<NodeIterator> {
+        self.point_cloud
+            .points_in_node(attributes, node_id, batch_size)
+    }
+
+    fn num_points_in_node(&self, node_id: Self::Id) -> usize {
+        self.point_cloud.num_points_in_node(node_id)
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.point_cloud.bounding_box()
+    }
+
+    fn coordinate_system(&self) -> Option<CoordinateSystem> {
+        self.point_cloud.coordinate_system()
+    }
+
+    fn label_dictionary(&self) -> Option<&LabelDictionary> {
+        self.point_cloud.label_dictionary()
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        self.point_cloud.metadata()
+    }
+
+    /// Returns the 'keep_fraction' of the matching points, spread evenly over the node.
+    fn stream_points_for_query_in_node<F>(
+        &self,
+        query: &PointQuery,
+        node_id: Self::Id,
+        batch_size: usize,
+        mut callback: F,
+    ) -> Result<()>
+    where
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        if self.keep_fraction >= 1. {
+            return self
+                .point_cloud
+                .stream_points_for_query_in_node(query, node_id, batch_size, callback);
+        }
+        let mut num_seen = 0;
+        self.point_cloud
+            .stream_points_for_query_in_node(query, node_id, batch_size, |mut batch| {
+                // A point is kept whenever the number of points to keep so far grows, so that
+                // exactly the fraction of them rounded down is kept in the end.
+                let num_to_keep =
+                    |num_points: usize| (num_points as f64 * self.keep_fraction).floor();
+                let keep: Vec<bool> = (num_seen..num_seen + batch.position.len())
+                    .map(|i| num_to_keep(i + 1) > num_to_keep(i))
+                    .collect();
+                num_seen += batch.position.len();
+                batch.retain(&keep);
+                if batch.position.is_empty() {
+                    return Ok(());
+                }
+                callback(batch)
+            })
+    }
+}
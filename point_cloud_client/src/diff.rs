@@ -0,0 +1,323 @@
+//! Change detection between two states of the same scene, e.g. to monitor the progress of a
+//! construction site, by comparing which cells of a voxel grid the points of each occupy.
+
+use crate::PointCloudClient;
+use fnv::{FnvHashMap, FnvHashSet};
+use nalgebra::{Point3, Vector3};
+use point_viewer::attributes::AttributeData;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::PointQuery;
+use point_viewer::read_write::{Encoding, NodeWriter, OpenMode, PlyNodeWriter};
+use point_viewer::PointsBatch;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// How a voxel changed from the earlier to the later point cloud.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Change {
+    /// Only the later point cloud has points in the voxel.
+    Added,
+    /// Only the earlier point cloud has points in the voxel.
+    Removed,
+    /// Both point clouds have points in the voxel.
+    Unchanged,
+}
+
+impl Change {
+    /// The color of the points with this change in 'write_colored_diff'.
+    pub fn color(self) -> Vector3<u8> {
+        match self {
+            Change::Added => Vector3::new(0, 200, 0),
+            Change::Removed => Vector3::new(220, 0, 0),
+            Change::Unchanged => Vector3::new(128, 128, 128),
+        }
+    }
+}
+
+type VoxelIndex = (i64, i64, i64);
+
+const BEFORE: u8 = 1;
+const AFTER: u8 = 2;
+
+/// The number of voxels of each change.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiffSummary {
+    pub voxel_size: f64,
+    pub num_added: usize,
+    pub num_removed: usize,
+    pub num_unchanged: usize,
+}
+
+impl DiffSummary {
+    fn volume(&self, num_voxels: usize) -> f64 {
+        num_voxels as f64 * self.voxel_size.powi(3)
+    }
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Voxel size: {}", self.voxel_size)?;
+        for (name, num_voxels) in &[
+            ("Added", self.num_added),
+            ("Removed", self.num_removed),
+            ("Unchanged", self.num_unchanged),
+        ] {
+            writeln!(
+                f,
+                "{}: {} voxels, volume {}",
+                name,
+                num_voxels,
+                self.volume(*num_voxels)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Neighboring voxels with the same change.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangedRegion {
+    pub change: Change,
+    pub bounding_box: Aabb,
+    pub num_voxels: usize,
+}
+
+/// The voxels occupied by the points of an earlier and a later point cloud. Memory depends only
+/// on the number of occupied voxels, so points can be added batch by batch.
+pub struct OccupancyDiff {
+    voxel_size: f64,
+    /// Whether a voxel has points 'BEFORE', 'AFTER' or both.
+    voxels: FnvHashMap<VoxelIndex, u8>,
+}
+
+impl OccupancyDiff {
+    pub fn new(voxel_size: f64) -> Result<Self> {
+        if voxel_size.is_nan() || voxel_size <= 0. {
+            return Err(ErrorKind::InvalidInput(format!(
+                "The voxel size must be positive, but is {}.",
+                voxel_size
+            ))
+            .into());
+        }
+        Ok(OccupancyDiff {
+            voxel_size,
+            voxels: FnvHashMap::default(),
+        })
+    }
+
+    fn voxel(&self, p: &Point3<f64>) -> VoxelIndex {
+        let v = p.coords / self.voxel_size;
+        (v.x.floor() as i64, v.y.floor() as i64, v.z.floor() as i64)
+    }
+
+    fn add(&mut self, points: &[Point3<f64>], flag: u8) {
+        for p in points {
+            *self.voxels.entry(self.voxel(p)).or_default() |= flag;
+        }
+    }
+
+    /// Adds points of the earlier point cloud.
+    pub fn add_before(&mut self, points: &[Point3<f64>]) {
+        self.add(points, BEFORE);
+    }
+
+    /// Adds points of the later point cloud.
+    pub fn add_after(&mut self, points: &[Point3<f64>]) {
+        self.add(points, AFTER);
+    }
+
+    fn change_of(flags: u8) -> Change {
+        match flags {
+            BEFORE => Change::Removed,
+            AFTER => Change::Added,
+            _ => Change::Unchanged,
+        }
+    }
+
+    /// The change of the voxel containing `p`, or None if neither point cloud has points in it.
+    pub fn change(&self, p: &Point3<f64>) -> Option<Change> {
+        self.voxels
+            .get(&self.voxel(p))
+            .copied()
+            .map(Self::change_of)
+    }
+
+    pub fn summary(&self) -> DiffSummary {
+        let mut summary = DiffSummary {
+            voxel_size: self.voxel_size,
+            ..Default::default()
+        };
+        for flags in self.voxels.values() {
+            match Self::change_of(*flags) {
+                Change::Added => summary.num_added += 1,
+                Change::Removed => summary.num_removed += 1,
+                Change::Unchanged => summary.num_unchanged += 1,
+            }
+        }
+        summary
+    }
+
+    /// The regions of added and removed voxels that touch each other, also at edges and corners,
+    /// the largest first.
+    pub fn regions(&self) -> Vec<ChangedRegion> {
+        let mut visited = FnvHashSet::default();
+        let mut regions = Vec::new();
+        for (start, flags) in &self.voxels {
+            let change = Self::change_of(*flags);
+            if change == Change::Unchanged || !visited.insert(*start) {
+                continue;
+            }
+            let (mut min, mut max) = (*start, *start);
+            let mut num_voxels = 0;
+            let mut stack = vec![*start];
+            while let Some((x, y, z)) = stack.pop() {
+                num_voxels += 1;
+                min = (min.0.min(x), min.1.min(y), min.2.min(z));
+                max = (max.0.max(x), max.1.max(y), max.2.max(z));
+                for dx in -1..=1 {
+                    for dy in -1..=1 {
+                        for dz in -1..=1 {
+                            let neighbor = (x + dx, y + dy, z + dz);
+                            let same_change =
+                                self.voxels.get(&neighbor).copied().map(Self::change_of)
+                                    == Some(change);
+                            if same_change && visited.insert(neighbor) {
+                                stack.push(neighbor);
+                            }
+                        }
+                    }
+                }
+            }
+            let corner =
+                |(x, y, z): VoxelIndex| Point3::new(x as f64, y as f64, z as f64) * self.voxel_size;
+            regions.push(ChangedRegion {
+                change,
+                bounding_box: Aabb::new(corner(min), corner((max.0 + 1, max.1 + 1, max.2 + 1))),
+                num_voxels,
+            });
+        }
+        regions.sort_by_key(|region| std::cmp::Reverse(region.num_voxels));
+        regions
+    }
+
+    /// Returns the points of `batch` with the color of their change as their only attribute.
+    /// Only the points in removed voxels are kept from the earlier point cloud, since the later
+    /// one has points in all the others.
+    pub fn colorize(&self, batch: &PointsBatch, before: bool) -> PointsBatch {
+        let (position, color) = batch
+            .position
+            .iter()
+            .filter_map(|p| {
+                let change = self.change(p)?;
+                if before && change != Change::Removed {
+                    return None;
+                }
+                Some((*p, change.color()))
+            })
+            .unzip();
+        let mut attributes = BTreeMap::new();
+        attributes.insert("color".to_string(), AttributeData::U8Vec3(color));
+        PointsBatch {
+            position,
+            attributes,
+        }
+    }
+}
+
+/// Compares the voxels occupied by the points matching `query` in `before` and `after`.
+pub fn diff(
+    before: &PointCloudClient,
+    after: &PointCloudClient,
+    query: &PointQuery,
+    voxel_size: f64,
+) -> Result<OccupancyDiff> {
+    let mut diff = OccupancyDiff::new(voxel_size)?;
+    before.for_each_point_data(query, |batch| {
+        diff.add_before(&batch.position);
+        Ok(())
+    })?;
+    after.for_each_point_data(query, |batch| {
+        diff.add_after(&batch.position);
+        Ok(())
+    })?;
+    Ok(diff)
+}
+
+/// Writes the points matching `query` into a PLY file, colored by the change of their voxel in
+/// `diff`, see 'OccupancyDiff::colorize'.
+pub fn write_colored_diff(
+    diff: &OccupancyDiff,
+    before: &PointCloudClient,
+    after: &PointCloudClient,
+    query: &PointQuery,
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let mut writer = PlyNodeWriter::new(path.as_ref(), Encoding::Plain, OpenMode::Truncate);
+    before.for_each_point_data(query, |batch| {
+        NodeWriter::write(&mut writer, &diff.colorize(&batch, true)).map_err(Error::from)
+    })?;
+    after.for_each_point_data(query, |batch| {
+        NodeWriter::write(&mut writer, &diff.colorize(&batch, false)).map_err(Error::from)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_occupancy_diff() {
+        let mut diff = OccupancyDiff::new(1.).unwrap();
+        // A wall that stays, a column that was built and a pile that was removed.
+        let wall: Vec<Point3<f64>> = (0..10).map(|x| Point3::new(x as f64, 0.5, 0.5)).collect();
+        let column: Vec<Point3<f64>> = (0..3).map(|z| Point3::new(5.5, 5.5, z as f64)).collect();
+        let pile = vec![Point3::new(-3.5, -3.5, 0.5), Point3::new(-2.5, -3.5, 0.5)];
+        diff.add_before(&wall);
+        diff.add_before(&pile);
+        diff.add_after(&wall);
+        diff.add_after(&column);
+
+        assert_eq!(
+            diff.summary(),
+            DiffSummary {
+                voxel_size: 1.,
+                num_added: 3,
+                num_removed: 2,
+                num_unchanged: 10,
+            }
+        );
+        assert_eq!(
+            diff.change(&Point3::new(5.2, 5.9, 1.5)),
+            Some(Change::Added)
+        );
+        assert_eq!(diff.change(&Point3::new(20., 20., 20.)), None);
+        assert_eq!(
+            diff.regions(),
+            vec![
+                ChangedRegion {
+                    change: Change::Added,
+                    bounding_box: Aabb::new(Point3::new(5., 5., 0.), Point3::new(6., 6., 3.)),
+                    num_voxels: 3,
+                },
+                ChangedRegion {
+                    change: Change::Removed,
+                    bounding_box: Aabb::new(Point3::new(-4., -4., 0.), Point3::new(-2., -3., 1.)),
+                    num_voxels: 2,
+                },
+            ]
+        );
+
+        let before = PointsBatch {
+            position: [&wall[..], &pile[..]].concat(),
+            attributes: BTreeMap::new(),
+        };
+        let colorized = diff.colorize(&before, true);
+        assert_eq!(colorized.position, pile);
+        match &colorized.attributes["color"] {
+            AttributeData::U8Vec3(color) => assert_eq!(color, &vec![Change::Removed.color(); 2]),
+            other => panic!("Expected colors, got {:?}.", other),
+        }
+    }
+}
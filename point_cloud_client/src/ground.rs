@@ -0,0 +1,266 @@
+//! Classification of ground points with a progressive morphological filter (Zhang et al., "A
+//! progressive morphological filter for removing nonground measurements from airborne LIDAR
+//! data", 2003). Morphological openings with growing windows remove objects of growing size from
+//! a grid of the lowest heights; points close above the opened surface are ground.
+
+use crate::raster::{HeightRaster, HeightStatistic};
+use nalgebra::Point3;
+use point_viewer::attributes::AttributeData;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use point_viewer::iterator::{
+    AttributeUpdate, ParallelIterator, PointCloud, PointLocation, PointQuery,
+};
+use point_viewer::labels::CLASSIFICATION_ATTRIBUTE;
+use point_viewer::{PointsBatch, NUM_POINTS_PER_BATCH};
+
+/// The parameters of the filter. Lengths and heights are in the unit of the positions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroundFilter {
+    /// The edge length of the cells of the grid of lowest heights.
+    pub cell_size: f64,
+    /// The largest window of the openings. Objects that fit into it are removed, so it should be
+    /// larger than the largest building.
+    pub max_window_size: f64,
+    /// The steepest slope of the terrain, as height difference per distance.
+    pub slope: f64,
+    /// How far points may be above the surface opened with the smallest window to be ground.
+    pub initial_distance: f64,
+    /// How far points may be above the opened surface at most to be ground.
+    pub max_distance: f64,
+}
+
+impl Default for GroundFilter {
+    fn default() -> Self {
+        GroundFilter {
+            cell_size: 1.,
+            max_window_size: 20.,
+            slope: 1.,
+            initial_distance: 0.5,
+            max_distance: 3.,
+        }
+    }
+}
+
+/// Applies `f`, i.e. min or max, to the values within `half` cells in each row and then in each
+/// column. Cells without a value are ignored.
+fn filter_window(
+    values: &[Option<f64>],
+    width: usize,
+    height: usize,
+    half: usize,
+    f: fn(f64, f64) -> f64,
+) -> Vec<Option<f64>> {
+    let fold = |get: &dyn Fn(usize) -> Option<f64>, i: usize, len: usize| {
+        let (start, end) = (i.saturating_sub(half), (i + half).min(len - 1));
+        (start..=end)
+            .filter_map(get)
+            .fold(None, |acc: Option<f64>, v| {
+                Some(acc.map_or(v, |acc| f(acc, v)))
+            })
+    };
+    let mut rows = vec![None; values.len()];
+    for row in 0..height {
+        let get = |col: usize| values[row * width + col];
+        for col in 0..width {
+            rows[row * width + col] = fold(&get, col, width);
+        }
+    }
+    let mut result = vec![None; values.len()];
+    for col in 0..width {
+        let get = |row: usize| rows[row * width + col];
+        for row in 0..height {
+            result[row * width + col] = fold(&get, row, height);
+        }
+    }
+    result
+}
+
+/// Which points of a grid of lowest heights are ground, see 'GroundFilter::fit'.
+pub struct GroundModel {
+    minimum_heights: HeightRaster,
+    /// The highest height of ground points in each cell, NaN for cells without points.
+    max_ground_heights: Vec<f64>,
+}
+
+impl GroundModel {
+    /// Whether `p` is ground. Points outside of the grid are not.
+    pub fn is_ground(&self, p: &Point3<f64>) -> bool {
+        self.minimum_heights.cell(p).is_some_and(|(col, row)| {
+            p.z <= self.max_ground_heights[row * self.minimum_heights.width() + col]
+        })
+    }
+}
+
+impl GroundFilter {
+    /// Finds the ground in `minimum_heights`, a raster of the 'HeightStatistic::Min' of the points.
+    pub fn fit(&self, minimum_heights: HeightRaster) -> GroundModel {
+        let (width, height) = (minimum_heights.width(), minimum_heights.height());
+        let cell_size = minimum_heights.resolution();
+        let mut surface: Vec<Option<f64>> = (0..height)
+            .flat_map(|row| (0..width).map(move |col| (col, row)))
+            .map(|(col, row)| minimum_heights.get(col, row))
+            .collect();
+        let mut max_ground_heights: Vec<f64> = surface
+            .iter()
+            .map(|v| v.map_or(f64::NAN, |_| f64::INFINITY))
+            .collect();
+        // The windows double in size, like the exponential windows of the paper.
+        let mut previous_window = 1;
+        let mut half = 1;
+        while (2 * half + 1) as f64 * cell_size <= self.max_window_size {
+            let window = 2 * half + 1;
+            let eroded = filter_window(&surface, width, height, half, f64::min);
+            surface = filter_window(&eroded, width, height, half, f64::max);
+            let distance = if window <= 3 {
+                self.initial_distance
+            } else {
+                let distance = self.slope * (window - previous_window) as f64 * cell_size;
+                (self.initial_distance + distance).min(self.max_distance)
+            };
+            for (max_ground_height, opened) in max_ground_heights.iter_mut().zip(&surface) {
+                if let Some(opened) = opened {
+                    *max_ground_height = max_ground_height.min(opened + distance);
+                }
+            }
+            previous_window = window;
+            half *= 2;
+        }
+        GroundModel {
+            minimum_heights,
+            max_ground_heights,
+        }
+    }
+}
+
+/// The labels of the points, with the data type of the classification attribute.
+fn labels(
+    batch: &PointsBatch,
+    mut label: impl FnMut(&Point3<f64>, u16) -> u16,
+) -> Result<AttributeData> {
+    macro_rules! relabel {
+        ($values:ident, $variant:ident, $scalar:ty) => {
+            AttributeData::$variant(
+                batch
+                    .position
+                    .iter()
+                    .zip($values)
+                    .map(|(p, v)| label(p, u16::from(*v)) as $scalar)
+                    .collect(),
+            )
+        };
+    }
+    match &batch.attributes[CLASSIFICATION_ATTRIBUTE] {
+        AttributeData::U8(values) => Ok(relabel!(values, U8, u8)),
+        AttributeData::U16(values) => Ok(relabel!(values, U16, u16)),
+        other => Err(ErrorKind::InvalidInput(format!(
+            "Labels need to be U8 or U16, not {:?}.",
+            other.data_type()
+        ))
+        .into()),
+    }
+}
+
+/// Labels the points of `point_cloud` in `bounding_box`: ground points get `ground_label`, the
+/// others `non_ground_label`, or keep their label if there is none. The points are streamed
+/// twice, once for the grid of lowest heights and once to rewrite their classification, so the
+/// point cloud needs to support 'PointCloud::update_attribute' and have both labels. The heights
+/// of the ground points are added to `dem`, if given, e.g. for a digital elevation model.
+/// Returns the number of ground points.
+pub fn classify_ground<C: PointCloud>(
+    point_cloud: &mut C,
+    bounding_box: &Aabb,
+    filter: &GroundFilter,
+    ground_label: &str,
+    non_ground_label: Option<&str>,
+    mut dem: Option<&mut HeightRaster>,
+) -> Result<usize> {
+    let label_dictionary = point_cloud
+        .label_dictionary()
+        .ok_or_else(|| ErrorKind::InvalidInput("The point cloud has no labels.".to_string()))?;
+    let ground_id = label_dictionary.ids(&[ground_label])?[0];
+    let non_ground_id = match non_ground_label {
+        Some(name) => Some(label_dictionary.ids(&[name])?[0]),
+        None => None,
+    };
+
+    let mut query = PointQuery {
+        location: PointLocation::Aabb(bounding_box.clone()),
+        ..Default::default()
+    };
+    let mut minimum_heights =
+        HeightRaster::new(bounding_box, filter.cell_size, HeightStatistic::Min)?;
+    let num_threads = num_cpus::get();
+    ParallelIterator::new(
+        std::slice::from_ref(&*point_cloud),
+        &query,
+        NUM_POINTS_PER_BATCH,
+        num_threads,
+        num_threads,
+    )
+    .try_for_each_batch(|batch| {
+        minimum_heights.add_points(&batch.position);
+        Ok(())
+    })?;
+    let model = filter.fit(minimum_heights);
+
+    query.attributes = vec![CLASSIFICATION_ATTRIBUTE];
+    let mut num_ground = 0;
+    let update = AttributeUpdate::PerPoint(Box::new(|batch: &PointsBatch| {
+        let mut ground = Vec::new();
+        let labels = labels(batch, |p, label| {
+            if model.is_ground(p) {
+                ground.push(*p);
+                ground_id
+            } else {
+                non_ground_id.unwrap_or(label)
+            }
+        })?;
+        num_ground += ground.len();
+        if let Some(dem) = dem.as_mut() {
+            dem.add_points(&ground);
+        }
+        Ok(labels)
+    }));
+    point_cloud.update_attribute(&query, CLASSIFICATION_ATTRIBUTE, update)?;
+    Ok(num_ground)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ground_filter() {
+        // Sloped terrain of 60 m x 60 m with a building of 10 m x 10 m and 8 m height on it.
+        let terrain_height = |x: f64, y: f64| 0.1 * x + 0.05 * y;
+        let in_building = |x: f64, y: f64| (20. ..30.).contains(&x) && (20. ..30.).contains(&y);
+        let mut points = Vec::new();
+        for i in 0..60 {
+            for j in 0..60 {
+                let (x, y) = (f64::from(i) + 0.5, f64::from(j) + 0.5);
+                let z = terrain_height(x, y);
+                points.push(Point3::new(
+                    x,
+                    y,
+                    if in_building(x, y) { z + 8. } else { z },
+                ));
+            }
+        }
+        let bounding_box = Aabb::new(Point3::new(0., 0., 0.), Point3::new(60., 60., 20.));
+        let mut minimum_heights =
+            HeightRaster::new(&bounding_box, 1., HeightStatistic::Min).unwrap();
+        minimum_heights.add_points(&points);
+
+        let model = GroundFilter::default().fit(minimum_heights);
+        for p in &points {
+            assert_eq!(model.is_ground(p), !in_building(p.x, p.y), "{:?}", p);
+        }
+        // A bush on the ground is not ground, the ground below the roof is.
+        let (x, y) = (5.5, 40.5);
+        assert!(!model.is_ground(&Point3::new(x, y, terrain_height(x, y) + 2.)));
+        let (x, y) = (25.5, 25.5);
+        assert!(model.is_ground(&Point3::new(x, y, terrain_height(x, y))));
+        assert!(!model.is_ground(&Point3::new(100., 0., 0.)));
+    }
+}
@@ -1,18 +1,164 @@
+use crate::budget::{num_points_for_query, BudgetedCloud, PointBudget};
+use nalgebra::Isometry3;
+use object_store_provider::register_object_stores;
+use point_viewer::attributes::AttributeDataType;
+use point_viewer::coordinates::{CoordinateSystem, Reprojection};
 use point_viewer::data_provider::{DataProvider, DataProviderFactory};
+use point_viewer::downsample::VoxelSize;
 use point_viewer::errors::*;
-use point_viewer::geometry::Aabb;
-use point_viewer::iterator::{ParallelIterator, PointCloud, PointQuery};
-use point_viewer::octree::Octree;
+use point_viewer::geometry::{fit_obb, Aabb, Obb};
+use point_viewer::iterator::{ParallelIterator, PointCloud, PointLocation, PointQuery};
+use point_viewer::layout::{lay_out, LaidOutBatch};
+use point_viewer::octree::{NodeCache, NodeCacheStats, Octree};
 use point_viewer::s2_cells::S2Cells;
-use point_viewer::{PointsBatch, NUM_POINTS_PER_BATCH};
+use point_viewer::segmentation::{sample_nodes, DetectedPlane, PlaneDetection};
+use point_viewer::statistics::PointStatistics;
+use point_viewer::{PointCloudMeta, PointsBatch, NUM_POINTS_PER_BATCH};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
-enum PointClouds {
-    Octrees(Vec<Octree>),
-    S2Cells(Vec<S2Cells>),
+pub mod budget;
+pub mod diff;
+pub mod ground;
+pub mod raster;
+
+enum PointCloudKind {
+    Octree(Octree),
+    S2Cells(S2Cells),
 }
 
+impl PointCloudKind {
+    fn bounding_box(&self) -> &Aabb {
+        match self {
+            PointCloudKind::Octree(octree) => octree.bounding_box(),
+            PointCloudKind::S2Cells(s2_cells) => s2_cells.bounding_box(),
+        }
+    }
+
+    fn coordinate_system(&self) -> Option<CoordinateSystem> {
+        match self {
+            PointCloudKind::Octree(octree) => octree.coordinate_system(),
+            PointCloudKind::S2Cells(s2_cells) => s2_cells.coordinate_system(),
+        }
+    }
+
+    fn attribute_data_types(&self) -> &HashMap<String, AttributeDataType> {
+        match self {
+            PointCloudKind::Octree(octree) => octree.meta().attribute_data_types(),
+            PointCloudKind::S2Cells(s2_cells) => s2_cells.meta().attribute_data_types(),
+        }
+    }
+}
+
+/// One of the point clouds of the client, placed in the common frame of the queries.
+struct Cloud {
+    point_cloud: PointCloudKind,
+    /// From the coordinate system of the cloud into the one of the client. None if they are the
+    /// same, or if either is not known.
+    reprojection: Option<Reprojection>,
+    /// Applied after the reprojection. None for clouds that are already in the common frame.
+    global_from_cloud: Option<Isometry3<f64>>,
+    visible: bool,
+}
+
+impl Cloud {
+    fn bounding_box(&self) -> Aabb {
+        let mut bounding_box = self.point_cloud.bounding_box().clone();
+        if let Some(reprojection) = &self.reprojection {
+            bounding_box = reprojection.transform_bounding_box(&bounding_box);
+        }
+        match &self.global_from_cloud {
+            Some(global_from_cloud) => bounding_box.transform(global_from_cloud),
+            None => bounding_box,
+        }
+    }
+
+    /// The transform from the cloud into the common frame, None if the reprojection is not rigid,
+    /// e.g. from UTM.
+    fn rigid_global_from_cloud(&self) -> Option<Isometry3<f64>> {
+        let global_from_cloud = self.global_from_cloud.unwrap_or_else(Isometry3::identity);
+        match &self.reprojection {
+            Some(reprojection) => reprojection
+                .isometry()
+                .map(|isometry| global_from_cloud * isometry),
+            None => Some(global_from_cloud),
+        }
+    }
+
+    /// The query in the frame of this cloud. If the reprojection is not rigid, the location is a
+    /// box around the query location without the clip planes, and the points need to be filtered
+    /// by 'keep_matching'.
+    fn local_query<'a>(&self, point_query: &PointQuery<'a>) -> Result<PointQuery<'a>> {
+        let mut local_query = point_query.clone();
+        // The output transform applies to the common frame, after 'to_global'.
+        local_query.output_from_query = None;
+        if let Some(global_from_cloud) = &self.global_from_cloud {
+            let cloud_from_global = global_from_cloud.inverse();
+            local_query.location = point_query.location.transformed(&cloud_from_global)?;
+            for half_space in &mut local_query.clip_planes {
+                *half_space = half_space.transformed(&cloud_from_global);
+            }
+        }
+        if let Some(reprojection) = &self.reprojection {
+            let inverse = reprojection.inverse();
+            local_query.location = inverse.transform_location(&local_query.location)?;
+            match inverse.isometry() {
+                Some(isometry) => {
+                    for half_space in &mut local_query.clip_planes {
+                        *half_space = half_space.transformed(isometry);
+                    }
+                }
+                None => local_query.clip_planes.clear(),
+            }
+        }
+        Ok(local_query)
+    }
+
+    fn to_global(&self, batch: &mut PointsBatch) {
+        if let Some(reprojection) = &self.reprojection {
+            reprojection.transform_batch(batch);
+        }
+        if let Some(global_from_cloud) = &self.global_from_cloud {
+            for position in &mut batch.position {
+                *position = global_from_cloud * *position;
+            }
+        }
+    }
+
+    /// Removes the points of a batch in the common frame that are not in the location or inside
+    /// the clip planes of `point_query`, which the query of a cloud with a non-rigid reprojection
+    /// only approximates.
+    fn keep_matching(&self, point_query: &PointQuery, batch: &mut PointsBatch) {
+        if self.rigid_global_from_cloud().is_some()
+            || (matches!(point_query.location, PointLocation::AllPoints)
+                && point_query.clip_planes.is_empty())
+        {
+            return;
+        }
+        let culling = point_query.location.get_point_culling();
+        let keep: Vec<bool> = batch
+            .position
+            .iter()
+            .map(|p| {
+                culling.contains(p)
+                    && point_query
+                        .clip_planes
+                        .iter()
+                        .all(|half_space| half_space.contains(p))
+            })
+            .collect();
+        batch.retain(&keep);
+    }
+}
+
+/// Queries several octrees and S2 point clouds together, as if they were one point cloud. Each
+/// cloud can be placed by a transform and hidden from the queries. If the client has a coordinate
+/// system, the clouds that know theirs are reprojected into it.
 pub struct PointCloudClient {
-    point_clouds: PointClouds,
+    clouds: Vec<Cloud>,
+    node_cache: Option<Arc<NodeCache>>,
     aabb: Aabb,
     num_points_per_batch: usize,
     num_threads: usize,
@@ -20,10 +166,76 @@ pub struct PointCloudClient {
 }
 
 impl PointCloudClient {
+    /// The bounding box of all point clouds, including the hidden ones.
     pub fn bounding_box(&self) -> &Aabb {
         &self.aabb
     }
 
+    /// The hit and miss counters of the node cache, if there is one.
+    pub fn node_cache_stats(&self) -> Option<NodeCacheStats> {
+        self.node_cache
+            .as_ref()
+            .map(|node_cache| node_cache.stats())
+    }
+
+    /// The number of point clouds, in the order of the locations they were opened from.
+    pub fn num_clouds(&self) -> usize {
+        self.clouds.len()
+    }
+
+    fn cloud_mut(&mut self, index: usize) -> Result<&mut Cloud> {
+        let num_clouds = self.clouds.len();
+        self.clouds.get_mut(index).ok_or_else(|| {
+            ErrorKind::InvalidInput(format!(
+                "There is no point cloud {}, there are {}.",
+                index, num_clouds
+            ))
+            .into()
+        })
+    }
+
+    /// Hidden point clouds are skipped by the queries.
+    pub fn set_visible(&mut self, index: usize, visible: bool) -> Result<()> {
+        self.cloud_mut(index)?.visible = visible;
+        Ok(())
+    }
+
+    pub fn is_visible(&self, index: usize) -> bool {
+        self.clouds.get(index).map_or(false, |cloud| cloud.visible)
+    }
+
+    /// The attributes that queries can ask for: those that all visible point clouds have with
+    /// the same data type.
+    pub fn attribute_data_types(&self) -> HashMap<String, AttributeDataType> {
+        let mut visible = self.clouds.iter().filter(|cloud| cloud.visible);
+        let mut data_types = match visible.next() {
+            Some(cloud) => cloud.point_cloud.attribute_data_types().clone(),
+            None => return HashMap::new(),
+        };
+        for cloud in visible {
+            let other = cloud.point_cloud.attribute_data_types();
+            data_types.retain(|name, data_type| other.get(name) == Some(data_type));
+        }
+        data_types
+    }
+
+    /// Places the point cloud `index` in the frame of the queries.
+    pub fn set_global_from_cloud(
+        &mut self,
+        index: usize,
+        global_from_cloud: Isometry3<f64>,
+    ) -> Result<()> {
+        self.cloud_mut(index)?.global_from_cloud = Some(global_from_cloud);
+        self.aabb = bounding_box(&self.clouds);
+        Ok(())
+    }
+
+    pub fn global_from_cloud(&self, index: usize) -> Option<Isometry3<f64>> {
+        self.clouds
+            .get(index)
+            .map(|cloud| cloud.global_from_cloud.unwrap_or_else(Isometry3::identity))
+    }
+
     fn for_each<C, F>(&self, point_cloud: &[C], point_query: &PointQuery, mut func: F) -> Result<()>
     where
         C: PointCloud,
@@ -39,33 +251,499 @@ impl PointCloudClient {
         parallel_iterator.try_for_each_batch(&mut func)
     }
 
-    pub fn for_each_point_data<F>(&self, point_query: &PointQuery, func: F) -> Result<()>
+    fn for_each_laid_out<C, F>(
+        &self,
+        point_cloud: &[C],
+        point_query: &PointQuery,
+        mut func: F,
+    ) -> Result<()>
+    where
+        C: PointCloud,
+        F: FnMut(LaidOutBatch) -> Result<()>,
+    {
+        let mut parallel_iterator = ParallelIterator::new(
+            point_cloud,
+            point_query,
+            self.num_points_per_batch,
+            self.num_threads,
+            self.buffer_size,
+        );
+        parallel_iterator.try_for_each_laid_out_batch(&mut func)
+    }
+
+    /// Returns the statistics of the points matching `point_query` in all visible point clouds.
+    /// The bounding boxes of the points of transformed clouds are the boxes around their
+    /// transformed bounding boxes. The points of clouds that are not rigidly reprojected are
+    /// streamed and summarized one by one instead.
+    pub fn statistics(&self, point_query: &PointQuery) -> Result<PointStatistics> {
+        let mut statistics = PointStatistics::default();
+        for cloud in self.clouds.iter().filter(|cloud| cloud.visible) {
+            let global_from_cloud = match cloud.rigid_global_from_cloud() {
+                Some(global_from_cloud) => global_from_cloud,
+                None => {
+                    self.for_each_cloud_point_data(cloud, point_query, |batch| {
+                        statistics.add_batch(&batch);
+                        Ok(())
+                    })?;
+                    continue;
+                }
+            };
+            let local_query = cloud.local_query(point_query)?;
+            let mut cloud_statistics = match &cloud.point_cloud {
+                PointCloudKind::Octree(octree) => octree.statistics(&local_query)?,
+                PointCloudKind::S2Cells(s2_cells) => s2_cells.statistics(&local_query)?,
+            };
+            let output_from_cloud = match &point_query.output_from_query {
+                Some(output_from_query) => Some(output_from_query * global_from_cloud),
+                None if cloud.global_from_cloud.is_some() || cloud.reprojection.is_some() => {
+                    Some(global_from_cloud)
+                }
+                None => None,
+            };
+            if let (Some(bounding_box), Some(output_from_cloud)) =
+                (&cloud_statistics.bounding_box, &output_from_cloud)
+            {
+                cloud_statistics.bounding_box = Some(bounding_box.transform(output_from_cloud));
+            }
+            statistics.merge(&cloud_statistics);
+        }
+        Ok(statistics)
+    }
+
+    /// Calls `func` with the batches of points matching `point_query`, from one visible point
+    /// cloud after the other. The points are in the frame of the query, or in its output frame.
+    /// Downsampling happens per point cloud, so overlapping clouds can contribute a point each to
+    /// the same voxel.
+    pub fn for_each_point_data<F>(&self, point_query: &PointQuery, mut func: F) -> Result<()>
+    where
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        for cloud in self.clouds.iter().filter(|cloud| cloud.visible) {
+            self.for_each_cloud_point_data(cloud, point_query, &mut func)?;
+        }
+        Ok(())
+    }
+
+    /// Like 'for_each_point_data', but the batches are in the layout of `point_query`. The points
+    /// of clouds that are not transformed are converted by the threads that read them.
+    pub fn for_each_laid_out_point_data<F>(
+        &self,
+        point_query: &PointQuery,
+        mut func: F,
+    ) -> Result<()>
+    where
+        F: FnMut(LaidOutBatch) -> Result<()>,
+    {
+        for cloud in self.clouds.iter().filter(|cloud| cloud.visible) {
+            if cloud.global_from_cloud.is_some() || cloud.reprojection.is_some() {
+                self.for_each_cloud_point_data(cloud, point_query, |batch| {
+                    func(lay_out(batch, point_query.layout))
+                })?;
+                continue;
+            }
+            match &cloud.point_cloud {
+                PointCloudKind::Octree(octree) => {
+                    self.for_each_laid_out(std::slice::from_ref(octree), point_query, &mut func)?
+                }
+                PointCloudKind::S2Cells(s2_cells) => {
+                    self.for_each_laid_out(std::slice::from_ref(s2_cells), point_query, &mut func)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a tight oriented bounding box of the points matching `point_query`, see
+    /// 'geometry::fit_obb', or None if there are none. All their positions are kept in memory.
+    pub fn fit_obb(&self, point_query: &PointQuery) -> Result<Option<Obb>> {
+        let mut positions = Vec::new();
+        self.for_each_point_data(point_query, |mut batch| {
+            positions.append(&mut batch.position);
+            Ok(())
+        })?;
+        Ok(fit_obb(&positions))
+    }
+
+    /// Detects planes in the points matching `point_query` in all visible point clouds, see
+    /// 'segmentation'. The nodes of each cloud are sampled separately, and the samples are moved
+    /// into the frame of the query, or its output frame.
+    pub fn detect_planes(
+        &self,
+        point_query: &PointQuery,
+        detection: &PlaneDetection,
+    ) -> Result<Vec<DetectedPlane>> {
+        let mut samples = Vec::new();
+        for cloud in self.clouds.iter().filter(|cloud| cloud.visible) {
+            let local_query = cloud.local_query(point_query)?;
+            let (max_samples, seed) = (detection.max_samples_per_node, detection.seed);
+            let cloud_samples = match &cloud.point_cloud {
+                PointCloudKind::Octree(octree) => {
+                    sample_nodes(octree, &local_query, max_samples, seed)?
+                }
+                PointCloudKind::S2Cells(s2_cells) => {
+                    sample_nodes(s2_cells, &local_query, max_samples, seed)?
+                }
+            };
+            for mut sample in cloud_samples {
+                let mut batch = PointsBatch {
+                    position: std::mem::take(&mut sample.points),
+                    attributes: BTreeMap::new(),
+                };
+                let num_sampled = batch.position.len();
+                cloud.to_global(&mut batch);
+                cloud.keep_matching(point_query, &mut batch);
+                point_query.transform_output(&mut batch);
+                // The samples outside of the location stand for points outside of it.
+                sample.num_points = (sample.num_points * batch.position.len())
+                    .checked_div(num_sampled)
+                    .unwrap_or(sample.num_points);
+                sample.points = batch.position;
+                samples.push(sample);
+            }
+        }
+        Ok(detection.detect_in_samples(samples))
+    }
+
+    /// Returns up to `max_points` of the points matching `point_query` from `cursor` on, and the
+    /// cursor of the next page, so that e.g. a stateless server can return a huge result over
+    /// many requests. The points come from one visible point cloud and node after the other.
+    /// Every page reads the node it starts in again up to the cursor, and the point clouds must
+    /// not change between the pages. Downsampled queries can not be paginated, as their voxels
+    /// span nodes.
+    pub fn query_page(
+        &self,
+        point_query: &PointQuery,
+        cursor: &QueryCursor,
+        max_points: usize,
+    ) -> Result<QueryPage> {
+        if point_query.downsample.is_some() {
+            return Err(ErrorKind::InvalidInput(
+                "Downsampled queries can not be paginated.".to_string(),
+            )
+            .into());
+        }
+        if max_points == 0 {
+            return Err(ErrorKind::InvalidInput(
+                "A page needs to hold at least one point.".to_string(),
+            )
+            .into());
+        }
+        let mut page = PointsBatch {
+            position: Vec::new(),
+            attributes: BTreeMap::new(),
+        };
+        let mut cursor = cursor.clone();
+        while let Some(cloud) = self.clouds.get(cursor.cloud) {
+            if cloud.visible {
+                let is_full = match &cloud.point_cloud {
+                    PointCloudKind::Octree(octree) => fill_page(
+                        octree,
+                        cloud,
+                        point_query,
+                        &mut cursor,
+                        max_points,
+                        &mut page,
+                    )?,
+                    PointCloudKind::S2Cells(s2_cells) => fill_page(
+                        s2_cells,
+                        cloud,
+                        point_query,
+                        &mut cursor,
+                        max_points,
+                        &mut page,
+                    )?,
+                };
+                if is_full {
+                    return Ok(QueryPage {
+                        batch: page,
+                        next: Some(cursor),
+                    });
+                }
+            }
+            cursor = QueryCursor {
+                cloud: cursor.cloud + 1,
+                node: None,
+                offset: 0,
+            };
+        }
+        Ok(QueryPage {
+            batch: page,
+            next: None,
+        })
+    }
+
+    fn for_each_cloud_point_data<F>(
+        &self,
+        cloud: &Cloud,
+        point_query: &PointQuery,
+        func: F,
+    ) -> Result<()>
     where
         F: FnMut(PointsBatch) -> Result<()>,
     {
-        match &self.point_clouds {
-            PointClouds::Octrees(octrees) => self.for_each(octrees, point_query, func),
-            PointClouds::S2Cells(s2_cells) => self.for_each(s2_cells, point_query, func),
+        let local_query = cloud.local_query(point_query)?;
+        match &cloud.point_cloud {
+            PointCloudKind::Octree(octree) => {
+                self.for_each_in_cloud(cloud, octree, &local_query, point_query, func)
+            }
+            PointCloudKind::S2Cells(s2_cells) => {
+                self.for_each_in_cloud(cloud, s2_cells, &local_query, point_query, func)
+            }
+        }
+    }
+
+    /// Calls `func` with the points of `point_cloud`, which is `cloud` or a part of it, matching
+    /// `local_query`, in the frame of `point_query`.
+    fn for_each_in_cloud<C, F>(
+        &self,
+        cloud: &Cloud,
+        point_cloud: &C,
+        local_query: &PointQuery,
+        point_query: &PointQuery,
+        mut func: F,
+    ) -> Result<()>
+    where
+        C: PointCloud,
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        let func = |mut batch: PointsBatch| {
+            cloud.to_global(&mut batch);
+            cloud.keep_matching(point_query, &mut batch);
+            if batch.position.is_empty() {
+                return Ok(());
+            }
+            point_query.transform_output(&mut batch);
+            func(batch)
+        };
+        self.for_each(std::slice::from_ref(point_cloud), local_query, func)
+    }
+
+    /// Like 'for_each_point_data', but with at most `budget.max_points` points. If the query
+    /// matches more, the budget is split between the visible point clouds by the number of points
+    /// in the nodes they read, and each returns the coarsest levels of detail that fit into its
+    /// share, see 'budget'. Returns whether no points were left out, i.e. whether the result is
+    /// the same as the one of 'for_each_point_data' with the downsampling of the budget.
+    pub fn for_each_point_data_within_budget<F>(
+        &self,
+        point_query: &PointQuery,
+        budget: &PointBudget,
+        mut func: F,
+    ) -> Result<bool>
+    where
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        let mut point_query = point_query.clone();
+        if point_query.downsample.is_none() {
+            point_query.downsample = budget.target_density.map(VoxelSize::for_density);
+        }
+        let clouds: Vec<&Cloud> = self.clouds.iter().filter(|cloud| cloud.visible).collect();
+        let local_queries = clouds
+            .iter()
+            .map(|cloud| cloud.local_query(&point_query))
+            .collect::<Result<Vec<_>>>()?;
+        let num_points: Vec<usize> = clouds
+            .iter()
+            .zip(&local_queries)
+            .map(|(cloud, local_query)| match &cloud.point_cloud {
+                PointCloudKind::Octree(octree) => num_points_for_query(octree, local_query),
+                PointCloudKind::S2Cells(s2_cells) => num_points_for_query(s2_cells, local_query),
+            })
+            .collect();
+        let total_num_points: usize = num_points.iter().sum();
+        if total_num_points <= budget.max_points {
+            self.for_each_point_data(&point_query, func)?;
+            return Ok(true);
+        }
+
+        let mut is_exhaustive = true;
+        for ((cloud, local_query), num_points) in clouds.iter().zip(&local_queries).zip(num_points)
+        {
+            let max_points = (budget.max_points as u128 * num_points as u128
+                / total_num_points as u128) as usize;
+            is_exhaustive &= match &cloud.point_cloud {
+                PointCloudKind::Octree(octree) => {
+                    let (octree, is_exhaustive) =
+                        BudgetedCloud::new(octree, local_query, max_points, |node_id| {
+                            node_id.level()
+                        });
+                    self.for_each_in_cloud(cloud, &octree, local_query, &point_query, &mut func)?;
+                    is_exhaustive
+                }
+                PointCloudKind::S2Cells(s2_cells) => {
+                    let (s2_cells, is_exhaustive) =
+                        BudgetedCloud::new(s2_cells, local_query, max_points, |_| 0);
+                    self.for_each_in_cloud(cloud, &s2_cells, local_query, &point_query, &mut func)?;
+                    is_exhaustive
+                }
+            };
         }
+        Ok(is_exhaustive)
     }
 }
 
+/// Where a paginated query continues, see 'PointCloudClient::query_page'. It is opaque, but can
+/// be passed between requests as a string with 'to_string' and 'parse'.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QueryCursor {
+    cloud: usize,
+    /// The node to continue in, None for the first node of the cloud.
+    node: Option<String>,
+    /// The number of matching points of the node that earlier pages returned.
+    offset: usize,
+}
+
+impl QueryCursor {
+    /// The cursor of the first page.
+    pub fn start() -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Display for QueryCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.cloud,
+            self.offset,
+            self.node.as_deref().unwrap_or("")
+        )
+    }
+}
+
+impl FromStr for QueryCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::from(ErrorKind::InvalidInput(format!("Invalid cursor '{}'.", s)));
+        let mut parts = s.splitn(3, ':');
+        let cloud = parts.next().and_then(|cloud| cloud.parse().ok());
+        let offset = parts.next().and_then(|offset| offset.parse().ok());
+        let node = parts.next();
+        match (cloud, offset, node) {
+            (Some(cloud), Some(offset), Some(node)) => Ok(QueryCursor {
+                cloud,
+                node: Some(node.to_string()).filter(|node| !node.is_empty()),
+                offset,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The points of one request of a paginated query, see 'PointCloudClient::query_page'.
+#[derive(Debug)]
+pub struct QueryPage {
+    pub batch: PointsBatch,
+    /// Where the next page starts, None if there are no more points. The last page can also be
+    /// empty.
+    pub next: Option<QueryCursor>,
+}
+
+/// Adds the matching points of `cloud` from `cursor` on to `page` until it has `max_points`, and
+/// moves the cursor behind them. Returns whether the page is full.
+fn fill_page<C: PointCloud>(
+    point_cloud: &C,
+    cloud: &Cloud,
+    point_query: &PointQuery,
+    cursor: &mut QueryCursor,
+    max_points: usize,
+    page: &mut PointsBatch,
+) -> Result<bool> {
+    let local_query = cloud.local_query(point_query)?;
+    let node_ids = point_cloud.nodes_for_query(&local_query);
+    let first = match &cursor.node {
+        Some(node) => node_ids
+            .iter()
+            .position(|node_id| node_id.to_string() == *node)
+            .ok_or_else(|| {
+                ErrorKind::InvalidInput(format!(
+                    "The query has no node {} in point cloud {}, the cursor is from another \
+                     query.",
+                    node, cursor.cloud
+                ))
+            })?,
+        None => 0,
+    };
+    for (i, node_id) in node_ids.iter().enumerate().skip(first) {
+        // The points that earlier pages returned.
+        let mut num_to_skip = if i == first { cursor.offset } else { 0 };
+        let mut num_returned = num_to_skip;
+        let mut is_full = false;
+        let result = point_cloud.stream_points_for_query_in_node(
+            &local_query,
+            *node_id,
+            NUM_POINTS_PER_BATCH,
+            |mut batch| {
+                cloud.to_global(&mut batch);
+                cloud.keep_matching(point_query, &mut batch);
+                if num_to_skip >= batch.position.len() {
+                    num_to_skip -= batch.position.len();
+                    return Ok(());
+                }
+                let mut batch = batch.split_off(num_to_skip);
+                num_to_skip = 0;
+                let num_missing = max_points - page.position.len();
+                batch.split_off(num_missing.min(batch.position.len()));
+                num_returned += batch.position.len();
+                point_query.transform_output(&mut batch);
+                page.append(&mut batch).map_err(Error::from)?;
+                // The rest of the node is not read once the page is full.
+                if page.position.len() == max_points {
+                    is_full = true;
+                    return Err("The page is full.".into());
+                }
+                Ok(())
+            },
+        );
+        match result {
+            Ok(()) => (),
+            Err(_) if is_full => (),
+            Err(err) => return Err(err),
+        }
+        if is_full {
+            cursor.node = Some(node_id.to_string());
+            cursor.offset = num_returned;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn bounding_box(clouds: &[Cloud]) -> Aabb {
+    let mut boxes = clouds.iter().map(Cloud::bounding_box);
+    let first = boxes.next().unwrap_or_else(Aabb::zero);
+    boxes.fold(first, |mut united, bounding_box| {
+        united.grow(*bounding_box.min());
+        united.grow(*bounding_box.max());
+        united
+    })
+}
+
 pub struct PointCloudClientBuilder<'a> {
     locations: &'a [String],
     data_provider_factory: DataProviderFactory,
     num_points_per_batch: usize,
     num_threads: usize,
     buffer_size: usize,
+    node_cache_size_bytes: usize,
+    global_from_clouds: Vec<Option<Isometry3<f64>>>,
+    coordinate_system: Option<CoordinateSystem>,
 }
 
 impl<'a> PointCloudClientBuilder<'a> {
+    /// The point clouds can be in directories or in 's3://' and 'gs://' buckets, unless another
+    /// 'data_provider_factory' is set.
     pub fn new(locations: &'a [String]) -> Self {
         Self {
             locations,
-            data_provider_factory: DataProviderFactory::new(),
+            data_provider_factory: register_object_stores(DataProviderFactory::new()),
             num_points_per_batch: NUM_POINTS_PER_BATCH,
             num_threads: std::cmp::max(1, num_cpus::get() - 1),
             buffer_size: 4,
+            node_cache_size_bytes: 0,
+            global_from_clouds: vec![None; locations.len()],
+            coordinate_system: None,
         }
     }
 
@@ -89,6 +767,28 @@ impl<'a> PointCloudClientBuilder<'a> {
         self
     }
 
+    /// Keeps up to this many bytes of decoded octree nodes in memory. Zero, the default, disables
+    /// the cache.
+    pub fn node_cache_size_bytes(mut self, node_cache_size_bytes: usize) -> Self {
+        self.node_cache_size_bytes = node_cache_size_bytes;
+        self
+    }
+
+    /// Places the point cloud from the location with this index in the frame of the queries. The
+    /// other clouds are already in that frame.
+    pub fn global_from_cloud(mut self, index: usize, global_from_cloud: Isometry3<f64>) -> Self {
+        self.global_from_clouds[index] = Some(global_from_cloud);
+        self
+    }
+
+    /// The coordinate system of the queries and the returned points. The point clouds that are
+    /// tagged with a different one are reprojected into it, the untagged ones are assumed to be
+    /// in it already. Without it, no point cloud is reprojected.
+    pub fn coordinate_system(mut self, coordinate_system: CoordinateSystem) -> Self {
+        self.coordinate_system = Some(coordinate_system);
+        self
+    }
+
     pub fn build(self) -> Result<PointCloudClient> {
         if self.locations.is_empty() {
             return Err("No locations specified for point cloud client.".into());
@@ -98,42 +798,44 @@ impl<'a> PointCloudClientBuilder<'a> {
             .iter()
             .map(|location| self.data_provider_factory.generate_data_provider(location))
             .collect::<Result<Vec<Box<dyn DataProvider>>>>()?;
-        let mut aabb: Option<Aabb> = None;
-        let unite = |bbox: &Aabb, with: &mut Option<Aabb>| {
-            let b = with.get_or_insert(bbox.clone());
-            b.grow(*bbox.min());
-            b.grow(*bbox.max());
-        };
-        let first_meta = data_providers[0].meta_proto()?;
-        let point_clouds = if first_meta.version <= 11 || first_meta.has_octree() {
-            PointClouds::Octrees(
-                data_providers
-                    .into_iter()
-                    .map(|provider| {
-                        Octree::from_data_provider(provider).map(|octree| {
-                            unite(octree.bounding_box(), &mut aabb);
-                            octree
-                        })
-                    })
-                    .collect::<Result<Vec<Octree>>>()?,
-            )
+        let node_cache = if self.node_cache_size_bytes > 0 {
+            Some(Arc::new(NodeCache::new(self.node_cache_size_bytes)))
         } else {
-            PointClouds::S2Cells(
-                data_providers
-                    .into_iter()
-                    .map(|provider| {
-                        S2Cells::from_data_provider(provider).map(|s2_cells| {
-                            unite(s2_cells.bounding_box(), &mut aabb);
-                            s2_cells
-                        })
-                    })
-                    .collect::<Result<Vec<S2Cells>>>()?,
-            )
+            None
         };
+        let coordinate_system = self.coordinate_system;
+        // Every location can hold either kind of point cloud.
+        let clouds = data_providers
+            .into_iter()
+            .zip(self.global_from_clouds)
+            .map(|(provider, global_from_cloud)| {
+                let meta = provider.meta_proto()?;
+                let point_cloud = if meta.version <= 11 || meta.has_octree() {
+                    let mut octree = Octree::from_data_provider(provider)?;
+                    if let Some(node_cache) = &node_cache {
+                        octree.set_node_cache(Arc::clone(node_cache));
+                    }
+                    PointCloudKind::Octree(octree)
+                } else {
+                    PointCloudKind::S2Cells(S2Cells::from_data_provider(provider)?)
+                };
+                let reprojection = match (point_cloud.coordinate_system(), coordinate_system) {
+                    (Some(from), Some(to)) if from != to => Some(Reprojection::new(from, to)),
+                    _ => None,
+                };
+                Ok(Cloud {
+                    point_cloud,
+                    reprojection,
+                    global_from_cloud,
+                    visible: true,
+                })
+            })
+            .collect::<Result<Vec<Cloud>>>()?;
 
         Ok(PointCloudClient {
-            point_clouds,
-            aabb: aabb.unwrap_or_else(Aabb::zero),
+            aabb: bounding_box(&clouds),
+            clouds,
+            node_cache,
             num_points_per_batch: self.num_points_per_batch,
             num_threads: self.num_threads,
             buffer_size: self.buffer_size,
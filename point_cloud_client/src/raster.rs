@@ -0,0 +1,297 @@
+//! Rasterization of point heights into a grid, e.g. for a digital elevation model.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use nalgebra::Point3;
+use point_viewer::errors::*;
+use point_viewer::geometry::Aabb;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Which value of the heights of the points in a cell ends up in the raster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeightStatistic {
+    Min,
+    Max,
+    Mean,
+}
+
+impl FromStr for HeightStatistic {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "min" => Ok(HeightStatistic::Min),
+            "max" => Ok(HeightStatistic::Max),
+            "mean" => Ok(HeightStatistic::Mean),
+            _ => Err(ErrorKind::InvalidInput(format!("Unknown height statistic '{}'.", s)).into()),
+        }
+    }
+}
+
+/// The value of cells without points in ASCII grids.
+const ASCII_GRID_NO_DATA: f64 = -9999.;
+
+/// A grid of cells in the x-y plane holding a statistic of the heights of the points that fell
+/// into them. Memory depends only on the number of cells, so points can be added batch by batch
+/// while streaming a point cloud of any size. Row 0 is the northernmost, i.e. at the maximum y.
+pub struct HeightRaster {
+    min_x: f64,
+    max_y: f64,
+    resolution: f64,
+    width: usize,
+    height: usize,
+    statistic: HeightStatistic,
+    /// The minimum, maximum or sum of the heights in every cell.
+    values: Vec<f64>,
+    counts: Vec<u32>,
+}
+
+impl HeightRaster {
+    /// Creates a raster covering the x-y extent of `bounding_box` with square cells of
+    /// `resolution` edge length.
+    pub fn new(bounding_box: &Aabb, resolution: f64, statistic: HeightStatistic) -> Result<Self> {
+        if resolution.is_nan() || resolution <= 0. {
+            return Err(ErrorKind::InvalidInput(format!(
+                "The resolution must be positive, but is {}.",
+                resolution
+            ))
+            .into());
+        }
+        let diag = bounding_box.diag();
+        let width = ((diag.x / resolution).ceil() as usize).max(1);
+        let height = ((diag.y / resolution).ceil() as usize).max(1);
+        let num_cells = width.checked_mul(height).ok_or_else(|| {
+            ErrorKind::InvalidInput(format!("A raster of {}x{} is too big.", width, height))
+        })?;
+        let initial_value = match statistic {
+            HeightStatistic::Min => f64::INFINITY,
+            HeightStatistic::Max => f64::NEG_INFINITY,
+            HeightStatistic::Mean => 0.,
+        };
+        Ok(HeightRaster {
+            min_x: bounding_box.min().x,
+            max_y: bounding_box.max().y,
+            resolution,
+            width,
+            height,
+            statistic,
+            values: vec![initial_value; num_cells],
+            counts: vec![0; num_cells],
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn resolution(&self) -> f64 {
+        self.resolution
+    }
+
+    /// The column and row of the cell containing the x-y position of `p`, or None if it is
+    /// outside of the raster.
+    pub fn cell(&self, p: &Point3<f64>) -> Option<(usize, usize)> {
+        self.cell_index(p).map(|i| (i % self.width, i / self.width))
+    }
+
+    fn cell_index(&self, p: &Point3<f64>) -> Option<usize> {
+        let col = ((p.x - self.min_x) / self.resolution).floor();
+        let row = ((self.max_y - p.y) / self.resolution).floor();
+        if col < 0. || row < 0. {
+            return None;
+        }
+        // Points on the maximum edge of the raster belong to the last cell.
+        let (col, row) = (col as usize, row as usize);
+        let col = if col == self.width { col - 1 } else { col };
+        let row = if row == self.height { row - 1 } else { row };
+        if col < self.width && row < self.height {
+            Some(row * self.width + col)
+        } else {
+            None
+        }
+    }
+
+    /// Adds the heights of `points`. Points outside of the raster are ignored.
+    pub fn add_points(&mut self, points: &[Point3<f64>]) {
+        for p in points {
+            let i = match self.cell_index(p) {
+                Some(i) => i,
+                None => continue,
+            };
+            let value = &mut self.values[i];
+            match self.statistic {
+                HeightStatistic::Min => *value = value.min(p.z),
+                HeightStatistic::Max => *value = value.max(p.z),
+                HeightStatistic::Mean => *value += p.z,
+            }
+            self.counts[i] += 1;
+        }
+    }
+
+    /// The statistic of the cell in `row` and `col`, or None if no points fell into it.
+    pub fn get(&self, col: usize, row: usize) -> Option<f64> {
+        let i = row * self.width + col;
+        match self.counts[i] {
+            0 => None,
+            count => match self.statistic {
+                HeightStatistic::Mean => Some(self.values[i] / f64::from(count)),
+                _ => Some(self.values[i]),
+            },
+        }
+    }
+
+    /// Writes the raster in the ESRI ASCII grid format.
+    pub fn write_ascii_grid(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "ncols {}", self.width)?;
+        writeln!(writer, "nrows {}", self.height)?;
+        writeln!(writer, "xllcorner {}", self.min_x)?;
+        writeln!(
+            writer,
+            "yllcorner {}",
+            self.max_y - self.height as f64 * self.resolution
+        )?;
+        writeln!(writer, "cellsize {}", self.resolution)?;
+        writeln!(writer, "NODATA_value {}", ASCII_GRID_NO_DATA)?;
+        for row in 0..self.height {
+            let line: Vec<String> = (0..self.width)
+                .map(|col| self.get(col, row).unwrap_or(ASCII_GRID_NO_DATA).to_string())
+                .collect();
+            writeln!(writer, "{}", line.join(" "))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the raster as an uncompressed single band 32 bit float GeoTIFF. Cells without points
+    /// are NaN. The coordinate system of the points is not written into the geokeys yet.
+    pub fn write_geotiff(&self, mut writer: impl Write) -> Result<()> {
+        const TYPE_ASCII: u16 = 2;
+        const TYPE_SHORT: u16 = 3;
+        const TYPE_LONG: u16 = 4;
+        const TYPE_DOUBLE: u16 = 12;
+        const NUM_ENTRIES: u32 = 15;
+
+        let image_bytes = self.width as u64 * self.height as u64 * 4;
+        // Header, directory and the values that do not fit into the directory entries.
+        let ifd_offset = 8;
+        let pixel_scale_offset = ifd_offset + 2 + NUM_ENTRIES * 12 + 4;
+        let tiepoint_offset = pixel_scale_offset + 3 * 8;
+        let geo_keys_offset = tiepoint_offset + 6 * 8;
+        let image_offset = geo_keys_offset + 8 * 2;
+        if u64::from(image_offset) + image_bytes > u64::from(u32::MAX) {
+            return Err(ErrorKind::InvalidInput(
+                "The raster is too big for a TIFF file, use a coarser resolution.".to_string(),
+            )
+            .into());
+        }
+
+        writer.write_all(b"II")?;
+        writer.write_u16::<LittleEndian>(42)?;
+        writer.write_u32::<LittleEndian>(ifd_offset)?;
+        writer.write_u16::<LittleEndian>(NUM_ENTRIES as u16)?;
+
+        // Entries must be sorted by tag. Shorts are stored in the lower bytes of the value.
+        let mut entry = |tag: u16, data_type: u16, count: u32, value: u32| -> Result<()> {
+            writer.write_u16::<LittleEndian>(tag)?;
+            writer.write_u16::<LittleEndian>(data_type)?;
+            writer.write_u32::<LittleEndian>(count)?;
+            writer.write_u32::<LittleEndian>(value)?;
+            Ok(())
+        };
+        entry(256, TYPE_LONG, 1, self.width as u32)?; // ImageWidth
+        entry(257, TYPE_LONG, 1, self.height as u32)?; // ImageLength
+        entry(258, TYPE_SHORT, 1, 32)?; // BitsPerSample
+        entry(259, TYPE_SHORT, 1, 1)?; // Compression: none
+        entry(262, TYPE_SHORT, 1, 1)?; // PhotometricInterpretation: BlackIsZero
+        entry(273, TYPE_LONG, 1, image_offset)?; // StripOffsets
+        entry(277, TYPE_SHORT, 1, 1)?; // SamplesPerPixel
+        entry(278, TYPE_LONG, 1, self.height as u32)?; // RowsPerStrip
+        entry(279, TYPE_LONG, 1, image_bytes as u32)?; // StripByteCounts
+        entry(284, TYPE_SHORT, 1, 1)?; // PlanarConfiguration: chunky
+        entry(339, TYPE_SHORT, 1, 3)?; // SampleFormat: IEEE floating point
+        entry(33550, TYPE_DOUBLE, 3, pixel_scale_offset)?; // ModelPixelScale
+        entry(33922, TYPE_DOUBLE, 6, tiepoint_offset)?; // ModelTiepoint
+        entry(34735, TYPE_SHORT, 8, geo_keys_offset)?; // GeoKeyDirectory
+        entry(42113, TYPE_ASCII, 4, u32::from_le_bytes(*b"nan\0"))?; // GDAL_NODATA
+        writer.write_u32::<LittleEndian>(0)?;
+
+        for v in &[self.resolution, self.resolution, 0.] {
+            writer.write_f64::<LittleEndian>(*v)?;
+        }
+        // The corner of the first pixel is at (min_x, max_y).
+        for v in &[0., 0., 0., self.min_x, self.max_y, 0.] {
+            writer.write_f64::<LittleEndian>(*v)?;
+        }
+        // Version 1.1.0 with one key, GTRasterTypeGeoKey: RasterPixelIsArea.
+        for v in &[1, 1, 0, 1, 1025, 0, 1, 1] {
+            writer.write_u16::<LittleEndian>(*v)?;
+        }
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let value = self.get(col, row).map_or(f32::NAN, |v| v as f32);
+                writer.write_f32::<LittleEndian>(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the raster as a GeoTIFF for '.tif' and '.tiff' files or as an ASCII grid for '.asc'
+    /// files.
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let writer = || -> Result<BufWriter<File>> { Ok(BufWriter::new(File::create(path)?)) };
+        match extension {
+            "tif" | "tiff" => self.write_geotiff(writer()?),
+            "asc" => self.write_ascii_grid(writer()?),
+            _ => Err(ErrorKind::InvalidInput(format!(
+                "Unknown raster format of '{}', use '.tif' or '.asc'.",
+                path.display()
+            ))
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_height_statistics() {
+        let bounding_box = Aabb::new(Point3::new(0., 0., 0.), Point3::new(2., 1., 10.));
+        let points = [
+            Point3::new(0.5, 0.5, 1.),
+            Point3::new(0.2, 0.7, 3.),
+            Point3::new(2., 0., 5.),
+            Point3::new(3., 0., 7.),
+        ];
+        let raster = |statistic| {
+            let mut raster = HeightRaster::new(&bounding_box, 1., statistic).unwrap();
+            raster.add_points(&points);
+            raster
+        };
+        let mean = raster(HeightStatistic::Mean);
+        assert_eq!((mean.width(), mean.height()), (2, 1));
+        assert_eq!(mean.get(0, 0), Some(2.));
+        // The point on the edge is in the last cell, the point outside is dropped.
+        assert_eq!(mean.get(1, 0), Some(5.));
+        assert_eq!(raster(HeightStatistic::Min).get(0, 0), Some(1.));
+        assert_eq!(raster(HeightStatistic::Max).get(0, 0), Some(3.));
+
+        let empty = HeightRaster::new(&bounding_box, 1., HeightStatistic::Max).unwrap();
+        assert_eq!(empty.get(0, 0), None);
+        let mut ascii = Vec::new();
+        mean.write_ascii_grid(&mut ascii).unwrap();
+        assert!(String::from_utf8(ascii).unwrap().ends_with("\n2 5\n"));
+        let mut tiff = Vec::new();
+        mean.write_geotiff(&mut tiff).unwrap();
+        assert_eq!(tiff.len(), 8 + 2 + 15 * 12 + 4 + 24 + 48 + 16 + 2 * 4);
+    }
+}
@@ -1,10 +1,22 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use nalgebra::{Isometry3, Point3, Vector3};
 use point_cloud_client::PointCloudClient;
+#[cfg(feature = "mmap")]
+use point_cloud_client::PointCloudClientBuilder;
+use point_cloud_test_lib::bench::{run_workload, BenchDataset, Workload};
+#[cfg(feature = "mmap")]
+use point_cloud_test_lib::get_s2_and_octree_path;
 use point_cloud_test_lib::queries::*;
 use point_cloud_test_lib::{
-    make_octree, make_s2_cells, setup_octree_client, setup_s2_client, Arguments, SyntheticData,
+    make_octree, make_s2_cells, setup_octree_client, setup_pointcloud, setup_s2_client, Arguments,
+    Distribution, SyntheticData,
 };
-use point_viewer::iterator::{PointLocation, PointQuery};
+#[cfg(feature = "mmap")]
+use point_viewer::data_provider::MMAP_PREFIX;
+use point_viewer::geometry::{Aabb, Frustum, Obb, Perspective};
+use point_viewer::iterator::{PointCloud, PointLocation, PointQuery};
+use point_viewer::math::{HalfSpace, PointCulling};
+use point_viewer::octree::{deflate, NodeData};
 use tempdir::TempDir;
 
 fn bench_octree_building_multithreaded(c: &mut Criterion) {
@@ -55,6 +67,37 @@ fn box_query_s2(b: &mut Criterion) {
     run_bench("box_query_s2", setup_s2_client, get_aabb_query, b)
 }
 
+#[cfg(feature = "mmap")]
+fn setup_octree_mmap_client(args: &Arguments) -> (PointCloudClient, SyntheticData) {
+    let (_, octree_path_buf, data) = get_s2_and_octree_path(args);
+    let octree_locations = &[format!("{}{}", MMAP_PREFIX, octree_path_buf.display())];
+    let client = PointCloudClientBuilder::new(octree_locations)
+        .build()
+        .unwrap();
+    (client, data)
+}
+
+/// Compare with 'all_query_octree' for the effect of reading through memory mappings.
+#[cfg(feature = "mmap")]
+fn all_query_octree_mmap(b: &mut Criterion) {
+    run_bench(
+        "all_query_octree_mmap",
+        setup_octree_mmap_client,
+        |_| PointLocation::AllPoints,
+        b,
+    )
+}
+
+#[cfg(feature = "mmap")]
+fn box_query_octree_mmap(b: &mut Criterion) {
+    run_bench(
+        "box_query_octree_mmap",
+        setup_octree_mmap_client,
+        get_aabb_query,
+        b,
+    )
+}
+
 fn frustum_query_octree(b: &mut Criterion) {
     run_bench(
         "frustum_query_octree",
@@ -94,6 +137,135 @@ fn cell_union_query_s2(b: &mut Criterion) {
     )
 }
 
+fn node_bytes(node_data: &NodeData) -> Vec<u8> {
+    [&node_data.position[..], &node_data.color[..]].concat()
+}
+
+/// Compresses all nodes the way the web viewer does and reports the bandwidth reduction.
+fn node_compression(c: &mut Criterion) {
+    let args = Arguments::default();
+    let (_, octree, _) = setup_pointcloud(&args);
+    let node_ids = octree.nodes_in_location(&PointLocation::AllPoints);
+    let all_node_data = || node_ids.iter().map(|id| octree.get_node_data(id).unwrap());
+
+    let num_bytes: usize = all_node_data().map(|n| node_bytes(&n).len()).sum();
+    let num_bytes_deflated: usize = all_node_data()
+        .map(|n| deflate(&node_bytes(&n)).len())
+        .sum();
+    let num_bytes_delta: usize = all_node_data()
+        .map(|mut n| {
+            n.delta_encode();
+            deflate(&node_bytes(&n)).len()
+        })
+        .sum();
+    println!(
+        "Node data: {} bytes, {} deflated ({:.1}%), {} delta coded and deflated ({:.1}%).",
+        num_bytes,
+        num_bytes_deflated,
+        100. * num_bytes_deflated as f64 / num_bytes as f64,
+        num_bytes_delta,
+        100. * num_bytes_delta as f64 / num_bytes as f64,
+    );
+
+    c.bench_function("node_compression_delta", |b| {
+        b.iter(|| {
+            for mut node_data in all_node_data() {
+                node_data.delta_encode();
+                black_box(deflate(&node_bytes(&node_data)));
+            }
+        })
+    });
+}
+
+/// Runs the standard workloads on octrees of different sizes and distributions, and reports
+/// how many points per second they return.
+fn workloads(c: &mut Criterion) {
+    let datasets = [
+        ("uniform_100k", 100_000, Distribution::Uniform),
+        ("uniform_1m", 1_000_000, Distribution::Uniform),
+        (
+            "clustered_1m",
+            1_000_000,
+            Distribution::Clustered {
+                num_clusters: 20,
+                stddev: 5.,
+            },
+        ),
+        ("ground_1m", 1_000_000, Distribution::Ground { stddev: 0.5 }),
+    ];
+    for (name, num_points, distribution) in &datasets {
+        let args = Arguments {
+            num_points: *num_points,
+            distribution: *distribution,
+            ..Default::default()
+        };
+        let dataset = BenchDataset::octree(&args);
+        let client = dataset.client();
+        let data = dataset.data();
+        let mut group = c.benchmark_group(format!("workload_{}", name));
+        for workload in Workload::standard() {
+            let num_points = run_workload(&client, &data, &workload, &["color"])
+                .unwrap()
+                .num_points;
+            group.throughput(Throughput::Elements(num_points as u64));
+            group.bench_function(workload.name(), |b| {
+                b.iter(|| black_box(run_workload(&client, &data, &workload, &["color"]).unwrap()))
+            });
+        }
+        group.finish();
+    }
+}
+
+/// Compares the culling of batches of points, which tests several points at once with SIMD
+/// instructions, with testing them one by one.
+fn contains_batch(c: &mut Criterion) {
+    let points: Vec<Point3<f64>> = (0..1_000_000)
+        .map(|i| {
+            let t = f64::from(i);
+            Point3::new(
+                (t * 0.37) % 6. - 3.,
+                (t * 0.61) % 6. - 3.,
+                (t * 0.13) % 6. - 1.,
+            )
+        })
+        .collect();
+    let aabb = Aabb::new(Point3::new(-1., -2., 0.), Point3::new(1.5, 0.5, 2.));
+    let obb = Obb::new(
+        Isometry3::new(Vector3::new(0.5, 0., 1.), Vector3::new(0.3, 0.2, 0.7)),
+        Vector3::new(1., 2., 0.5),
+    );
+    let frustum = Frustum::new(
+        Isometry3::new(Vector3::new(0., 0., 5.), Vector3::new(0.1, 0., 0.)),
+        Perspective::new(-1., 1., -1., 1., 0.5, 4.),
+    );
+    let half_space = HalfSpace::new(Vector3::new(0.3, -1., 0.2), &Point3::new(0., 0.5, 1.));
+    let cullings: [(&str, &dyn PointCulling); 4] = [
+        ("aabb", &aabb),
+        ("obb", &obb),
+        ("frustum", &frustum),
+        ("half_space", &half_space),
+    ];
+    let mut group = c.benchmark_group("contains_batch");
+    group.throughput(Throughput::Elements(points.len() as u64));
+    let mut inside = Vec::new();
+    for (name, culling) in cullings.iter() {
+        group.bench_function(format!("{}_simd", name), |b| {
+            b.iter(|| {
+                culling.contains_batch(&points, &mut inside);
+                black_box(&inside);
+            })
+        });
+        group.bench_function(format!("{}_scalar", name), |b| {
+            b.iter(|| {
+                inside.clear();
+                inside.extend(points.iter().map(|p| culling.contains(p)));
+                black_box(&inside);
+            })
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     bench_octree_building_multithreaded,
@@ -108,8 +280,16 @@ criterion_group!(
     obb_query_s2,
     cell_union_query_octree,
     cell_union_query_s2,
+    node_compression,
+    workloads,
+    contains_batch,
 );
+#[cfg(feature = "mmap")]
+criterion_group!(mmap_benches, all_query_octree_mmap, box_query_octree_mmap);
+#[cfg(not(feature = "mmap"))]
 criterion_main!(benches);
+#[cfg(feature = "mmap")]
+criterion_main!(benches, mmap_benches);
 
 type SetupClientFn = fn(&Arguments) -> (PointCloudClient, SyntheticData);
 
@@ -0,0 +1,184 @@
+// Standardized query workloads on synthetic point clouds, to catch performance regressions in
+// node traversal and decoding. 'benches/main.rs' runs them with criterion on clouds of several
+// sizes and distributions, but they can be timed on any client with 'run_workload'.
+use crate::synthetic_data::SyntheticData;
+use crate::{make_octree, make_s2_cells, Arguments};
+use nalgebra::{Perspective3, Point3, Translation3, Vector3};
+use point_cloud_client::{PointCloudClient, PointCloudClientBuilder};
+use point_viewer::errors::Result;
+use point_viewer::geometry::{Frustum, Obb, Perspective};
+use point_viewer::iterator::{PointLocation, PointQuery};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tempdir::TempDir;
+
+/// A synthetic point cloud on disk, removed when it is dropped.
+pub struct BenchDataset {
+    args: Arguments,
+    directory: TempDir,
+}
+
+impl BenchDataset {
+    /// Builds an octree from the points described by `args`.
+    pub fn octree(args: &Arguments) -> Self {
+        let directory = TempDir::new("bench_octree").unwrap();
+        make_octree(args, directory.path());
+        BenchDataset {
+            args: args.clone(),
+            directory,
+        }
+    }
+
+    /// Writes S2 cells with the points described by `args`.
+    pub fn s2_cells(args: &Arguments) -> Self {
+        let directory = TempDir::new("bench_s2").unwrap();
+        make_s2_cells(args, directory.path());
+        BenchDataset {
+            args: args.clone(),
+            directory,
+        }
+    }
+
+    pub fn client(&self) -> PointCloudClient {
+        let locations = &[self.directory.path().to_str().unwrap().to_owned()];
+        PointCloudClientBuilder::new(locations).build().unwrap()
+    }
+
+    /// The points of the dataset, e.g. for the queries of 'Workload::queries'.
+    pub fn data(&self) -> SyntheticData {
+        self.args.synthetic_data()
+    }
+}
+
+/// A set of queries that is timed together.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Workload {
+    /// A camera looking down flies diagonally over the cloud, stopping at `num_frustums` evenly
+    /// spaced positions.
+    FrustumSweep { num_frustums: usize },
+    /// Boxes over the full height of the cloud, each a fifth of its width, in a grid of
+    /// `num_obbs` cells over the cloud.
+    ObbBatch { num_obbs: usize },
+    /// All points, once.
+    FullScan,
+}
+
+impl Workload {
+    /// The workloads that the benchmarks run.
+    pub fn standard() -> Vec<Workload> {
+        vec![
+            Workload::FrustumSweep { num_frustums: 16 },
+            Workload::ObbBatch { num_obbs: 25 },
+            Workload::FullScan,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Workload::FrustumSweep { .. } => "frustum_sweep",
+            Workload::ObbBatch { .. } => "obb_batch",
+            Workload::FullScan => "full_scan",
+        }
+    }
+
+    /// The locations that are queried, in order.
+    pub fn locations(&self, data: &SyntheticData) -> Vec<PointLocation> {
+        let (w, h) = (data.half_width, data.half_height);
+        let ecef_from_local = *data.ecef_from_local();
+        match *self {
+            Workload::FrustumSweep { num_frustums } => {
+                let perspective = Perspective::from(Perspective3::new(
+                    /* aspect */ 1.0,
+                    /* fovy */ 1.2,
+                    /* near */ 0.1,
+                    /* far */ 2. * h + 1.,
+                ));
+                (0..num_frustums)
+                    .map(|i| {
+                        let t = 0.8 * w * (2. * (i as f64 + 0.5) / num_frustums as f64 - 1.);
+                        let camera_position = Translation3::new(t, t, h + 0.5);
+                        PointLocation::Frustum(Frustum::new(
+                            ecef_from_local * camera_position,
+                            perspective.clone(),
+                        ))
+                    })
+                    .collect()
+            }
+            Workload::ObbBatch { num_obbs } => {
+                let num_per_side = (num_obbs as f64).sqrt().ceil() as usize;
+                let cell_width = 2. * w / num_per_side as f64;
+                (0..num_obbs)
+                    .map(|i| {
+                        let center = Point3::new(
+                            -w + cell_width * ((i % num_per_side) as f64 + 0.5),
+                            -w + cell_width * ((i / num_per_side) as f64 + 0.5),
+                            0.,
+                        );
+                        PointLocation::Obb(Obb::new(
+                            ecef_from_local * Translation3::from(center.coords),
+                            Vector3::new(0.1 * w, 0.1 * w, h),
+                        ))
+                    })
+                    .collect()
+            }
+            Workload::FullScan => vec![PointLocation::AllPoints],
+        }
+    }
+}
+
+/// How fast the points of a workload came back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Throughput {
+    pub num_queries: usize,
+    /// Summed over all queries, so points that several queries return are counted repeatedly.
+    pub num_points: usize,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    pub fn points_per_second(&self) -> f64 {
+        self.num_points as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} queries, {} points in {:.3} s: {:.0} points/s",
+            self.num_queries,
+            self.num_points,
+            self.elapsed.as_secs_f64(),
+            self.points_per_second()
+        )
+    }
+}
+
+/// Runs the queries of `workload` on `client` one after another, decoding `attributes` of
+/// every point.
+pub fn run_workload(
+    client: &PointCloudClient,
+    data: &SyntheticData,
+    workload: &Workload,
+    attributes: &[&str],
+) -> Result<Throughput> {
+    let locations = workload.locations(data);
+    let mut num_points = 0;
+    let start = Instant::now();
+    for location in &locations {
+        let query = PointQuery {
+            attributes: attributes.to_vec(),
+            location: location.clone(),
+            ..Default::default()
+        };
+        client.for_each_point_data(&query, |batch| {
+            num_points += batch.position.len();
+            Ok(())
+        })?;
+    }
+    Ok(Throughput {
+        num_queries: locations.len(),
+        num_points,
+        elapsed: start.elapsed(),
+    })
+}
@@ -14,8 +14,9 @@ use std::sync::Once;
 use tempdir::TempDir;
 
 pub mod synthetic_data;
-pub use synthetic_data::{Batched, SyntheticData};
+pub use synthetic_data::{Batched, Distribution, SyntheticData};
 
+pub mod bench;
 pub mod queries;
 
 pub const S2_LEVEL: u64 = 20;
@@ -35,6 +36,8 @@ pub struct Arguments {
     pub batch_size: usize,
     // The seed used for generating point clouds
     pub seed: u64,
+    // How the points are spread over the area.
+    pub distribution: Distribution,
 }
 
 impl Eq for Arguments {}
@@ -48,12 +51,29 @@ impl Default for Arguments {
             num_points: 1_000_000,
             batch_size: 5000,
             seed: 80_293_751_232,
+            distribution: Distribution::Uniform,
         }
     }
 }
 
+impl Arguments {
+    /// Chooses the width so that there are `points_per_square_meter` points on average over the
+    /// area.
+    pub fn with_density(self, points_per_square_meter: f64) -> Self {
+        Arguments {
+            width: (self.num_points as f64 / points_per_square_meter).sqrt(),
+            ..self
+        }
+    }
+
+    pub fn synthetic_data(&self) -> SyntheticData {
+        SyntheticData::new(self.width, self.height, self.num_points, self.seed)
+            .with_distribution(self.distribution)
+    }
+}
+
 pub fn make_octree(args: &Arguments, dir: &Path) {
-    let points_oct = SyntheticData::new(args.width, args.height, args.num_points, args.seed);
+    let points_oct = args.synthetic_data();
     let bbox = points_oct.bbox();
     let batches_oct = Batched::new(points_oct, args.batch_size);
 
@@ -61,7 +81,7 @@ pub fn make_octree(args: &Arguments, dir: &Path) {
 }
 
 pub fn make_s2_cells(args: &Arguments, dir: &Path) {
-    let points_s2 = SyntheticData::new(args.width, args.height, args.num_points, args.seed);
+    let points_s2 = args.synthetic_data();
     let mut s2_writer: S2Splitter<RawNodeWriter> =
         S2Splitter::with_split_level(S2_LEVEL, dir, Encoding::Plain, OpenMode::Truncate);
     Batched::new(points_s2, args.batch_size)
@@ -96,7 +116,7 @@ pub fn get_s2_and_octree_path(args: &Arguments) -> (PathBuf, PathBuf, SyntheticD
         let octree_path_buf = OCTREE_DIR.as_ref().unwrap().path().to_owned();
         (s2_path_buf, octree_path_buf)
     };
-    let data = SyntheticData::new(args.width, args.height, args.num_points, args.seed);
+    let data = args.synthetic_data();
     (s2_path_buf, octree_path_buf, data)
 }
 
@@ -1,9 +1,11 @@
 // Some synthetic queries for synthetic data. These are just examples, more can be added.
 use crate::synthetic_data::SyntheticData;
 use crate::S2_LEVEL;
-use nalgebra::{Perspective3, Point3, Vector2, Vector3};
+use nalgebra::{Perspective3, Point2, Point3, Vector2, Vector3};
 use nav_types::{ECEF, WGS84};
-use point_viewer::geometry::{Aabb, CellUnion, Frustum, Obb, WebMercatorRect};
+use point_viewer::geometry::{
+    Aabb, Capsule, CellUnion, Frustum, Obb, Perspective, PolygonPrism, Sphere, WebMercatorRect,
+};
 use point_viewer::iterator::PointLocation;
 use point_viewer::math::{FromPoint3, WebMercatorCoord};
 use s2::cellid::CellID;
@@ -40,7 +42,7 @@ pub fn get_frustum(data: SyntheticData) -> Frustum {
     let perspective = Perspective3::new(
         /* aspect */ 1.0, /* fovy */ 1.2, /* near */ 0.1, /* far */ 10.0,
     );
-    Frustum::new(ecef_from_local, perspective.into())
+    Frustum::new(ecef_from_local, Perspective::from(perspective))
 }
 pub fn get_frustum_query(data: SyntheticData) -> PointLocation {
     PointLocation::Frustum(get_frustum(data))
@@ -70,3 +72,59 @@ pub fn get_web_mercator_rect(data: SyntheticData) -> WebMercatorRect {
 pub fn get_web_mercator_rect_query(data: SyntheticData) -> PointLocation {
     PointLocation::WebMercatorRect(get_web_mercator_rect(data))
 }
+
+// A sphere in the center of the point cloud, reaching half way to its sides.
+pub fn get_sphere(data: SyntheticData) -> Sphere {
+    let center = data.ecef_from_local().translation.vector;
+    Sphere::new(Point3::from(center), 0.5 * data.half_width)
+}
+
+pub fn get_sphere_query(data: SyntheticData) -> PointLocation {
+    PointLocation::Sphere(get_sphere(data))
+}
+
+// A capsule crossing the point cloud diagonally through its center.
+pub fn get_capsule(data: SyntheticData) -> Capsule {
+    let ecef_from_local = *data.ecef_from_local();
+    let start = ecef_from_local
+        * Point3::new(
+            -0.8 * data.half_width,
+            -0.8 * data.half_width,
+            -0.5 * data.half_height,
+        );
+    let end = ecef_from_local
+        * Point3::new(
+            0.8 * data.half_width,
+            0.8 * data.half_width,
+            0.5 * data.half_height,
+        );
+    Capsule::new(start, end, 0.2 * data.half_width)
+}
+
+pub fn get_capsule_query(data: SyntheticData) -> PointLocation {
+    PointLocation::Capsule(get_capsule(data))
+}
+
+// An L-shaped footprint around the center of the point cloud, extruded over its full height.
+pub fn get_polygon_prism(data: SyntheticData) -> PolygonPrism {
+    let w = data.half_width;
+    let polygon = vec![
+        Point2::new(-0.6 * w, -0.6 * w),
+        Point2::new(0.6 * w, -0.6 * w),
+        Point2::new(0.6 * w, -0.2 * w),
+        Point2::new(-0.2 * w, -0.2 * w),
+        Point2::new(-0.2 * w, 0.6 * w),
+        Point2::new(-0.6 * w, 0.6 * w),
+    ];
+    PolygonPrism::new(
+        *data.ecef_from_local(),
+        polygon,
+        -data.half_height,
+        data.half_height,
+    )
+    .unwrap()
+}
+
+pub fn get_polygon_prism_query(data: SyntheticData) -> PointLocation {
+    PointLocation::PolygonPrism(get_polygon_prism(data))
+}
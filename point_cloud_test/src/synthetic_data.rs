@@ -7,6 +7,20 @@ use point_viewer::{NumberOfPoints, Point, PointsBatch};
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
 use std::collections::BTreeMap;
+use std::f64::consts::PI;
+
+/// How the points of 'SyntheticData' are spread over its box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distribution {
+    /// Evenly over the whole box.
+    Uniform,
+    /// Normally distributed with `stddev` meters around `num_clusters` random centers, like the
+    /// dense returns of a few objects.
+    Clustered { num_clusters: usize, stddev: f64 },
+    /// Evenly over the bottom of the box, up to a few `stddev` meters above it by a half-normal
+    /// distribution, like a scan of flat ground.
+    Ground { stddev: f64 },
+}
 
 #[derive(Clone)]
 pub struct SyntheticData {
@@ -16,6 +30,9 @@ pub struct SyntheticData {
     ecef_from_local: Isometry3<f64>,
     size: usize,
     count: usize,
+    distribution: Distribution,
+    // In the local frame, only for 'Distribution::Clustered'.
+    cluster_centers: Vec<Point3<f64>>,
 }
 
 impl SyntheticData {
@@ -32,14 +49,63 @@ impl SyntheticData {
             ecef_from_local,
             size,
             count: 0,
+            distribution: Distribution::Uniform,
+            cluster_centers: Vec::new(),
         }
     }
 
-    pub fn next_pos(&mut self) -> Point3<f64> {
+    /// The same box with its points spread according to `distribution`. Points that would fall
+    /// outside of the box are moved onto its sides.
+    pub fn with_distribution(mut self, distribution: Distribution) -> Self {
+        if let Distribution::Clustered { num_clusters, .. } = distribution {
+            assert!(num_clusters > 0, "Clusters need at least one center.");
+            self.cluster_centers = (0..num_clusters)
+                .map(|_| self.uniform_local_pos())
+                .collect();
+        }
+        self.distribution = distribution;
+        self
+    }
+
+    fn uniform_local_pos(&mut self) -> Point3<f64> {
         let x = self.rng.gen_range(-self.half_width, self.half_width);
         let y = self.rng.gen_range(-self.half_width, self.half_width);
         let z = self.rng.gen_range(-self.half_height, self.half_height);
-        let pt_local = Point3::new(x, y, z);
+        Point3::new(x, y, z)
+    }
+
+    /// A sample of the standard normal distribution, by the Box-Muller transform.
+    fn standard_normal(&mut self) -> f64 {
+        let u1: f64 = self.rng.gen();
+        let u2: f64 = self.rng.gen();
+        (-2. * (1. - u1).ln()).sqrt() * (2. * PI * u2).cos()
+    }
+
+    fn clamp_to_box(&self, p: Point3<f64>) -> Point3<f64> {
+        let (w, h) = (self.half_width, self.half_height);
+        Point3::new(p.x.max(-w).min(w), p.y.max(-w).min(w), p.z.max(-h).min(h))
+    }
+
+    pub fn next_pos(&mut self) -> Point3<f64> {
+        let pt_local = match self.distribution {
+            Distribution::Uniform => self.uniform_local_pos(),
+            Distribution::Clustered { stddev, .. } => {
+                let center =
+                    self.cluster_centers[self.rng.gen_range(0, self.cluster_centers.len())];
+                let offset = Vector3::new(
+                    self.standard_normal(),
+                    self.standard_normal(),
+                    self.standard_normal(),
+                );
+                self.clamp_to_box(center + stddev * offset)
+            }
+            Distribution::Ground { stddev } => {
+                let x = self.rng.gen_range(-self.half_width, self.half_width);
+                let y = self.rng.gen_range(-self.half_width, self.half_width);
+                let z = -self.half_height + stddev * self.standard_normal().abs();
+                self.clamp_to_box(Point3::new(x, y, z))
+            }
+        };
         self.ecef_from_local.transform_point(&pt_local)
     }
 
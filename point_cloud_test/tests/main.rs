@@ -1,7 +1,16 @@
-use nalgebra::{Point3, Vector3};
+use nalgebra::{Isometry3, Point3, Translation3, UnitQuaternion, Vector3};
+use nav_types::{ECEF, WGS84};
 use num_integer::div_ceil;
+use point_cloud_client::budget::PointBudget;
+use point_cloud_client::{PointCloudClient, PointCloudClientBuilder, QueryCursor};
+use point_cloud_test_lib::bench::{run_workload, BenchDataset, Workload};
 use point_cloud_test_lib::queries::*;
-use point_cloud_test_lib::{setup_pointcloud, Arguments, SyntheticData};
+use point_cloud_test_lib::{
+    get_s2_and_octree_path, setup_pointcloud, Arguments, Distribution, SyntheticData,
+};
+use point_viewer::coordinates::{CoordinateSystem, UtmZone};
+use point_viewer::downsample::VoxelSize;
+use point_viewer::geometry::Sphere;
 use point_viewer::iterator::PointCloud;
 use point_viewer::iterator::{PointLocation, PointQuery};
 use point_viewer::math::{sat, ConvexPolyhedron, PointCulling};
@@ -62,6 +71,21 @@ fn check_web_mercator_rect_query_equality() {
     check_equality(get_web_mercator_rect_query)
 }
 
+#[test]
+fn check_sphere_query_equality() {
+    check_equality(get_sphere_query)
+}
+
+#[test]
+fn check_capsule_query_equality() {
+    check_equality(get_capsule_query)
+}
+
+#[test]
+fn check_polygon_prism_query_equality() {
+    check_equality(get_polygon_prism_query)
+}
+
 #[test]
 fn check_box_point_culling_equality() {
     check_point_culling_equality(get_aabb)
@@ -82,6 +106,231 @@ fn check_web_mercator_rect_point_culling_equality() {
     check_point_culling_equality(get_web_mercator_rect);
 }
 
+#[test]
+fn client_composites_transformed_and_hidden_clouds() {
+    let args = Arguments::default();
+    let (s2_path, oct_path, data) = get_s2_and_octree_path(&args);
+    let locations = [
+        s2_path.to_str().unwrap().to_owned(),
+        oct_path.to_str().unwrap().to_owned(),
+    ];
+    // Moves the octree next to the S2 cells, so that they do not overlap.
+    let global_from_octree = Isometry3::from_parts(
+        Translation3::new(4.0 * data.half_width, 0.0, 0.0),
+        UnitQuaternion::identity(),
+    );
+    let mut client = PointCloudClientBuilder::new(&locations)
+        .global_from_cloud(1, global_from_octree)
+        .build()
+        .unwrap();
+    let count = |client: &PointCloudClient, location: PointLocation| {
+        let query = PointQuery {
+            location,
+            ..Default::default()
+        };
+        let mut num_points = 0;
+        client
+            .for_each_point_data(&query, |batch| {
+                num_points += batch.position.len();
+                Ok(())
+            })
+            .unwrap();
+        num_points
+    };
+    assert_eq!(
+        count(&client, PointLocation::AllPoints),
+        2 * args.num_points
+    );
+
+    let (_, oct, data) = setup_pointcloud(&args);
+    let sphere = get_sphere(data);
+    let num_in_octree = query_and_sort(
+        &oct,
+        &PointQuery {
+            attributes: vec!["color"],
+            location: PointLocation::Sphere(sphere.clone()),
+            ..Default::default()
+        },
+        args.batch_size,
+    )
+    .len();
+    let moved_sphere = PointLocation::Sphere(Sphere::new(
+        global_from_octree * sphere.center(),
+        sphere.radius(),
+    ));
+    assert_eq!(count(&client, moved_sphere), num_in_octree);
+
+    client.set_visible(1, false).unwrap();
+    assert_eq!(count(&client, PointLocation::AllPoints), args.num_points);
+}
+
+#[test]
+fn client_reprojects_into_its_coordinate_system() {
+    let args = Arguments::default();
+    let (s2_path, _, data) = get_s2_and_octree_path(&args);
+    let locations = [s2_path.to_str().unwrap().to_owned()];
+    let count = |coordinate_system: CoordinateSystem, location: PointLocation| {
+        let client = PointCloudClientBuilder::new(&locations)
+            .coordinate_system(coordinate_system)
+            .build()
+            .unwrap();
+        let query = PointQuery {
+            location,
+            ..Default::default()
+        };
+        let mut num_points = 0;
+        client
+            .for_each_point_data(&query, |batch| {
+                num_points += batch.position.len();
+                Ok(())
+            })
+            .unwrap();
+        num_points
+    };
+    let (s2, _, _) = setup_pointcloud(&args);
+    let ecef_sphere = get_sphere(data.clone());
+    let ecef_points = query_and_sort(
+        &s2,
+        &PointQuery {
+            attributes: vec!["color"],
+            ..Default::default()
+        },
+        args.batch_size,
+    );
+    let center = WGS84::from(ECEF::new(
+        ecef_sphere.center().x,
+        ecef_sphere.center().y,
+        ecef_sphere.center().z,
+    ));
+
+    // The local frame differs from ECEF by a rigid transform, so spheres stay spheres.
+    let enu = CoordinateSystem::LocalEnu(center);
+    let enu_sphere = Sphere::new(
+        enu.point_from_ecef(ecef_sphere.center()),
+        ecef_sphere.radius(),
+    );
+    let num_in_ecef_sphere = ecef_points
+        .iter()
+        .filter(|p| ecef_sphere.contains(&p.pos))
+        .count();
+    assert_eq!(
+        count(enu, PointLocation::Sphere(enu_sphere)),
+        num_in_ecef_sphere
+    );
+
+    // UTM is not, so the points are tested against the sphere after reprojecting them.
+    let utm = CoordinateSystem::Utm(UtmZone::containing(&center));
+    let utm_sphere = Sphere::new(
+        utm.point_from_ecef(ecef_sphere.center()),
+        ecef_sphere.radius(),
+    );
+    let num_in_utm_sphere = ecef_points
+        .iter()
+        .filter(|p| utm_sphere.contains(&utm.point_from_ecef(&p.pos)))
+        .count();
+    assert_eq!(
+        count(utm, PointLocation::Sphere(utm_sphere)),
+        num_in_utm_sphere
+    );
+    assert_eq!(count(utm, PointLocation::AllPoints), args.num_points);
+}
+
+#[test]
+fn client_fits_obb_to_query_result() {
+    let args = Arguments::default();
+    let (s2_path, _, data) = get_s2_and_octree_path(&args);
+    let locations = [s2_path.to_str().unwrap().to_owned()];
+    let client = PointCloudClientBuilder::new(&locations).build().unwrap();
+    let obb = client
+        .fit_obb(&PointQuery::default())
+        .unwrap()
+        .expect("The query returned no points");
+    // The points are uniformly distributed in a box around the local frame of the data.
+    let box_volume = 8. * data.half_width * data.half_width * data.half_height;
+    assert!(obb.volume() <= 1.001 * box_volume);
+    assert!(obb.volume() >= 0.99 * box_volume);
+    let up = data.ecef_from_local() * Vector3::z();
+    let obb_axes = obb.query_from_obb().rotation;
+    assert!((0..3).any(|i| {
+        let mut axis = Vector3::zeros();
+        axis[i] = 1.;
+        (obb_axes * axis).dot(&up).abs() > 0.999
+    }));
+}
+
+#[test]
+fn client_keeps_query_result_within_budget() {
+    let args = Arguments::default();
+    let (s2_path, oct_path, _) = get_s2_and_octree_path(&args);
+    let count = |location: &std::path::Path, max_points: usize| {
+        let locations = [location.to_str().unwrap().to_owned()];
+        let client = PointCloudClientBuilder::new(&locations).build().unwrap();
+        let mut num_points = 0;
+        let is_exhaustive = client
+            .for_each_point_data_within_budget(
+                &PointQuery::default(),
+                &PointBudget::new(max_points),
+                |batch| {
+                    num_points += batch.position.len();
+                    Ok(())
+                },
+            )
+            .unwrap();
+        (num_points, is_exhaustive)
+    };
+    for path in &[&s2_path, &oct_path] {
+        assert_eq!(count(path, args.num_points), (args.num_points, true));
+        let (num_points, is_exhaustive) = count(path, args.num_points / 10);
+        assert!(!is_exhaustive);
+        assert!(num_points <= args.num_points / 10);
+        // The octree returns whole levels, which have at least a quarter of the budget.
+        assert!(num_points >= args.num_points / 40, "{}", num_points);
+    }
+}
+
+#[test]
+fn client_pages_through_query_result() {
+    let args = Arguments::default();
+    let (s2_path, oct_path, _) = get_s2_and_octree_path(&args);
+    let locations = [
+        s2_path.to_str().unwrap().to_owned(),
+        oct_path.to_str().unwrap().to_owned(),
+    ];
+    let client = PointCloudClientBuilder::new(&locations).build().unwrap();
+    let query = PointQuery {
+        attributes: vec!["color"],
+        ..Default::default()
+    };
+    let max_points = 99_991;
+    let mut num_returned = vec![0; args.num_points];
+    let mut cursor = QueryCursor::start();
+    loop {
+        let page = client.query_page(&query, &cursor, max_points).unwrap();
+        assert!(page.batch.position.len() <= max_points);
+        let color: &Vec<Vector3<u8>> = page.batch.get_attribute_vec("color").unwrap();
+        for c in color {
+            let idx = ((c.x as usize) << 16) + ((c.y as usize) << 8) + c.z as usize;
+            num_returned[idx] += 1;
+        }
+        match page.next {
+            // Like a server, that only hands out the cursor as a string.
+            Some(next) => cursor = next.to_string().parse().unwrap(),
+            None => break,
+        }
+    }
+    // Every point is in both point clouds.
+    assert!(num_returned.iter().all(|n| *n == 2));
+
+    let downsampled = PointQuery {
+        downsample: Some(VoxelSize::new(1.)),
+        ..Default::default()
+    };
+    assert!(client
+        .query_page(&downsampled, &QueryCursor::start(), max_points)
+        .is_err());
+    assert!("0:12".parse::<QueryCursor>().is_err());
+}
+
 fn check_equality<F>(gen_location: F)
 where
     F: FnOnce(SyntheticData) -> PointLocation,
@@ -201,3 +450,41 @@ fn assert_points_equal(points_s2: &[IndexedPoint], points_oct: &[IndexedPoint],
         "More than 1% point index mismatches."
     );
 }
+
+#[test]
+fn bench_workloads_see_the_points_of_every_distribution() {
+    let distributions = [
+        Distribution::Uniform,
+        Distribution::Clustered {
+            num_clusters: 3,
+            stddev: 2.,
+        },
+        Distribution::Ground { stddev: 0.5 },
+    ];
+    for distribution in &distributions {
+        let args = Arguments {
+            num_points: 20_000,
+            distribution: *distribution,
+            ..Default::default()
+        }
+        .with_density(5.);
+        assert_eq!(args.width, 4000_f64.sqrt());
+        let dataset = BenchDataset::octree(&args);
+        let client = dataset.client();
+        let data = dataset.data();
+        let run = |workload| run_workload(&client, &data, &workload, &["color"]).unwrap();
+
+        let full_scan = run(Workload::FullScan);
+        assert_eq!((full_scan.num_queries, full_scan.num_points), (1, 20_000));
+        let obb_batch = run(Workload::ObbBatch { num_obbs: 25 });
+        assert_eq!(obb_batch.num_queries, 25);
+        assert!(obb_batch.num_points < 20_000);
+        let frustum_sweep = run(Workload::FrustumSweep { num_frustums: 4 });
+        assert_eq!(frustum_sweep.num_queries, 4);
+        if *distribution == Distribution::Uniform {
+            // The boxes cover a quarter of the area, and the frustums see the ground.
+            assert!((4000..6000).contains(&obb_batch.num_points));
+            assert!(frustum_sweep.num_points > 0);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// C interface for querying octrees and S2 cells, implemented in 'src/lib.rs'.
+//
+// Every function returns a PvStatus. If it is not PV_OK, pv_last_error_message() describes
+// what went wrong. A PvCloud belongs to the caller, who releases it with pv_cloud_free(). The
+// buffers of a PvBatch belong to the library and are only valid during the callback that
+// receives them.
+
+#ifndef POINT_VIEWER_H_
+#define POINT_VIEWER_H_
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum {
+  PV_OK = 0,
+  // A pointer argument was null.
+  PV_NULL_ARGUMENT = 1,
+  // An argument is invalid, e.g. a string is not UTF-8 or a location kind is unknown.
+  PV_INVALID_ARGUMENT = 2,
+  // Reading the point cloud failed.
+  PV_IO = 3,
+  // The point cloud was written by an unsupported version.
+  PV_INVALID_VERSION = 4,
+  // The batch callback asked to stop.
+  PV_ABORTED = 5,
+  // The library panicked. The state of the handles that were used is unspecified.
+  PV_PANIC = 6,
+  // Any other error.
+  PV_FAILED = 7,
+} PvStatus;
+
+// The values of PvLocation::kind.
+#define PV_LOCATION_ALL_POINTS 0
+#define PV_LOCATION_AABB 1
+#define PV_LOCATION_OBB 2
+#define PV_LOCATION_SPHERE 3
+
+// Where to query points. Only the fields of its kind are read.
+typedef struct {
+  uint32_t kind;
+  // The corners of an AABB.
+  double min[3];
+  double max[3];
+  // The center of an OBB or sphere.
+  double center[3];
+  // The rotation of an OBB as a quaternion in x, y, z, w order. It does not need to be
+  // normalized.
+  double rotation[4];
+  // Half of the edge lengths of an OBB.
+  double half_extent[3];
+  // The radius of a sphere.
+  double radius;
+} PvLocation;
+
+typedef struct {
+  PvLocation location;
+  // The names of the attributes to return with the positions, e.g. "color".
+  const char* const* attributes;
+  size_t num_attributes;
+} PvQuery;
+
+// The scalar types of attribute values.
+typedef enum {
+  PV_U8 = 0,
+  PV_U16 = 1,
+  PV_U32 = 2,
+  PV_U64 = 3,
+  PV_I8 = 4,
+  PV_I16 = 5,
+  PV_I32 = 6,
+  PV_I64 = 7,
+  PV_F32 = 8,
+  PV_F64 = 9,
+} PvDataType;
+
+typedef struct {
+  // The name as given in the query.
+  const char* name;
+  PvDataType data_type;
+  // 1 for scalars, 3 for vectors like colors.
+  size_t num_components;
+  // num_points times num_components values, point after point.
+  const void* data;
+} PvAttribute;
+
+typedef struct {
+  size_t num_points;
+  // x, y and z of every point.
+  const double* positions;
+  // In the order of PvQuery::attributes.
+  const PvAttribute* attributes;
+  size_t num_attributes;
+} PvBatch;
+
+// Receives the points of a query batch after batch. Returning anything but 0 stops the query.
+typedef int (*PvBatchCallback)(const PvBatch* batch, void* user_data);
+
+// An open point cloud.
+typedef struct PvCloud PvCloud;
+
+// The message of the last error on this thread, or an empty string. It stays valid until the
+// next call into the library on this thread.
+const char* pv_last_error_message(void);
+
+// Opens the point clouds at `locations`, e.g. directories of octrees or S2 cells, or octrees in
+// 's3://' and 'gs://' buckets, to be queried together, and stores the handle in `out_cloud`.
+PvStatus pv_cloud_open(const char* const* locations, size_t num_locations, PvCloud** out_cloud);
+
+// Closes a cloud from pv_cloud_open(). Null is ignored.
+void pv_cloud_free(PvCloud* cloud);
+
+// Writes the corners of the box around all points of `cloud` to `out_min` and `out_max`.
+PvStatus pv_cloud_bounding_box(const PvCloud* cloud, double out_min[3], double out_max[3]);
+
+// Calls `callback` with the points of `cloud` that `query` matches, batch after batch, on the
+// calling thread. Returns PV_ABORTED if the callback stopped the query.
+PvStatus pv_cloud_query(const PvCloud* cloud, const PvQuery* query, PvBatchCallback callback,
+                        void* user_data);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif
+
+#endif  // POINT_VIEWER_H_
@@ -0,0 +1,682 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A C interface for querying octrees and S2 cells, so that e.g. Python and C++ pipelines can
+//! read them without re-implementing their format. The declarations are in
+//! 'include/point_viewer.h'.
+//!
+//! Every function returns a 'PvStatus'. If it is not 'PvStatus::Ok', 'pv_last_error_message'
+//! describes what went wrong. A 'PvCloud' belongs to the caller, who releases it with
+//! 'pv_cloud_free'. The buffers of a 'PvBatch' belong to the library and are only valid during
+//! the callback that receives them.
+
+use nalgebra::{Isometry3, Point3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use point_cloud_client::{PointCloudClient, PointCloudClientBuilder};
+use point_viewer::attributes::AttributeData;
+use point_viewer::errors::{Error, ErrorKind};
+use point_viewer::geometry::{Aabb, Obb, Sphere};
+use point_viewer::iterator::{PointLocation, PointQuery};
+use point_viewer::PointsBatch;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PvStatus {
+    Ok = 0,
+    /// A pointer argument was null.
+    NullArgument = 1,
+    /// An argument is invalid, e.g. a string is not UTF-8 or a location kind is unknown.
+    InvalidArgument = 2,
+    /// Reading the point cloud failed.
+    Io = 3,
+    /// The point cloud was written by an unsupported version.
+    InvalidVersion = 4,
+    /// The batch callback asked to stop.
+    Aborted = 5,
+    /// The library panicked. The state of the handles that were used is unspecified.
+    Panic = 6,
+    /// Any other error.
+    Failed = 7,
+}
+
+/// The values of 'PvLocation::kind'.
+pub const PV_LOCATION_ALL_POINTS: u32 = 0;
+pub const PV_LOCATION_AABB: u32 = 1;
+pub const PV_LOCATION_OBB: u32 = 2;
+pub const PV_LOCATION_SPHERE: u32 = 3;
+
+/// Where to query points. Only the fields of its 'kind' are read.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PvLocation {
+    /// One of the 'PV_LOCATION_*' constants.
+    pub kind: u32,
+    /// The corners of an AABB.
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+    /// The center of an OBB or sphere.
+    pub center: [f64; 3],
+    /// The rotation of an OBB as a quaternion in x, y, z, w order. It does not need to be
+    /// normalized.
+    pub rotation: [f64; 4],
+    /// Half of the edge lengths of an OBB.
+    pub half_extent: [f64; 3],
+    /// The radius of a sphere.
+    pub radius: f64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PvQuery {
+    pub location: PvLocation,
+    /// The names of the attributes to return with the positions, e.g. "color".
+    pub attributes: *const *const c_char,
+    pub num_attributes: usize,
+}
+
+/// The scalar types of attribute values.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PvDataType {
+    U8 = 0,
+    U16 = 1,
+    U32 = 2,
+    U64 = 3,
+    I8 = 4,
+    I16 = 5,
+    I32 = 6,
+    I64 = 7,
+    F32 = 8,
+    F64 = 9,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PvAttribute {
+    /// The name as given in the query.
+    pub name: *const c_char,
+    pub data_type: PvDataType,
+    /// 1 for scalars, 3 for vectors like colors.
+    pub num_components: usize,
+    /// 'num_points' times 'num_components' values, point after point.
+    pub data: *const c_void,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct PvBatch {
+    pub num_points: usize,
+    /// x, y and z of every point.
+    pub positions: *const f64,
+    /// In the order of 'PvQuery::attributes'.
+    pub attributes: *const PvAttribute,
+    pub num_attributes: usize,
+}
+
+/// Receives the points of a query batch after batch. Returning anything but 0 stops the query.
+pub type PvBatchCallback = extern "C" fn(batch: *const PvBatch, user_data: *mut c_void) -> c_int;
+
+/// An open point cloud.
+pub struct PvCloud {
+    client: PointCloudClient,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+struct Failure {
+    status: PvStatus,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: PvStatus, message: impl Into<String>) -> Self {
+        Failure {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn null_argument(name: &str) -> Self {
+        Self::new(PvStatus::NullArgument, format!("'{}' is null.", name))
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        let status = match error.kind() {
+            ErrorKind::Io(_) => PvStatus::Io,
+            ErrorKind::InvalidInput(_) => PvStatus::InvalidArgument,
+            ErrorKind::InvalidVersion(_) => PvStatus::InvalidVersion,
+            _ => PvStatus::Failed,
+        };
+        Failure::new(status, error.to_string())
+    }
+}
+
+/// Runs `f`, turning its errors and panics into a status and the last error message.
+fn run(f: impl FnOnce() -> Result<(), Failure>) -> PvStatus {
+    let failure = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return PvStatus::Ok,
+        Ok(Err(failure)) => failure,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Failure::new(PvStatus::Panic, format!("Panicked: {}", message))
+        }
+    };
+    // Interior null bytes would end the message early, so they are dropped.
+    let message = CString::new(failure.message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+    failure.status
+}
+
+unsafe fn to_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    if s.is_null() {
+        return Err(Failure::null_argument(name));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| {
+        Failure::new(
+            PvStatus::InvalidArgument,
+            format!("'{}' is not UTF-8.", name),
+        )
+    })
+}
+
+/// `len` elements from `data`, which may only be null if `len` is 0.
+unsafe fn to_slice<'a, T>(data: *const T, len: usize, name: &str) -> Result<&'a [T], Failure> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err(Failure::null_argument(name));
+    }
+    Ok(slice::from_raw_parts(data, len))
+}
+
+fn point_location(location: &PvLocation) -> Result<PointLocation, Failure> {
+    let center = Point3::from(location.center);
+    match location.kind {
+        PV_LOCATION_ALL_POINTS => Ok(PointLocation::AllPoints),
+        PV_LOCATION_AABB => Ok(PointLocation::Aabb(Aabb::new(
+            Point3::from(location.min),
+            Point3::from(location.max),
+        ))),
+        PV_LOCATION_OBB => {
+            let [x, y, z, w] = location.rotation;
+            let rotation = Quaternion::new(w, x, y, z);
+            if rotation.norm() == 0. {
+                return Err(Failure::new(
+                    PvStatus::InvalidArgument,
+                    "The rotation of the OBB is zero.",
+                ));
+            }
+            let query_from_obb = Isometry3::from_parts(
+                Translation3::from(center.coords),
+                UnitQuaternion::from_quaternion(rotation),
+            );
+            Ok(PointLocation::Obb(Obb::new(
+                query_from_obb,
+                Vector3::from(location.half_extent),
+            )))
+        }
+        PV_LOCATION_SPHERE => Ok(PointLocation::Sphere(Sphere::new(center, location.radius))),
+        kind => Err(Failure::new(
+            PvStatus::InvalidArgument,
+            format!("Unknown location kind {}.", kind),
+        )),
+    }
+}
+
+/// The values of `data` as one flat buffer, which keeps the vectors of the batch alive.
+enum AttributeBuffer<'a> {
+    Borrowed(&'a AttributeData),
+    U8(Vec<u8>),
+    F64(Vec<f64>),
+}
+
+impl<'a> AttributeBuffer<'a> {
+    fn new(data: &'a AttributeData) -> Self {
+        match data {
+            AttributeData::U8Vec3(data) => {
+                AttributeBuffer::U8(data.iter().flat_map(|v| v.iter().cloned()).collect())
+            }
+            AttributeData::F64Vec3(data) => {
+                AttributeBuffer::F64(data.iter().flat_map(|v| v.iter().cloned()).collect())
+            }
+            data => AttributeBuffer::Borrowed(data),
+        }
+    }
+
+    /// The type, components and values of the buffer.
+    fn describe(&self) -> (PvDataType, usize, *const c_void) {
+        fn scalars<T>(data: &[T], data_type: PvDataType) -> (PvDataType, usize, *const c_void) {
+            (data_type, 1, data.as_ptr() as *const c_void)
+        }
+        fn vectors<T>(data: &[T], data_type: PvDataType) -> (PvDataType, usize, *const c_void) {
+            (data_type, 3, data.as_ptr() as *const c_void)
+        }
+        match self {
+            AttributeBuffer::U8(data) => vectors(data, PvDataType::U8),
+            AttributeBuffer::F64(data) => vectors(data, PvDataType::F64),
+            AttributeBuffer::Borrowed(data) => match data {
+                AttributeData::U8(data) => scalars(data, PvDataType::U8),
+                AttributeData::U16(data) => scalars(data, PvDataType::U16),
+                AttributeData::U32(data) => scalars(data, PvDataType::U32),
+                AttributeData::U64(data) => scalars(data, PvDataType::U64),
+                AttributeData::I8(data) => scalars(data, PvDataType::I8),
+                AttributeData::I16(data) => scalars(data, PvDataType::I16),
+                AttributeData::I32(data) => scalars(data, PvDataType::I32),
+                AttributeData::I64(data) => scalars(data, PvDataType::I64),
+                AttributeData::F32(data) => scalars(data, PvDataType::F32),
+                AttributeData::F64(data) => scalars(data, PvDataType::F64),
+                AttributeData::U8Vec3(_) | AttributeData::F64Vec3(_) => {
+                    unreachable!("Vectors are flattened.")
+                }
+            },
+        }
+    }
+}
+
+/// Hands `batch` to `callback` with the attributes in the order of `names`, returning whether
+/// the callback wants more.
+fn send_batch(
+    batch: &PointsBatch,
+    names: &[&str],
+    c_names: &[*const c_char],
+    callback: PvBatchCallback,
+    user_data: *mut c_void,
+) -> Result<bool, Failure> {
+    let positions: Vec<f64> = batch
+        .position
+        .iter()
+        .flat_map(|p| p.coords.iter().cloned())
+        .collect();
+    let buffers = names
+        .iter()
+        .map(|name| {
+            batch
+                .attributes
+                .get(*name)
+                .map(AttributeBuffer::new)
+                .ok_or_else(|| {
+                    Failure::new(
+                        PvStatus::InvalidArgument,
+                        format!("The points have no attribute '{}'.", name),
+                    )
+                })
+        })
+        .collect::<Result<Vec<_>, Failure>>()?;
+    let attributes: Vec<PvAttribute> = buffers
+        .iter()
+        .zip(c_names)
+        .map(|(buffer, name)| {
+            let (data_type, num_components, data) = buffer.describe();
+            PvAttribute {
+                name: *name,
+                data_type,
+                num_components,
+                data,
+            }
+        })
+        .collect();
+    let pv_batch = PvBatch {
+        num_points: batch.position.len(),
+        positions: positions.as_ptr(),
+        attributes: attributes.as_ptr(),
+        num_attributes: attributes.len(),
+    };
+    Ok(callback(&pv_batch, user_data) == 0)
+}
+
+/// The message of the last error on this thread, or an empty string. It stays valid until the
+/// next call into the library on this thread.
+#[no_mangle]
+pub extern "C" fn pv_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+}
+
+/// Opens the point clouds at `locations`, e.g. directories of octrees or S2 cells, or octrees in
+/// 's3://' and 'gs://' buckets, to be queried together, and stores the handle in `out_cloud`.
+///
+/// # Safety
+///
+/// `locations` must point to `num_locations` null-terminated strings and `out_cloud` to
+/// writable memory for a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn pv_cloud_open(
+    locations: *const *const c_char,
+    num_locations: usize,
+    out_cloud: *mut *mut PvCloud,
+) -> PvStatus {
+    run(|| {
+        if out_cloud.is_null() {
+            return Err(Failure::null_argument("out_cloud"));
+        }
+        let locations = to_slice(locations, num_locations, "locations")?
+            .iter()
+            .map(|location| to_str(*location, "location").map(str::to_string))
+            .collect::<Result<Vec<String>, Failure>>()?;
+        let client = PointCloudClientBuilder::new(&locations).build()?;
+        *out_cloud = Box::into_raw(Box::new(PvCloud { client }));
+        Ok(())
+    })
+}
+
+/// Closes a cloud from 'pv_cloud_open'. Null is ignored.
+///
+/// # Safety
+///
+/// `cloud` must be null or a handle from 'pv_cloud_open' that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pv_cloud_free(cloud: *mut PvCloud) {
+    if !cloud.is_null() {
+        drop(Box::from_raw(cloud));
+    }
+}
+
+/// Writes the corners of the box around all points of `cloud` to `out_min` and `out_max`.
+///
+/// # Safety
+///
+/// `cloud` must be a handle from 'pv_cloud_open', and `out_min` and `out_max` must point to
+/// writable memory for 3 doubles each.
+#[no_mangle]
+pub unsafe extern "C" fn pv_cloud_bounding_box(
+    cloud: *const PvCloud,
+    out_min: *mut f64,
+    out_max: *mut f64,
+) -> PvStatus {
+    run(|| {
+        let cloud = cloud
+            .as_ref()
+            .ok_or_else(|| Failure::null_argument("cloud"))?;
+        if out_min.is_null() || out_max.is_null() {
+            return Err(Failure::null_argument("out_min or out_max"));
+        }
+        let bounding_box = cloud.client.bounding_box();
+        ptr::copy_nonoverlapping(bounding_box.min().coords.as_ptr(), out_min, 3);
+        ptr::copy_nonoverlapping(bounding_box.max().coords.as_ptr(), out_max, 3);
+        Ok(())
+    })
+}
+
+/// Calls `callback` with the points of `cloud` that `query` matches, batch after batch, on the
+/// calling thread. Returns 'PvStatus::Aborted' if the callback stopped the query.
+///
+/// # Safety
+///
+/// `cloud` must be a handle from 'pv_cloud_open' and `query` must point to a query whose
+/// `attributes` are `num_attributes` null-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn pv_cloud_query(
+    cloud: *const PvCloud,
+    query: *const PvQuery,
+    callback: Option<PvBatchCallback>,
+    user_data: *mut c_void,
+) -> PvStatus {
+    run(|| {
+        let cloud = cloud
+            .as_ref()
+            .ok_or_else(|| Failure::null_argument("cloud"))?;
+        let query = query
+            .as_ref()
+            .ok_or_else(|| Failure::null_argument("query"))?;
+        let callback = callback.ok_or_else(|| Failure::null_argument("callback"))?;
+        let c_names = to_slice(query.attributes, query.num_attributes, "attributes")?;
+        let names = c_names
+            .iter()
+            .map(|name| to_str(*name, "attribute"))
+            .collect::<Result<Vec<&str>, Failure>>()?;
+        // The client panics on attributes that the clouds do not have.
+        let data_types = cloud.client.attribute_data_types();
+        if let Some(name) = names.iter().find(|name| !data_types.contains_key(**name)) {
+            return Err(Failure::new(
+                PvStatus::InvalidArgument,
+                format!("The point clouds have no attribute '{}'.", name),
+            ));
+        }
+        let point_query = PointQuery {
+            attributes: names.clone(),
+            location: point_location(&query.location)?,
+            ..Default::default()
+        };
+        // The client only knows its own errors, so the first failure is kept aside.
+        let mut failure = None;
+        let result = cloud.client.for_each_point_data(&point_query, |batch| {
+            match send_batch(&batch, &names, c_names, callback, user_data) {
+                Ok(true) => Ok(()),
+                Ok(false) => {
+                    failure = Some(Failure::new(PvStatus::Aborted, "The callback stopped."));
+                    Err("Stopped by the callback.".into())
+                }
+                Err(error) => {
+                    let message = error.message.clone();
+                    failure = Some(error);
+                    Err(message.into())
+                }
+            }
+        });
+        match (result, failure) {
+            (Ok(()), _) => Ok(()),
+            (Err(_), Some(failure)) => Err(failure),
+            (Err(error), None) => Err(error.into()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use point_viewer::octree::build_octree;
+    use point_viewer::NumberOfPoints;
+    use tempdir::TempDir;
+
+    struct Batches(Vec<PointsBatch>);
+
+    impl Iterator for Batches {
+        type Item = PointsBatch;
+
+        fn next(&mut self) -> Option<PointsBatch> {
+            self.0.pop()
+        }
+    }
+
+    impl NumberOfPoints for Batches {
+        fn num_points(&self) -> usize {
+            self.0.iter().map(|batch| batch.position.len()).sum()
+        }
+    }
+
+    #[derive(Default)]
+    struct Received {
+        num_points: usize,
+        xs: Vec<f64>,
+        reds: Vec<u8>,
+        intensities: Vec<f32>,
+        max_batches: Option<usize>,
+        num_batches: usize,
+    }
+
+    extern "C" fn receive(batch: *const PvBatch, user_data: *mut c_void) -> c_int {
+        let (batch, received) = unsafe { (&*batch, &mut *(user_data as *mut Received)) };
+        let n = batch.num_points;
+        received.num_points += n;
+        received.num_batches += 1;
+        unsafe {
+            let positions = slice::from_raw_parts(batch.positions, 3 * n);
+            received.xs.extend(positions.iter().step_by(3));
+            let attributes = slice::from_raw_parts(batch.attributes, batch.num_attributes);
+            assert_eq!(CStr::from_ptr(attributes[0].name).to_str(), Ok("color"));
+            assert_eq!(attributes[0].data_type, PvDataType::U8);
+            assert_eq!(attributes[0].num_components, 3);
+            let colors = slice::from_raw_parts(attributes[0].data as *const u8, 3 * n);
+            received.reds.extend(colors.iter().step_by(3));
+            assert_eq!(attributes[1].data_type, PvDataType::F32);
+            let intensities = slice::from_raw_parts(attributes[1].data as *const f32, n);
+            received.intensities.extend(intensities);
+        }
+        match received.max_batches {
+            Some(max_batches) if received.num_batches >= max_batches => 1,
+            _ => 0,
+        }
+    }
+
+    fn location(kind: u32) -> PvLocation {
+        PvLocation {
+            kind,
+            min: [0.; 3],
+            max: [0.; 3],
+            center: [0.; 3],
+            rotation: [0., 0., 0., 1.],
+            half_extent: [0.; 3],
+            radius: 0.,
+        }
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(pv_last_error_message()) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_query_through_the_c_interface() {
+        // Points at x = 0, 1, ..., 99, with red and intensity equal to x.
+        let directory = TempDir::new("octree").unwrap();
+        let batch = PointsBatch {
+            position: (0..100)
+                .map(|x| Point3::new(f64::from(x), 0., 0.))
+                .collect(),
+            attributes: vec![
+                (
+                    "color".to_string(),
+                    AttributeData::U8Vec3((0..100).map(|x| Vector3::new(x, 0, 0)).collect()),
+                ),
+                (
+                    "intensity".to_string(),
+                    AttributeData::F32((0..100u8).map(f32::from).collect()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        build_octree(
+            directory.path(),
+            0.001,
+            Aabb::new(Point3::new(0., 0., 0.), Point3::new(99., 1., 1.)),
+            Batches(vec![batch]),
+            &["color", "intensity"],
+        );
+
+        let location_string = CString::new(directory.path().to_str().unwrap()).unwrap();
+        let locations = [location_string.as_ptr()];
+        let mut cloud = ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                pv_cloud_open(locations.as_ptr(), 1, &mut cloud),
+                PvStatus::Ok
+            );
+            let (mut min, mut max) = ([0.; 3], [0.; 3]);
+            assert_eq!(
+                pv_cloud_bounding_box(cloud, min.as_mut_ptr(), max.as_mut_ptr()),
+                PvStatus::Ok
+            );
+            assert_eq!((min[0], max[0]), (0., 99.));
+        }
+
+        let names = [
+            CString::new("color").unwrap(),
+            CString::new("intensity").unwrap(),
+        ];
+        let attributes: Vec<*const c_char> = names.iter().map(|name| name.as_ptr()).collect();
+        let mut query = PvQuery {
+            location: PvLocation {
+                min: [9.5, -1., -1.],
+                max: [19.5, 1., 1.],
+                ..location(PV_LOCATION_AABB)
+            },
+            attributes: attributes.as_ptr(),
+            num_attributes: 2,
+        };
+        let query_into = |query: &PvQuery, received: &mut Received| unsafe {
+            pv_cloud_query(
+                cloud,
+                query,
+                Some(receive),
+                received as *mut Received as *mut c_void,
+            )
+        };
+        let mut received = Received::default();
+        assert_eq!(query_into(&query, &mut received), PvStatus::Ok);
+        received.xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(received.num_points, 10);
+        assert!((received.xs[0] - 10.).abs() < 0.01);
+        received.reds.sort_unstable();
+        assert_eq!(received.reds, (10..20).collect::<Vec<u8>>());
+        assert!(received
+            .intensities
+            .iter()
+            .all(|intensity| (10. ..20.).contains(intensity)));
+
+        // A callback that stops after the first batch.
+        query.location = location(PV_LOCATION_ALL_POINTS);
+        let mut received = Received {
+            max_batches: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(query_into(&query, &mut received), PvStatus::Aborted);
+        assert_eq!(received.num_batches, 1);
+
+        let unknown = CString::new("unknown").unwrap();
+        let unknown_attributes = [unknown.as_ptr()];
+        let unknown_query = PvQuery {
+            attributes: unknown_attributes.as_ptr(),
+            num_attributes: 1,
+            ..query
+        };
+        assert_eq!(
+            query_into(&unknown_query, &mut Received::default()),
+            PvStatus::InvalidArgument
+        );
+        assert_eq!(
+            last_error(),
+            "The point clouds have no attribute 'unknown'."
+        );
+
+        query.location = location(42);
+        assert_eq!(
+            query_into(&query, &mut Received::default()),
+            PvStatus::InvalidArgument
+        );
+        assert_eq!(last_error(), "Unknown location kind 42.");
+        unsafe {
+            assert_eq!(
+                pv_cloud_query(ptr::null(), &query, Some(receive), ptr::null_mut()),
+                PvStatus::NullArgument
+            );
+            pv_cloud_free(cloud);
+        }
+    }
+}
@@ -0,0 +1,45 @@
+#version 410 core
+
+uniform sampler2D depth_texture;
+uniform sampler2D color_texture;
+uniform sampler2D log_depth_texture;
+// The size of a pixel in texture coordinates.
+uniform vec2 pixel_size;
+// The distance of the neighbors in pixels.
+uniform float radius;
+uniform float strength;
+
+// inputs
+in vec2 v_uv;
+
+// outputs
+out vec4 FragColor;
+
+const int NUM_NEIGHBORS = 8;
+const vec2 NEIGHBORS[NUM_NEIGHBORS] =
+    vec2[](vec2(1., 0.), vec2(0.7071, 0.7071), vec2(0., 1.), vec2(-0.7071, 0.7071),
+           vec2(-1., 0.), vec2(-0.7071, -0.7071), vec2(0., -1.), vec2(0.7071, -0.7071));
+
+void main() {
+  float depth = texture(depth_texture, v_uv).r;
+  // Nothing was drawn here.
+  if (depth == 1.) {
+    discard;
+  }
+  float log_depth = texture(log_depth_texture, v_uv).r;
+  // Pixels in front of their neighbors stay lit, pixels behind them get shaded.
+  float response = 0.;
+  for (int i = 0; i < NUM_NEIGHBORS; ++i) {
+    vec2 uv = v_uv + radius * pixel_size * NEIGHBORS[i];
+    if (texture(depth_texture, uv).r == 1.) {
+      // The background is far away, which outlines the silhouettes.
+      response += 100.;
+    } else {
+      response += max(0., log_depth - texture(log_depth_texture, uv).r);
+    }
+  }
+  response /= float(NUM_NEIGHBORS);
+  float shade = exp(-response * 300. * strength);
+  FragColor = vec4(texture(color_texture, v_uv).rgb * shade, 1.);
+  gl_FragDepth = depth;
+}
@@ -0,0 +1,10 @@
+#version 410 core
+
+// varying outputs
+out vec2 v_uv;
+
+// Draws a triangle covering the whole screen without any vertex data.
+void main() {
+  v_uv = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+  gl_Position = vec4(2. * v_uv - 1., 0., 1.);
+}
@@ -1,9 +1,34 @@
 #version 410 core
 
+// 0: squares, 1: circles, 2: disks facing along the normal.
+uniform int splat_mode;
+
 // inputs
 in vec4 v_color;
+in vec3 v_normal;
 
 // outputs
-out vec4 FragColor;
+layout(location = 0) out vec4 FragColor;
+// Only used by the eye-dome lighting pass.
+layout(location = 1) out float LogDepth;
 
-void main() { FragColor = v_color; }
+void main() {
+  if (splat_mode != 0) {
+    // The position in the point sprite, from -1 to 1 with y up like in eye space.
+    vec2 d = vec2(2. * gl_PointCoord.x - 1., 1. - 2. * gl_PointCoord.y);
+    float r2 = dot(d, d);
+    if (splat_mode == 2 && dot(v_normal, v_normal) > 0.) {
+      // The distance of the disk from the sprite along the view direction, which makes disks
+      // seen at an angle elliptic.
+      vec3 n = normalize(v_normal);
+      float dz = dot(n.xy, d) / max(abs(n.z), 0.1);
+      r2 += dz * dz;
+    }
+    if (r2 > 1.) {
+      discard;
+    }
+  }
+  FragColor = v_color;
+  // 1 / w is the inverse distance from the eye for perspective projections.
+  LogDepth = log2(1. / gl_FragCoord.w);
+}
@@ -3,20 +3,84 @@
 // inputs
 layout(location = 0) in vec3 position;
 layout(location = 1) in vec3 color;
+// Zero if the octree has no normals.
+layout(location = 2) in vec3 normal;
+// The attribute of the color mode, zero if the octree has none.
+layout(location = 3) in float value;
 
 uniform dmat4 world_to_gl;
+uniform mat3 eye_from_world;
 uniform double edge_length;
 uniform float size;
 uniform float gamma;
 uniform dvec3 min;
+// 0: RGB, 1: intensity, 2: height, 3: classification.
+uniform int color_mode;
+// 0: viridis, 1: turbo.
+uniform int color_map;
+// The values mapped to the ends of the color map.
+uniform vec2 value_range;
+// Points with dot(clip_plane.xyz, p) > clip_plane.w are not drawn.
+uniform dvec4 clip_plane;
 
 // varying outputs
 out vec4 v_color;
+out vec3 v_normal;
+
+// Polynomial approximations of the color maps, see
+// https://www.shadertoy.com/view/WlfXRN and
+// https://ai.googleblog.com/2019/08/turbo-improved-rainbow-colormap-for.html
+vec3 viridis(float t) {
+  const vec3 c0 = vec3(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+  const vec3 c1 = vec3(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+  const vec3 c2 = vec3(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+  const vec3 c3 = vec3(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+  const vec3 c4 = vec3(6.228269936347081, 14.17993336680509, 56.69055260068105);
+  const vec3 c5 = vec3(4.776384997670288, -13.74514537774601, -65.35303263337234);
+  const vec3 c6 = vec3(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+  return c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6)))));
+}
+
+vec3 turbo(float t) {
+  const vec4 red4 = vec4(0.13572138, 4.61539260, -42.66032258, 132.13108234);
+  const vec4 green4 = vec4(0.09140261, 2.19418839, 4.84296658, -14.18503333);
+  const vec4 blue4 = vec4(0.10667330, 12.64194608, -60.58204836, 110.36276771);
+  const vec2 red2 = vec2(-152.94239396, 59.28637943);
+  const vec2 green2 = vec2(4.27729857, 2.82956604);
+  const vec2 blue2 = vec2(-89.90310912, 27.34824973);
+  vec4 v4 = vec4(1., t, t * t, t * t * t);
+  vec2 v2 = v4.zw * v4.z;
+  return vec3(dot(v4, red4) + dot(v2, red2), dot(v4, green4) + dot(v2, green2),
+              dot(v4, blue4) + dot(v2, blue2));
+}
+
+// Spreads the hues of neighboring labels by the golden ratio, so that they are easy to tell apart.
+vec3 label_color(float label) {
+  float hue = fract(label * 0.61803399);
+  vec3 rgb = clamp(abs(mod(hue * 6. + vec3(0., 4., 2.), 6.) - 3.) - 1., 0., 1.);
+  return mix(vec3(1.), rgb, 0.8);
+}
+
+float normalized(float v) {
+  return clamp((v - value_range.x) / max(value_range.y - value_range.x, 1e-6), 0., 1.);
+}
 
 void main() {
-  vec3 corrected_color = pow(color / 255., vec3(1.0 / gamma));
+  dvec3 world_position = dvec3(position) * edge_length + min;
+  vec3 corrected_color;
+  if (color_mode == 1) {
+    corrected_color = vec3(pow(normalized(value), 1.0 / gamma));
+  } else if (color_mode == 2) {
+    float t = normalized(float(world_position.z));
+    corrected_color = color_map == 0 ? viridis(t) : turbo(t);
+  } else if (color_mode == 3) {
+    corrected_color = label_color(value);
+  } else {
+    corrected_color = pow(color / 255., vec3(1.0 / gamma));
+  }
   v_color = vec4(corrected_color, 1.);
+  v_normal = eye_from_world * normal;
   gl_PointSize = size;
-  gl_Position =
-      vec4(world_to_gl * dvec4(dvec3(position) * edge_length + min, 1.0lf));
+  gl_Position = vec4(world_to_gl * dvec4(world_position, 1.0lf));
+  gl_ClipDistance[0] = float(clip_plane.w - dot(clip_plane.xyz, world_position));
 }
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use nalgebra::{Isometry3, Matrix4};
+use object_store_provider::register_object_stores;
 use point_viewer::data_provider::DataProviderFactory;
 use point_viewer::octree::Octree;
 use sdl_viewer::{opengl, run, Extension};
@@ -39,7 +40,7 @@ impl Extension for NullExtension {
 }
 
 fn main() {
-    let data_provider_factory = DataProviderFactory::new();
+    let data_provider_factory = register_object_stores(DataProviderFactory::new());
     // TODO(catevita): hide data provider factory details, simplify the run method interface
     run::<NullExtension>(data_provider_factory);
 }
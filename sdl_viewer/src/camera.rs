@@ -13,7 +13,8 @@
 // limitations under the License.
 
 use crate::opengl;
-use nalgebra::{Isometry3, Matrix4, Perspective3, UnitQuaternion, Vector3};
+use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, UnitQuaternion, Vector3};
+use point_viewer::geometry::Ray;
 
 use serde_derive::{Deserialize, Serialize};
 use std::f64;
@@ -78,13 +79,39 @@ pub struct Camera {
     local_from_global: Isometry3<f64>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub struct State {
     transform: Isometry3<f64>,
     phi: f64,
     theta: f64,
 }
 
+impl State {
+    /// The state a fraction `t` of the way from `self` to `other`. The rotation is rebuilt from
+    /// the interpolated angles like in 'Camera::update', so that the camera never rolls and turns
+    /// of more than half a circle are kept.
+    pub fn interpolate(&self, other: &State, t: f64) -> State {
+        let theta = self.theta + t * (other.theta - self.theta);
+        let phi = self.phi + t * (other.phi - self.phi);
+        let translation = self
+            .transform
+            .translation
+            .vector
+            .lerp(&other.transform.translation.vector, t);
+        State {
+            transform: Isometry3::from_parts(translation.into(), camera_rotation(theta, phi)),
+            phi,
+            theta,
+        }
+    }
+}
+
+fn camera_rotation(theta: f64, phi: f64) -> UnitQuaternion<f64> {
+    let rotation_z = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), theta);
+    let rotation_x = UnitQuaternion::from_axis_angle(&Vector3::x_axis(), phi);
+    rotation_z * rotation_x
+}
+
 const FAR_PLANE: f32 = 10000.;
 const NEAR_PLANE: f32 = 0.1;
 
@@ -201,6 +228,25 @@ impl Camera {
             * camera_from_global.to_homogeneous()
     }
 
+    /// Returns the ray from the near to the far plane through the pixel at (`x`, `y`), in world
+    /// coordinates.
+    pub fn ray_through_pixel(&self, x: i32, y: i32) -> Ray {
+        let world_from_gl = self
+            .get_world_to_gl()
+            .try_inverse()
+            .expect("The projection should be invertible.");
+        let gl_x = 2. * (f64::from(x) + 0.5) / f64::from(self.width) - 1.;
+        let gl_y = 1. - 2. * (f64::from(y) + 0.5) / f64::from(self.height);
+        let near = world_from_gl.transform_point(&Point3::new(gl_x, gl_y, -1.));
+        let far = world_from_gl.transform_point(&Point3::new(gl_x, gl_y, 1.));
+        Ray::new(near, far - near, nalgebra::distance(&near, &far))
+    }
+
+    /// The vertical angle covered by one pixel.
+    pub fn pixel_angle(&self) -> f64 {
+        f64::consts::FRAC_PI_4 / f64::from(self.height)
+    }
+
     /// Update the camera position for the current frame. Returns true if the camera moved in this
     /// step.
     pub fn update(&mut self, elapsed: time::Duration) -> bool {
@@ -270,9 +316,7 @@ impl Camera {
                 self.theta += self.rotation_speed.theta * elapsed_seconds;
                 self.phi += self.rotation_speed.phi * elapsed_seconds;
             }
-            let rotation_z = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), self.theta);
-            let rotation_x = UnitQuaternion::from_axis_angle(&Vector3::x_axis(), self.phi);
-            self.transform.rotation = rotation_z * rotation_x;
+            self.transform.rotation = camera_rotation(self.theta, self.phi);
         }
 
         self.pan = nalgebra::zero();
@@ -312,3 +356,38 @@ impl Camera {
         self.rotation_speed.theta += around;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate_state() {
+        let state = |x: f64, theta: f64| State {
+            transform: Isometry3::from_parts(
+                Vector3::new(x, 0., 10.).into(),
+                camera_rotation(theta, 0.5),
+            ),
+            phi: 0.5,
+            theta,
+        };
+        // Three quarters of a turn, which a slerp of the rotations would take the short way.
+        let start = state(0., 0.);
+        let end = state(20., 1.5 * f64::consts::PI);
+        let between = start.interpolate(&end, 0.5);
+        assert_eq!(
+            between.transform.translation.vector,
+            Vector3::new(10., 0., 10.)
+        );
+        let expected = state(10., 0.75 * f64::consts::PI);
+        assert!(
+            between
+                .transform
+                .rotation
+                .angle_to(&expected.transform.rotation)
+                < 1e-10
+        );
+        assert_eq!(start.interpolate(&end, 0.), start);
+        assert_eq!(start.interpolate(&end, 1.).theta, end.theta);
+    }
+}
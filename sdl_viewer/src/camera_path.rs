@@ -0,0 +1,94 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Camera paths recorded in the viewer, which can be replayed to render the frames of a video.
+
+use crate::camera::State;
+use serde_derive::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+/// The state of the camera `seconds` after the recording started.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Keyframe {
+    pub seconds: f64,
+    pub state: State,
+}
+
+/// Keyframes in the order of their time.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let camera_path: CameraPath = serde_json::from_str(&data)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if camera_path
+            .keyframes
+            .windows(2)
+            .any(|pair| pair[1].seconds < pair[0].seconds)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The keyframes are not in the order of their time.",
+            ));
+        }
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let data = serde_json::to_string_pretty(self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        std::fs::write(path, data)
+    }
+
+    /// Appends the state at `seconds`, which must not be before the last keyframe. While the
+    /// camera rests, only the keyframes at the start and the end of the pause are kept.
+    pub fn push(&mut self, seconds: f64, state: State) {
+        let len = self.keyframes.len();
+        if len >= 2
+            && self.keyframes[len - 1].state == state
+            && self.keyframes[len - 2].state == state
+        {
+            self.keyframes[len - 1].seconds = seconds;
+            return;
+        }
+        self.keyframes.push(Keyframe { seconds, state });
+    }
+
+    /// The time of the last keyframe.
+    pub fn duration_seconds(&self) -> f64 {
+        self.keyframes
+            .last()
+            .map_or(0., |keyframe| keyframe.seconds)
+    }
+
+    /// The state at `seconds`, interpolated between the keyframes around it. Before the first and
+    /// after the last keyframe, the camera stays where it is in these. None if there are none.
+    pub fn state_at(&self, seconds: f64) -> Option<State> {
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.seconds <= seconds);
+        if next == 0 || next == self.keyframes.len() {
+            let keyframe = self.keyframes.get(next).or_else(|| self.keyframes.last())?;
+            return Some(keyframe.state);
+        }
+        let (before, after) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = (seconds - before.seconds) / (after.seconds - before.seconds);
+        Some(before.state.interpolate(&after.state, t))
+    }
+}
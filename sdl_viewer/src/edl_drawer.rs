@@ -0,0 +1,119 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Eye-Dome Lighting, which shades every pixel by how much it lies behind its neighbors. This
+//! makes the shape of the geometry visible even without colors or normals.
+
+use crate::graphic::{
+    AttachmentFormat, GlFramebuffer, GlProgram, GlProgramBuilder, GlUniform, GlVertexArray,
+};
+use crate::opengl;
+use crate::opengl::types::{GLint, GLuint};
+use nalgebra::Vector2;
+use std::rc::Rc;
+
+const FRAGMENT_SHADER: &str = include_str!("../shaders/edl.fs");
+const VERTEX_SHADER: &str = include_str!("../shaders/edl.vs");
+
+pub struct EdlDrawer {
+    program: GlProgram,
+    u_pixel_size: GlUniform<Vector2<f32>>,
+    u_radius: GlUniform<f32>,
+    u_strength: GlUniform<f32>,
+    // The points are drawn into this, color at location 0 and log depth at location 1.
+    framebuffer: GlFramebuffer,
+    // Where drawing went before 'begin', the window or an offscreen target.
+    target_framebuffer: GLuint,
+    // The full screen triangle has no vertex data, but core profile requires a vertex array.
+    vertex_array: GlVertexArray,
+}
+
+impl EdlDrawer {
+    pub fn new(gl: &Rc<opengl::Gl>) -> Self {
+        let program = GlProgramBuilder::new_with_vertex_shader(Rc::clone(gl), VERTEX_SHADER)
+            .fragment_shader(FRAGMENT_SHADER)
+            .build();
+        unsafe {
+            gl.UseProgram(program.id);
+            // The texture units of 'GlFramebuffer::bind_textures'.
+            gl.Uniform1i(
+                gl.GetUniformLocation(program.id, c_str!("depth_texture")),
+                0,
+            );
+            gl.Uniform1i(
+                gl.GetUniformLocation(program.id, c_str!("color_texture")),
+                1,
+            );
+            gl.Uniform1i(
+                gl.GetUniformLocation(program.id, c_str!("log_depth_texture")),
+                2,
+            );
+        }
+        let u_pixel_size = GlUniform::new(&program, "pixel_size", Vector2::new(0., 0.));
+        let u_radius = GlUniform::new(&program, "radius", 1.4);
+        let u_strength = GlUniform::new(&program, "strength", 1.);
+        let framebuffer = GlFramebuffer::new(
+            Rc::clone(gl),
+            &[AttachmentFormat::RGBA8, AttachmentFormat::R32F],
+        );
+        EdlDrawer {
+            program,
+            u_pixel_size,
+            u_radius,
+            u_strength,
+            framebuffer,
+            target_framebuffer: 0,
+            vertex_array: GlVertexArray::new(Rc::clone(gl)),
+        }
+    }
+
+    pub fn adjust_strength(&mut self, delta: f32) {
+        self.u_strength.value = (self.u_strength.value + delta).max(0.);
+    }
+
+    /// Directs drawing into the offscreen framebuffer until 'finish' is called.
+    pub fn begin(&mut self, width: i32, height: i32) {
+        self.framebuffer.resize(width, height);
+        self.u_pixel_size.value = Vector2::new(1. / width as f32, 1. / height as f32);
+        let mut target_framebuffer: GLint = 0;
+        unsafe {
+            self.program
+                .gl
+                .GetIntegerv(opengl::FRAMEBUFFER_BINDING, &mut target_framebuffer);
+        }
+        self.target_framebuffer = target_framebuffer as GLuint;
+        self.framebuffer.bind();
+    }
+
+    /// Draws what was drawn since 'begin' with eye-dome lighting to where drawing went before,
+    /// including its depth, so that anything drawn afterwards is still occluded correctly.
+    pub fn finish(&self) {
+        let gl = &self.program.gl;
+        unsafe {
+            gl.BindFramebuffer(opengl::FRAMEBUFFER, self.target_framebuffer);
+            gl.ClearColor(0., 0., 0., 1.);
+            gl.Clear(opengl::COLOR_BUFFER_BIT | opengl::DEPTH_BUFFER_BIT);
+            gl.UseProgram(self.program.id);
+            gl.Enable(opengl::DEPTH_TEST);
+        }
+        self.u_pixel_size.submit();
+        self.u_radius.submit();
+        self.u_strength.submit();
+        self.framebuffer.bind_textures();
+        self.vertex_array.bind();
+        unsafe {
+            gl.DrawArrays(opengl::TRIANGLES, 0, 3);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::opengl;
+use crate::opengl::types::{GLenum, GLint, GLuint};
+use std::ffi::c_void;
+use std::ptr;
+use std::rc::Rc;
+
+/// The format of a texture that a 'GlFramebuffer' renders into.
+#[derive(Debug, Clone, Copy)]
+pub struct AttachmentFormat {
+    pub internal_format: GLint,
+    pub format: GLenum,
+    pub data_type: GLenum,
+}
+
+impl AttachmentFormat {
+    pub const RGBA8: AttachmentFormat = AttachmentFormat {
+        internal_format: opengl::RGBA8 as GLint,
+        format: opengl::RGBA,
+        data_type: opengl::UNSIGNED_BYTE,
+    };
+    pub const R32F: AttachmentFormat = AttachmentFormat {
+        internal_format: opengl::R32F as GLint,
+        format: opengl::RED,
+        data_type: opengl::FLOAT,
+    };
+    pub const DEPTH32F: AttachmentFormat = AttachmentFormat {
+        internal_format: opengl::DEPTH_COMPONENT32F as GLint,
+        format: opengl::DEPTH_COMPONENT,
+        data_type: opengl::FLOAT,
+    };
+}
+
+/// A framebuffer rendering into textures instead of the window, so that the rendered image can be
+/// sampled in a later pass. The first texture of 'new' is the depth attachment, the others are the
+/// color attachments, in order.
+pub struct GlFramebuffer {
+    gl: Rc<opengl::Gl>,
+    id: GLuint,
+    depth_texture: GLuint,
+    color_textures: Vec<GLuint>,
+    formats: Vec<AttachmentFormat>,
+    width: i32,
+    height: i32,
+}
+
+impl GlFramebuffer {
+    pub fn new(gl: Rc<opengl::Gl>, color_formats: &[AttachmentFormat]) -> Self {
+        let mut id = 0;
+        let mut textures = vec![0; color_formats.len() + 1];
+        unsafe {
+            gl.GenFramebuffers(1, &mut id);
+            gl.GenTextures(textures.len() as i32, textures.as_mut_ptr());
+            gl.BindFramebuffer(opengl::FRAMEBUFFER, id);
+            for (i, texture) in textures.iter().enumerate() {
+                gl.BindTexture(opengl::TEXTURE_2D, *texture);
+                // The passes sample single pixels, so there is no need for filtering.
+                gl.TexParameteri(
+                    opengl::TEXTURE_2D,
+                    opengl::TEXTURE_MIN_FILTER,
+                    opengl::NEAREST as GLint,
+                );
+                gl.TexParameteri(
+                    opengl::TEXTURE_2D,
+                    opengl::TEXTURE_MAG_FILTER,
+                    opengl::NEAREST as GLint,
+                );
+                gl.TexParameteri(
+                    opengl::TEXTURE_2D,
+                    opengl::TEXTURE_WRAP_S,
+                    opengl::CLAMP_TO_EDGE as GLint,
+                );
+                gl.TexParameteri(
+                    opengl::TEXTURE_2D,
+                    opengl::TEXTURE_WRAP_T,
+                    opengl::CLAMP_TO_EDGE as GLint,
+                );
+                let attachment = if i == 0 {
+                    opengl::DEPTH_ATTACHMENT
+                } else {
+                    opengl::COLOR_ATTACHMENT0 + i as GLenum - 1
+                };
+                gl.FramebufferTexture2D(
+                    opengl::FRAMEBUFFER,
+                    attachment,
+                    opengl::TEXTURE_2D,
+                    *texture,
+                    0,
+                );
+            }
+            let draw_buffers: Vec<GLenum> = (0..color_formats.len() as GLenum)
+                .map(|i| opengl::COLOR_ATTACHMENT0 + i)
+                .collect();
+            gl.DrawBuffers(draw_buffers.len() as i32, draw_buffers.as_ptr());
+            gl.BindFramebuffer(opengl::FRAMEBUFFER, 0);
+        }
+        let mut formats = vec![AttachmentFormat::DEPTH32F];
+        formats.extend_from_slice(color_formats);
+        GlFramebuffer {
+            gl,
+            id,
+            depth_texture: textures[0],
+            color_textures: textures[1..].to_vec(),
+            formats,
+            width: 0,
+            height: 0,
+        }
+    }
+
+    /// Reallocates the textures if the size changed.
+    pub fn resize(&mut self, width: i32, height: i32) {
+        if self.width == width && self.height == height {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        let textures = std::iter::once(&self.depth_texture).chain(self.color_textures.iter());
+        unsafe {
+            for (texture, format) in textures.zip(self.formats.iter()) {
+                self.gl.BindTexture(opengl::TEXTURE_2D, *texture);
+                self.gl.TexImage2D(
+                    opengl::TEXTURE_2D,
+                    0,
+                    format.internal_format,
+                    width,
+                    height,
+                    0,
+                    format.format,
+                    format.data_type,
+                    ptr::null(),
+                );
+            }
+            self.gl.BindTexture(opengl::TEXTURE_2D, 0);
+        }
+    }
+
+    /// Directs all drawing into the textures of this framebuffer.
+    pub fn bind(&self) {
+        unsafe {
+            self.gl.BindFramebuffer(opengl::FRAMEBUFFER, self.id);
+        }
+    }
+
+    /// Directs drawing back to the window.
+    pub fn unbind(&self) {
+        unsafe {
+            self.gl.BindFramebuffer(opengl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// Reads the pixels of the first color texture, which needs to be 'AttachmentFormat::RGBA8'.
+    /// The rows go from the bottom to the top of the image, like in OpenGL.
+    pub fn read_rgba(&self) -> Vec<u8> {
+        let mut pixels = vec![0; 4 * self.width as usize * self.height as usize];
+        unsafe {
+            self.gl.BindFramebuffer(opengl::READ_FRAMEBUFFER, self.id);
+            self.gl.ReadBuffer(opengl::COLOR_ATTACHMENT0);
+            self.gl.PixelStorei(opengl::PACK_ALIGNMENT, 1);
+            self.gl.ReadPixels(
+                0,
+                0,
+                self.width,
+                self.height,
+                opengl::RGBA,
+                opengl::UNSIGNED_BYTE,
+                pixels.as_mut_ptr() as *mut c_void,
+            );
+        }
+        pixels
+    }
+
+    /// Binds the depth texture to texture unit 0 and the color textures to the following ones.
+    pub fn bind_textures(&self) {
+        let textures = std::iter::once(&self.depth_texture).chain(self.color_textures.iter());
+        unsafe {
+            for (unit, texture) in textures.enumerate() {
+                self.gl.ActiveTexture(opengl::TEXTURE0 + unit as GLenum);
+                self.gl.BindTexture(opengl::TEXTURE_2D, *texture);
+            }
+            self.gl.ActiveTexture(opengl::TEXTURE0);
+        }
+    }
+}
+
+impl Drop for GlFramebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteTextures(1, &self.depth_texture);
+            self.gl.DeleteTextures(
+                self.color_textures.len() as i32,
+                self.color_textures.as_ptr(),
+            );
+            self.gl.DeleteFramebuffers(1, &self.id);
+        }
+    }
+}
@@ -18,12 +18,14 @@ use crate::opengl::types::GLuint;
 use crate::opengl::{self, Gl};
 use std::rc::Rc;
 
+mod framebuffer;
 mod moving_window_texture;
 mod program;
 mod uniform;
 // This is namespaced as it doesn't deal with Gl directly
 pub mod tiled_texture_loader;
 
+pub use framebuffer::{AttachmentFormat, GlFramebuffer};
 pub use moving_window_texture::GlMovingWindowTexture;
 pub use program::{GlProgram, GlProgramBuilder};
 pub use uniform::GlUniform;
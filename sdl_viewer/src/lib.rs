@@ -23,6 +23,8 @@ macro_rules! c_str {
 }
 
 mod camera;
+mod camera_path;
+mod prefetch;
 #[allow(
     non_upper_case_globals,
     clippy::missing_safety_doc,
@@ -36,114 +38,428 @@ pub mod opengl {
     include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 }
 pub mod box_drawer;
+pub mod edl_drawer;
 pub mod graphic;
 pub mod node_drawer;
 pub mod terrain_drawer;
 
 use crate::box_drawer::BoxDrawer;
 use crate::camera::Camera;
-use crate::node_drawer::{NodeDrawer, NodeViewContainer};
+use crate::camera_path::CameraPath;
+use crate::edl_drawer::EdlDrawer;
+use crate::graphic::{AttachmentFormat, GlFramebuffer};
+use crate::node_drawer::{
+    ColorMap, ColorMapping, ColorMode, NodeDrawer, NodeViewContainer, SplatMode,
+};
+use crate::prefetch::CameraMotion;
 use crate::terrain_drawer::TerrainRenderer;
-use nalgebra::{Isometry3, Matrix4};
+use fnv::FnvHashSet;
+use nalgebra::{Isometry3, Matrix4, Point3, Translation3, UnitQuaternion, Vector3};
 use point_viewer::color::YELLOW;
 use point_viewer::data_provider::DataProviderFactory;
-use point_viewer::octree::{self, Octree};
+use point_viewer::geometry::{Frustum, PickRadius, Ray};
+use point_viewer::iterator::{PickHit, PointCloud};
+use point_viewer::math::HalfSpace;
+use point_viewer::octree::{self, AttributeRanges, Octree, Viewport};
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::{Mod, Scancode};
+use sdl2::mouse::MouseButton;
 use sdl2::video::{GLProfile, SwapInterval};
 use std::cmp;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{mpsc, Arc};
 use std::thread;
 
+/// The nodes to select for the current camera, and for where the camera is predicted to be.
+struct VisibleNodesParams {
+    // Counts the requests, so that the result of the latest one can be told apart.
+    request: usize,
+    cloud_to_gl: Matrix4<f64>,
+    predicted_cloud_to_gl: Option<Matrix4<f64>>,
+    viewport: Viewport,
+}
+
+/// The nodes selected for the current camera, and those the predicted camera sees in addition.
+struct VisibleNodes {
+    request: usize,
+    visible: Vec<octree::NodeId>,
+    prefetch: Vec<octree::NodeId>,
+}
+
+/// One of the octrees that are drawn together, placed in the world by `world_from_cloud`.
+struct CloudView {
+    octree: Arc<Octree>,
+    world_from_cloud: Isometry3<f64>,
+    visible: bool,
+    visible_nodes: Vec<octree::NodeId>,
+    // Loaded while there is time, so that they are there when the camera gets to them.
+    prefetch_nodes: Vec<octree::NodeId>,
+    // The request that 'visible_nodes' were selected for, and the latest one that was sent.
+    visible_nodes_request: usize,
+    latest_request: usize,
+    get_visible_nodes_params_tx: mpsc::Sender<VisibleNodesParams>,
+    get_visible_nodes_result_rx: mpsc::Receiver<VisibleNodes>,
+    node_views: NodeViewContainer,
+}
+
+impl CloudView {
+    fn new(
+        octree: Arc<Octree>,
+        world_from_cloud: Isometry3<f64>,
+        max_nodes_in_memory: usize,
+        point_budget: usize,
+    ) -> Self {
+        // This thread waits for requests to calculate the currently visible nodes, runs a
+        // calculation and sends the visible nodes back to the drawing thread. If multiple requests
+        // queue up while it is processing one, it will drop all but the latest one before
+        // restarting the next calculation.
+        let (get_visible_nodes_params_tx, rx) = mpsc::channel::<VisibleNodesParams>();
+        let (tx, get_visible_nodes_result_rx) = mpsc::channel();
+        let octree_clone = octree.clone();
+        thread::spawn(move || {
+            while let Ok(mut params) = rx.recv() {
+                // Drain the channel, we only ever want to update the latest.
+                while let Ok(newer_params) = rx.try_recv() {
+                    params = newer_params;
+                }
+                let select_nodes = |matrix: Matrix4<f64>| -> Vec<octree::NodeId> {
+                    let frustum =
+                        Frustum::from_matrix4(matrix).expect("Invalid projection matrix.");
+                    octree_clone
+                        .select_nodes_for_view(&frustum, params.viewport, point_budget)
+                        .into_iter()
+                        .map(|lod_node| lod_node.id)
+                        .collect()
+                };
+                let visible = select_nodes(params.cloud_to_gl);
+                let prefetch = match params.predicted_cloud_to_gl {
+                    Some(predicted_cloud_to_gl) => {
+                        let visible: FnvHashSet<&octree::NodeId> = visible.iter().collect();
+                        select_nodes(predicted_cloud_to_gl)
+                            .into_iter()
+                            .filter(|node_id| !visible.contains(node_id))
+                            .collect()
+                    }
+                    None => Vec::new(),
+                };
+                let result = VisibleNodes {
+                    request: params.request,
+                    visible,
+                    prefetch,
+                };
+                if tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        CloudView {
+            node_views: NodeViewContainer::new(Arc::clone(&octree), max_nodes_in_memory),
+            octree,
+            world_from_cloud,
+            visible: true,
+            visible_nodes: Vec::new(),
+            prefetch_nodes: Vec::new(),
+            visible_nodes_request: 0,
+            latest_request: 0,
+            get_visible_nodes_params_tx,
+            get_visible_nodes_result_rx,
+        }
+    }
+
+    fn cloud_to_gl(&self, world_to_gl: &Matrix4<f64>) -> Matrix4<f64> {
+        world_to_gl * self.world_from_cloud.to_homogeneous()
+    }
+}
+
 struct PointCloudRenderer {
     gl: Rc<opengl::Gl>,
     node_drawer: NodeDrawer,
     last_moving: time::Instant,
     // TODO(sirver): Logging does not fit into this classes responsibilities.
     last_log: time::Instant,
-    visible_nodes: Vec<octree::NodeId>,
-    get_visible_nodes_params_tx: mpsc::Sender<Matrix4<f64>>,
-    get_visible_nodes_result_rx: mpsc::Receiver<Vec<octree::NodeId>>,
+    clouds: Vec<CloudView>,
     num_frames: u32,
     point_size: f32,
     gamma: f32,
+    splat_mode: SplatMode,
+    color_mapping: ColorMapping,
+    // The ranges of the attributes and heights for scaling the color maps.
+    attribute_ranges: AttributeRanges,
+    height_range: (f32, f32),
+    // Eye-Dome Lighting is only applied while this is set.
+    edl_drawer: Option<EdlDrawer>,
+    viewport: Viewport,
     needs_drawing: bool,
     max_nodes_in_memory: usize,
     world_to_gl: Matrix4<f64>,
+    camera_to_world: Isometry3<f64>,
+    camera_motion: CameraMotion,
+    // How far ahead the nodes are prefetched, zero for not at all.
+    prefetch_lookahead: time::Duration,
+    predicted_world_to_gl: Option<Matrix4<f64>>,
     max_nodes_moving: usize,
+    // Draws all nodes even while the camera moves, e.g. when rendering frames offline.
+    full_detail: bool,
     show_octree_nodes: bool,
-    node_views: NodeViewContainer,
     box_drawer: BoxDrawer,
+    // Only the points inside are drawn, in the world frame, e.g. for cross sections.
+    clip_plane: Option<HalfSpace>,
 }
 
+/// How far in front of the camera a new clip plane is placed, in meters.
+const CLIP_PLANE_DISTANCE: f64 = 10.;
+
 #[derive(Debug)]
 enum DrawResult {
     HasDrawn,
     NoChange,
 }
 impl PointCloudRenderer {
+    /// The node cache and the point budget are shared evenly by the octrees. While the camera
+    /// moves, the nodes it is going to see in `prefetch_lookahead` are loaded ahead of time.
     pub fn new(
         max_nodes_in_memory: usize,
+        point_budget: usize,
+        prefetch_lookahead: time::Duration,
         gl: Rc<opengl::Gl>,
-        octree: Arc<octree::Octree>,
+        octrees: Vec<(Arc<Octree>, Isometry3<f64>)>,
     ) -> Self {
         let now = time::Instant::now();
-
-        // This thread waits for requests to calculate the currently visible nodes, runs a
-        // calculation and sends the visible nodes back to the drawing thread. If multiple requests
-        // queue up while it is processing one, it will drop all but the latest one before
-        // restarting the next calculation.
-        let (get_visible_nodes_params_tx, rx) = mpsc::channel::<Matrix4<f64>>();
-        let (tx, get_visible_nodes_result_rx) = mpsc::channel();
-        let octree_clone = octree.clone();
-        thread::spawn(move || {
-            while let Ok(mut matrix) = rx.recv() {
-                // Drain the channel, we only ever want to update the latest.
-                while let Ok(newer_matrix) = rx.try_recv() {
-                    matrix = newer_matrix;
-                }
-                let visible_nodes = octree_clone.get_visible_nodes(&matrix);
-                tx.send(visible_nodes).unwrap();
-            }
-        });
+        let num_clouds = octrees.len().max(1);
+        let mut attribute_ranges = AttributeRanges::default();
+        let mut height_range: Option<(f32, f32)> = None;
+        let clouds: Vec<CloudView> = octrees
+            .into_iter()
+            .map(|(octree, world_from_cloud)| {
+                attribute_ranges.merge(&octree.attribute_ranges());
+                let bounding_box = octree.bounding_box().transform(&world_from_cloud);
+                let (min, max) = (bounding_box.min().z as f32, bounding_box.max().z as f32);
+                height_range = Some(height_range.map_or((min, max), |(lower, upper)| {
+                    (lower.min(min), upper.max(max))
+                }));
+                CloudView::new(
+                    octree,
+                    world_from_cloud,
+                    max_nodes_in_memory / num_clouds,
+                    point_budget / num_clouds,
+                )
+            })
+            .collect();
+        let height_range = height_range.unwrap_or((0., 1.));
 
         Self {
             last_moving: now,
             last_log: now,
-            visible_nodes: Vec::new(),
+            clouds,
             node_drawer: NodeDrawer::new(&Rc::clone(&gl)),
             num_frames: 0,
             point_size: 1.,
             gamma: 1.,
-            get_visible_nodes_params_tx,
-            get_visible_nodes_result_rx,
+            splat_mode: SplatMode::Squares,
+            color_mapping: ColorMapping {
+                mode: ColorMode::Rgb,
+                color_map: ColorMap::Viridis,
+                range: height_range,
+            },
+            attribute_ranges,
+            height_range,
+            edl_drawer: None,
+            viewport: Viewport::new(0, 0),
             max_nodes_moving: max_nodes_in_memory,
+            full_detail: false,
             needs_drawing: true,
             show_octree_nodes: false,
             max_nodes_in_memory,
-            node_views: NodeViewContainer::new(octree, max_nodes_in_memory),
             box_drawer: BoxDrawer::new(&Rc::clone(&gl)),
             world_to_gl: Matrix4::identity(),
+            camera_to_world: Isometry3::identity(),
+            camera_motion: CameraMotion::new(),
+            prefetch_lookahead,
+            predicted_world_to_gl: None,
+            clip_plane: None,
             gl,
         }
     }
 
-    pub fn camera_changed(&mut self, world_to_gl: &Matrix4<f64>) {
-        self.last_moving = time::Instant::now();
+    pub fn camera_changed(
+        &mut self,
+        world_to_gl: &Matrix4<f64>,
+        camera_to_world: &Isometry3<f64>,
+        viewport: Viewport,
+    ) {
+        let now = time::Instant::now();
+        self.last_moving = now;
         self.needs_drawing = true;
-        self.node_drawer.update_world_to_gl(world_to_gl);
-        self.get_visible_nodes_params_tx.send(*world_to_gl).unwrap();
-        self.last_moving = time::Instant::now();
+        self.viewport = viewport;
         self.world_to_gl = *world_to_gl;
+        self.camera_to_world = *camera_to_world;
+        self.camera_motion.update(now, camera_to_world);
+        self.predicted_world_to_gl = if self.prefetch_lookahead > time::Duration::zero() {
+            // The projection stays the same, only the camera moves.
+            let gl_from_camera = world_to_gl * camera_to_world.to_homogeneous();
+            self.camera_motion
+                .predict(self.prefetch_lookahead)
+                .map(|predicted| gl_from_camera * predicted.inverse().to_homogeneous())
+        } else {
+            None
+        };
+        self.request_visible_nodes();
+    }
+
+    fn request_visible_nodes(&mut self) {
+        for cloud in self.clouds.iter_mut().filter(|cloud| cloud.visible) {
+            cloud.latest_request += 1;
+            cloud
+                .get_visible_nodes_params_tx
+                .send(VisibleNodesParams {
+                    request: cloud.latest_request,
+                    cloud_to_gl: cloud.cloud_to_gl(&self.world_to_gl),
+                    predicted_cloud_to_gl: self
+                        .predicted_world_to_gl
+                        .map(|predicted_world_to_gl| cloud.cloud_to_gl(&predicted_world_to_gl)),
+                    viewport: self.viewport,
+                })
+                .unwrap();
+        }
+    }
+
+    /// Shows or hides the octree with this index, in the order of the command line.
+    pub fn toggle_cloud_visibility(&mut self, index: usize) {
+        let cloud = match self.clouds.get_mut(index) {
+            Some(cloud) => cloud,
+            None => return,
+        };
+        cloud.visible = !cloud.visible;
+        eprintln!(
+            "{} point cloud {}.",
+            if cloud.visible { "Showing" } else { "Hiding" },
+            index + 1
+        );
+        self.request_visible_nodes();
+        self.needs_drawing = true;
+    }
+
+    pub fn set_full_detail(&mut self, full_detail: bool) {
+        self.full_detail = full_detail;
+        self.needs_drawing = true;
+    }
+
+    /// Draws on the next call to 'draw' even if nothing changed.
+    pub fn request_redraw(&mut self) {
+        self.needs_drawing = true;
+    }
+
+    fn max_nodes_per_cloud(&self, moving: bool) -> usize {
+        // We use a heuristic to keep the frame rate as stable as possible by increasing/decreasing the number of nodes to draw.
+        let max_nodes_to_display = if moving {
+            self.max_nodes_moving
+        } else {
+            self.max_nodes_in_memory
+        };
+        max_nodes_to_display / self.clouds.len().max(1)
+    }
+
+    /// Whether the nodes for the latest camera are selected and all of those that are drawn when
+    /// the camera rests are loaded.
+    pub fn is_complete(&self) -> bool {
+        let max_nodes_per_cloud = self.max_nodes_per_cloud(false);
+        self.clouds
+            .iter()
+            .filter(|cloud| cloud.visible)
+            .all(|cloud| {
+                cloud.visible_nodes_request == cloud.latest_request
+                    && cloud
+                        .visible_nodes
+                        .iter()
+                        .take(max_nodes_per_cloud)
+                        .all(|node_id| cloud.node_views.is_loaded(node_id))
+            })
     }
 
     pub fn toggle_show_octree_nodes(&mut self) {
         self.show_octree_nodes = !self.show_octree_nodes;
     }
 
+    /// Cuts away the points between the camera and a plane in front of it, facing it, or shows
+    /// them again.
+    pub fn toggle_clip_plane(&mut self) {
+        self.clip_plane = match self.clip_plane {
+            Some(_) => None,
+            None => {
+                let forward = self.camera_to_world.rotation * -Vector3::z();
+                let point = self.camera_to_world * Point3::new(0., 0., -CLIP_PLANE_DISTANCE);
+                Some(HalfSpace::new(-forward, &point))
+            }
+        };
+        self.needs_drawing = true;
+    }
+
+    /// Moves the clip plane by `delta` meters along its normal, i.e. towards the cut away points.
+    pub fn move_clip_plane(&mut self, delta: f64) {
+        if let Some(clip_plane) = &mut self.clip_plane {
+            clip_plane.offset += delta;
+            self.needs_drawing = true;
+        }
+    }
+
+    /// Cuts away the other side of the clip plane.
+    pub fn flip_clip_plane(&mut self) {
+        if let Some(clip_plane) = &mut self.clip_plane {
+            clip_plane.normal = -clip_plane.normal;
+            clip_plane.offset = -clip_plane.offset;
+            self.needs_drawing = true;
+        }
+    }
+
+    pub fn toggle_edl(&mut self) {
+        self.edl_drawer = match self.edl_drawer {
+            Some(_) => None,
+            None => Some(EdlDrawer::new(&self.gl)),
+        };
+        self.needs_drawing = true;
+    }
+
+    pub fn adjust_edl_strength(&mut self, delta: f32) {
+        if let Some(edl_drawer) = &mut self.edl_drawer {
+            edl_drawer.adjust_strength(delta);
+            self.needs_drawing = true;
+        }
+    }
+
+    pub fn next_splat_mode(&mut self) {
+        self.splat_mode = self.splat_mode.next();
+        eprintln!("Drawing points as {:?}.", self.splat_mode);
+        self.needs_drawing = true;
+    }
+
+    pub fn next_color_mode(&mut self) {
+        let mode = self.color_mapping.mode.next();
+        self.color_mapping.mode = mode;
+        self.color_mapping.range = match mode.attribute() {
+            // Without a known range, we assume 8 bit values.
+            Some(attribute) => self
+                .attribute_ranges
+                .get(attribute)
+                .map_or((0., 255.), |range| {
+                    (range.lower_bound() as f32, range.upper_bound() as f32)
+                }),
+            None => self.height_range,
+        };
+        for cloud in &mut self.clouds {
+            cloud.node_views.set_value_attribute(mode.attribute());
+        }
+        eprintln!("Coloring points by {:?}.", mode);
+        self.needs_drawing = true;
+    }
+
+    pub fn next_color_map(&mut self) {
+        self.color_mapping.color_map = self.color_mapping.color_map.next();
+        eprintln!("Using the {:?} color map.", self.color_mapping.color_map);
+        self.needs_drawing = true;
+    }
+
     pub fn adjust_gamma(&mut self, delta: f32) {
         self.gamma += delta;
         self.needs_drawing = true;
@@ -161,15 +477,26 @@ impl PointCloudRenderer {
         let mut num_nodes_drawn = 0;
 
         let now = time::Instant::now();
-        let moving = now - self.last_moving < time::Duration::milliseconds(150);
-        self.needs_drawing |= self.node_views.consume_arrived_nodes(&self.node_drawer);
-        while let Ok(visible_nodes) = self.get_visible_nodes_result_rx.try_recv() {
-            self.visible_nodes.clear();
-            self.visible_nodes.extend(visible_nodes);
-            self.needs_drawing = true;
+        let moving =
+            !self.full_detail && now - self.last_moving < time::Duration::milliseconds(150);
+        for cloud in &mut self.clouds {
+            self.needs_drawing |= cloud.node_views.consume_arrived_nodes(&self.node_drawer);
+            while let Ok(visible_nodes) = cloud.get_visible_nodes_result_rx.try_recv() {
+                cloud.visible_nodes_request = visible_nodes.request;
+                cloud.visible_nodes = visible_nodes.visible;
+                cloud.prefetch_nodes = visible_nodes.prefetch;
+                self.needs_drawing = true;
+            }
+            // The camera stopped before it got to the predicted pose.
+            if !moving {
+                cloud.prefetch_nodes.clear();
+            }
         }
 
         if self.needs_drawing {
+            if let Some(edl_drawer) = &mut self.edl_drawer {
+                edl_drawer.begin(self.viewport.width as i32, self.viewport.height as i32);
+            }
             unsafe {
                 self.gl.ClearColor(0., 0., 0., 1.);
                 self.gl
@@ -177,37 +504,56 @@ impl PointCloudRenderer {
             }
         }
 
-        // We use a heuristic to keep the frame rate as stable as possible by increasing/decreasing the number of nodes to draw.
-        let max_nodes_to_display = if moving {
-            self.max_nodes_moving
-        } else {
-            self.max_nodes_in_memory
-        };
-        let filtered_visible_nodes = self.visible_nodes.iter().take(max_nodes_to_display);
+        let max_nodes_per_cloud = self.max_nodes_per_cloud(moving);
 
-        for node_id in filtered_visible_nodes {
-            let view = self.node_views.get_or_request(&node_id);
-            if !self.needs_drawing || view.is_none() {
-                continue;
+        for cloud in self.clouds.iter_mut().filter(|cloud| cloud.visible) {
+            let cloud_to_gl = cloud.cloud_to_gl(&self.world_to_gl);
+            let clip_plane = self
+                .clip_plane
+                .as_ref()
+                .map(|clip_plane| clip_plane.transformed(&cloud.world_from_cloud.inverse()));
+            if self.needs_drawing {
+                self.node_drawer.update_world_to_gl(&cloud_to_gl);
+                self.node_drawer.update_camera_to_world(
+                    &(cloud.world_from_cloud.inverse() * self.camera_to_world),
+                );
+                self.node_drawer.update_clip_plane(clip_plane.as_ref());
             }
-            let view = view.unwrap();
-            num_points_drawn += self.node_drawer.draw(
-                view,
-                1, /* level of detail */
-                self.point_size,
-                self.gamma,
-            );
-            num_nodes_drawn += 1;
-
-            if self.show_octree_nodes {
-                self.box_drawer.draw_outlines(
-                    &view.meta.bounding_cube.to_aabb(),
-                    &self.world_to_gl,
-                    &YELLOW,
+            let filtered_visible_nodes = cloud.visible_nodes.iter().take(max_nodes_per_cloud);
+            for node_id in filtered_visible_nodes {
+                let view = cloud.node_views.get_or_request(&node_id);
+                if !self.needs_drawing || view.is_none() {
+                    continue;
+                }
+                let view = view.unwrap();
+                let aabb = view.meta.bounding_cube.to_aabb();
+                if clip_plane
+                    .as_ref()
+                    .is_some_and(|clip_plane| clip_plane.excludes_aabb(&aabb))
+                {
+                    continue;
+                }
+                num_points_drawn += self.node_drawer.draw(
+                    view,
+                    1, /* level of detail */
+                    self.point_size,
+                    self.gamma,
+                    self.splat_mode,
+                    &self.color_mapping,
                 );
+                num_nodes_drawn += 1;
+
+                if self.show_octree_nodes {
+                    self.box_drawer.draw_outlines(&aabb, &cloud_to_gl, &YELLOW);
+                }
             }
+            // After the visible nodes, so that these are loaded first.
+            cloud.node_views.prefetch(&cloud.prefetch_nodes);
         }
         if self.needs_drawing {
+            if let Some(edl_drawer) = &self.edl_drawer {
+                edl_drawer.finish();
+            }
             draw_result = DrawResult::HasDrawn;
         }
         self.needs_drawing = moving;
@@ -233,8 +579,17 @@ impl PointCloudRenderer {
                 fps,
                 num_points_drawn,
                 num_nodes_drawn,
-                self.visible_nodes.len(),
-                self.node_views.get_used_memory_bytes() as f32 / 1024. / 1024.,
+                self.clouds
+                    .iter()
+                    .filter(|cloud| cloud.visible)
+                    .map(|cloud| cloud.visible_nodes.len())
+                    .sum::<usize>(),
+                self.clouds
+                    .iter()
+                    .map(|cloud| cloud.node_views.get_used_memory_bytes())
+                    .sum::<usize>() as f32
+                    / 1024.
+                    / 1024.,
             );
         }
         draw_result
@@ -293,6 +648,179 @@ fn load_camera(index: usize, pose_path: &Option<PathBuf>, camera: &mut Camera) {
     camera.set_state(states.states[index]);
 }
 
+/// Starts recording the camera path, or stops the recording and saves it to `path`.
+fn toggle_recording(
+    recording: &mut Option<(time::Instant, CameraPath)>,
+    path: &Option<PathBuf>,
+    camera: &Camera,
+) {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("No '--record_camera_path' given. Cannot record the camera path.");
+            return;
+        }
+    };
+    match recording.take() {
+        None => {
+            let mut camera_path = CameraPath::default();
+            camera_path.push(0., camera.state());
+            *recording = Some((time::Instant::now(), camera_path));
+            eprintln!("Recording the camera path.");
+        }
+        Some((_, camera_path)) => match camera_path.save(path) {
+            Ok(()) => eprintln!(
+                "Saved the camera path of {:.1} s to {}.",
+                camera_path.duration_seconds(),
+                path.display()
+            ),
+            Err(e) => eprintln!("Could not write {}: {}", path.display(), e),
+        },
+    }
+}
+
+/// The frames of a camera path to render, see 'render_camera_path'.
+struct FrameSettings {
+    directory: PathBuf,
+    frames_per_second: f64,
+    width: i32,
+    height: i32,
+}
+
+/// Renders `camera_path` into numbered PNG files, one per frame. Every frame waits until all the
+/// nodes the camera sees are loaded, so rendering is slower than real time for large clouds.
+fn render_camera_path<T: Extension>(
+    camera_path: &CameraPath,
+    settings: &FrameSettings,
+    gl: &Rc<opengl::Gl>,
+    camera: &mut Camera,
+    renderer: &mut PointCloudRenderer,
+    terrain_renderer: &mut TerrainRenderer,
+    extension: &mut T,
+) -> io::Result<()> {
+    std::fs::create_dir_all(&settings.directory)?;
+    let mut target = GlFramebuffer::new(Rc::clone(gl), &[AttachmentFormat::RGBA8]);
+    target.resize(settings.width, settings.height);
+    target.bind();
+    camera.set_size(gl, settings.width, settings.height);
+    renderer.set_full_detail(true);
+
+    let num_frames =
+        (camera_path.duration_seconds() * settings.frames_per_second).floor() as usize + 1;
+    for frame in 0..num_frames {
+        let state = camera_path
+            .state_at(frame as f64 / settings.frames_per_second)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "The camera path is empty.")
+            })?;
+        camera.set_state(state);
+        camera.update(time::Duration::zero());
+        let viewport = Viewport::new(settings.width as u32, settings.height as u32);
+        renderer.camera_changed(
+            &camera.get_world_to_gl(),
+            &camera.get_camera_to_world(),
+            viewport,
+        );
+        terrain_renderer.camera_changed(&camera.get_world_to_gl(), &camera.get_camera_to_world());
+        extension.camera_changed(&camera.get_world_to_gl());
+
+        // Drawing requests the missing nodes and takes in those that arrived.
+        renderer.draw();
+        while !renderer.is_complete() {
+            thread::sleep(std::time::Duration::from_millis(5));
+            renderer.draw();
+        }
+        renderer.request_redraw();
+        renderer.draw();
+        terrain_renderer.draw();
+        extension.draw();
+
+        let image = image::RgbaImage::from_raw(
+            settings.width as u32,
+            settings.height as u32,
+            target.read_rgba(),
+        )
+        .expect("The pixels should fill the image.");
+        // Images start with the top row.
+        let path = settings.directory.join(format!("frame_{:06}.png", frame));
+        image::imageops::flip_vertical(&image)
+            .save(&path)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        eprintln!("Rendered frame {} of {}.", frame + 1, num_frames);
+    }
+    Ok(())
+}
+
+/// Parses 'WIDTHxHEIGHT', in pixels.
+fn parse_frame_size(s: &str) -> Option<(i32, i32)> {
+    let mut parts = s.split('x');
+    let width: i32 = parts.next()?.trim().parse().ok()?;
+    let height: i32 = parts.next()?.trim().parse().ok()?;
+    if parts.next().is_some() || width <= 0 || height <= 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+/// Prints the point under the pixel at (`x`, `y`), the closest of all visible octrees.
+fn inspect_point(renderer: &PointCloudRenderer, camera: &Camera, x: i32, y: i32) {
+    const PICK_RADIUS_PIXELS: f64 = 3.;
+    let ray = camera.ray_through_pixel(x, y);
+    let radius = PickRadius::Angular(PICK_RADIUS_PIXELS * camera.pixel_angle());
+    let mut closest: Option<PickHit> = None;
+    for cloud in renderer.clouds.iter().filter(|cloud| cloud.visible) {
+        let cloud_from_world = cloud.world_from_cloud.inverse();
+        let cloud_ray = Ray::new(
+            cloud_from_world * ray.origin(),
+            cloud_from_world * ray.direction(),
+            ray.max_distance(),
+        );
+        match cloud.octree.pick(&cloud_ray, radius, &["color"]) {
+            Ok(Some(mut hit)) => {
+                if closest
+                    .as_ref()
+                    .map_or(true, |closest| hit.is_closer_than(closest))
+                {
+                    hit.position = cloud.world_from_cloud * hit.position;
+                    closest = Some(hit);
+                }
+            }
+            Ok(None) => (),
+            Err(err) => {
+                eprintln!("Could not pick a point: {}", err);
+                return;
+            }
+        }
+    }
+    match closest {
+        Some(hit) => {
+            eprintln!(
+                "Picked point ({:.3}, {:.3}, {:.3}) at a distance of {:.3}.",
+                hit.position.x, hit.position.y, hit.position.z, hit.distance
+            );
+            for (name, data) in &hit.attributes {
+                eprintln!("  {}: {:?}", name, data);
+            }
+        }
+        None => eprintln!("No point under the mouse."),
+    }
+}
+
+/// Parses 'x,y,z,roll,pitch,yaw', in meters and radians.
+fn parse_isometry(s: &str) -> Option<Isometry3<f64>> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    if values.len() != 6 {
+        return None;
+    }
+    Some(Isometry3::from_parts(
+        Translation3::new(values[0], values[1], values[2]),
+        UnitQuaternion::from_euler_angles(values[3], values[4], values[5]),
+    ))
+}
+
 pub trait Extension {
     fn pre_init(app: clap::App) -> clap::App;
     fn new(matches: &clap::ArgMatches, opengl: Rc<opengl::Gl>) -> Self;
@@ -350,9 +878,19 @@ impl Joystick for SpaceMouseJoystick {
 pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
     let mut app = clap::App::new("sdl_viewer").args(&[
         clap::Arg::new("octree")
-            .about("Input path of the octree.")
+            .about("Input paths of the octrees, which are drawn together.")
             .index(1)
+            .multiple(true)
             .required(true),
+        clap::Arg::new("transform")
+            .long("transform")
+            .takes_value(true)
+            .multiple(true)
+            .about(
+                "Places the octrees in the world, one 'x,y,z,roll,pitch,yaw' per octree in the \
+                 order of the octrees, in meters and radians. Octrees without a transform stay \
+                 where they are.",
+            ),
         clap::Arg::new("terrain")
             .long("terrain")
             .takes_value(true)
@@ -364,12 +902,68 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
                  The default value is 2000 MB and the valid range is 1000 MB to 16000 MB.",
             )
             .required(false),
+        clap::Arg::new("point_budget")
+            .long("point_budget")
+            .takes_value(true)
+            .about(
+                "Maximum number of points to show at once. The nodes largest on screen are \
+                 shown first. By default, all visible nodes are shown.",
+            ),
+        clap::Arg::new("prefetch_ms")
+            .long("prefetch_ms")
+            .takes_value(true)
+            .about(
+                "While the camera moves, loads the nodes it is going to see in this many \
+                 milliseconds if it keeps moving like this. The default is 300, 0 turns \
+                 prefetching off.",
+            ),
+        clap::Arg::new("record_camera_path")
+            .long("record_camera_path")
+            .takes_value(true)
+            .about(
+                "Pressing R starts recording the path of the camera, pressing it again saves the \
+                 path into this JSON file.",
+            ),
+        clap::Arg::new("replay_camera_path")
+            .long("replay_camera_path")
+            .takes_value(true)
+            .requires("frames_directory")
+            .about(
+                "Renders the camera path in this JSON file into PNG frames in \
+                 '--frames_directory' and exits, without showing the window.",
+            ),
+        clap::Arg::new("frames_directory")
+            .long("frames_directory")
+            .takes_value(true)
+            .about("Directory to write the frames of '--replay_camera_path' into."),
+        clap::Arg::new("frames_per_second")
+            .long("frames_per_second")
+            .takes_value(true)
+            .about("Frame rate of '--replay_camera_path'. The default is 30."),
+        clap::Arg::new("frame_size")
+            .long("frame_size")
+            .takes_value(true)
+            .about("Size of the frames of '--replay_camera_path'. The default is 1920x1080."),
     ]);
     app = T::pre_init(app);
 
     let matches = app.get_matches();
 
-    let octree_argument = matches.value_of("octree").unwrap();
+    let octree_arguments: Vec<&str> = matches.values_of("octree").unwrap().collect();
+    let octree_argument = octree_arguments[0];
+    let mut world_from_clouds: Vec<Isometry3<f64>> = matches
+        .values_of("transform")
+        .unwrap_or_default()
+        .map(|transform| {
+            parse_isometry(transform)
+                .unwrap_or_else(|| panic!("Could not parse transform '{}'.", transform))
+        })
+        .collect();
+    assert!(
+        world_from_clouds.len() <= octree_arguments.len(),
+        "There are more transforms than octrees."
+    );
+    world_from_clouds.resize(octree_arguments.len(), Isometry3::identity());
 
     // Maximum number of MB for the octree node cache. The default is 2 GB
     let cache_size_mb: usize = matches
@@ -381,16 +975,65 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
     // Maximum number of MB for the octree node cache in range 1..16 GB. The default is 2 GB
     let limit_cache_size_mb = cmp::max(1000, cmp::min(16_000, cache_size_mb));
 
+    let point_budget: usize = matches
+        .value_of("point_budget")
+        .map(|budget| {
+            budget
+                .parse()
+                .expect("Could not parse 'point_budget' option.")
+        })
+        .unwrap_or(usize::MAX);
+
+    let prefetch_ms: i64 = matches
+        .value_of("prefetch_ms")
+        .unwrap_or("300")
+        .parse()
+        .expect("Could not parse 'prefetch_ms' option.");
+
+    let record_path = matches.value_of("record_camera_path").map(PathBuf::from);
+    let replay = matches.value_of("replay_camera_path").map(|path| {
+        let camera_path = CameraPath::load(Path::new(path))
+            .unwrap_or_else(|e| panic!("Could not read camera path '{}': {}", path, e));
+        let frame_size = matches.value_of("frame_size").unwrap_or("1920x1080");
+        let (width, height) = parse_frame_size(frame_size)
+            .unwrap_or_else(|| panic!("Could not parse frame size '{}'.", frame_size));
+        let frames_per_second: f64 = matches
+            .value_of("frames_per_second")
+            .unwrap_or("30")
+            .parse()
+            .expect("Could not parse 'frames_per_second' option.");
+        assert!(
+            frames_per_second > 0.,
+            "'frames_per_second' needs to be positive."
+        );
+        let settings = FrameSettings {
+            directory: PathBuf::from(matches.value_of("frames_directory").unwrap()),
+            frames_per_second,
+            width,
+            height,
+        };
+        (camera_path, settings)
+    });
+    // Replayed frames are drawn from where the camera is, not where it might be going.
+    let prefetch_ms = if replay.is_some() { 0 } else { prefetch_ms };
+
     // Assuming about 200 KB per octree node on average
     let max_nodes_in_memory = limit_cache_size_mb * 5;
 
     // If no octree was generated create a FromDisk loader
-    let octree: Arc<Octree> = Arc::from(
-        data_provider_factory
-            .generate_data_provider(octree_argument)
-            .and_then(|provider| Octree::from_data_provider(provider))
-            .unwrap_or_else(|_| panic!("Couldn't create octree from path '{}'.", octree_argument)),
-    );
+    let octrees: Vec<Arc<Octree>> = octree_arguments
+        .iter()
+        .map(|octree_argument| {
+            Arc::from(
+                data_provider_factory
+                    .generate_data_provider(octree_argument)
+                    .and_then(|provider| Octree::from_data_provider(provider))
+                    .unwrap_or_else(|_| {
+                        panic!("Couldn't create octree from path '{}'.", octree_argument)
+                    }),
+            )
+        })
+        .collect();
 
     let mut pose_path = None;
     let pose_path_buf = PathBuf::from(&octree_argument).join("poses.json");
@@ -444,13 +1087,14 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
 
     const WINDOW_WIDTH: i32 = 800;
     const WINDOW_HEIGHT: i32 = 600;
-    let window = match video_subsystem
-        .window("sdl2_viewer", WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
-        .position_centered()
-        .resizable()
-        .opengl()
-        .build()
-    {
+    let mut window_builder =
+        video_subsystem.window("sdl2_viewer", WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32);
+    window_builder.position_centered().resizable().opengl();
+    // Replaying only needs the OpenGL context of the window.
+    if replay.is_some() {
+        window_builder.hidden();
+    }
+    let window = match window_builder.build() {
         Ok(window) => window,
         Err(err) => panic!("failed to create window: {}", err),
     };
@@ -468,15 +1112,40 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
     }));
 
     let mut extension = T::new(&matches, Rc::clone(&gl));
-    let ext_local_from_global = T::local_from_global(&matches, &octree);
-    let mut renderer = PointCloudRenderer::new(max_nodes_in_memory, Rc::clone(&gl), octree);
+    let ext_local_from_global = T::local_from_global(&matches, &octrees[0]);
+    let mut renderer = PointCloudRenderer::new(
+        max_nodes_in_memory,
+        point_budget,
+        time::Duration::milliseconds(prefetch_ms),
+        Rc::clone(&gl),
+        octrees.into_iter().zip(world_from_clouds).collect(),
+    );
     let terrain_paths = matches.values_of("terrain").unwrap_or_default();
     let mut terrain_renderer = TerrainRenderer::new(Rc::clone(&gl), terrain_paths);
     let local_from_global = ext_local_from_global.or_else(|| terrain_renderer.local_from_global());
     let mut camera = Camera::new(&gl, WINDOW_WIDTH, WINDOW_HEIGHT, local_from_global);
 
+    if let Some((camera_path, settings)) = replay {
+        if let Err(e) = render_camera_path(
+            &camera_path,
+            &settings,
+            &gl,
+            &mut camera,
+            &mut renderer,
+            &mut terrain_renderer,
+            &mut extension,
+        ) {
+            eprintln!("Could not render the camera path: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut events = ctx.event_pump().unwrap();
     let mut last_frame_time = time::Instant::now();
+    // A left click without dragging inspects the point under the mouse.
+    let mut dragged_since_click = false;
+    let mut recording: Option<(time::Instant, CameraPath)> = None;
     'outer_loop: loop {
         for event in events.poll_iter() {
             match event {
@@ -505,10 +1174,30 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
                             Scancode::Down => camera.turning_down = true,
                             Scancode::Up => camera.turning_up = true,
                             Scancode::O => renderer.toggle_show_octree_nodes(),
+                            Scancode::E => renderer.toggle_edl(),
+                            Scancode::P => renderer.next_splat_mode(),
+                            Scancode::C => renderer.next_color_mode(),
+                            Scancode::M => renderer.next_color_map(),
+                            Scancode::R => toggle_recording(&mut recording, &record_path, &camera),
+                            Scancode::L => renderer.toggle_clip_plane(),
+                            Scancode::LeftBracket => renderer.move_clip_plane(-0.5),
+                            Scancode::RightBracket => renderer.move_clip_plane(0.5),
+                            Scancode::Backslash => renderer.flip_clip_plane(),
+                            Scancode::Num5 => renderer.adjust_edl_strength(-0.1),
+                            Scancode::Num6 => renderer.adjust_edl_strength(0.1),
                             Scancode::Num7 => renderer.adjust_gamma(-0.1),
                             Scancode::Num8 => renderer.adjust_gamma(0.1),
                             Scancode::Num9 => renderer.adjust_point_size(-0.1),
                             Scancode::Num0 => renderer.adjust_point_size(0.1),
+                            Scancode::F1 => renderer.toggle_cloud_visibility(0),
+                            Scancode::F2 => renderer.toggle_cloud_visibility(1),
+                            Scancode::F3 => renderer.toggle_cloud_visibility(2),
+                            Scancode::F4 => renderer.toggle_cloud_visibility(3),
+                            Scancode::F5 => renderer.toggle_cloud_visibility(4),
+                            Scancode::F6 => renderer.toggle_cloud_visibility(5),
+                            Scancode::F7 => renderer.toggle_cloud_visibility(6),
+                            Scancode::F8 => renderer.toggle_cloud_visibility(7),
+                            Scancode::F9 => renderer.toggle_cloud_visibility(8),
                             _ => (),
                         }
                     } else if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
@@ -568,11 +1257,26 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
                     ..
                 } => {
                     if mousestate.left() {
+                        dragged_since_click = true;
                         camera.mouse_drag_rotate(xrel, yrel)
                     } else if mousestate.right() {
                         camera.mouse_drag_pan(xrel, yrel)
                     }
                 }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => dragged_since_click = false,
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    if !dragged_since_click {
+                        inspect_point(&renderer, &camera, x, y);
+                    }
+                }
                 Event::MouseWheel { y, .. } => {
                     camera.mouse_wheel(y);
                 }
@@ -593,11 +1297,19 @@ pub fn run<T: Extension>(data_provider_factory: DataProviderFactory) {
         let elapsed = current_time - last_frame_time;
         last_frame_time = current_time;
         if camera.update(elapsed) {
-            renderer.camera_changed(&camera.get_world_to_gl());
+            let viewport = Viewport::new(camera.width as u32, camera.height as u32);
+            renderer.camera_changed(
+                &camera.get_world_to_gl(),
+                &camera.get_camera_to_world(),
+                viewport,
+            );
             terrain_renderer
                 .camera_changed(&camera.get_world_to_gl(), &camera.get_camera_to_world());
             extension.camera_changed(&camera.get_world_to_gl());
         }
+        if let Some((start, camera_path)) = &mut recording {
+            camera_path.push((current_time - *start).as_seconds_f64(), camera.state());
+        }
 
         match renderer.draw() {
             DrawResult::HasDrawn => {
@@ -15,12 +15,16 @@
 use crate::graphic::{GlBuffer, GlProgram, GlProgramBuilder, GlVertexArray};
 use crate::opengl;
 use crate::opengl::types::{GLboolean, GLint, GLsizeiptr, GLuint};
-use fnv::FnvHashSet;
+use byteorder::{ByteOrder, LittleEndian};
+use fnv::{FnvHashMap, FnvHashSet};
 use lru::LruCache;
-use nalgebra::Matrix4;
+use nalgebra::{Isometry3, Matrix3, Matrix4, Vector4};
+use point_viewer::attributes::AttributeDataType;
+use point_viewer::math::HalfSpace;
 use point_viewer::octree;
 use point_viewer::read_write::PositionEncoding;
 use rand::{prelude::SliceRandom, thread_rng};
+use std::convert::TryInto;
 use std::os::raw::c_void;
 use std::ptr;
 use std::rc::Rc;
@@ -42,15 +46,100 @@ fn reshuffle(new_order: &[usize], old_data: &[u8], bytes_per_vertex: usize) -> V
     new_data
 }
 
+/// How the points are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplatMode {
+    Squares,
+    Circles,
+    /// Disks facing along the normals of the points, or circles if there are no normals.
+    Oriented,
+}
+
+impl SplatMode {
+    pub fn next(self) -> Self {
+        match self {
+            SplatMode::Squares => SplatMode::Circles,
+            SplatMode::Circles => SplatMode::Oriented,
+            SplatMode::Oriented => SplatMode::Squares,
+        }
+    }
+}
+
+/// Where the colors of the points come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Rgb,
+    /// Gray values of the intensity, with the gamma applied.
+    Intensity,
+    /// The height above the bottom of the octree, through the 'ColorMap'.
+    Height,
+    /// A different color for every label.
+    Classification,
+}
+
+impl ColorMode {
+    pub fn next(self) -> Self {
+        match self {
+            ColorMode::Rgb => ColorMode::Intensity,
+            ColorMode::Intensity => ColorMode::Height,
+            ColorMode::Height => ColorMode::Classification,
+            ColorMode::Classification => ColorMode::Rgb,
+        }
+    }
+
+    /// The attribute the colors are computed from, if it is not color or position.
+    pub fn attribute(self) -> Option<&'static str> {
+        match self {
+            ColorMode::Rgb | ColorMode::Height => None,
+            ColorMode::Intensity => Some("intensity"),
+            ColorMode::Classification => Some("classification"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMap {
+    Viridis,
+    Turbo,
+}
+
+impl ColorMap {
+    pub fn next(self) -> Self {
+        match self {
+            ColorMap::Viridis => ColorMap::Turbo,
+            ColorMap::Turbo => ColorMap::Viridis,
+        }
+    }
+}
+
+/// How the colors of the points are computed.
+#[derive(Debug, Clone, Copy)]
+pub struct ColorMapping {
+    pub mode: ColorMode,
+    pub color_map: ColorMap,
+    /// The values that are mapped to the ends of the color map or to black and white.
+    pub range: (f32, f32),
+}
+
 pub struct NodeProgram {
     program: GlProgram,
 
     // Uniforms locations.
     u_world_to_gl: GLint,
+    u_eye_from_world: GLint,
     u_edge_length: GLint,
     u_size: GLint,
     u_gamma: GLint,
     u_min: GLint,
+    u_splat_mode: GLint,
+    u_color_mode: GLint,
+    u_color_map: GLint,
+    u_value_range: GLint,
+    u_clip_plane: GLint,
+
+    // Attribute locations.
+    a_normal: GLuint,
+    a_value: GLuint,
 }
 
 pub struct NodeDrawer {
@@ -65,26 +154,50 @@ impl NodeDrawer {
                 .fragment_shader(FRAGMENT_SHADER)
                 .build();
             let u_world_to_gl;
+            let u_eye_from_world;
             let u_edge_length;
             let u_size;
             let u_gamma;
             let u_min;
+            let u_splat_mode;
+            let u_color_mode;
+            let u_color_map;
+            let u_value_range;
+            let u_clip_plane;
+            let a_normal;
+            let a_value;
             unsafe {
                 gl.UseProgram(program.id);
 
                 u_world_to_gl = gl.GetUniformLocation(program.id, c_str!("world_to_gl"));
+                u_eye_from_world = gl.GetUniformLocation(program.id, c_str!("eye_from_world"));
                 u_edge_length = gl.GetUniformLocation(program.id, c_str!("edge_length"));
                 u_size = gl.GetUniformLocation(program.id, c_str!("size"));
                 u_gamma = gl.GetUniformLocation(program.id, c_str!("gamma"));
                 u_min = gl.GetUniformLocation(program.id, c_str!("min"));
+                u_splat_mode = gl.GetUniformLocation(program.id, c_str!("splat_mode"));
+                u_color_mode = gl.GetUniformLocation(program.id, c_str!("color_mode"));
+                u_color_map = gl.GetUniformLocation(program.id, c_str!("color_map"));
+                u_value_range = gl.GetUniformLocation(program.id, c_str!("value_range"));
+                u_clip_plane = gl.GetUniformLocation(program.id, c_str!("clip_plane"));
+                a_normal = gl.GetAttribLocation(program.id, c_str!("normal")) as GLuint;
+                a_value = gl.GetAttribLocation(program.id, c_str!("value")) as GLuint;
             }
             NodeProgram {
                 program,
                 u_world_to_gl,
+                u_eye_from_world,
                 u_edge_length,
                 u_size,
                 u_gamma,
                 u_min,
+                u_splat_mode,
+                u_color_mode,
+                u_color_map,
+                u_value_range,
+                u_clip_plane,
+                a_normal,
+                a_value,
             }
         };
         let program_f32 = create_program(VERTEX_SHADER);
@@ -93,10 +206,12 @@ impl NodeDrawer {
                 .to_string()
                 .replace("vec3 position", "dvec3 position"),
         );
-        NodeDrawer {
+        let mut node_drawer = NodeDrawer {
             program_f32,
             program_f64,
-        }
+        };
+        node_drawer.update_clip_plane(None);
+        node_drawer
     }
 
     pub fn program(&self, position_encoding: &PositionEncoding) -> &NodeProgram {
@@ -121,12 +236,54 @@ impl NodeDrawer {
         update_matrix(&mut self.program_f64);
     }
 
+    /// Sets the rotation of the normals into eye space, for 'SplatMode::Oriented'.
+    pub fn update_camera_to_world(&mut self, camera_to_world: &Isometry3<f64>) {
+        let eye_from_world: Matrix3<f32> = nalgebra::convert(
+            camera_to_world
+                .rotation
+                .inverse()
+                .to_rotation_matrix()
+                .into_inner(),
+        );
+        let update_matrix = |node_program: &mut NodeProgram| unsafe {
+            node_program.program.gl.UseProgram(node_program.program.id);
+            node_program.program.gl.UniformMatrix3fv(
+                node_program.u_eye_from_world,
+                1,
+                false as GLboolean,
+                eye_from_world.as_ptr(),
+            );
+        };
+        update_matrix(&mut self.program_f32);
+        update_matrix(&mut self.program_f64);
+    }
+
+    /// Only the points inside `clip_plane`, in the frame of the octree, are drawn.
+    pub fn update_clip_plane(&mut self, clip_plane: Option<&HalfSpace>) {
+        // The plane of a half-space that all points are in.
+        let plane = clip_plane.map_or(Vector4::new(0., 0., 0., 1.), |half_space| {
+            let normal = &half_space.normal;
+            Vector4::new(normal.x, normal.y, normal.z, half_space.offset)
+        });
+        let update_plane = |node_program: &mut NodeProgram| unsafe {
+            node_program.program.gl.UseProgram(node_program.program.id);
+            node_program
+                .program
+                .gl
+                .Uniform4dv(node_program.u_clip_plane, 1, plane.as_ptr());
+        };
+        update_plane(&mut self.program_f32);
+        update_plane(&mut self.program_f64);
+    }
+
     pub fn draw(
         &self,
         node_view: &NodeView,
         level_of_detail: i32,
         point_size: f32,
         gamma: f32,
+        splat_mode: SplatMode,
+        color_mapping: &ColorMapping,
     ) -> i64 {
         node_view.vertex_array.bind();
         let num_points = node_view
@@ -138,6 +295,7 @@ impl NodeDrawer {
             program.gl.UseProgram(program.id);
             program.gl.Enable(opengl::PROGRAM_POINT_SIZE);
             program.gl.Enable(opengl::DEPTH_TEST);
+            program.gl.Enable(opengl::CLIP_DISTANCE0);
 
             program.gl.Uniform1d(
                 node_program.u_edge_length,
@@ -145,6 +303,27 @@ impl NodeDrawer {
             );
             program.gl.Uniform1f(node_program.u_size, point_size);
             program.gl.Uniform1f(node_program.u_gamma, gamma);
+            program
+                .gl
+                .Uniform1i(node_program.u_splat_mode, splat_mode as GLint);
+            program
+                .gl
+                .Uniform1i(node_program.u_color_mode, color_mapping.mode as GLint);
+            program
+                .gl
+                .Uniform1i(node_program.u_color_map, color_mapping.color_map as GLint);
+            program.gl.Uniform2f(
+                node_program.u_value_range,
+                color_mapping.range.0,
+                color_mapping.range.1,
+            );
+            // Without a buffer, the attribute is this constant for all points.
+            if node_view.buffer_normal.is_none() {
+                program.gl.VertexAttrib3f(node_program.a_normal, 0., 0., 0.);
+            }
+            if node_view.buffer_value.is_none() {
+                program.gl.VertexAttrib1f(node_program.a_value, 0.);
+            }
 
             program.gl.Uniform3dv(
                 node_program.u_min,
@@ -155,6 +334,7 @@ impl NodeDrawer {
             program.gl.DrawArrays(opengl::POINTS, 0, num_points as i32);
 
             program.gl.Disable(opengl::PROGRAM_POINT_SIZE);
+            program.gl.Disable(opengl::CLIP_DISTANCE0);
         }
         num_points
     }
@@ -168,11 +348,89 @@ pub struct NodeView {
     vertex_array: GlVertexArray,
     _buffer_position: GlBuffer,
     _buffer_color: GlBuffer,
+    // Only if the octree has normals.
+    buffer_normal: Option<GlBuffer>,
+    // The attribute of the 'ColorMode', if any and the octree has it.
+    buffer_value: Option<GlBuffer>,
     used_memory_bytes: usize,
 }
 
+/// Converts the values of an attribute with a single component to f32.
+fn scalar_values(attribute: &octree::NodeAttributeData) -> Option<Vec<f32>> {
+    let data = &attribute.data;
+    let values = match attribute.data_type {
+        AttributeDataType::U8 => data.iter().map(|v| f32::from(*v)).collect(),
+        AttributeDataType::I8 => data.iter().map(|v| f32::from(*v as i8)).collect(),
+        AttributeDataType::U16 => data
+            .chunks_exact(2)
+            .map(|c| f32::from(LittleEndian::read_u16(c)))
+            .collect(),
+        AttributeDataType::I16 => data
+            .chunks_exact(2)
+            .map(|c| f32::from(LittleEndian::read_i16(c)))
+            .collect(),
+        AttributeDataType::U32 => data
+            .chunks_exact(4)
+            .map(|c| LittleEndian::read_u32(c) as f32)
+            .collect(),
+        AttributeDataType::I32 => data
+            .chunks_exact(4)
+            .map(|c| LittleEndian::read_i32(c) as f32)
+            .collect(),
+        AttributeDataType::U64 => data
+            .chunks_exact(8)
+            .map(|c| LittleEndian::read_u64(c) as f32)
+            .collect(),
+        AttributeDataType::I64 => data
+            .chunks_exact(8)
+            .map(|c| LittleEndian::read_i64(c) as f32)
+            .collect(),
+        AttributeDataType::F32 => data.chunks_exact(4).map(LittleEndian::read_f32).collect(),
+        AttributeDataType::F64 => data
+            .chunks_exact(8)
+            .map(|c| LittleEndian::read_f64(c) as f32)
+            .collect(),
+        AttributeDataType::U8Vec3 | AttributeDataType::F64Vec3 => return None,
+    };
+    Some(values)
+}
+
+/// Uploads `data` to a new buffer that feeds the attribute at `location` with `num_components`
+/// floats per point.
+fn float_buffer(
+    gl: &Rc<opengl::Gl>,
+    location: GLuint,
+    num_components: GLint,
+    data: &[u8],
+) -> GlBuffer {
+    let buffer = GlBuffer::new_array_buffer(Rc::clone(gl));
+    unsafe {
+        buffer.bind();
+        gl.BufferData(
+            opengl::ARRAY_BUFFER,
+            data.len() as GLsizeiptr,
+            &data[0] as *const u8 as *const c_void,
+            opengl::STATIC_DRAW,
+        );
+        gl.EnableVertexAttribArray(location);
+        gl.VertexAttribPointer(
+            location,
+            num_components,
+            opengl::FLOAT,
+            opengl::FALSE as GLboolean,
+            0,
+            ptr::null(),
+        );
+    }
+    buffer
+}
+
 impl NodeView {
-    fn new(node_drawer: &NodeDrawer, node_data: octree::NodeData) -> Self {
+    fn new(
+        node_drawer: &NodeDrawer,
+        node_data: octree::NodeData,
+        value_attribute: Option<&str>,
+    ) -> Self {
         let node_program = node_drawer.program(&node_data.meta.position_encoding);
         let program = &node_program.program;
         unsafe {
@@ -199,6 +457,28 @@ impl NodeView {
             },
         );
         let color = reshuffle(&indices, &node_data.color, 3);
+        // The normals are stored as f64, but f32 is precise enough for drawing.
+        let normal = node_data
+            .attributes
+            .iter()
+            .find(|attribute| attribute.name == "normal")
+            .map(|attribute| {
+                let normal: Vec<u8> = attribute
+                    .data
+                    .chunks_exact(8)
+                    .flat_map(|c| (f64::from_le_bytes(c.try_into().unwrap()) as f32).to_le_bytes())
+                    .collect();
+                reshuffle(&indices, &normal, 12)
+            });
+        let value = node_data
+            .attributes
+            .iter()
+            .find(|attribute| Some(attribute.name.as_str()) == value_attribute)
+            .and_then(scalar_values)
+            .map(|values| {
+                let value: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+                reshuffle(&indices, &value, 4)
+            });
 
         let buffer_position = GlBuffer::new_array_buffer(Rc::clone(&program.gl));
         let buffer_color = GlBuffer::new_array_buffer(Rc::clone(&program.gl));
@@ -249,24 +529,40 @@ impl NodeView {
                 ptr::null(),
             );
         }
+        let buffer_normal = normal
+            .as_ref()
+            .map(|normal| float_buffer(&program.gl, node_program.a_normal, 3, normal));
+        let buffer_value = value
+            .as_ref()
+            .map(|value| float_buffer(&program.gl, node_program.a_value, 1, value));
         NodeView {
             vertex_array,
             _buffer_position: buffer_position,
             _buffer_color: buffer_color,
+            buffer_normal,
+            buffer_value,
             meta: node_data.meta,
-            used_memory_bytes: position.len() + color.len(),
+            used_memory_bytes: position.len()
+                + color.len()
+                + normal.map_or(0, |n| n.len())
+                + value.map_or(0, |v| v.len()),
         }
     }
 }
 
+/// The number of nodes that 'NodeViewContainer' loads at once.
+const MAX_NUM_REQUESTED: usize = 10;
+
 // Keeps track of the nodes that were requested in-order and loads then one by one on request.
 pub struct NodeViewContainer {
     node_views: LruCache<octree::NodeId, NodeView>,
     // The node_ids that the I/O thread is currently loading.
     requested: FnvHashSet<octree::NodeId>,
+    // The attribute that is loaded in addition to position, color and normals.
+    value_attribute: Option<String>,
     // Communication with the I/O thread.
-    node_id_sender: Sender<octree::NodeId>,
-    node_data_receiver: Receiver<(octree::NodeId, octree::NodeData)>,
+    node_id_sender: Sender<(octree::NodeId, Option<String>)>,
+    node_data_receiver: Receiver<(octree::NodeId, Option<String>, octree::NodeData)>,
 }
 
 impl NodeViewContainer {
@@ -276,16 +572,39 @@ impl NodeViewContainer {
         let (node_id_sender, node_id_receiver) = mpsc::channel();
         let (node_data_sender, node_data_receiver) = mpsc::channel();
         std::thread::spawn(move || {
+            // Normals are only there if they were estimated for the octree, and other attributes
+            // depend on the input data, so we check once whether the root node has them.
+            let root_id = octree::NodeId::from_level_index(0, 0);
+            let mut available = FnvHashMap::default();
             // Loads the next node data in the receiver queue.
-            for node_id in node_id_receiver {
-                let node_data = octree.get_node_data(&node_id).unwrap();
+            for (node_id, value_attribute) in node_id_receiver {
+                let attributes: Vec<&str> = std::iter::once("normal")
+                    .chain(value_attribute.as_deref())
+                    .filter(|name| {
+                        *available.entry(name.to_string()).or_insert_with(|| {
+                            let exists = octree
+                                .get_node_data_with_attributes(&root_id, &[*name])
+                                .is_ok();
+                            if !exists {
+                                eprintln!("The octree has no attribute '{}'.", name);
+                            }
+                            exists
+                        })
+                    })
+                    .collect();
+                let node_data = octree
+                    .get_node_data_with_attributes(&node_id, &attributes)
+                    .unwrap();
                 // TODO(hrapp): reshuffle
-                node_data_sender.send((node_id, node_data)).unwrap();
+                node_data_sender
+                    .send((node_id, value_attribute, node_data))
+                    .unwrap();
             }
         });
         NodeViewContainer {
             node_views: LruCache::new(max_nodes_in_memory),
             requested: FnvHashSet::default(),
+            value_attribute: None,
             node_id_sender,
             node_data_receiver,
         }
@@ -293,16 +612,30 @@ impl NodeViewContainer {
 
     pub fn consume_arrived_nodes(&mut self, node_drawer: &NodeDrawer) -> bool {
         let mut consumed_any = false;
-        while let Ok((node_id, node_data)) = self.node_data_receiver.try_recv() {
-            // Put loaded node into hash map.
+        while let Ok((node_id, value_attribute, node_data)) = self.node_data_receiver.try_recv() {
             self.requested.remove(&node_id);
-            self.node_views
-                .put(node_id, NodeView::new(node_drawer, node_data));
+            // The node was requested before the attribute changed.
+            if value_attribute != self.value_attribute {
+                continue;
+            }
+            // Put loaded node into hash map.
+            let node_view = NodeView::new(node_drawer, node_data, value_attribute.as_deref());
+            self.node_views.put(node_id, node_view);
             consumed_any = true;
         }
         consumed_any
     }
 
+    /// Loads `value_attribute` with the nodes from now on. The nodes loaded so far are dropped
+    /// if they have a different one.
+    pub fn set_value_attribute(&mut self, value_attribute: Option<&str>) {
+        if self.value_attribute.as_deref() == value_attribute {
+            return;
+        }
+        self.value_attribute = value_attribute.map(str::to_string);
+        self.node_views.clear();
+    }
+
     // Returns the 'NodeView' for 'node_id' if it is already loaded, otherwise returns None, but
     // requested the node for loading in the I/O thread
     pub fn get_or_request(&mut self, node_id: &octree::NodeId) -> Option<&NodeView> {
@@ -312,18 +645,41 @@ impl NodeViewContainer {
 
         // Limit the number of requested nodes because after a camera move
         // requested nodes might not be in the frustum anymore.
-        if !self.requested.contains(node_id) && self.requested.len() < 10 {
+        if !self.requested.contains(node_id) && self.requested.len() < MAX_NUM_REQUESTED {
             self.requested.insert(*node_id);
-            self.node_id_sender.send(*node_id).unwrap();
+            self.node_id_sender
+                .send((*node_id, self.value_attribute.clone()))
+                .unwrap();
         }
         None
     }
 
+    pub fn is_loaded(&self, node_id: &octree::NodeId) -> bool {
+        self.node_views.contains(node_id)
+    }
+
+    /// Requests the first of `node_ids` that are not loaded yet, as long as the I/O thread has
+    /// capacity left after the requests of 'get_or_request'.
+    pub fn prefetch(&mut self, node_ids: &[octree::NodeId]) {
+        for node_id in node_ids {
+            if self.requested.len() >= MAX_NUM_REQUESTED {
+                break;
+            }
+            if !self.node_views.contains(node_id) && self.requested.insert(*node_id) {
+                self.node_id_sender
+                    .send((*node_id, self.value_attribute.clone()))
+                    .unwrap();
+            }
+        }
+    }
+
     pub fn request_all(&mut self, node_ids: &[octree::NodeId]) {
         for &node_id in node_ids {
             if !self.node_views.contains(&node_id) && !self.requested.contains(&node_id) {
                 self.requested.insert(node_id);
-                self.node_id_sender.send(node_id).unwrap();
+                self.node_id_sender
+                    .send((node_id, self.value_attribute.clone()))
+                    .unwrap();
             }
         }
     }
@@ -0,0 +1,123 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Predicts where the camera will be shortly, so that the nodes it is going to see can be loaded
+//! before they come into view.
+
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+
+/// After a pause this long, the camera starts moving anew instead of continuing its motion.
+const MAX_SECONDS_BETWEEN_POSES: f64 = 0.5;
+
+/// How much of the last velocity is kept when a new pose arrives, to smooth out jittery input.
+const SMOOTHING: f64 = 0.5;
+
+/// Tracks the velocity of the camera from its recent poses.
+pub struct CameraMotion {
+    last: Option<(time::Instant, Isometry3<f64>)>,
+    /// Meters per second in the world frame.
+    linear_velocity: Vector3<f64>,
+    /// Scaled axis of the rotation per second, in the world frame.
+    angular_velocity: Vector3<f64>,
+}
+
+impl CameraMotion {
+    pub fn new() -> Self {
+        CameraMotion {
+            last: None,
+            linear_velocity: Vector3::zeros(),
+            angular_velocity: Vector3::zeros(),
+        }
+    }
+
+    /// Records the pose of the camera at `now`.
+    pub fn update(&mut self, now: time::Instant, camera_to_world: &Isometry3<f64>) {
+        let (then, last_pose) = match self.last {
+            Some(last) => last,
+            None => {
+                self.last = Some((now, *camera_to_world));
+                return;
+            }
+        };
+        let seconds = (now - then).as_seconds_f64();
+        // Several poses in the same instant are the same motion.
+        if seconds <= 0. {
+            return;
+        }
+        if seconds <= MAX_SECONDS_BETWEEN_POSES {
+            let linear_velocity =
+                (camera_to_world.translation.vector - last_pose.translation.vector) / seconds;
+            let angular_velocity =
+                (camera_to_world.rotation * last_pose.rotation.inverse()).scaled_axis() / seconds;
+            self.linear_velocity =
+                SMOOTHING * self.linear_velocity + (1. - SMOOTHING) * linear_velocity;
+            self.angular_velocity =
+                SMOOTHING * self.angular_velocity + (1. - SMOOTHING) * angular_velocity;
+        } else {
+            self.linear_velocity = Vector3::zeros();
+            self.angular_velocity = Vector3::zeros();
+        }
+        self.last = Some((now, *camera_to_world));
+    }
+
+    /// The pose of the camera `lookahead` after the last update if it keeps moving like it did,
+    /// or None if it has not moved yet.
+    pub fn predict(&self, lookahead: time::Duration) -> Option<Isometry3<f64>> {
+        let (_, last_pose) = self.last?;
+        if self.linear_velocity == Vector3::zeros() && self.angular_velocity == Vector3::zeros() {
+            return None;
+        }
+        let seconds = lookahead.as_seconds_f64();
+        let rotation = UnitQuaternion::new(self.angular_velocity * seconds) * last_pose.rotation;
+        let translation =
+            Translation3::from(last_pose.translation.vector + self.linear_velocity * seconds);
+        Some(Isometry3::from_parts(translation, rotation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extrapolates_motion() {
+        let mut motion = CameraMotion::new();
+        let start = time::Instant::now();
+        assert!(motion.predict(time::Duration::milliseconds(300)).is_none());
+        motion.update(start, &Isometry3::identity());
+        assert!(motion.predict(time::Duration::milliseconds(300)).is_none());
+
+        // Moving along x at 10 m/s while turning around z at 1 rad/s.
+        let pose_at = |seconds: f64| {
+            Isometry3::new(
+                Vector3::new(10. * seconds, 0., 0.),
+                Vector3::new(0., 0., seconds),
+            )
+        };
+        for i in 1..=20 {
+            motion.update(
+                start + time::Duration::milliseconds(i * 10),
+                &pose_at(i as f64 * 0.01),
+            );
+        }
+        let predicted = motion.predict(time::Duration::milliseconds(300)).unwrap();
+        let expected = pose_at(0.5);
+        assert!((predicted.translation.vector - expected.translation.vector).norm() < 1e-4);
+        assert!(predicted.rotation.angle_to(&expected.rotation) < 1e-5);
+
+        // After a pause, the camera starts from rest.
+        motion.update(start + time::Duration::seconds(5), &pose_at(0.2));
+        assert!(motion.predict(time::Duration::milliseconds(300)).is_none());
+    }
+}
@@ -204,6 +204,70 @@ impl AttributeData {
         match_attr_data!(self, rhs, at)
     }
 
+    /// Returns a copy of the values in `range`.
+    pub fn slice(&self, range: std::ops::Range<usize>) -> Self {
+        macro_rules! rhs {
+            ($dtype:ident, $data:ident, $range:expr) => {
+                AttributeData::$dtype($data[$range].to_vec())
+            };
+        }
+        match_attr_data!(self, rhs, range)
+    }
+
+    /// Replaces the values where `mask` is true, in order, with those of `values`.
+    pub fn replace_masked(
+        &mut self,
+        mask: &[bool],
+        values: &Self,
+    ) -> std::result::Result<(), String> {
+        fn replace<T: Copy>(data: &mut [T], mask: &[bool], values: &[T]) {
+            let masked = data.iter_mut().zip(mask).filter(|(_, m)| **m);
+            for ((d, _), v) in masked.zip(values) {
+                *d = *v;
+            }
+        }
+        match (self, values) {
+            (AttributeData::U8(s), AttributeData::U8(v)) => replace(s, mask, v),
+            (AttributeData::U16(s), AttributeData::U16(v)) => replace(s, mask, v),
+            (AttributeData::U32(s), AttributeData::U32(v)) => replace(s, mask, v),
+            (AttributeData::U64(s), AttributeData::U64(v)) => replace(s, mask, v),
+            (AttributeData::I8(s), AttributeData::I8(v)) => replace(s, mask, v),
+            (AttributeData::I16(s), AttributeData::I16(v)) => replace(s, mask, v),
+            (AttributeData::I32(s), AttributeData::I32(v)) => replace(s, mask, v),
+            (AttributeData::I64(s), AttributeData::I64(v)) => replace(s, mask, v),
+            (AttributeData::F32(s), AttributeData::F32(v)) => replace(s, mask, v),
+            (AttributeData::F64(s), AttributeData::F64(v)) => replace(s, mask, v),
+            (AttributeData::U8Vec3(s), AttributeData::U8Vec3(v)) => replace(s, mask, v),
+            (AttributeData::F64Vec3(s), AttributeData::F64Vec3(v)) => replace(s, mask, v),
+            (s, v) => {
+                return Err(format!(
+                    "Own data type '{:?}' is incompatible with other type '{:?}'.",
+                    s.data_type(),
+                    v.data_type(),
+                ))
+            }
+        };
+        Ok(())
+    }
+
+    /// Returns `len` zeros of `data_type`, e.g. for points that do not have an attribute.
+    pub fn zeros(data_type: AttributeDataType, len: usize) -> Self {
+        match data_type {
+            AttributeDataType::U8 => AttributeData::U8(vec![0; len]),
+            AttributeDataType::U16 => AttributeData::U16(vec![0; len]),
+            AttributeDataType::U32 => AttributeData::U32(vec![0; len]),
+            AttributeDataType::U64 => AttributeData::U64(vec![0; len]),
+            AttributeDataType::I8 => AttributeData::I8(vec![0; len]),
+            AttributeDataType::I16 => AttributeData::I16(vec![0; len]),
+            AttributeDataType::I32 => AttributeData::I32(vec![0; len]),
+            AttributeDataType::I64 => AttributeData::I64(vec![0; len]),
+            AttributeDataType::F32 => AttributeData::F32(vec![0.; len]),
+            AttributeDataType::F64 => AttributeData::F64(vec![0.; len]),
+            AttributeDataType::U8Vec3 => AttributeData::U8Vec3(vec![Vector3::zeros(); len]),
+            AttributeDataType::F64Vec3 => AttributeData::F64Vec3(vec![Vector3::zeros(); len]),
+        }
+    }
+
     pub fn get(&self, idx: usize) -> Self {
         macro_rules! rhs {
             ($dtype:ident, $data:ident, $idx:expr) => {
@@ -13,14 +13,24 @@
 // limitations under the License.
 
 use clap::Clap;
-use point_viewer::octree::build_octree_from_file;
+use nalgebra::Point3;
+use point_viewer::coordinates::{self, CoordinateSystem};
+use point_viewer::metadata::{read_metadata, write_metadata, ProcessingStep};
+use point_viewer::octree::{
+    self, build_octree_from_file, resume_octree, IntensityNormalization, OutlierFilter,
+    RangeCorrection,
+};
+use point_viewer::read_write::InputFileIterator;
+use point_viewer::utils::parse_key_val;
+use point_viewer::NUM_POINTS_PER_BATCH;
 use rayon::ThreadPoolBuilder;
 use std::path::PathBuf;
 
 #[derive(Clap, Debug)]
 #[clap(name = "build_octree")]
 struct CommandlineArguments {
-    /// PLY/PTS file to parse for the points.
+    /// PLY, E57 or PCD file to parse for the points. The scans of E57 files are merged into one
+    /// octree.
     #[clap(parse(from_os_str))]
     input: PathBuf,
 
@@ -28,6 +38,22 @@ struct CommandlineArguments {
     #[clap(long, parse(from_os_str))]
     output_directory: PathBuf,
 
+    /// Add the points to the existing octree in the output directory instead of building a new
+    /// one. Points outside of the octree's bounding box are dropped.
+    #[clap(long)]
+    append: bool,
+
+    /// Continue the interrupted build of the octree in the output directory from its last
+    /// checkpoint instead of starting over. The outlier filters need to be the same as before.
+    /// Builds from scratch if there is nothing to resume.
+    #[clap(long)]
+    resume: bool,
+
+    /// Estimate a 'normal' attribute for every point from its nearest neighbors once the octree
+    /// is built.
+    #[clap(long)]
+    estimate_normals: bool,
+
     /// Minimal precision that this point cloud should have.
     /// This decides on the number of bits used to encode each node.
     #[clap(long, default_value = "0.001")]
@@ -36,6 +62,93 @@ struct CommandlineArguments {
     /// The number of threads used to shard octree building. Set this as high as possible for SSDs.
     #[clap(long, default_value = "10")]
     num_threads: usize,
+
+    /// The coordinate system of the input points, stored in the octree's meta data: 'ecef',
+    /// 'utm:<zone><N or S>', e.g. 'utm:32N', or 'enu:<latitude>,<longitude>,<altitude>'.
+    #[clap(long)]
+    coordinate_system: Option<CoordinateSystem>,
+
+    /// Remove the points whose mean distance to this many nearest neighbors is unusually large,
+    /// e.g. sensor noise. Not supported with '--append'.
+    #[clap(long)]
+    sor_k: Option<usize>,
+
+    /// The number of standard deviations above the mean that the mean distance of a point to its
+    /// neighbors may be for '--sor-k'.
+    #[clap(long, default_value = "1.0")]
+    sor_stddev: f64,
+
+    /// Remove the points with fewer than '--ror-min-neighbors' other points within this radius,
+    /// e.g. birds. Not supported with '--append'.
+    #[clap(long)]
+    ror_radius: Option<f64>,
+
+    /// The number of neighbors a point needs within '--ror-radius' to be kept.
+    #[clap(long, default_value = "2")]
+    ror_min_neighbors: usize,
+
+    /// Map the intensities of the input file to [0, 1] before they are stored, so that they can
+    /// be used for coloring without knowing the sensor. They are clipped to the percentiles of
+    /// '--intensity-percentiles' and stretched linearly in between.
+    #[clap(long)]
+    normalize_intensity: bool,
+
+    /// The lower and upper percentile of the intensities for '--normalize-intensity'.
+    #[clap(long, default_value = "1,99", parse(try_from_str = parse_percentiles))]
+    intensity_percentiles: (f64, f64),
+
+    /// Stretch the intensities by their percentile instead of linearly, i.e. histogram
+    /// equalization, with '--normalize-intensity'.
+    #[clap(long)]
+    equalize_intensity: bool,
+
+    /// The normalized intensities are raised to this power. Below 1, dark points get brighter.
+    #[clap(long, default_value = "1.0")]
+    intensity_gamma: f64,
+
+    /// The position 'x,y,z' of the sensor that scanned the input file, in the frame of the
+    /// points. With '--normalize-intensity', the intensities are corrected for the weaker returns
+    /// of far points first, relative to '--intensity-reference-range'.
+    #[clap(long, parse(try_from_str = parse_point))]
+    intensity_sensor_position: Option<Point3<f64>>,
+
+    /// The distance to the sensor at which the intensities stay the same with
+    /// '--intensity-sensor-position'.
+    #[clap(long, default_value = "10.0")]
+    intensity_reference_range: f64,
+
+    /// The day the points were recorded, e.g. '2020-10-14', stored in the octree's meta data.
+    #[clap(long)]
+    acquisition_date: Option<String>,
+
+    /// The model of the sensor that recorded the points, stored in the octree's meta data.
+    #[clap(long)]
+    sensor_model: Option<String>,
+
+    /// Any other 'key=value' pair to store in the octree's meta data. Can be given several times.
+    #[clap(long, number_of_values = 1, parse(try_from_str = parse_key_val))]
+    metadata: Vec<(String, String)>,
+}
+
+fn parse_values(s: &str, num_values: usize) -> Result<Vec<f64>, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<f64>, String>>()?;
+    if values.len() != num_values {
+        return Err(format!("Expected {} values, got '{}'.", num_values, s));
+    }
+    Ok(values)
+}
+
+fn parse_percentiles(s: &str) -> Result<(f64, f64), String> {
+    let values = parse_values(s, 2)?;
+    Ok((values[0], values[1]))
+}
+
+fn parse_point(s: &str) -> Result<Point3<f64>, String> {
+    let values = parse_values(s, 3)?;
+    Ok(Point3::new(values[0], values[1], values[2]))
 }
 
 fn main() {
@@ -44,10 +157,90 @@ fn main() {
         .num_threads(args.num_threads)
         .build_global()
         .expect("Could not create thread pool.");
-    build_octree_from_file(
-        args.output_directory,
-        args.resolution,
-        args.input,
-        &["color", "intensity"],
-    );
+    let attributes = &["color", "intensity"];
+    let mut outlier_filters = Vec::new();
+    if let Some(num_neighbors) = args.sor_k {
+        outlier_filters.push(OutlierFilter::Statistical {
+            num_neighbors,
+            stddev_multiplier: args.sor_stddev,
+        });
+    }
+    if let Some(radius) = args.ror_radius {
+        outlier_filters.push(OutlierFilter::Radius {
+            radius,
+            min_neighbors: args.ror_min_neighbors,
+        });
+    }
+    let intensity_normalization = if args.normalize_intensity {
+        Some(IntensityNormalization {
+            range_correction: args.intensity_sensor_position.map(|sensor_position| {
+                RangeCorrection {
+                    sensor_position,
+                    reference_range: args.intensity_reference_range,
+                }
+            }),
+            lower_percentile: args.intensity_percentiles.0,
+            upper_percentile: args.intensity_percentiles.1,
+            equalize: args.equalize_intensity,
+            gamma: args.intensity_gamma,
+        })
+    } else {
+        None
+    };
+    if args.append {
+        assert!(!args.resume, "Only new octrees can be resumed.");
+        assert!(
+            outlier_filters.is_empty(),
+            "Outliers can only be removed when building a new octree."
+        );
+        let stream = InputFileIterator::from_file(&args.input, NUM_POINTS_PER_BATCH).unwrap();
+        match &intensity_normalization {
+            Some(intensity_normalization) => {
+                let mapping = intensity_normalization
+                    .fit(InputFileIterator::from_file(&args.input, NUM_POINTS_PER_BATCH).unwrap())
+                    .unwrap();
+                octree::update(
+                    &args.output_directory,
+                    mapping.normalize_batches(stream),
+                    attributes,
+                )
+            }
+            None => octree::update(&args.output_directory, stream, attributes),
+        }
+        .unwrap();
+    } else if !(args.resume && resume_octree(&args.output_directory, &outlier_filters).unwrap()) {
+        build_octree_from_file(
+            &args.output_directory,
+            args.resolution,
+            &args.input,
+            attributes,
+            &outlier_filters,
+            intensity_normalization.as_ref(),
+        );
+    }
+    if args.estimate_normals {
+        octree::estimate_normals(&args.output_directory, octree::NUM_NORMAL_NEIGHBORS).unwrap();
+    }
+    if let Some(coordinate_system) = &args.coordinate_system {
+        coordinates::write_coordinate_system(&args.output_directory, Some(coordinate_system))
+            .unwrap();
+    }
+    let mut metadata = read_metadata(&args.output_directory).unwrap();
+    if let Some(acquisition_date) = args.acquisition_date {
+        metadata.acquisition_date = Some(acquisition_date);
+    }
+    if let Some(sensor_model) = args.sensor_model {
+        metadata.sensor_model = Some(sensor_model);
+    }
+    metadata.custom.extend(args.metadata);
+    let action = if args.append {
+        "Appended"
+    } else {
+        "Built from"
+    };
+    metadata.processing_history.push(ProcessingStep::now(
+        "build_octree",
+        format!("{} {}.", action, args.input.display()),
+    ));
+    write_metadata(&args.output_directory, &metadata).unwrap();
 }
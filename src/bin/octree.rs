@@ -0,0 +1,281 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::Clap;
+use point_viewer::data_provider::OnDiskDataProvider;
+use point_viewer::errors::Result;
+use point_viewer::iterator::{PointCloud, PointLocation};
+use point_viewer::octree::{
+    build_octree_from_s2_cells, export_octree, merge_octrees, repair_octree, validate_octree,
+    write_s2_cells_from_file, write_s2_cells_from_octree, Deduplication, DuplicatePreference,
+    ExportFormat, Octree,
+};
+use std::path::PathBuf;
+
+#[derive(Clap, Debug)]
+#[clap(name = "octree", about = "Tools working on octrees on disk.")]
+struct CommandlineArguments {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Clap, Debug)]
+enum Command {
+    /// Prints the meta data of an octree: its size, attributes, coordinate system and where its
+    /// points come from.
+    Info(InfoArguments),
+    /// Checks the meta of an octree against its node files, e.g. after a partial copy.
+    Validate(ValidateArguments),
+    /// Builds an octree out of an S2 point cloud, with all of its attributes.
+    FromS2(FromS2Arguments),
+    /// Writes the points of an octree or of a PLY, E57 or PCD file into an S2 point cloud, with all
+    /// of their attributes.
+    ToS2(ToS2Arguments),
+    /// Merges several octrees into one, with the attributes of all of them.
+    Merge(MergeArguments),
+    /// Converts an octree for third-party web viewers, keeping its nodes as levels of detail.
+    Export(ExportArguments),
+}
+
+#[derive(Clap, Debug)]
+struct InfoArguments {
+    /// Directory of the octree to describe.
+    #[clap(parse(from_os_str))]
+    directory: PathBuf,
+}
+
+#[derive(Clap, Debug)]
+struct ValidateArguments {
+    /// Directory of the octree to validate.
+    #[clap(parse(from_os_str))]
+    directory: PathBuf,
+
+    /// Removes orphaned and broken nodes and rewrites the meta to match the files on disk.
+    #[clap(long)]
+    repair: bool,
+}
+
+#[derive(Clap, Debug)]
+struct FromS2Arguments {
+    /// Directory of the S2 point cloud to read.
+    #[clap(parse(from_os_str))]
+    s2_directory: PathBuf,
+
+    /// Output directory to write the octree into.
+    #[clap(parse(from_os_str))]
+    output_directory: PathBuf,
+
+    /// Minimal precision that this point cloud should have.
+    /// This decides on the number of bits used to encode each node.
+    #[clap(long, default_value = "0.001")]
+    resolution: f64,
+}
+
+#[derive(Clap, Debug)]
+struct ToS2Arguments {
+    /// Directory of the octree to read, or a PLY, E57 or PCD file whose points are in ECEF.
+    #[clap(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Output directory to write the S2 point cloud into.
+    #[clap(parse(from_os_str))]
+    output_directory: PathBuf,
+
+    /// The S2 level of the cells the points are split into. Level 20 cells are about 10 m wide.
+    #[clap(long, default_value = "20")]
+    split_level: u64,
+}
+
+#[derive(Clap, Debug)]
+struct MergeArguments {
+    /// Directories of the octrees to merge. They need to be in the same coordinate system.
+    #[clap(parse(from_os_str), required = true)]
+    input_directories: Vec<PathBuf>,
+
+    /// Output directory to write the merged octree into.
+    #[clap(long, parse(from_os_str))]
+    output_directory: PathBuf,
+
+    /// Minimal precision that the merged point cloud should have. Defaults to the finest
+    /// resolution of the inputs.
+    #[clap(long)]
+    resolution: Option<f64>,
+
+    /// Remove the points within this distance of a preferred point, e.g. where the octrees
+    /// overlap.
+    #[clap(long)]
+    dedup_epsilon: Option<f64>,
+
+    /// Which of the duplicates '--dedup-epsilon' keeps: the one with the highest 'intensity' or
+    /// the latest 'timestamp'.
+    #[clap(long, default_value = "intensity")]
+    dedup_keep: DuplicatePreference,
+}
+
+#[derive(Clap, Debug)]
+struct ExportArguments {
+    /// Directory of the octree to export.
+    #[clap(parse(from_os_str))]
+    octree_directory: PathBuf,
+
+    /// Output directory to write the export into.
+    #[clap(long, parse(from_os_str))]
+    output_directory: PathBuf,
+
+    /// '3d-tiles' for a Cesium tileset of pnts tiles in ECEF, 'potree' for Potree 2, or 'pcd' for
+    /// a single PCD file.
+    #[clap(long, default_value = "3d-tiles")]
+    format: ExportFormat,
+}
+
+fn info(args: &InfoArguments) -> Result<bool> {
+    let octree_data_provider = OnDiskDataProvider {
+        directory: args.directory.clone(),
+    };
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: args.directory.clone(),
+    }))?;
+    let node_ids = octree.nodes_in_location(&PointLocation::AllPoints);
+    let num_points: usize = node_ids
+        .iter()
+        .map(|node_id| octree.num_points_in_node(*node_id))
+        .sum();
+    println!("Points: {} in {} nodes", num_points, node_ids.len());
+    println!("Resolution: {}", octree.meta().resolution);
+    let (min, max) = (octree.bounding_box().min(), octree.bounding_box().max());
+    println!(
+        "Bounding box: ({}, {}, {}) to ({}, {}, {})",
+        min.x, min.y, min.z, max.x, max.y, max.z
+    );
+    match octree.coordinate_system() {
+        Some(coordinate_system) => println!("Coordinate system: {}", coordinate_system),
+        None => println!("Coordinate system: unknown"),
+    }
+    // Every node of a build has the same attributes.
+    if let Some(node_id) = node_ids
+        .iter()
+        .find(|node_id| octree.num_points_in_node(**node_id) > 0)
+    {
+        let mut attributes: Vec<_> = octree
+            .attributes_on_disk(&octree_data_provider, node_id)
+            .into_iter()
+            .map(|(name, data_type)| format!("{} ({:?})", name, data_type))
+            .collect();
+        attributes.sort();
+        println!("Attributes: {}", attributes.join(", "));
+    }
+    if let Some(label_dictionary) = octree.label_dictionary() {
+        let labels: Vec<_> = label_dictionary
+            .labels()
+            .map(|(id, name)| format!("{} {}", id, name))
+            .collect();
+        println!("Labels: {}", labels.join(", "));
+    }
+
+    let metadata = &octree.meta().metadata;
+    let unknown = |value: &Option<String>| value.clone().unwrap_or_else(|| "unknown".to_string());
+    println!("Acquisition date: {}", unknown(&metadata.acquisition_date));
+    println!("Sensor model: {}", unknown(&metadata.sensor_model));
+    if !metadata.processing_history.is_empty() {
+        println!("Processing history:");
+        for step in &metadata.processing_history {
+            println!("  {}", step);
+        }
+    }
+    if !metadata.custom.is_empty() {
+        println!("Custom:");
+        for (key, value) in &metadata.custom {
+            println!("  {} = {}", key, value);
+        }
+    }
+    Ok(true)
+}
+
+/// Returns whether the octree is valid in the end.
+fn validate(args: &ValidateArguments) -> Result<bool> {
+    let problems = validate_octree(&args.directory)?;
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        eprintln!("The octree is valid.");
+        return Ok(true);
+    }
+    eprintln!("Found {} problems.", problems.len());
+    if !args.repair {
+        return Ok(false);
+    }
+    let remaining = repair_octree(&args.directory, &problems)?;
+    for problem in &remaining {
+        println!("Not repaired: {}", problem);
+    }
+    eprintln!(
+        "Repaired {} problems.",
+        problems.len().saturating_sub(remaining.len())
+    );
+    Ok(remaining.is_empty())
+}
+
+fn main() {
+    let args = CommandlineArguments::parse();
+    let result = match &args.command {
+        Command::Info(info_args) => info(info_args),
+        Command::Validate(validate_args) => validate(validate_args),
+        Command::FromS2(from_s2_args) => build_octree_from_s2_cells(
+            &from_s2_args.output_directory,
+            from_s2_args.resolution,
+            &from_s2_args.s2_directory,
+        )
+        .map(|()| true),
+        Command::ToS2(to_s2_args) if to_s2_args.input.is_file() => write_s2_cells_from_file(
+            &to_s2_args.output_directory,
+            to_s2_args.split_level,
+            &to_s2_args.input,
+        )
+        .map(|()| true),
+        Command::ToS2(to_s2_args) => write_s2_cells_from_octree(
+            &to_s2_args.output_directory,
+            to_s2_args.split_level,
+            &to_s2_args.input,
+        )
+        .map(|()| true),
+        Command::Merge(merge_args) => {
+            let deduplication = merge_args.dedup_epsilon.map(|epsilon| Deduplication {
+                epsilon,
+                keep: merge_args.dedup_keep,
+            });
+            merge_octrees(
+                &merge_args.output_directory,
+                &merge_args.input_directories,
+                merge_args.resolution,
+                deduplication.as_ref(),
+            )
+            .map(|()| true)
+        }
+        Command::Export(export_args) => export_octree(
+            &export_args.octree_directory,
+            &export_args.output_directory,
+            export_args.format,
+        )
+        .map(|()| true),
+    };
+    match result {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Encountered error:\n{}", e);
+            std::process::exit(1);
+        }
+    }
+}
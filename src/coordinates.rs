@@ -0,0 +1,623 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Coordinate reference systems of point clouds, and the reprojection of queries and points
+//! between them. All systems are based on the WGS84 ellipsoid and converted through ECEF.
+
+use crate::data_provider::{DataProvider, OnDiskDataProvider};
+use crate::errors::*;
+use crate::geometry::Aabb;
+use crate::iterator::PointLocation;
+use crate::math::{local_frame_from_lat_lng, ConvexPolyhedron};
+use crate::proto;
+use crate::PointsBatch;
+use nalgebra::{Isometry3, Point3, Translation3, Vector3};
+use nav_types::{ECEF, WGS84};
+use std::convert::TryFrom;
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+// The WGS84 ellipsoid.
+const SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+const FLATTENING: f64 = 1.0 / 298.257_223_563;
+// The radius of curvature of the ellipsoid is never smaller than this.
+const MIN_RADIUS_OF_CURVATURE: f64 = 6_335_439.0;
+
+const UTM_SCALE_ON_CENTRAL_MERIDIAN: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+const UTM_FALSE_NORTHING_SOUTH: f64 = 10_000_000.0;
+
+/// A zone of the Universal Transverse Mercator projection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UtmZone {
+    number: u8,
+    north: bool,
+}
+
+impl UtmZone {
+    /// Fails if `number` is not in 1 to 60.
+    pub fn new(number: u8, north: bool) -> Result<Self> {
+        if !(1..=60).contains(&number) {
+            return Err(
+                ErrorKind::InvalidInput(format!("There is no UTM zone {}.", number)).into(),
+            );
+        }
+        Ok(UtmZone { number, north })
+    }
+
+    /// The zone that `lat_lng` lies in. The exceptions around Norway and Svalbard are ignored.
+    pub fn containing(lat_lng: &WGS84<f64>) -> Self {
+        let index = ((lat_lng.longitude_degrees() + 180.) / 6.).floor() as i64;
+        UtmZone {
+            number: index.rem_euclid(60) as u8 + 1,
+            north: lat_lng.latitude_degrees() >= 0.,
+        }
+    }
+
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    pub fn north(&self) -> bool {
+        self.north
+    }
+
+    fn central_meridian(&self) -> f64 {
+        (f64::from(self.number) * 6. - 183.).to_radians()
+    }
+
+    fn false_northing(&self) -> f64 {
+        if self.north {
+            0.
+        } else {
+            UTM_FALSE_NORTHING_SOUTH
+        }
+    }
+}
+
+/// The coefficients of the series of Krüger for the transverse Mercator projection, see
+/// https://en.wikipedia.org/wiki/Universal_Transverse_Mercator_coordinate_system.
+struct KruegerSeries {
+    n: f64,
+    /// The scaled radius of the rectifying sphere.
+    k0_a: f64,
+    alpha: [f64; 3],
+    beta: [f64; 3],
+    delta: [f64; 3],
+}
+
+impl KruegerSeries {
+    fn new() -> Self {
+        let n = FLATTENING / (2. - FLATTENING);
+        let (n2, n3) = (n * n, n * n * n);
+        KruegerSeries {
+            n,
+            k0_a: UTM_SCALE_ON_CENTRAL_MERIDIAN * SEMI_MAJOR_AXIS / (1. + n)
+                * (1. + n2 / 4. + n2 * n2 / 64.),
+            alpha: [
+                n / 2. - 2. / 3. * n2 + 5. / 16. * n3,
+                13. / 48. * n2 - 3. / 5. * n3,
+                61. / 240. * n3,
+            ],
+            beta: [
+                n / 2. - 2. / 3. * n2 + 37. / 96. * n3,
+                1. / 48. * n2 + 1. / 15. * n3,
+                17. / 480. * n3,
+            ],
+            delta: [
+                2. * n - 2. / 3. * n2 - 2. * n3,
+                7. / 3. * n2 - 8. / 5. * n3,
+                56. / 15. * n3,
+            ],
+        }
+    }
+}
+
+/// Projects `lat_lng` into `zone` and returns its easting and northing in meters. The projection
+/// is accurate to a millimeter within a few thousand kilometers of the central meridian.
+pub fn utm_from_wgs84(lat_lng: &WGS84<f64>, zone: UtmZone) -> (f64, f64) {
+    let series = KruegerSeries::new();
+    let sin_latitude = lat_lng.latitude_radians().sin();
+    let longitude = lat_lng.longitude_radians() - zone.central_meridian();
+    let c = 2. * series.n.sqrt() / (1. + series.n);
+    let t = (sin_latitude.atanh() - c * (c * sin_latitude).atanh()).sinh();
+    let xi = t.atan2(longitude.cos());
+    let eta = (longitude.sin() / (1. + t * t).sqrt()).atanh();
+    let (mut easting, mut northing) = (eta, xi);
+    for (j, alpha) in series.alpha.iter().enumerate() {
+        let k = 2. * (j + 1) as f64;
+        easting += alpha * (k * xi).cos() * (k * eta).sinh();
+        northing += alpha * (k * xi).sin() * (k * eta).cosh();
+    }
+    (
+        UTM_FALSE_EASTING + series.k0_a * easting,
+        zone.false_northing() + series.k0_a * northing,
+    )
+}
+
+/// The inverse of 'utm_from_wgs84'.
+pub fn wgs84_from_utm(easting: f64, northing: f64, altitude: f64, zone: UtmZone) -> WGS84<f64> {
+    let series = KruegerSeries::new();
+    let xi = (northing - zone.false_northing()) / series.k0_a;
+    let eta = (easting - UTM_FALSE_EASTING) / series.k0_a;
+    let (mut xi_prime, mut eta_prime) = (xi, eta);
+    for (j, beta) in series.beta.iter().enumerate() {
+        let k = 2. * (j + 1) as f64;
+        xi_prime -= beta * (k * xi).sin() * (k * eta).cosh();
+        eta_prime -= beta * (k * xi).cos() * (k * eta).sinh();
+    }
+    let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+    let mut latitude = chi;
+    for (j, delta) in series.delta.iter().enumerate() {
+        latitude += delta * (2. * (j + 1) as f64 * chi).sin();
+    }
+    let mut longitude = zone.central_meridian() + eta_prime.sinh().atan2(xi_prime.cos());
+    if longitude > PI {
+        longitude -= 2. * PI;
+    } else if longitude < -PI {
+        longitude += 2. * PI;
+    }
+    WGS84::from_radians_and_meters(latitude, longitude, altitude)
+}
+
+/// The transform from the east, north and up axes at `origin` to ECEF.
+pub fn ecef_from_enu(origin: &WGS84<f64>) -> Isometry3<f64> {
+    let enu_from_ecef = Translation3::new(0., 0., -origin.altitude())
+        * local_frame_from_lat_lng(origin.latitude_degrees(), origin.longitude_degrees());
+    enu_from_ecef.inverse()
+}
+
+/// The coordinate reference system of the positions of a point cloud.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordinateSystem {
+    Ecef,
+    /// x is the easting, y the northing and z the altitude above the ellipsoid.
+    Utm(UtmZone),
+    /// East, north and up axes at the origin.
+    LocalEnu(WGS84<f64>),
+}
+
+impl CoordinateSystem {
+    /// The coordinate system in a meta proto, None if it is not known.
+    pub fn from_meta_proto(meta: &proto::Meta) -> Result<Option<Self>> {
+        if !meta.has_coordinate_system() {
+            return Ok(None);
+        }
+        let system = meta.get_coordinate_system();
+        if system.has_ecef() {
+            Ok(Some(CoordinateSystem::Ecef))
+        } else if system.has_utm() {
+            let utm = system.get_utm();
+            let number = u8::try_from(utm.number).unwrap_or(0);
+            Ok(Some(CoordinateSystem::Utm(UtmZone::new(
+                number, utm.north,
+            )?)))
+        } else if system.has_local_enu() {
+            let origin = system.get_local_enu();
+            WGS84::try_from_degrees_and_meters(
+                origin.latitude_degrees,
+                origin.longitude_degrees,
+                origin.altitude_meters,
+            )
+            .map(|origin| Some(CoordinateSystem::LocalEnu(origin)))
+            .ok_or_else(|| {
+                ErrorKind::InvalidInput("Invalid origin of the local ENU frame.".to_string()).into()
+            })
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn to_proto(&self) -> proto::CoordinateSystem {
+        let mut system = proto::CoordinateSystem::new();
+        match self {
+            CoordinateSystem::Ecef => system.set_ecef(proto::CoordinateSystem_Ecef::new()),
+            CoordinateSystem::Utm(zone) => {
+                let mut utm = proto::UtmZone::new();
+                utm.set_number(u32::from(zone.number));
+                utm.set_north(zone.north);
+                system.set_utm(utm);
+            }
+            CoordinateSystem::LocalEnu(origin) => {
+                let mut local_enu = proto::Wgs84Point::new();
+                local_enu.set_latitude_degrees(origin.latitude_degrees());
+                local_enu.set_longitude_degrees(origin.longitude_degrees());
+                local_enu.set_altitude_meters(origin.altitude());
+                system.set_local_enu(local_enu);
+            }
+        }
+        system
+    }
+
+    pub fn ecef_from_point(&self, p: &Point3<f64>) -> Point3<f64> {
+        Conversion::new(self).ecef_from_point(p)
+    }
+
+    pub fn point_from_ecef(&self, p: &Point3<f64>) -> Point3<f64> {
+        Conversion::new(self).point_from_ecef(p)
+    }
+}
+
+/// Parses 'ecef', 'utm:<zone number><N or S>', e.g. 'utm:10N', or
+/// 'enu:<latitude>,<longitude>,<altitude>' in degrees and meters.
+impl FromStr for CoordinateSystem {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || -> Error {
+            ErrorKind::InvalidInput(format!("Invalid coordinate system '{}'.", s)).into()
+        };
+        let lowercase = s.trim().to_lowercase();
+        let mut parts = lowercase.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("ecef"), None) => Ok(CoordinateSystem::Ecef),
+            (Some("utm"), Some(zone)) if zone.len() > 1 => {
+                let (number, hemisphere) = zone.split_at(zone.len() - 1);
+                let north = match hemisphere {
+                    "n" => true,
+                    "s" => false,
+                    _ => return Err(invalid()),
+                };
+                let number = number.parse().map_err(|_| invalid())?;
+                Ok(CoordinateSystem::Utm(UtmZone::new(number, north)?))
+            }
+            (Some("enu"), Some(origin)) => {
+                let values = origin
+                    .split(',')
+                    .map(|v| v.trim().parse::<f64>().ok())
+                    .collect::<Option<Vec<f64>>>()
+                    .ok_or_else(invalid)?;
+                if values.len() != 3 {
+                    return Err(invalid());
+                }
+                WGS84::try_from_degrees_and_meters(values[0], values[1], values[2])
+                    .map(CoordinateSystem::LocalEnu)
+                    .ok_or_else(invalid)
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for CoordinateSystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoordinateSystem::Ecef => write!(f, "ecef"),
+            CoordinateSystem::Utm(zone) => write!(
+                f,
+                "utm:{}{}",
+                zone.number,
+                if zone.north { "N" } else { "S" }
+            ),
+            CoordinateSystem::LocalEnu(origin) => write!(
+                f,
+                "enu:{},{},{}",
+                origin.latitude_degrees(),
+                origin.longitude_degrees(),
+                origin.altitude()
+            ),
+        }
+    }
+}
+
+/// Tags the point cloud in `directory` with `system`, or removes the tag for None.
+pub fn write_coordinate_system(
+    directory: impl AsRef<Path>,
+    system: Option<&CoordinateSystem>,
+) -> Result<()> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    let mut meta = data_provider.meta_proto()?;
+    match system {
+        Some(system) => meta.set_coordinate_system(system.to_proto()),
+        None => meta.clear_coordinate_system(),
+    }
+    data_provider.write_meta_proto(&meta)
+}
+
+/// The conversion of a coordinate system to and from ECEF.
+#[derive(Debug, Clone)]
+enum Conversion {
+    Isometry { ecef_from_local: Isometry3<f64> },
+    Utm(UtmZone),
+}
+
+impl Conversion {
+    fn new(system: &CoordinateSystem) -> Self {
+        match system {
+            CoordinateSystem::Ecef => Conversion::Isometry {
+                ecef_from_local: Isometry3::identity(),
+            },
+            CoordinateSystem::Utm(zone) => Conversion::Utm(*zone),
+            CoordinateSystem::LocalEnu(origin) => Conversion::Isometry {
+                ecef_from_local: ecef_from_enu(origin),
+            },
+        }
+    }
+
+    fn ecef_from_point(&self, p: &Point3<f64>) -> Point3<f64> {
+        match self {
+            Conversion::Isometry { ecef_from_local } => ecef_from_local * p,
+            Conversion::Utm(zone) => {
+                let ecef = ECEF::from(wgs84_from_utm(p.x, p.y, p.z, *zone));
+                Point3::new(ecef.x(), ecef.y(), ecef.z())
+            }
+        }
+    }
+
+    fn point_from_ecef(&self, p: &Point3<f64>) -> Point3<f64> {
+        match self {
+            Conversion::Isometry { ecef_from_local } => ecef_from_local.inverse_transform_point(p),
+            Conversion::Utm(zone) => {
+                let lat_lng = WGS84::from(ECEF::new(p.x, p.y, p.z));
+                let (easting, northing) = utm_from_wgs84(&lat_lng, *zone);
+                Point3::new(easting, northing, lat_lng.altitude())
+            }
+        }
+    }
+}
+
+/// Converts positions from one coordinate system into another.
+#[derive(Debug, Clone)]
+pub struct Reprojection {
+    from: CoordinateSystem,
+    to: CoordinateSystem,
+    from_conversion: Conversion,
+    to_conversion: Conversion,
+    /// Set if the systems differ only by an isometry, e.g. ECEF and local ENU.
+    to_from: Option<Isometry3<f64>>,
+}
+
+impl Reprojection {
+    pub fn new(from: CoordinateSystem, to: CoordinateSystem) -> Self {
+        let from_conversion = Conversion::new(&from);
+        let to_conversion = Conversion::new(&to);
+        let to_from = match (&from_conversion, &to_conversion) {
+            (
+                Conversion::Isometry {
+                    ecef_from_local: ecef_from_a,
+                },
+                Conversion::Isometry {
+                    ecef_from_local: ecef_from_b,
+                },
+            ) => Some(ecef_from_b.inverse() * ecef_from_a),
+            _ if from == to => Some(Isometry3::identity()),
+            _ => None,
+        };
+        Reprojection {
+            from,
+            to,
+            from_conversion,
+            to_conversion,
+            to_from,
+        }
+    }
+
+    pub fn from(&self) -> &CoordinateSystem {
+        &self.from
+    }
+
+    pub fn to(&self) -> &CoordinateSystem {
+        &self.to
+    }
+
+    pub fn inverse(&self) -> Self {
+        Reprojection::new(self.to, self.from)
+    }
+
+    /// The transform between the systems if they differ only by an isometry, e.g. ECEF and local
+    /// ENU. Locations are then reprojected exactly.
+    pub fn isometry(&self) -> Option<&Isometry3<f64>> {
+        self.to_from.as_ref()
+    }
+
+    pub fn transform_point(&self, p: &Point3<f64>) -> Point3<f64> {
+        match &self.to_from {
+            Some(to_from) => to_from * p,
+            None => self
+                .to_conversion
+                .point_from_ecef(&self.from_conversion.ecef_from_point(p)),
+        }
+    }
+
+    pub fn transform_batch(&self, batch: &mut PointsBatch) {
+        for position in &mut batch.position {
+            *position = self.transform_point(position);
+        }
+    }
+
+    /// Returns a location in the target system that contains all points of `location`. This is
+    /// exact if there is an 'isometry'. Otherwise, it is a box around the reprojected location, and
+    /// the points in it need to be checked against `location` after reprojecting them back.
+    /// S2 cells and Web Mercator rectangles are tied to ECEF and can only be used for ECEF.
+    pub fn transform_location(&self, location: &PointLocation) -> Result<PointLocation> {
+        let bounding_box = match location {
+            PointLocation::AllPoints => return Ok(PointLocation::AllPoints),
+            PointLocation::S2Cells(_) | PointLocation::WebMercatorRect(_) => {
+                if self.to != CoordinateSystem::Ecef {
+                    return Err(ErrorKind::InvalidInput(
+                        "S2 cells and Web Mercator rectangles can only query points in ECEF."
+                            .to_string(),
+                    )
+                    .into());
+                }
+                return Ok(location.clone());
+            }
+            _ if self.to_from.is_some() => {
+                return location.transformed(self.to_from.as_ref().unwrap());
+            }
+            PointLocation::Aabb(aabb) => aabb.clone(),
+            PointLocation::Obb(obb) => bounding_box_of(&obb.compute_corners()),
+            PointLocation::Frustum(frustum) => bounding_box_of(&frustum.compute_corners()),
+            PointLocation::Sphere(sphere) => sphere.bounding_box(),
+            PointLocation::Capsule(capsule) => capsule.bounding_box(),
+            PointLocation::PolygonPrism(prism) => prism.bounding_box(),
+        };
+        Ok(PointLocation::Aabb(
+            self.transform_bounding_box(&bounding_box),
+        ))
+    }
+
+    /// Returns a box in the target system that contains `aabb`. Unless there is an 'isometry',
+    /// the box is sampled on a grid, and the box around the reprojected samples is grown by how
+    /// far the surface of the earth and the UTM grid can bend between them.
+    pub fn transform_bounding_box(&self, aabb: &Aabb) -> Aabb {
+        if let Some(to_from) = &self.to_from {
+            return aabb.transform(to_from);
+        }
+        const STEPS: usize = 4;
+        let cell = aabb.diag() / STEPS as f64;
+        let mut transformed: Option<Aabb> = None;
+        for i in 0..=STEPS {
+            for j in 0..=STEPS {
+                for k in 0..=STEPS {
+                    let offset =
+                        Vector3::new(cell.x * i as f64, cell.y * j as f64, cell.z * k as f64);
+                    let p = self.transform_point(&(aabb.min() + offset));
+                    transformed.get_or_insert_with(|| Aabb::new(p, p)).grow(p);
+                }
+            }
+        }
+        let mut transformed = transformed.unwrap();
+        let cell_diagonal = cell.norm();
+        // Eight times the deviation of a circle of the minimum radius from its chord.
+        let margin = Vector3::repeat(cell_diagonal * cell_diagonal / MIN_RADIUS_OF_CURVATURE);
+        let (min, max) = (transformed.min() - margin, transformed.max() + margin);
+        transformed.grow(min);
+        transformed.grow(max);
+        transformed
+    }
+}
+
+fn bounding_box_of(points: &[Point3<f64>]) -> Aabb {
+    let mut aabb = Aabb::new(points[0], points[0]);
+    for p in &points[1..] {
+        aabb.grow(*p);
+    }
+    aabb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Sphere;
+
+    fn assert_close(a: &Point3<f64>, b: &Point3<f64>, tolerance: f64) {
+        assert!((a - b).norm() < tolerance, "{} is not close to {}", a, b);
+    }
+
+    #[test]
+    fn test_utm_ground_truth() {
+        // The Golden Gate Bridge.
+        let lat_lng = WGS84::from_degrees_and_meters(37.8199, -122.4783, 0.);
+        let zone = UtmZone::containing(&lat_lng);
+        assert_eq!(zone, UtmZone::new(10, true).unwrap());
+        let (easting, northing) = utm_from_wgs84(&lat_lng, zone);
+        assert!((easting - 545_915.816).abs() < 1e-2, "easting {}", easting);
+        assert!(
+            (northing - 4_185_961.039).abs() < 1e-2,
+            "northing {}",
+            northing
+        );
+        let back = wgs84_from_utm(easting, northing, 0., zone);
+        assert!((back.latitude_degrees() - 37.8199).abs() < 1e-9);
+        assert!((back.longitude_degrees() + 122.4783).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_systems_round_trip() {
+        let origin = WGS84::from_degrees_and_meters(-33.8568, 151.2153, 20.);
+        let systems = [
+            CoordinateSystem::Ecef,
+            CoordinateSystem::Utm(UtmZone::containing(&origin)),
+            CoordinateSystem::LocalEnu(origin),
+        ];
+        let ecef = ECEF::from(WGS84::from_degrees_and_meters(-33.86, 151.21, 55.));
+        let ecef = Point3::new(ecef.x(), ecef.y(), ecef.z());
+        for from in &systems {
+            for to in &systems {
+                let p = from.point_from_ecef(&ecef);
+                let reprojection = Reprojection::new(*from, *to);
+                let q = reprojection.transform_point(&p);
+                assert_close(&to.ecef_from_point(&q), &ecef, 1e-3);
+                assert_close(&reprojection.inverse().transform_point(&q), &p, 1e-3);
+            }
+        }
+        // The up axis of the local frame is the altitude of UTM.
+        let utm = systems[1].point_from_ecef(&ecef);
+        let enu = systems[2].point_from_ecef(&ecef);
+        assert!((utm.z - 55.).abs() < 1e-3 && (enu.z - 35.).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_transform_location_contains_reprojected_points() {
+        let origin = WGS84::from_degrees_and_meters(48.1372, 11.5755, 500.);
+        let utm = CoordinateSystem::Utm(UtmZone::containing(&origin));
+        let enu = CoordinateSystem::LocalEnu(origin);
+        let center = utm.point_from_ecef(
+            &CoordinateSystem::LocalEnu(origin).ecef_from_point(&Point3::new(0., 0., 0.)),
+        );
+        let sphere = Sphere::new(center, 5000.);
+        let reprojection = Reprojection::new(utm, enu);
+        let location = reprojection
+            .transform_location(&PointLocation::Sphere(sphere.clone()))
+            .unwrap();
+        let culling = location.get_point_culling();
+        for i in 0..1000 {
+            let angle = i as f64 * 0.1;
+            let height = (i as f64 * 0.37).sin();
+            let p = center + 5000. * Vector3::new(angle.cos(), angle.sin(), height).normalize();
+            assert!(culling.contains(&reprojection.transform_point(&p)));
+        }
+        assert!(Reprojection::new(enu, CoordinateSystem::Ecef)
+            .isometry()
+            .is_some());
+        assert!(reprojection.isometry().is_none());
+    }
+
+    #[test]
+    fn test_parse_coordinate_system() {
+        // The origin of a local frame is converted from and to radians, so it is compared by position.
+        let p = Point3::new(100., 200., 300.);
+        for s in &["ecef", "utm:10N", "utm:33S", "enu:52.5,13.4,34"] {
+            let system: CoordinateSystem = s.parse().unwrap();
+            let reparsed: CoordinateSystem = system.to_string().parse().unwrap();
+            assert_close(
+                &reparsed.ecef_from_point(&p),
+                &system.ecef_from_point(&p),
+                1e-6,
+            );
+            let mut meta = proto::Meta::new();
+            meta.set_coordinate_system(system.to_proto());
+            let from_proto = CoordinateSystem::from_meta_proto(&meta).unwrap().unwrap();
+            assert_close(
+                &from_proto.ecef_from_point(&p),
+                &system.ecef_from_point(&p),
+                1e-6,
+            );
+        }
+        assert_eq!(
+            "utm:33S".parse::<CoordinateSystem>().unwrap().to_string(),
+            "utm:33S"
+        );
+        assert!("utm:61N".parse::<CoordinateSystem>().is_err());
+        assert!("enu:1,2".parse::<CoordinateSystem>().is_err());
+        assert!(CoordinateSystem::from_meta_proto(&proto::Meta::new())
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,7 +1,32 @@
 use crate::errors::*;
 use crate::proto;
 use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::future::Future;
 use std::io::Read;
+use std::path::Path;
+#[cfg(feature = "async")]
+use std::pin::Pin;
+
+/// The result of 'DataProvider::data_async', the readers of the node's files by attribute.
+#[cfg(feature = "async")]
+pub type NodeDataFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<HashMap<String, Box<dyn Read + Send>>>> + Send + 'a>>;
+
+/// A node file that a data provider holds in memory, e.g. mapped, see 'DataProvider::mapped_data'.
+pub struct NodeBytes(Box<dyn AsRef<[u8]> + Send>);
+
+impl NodeBytes {
+    pub fn new(bytes: impl AsRef<[u8]> + Send + 'static) -> Self {
+        NodeBytes(Box::new(bytes))
+    }
+}
+
+impl AsRef<[u8]> for NodeBytes {
+    fn as_ref(&self) -> &[u8] {
+        (*self.0).as_ref()
+    }
+}
 
 pub trait DataProvider: Send + Sync {
     fn meta_proto(&self) -> Result<proto::Meta>;
@@ -10,4 +35,29 @@ pub trait DataProvider: Send + Sync {
         node_id: &str,
         node_attributes: &[&str],
     ) -> Result<HashMap<String, Box<dyn Read + Send>>>;
+
+    /// Like 'data', but returns the files if the provider holds them in memory, e.g. mapped, so
+    /// that the points are decoded from them without copying them first. Returns None if the files
+    /// need to be read through 'data'.
+    fn mapped_data(
+        &self,
+        _node_id: &str,
+        _node_attributes: &[&str],
+    ) -> Option<Result<HashMap<String, NodeBytes>>> {
+        None
+    }
+
+    /// Like 'data', but without blocking the caller while the node is fetched, so that fetches
+    /// of several nodes can overlap. The default is synchronous: it calls 'data' before returning
+    /// the future, so it blocks the caller just as long and fetches do not overlap. That is fine
+    /// for local files, providers for remote storage override it.
+    #[cfg(feature = "async")]
+    fn data_async(&self, node_id: &str, node_attributes: &[&str]) -> NodeDataFuture<'_> {
+        Box::pin(futures::future::ready(self.data(node_id, node_attributes)))
+    }
+
+    /// Returns the directory holding the data, if it can be modified in place.
+    fn directory(&self) -> Option<&Path> {
+        None
+    }
 }
@@ -13,9 +13,15 @@ pub struct DataProviderFactory {
 
 impl DataProviderFactory {
     pub fn new() -> Self {
-        Self {
+        let factory = Self {
             data_provider_fn_map: FnvHashMap::default(),
-        }
+        };
+        #[cfg(feature = "mmap")]
+        let factory = factory.register(
+            crate::data_provider::MMAP_PREFIX,
+            crate::data_provider::MmapDataProvider::from_uri,
+        );
+        factory
     }
 
     pub fn register(
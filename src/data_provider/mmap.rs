@@ -0,0 +1,277 @@
+use crate::attribute_extension;
+use crate::data_provider::{
+    DataProvider, DataProviderFactoryResult, NodeBytes, OnDiskDataProvider,
+};
+use crate::errors::*;
+use crate::proto;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// The prefix under which 'DataProviderFactory' creates a 'MmapDataProvider'.
+pub const MMAP_PREFIX: &str = "mmap://";
+
+/// A read-only memory mapping of a whole file.
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is never written to and only unmapped on drop.
+unsafe impl Send for Mapping {}
+
+impl Mapping {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        // Mapping zero bytes is an error.
+        if len == 0 {
+            return Ok(Mapping {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // Nodes are decoded front to back, so the kernel can read ahead aggressively. This is
+        // only a hint, so failure is fine.
+        unsafe {
+            libc::madvise(ptr, len, libc::MADV_SEQUENTIAL);
+        }
+        Ok(Mapping { ptr, len })
+    }
+}
+
+impl AsRef<[u8]> for Mapping {
+    fn as_ref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+/// Like 'OnDiskDataProvider', but maps the node files into memory instead of reading them. Points
+/// are decoded straight from the page cache, without a read syscall and a copy into a heap buffer
+/// for every few kilobytes. The octree is read-only through it, 'directory' returns None, since
+/// accessing a mapping of a truncated file crashes the process. For the same reason, node files
+/// must not be rewritten in other ways while they are being read, e.g. by running 'update' on the
+/// octree.
+pub struct MmapDataProvider {
+    pub directory: PathBuf,
+}
+
+impl MmapDataProvider {
+    /// Creates the data provider for arguments like 'mmap:///path/to/octree', for registering
+    /// with a 'DataProviderFactory'.
+    pub fn from_uri(uri: &str) -> DataProviderFactoryResult {
+        let directory = Path::new(uri.trim_start_matches(MMAP_PREFIX));
+        if !directory.exists() {
+            return Err(format!(
+                "Directory '{}' for creating an MmapDataProvider doesn't exist.",
+                directory.display()
+            )
+            .into());
+        }
+        Ok(Box::new(MmapDataProvider {
+            directory: directory.to_path_buf(),
+        }))
+    }
+
+    fn mappings(&self, node_id: &str, node_attributes: &[&str]) -> Result<Vec<Mapping>> {
+        let stem = self.directory.join(node_id);
+        node_attributes
+            .iter()
+            .map(|node_attribute| {
+                Mapping::open(&stem.with_extension(attribute_extension(node_attribute))).map_err(
+                    |err| match err.kind() {
+                        ::std::io::ErrorKind::NotFound => ErrorKind::NodeNotFound.into(),
+                        _ => err.into(),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+impl DataProvider for MmapDataProvider {
+    fn meta_proto(&self) -> Result<proto::Meta> {
+        OnDiskDataProvider {
+            directory: self.directory.clone(),
+        }
+        .meta_proto()
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        let mappings = self.mappings(node_id, node_attributes)?;
+        let mut readers = HashMap::<String, Box<dyn Read + Send>>::new();
+        for (node_attribute, mapping) in node_attributes.iter().zip(mappings) {
+            readers.insert(
+                (*node_attribute).to_string(),
+                Box::new(Cursor::new(mapping)),
+            );
+        }
+        Ok(readers)
+    }
+
+    fn mapped_data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Option<Result<HashMap<String, NodeBytes>>> {
+        let mappings = match self.mappings(node_id, node_attributes) {
+            Ok(mappings) => mappings,
+            Err(err) => return Some(Err(err)),
+        };
+        let all_bytes = node_attributes
+            .iter()
+            .zip(mappings)
+            .map(|(node_attribute, mapping)| {
+                ((*node_attribute).to_string(), NodeBytes::new(mapping))
+            })
+            .collect();
+        Some(Ok(all_bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_write::{
+        DataWriter, Encoding, NodeIterator, NodeWriter, OpenMode, PositionEncoding, RawNodeWriter,
+    };
+    use crate::{AttributeData, AttributeDataType, PointsBatch};
+    use nalgebra::Point3;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_reads_like_on_disk() {
+        let tmp_dir = TempDir::new("mmap").unwrap();
+        let bytes: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let mut writer =
+            DataWriter::new(tmp_dir.path().join("r0.rgb"), OpenMode::Truncate).unwrap();
+        writer.write_all(&bytes).unwrap();
+        drop(writer);
+
+        let read = |data_provider: &dyn DataProvider| {
+            let mut data = Vec::new();
+            data_provider
+                .data("r0", &["color"])
+                .unwrap()
+                .remove("color")
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            data
+        };
+        let on_disk = OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        };
+        let mmap =
+            MmapDataProvider::from_uri(&format!("{}{}", MMAP_PREFIX, tmp_dir.path().display()))
+                .unwrap();
+        assert_eq!(read(&on_disk), bytes);
+        assert_eq!(read(&*mmap), bytes);
+        assert!(matches!(
+            mmap.data("r1", &["color"]),
+            Err(Error(ErrorKind::NodeNotFound, _))
+        ));
+    }
+
+    #[test]
+    fn test_decodes_positions_like_on_disk() {
+        let tmp_dir = TempDir::new("mmap").unwrap();
+        let batch = PointsBatch {
+            position: (0..1000)
+                .map(|i| {
+                    let t = f64::from(i);
+                    Point3::new(t * 0.01, (t * 0.37) % 10., 10. - t * 0.001)
+                })
+                .collect(),
+            attributes: vec![(
+                "intensity".to_string(),
+                AttributeData::F32((0..1000).map(|i| i as f32).collect()),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let attribute_data_types = vec![("intensity".to_string(), AttributeDataType::F32)]
+            .into_iter()
+            .collect();
+        let on_disk = OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        };
+        let mmap = MmapDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        };
+        assert!(mmap.directory().is_none());
+        let min = Point3::new(-1., -1., -1.);
+        let encodings = [
+            Encoding::Plain,
+            Encoding::ScaledToCube(min, 12., PositionEncoding::Uint8),
+            Encoding::ScaledToCube(min, 12., PositionEncoding::Uint16),
+            Encoding::ScaledToCube(min, 12., PositionEncoding::Float32),
+            Encoding::ScaledToCube(min, 12., PositionEncoding::Float64),
+        ];
+        for encoding in encodings.iter() {
+            let mut writer = RawNodeWriter::new(
+                tmp_dir.path().join("r0"),
+                encoding.clone(),
+                OpenMode::Truncate,
+            );
+            writer.write(&batch).unwrap();
+            drop(writer);
+            // A batch size that does not divide the number of points.
+            let read = |data_provider: &dyn DataProvider| -> Vec<PointsBatch> {
+                NodeIterator::from_data_provider(
+                    data_provider,
+                    &attribute_data_types,
+                    encoding.clone(),
+                    &"r0",
+                    1000,
+                    300,
+                )
+                .unwrap()
+                .collect()
+            };
+            let expected = read(&on_disk);
+            let batches = read(&mmap);
+            assert_eq!(batches.len(), 4);
+            for (batch, expected) in batches.iter().zip(&expected) {
+                assert_eq!(batch.position, expected.position);
+                assert_eq!(
+                    batch.get_attribute_vec::<f32>("intensity"),
+                    expected.get_attribute_vec::<f32>("intensity")
+                );
+            }
+        }
+    }
+}
@@ -1,7 +1,17 @@
 mod common;
 mod factory;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod object_store;
 mod on_disk;
 
-pub use common::DataProvider;
+pub use common::{DataProvider, NodeBytes};
+#[cfg(feature = "async")]
+pub use common::NodeDataFuture;
 pub use factory::{DataProviderFactory, DataProviderFactoryResult};
+#[cfg(feature = "mmap")]
+pub use mmap::{MmapDataProvider, MMAP_PREFIX};
+#[cfg(feature = "async")]
+pub use object_store::RangeFuture;
+pub use object_store::{ObjectStore, ObjectStoreConfig, ObjectStoreDataProvider};
 pub use on_disk::OnDiskDataProvider;
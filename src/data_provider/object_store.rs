@@ -0,0 +1,426 @@
+use crate::attribute_extension;
+use crate::data_provider::DataProvider;
+#[cfg(feature = "async")]
+use crate::data_provider::NodeDataFuture;
+use crate::errors::*;
+use crate::proto;
+use crate::META_FILENAME;
+use lru::LruCache;
+use std::collections::HashMap;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io::{self, Read};
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+/// The result of 'ObjectStore::get_range_async'.
+#[cfg(feature = "async")]
+pub type RangeFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send + 'a>>;
+
+/// A bucket in a cloud object store, e.g. S3 or GCS.
+pub trait ObjectStore: Send + Sync {
+    /// Returns up to `len` bytes of the object `key`, starting at `start`. Fewer bytes are
+    /// returned only at the end of the object. Fails with 'NodeNotFound' if there is no such
+    /// object.
+    fn get_range(&self, key: &str, start: u64, len: u64) -> Result<Vec<u8>>;
+
+    /// Like 'get_range', but without blocking the caller during the request. The default calls
+    /// 'get_range' right away.
+    #[cfg(feature = "async")]
+    fn get_range_async(&self, key: &str, start: u64, len: u64) -> RangeFuture<'_> {
+        Box::pin(futures::future::ready(self.get_range(key, start, len)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectStoreConfig {
+    /// The unit in which objects are fetched and cached.
+    pub block_size: u64,
+    /// The number of blocks fetched with one request when a block is not cached. Node files are
+    /// read from front to back, so this saves round trips.
+    pub read_ahead_blocks: u64,
+    /// The maximum number of bytes of blocks kept in memory.
+    pub cache_size_bytes: u64,
+}
+
+impl Default for ObjectStoreConfig {
+    fn default() -> Self {
+        ObjectStoreConfig {
+            block_size: 1 << 20,
+            read_ahead_blocks: 4,
+            cache_size_bytes: 512 << 20,
+        }
+    }
+}
+
+type BlockCache = Mutex<LruCache<(String, u64), Arc<Vec<u8>>>>;
+
+struct Blocks {
+    store: Box<dyn ObjectStore>,
+    config: ObjectStoreConfig,
+    cache: BlockCache,
+}
+
+impl Blocks {
+    /// Returns the block `index` of the object `key`, which is shorter than the block size only
+    /// for the last block of the object.
+    fn get(&self, key: &str, index: u64) -> Result<Arc<Vec<u8>>> {
+        if let Some(block) = self.cached(key, index) {
+            return Ok(block);
+        }
+        let (start, len) = self.range_from(index);
+        let data = self.store.get_range(key, start, len)?;
+        Ok(self.insert(key, index, &data))
+    }
+
+    /// Like 'get', but the request does not block the caller.
+    #[cfg(feature = "async")]
+    async fn get_async(&self, key: &str, index: u64) -> Result<Arc<Vec<u8>>> {
+        if let Some(block) = self.cached(key, index) {
+            return Ok(block);
+        }
+        let (start, len) = self.range_from(index);
+        let data = self.store.get_range_async(key, start, len).await?;
+        Ok(self.insert(key, index, &data))
+    }
+
+    /// Fetches all blocks of the object `key` into the cache and returns the first one.
+    #[cfg(feature = "async")]
+    async fn prefetch(&self, key: &str) -> Result<Arc<Vec<u8>>> {
+        let first_block = self.get_async(key, 0).await?;
+        let mut block = Arc::clone(&first_block);
+        let mut index = 0;
+        // A short block is the last one.
+        while block.len() as u64 == self.config.block_size {
+            index += 1;
+            block = self.get_async(key, index).await?;
+        }
+        Ok(first_block)
+    }
+
+    fn cached(&self, key: &str, index: u64) -> Option<Arc<Vec<u8>>> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(&(key.to_string(), index))
+            .map(Arc::clone)
+    }
+
+    /// The byte range that is requested when block `index` is not cached.
+    fn range_from(&self, index: u64) -> (u64, u64) {
+        let block_size = self.config.block_size;
+        let num_blocks = self.config.read_ahead_blocks.max(1);
+        (index * block_size, num_blocks * block_size)
+    }
+
+    /// Caches the blocks in `data`, which starts at block `index`, and returns the first one.
+    fn insert(&self, key: &str, index: u64, data: &[u8]) -> Arc<Vec<u8>> {
+        let mut cache = self.cache.lock().unwrap();
+        let mut result = Arc::new(Vec::new());
+        for (i, chunk) in data.chunks(self.config.block_size as usize).enumerate() {
+            let block = Arc::new(chunk.to_vec());
+            if i == 0 {
+                result = Arc::clone(&block);
+            }
+            cache.put((key.to_string(), index + i as u64), block);
+        }
+        result
+    }
+}
+
+/// Reads an object block by block through the cache.
+struct ObjectReader {
+    blocks: Arc<Blocks>,
+    key: String,
+    block: Arc<Vec<u8>>,
+    block_index: u64,
+    /// The position in `block`.
+    offset: usize,
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_read = (&self.block[self.offset..]).read(buf)?;
+        self.offset += num_read;
+        if num_read > 0 || buf.is_empty() {
+            return Ok(num_read);
+        }
+        // A short block is the last one.
+        if (self.block.len() as u64) < self.blocks.config.block_size {
+            return Ok(0);
+        }
+        self.block_index += 1;
+        let block = self
+            .blocks
+            .get(&self.key, self.block_index)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        self.block = block;
+        self.offset = 0;
+        self.read(buf)
+    }
+}
+
+/// Reads point clouds from an object store. Objects are fetched in blocks, with read-ahead, and
+/// the blocks are kept in an LRU cache shared by all readers of this data provider. See the
+/// 'object_store_provider' crate for S3 and GCS.
+pub struct ObjectStoreDataProvider {
+    blocks: Arc<Blocks>,
+    /// The prefix of all keys of the point cloud, e.g. the directory of an octree.
+    prefix: String,
+}
+
+impl ObjectStoreDataProvider {
+    pub fn new(
+        store: Box<dyn ObjectStore>,
+        prefix: impl Into<String>,
+        config: ObjectStoreConfig,
+    ) -> Self {
+        let num_cached_blocks = (config.cache_size_bytes / config.block_size).max(1) as usize;
+        let prefix = prefix.into().trim_end_matches('/').to_string();
+        ObjectStoreDataProvider {
+            blocks: Arc::new(Blocks {
+                store,
+                config,
+                cache: Mutex::new(LruCache::new(num_cached_blocks)),
+            }),
+            prefix,
+        }
+    }
+
+    fn key(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", self.prefix, name)
+        }
+    }
+
+    fn reader(&self, name: &str) -> Result<ObjectReader> {
+        let key = self.key(name);
+        // Fetching the first block tells us whether the object exists.
+        let block = self.blocks.get(&key, 0)?;
+        Ok(self.reader_from(key, block))
+    }
+
+    fn reader_from(&self, key: String, first_block: Arc<Vec<u8>>) -> ObjectReader {
+        ObjectReader {
+            blocks: Arc::clone(&self.blocks),
+            key,
+            block: first_block,
+            block_index: 0,
+            offset: 0,
+        }
+    }
+
+    fn node_keys(&self, node_id: &str, node_attributes: &[&str]) -> Vec<(String, String)> {
+        node_attributes
+            .iter()
+            .map(|node_attribute| {
+                let name = format!("{}.{}", node_id, attribute_extension(node_attribute));
+                ((*node_attribute).to_string(), self.key(&name))
+            })
+            .collect()
+    }
+}
+
+impl DataProvider for ObjectStoreDataProvider {
+    fn meta_proto(&self) -> Result<proto::Meta> {
+        let mut reader = self.reader(META_FILENAME)?;
+        Ok(protobuf::parse_from_reader::<proto::Meta>(&mut reader)
+            .chain_err(|| format!("Could not parse {}", META_FILENAME))?)
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Result<HashMap<String, Box<dyn Read + Send>>> {
+        let mut readers = HashMap::<String, Box<dyn Read + Send>>::new();
+        for (node_attribute, key) in self.node_keys(node_id, node_attributes) {
+            // Fetching the first block tells us whether the object exists.
+            let block = self.blocks.get(&key, 0)?;
+            readers.insert(node_attribute, Box::new(self.reader_from(key, block)));
+        }
+        Ok(readers)
+    }
+
+    /// Fetches the files of the node into the block cache without blocking, so that reading them
+    /// afterwards only blocks if the cache is too small to hold them. The files of the attributes
+    /// are fetched concurrently.
+    #[cfg(feature = "async")]
+    fn data_async(&self, node_id: &str, node_attributes: &[&str]) -> NodeDataFuture<'_> {
+        let fetches = self.node_keys(node_id, node_attributes).into_iter().map(
+            move |(node_attribute, key)| async move {
+                let block = self.blocks.prefetch(&key).await?;
+                let reader: Box<dyn Read + Send> = Box::new(self.reader_from(key, block));
+                Ok::<_, Error>((node_attribute, reader))
+            },
+        );
+        Box::pin(async move {
+            let readers = futures::future::try_join_all(fetches).await?;
+            Ok(readers.into_iter().collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct InMemoryStore {
+        objects: HashMap<String, Vec<u8>>,
+        num_requests: Arc<AtomicUsize>,
+    }
+
+    impl ObjectStore for InMemoryStore {
+        fn get_range(&self, key: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+            self.num_requests.fetch_add(1, Ordering::SeqCst);
+            let object = self.objects.get(key).ok_or(ErrorKind::NodeNotFound)?;
+            let start = (start as usize).min(object.len());
+            let end = (start + len as usize).min(object.len());
+            Ok(object[start..end].to_vec())
+        }
+    }
+
+    /// Answers every request only after yielding to the executor once, and records how many
+    /// requests were in flight at the same time.
+    #[cfg(feature = "async")]
+    struct YieldingStore {
+        store: InMemoryStore,
+        in_flight: AtomicUsize,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    #[cfg(feature = "async")]
+    impl ObjectStore for YieldingStore {
+        fn get_range(&self, key: &str, start: u64, len: u64) -> Result<Vec<u8>> {
+            self.store.get_range(key, start, len)
+        }
+
+        fn get_range_async(&self, key: &str, start: u64, len: u64) -> RangeFuture<'_> {
+            let key = key.to_string();
+            Box::pin(async move {
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                let mut yielded = false;
+                futures::future::poll_fn(|context| {
+                    if yielded {
+                        std::task::Poll::Ready(())
+                    } else {
+                        yielded = true;
+                        context.waker().wake_by_ref();
+                        std::task::Poll::Pending
+                    }
+                })
+                .await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                self.get_range(&key, start, len)
+            })
+        }
+    }
+
+    fn read(data_provider: &ObjectStoreDataProvider, node_id: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data_provider
+            .data(node_id, &["color"])?
+            .remove("color")
+            .unwrap()
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn test_reads_through_cache() {
+        let bytes: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let num_requests = Arc::new(AtomicUsize::new(0));
+        let store = InMemoryStore {
+            objects: vec![("octree/r0.rgb".to_string(), bytes.clone())]
+                .into_iter()
+                .collect(),
+            num_requests: Arc::clone(&num_requests),
+        };
+        let config = ObjectStoreConfig {
+            block_size: 64,
+            read_ahead_blocks: 4,
+            cache_size_bytes: 64 * 100,
+        };
+        let data_provider = ObjectStoreDataProvider::new(Box::new(store), "octree/", config);
+
+        assert_eq!(read(&data_provider, "r0").unwrap(), bytes);
+        // 16 blocks, 4 per request.
+        assert_eq!(num_requests.load(Ordering::SeqCst), 4);
+        assert_eq!(read(&data_provider, "r0").unwrap(), bytes);
+        assert_eq!(num_requests.load(Ordering::SeqCst), 4);
+
+        assert!(matches!(
+            read(&data_provider, "r1"),
+            Err(Error(ErrorKind::NodeNotFound, _))
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_data_async_fetches_whole_files() {
+        let bytes: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let num_requests = Arc::new(AtomicUsize::new(0));
+        let store = InMemoryStore {
+            objects: vec![("octree/r0.rgb".to_string(), bytes.clone())]
+                .into_iter()
+                .collect(),
+            num_requests: Arc::clone(&num_requests),
+        };
+        let config = ObjectStoreConfig {
+            block_size: 64,
+            read_ahead_blocks: 4,
+            cache_size_bytes: 64 * 100,
+        };
+        let data_provider = ObjectStoreDataProvider::new(Box::new(store), "octree", config);
+
+        let mut readers =
+            futures::executor::block_on(data_provider.data_async("r0", &["color"])).unwrap();
+        assert_eq!(num_requests.load(Ordering::SeqCst), 4);
+        let mut data = Vec::new();
+        readers
+            .remove("color")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, bytes);
+        // Reading did not need any more requests.
+        assert_eq!(num_requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_data_async_fetches_attributes_concurrently() {
+        let bytes: Vec<u8> = (0..100).collect();
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let store = YieldingStore {
+            store: InMemoryStore {
+                objects: vec![
+                    ("octree/r0.rgb".to_string(), bytes.clone()),
+                    (
+                        format!("octree/r0.{}", attribute_extension("intensity")),
+                        bytes.clone(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            },
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: Arc::clone(&max_in_flight),
+        };
+        let data_provider =
+            ObjectStoreDataProvider::new(Box::new(store), "octree", ObjectStoreConfig::default());
+
+        let readers =
+            futures::executor::block_on(data_provider.data_async("r0", &["color", "intensity"]))
+                .unwrap();
+        assert_eq!(readers.len(), 2);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+}
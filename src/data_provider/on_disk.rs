@@ -3,10 +3,11 @@ use crate::data_provider::DataProvider;
 use crate::errors::*;
 use crate::proto;
 use crate::META_FILENAME;
+use protobuf::Message;
 use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::{Cursor, Read};
-use std::path::PathBuf;
+use std::io::{BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
 
 pub struct OnDiskDataProvider {
     pub directory: PathBuf,
@@ -31,6 +32,21 @@ impl OnDiskDataProvider {
         // color has 3 bytes per point
         Ok((file_size_bytes / 3) as i64)
     }
+
+    /// Replaces the meta file. It is written to a temporary file first, so that readers never see
+    /// a partially written one.
+    pub fn write_meta_proto(&self, meta: &proto::Meta) -> Result<()> {
+        let meta_path = self.directory.join(META_FILENAME);
+        let tmp_path = meta_path.with_extension("pb.tmp");
+        {
+            let mut buf_writer = BufWriter::new(File::create(&tmp_path)?);
+            meta.write_to_writer(&mut buf_writer)
+                .chain_err(|| format!("Could not write {}", META_FILENAME))?;
+            buf_writer.flush()?;
+        }
+        fs::rename(&tmp_path, &meta_path)?;
+        Ok(())
+    }
 }
 
 impl DataProvider for OnDiskDataProvider {
@@ -66,4 +82,8 @@ impl DataProvider for OnDiskDataProvider {
         }
         Ok(readers)
     }
+
+    fn directory(&self) -> Option<&Path> {
+        Some(&self.directory)
+    }
 }
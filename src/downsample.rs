@@ -0,0 +1,264 @@
+//! Thinning of points to a voxel grid, see 'PointQuery::downsample'.
+
+use crate::{AttributeData, AttributeDataType, PointsBatch};
+use fnv::{FnvHashMap, FnvHashSet};
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The voxels that a query is downsampled to. They are cubes aligned to the origin, so that the
+/// voxels of neighboring nodes and point clouds line up.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VoxelSize {
+    pub edge_length: f64,
+    /// Whether a voxel's point is the mean of its points instead of one of them. Averaged points
+    /// are only returned once all points were seen.
+    #[serde(default)]
+    pub average: bool,
+}
+
+impl VoxelSize {
+    pub fn new(edge_length: f64) -> Self {
+        VoxelSize {
+            edge_length,
+            average: false,
+        }
+    }
+
+    /// The voxels that leave roughly `points_per_unit_volume` points in densely sampled regions.
+    pub fn for_density(points_per_unit_volume: f64) -> Self {
+        Self::new(points_per_unit_volume.recip().cbrt())
+    }
+
+    fn voxel(&self, p: &Point3<f64>) -> (i64, i64, i64) {
+        let v = p.coords / self.edge_length;
+        (v.x.floor() as i64, v.y.floor() as i64, v.z.floor() as i64)
+    }
+}
+
+fn dim(data_type: AttributeDataType) -> usize {
+    match data_type {
+        AttributeDataType::U8Vec3 | AttributeDataType::F64Vec3 => 3,
+        _ => 1,
+    }
+}
+
+/// Adds the components of the value at `i` to `sums`.
+fn add_components(data: &AttributeData, i: usize, sums: &mut [f64]) {
+    macro_rules! scalar {
+        ($data:ident) => {
+            sums[0] += $data[i] as f64
+        };
+    }
+    match data {
+        AttributeData::U8(data) => scalar!(data),
+        AttributeData::U16(data) => scalar!(data),
+        AttributeData::U32(data) => scalar!(data),
+        AttributeData::U64(data) => scalar!(data),
+        AttributeData::I8(data) => scalar!(data),
+        AttributeData::I16(data) => scalar!(data),
+        AttributeData::I32(data) => scalar!(data),
+        AttributeData::I64(data) => scalar!(data),
+        AttributeData::F32(data) => scalar!(data),
+        AttributeData::F64(data) => scalar!(data),
+        AttributeData::U8Vec3(data) => {
+            for (s, c) in sums.iter_mut().zip(data[i].iter()) {
+                *s += f64::from(*c);
+            }
+        }
+        AttributeData::F64Vec3(data) => {
+            for (s, c) in sums.iter_mut().zip(data[i].iter()) {
+                *s += *c;
+            }
+        }
+    }
+}
+
+/// The attribute data of the given means, rounded for integer types.
+fn data_from_means<'a>(
+    data_type: AttributeDataType,
+    means: impl Iterator<Item = &'a [f64]>,
+) -> AttributeData {
+    macro_rules! scalars {
+        ($variant:ident, $round:expr) => {
+            AttributeData::$variant(means.map(|m| $round(m[0]) as _).collect())
+        };
+    }
+    match data_type {
+        AttributeDataType::U8 => scalars!(U8, f64::round),
+        AttributeDataType::U16 => scalars!(U16, f64::round),
+        AttributeDataType::U32 => scalars!(U32, f64::round),
+        AttributeDataType::U64 => scalars!(U64, f64::round),
+        AttributeDataType::I8 => scalars!(I8, f64::round),
+        AttributeDataType::I16 => scalars!(I16, f64::round),
+        AttributeDataType::I32 => scalars!(I32, f64::round),
+        AttributeDataType::I64 => scalars!(I64, f64::round),
+        AttributeDataType::F32 => scalars!(F32, std::convert::identity),
+        AttributeDataType::F64 => scalars!(F64, std::convert::identity),
+        AttributeDataType::U8Vec3 => AttributeData::U8Vec3(
+            means
+                .map(|m| Vector3::new(m[0], m[1], m[2]).map(|c| c.round() as u8))
+                .collect(),
+        ),
+        AttributeDataType::F64Vec3 => {
+            AttributeData::F64Vec3(means.map(|m| Vector3::new(m[0], m[1], m[2])).collect())
+        }
+    }
+}
+
+/// The sums of the points in a voxel.
+struct VoxelSums {
+    num_points: usize,
+    position: Vector3<f64>,
+    /// The components of all attributes, in the order of 'VoxelDownsampler::layout'.
+    attributes: Vec<f64>,
+}
+
+/// Keeps one point per voxel of the batches it is given, also across batches from different
+/// nodes or point clouds.
+pub struct VoxelDownsampler {
+    voxel_size: VoxelSize,
+    seen: FnvHashSet<(i64, i64, i64)>,
+    voxels: FnvHashMap<(i64, i64, i64), VoxelSums>,
+    /// The attributes with their types and number of components, from the first batch.
+    layout: Vec<(String, AttributeDataType, usize)>,
+}
+
+impl VoxelDownsampler {
+    pub fn new(voxel_size: VoxelSize) -> Self {
+        VoxelDownsampler {
+            voxel_size,
+            seen: FnvHashSet::default(),
+            voxels: FnvHashMap::default(),
+            layout: Vec::new(),
+        }
+    }
+
+    /// Returns the points of `batch` in voxels without a point so far. Averaging keeps all points
+    /// until 'finish'.
+    pub fn add_batch(&mut self, mut batch: PointsBatch) -> Option<PointsBatch> {
+        if !self.voxel_size.average {
+            let keep: Vec<bool> = batch
+                .position
+                .iter()
+                .map(|p| self.seen.insert(self.voxel_size.voxel(p)))
+                .collect();
+            batch.retain(&keep);
+            return Some(batch).filter(|batch| !batch.position.is_empty());
+        }
+        if self.layout.is_empty() {
+            self.layout = batch
+                .attributes
+                .iter()
+                .map(|(name, data)| (name.clone(), data.data_type(), dim(data.data_type())))
+                .collect();
+        }
+        let num_components = self.layout.iter().map(|(_, _, dim)| dim).sum();
+        for (i, p) in batch.position.iter().enumerate() {
+            let sums = self
+                .voxels
+                .entry(self.voxel_size.voxel(p))
+                .or_insert_with(|| VoxelSums {
+                    num_points: 0,
+                    position: Vector3::zeros(),
+                    attributes: vec![0.; num_components],
+                });
+            sums.num_points += 1;
+            sums.position += p.coords;
+            let mut start = 0;
+            for (name, _, dim) in &self.layout {
+                add_components(
+                    &batch.attributes[name],
+                    i,
+                    &mut sums.attributes[start..start + dim],
+                );
+                start += dim;
+            }
+        }
+        None
+    }
+
+    /// Returns the averaged points, if the points are averaged.
+    pub fn finish(self) -> Option<PointsBatch> {
+        if self.voxels.is_empty() {
+            return None;
+        }
+        let means: Vec<(Point3<f64>, Vec<f64>)> = self
+            .voxels
+            .into_iter()
+            .map(|(_, sums)| {
+                let n = sums.num_points as f64;
+                let attributes = sums.attributes.iter().map(|s| s / n).collect();
+                (Point3::from(sums.position / n), attributes)
+            })
+            .collect();
+        let mut attributes = BTreeMap::new();
+        let mut start = 0;
+        for (name, data_type, dim) in self.layout {
+            let data =
+                data_from_means(data_type, means.iter().map(|(_, m)| &m[start..start + dim]));
+            attributes.insert(name, data);
+            start += dim;
+        }
+        Some(PointsBatch {
+            position: means.into_iter().map(|(p, _)| p).collect(),
+            attributes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(position: Vec<Point3<f64>>, color: Vec<Vector3<u8>>) -> PointsBatch {
+        PointsBatch {
+            position,
+            attributes: vec![("color".to_string(), AttributeData::U8Vec3(color))]
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_one_point_per_voxel() {
+        let points = || {
+            batch(
+                vec![
+                    Point3::new(0.1, 0.1, 0.1),
+                    Point3::new(0.9, 0.2, 0.3),
+                    Point3::new(-0.1, 0.1, 0.1),
+                    Point3::new(1.5, 0., 0.),
+                ],
+                vec![
+                    Vector3::new(0, 0, 0),
+                    Vector3::new(100, 50, 0),
+                    Vector3::new(7, 7, 7),
+                    Vector3::new(9, 9, 9),
+                ],
+            )
+        };
+        let mut downsampler = VoxelDownsampler::new(VoxelSize::new(1.));
+        let first = downsampler.add_batch(points()).unwrap();
+        assert_eq!(first.position.len(), 3);
+        assert_eq!(first.position[1], Point3::new(-0.1, 0.1, 0.1));
+        // The voxels are already taken by the points of the first batch.
+        assert!(downsampler.add_batch(points()).is_none());
+        assert!(downsampler.finish().is_none());
+
+        let mut voxel_size = VoxelSize::new(1.);
+        voxel_size.average = true;
+        let mut downsampler = VoxelDownsampler::new(voxel_size);
+        assert!(downsampler.add_batch(points()).is_none());
+        let averaged = downsampler.finish().unwrap();
+        assert_eq!(averaged.position.len(), 3);
+        let i = averaged
+            .position
+            .iter()
+            .position(|p| (p - Point3::new(0.5, 0.15, 0.2)).norm() < 1e-9)
+            .unwrap();
+        let color: &Vec<Vector3<u8>> = averaged.get_attribute_vec("color").unwrap();
+        assert_eq!(color[i], Vector3::new(50, 25, 0));
+        assert!((VoxelSize::for_density(8.).edge_length - 0.5).abs() < 1e-9);
+    }
+}
@@ -1,7 +1,8 @@
 //! Axis-aligned box and cube.
 
-use crate::math::base::{HasAabbIntersector, PointCulling};
+use crate::math::base::{contains_lanes, HasAabbIntersector, PointCulling};
 use crate::math::sat::{CachedAxesIntersector, ConvexPolyhedron, Intersector};
+use crate::math::simd::splat3;
 use crate::proto;
 use arrayvec::ArrayVec;
 use nalgebra::{Isometry3, Point3, Vector3};
@@ -47,6 +48,12 @@ impl Aabb {
         nalgebra::partial_le(&self.mins, p) && nalgebra::partial_lt(p, &self.maxs)
     }
 
+    /// Returns the squared distance from `p` to the closest point inside the box, which is zero
+    /// for points inside.
+    pub fn distance_squared_to(&self, p: &Point3<f64>) -> f64 {
+        (p - p.sup(&self.mins).inf(&self.maxs)).norm_squared()
+    }
+
     pub fn center(&self) -> Point3<f64> {
         nalgebra::center(&self.mins, &self.maxs)
     }
@@ -96,6 +103,19 @@ impl PointCulling for Aabb {
     fn contains(&self, p: &Point3<f64>) -> bool {
         self.contains(p)
     }
+
+    fn contains_batch(&self, points: &[Point3<f64>], inside: &mut Vec<bool>) {
+        let [min_x, min_y, min_z] = splat3(&self.mins.coords);
+        let [max_x, max_y, max_z] = splat3(&self.maxs.coords);
+        contains_lanes(points, inside, |x, y, z| {
+            min_x.cmp_le(x)
+                & min_y.cmp_le(y)
+                & min_z.cmp_le(z)
+                & x.cmp_lt(max_x)
+                & y.cmp_lt(max_y)
+                & z.cmp_lt(max_z)
+        })
+    }
 }
 
 // This should be a tad more efficient than the generic ConvexPolyhedron
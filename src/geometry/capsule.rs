@@ -0,0 +1,128 @@
+//! A line segment with a radius, i.e. a cylinder with hemispherical caps.
+
+use super::aabb::Aabb;
+use crate::math::base::{HasAabbIntersector, IntersectAabb, PointCulling};
+use nalgebra::{Point3, Vector3};
+use num::clamp;
+use serde::{Deserialize, Serialize};
+
+/// All points within `radius` of the line segment from `start` to `end`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Capsule {
+    start: Point3<f64>,
+    end: Point3<f64>,
+    radius: f64,
+}
+
+impl Capsule {
+    pub fn new(start: Point3<f64>, end: Point3<f64>, radius: f64) -> Self {
+        Capsule { start, end, radius }
+    }
+
+    pub fn start(&self) -> &Point3<f64> {
+        &self.start
+    }
+
+    pub fn end(&self) -> &Point3<f64> {
+        &self.end
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    /// The smallest AABB containing the capsule.
+    pub fn bounding_box(&self) -> Aabb {
+        let r = Vector3::repeat(self.radius);
+        Aabb::new(self.start.inf(&self.end) - r, self.start.sup(&self.end) + r)
+    }
+
+    fn point_on_segment(&self, t: f64) -> Point3<f64> {
+        self.start + t * (self.end - self.start)
+    }
+
+    /// Returns the squared distance of `p` to the segment.
+    fn distance_squared_to_segment(&self, p: &Point3<f64>) -> f64 {
+        let d = self.end - self.start;
+        let length_squared = d.norm_squared();
+        let t = if length_squared > 0. {
+            clamp((p - self.start).dot(&d) / length_squared, 0., 1.)
+        } else {
+            0.
+        };
+        (p - self.point_on_segment(t)).norm_squared()
+    }
+}
+
+impl PointCulling for Capsule {
+    fn contains(&self, p: &Point3<f64>) -> bool {
+        self.distance_squared_to_segment(p) <= self.radius * self.radius
+    }
+}
+
+impl IntersectAabb for Capsule {
+    /// The distance of a point on the segment to the box is a convex function of the position
+    /// along the segment, since the box is convex. So its minimum can be found by ternary search.
+    fn intersect_aabb(&self, aabb: &Aabb) -> bool {
+        let radius_squared = self.radius * self.radius;
+        let distance_squared = |t: f64| aabb.distance_squared_to(&self.point_on_segment(t));
+        let (mut lo, mut hi) = (0., 1.);
+        // Each iteration shrinks the interval to 2/3, so after 100 iterations it is far below
+        // floating point precision.
+        for _ in 0..100 {
+            let m1 = lo + (hi - lo) / 3.;
+            let m2 = hi - (hi - lo) / 3.;
+            let (d1, d2) = (distance_squared(m1), distance_squared(m2));
+            if d1.min(d2) <= radius_squared {
+                return true;
+            }
+            if d1 < d2 {
+                hi = m2;
+            } else {
+                lo = m1;
+            }
+        }
+        distance_squared(0.).min(distance_squared(1.)) <= radius_squared
+    }
+}
+
+impl<'a> HasAabbIntersector<'a> for Capsule {
+    type Intersector = &'a Self;
+    fn aabb_intersector(&'a self) -> Self::Intersector {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capsule_aabb_intersection() {
+        let aabb = Aabb::new(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.));
+        // Passes diagonally by the (1, 1, z) edge of the box at a distance of sqrt(0.5).
+        let start = Point3::new(2., 1., 0.5);
+        let end = Point3::new(1., 2., 0.5);
+        let distance = 0.5f64.sqrt();
+        assert!(!Capsule::new(start, end, distance - 0.01).intersect_aabb(&aabb));
+        assert!(Capsule::new(start, end, distance + 0.01).intersect_aabb(&aabb));
+        // Segment passing through the box.
+        assert!(
+            Capsule::new(Point3::new(-1., 0.5, 0.5), Point3::new(2., 0.5, 0.5), 0.01)
+                .intersect_aabb(&aabb)
+        );
+        // A degenerate capsule is a sphere.
+        let p = Point3::new(1.5, 1.5, 1.5);
+        assert!(!Capsule::new(p, p, 0.8).intersect_aabb(&aabb));
+        assert!(Capsule::new(p, p, 0.9).intersect_aabb(&aabb));
+    }
+
+    #[test]
+    fn test_capsule_contains() {
+        let capsule = Capsule::new(Point3::new(0., 0., 0.), Point3::new(10., 0., 0.), 1.);
+        assert!(capsule.contains(&Point3::new(5., 0.9, 0.)));
+        assert!(capsule.contains(&Point3::new(10.5, 0., 0.5)));
+        assert!(!capsule.contains(&Point3::new(5., 1.1, 0.)));
+        assert!(!capsule.contains(&Point3::new(-0.8, 0.8, 0.)));
+    }
+}
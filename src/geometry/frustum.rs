@@ -1,9 +1,9 @@
 //! An asymmetric frustum with an arbitrary 3D pose.
 
-use crate::math::base::{HasAabbIntersector, PointCulling};
+use crate::math::base::{contains_lanes, HasAabbIntersector, PointCulling};
 use crate::math::sat::{CachedAxesIntersector, ConvexPolyhedron, Intersector};
-use arrayvec::ArrayVec;
-use nalgebra::{Isometry3, Matrix4, Perspective3, Point3, Unit, Vector3};
+use crate::math::simd::{AffineRow, F64x4};
+use nalgebra::{Isometry3, Matrix4, Orthographic3, Perspective3, Point3};
 use serde::{Deserialize, Serialize};
 
 /// A perspective projection matrix analogous to cgmath::Perspective.
@@ -86,11 +86,126 @@ impl From<Perspective3<f64>> for Perspective {
     }
 }
 
+/// An orthographic projection matrix analogous to cgmath::Ortho, e.g. for top-down views.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Orthographic {
+    matrix: Matrix4<f64>,
+}
+
+impl Orthographic {
+    /// Left, right, bottom, top, near and far are distances in eye coordinates.
+    pub fn new(left: f64, right: f64, bottom: f64, top: f64, near: f64, far: f64) -> Self {
+        assert!(
+            left < right,
+            "`left` must be smaller than `right`, found: left: {:?} right: {:?}",
+            left,
+            right
+        );
+        assert!(
+            bottom < top,
+            "`bottom` must be smaller than `top`, found: bottom: {:?} top: {:?}",
+            bottom,
+            top
+        );
+        assert!(
+            near < far,
+            "`near` must be smaller than `far`, found: near: {:?} far: {:?}",
+            near,
+            far
+        );
+
+        let r0c0 = 2.0 / (right - left);
+        let r0c3 = -(right + left) / (right - left);
+
+        let r1c1 = 2.0 / (top - bottom);
+        let r1c3 = -(top + bottom) / (top - bottom);
+
+        let r2c2 = -2.0 / (far - near);
+        let r2c3 = -(far + near) / (far - near);
+
+        #[rustfmt::skip]
+        let matrix = Matrix4::new(
+            r0c0, 0.0,  0.0,  r0c3,
+            0.0,  r1c1, 0.0,  r1c3,
+            0.0,  0.0,  r2c2, r2c3,
+            0.0,  0.0,  0.0,  1.0,
+        );
+        Self { matrix }
+    }
+
+    pub fn as_matrix(&self) -> &Matrix4<f64> {
+        &self.matrix
+    }
+
+    pub fn inverse(&self) -> Matrix4<f64> {
+        let inverse_row = |r: usize| {
+            let scale = self.matrix[(r, r)].recip();
+            (scale, -self.matrix[(r, 3)] * scale)
+        };
+        let (r0c0, r0c3) = inverse_row(0);
+        let (r1c1, r1c3) = inverse_row(1);
+        let (r2c2, r2c3) = inverse_row(2);
+
+        #[rustfmt::skip]
+        let matrix = Matrix4::new(
+            r0c0, 0.0,  0.0,  r0c3,
+            0.0,  r1c1, 0.0,  r1c3,
+            0.0,  0.0,  r2c2, r2c3,
+            0.0,  0.0,  0.0,  1.0,
+        );
+        matrix
+    }
+}
+
+impl From<Orthographic3<f64>> for Orthographic {
+    fn from(ortho3: Orthographic3<f64>) -> Self {
+        Self {
+            matrix: ortho3.to_homogeneous(),
+        }
+    }
+}
+
+/// The projection from eye coordinates into clip space of a 'Frustum'.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Projection {
+    Perspective(Perspective),
+    Orthographic(Orthographic),
+}
+
+impl Projection {
+    pub fn as_matrix(&self) -> &Matrix4<f64> {
+        match self {
+            Projection::Perspective(perspective) => perspective.as_matrix(),
+            Projection::Orthographic(orthographic) => orthographic.as_matrix(),
+        }
+    }
+
+    pub fn inverse(&self) -> Matrix4<f64> {
+        match self {
+            Projection::Perspective(perspective) => perspective.inverse(),
+            Projection::Orthographic(orthographic) => orthographic.inverse(),
+        }
+    }
+}
+
+impl From<Perspective> for Projection {
+    fn from(perspective: Perspective) -> Self {
+        Projection::Perspective(perspective)
+    }
+}
+
+impl From<Orthographic> for Projection {
+    fn from(orthographic: Orthographic) -> Self {
+        Projection::Orthographic(orthographic)
+    }
+}
+
 /// A frustum is defined in eye coordinates, where x points right, y points up,
 /// and z points against the viewing direction. This is not how e.g. OpenCV
 /// defines a camera coordinate system. To get from OpenCV camera coordinates
 /// to eye coordinates, you need to rotate 180 deg around the x axis before
 /// creating the perspective projection, see also the frustum unit test below.
+/// With an orthographic projection, the frustum is a box.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Frustum {
     query_from_clip: Matrix4<f64>,
@@ -98,7 +213,8 @@ pub struct Frustum {
 }
 
 impl Frustum {
-    pub fn new(query_from_eye: Isometry3<f64>, clip_from_eye: Perspective) -> Self {
+    pub fn new(query_from_eye: Isometry3<f64>, clip_from_eye: impl Into<Projection>) -> Self {
+        let clip_from_eye = clip_from_eye.into();
         let clip_from_query = clip_from_eye.as_matrix() * query_from_eye.inverse().to_homogeneous();
         let query_from_clip = query_from_eye.to_homogeneous() * clip_from_eye.inverse();
         Frustum {
@@ -115,6 +231,18 @@ impl Frustum {
             clip_from_query,
         })
     }
+
+    /// The projection from the query frame into clip space.
+    pub fn clip_from_query(&self) -> &Matrix4<f64> {
+        &self.clip_from_query
+    }
+
+    pub fn transformed(&self, global_from_query: &Isometry3<f64>) -> Self {
+        Frustum {
+            query_from_clip: global_from_query.to_homogeneous() * self.query_from_clip,
+            clip_from_query: self.clip_from_query * global_from_query.inverse().to_homogeneous(),
+        }
+    }
 }
 
 impl PointCulling for Frustum {
@@ -122,6 +250,29 @@ impl PointCulling for Frustum {
         let p_clip = self.clip_from_query.transform_point(point);
         p_clip.coords.min() > -1.0 && p_clip.coords.max() < 1.0
     }
+
+    fn contains_batch(&self, points: &[Point3<f64>], inside: &mut Vec<bool>) {
+        let m = &self.clip_from_query;
+        let rows = [
+            AffineRow::new(m, 0),
+            AffineRow::new(m, 1),
+            AffineRow::new(m, 2),
+            AffineRow::new(m, 3),
+        ];
+        let (zero, one, minus_one) = (F64x4::splat(0.), F64x4::splat(1.), F64x4::splat(-1.));
+        contains_lanes(points, inside, |x, y, z| {
+            // Like 'transform_point', which only divides by non-zero w.
+            let w = rows[3].apply(x, y, z);
+            let w = F64x4::select(w.cmp_eq(zero), one, w);
+            let in_clip = |c: F64x4| {
+                let c = c / w;
+                c.cmp_gt(minus_one) & c.cmp_lt(one)
+            };
+            in_clip(rows[0].apply(x, y, z))
+                & in_clip(rows[1].apply(x, y, z))
+                & in_clip(rows[2].apply(x, y, z))
+        })
+    }
 }
 
 impl ConvexPolyhedron for Frustum {
@@ -141,28 +292,7 @@ impl ConvexPolyhedron for Frustum {
     }
 
     fn intersector(&self) -> Intersector {
-        let corners = self.compute_corners();
-
-        let mut edges: ArrayVec<[Unit<Vector3<f64>>; 12]> = ArrayVec::new();
-        edges.push(Unit::new_normalize(corners[4] - corners[0])); // x
-        edges.push(Unit::new_normalize(corners[2] - corners[0])); // y
-        edges.push(Unit::new_normalize(corners[1] - corners[0])); // z lower left
-        edges.push(Unit::new_normalize(corners[3] - corners[2])); // z upper left
-        edges.push(Unit::new_normalize(corners[5] - corners[4])); // z lower right
-        edges.push(Unit::new_normalize(corners[7] - corners[6])); // z upper right
-
-        let mut face_normals = ArrayVec::new();
-        face_normals.push(Unit::new_normalize(edges[0].cross(&edges[1]))); // Front and back sides
-        face_normals.push(Unit::new_normalize(edges[0].cross(&edges[2]))); // Lower side
-        face_normals.push(Unit::new_normalize(edges[0].cross(&edges[3]))); // Upper side
-        face_normals.push(Unit::new_normalize(edges[1].cross(&edges[2]))); // Left side
-        face_normals.push(Unit::new_normalize(edges[1].cross(&edges[4]))); // Right side
-
-        Intersector {
-            corners,
-            edges,
-            face_normals,
-        }
+        Intersector::from_transformed_unit_cube(|p| self.query_from_clip.transform_point(p))
     }
 }
 
@@ -203,4 +333,28 @@ mod tests {
             assert_eq!(el_a, el_b);
         }
     }
+
+    #[test]
+    fn test_orthographic() {
+        let ortho = Orthographic::new(-2., 3., -1., 0.5, 0.5, 100.);
+        let ortho3: Orthographic =
+            nalgebra::Orthographic3::new(-2., 3., -1., 0.5, 0.5, 100.).into();
+        let diff = (ortho.as_matrix() - ortho3.as_matrix()).abs();
+        assert!(diff.max() < 1e-12, "diff.max() is {}", diff.max());
+        let diff = (ortho.as_matrix().try_inverse().unwrap() - ortho.inverse()).abs();
+        assert!(diff.max() < 1e-12, "diff.max() is {}", diff.max());
+
+        // Looking down from 100 m, the frustum is a box from the ground to 0.5 m below the eye.
+        let query_from_eye = Isometry3::translation(0., 0., 100.);
+        let frustum = Frustum::new(query_from_eye, ortho);
+        assert!(frustum.contains(&Point3::new(-1.9, 0.4, 0.5)));
+        assert!(frustum.contains(&Point3::new(2.9, -0.9, 9.)));
+        assert!(!frustum.contains(&Point3::new(3.1, 0., 5.)));
+        assert!(!frustum.contains(&Point3::new(0., 0.6, 5.)));
+        assert!(!frustum.contains(&Point3::new(0., 0., -0.1)));
+        assert!(!frustum.contains(&Point3::new(0., 0., 99.6)));
+        let corners = frustum.compute_corners();
+        assert!((corners[0] - Point3::new(-2., -1., 99.5)).norm() < 1e-9);
+        assert!((corners[7] - Point3::new(3., 0.5, 0.)).norm() < 1e-9);
+    }
 }
@@ -1,12 +1,20 @@
 //! Contains geometric primitives, e.g. for defining queries against the point cloud.
 mod aabb;
+mod capsule;
 mod frustum;
 mod obb;
+mod polygon_prism;
+mod ray;
 mod s2_cell_union;
+mod sphere;
 mod web_mercator_rect;
 
 pub use aabb::*;
+pub use capsule::*;
 pub use frustum::*;
 pub use obb::*;
+pub use polygon_prism::*;
+pub use ray::*;
 pub use s2_cell_union::*;
+pub use sphere::*;
 pub use web_mercator_rect::*;
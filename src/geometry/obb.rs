@@ -1,10 +1,14 @@
 //! A bounding box with an arbitrary 3D pose.
 
 use super::aabb::Aabb;
-use crate::math::base::{HasAabbIntersector, PointCulling};
+use crate::math::base::{contains_lanes, HasAabbIntersector, PointCulling};
 use crate::math::sat::{CachedAxesIntersector, ConvexPolyhedron, Intersector};
+use crate::math::simd::{splat3, AffineRow};
 use arrayvec::ArrayVec;
-use nalgebra::{Isometry3, Point3, Unit, UnitQuaternion, Vector3};
+use nalgebra::{
+    Isometry3, Matrix3, Point2, Point3, Rotation3, SymmetricEigen, Unit, UnitQuaternion, Vector2,
+    Vector3,
+};
 use serde::{Deserialize, Serialize};
 use std::iter::FromIterator;
 
@@ -43,6 +47,129 @@ impl Obb {
     pub fn transformed(&self, global_from_query: &Isometry3<f64>) -> Self {
         Self::new(global_from_query * self.query_from_obb, self.half_extent)
     }
+
+    /// The pose of the box, whose center is the origin of the box frame.
+    pub fn query_from_obb(&self) -> &Isometry3<f64> {
+        &self.query_from_obb
+    }
+
+    pub fn half_extent(&self) -> &Vector3<f64> {
+        &self.half_extent
+    }
+
+    pub fn volume(&self) -> f64 {
+        8. * self.half_extent.x * self.half_extent.y * self.half_extent.z
+    }
+}
+
+/// Returns a tight oriented bounding box of `points`, or None if there are none. Each principal
+/// axis of the points is tried as the axis of the box, with the other two axes chosen by rotating
+/// calipers around the convex hull of the points projected along it, and the box with the
+/// smallest volume is kept. This is exact for boxes with a principal axis along one of their
+/// edges, e.g. objects standing upright.
+pub fn fit_obb(points: &[Point3<f64>]) -> Option<Obb> {
+    if points.is_empty() {
+        return None;
+    }
+    let mean = points
+        .iter()
+        .fold(Vector3::zeros(), |sum, p| sum + p.coords)
+        / points.len() as f64;
+    let covariance = points.iter().fold(Matrix3::zeros(), |sum, p| {
+        let d = p.coords - mean;
+        sum + d * d.transpose()
+    });
+    let principal_axes = SymmetricEigen::new(covariance).eigenvectors;
+    (0..3)
+        .map(|i| fit_obb_around_axis(points, &principal_axes.column(i).into_owned()))
+        .min_by(|a, b| a.volume().partial_cmp(&b.volume()).unwrap())
+}
+
+fn fit_obb_around_axis(points: &[Point3<f64>], axis: &Vector3<f64>) -> Obb {
+    let axis = Unit::new_normalize(*axis);
+    let u = Unit::new_normalize(if axis.x.abs() < 0.9 {
+        axis.cross(&Vector3::x())
+    } else {
+        axis.cross(&Vector3::y())
+    });
+    let v = axis.cross(&u);
+    let projected: Vec<Point2<f64>> = points
+        .iter()
+        .map(|p| Point2::new(p.coords.dot(&u), p.coords.dot(&v)))
+        .collect();
+    let hull = convex_hull(projected);
+    // The minimal rectangle around a convex polygon has an edge on one of the polygon's edges.
+    let mut direction = Vector2::x();
+    let mut min_area = f64::INFINITY;
+    for (i, a) in hull.iter().enumerate() {
+        let edge = hull[(i + 1) % hull.len()] - a;
+        if edge.norm() == 0. {
+            continue;
+        }
+        let e = edge.normalize();
+        let n = Vector2::new(-e.y, e.x);
+        let (mut min, mut max) = (Vector2::repeat(f64::MAX), Vector2::repeat(f64::MIN));
+        for p in &hull {
+            let extent = Vector2::new(p.coords.dot(&e), p.coords.dot(&n));
+            min = min.inf(&extent);
+            max = max.sup(&extent);
+        }
+        let area = (max.x - min.x) * (max.y - min.y);
+        if area < min_area {
+            min_area = area;
+            direction = e;
+        }
+    }
+    let x_axis = u.into_inner() * direction.x + v * direction.y;
+    let y_axis = axis.cross(&x_axis);
+    let rotation = Rotation3::from_matrix_unchecked(Matrix3::from_columns(&[
+        x_axis,
+        y_axis,
+        axis.into_inner(),
+    ]));
+    let (mut min, mut max) = (Vector3::repeat(f64::MAX), Vector3::repeat(f64::MIN));
+    for p in points {
+        let local = rotation.inverse_transform_vector(&p.coords);
+        min = min.inf(&local);
+        max = max.sup(&local);
+    }
+    let center = rotation * ((min + max) * 0.5);
+    Obb::new(
+        Isometry3::from_parts(
+            center.into(),
+            UnitQuaternion::from_rotation_matrix(&rotation),
+        ),
+        (max - min) * 0.5,
+    )
+}
+
+/// Andrew's monotone chain, returns the hull in counterclockwise order.
+fn convex_hull(mut points: Vec<Point2<f64>>) -> Vec<Point2<f64>> {
+    points.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let mut hull = half_hull(points.iter());
+    hull.extend(half_hull(points.iter().rev()));
+    hull
+}
+
+/// The lower hull of points sorted by x, or the upper one for the reverse order, without the
+/// last point, which starts the other half.
+fn half_hull<'a>(points: impl Iterator<Item = &'a Point2<f64>>) -> Vec<Point2<f64>> {
+    let cross = |o: &Point2<f64>, a: &Point2<f64>, b: &Point2<f64>| {
+        (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+    };
+    let mut hull: Vec<Point2<f64>> = Vec::new();
+    for p in points {
+        while hull.len() >= 2 && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0. {
+            hull.pop();
+        }
+        hull.push(*p);
+    }
+    hull.pop();
+    hull
 }
 
 impl ConvexPolyhedron for Obb {
@@ -87,6 +214,21 @@ impl PointCulling for Obb {
             && p.y.abs() <= self.half_extent.y
             && p.z.abs() <= self.half_extent.z
     }
+
+    fn contains_batch(&self, points: &[Point3<f64>], inside: &mut Vec<bool>) {
+        let m = self.obb_from_query.to_homogeneous();
+        let rows = [
+            AffineRow::new(&m, 0),
+            AffineRow::new(&m, 1),
+            AffineRow::new(&m, 2),
+        ];
+        let [half_x, half_y, half_z] = splat3(&self.half_extent);
+        contains_lanes(points, inside, |x, y, z| {
+            rows[0].apply(x, y, z).abs().cmp_le(half_x)
+                & rows[1].apply(x, y, z).abs().cmp_le(half_y)
+                & rows[2].apply(x, y, z).abs().cmp_le(half_z)
+        })
+    }
 }
 
 #[cfg(test)]
@@ -138,4 +280,52 @@ mod tests {
         let arbitrary_obb_isec = arbitrary_obb.intersector().cache_separating_axes_for_aabb();
         assert_eq!(arbitrary_obb_isec.axes.len(), 15);
     }
+
+    #[test]
+    fn test_fit_obb_recovers_rotated_box() {
+        let rotation = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 0.3)
+            * UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.1);
+        let query_from_box = Isometry3::from_parts(Vector3::new(5., -2., 1.).into(), rotation);
+        let half_extent = Vector3::new(4., 1.5, 0.5);
+        // Points on a grid through the box, including its corners.
+        let mut points = Vec::new();
+        for i in 0..=8 {
+            for j in 0..=6 {
+                for k in 0..=2 {
+                    let local = Point3::new(
+                        (f64::from(i) / 4. - 1.) * half_extent.x,
+                        (f64::from(j) / 3. - 1.) * half_extent.y,
+                        (f64::from(k) - 1.) * half_extent.z,
+                    );
+                    points.push(query_from_box * local);
+                }
+            }
+        }
+        let obb = fit_obb(&points).unwrap();
+        let obb_volume = 8. * half_extent.x * half_extent.y * half_extent.z;
+        assert!((obb.volume() - obb_volume).abs() < 1e-6 * obb_volume);
+        assert!(
+            (obb.query_from_obb().translation.vector - query_from_box.translation.vector).norm()
+                < 1e-9
+        );
+        // Points on the surface are inside up to rounding.
+        let grown = Obb::new(
+            *obb.query_from_obb(),
+            obb.half_extent() + Vector3::repeat(1e-9),
+        );
+        assert!(points.iter().all(|p| grown.contains(p)));
+    }
+
+    #[test]
+    fn test_fit_obb_degenerate_points() {
+        assert!(fit_obb(&[]).is_none());
+        let single = fit_obb(&[Point3::new(1., 2., 3.)]).unwrap();
+        assert_eq!(single.volume(), 0.);
+        let line = [Point3::new(0., 0., 0.), Point3::new(1., 1., 1.)];
+        let obb = fit_obb(&line).unwrap();
+        let mut extents: Vec<f64> = obb.half_extent().iter().copied().collect();
+        extents.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(extents[0] < 1e-9 && extents[1] < 1e-9);
+        assert!((extents[2] - 0.5 * 3f64.sqrt()).abs() < 1e-9);
+    }
 }
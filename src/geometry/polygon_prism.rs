@@ -0,0 +1,259 @@
+//! A 2D polygon extruded along the z axis of a local frame.
+
+use super::aabb::Aabb;
+use crate::errors::*;
+use crate::math::base::{HasAabbIntersector, IntersectAabb, PointCulling};
+use nalgebra::{Isometry3, Point2, Point3};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// All points whose position in the local frame lies inside `polygon` in x and y, and between
+/// `z_min` and `z_max` in z. The polygon must be simple, but need not be convex.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolygonPrism {
+    query_from_local: Isometry3<f64>,
+    local_from_query: Isometry3<f64>,
+    polygon: Vec<Point2<f64>>,
+    z_min: f64,
+    z_max: f64,
+}
+
+/// Returns whether the segments `a0`-`a1` and `b0`-`b1` intersect.
+fn segments_intersect(
+    a0: &Point2<f64>,
+    a1: &Point2<f64>,
+    b0: &Point2<f64>,
+    b1: &Point2<f64>,
+) -> bool {
+    // Collinear unless clearly on one side, which also holds for NaN.
+    let orientation = |p: &Point2<f64>, q: &Point2<f64>, r: &Point2<f64>| {
+        let cross = (q - p).perp(&(r - p));
+        if cross > 0. {
+            Ordering::Greater
+        } else if cross < 0. {
+            Ordering::Less
+        } else {
+            Ordering::Equal
+        }
+    };
+    let on_segment = |p: &Point2<f64>, q: &Point2<f64>, r: &Point2<f64>| {
+        r.x >= p.x.min(q.x) && r.x <= p.x.max(q.x) && r.y >= p.y.min(q.y) && r.y <= p.y.max(q.y)
+    };
+    let o1 = orientation(a0, a1, b0);
+    let o2 = orientation(a0, a1, b1);
+    let o3 = orientation(b0, b1, a0);
+    let o4 = orientation(b0, b1, a1);
+    (o1 != o2 && o3 != o4)
+        || (o1 == Ordering::Equal && on_segment(a0, a1, b0))
+        || (o2 == Ordering::Equal && on_segment(a0, a1, b1))
+        || (o3 == Ordering::Equal && on_segment(b0, b1, a0))
+        || (o4 == Ordering::Equal && on_segment(b0, b1, a1))
+}
+
+impl PolygonPrism {
+    /// Returns an error when the polygon has less than three vertices, a vertex or z bound is not
+    /// finite, or `z_min` is greater than `z_max`.
+    pub fn new(
+        query_from_local: Isometry3<f64>,
+        polygon: Vec<Point2<f64>>,
+        z_min: f64,
+        z_max: f64,
+    ) -> Result<Self> {
+        if polygon.len() < 3 {
+            return Err(ErrorKind::InvalidInput(format!(
+                "A polygon prism needs at least three vertices, got {}.",
+                polygon.len()
+            ))
+            .into());
+        }
+        if polygon.iter().any(|p| !p.x.is_finite() || !p.y.is_finite()) {
+            return Err(ErrorKind::InvalidInput(
+                "The vertices of a polygon prism need to be finite.".to_string(),
+            )
+            .into());
+        }
+        if !z_min.is_finite() || !z_max.is_finite() || z_min > z_max {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Invalid z bounds [{}, {}] of a polygon prism.",
+                z_min, z_max
+            ))
+            .into());
+        }
+        Ok(PolygonPrism {
+            local_from_query: query_from_local.inverse(),
+            query_from_local,
+            polygon,
+            z_min,
+            z_max,
+        })
+    }
+
+    pub fn polygon(&self) -> &[Point2<f64>] {
+        &self.polygon
+    }
+
+    pub fn transformed(&self, global_from_query: &Isometry3<f64>) -> Self {
+        let query_from_local = global_from_query * self.query_from_local;
+        PolygonPrism {
+            local_from_query: query_from_local.inverse(),
+            query_from_local,
+            polygon: self.polygon.clone(),
+            z_min: self.z_min,
+            z_max: self.z_max,
+        }
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (&Point2<f64>, &Point2<f64>)> {
+        self.polygon.iter().zip(self.polygon.iter().cycle().skip(1))
+    }
+
+    /// Even-odd rule, i.e. casts a ray in +x direction and counts the crossed edges.
+    fn polygon_contains(&self, p: &Point2<f64>) -> bool {
+        let mut inside = false;
+        for (a, b) in self.edges() {
+            if (a.y > p.y) != (b.y > p.y) {
+                let x = a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x);
+                if p.x < x {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    /// The prism's bounding box in the local frame.
+    fn local_bounding_box(&self) -> Aabb {
+        let mut aabb = Aabb::new(
+            Point3::new(self.polygon[0].x, self.polygon[0].y, self.z_min),
+            Point3::new(self.polygon[0].x, self.polygon[0].y, self.z_max),
+        );
+        for p in &self.polygon {
+            aabb.grow(Point3::new(p.x, p.y, self.z_min));
+        }
+        aabb
+    }
+
+    /// An AABB in the query frame containing the prism.
+    pub fn bounding_box(&self) -> Aabb {
+        self.local_bounding_box().transform(&self.query_from_local)
+    }
+}
+
+impl PointCulling for PolygonPrism {
+    fn contains(&self, p: &Point3<f64>) -> bool {
+        let local = self.local_from_query * p;
+        local.z >= self.z_min && local.z <= self.z_max && self.polygon_contains(&local.xy())
+    }
+}
+
+impl IntersectAabb for PolygonPrism {
+    /// Conservative test: The AABB is replaced by its bounding box in the local frame, which is
+    /// then checked exactly against the prism.
+    fn intersect_aabb(&self, aabb: &Aabb) -> bool {
+        let local = aabb.transform(&self.local_from_query);
+        let (min, max) = (local.min(), local.max());
+        if max.z < self.z_min || min.z > self.z_max {
+            return false;
+        }
+        let rect = [
+            Point2::new(min.x, min.y),
+            Point2::new(max.x, min.y),
+            Point2::new(max.x, max.y),
+            Point2::new(min.x, max.y),
+        ];
+        // Either one shape lies within the other, or their boundaries cross.
+        let vertex_in_rect =
+            |p: &Point2<f64>| p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y;
+        self.polygon.iter().any(vertex_in_rect)
+            || rect.iter().any(|corner| self.polygon_contains(corner))
+            || self.edges().any(|(a, b)| {
+                (0..4).any(|i| segments_intersect(a, b, &rect[i], &rect[(i + 1) % 4]))
+            })
+    }
+}
+
+impl<'a> HasAabbIntersector<'a> for PolygonPrism {
+    type Intersector = &'a Self;
+    fn aabb_intersector(&'a self) -> Self::Intersector {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Translation3, UnitQuaternion, Vector3};
+
+    // An L-shaped footprint.
+    fn l_shape() -> Vec<Point2<f64>> {
+        vec![
+            Point2::new(0., 0.),
+            Point2::new(4., 0.),
+            Point2::new(4., 1.),
+            Point2::new(1., 1.),
+            Point2::new(1., 4.),
+            Point2::new(0., 4.),
+        ]
+    }
+
+    #[test]
+    fn test_polygon_prism_contains() {
+        let prism = PolygonPrism::new(Isometry3::identity(), l_shape(), -1., 1.).unwrap();
+        assert!(prism.contains(&Point3::new(0.5, 3., 0.)));
+        assert!(prism.contains(&Point3::new(3., 0.5, 0.9)));
+        assert!(!prism.contains(&Point3::new(3., 3., 0.)));
+        assert!(!prism.contains(&Point3::new(0.5, 0.5, 1.1)));
+    }
+
+    #[test]
+    fn test_polygon_prism_aabb_intersection() {
+        let prism = PolygonPrism::new(Isometry3::identity(), l_shape(), -1., 1.).unwrap();
+        let aabb = |min: (f64, f64, f64), max: (f64, f64, f64)| {
+            Aabb::new(
+                Point3::new(min.0, min.1, min.2),
+                Point3::new(max.0, max.1, max.2),
+            )
+        };
+        // In the notch of the L.
+        assert!(!prism.intersect_aabb(&aabb((2., 2., 0.), (3., 3., 0.5))));
+        // Above the prism.
+        assert!(!prism.intersect_aabb(&aabb((0., 0., 2.), (1., 1., 3.))));
+        // Overlapping an arm, but no vertex of either shape in the other.
+        assert!(prism.intersect_aabb(&aabb((2., -1., 0.), (3., 2., 0.5))));
+        // Containing the whole prism.
+        assert!(prism.intersect_aabb(&aabb((-10., -10., -10.), (10., 10., 10.))));
+        // Inside the prism.
+        assert!(prism.intersect_aabb(&aabb((0.2, 2., 0.), (0.3, 3., 0.5))));
+        // Whatever the answer for NaN, it must not panic.
+        prism.intersect_aabb(&aabb((f64::NAN, 2., 0.), (0.3, f64::NAN, 0.5)));
+    }
+
+    #[test]
+    fn test_polygon_prism_invalid() {
+        let polygon = vec![Point2::new(0., 0.), Point2::new(1., 0.)];
+        assert!(PolygonPrism::new(Isometry3::identity(), polygon, -1., 1.).is_err());
+        assert!(PolygonPrism::new(Isometry3::identity(), l_shape(), 1., -1.).is_err());
+        assert!(PolygonPrism::new(Isometry3::identity(), l_shape(), -1., f64::NAN).is_err());
+        assert!(
+            PolygonPrism::new(Isometry3::identity(), l_shape(), f64::NEG_INFINITY, 1.).is_err()
+        );
+        let mut polygon = l_shape();
+        polygon[2].x = f64::NAN;
+        assert!(PolygonPrism::new(Isometry3::identity(), polygon, -1., 1.).is_err());
+    }
+
+    #[test]
+    fn test_polygon_prism_in_local_frame() {
+        let query_from_local = Isometry3::from_parts(
+            Translation3::new(10., 0., 0.),
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f64::consts::FRAC_PI_2),
+        );
+        let prism = PolygonPrism::new(query_from_local, l_shape(), -1., 1.).unwrap();
+        // The local point (3, 0.5) is at (9.5, 3) in the query frame.
+        assert!(prism.contains(&Point3::new(9.5, 3., 0.)));
+        assert!(!prism.contains(&Point3::new(13., 0.5, 0.)));
+        let bounding_box = prism.bounding_box();
+        assert!((bounding_box.min() - Point3::new(6., 0., -1.)).norm() < 1e-9);
+        assert!((bounding_box.max() - Point3::new(10., 4., 1.)).norm() < 1e-9);
+    }
+}
@@ -0,0 +1,166 @@
+//! A ray for picking points, e.g. the ray through the pixel a user clicked on.
+
+use super::aabb::Aabb;
+use super::capsule::Capsule;
+use nalgebra::{Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// How far from a ray a point may be to be picked.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum PickRadius {
+    /// A fixed distance to the ray, in the units of the point cloud.
+    Metric(f64),
+    /// An angle in radians, so that the allowed distance grows with the distance along the ray.
+    /// This is a radius in screen space, e.g. the angle covered by a few pixels.
+    Angular(f64),
+}
+
+/// The half-line from `origin` in `direction`, cut off at `max_distance`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Ray {
+    origin: Point3<f64>,
+    direction: Unit<Vector3<f64>>,
+    max_distance: f64,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f64>, direction: Vector3<f64>, max_distance: f64) -> Self {
+        Ray {
+            origin,
+            direction: Unit::new_normalize(direction),
+            max_distance,
+        }
+    }
+
+    pub fn origin(&self) -> &Point3<f64> {
+        &self.origin
+    }
+
+    pub fn direction(&self) -> &Vector3<f64> {
+        &self.direction
+    }
+
+    pub fn max_distance(&self) -> f64 {
+        self.max_distance
+    }
+
+    pub fn point_at(&self, distance: f64) -> Point3<f64> {
+        self.origin + distance * self.direction.into_inner()
+    }
+
+    /// The distance from the ray up to which points are picked at `distance` along the ray.
+    pub fn radius_at(&self, radius: PickRadius, distance: f64) -> f64 {
+        match radius {
+            PickRadius::Metric(r) => r,
+            PickRadius::Angular(angle) => angle.tan() * distance.max(0.),
+        }
+    }
+
+    /// A capsule containing all points that can be picked with `radius`.
+    pub fn bounding_capsule(&self, radius: PickRadius) -> Capsule {
+        Capsule::new(
+            self.origin,
+            self.point_at(self.max_distance),
+            self.radius_at(radius, self.max_distance),
+        )
+    }
+
+    /// Returns the distance along the ray of the foot of `p` and the distance of `p` to the ray if
+    /// `p` can be picked with `radius`.
+    pub fn pick_distance(&self, p: &Point3<f64>, radius: PickRadius) -> Option<(f64, f64)> {
+        let v = p - self.origin;
+        let along = v.dot(&self.direction);
+        if along < 0. || along > self.max_distance {
+            return None;
+        }
+        let off_ray = (v - along * self.direction.into_inner()).norm();
+        if off_ray <= self.radius_at(radius, along) {
+            Some((along, off_ray))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the distance along the ray at which it enters `aabb`, or 0 if the origin is inside.
+    /// Returns None if the ray misses the box or enters it beyond `max_distance`.
+    pub fn entry_distance(&self, aabb: &Aabb) -> Option<f64> {
+        let (mut near, mut far) = (0., self.max_distance);
+        for i in 0..3 {
+            let (o, d) = (self.origin[i], self.direction[i]);
+            let (min, max) = (aabb.min()[i], aabb.max()[i]);
+            if d == 0. {
+                if o < min || o > max {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((min - o) / d, (max - o) / d);
+            near = t0.min(t1).max(near);
+            far = t0.max(t1).min(far);
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_distance() {
+        let ray = Ray::new(Point3::origin(), Vector3::new(2., 0., 0.), 10.);
+        assert_eq!(
+            ray.pick_distance(&Point3::new(5., 0.5, 0.), PickRadius::Metric(1.)),
+            Some((5., 0.5))
+        );
+        assert_eq!(
+            ray.pick_distance(&Point3::new(5., 1.5, 0.), PickRadius::Metric(1.)),
+            None
+        );
+        assert_eq!(
+            ray.pick_distance(&Point3::new(-1., 0., 0.), PickRadius::Metric(1.)),
+            None
+        );
+        assert_eq!(
+            ray.pick_distance(&Point3::new(11., 0., 0.), PickRadius::Metric(1.)),
+            None
+        );
+        // 45 degrees, so the radius is equal to the distance along the ray.
+        let angular = PickRadius::Angular(std::f64::consts::FRAC_PI_4);
+        assert!(ray
+            .pick_distance(&Point3::new(1., 0.9, 0.), angular)
+            .is_some());
+        assert!(ray
+            .pick_distance(&Point3::new(1., 1.1, 0.), angular)
+            .is_none());
+        assert!(ray
+            .pick_distance(&Point3::new(8., 7.9, 0.), angular)
+            .is_some());
+    }
+
+    #[test]
+    fn test_entry_distance() {
+        let aabb = Aabb::new(Point3::new(1., -1., -1.), Point3::new(3., 1., 1.));
+        let ray = Ray::new(Point3::origin(), Vector3::x(), 10.);
+        assert_eq!(ray.entry_distance(&aabb), Some(1.));
+        assert_eq!(
+            Ray::new(Point3::new(2., 0., 0.), Vector3::x(), 10.).entry_distance(&aabb),
+            Some(0.)
+        );
+        assert_eq!(
+            Ray::new(Point3::origin(), Vector3::x(), 0.5).entry_distance(&aabb),
+            None
+        );
+        assert_eq!(
+            Ray::new(Point3::origin(), -Vector3::x(), 10.).entry_distance(&aabb),
+            None
+        );
+        assert_eq!(
+            Ray::new(Point3::origin(), Vector3::y(), 10.).entry_distance(&aabb),
+            None
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! A ball around a center point.
+
+use super::aabb::Aabb;
+use crate::math::base::{HasAabbIntersector, IntersectAabb, PointCulling};
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// All points within `radius` of `center`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Sphere {
+    center: Point3<f64>,
+    radius: f64,
+}
+
+impl Sphere {
+    pub fn new(center: Point3<f64>, radius: f64) -> Self {
+        Sphere { center, radius }
+    }
+
+    pub fn center(&self) -> &Point3<f64> {
+        &self.center
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    /// The smallest AABB containing the sphere.
+    pub fn bounding_box(&self) -> Aabb {
+        let r = Vector3::repeat(self.radius);
+        Aabb::new(self.center - r, self.center + r)
+    }
+}
+
+impl PointCulling for Sphere {
+    fn contains(&self, p: &Point3<f64>) -> bool {
+        (p - self.center).norm_squared() <= self.radius * self.radius
+    }
+}
+
+impl IntersectAabb for Sphere {
+    fn intersect_aabb(&self, aabb: &Aabb) -> bool {
+        aabb.distance_squared_to(&self.center) <= self.radius * self.radius
+    }
+}
+
+impl<'a> HasAabbIntersector<'a> for Sphere {
+    type Intersector = &'a Self;
+    fn aabb_intersector(&'a self) -> Self::Intersector {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_aabb_intersection() {
+        let aabb = Aabb::new(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.));
+        // Overlaps a face.
+        assert!(Sphere::new(Point3::new(1.5, 0.5, 0.5), 0.6).intersect_aabb(&aabb));
+        assert!(!Sphere::new(Point3::new(1.5, 0.5, 0.5), 0.4).intersect_aabb(&aabb));
+        // Close to a corner, but not touching it, even though the bounding boxes overlap.
+        assert!(!Sphere::new(Point3::new(1.5, 1.5, 1.5), 0.8).intersect_aabb(&aabb));
+        assert!(Sphere::new(Point3::new(1.5, 1.5, 1.5), 0.9).intersect_aabb(&aabb));
+        // Center inside the box.
+        assert!(Sphere::new(Point3::new(0.5, 0.5, 0.5), 0.1).intersect_aabb(&aabb));
+    }
+
+    #[test]
+    fn test_sphere_contains() {
+        let sphere = Sphere::new(Point3::new(1., 2., 3.), 2.);
+        assert!(sphere.contains(&Point3::new(1., 2., 5.)));
+        assert!(!sphere.contains(&Point3::new(2.5, 3.5, 3.)));
+    }
+}
@@ -1,10 +1,22 @@
+use crate::coordinates::CoordinateSystem;
+use crate::downsample::{VoxelDownsampler, VoxelSize};
 use crate::errors::*;
-use crate::geometry::{Aabb, CellUnion, Frustum, Obb, WebMercatorRect};
-use crate::math::{AllPoints, ClosedInterval, PointCulling};
+use crate::geometry::{
+    Aabb, Capsule, CellUnion, Frustum, Obb, PickRadius, PolygonPrism, Ray, Sphere, WebMercatorRect,
+};
+use crate::labels::{LabelDictionary, CLASSIFICATION_ATTRIBUTE};
+use crate::layout::{lay_out, BatchLayout, LaidOutBatch};
+use crate::math::{AllPoints, ClosedInterval, HalfSpace, PointCulling};
+use crate::metadata::Metadata;
 use crate::read_write::{Encoding, NodeIterator};
-use crate::{match_1d_attr_data, AttributeData, PointsBatch};
+use crate::statistics::PointStatistics;
+#[cfg(feature = "async")]
+use crate::stream::{BatchStream, NodeIteratorFuture};
+use crate::{AttributeData, PointsBatch, NUM_POINTS_PER_BATCH};
 use crossbeam::deque::{Injector, Steal, Worker};
+use nalgebra::{Isometry3, Point3};
 use num_traits::ToPrimitive;
+use rayon::iter::{IntoParallelIterator, ParallelIterator as _};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 
@@ -17,6 +29,9 @@ pub enum PointLocation {
     Obb(Obb),
     S2Cells(CellUnion),
     WebMercatorRect(WebMercatorRect),
+    Sphere(Sphere),
+    Capsule(Capsule),
+    PolygonPrism(PolygonPrism),
 }
 
 impl Default for PointLocation {
@@ -34,8 +49,77 @@ impl PointLocation {
             PointLocation::Obb(obb) => Box::new(obb.clone()),
             PointLocation::S2Cells(cell_union) => Box::new(cell_union.clone()),
             PointLocation::WebMercatorRect(wmr) => Box::new(wmr.clone()),
+            PointLocation::Sphere(sphere) => Box::new(sphere.clone()),
+            PointLocation::Capsule(capsule) => Box::new(capsule.clone()),
+            PointLocation::PolygonPrism(prism) => Box::new(prism.clone()),
         }
     }
+
+    /// Whether all points in `aabb` are inside this location. This may return false for boxes
+    /// that are inside, e.g. for locations that are not convex.
+    pub fn contains_aabb(&self, aabb: &Aabb) -> bool {
+        let contains_corners = |culling: &dyn PointCulling| {
+            let (min, max) = (aabb.min(), aabb.max());
+            (0..8).all(|i| {
+                let corner = Point3::new(
+                    if i & 1 == 0 { min.x } else { max.x },
+                    if i & 2 == 0 { min.y } else { max.y },
+                    if i & 4 == 0 { min.z } else { max.z },
+                );
+                culling.contains(&corner)
+            })
+        };
+        match &self {
+            PointLocation::AllPoints => true,
+            // The maximum of a box is not contained in it, but neither are the points of `aabb`.
+            PointLocation::Aabb(location) => {
+                nalgebra::partial_le(location.min(), aabb.min())
+                    && nalgebra::partial_le(aabb.max(), location.max())
+            }
+            // A convex location contains a box if it contains its corners.
+            PointLocation::Frustum(frustum) => contains_corners(frustum),
+            PointLocation::Obb(obb) => contains_corners(obb),
+            PointLocation::Sphere(sphere) => contains_corners(sphere),
+            PointLocation::Capsule(capsule) => contains_corners(capsule),
+            PointLocation::S2Cells(_)
+            | PointLocation::WebMercatorRect(_)
+            | PointLocation::PolygonPrism(_) => false,
+        }
+    }
+
+    /// Returns this location in the frame `global_from_query` maps into, e.g. to query a point
+    /// cloud that is placed in a common frame by `global_from_query`. Boxes become oriented
+    /// boxes. S2 cells and Web Mercator rectangles are tied to the earth and can not be moved.
+    pub fn transformed(&self, global_from_query: &Isometry3<f64>) -> Result<Self> {
+        Ok(match self {
+            PointLocation::AllPoints => PointLocation::AllPoints,
+            PointLocation::Aabb(aabb) => {
+                PointLocation::Obb(Obb::from(aabb).transformed(global_from_query))
+            }
+            PointLocation::Frustum(frustum) => {
+                PointLocation::Frustum(frustum.transformed(global_from_query))
+            }
+            PointLocation::Obb(obb) => PointLocation::Obb(obb.transformed(global_from_query)),
+            PointLocation::Sphere(sphere) => PointLocation::Sphere(Sphere::new(
+                global_from_query * sphere.center(),
+                sphere.radius(),
+            )),
+            PointLocation::Capsule(capsule) => PointLocation::Capsule(Capsule::new(
+                global_from_query * capsule.start(),
+                global_from_query * capsule.end(),
+                capsule.radius(),
+            )),
+            PointLocation::PolygonPrism(prism) => {
+                PointLocation::PolygonPrism(prism.transformed(global_from_query))
+            }
+            PointLocation::S2Cells(_) | PointLocation::WebMercatorRect(_) => {
+                return Err(ErrorKind::InvalidInput(
+                    "S2 cells and Web Mercator rectangles can not be transformed.".to_string(),
+                )
+                .into())
+            }
+        })
+    }
 }
 
 /// This macro is an alternative to `get_point_culling()`, to be used where
@@ -58,10 +142,16 @@ macro_rules! dispatch_point_location {
             PointLocation::Obb(obb) => $func($($arg,)* obb),
             PointLocation::S2Cells(cu) => $func($($arg,)* cu),
             PointLocation::WebMercatorRect(wmr) => $func($($arg,)* wmr),
+            PointLocation::Sphere(sphere) => $func($($arg,)* sphere),
+            PointLocation::Capsule(capsule) => $func($($arg,)* capsule),
+            PointLocation::PolygonPrism(prism) => $func($($arg,)* prism),
         }
     }
 }
 
+/// The attribute holding the time at which a point was recorded, e.g. a GPS timestamp.
+pub const TIMESTAMP_ATTRIBUTE: &str = "timestamp";
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct PointQuery<'a> {
     #[serde(borrow)]
@@ -69,6 +159,177 @@ pub struct PointQuery<'a> {
     pub location: PointLocation,
     #[serde(borrow)]
     pub filter_intervals: HashMap<&'a str, ClosedInterval<f64>>,
+    /// Only points with a timestamp from the start to the end of this range, both included, are
+    /// returned. The timestamps are read even if they are not among the `attributes`.
+    #[serde(default)]
+    pub time_range: Option<(f64, f64)>,
+    /// Only one point per voxel of this size is returned. Point clouds with levels of detail
+    /// skip the levels that are finer than the voxels.
+    #[serde(default)]
+    pub downsample: Option<VoxelSize>,
+    /// Only points with one of these labels of the 'LabelDictionary' are returned. The
+    /// classifications are read even if they are not among the `attributes`.
+    #[serde(borrow, default)]
+    pub labels: Option<Vec<&'a str>>,
+    /// How 'ParallelIterator::try_for_each_laid_out_batch' lays out the points. The other ways
+    /// of iterating return the default layout.
+    #[serde(default)]
+    pub layout: BatchLayout,
+    /// Applied to the positions of the matching points before they are returned, e.g. to get
+    /// them in a local frame instead of ECEF. The location is still in the frame of the point
+    /// cloud, while the voxels of 'downsample' are in the output frame.
+    #[serde(default)]
+    pub output_from_query: Option<Isometry3<f64>>,
+    /// Only points inside all of these half-spaces are returned, e.g. for cross sections. They
+    /// are in the frame of the point cloud, like the location.
+    #[serde(default)]
+    pub clip_planes: Vec<HalfSpace>,
+}
+
+impl<'a> PointQuery<'a> {
+    pub fn time_interval(&self) -> Option<ClosedInterval<f64>> {
+        self.time_range
+            .map(|(start, end)| ClosedInterval::new(start, end))
+    }
+
+    /// The intervals that the attributes of the matching points lie in, i.e. the filter intervals
+    /// and the time range of their timestamps.
+    pub fn attribute_intervals(&self) -> Vec<(&'a str, ClosedInterval<f64>)> {
+        self.filter_intervals
+            .iter()
+            .map(|(name, interval)| (*name, *interval))
+            .chain(
+                self.time_interval()
+                    .map(|interval| (TIMESTAMP_ATTRIBUTE, interval)),
+            )
+            .collect()
+    }
+
+    /// Moves the positions of `batch` into the output frame, see 'output_from_query'.
+    pub fn transform_output(&self, batch: &mut PointsBatch) {
+        if let Some(output_from_query) = &self.output_from_query {
+            for position in &mut batch.position {
+                *position = output_from_query * *position;
+            }
+        }
+    }
+
+    /// Whether the clip planes cut away all of `aabb`.
+    pub fn clips_away(&self, aabb: &Aabb) -> bool {
+        self.clip_planes
+            .iter()
+            .any(|half_space| half_space.excludes_aabb(aabb))
+    }
+
+    /// Whether all points in `aabb` are in the location and inside the clip planes.
+    pub fn contains_aabb(&self, aabb: &Aabb) -> bool {
+        self.location.contains_aabb(aabb)
+            && self
+                .clip_planes
+                .iter()
+                .all(|half_space| half_space.contains_aabb(aabb))
+    }
+
+    /// Whether the query filters points by their attributes, not only by their position.
+    pub fn has_attribute_filters(&self) -> bool {
+        !self.filter_intervals.is_empty() || self.time_range.is_some() || self.labels.is_some()
+    }
+}
+
+/// Returns the new values of an attribute for the points of a batch, one for each of them.
+pub type AttributeValuesFn<'a> = dyn FnMut(&PointsBatch) -> Result<AttributeData> + 'a;
+
+/// The new values of an attribute, see 'PointCloud::update_attribute'.
+pub enum AttributeUpdate<'a> {
+    /// The same value, i.e. data with a single element, for all points.
+    Constant(AttributeData),
+    /// Called with the matching points of each node, with the attributes and in the output frame
+    /// of the query, it returns a value for each of them.
+    PerPoint(Box<AttributeValuesFn<'a>>),
+}
+
+/// The point picked by 'PointCloud::pick'.
+#[derive(Debug, Clone)]
+pub struct PickHit {
+    pub position: Point3<f64>,
+    /// The distance along the ray.
+    pub distance: f64,
+    /// The distance to the ray.
+    pub distance_to_ray: f64,
+    /// The requested attributes of the point, with a single value each.
+    pub attributes: BTreeMap<String, AttributeData>,
+}
+
+impl PickHit {
+    /// Whether this hit is closer to the origin of the ray than `other`. Of points at the same
+    /// distance along the ray, the one closer to the ray wins.
+    pub fn is_closer_than(&self, other: &PickHit) -> bool {
+        (self.distance, self.distance_to_ray) < (other.distance, other.distance_to_ray)
+    }
+}
+
+/// Picks the points of `node_id` along `ray` and replaces `best` if one of them is closer.
+pub fn pick_in_node<C: PointCloud + ?Sized>(
+    point_cloud: &C,
+    ray: &Ray,
+    radius: PickRadius,
+    attributes: &[&str],
+    node_id: C::Id,
+    best: &mut Option<PickHit>,
+) -> Result<()> {
+    for batch in point_cloud.points_in_node(attributes, node_id, NUM_POINTS_PER_BATCH)? {
+        for (i, p) in batch.position.iter().enumerate() {
+            let (distance, distance_to_ray) = match ray.pick_distance(p, radius) {
+                Some(distances) => distances,
+                None => continue,
+            };
+            let hit = PickHit {
+                position: *p,
+                distance,
+                distance_to_ray,
+                attributes: BTreeMap::new(),
+            };
+            if best.as_ref().map_or(true, |best| hit.is_closer_than(best)) {
+                *best = Some(PickHit {
+                    attributes: batch
+                        .attributes
+                        .iter()
+                        .map(|(name, data)| (name.clone(), data.get(i)))
+                        .collect(),
+                    ..hit
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The statistics of the points of a downsampled query. The voxels span nodes, so the nodes are
+/// read one after the other.
+pub fn downsampled_statistics<C: PointCloud + ?Sized>(
+    point_cloud: &C,
+    query: &PointQuery,
+    voxel_size: VoxelSize,
+) -> Result<PointStatistics> {
+    let mut downsampler = VoxelDownsampler::new(voxel_size);
+    let mut statistics = PointStatistics::default();
+    for node_id in point_cloud.nodes_for_query(query) {
+        point_cloud.stream_points_for_query_in_node(
+            query,
+            node_id,
+            NUM_POINTS_PER_BATCH,
+            |batch| {
+                if let Some(batch) = downsampler.add_batch(batch) {
+                    statistics.add_batch(&batch);
+                }
+                Ok(())
+            },
+        )?;
+    }
+    if let Some(batch) = downsampler.finish() {
+        statistics.add_batch(&batch);
+    }
+    Ok(statistics)
 }
 
 /// Iterator over the points of a point cloud node within the specified PointCulling
@@ -76,6 +337,12 @@ pub struct PointQuery<'a> {
 pub struct FilteredIterator<'a, Culling: PointCulling> {
     pub culling: Culling,
     pub filter_intervals: &'a HashMap<&'a str, ClosedInterval<f64>>,
+    /// See 'PointQuery::clip_planes'.
+    pub clip_planes: &'a [HalfSpace],
+    /// The interval of the timestamps, see 'PointQuery::time_range'.
+    pub time_interval: Option<ClosedInterval<f64>>,
+    /// The sorted ids of the labels, see 'PointQuery::labels'.
+    pub label_ids: Option<Vec<u16>>,
     pub node_iterator: NodeIterator,
 }
 
@@ -90,32 +357,164 @@ where
     }
 }
 
+/// Whether each point of `batch` is inside `culling` and passes the filters of a query, see
+/// 'FilteredIterator'.
+pub(crate) fn matching_points<C: PointCulling + ?Sized>(
+    batch: &PointsBatch,
+    culling: &C,
+    clip_planes: &[HalfSpace],
+    filter_intervals: &HashMap<&str, ClosedInterval<f64>>,
+    time_interval: Option<ClosedInterval<f64>>,
+    label_ids: Option<&[u16]>,
+) -> Vec<bool> {
+    let mut keep = Vec::new();
+    culling.contains_batch(&batch.position, &mut keep);
+    let mut inside = Vec::new();
+    for half_space in clip_planes {
+        half_space.contains_batch(&batch.position, &mut inside);
+        for (k, i) in keep.iter_mut().zip(&inside) {
+            *k &= *i;
+        }
+    }
+    macro_rules! rhs {
+        ($dtype:ident, $data:ident, $interval:expr) => {
+            update_keep(&mut keep, $data, $interval)
+        };
+    }
+    for (attrib, interval) in filter_intervals {
+        let attr_data = batch
+            .attributes
+            .get(*attrib)
+            .expect("Filter attribute needs to be specified as query attribute.");
+        match_1d_attr_data!(attr_data, rhs, interval)
+    }
+    if let Some(interval) = &time_interval {
+        let attr_data = batch
+            .attributes
+            .get(TIMESTAMP_ATTRIBUTE)
+            .expect("Timestamps need to be read for a time range.");
+        match_1d_attr_data!(attr_data, rhs, interval)
+    }
+    if let Some(label_ids) = label_ids {
+        let has_label = |id: u16| label_ids.binary_search(&id).is_ok();
+        match batch.attributes.get(CLASSIFICATION_ATTRIBUTE) {
+            Some(AttributeData::U8(data)) => {
+                for (k, id) in keep.iter_mut().zip(data) {
+                    *k &= has_label(u16::from(*id));
+                }
+            }
+            Some(AttributeData::U16(data)) => {
+                for (k, id) in keep.iter_mut().zip(data) {
+                    *k &= has_label(*id);
+                }
+            }
+            _ => panic!("Classifications need to be read as U8 or U16 for labels."),
+        }
+    }
+    keep
+}
+
+/// The sorted ids of the `labels` of `query`, see 'PointQuery::labels'. Fails if the point cloud
+/// has no `label_dictionary` or it does not know one of the labels.
+pub(crate) fn label_ids_for_query(
+    query: &PointQuery,
+    label_dictionary: Option<&LabelDictionary>,
+) -> Result<Option<Vec<u16>>> {
+    match &query.labels {
+        Some(labels) => Ok(Some(
+            label_dictionary
+                .ok_or_else(|| {
+                    ErrorKind::InvalidInput("The point cloud has no labels.".to_string())
+                })?
+                .ids(labels)?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// What 'PointCloud::stream_points_for_query_in_node' reads of a node for a query and how it
+/// filters the points, so that nodes fetched elsewhere can be filtered the same way.
+pub(crate) struct NodeQuery<'a> {
+    /// The attributes of the query, plus those that are only read for filtering.
+    pub attributes: Vec<&'a str>,
+    add_timestamps: bool,
+    add_classifications: bool,
+    label_ids: Option<Vec<u16>>,
+}
+
+impl<'a> NodeQuery<'a> {
+    pub fn new(query: &PointQuery<'a>, label_dictionary: Option<&LabelDictionary>) -> Result<Self> {
+        let label_ids = label_ids_for_query(query, label_dictionary)?;
+        let mut attributes = query.attributes.clone();
+        let add_timestamps =
+            query.time_range.is_some() && !attributes.contains(&TIMESTAMP_ATTRIBUTE);
+        if add_timestamps {
+            attributes.push(TIMESTAMP_ATTRIBUTE);
+        }
+        let add_classifications =
+            label_ids.is_some() && !attributes.contains(&CLASSIFICATION_ATTRIBUTE);
+        if add_classifications {
+            attributes.push(CLASSIFICATION_ATTRIBUTE);
+        }
+        Ok(NodeQuery {
+            attributes,
+            add_timestamps,
+            add_classifications,
+            label_ids,
+        })
+    }
+
+    /// Calls `callback` with the points of `node_iterator` that match `query`.
+    pub fn stream<F>(
+        &self,
+        query: &PointQuery,
+        node_iterator: NodeIterator,
+        callback: F,
+    ) -> Result<()>
+    where
+        F: FnMut(PointsBatch) -> Result<()>,
+    {
+        let mut callback = callback;
+        // Timestamps and classifications that were only read for filtering are not returned.
+        let callback = |mut batch: PointsBatch| {
+            if self.add_timestamps {
+                batch.attributes.remove(TIMESTAMP_ATTRIBUTE);
+            }
+            if self.add_classifications {
+                batch.attributes.remove(CLASSIFICATION_ATTRIBUTE);
+            }
+            query.transform_output(&mut batch);
+            callback(batch)
+        };
+
+        dispatch_point_location!(
+            stream,
+            &query.location,
+            &query.clip_planes,
+            &query.filter_intervals,
+            query.time_interval(),
+            self.label_ids.clone(),
+            node_iterator,
+            callback
+        )
+    }
+}
+
 impl<'a, Culling: PointCulling> Iterator for FilteredIterator<'a, Culling> {
     type Item = PointsBatch;
 
     fn next(&mut self) -> Option<PointsBatch> {
-        let culling = &self.culling;
-        self.node_iterator.next().map(|mut batch| {
-            let mut keep: Vec<bool> = batch
-                .position
-                .iter()
-                .map(|pos| culling.contains(&pos))
-                .collect();
-            macro_rules! rhs {
-                ($dtype:ident, $data:ident, $interval:expr) => {
-                    update_keep(&mut keep, $data, $interval)
-                };
-            }
-            for (attrib, interval) in self.filter_intervals {
-                let attr_data = batch
-                    .attributes
-                    .get(*attrib)
-                    .expect("Filter attribute needs to be specified as query attribute.");
-                match_1d_attr_data!(attr_data, rhs, interval)
-            }
-            batch.retain(&keep);
-            batch
-        })
+        let mut batch = self.node_iterator.next()?;
+        let keep = matching_points(
+            &batch,
+            &self.culling,
+            self.clip_planes,
+            self.filter_intervals,
+            self.time_interval,
+            self.label_ids.as_deref(),
+        );
+        batch.retain(&keep);
+        Some(batch)
     }
 }
 
@@ -169,6 +568,11 @@ where
 pub trait PointCloud: Sync {
     type Id: ToString + Send + Copy;
     fn nodes_in_location(&self, location: &PointLocation) -> Vec<Self::Id>;
+    /// Returns the nodes that may contain points matching `query`. Point clouds that know the
+    /// values of the attributes in their nodes can skip nodes that the filters rule out.
+    fn nodes_for_query(&self, query: &PointQuery) -> Vec<Self::Id> {
+        self.nodes_in_location(&query.location)
+    }
     fn encoding_for_node(&self, id: Self::Id) -> Encoding;
     /// Return all points in the selected node.
     fn points_in_node(
@@ -177,11 +581,123 @@ pub trait PointCloud: Sync {
         node_id: Self::Id,
         batch_size: usize,
     ) -> Result<NodeIterator>;
+    /// The number of points in the selected node, before any of them are filtered.
+    fn num_points_in_node(&self, node_id: Self::Id) -> usize;
     fn bounding_box(&self) -> &Aabb;
+    /// The coordinate system of the positions, None if it is not known.
+    fn coordinate_system(&self) -> Option<CoordinateSystem> {
+        None
+    }
+    /// The names of the values of the classification attribute, None if there is none.
+    fn label_dictionary(&self) -> Option<&LabelDictionary> {
+        None
+    }
+    /// Where the points come from and how they were processed, None if it is not stored.
+    fn metadata(&self) -> Option<&Metadata> {
+        None
+    }
+
+    /// Removes all points inside `location` and returns the number of removed points.
+    fn delete_in(&mut self, location: &PointLocation) -> Result<usize> {
+        let _ = location;
+        Err(ErrorKind::InvalidInput(
+            "This point cloud does not support deleting points.".to_string(),
+        )
+        .into())
+    }
+
+    /// Sets `attribute` of the points matching `query` to the values of `update` and returns the
+    /// number of updated points. The positions and other attributes stay as they are.
+    fn update_attribute(
+        &mut self,
+        query: &PointQuery,
+        attribute: &str,
+        update: AttributeUpdate,
+    ) -> Result<usize> {
+        let _ = (query, attribute, update);
+        Err(ErrorKind::InvalidInput(
+            "This point cloud does not support updating attributes.".to_string(),
+        )
+        .into())
+    }
+
+    /// Returns the point closest to the origin of `ray` among the points within `radius` of it,
+    /// with the requested `attributes`, e.g. for inspecting the point a user clicked on.
+    fn pick(&self, ray: &Ray, radius: PickRadius, attributes: &[&str]) -> Result<Option<PickHit>> {
+        let location = PointLocation::Capsule(ray.bounding_capsule(radius));
+        let mut best = None;
+        for node_id in self.nodes_in_location(&location) {
+            pick_in_node(self, ray, radius, attributes, node_id, &mut best)?;
+        }
+        Ok(best)
+    }
+
+    /// Like 'points_in_node', but without blocking the caller while the node is fetched, see
+    /// 'DataProvider::data_async'. The default is synchronous: it reads the node with
+    /// 'points_in_node' before returning the future, so it blocks the caller just as long.
+    /// Point clouds whose nodes are fetched from remote storage override it.
+    #[cfg(feature = "async")]
+    fn points_in_node_async(
+        &self,
+        attributes: &[&str],
+        node_id: Self::Id,
+        batch_size: usize,
+    ) -> NodeIteratorFuture<'_> {
+        Box::pin(futures::future::ready(
+            self.points_in_node(attributes, node_id, batch_size),
+        ))
+    }
+
+    /// The points matching `query` in batches of at most `batch_size`, like
+    /// 'ParallelIterator::try_for_each_batch', but as an asynchronous stream. The nodes are
+    /// fetched with 'points_in_node_async', several at a time, and filtered like in
+    /// 'stream_points_for_query_in_node' whenever the stream is polled.
+    #[cfg(feature = "async")]
+    fn stream_batches<'a>(
+        &'a self,
+        query: &'a PointQuery<'a>,
+        batch_size: usize,
+    ) -> Result<BatchStream<'a, Self>>
+    where
+        Self: Sized,
+    {
+        BatchStream::new(self, query, batch_size)
+    }
+
+    /// Returns the number, bounding box and attribute statistics of the points matching `query`.
+    /// The nodes are summarized in parallel.
+    fn statistics(&self, query: &PointQuery) -> Result<PointStatistics> {
+        if let Some(voxel_size) = query.downsample {
+            return downsampled_statistics(self, query, voxel_size);
+        }
+        let node_statistics = self
+            .nodes_for_query(query)
+            .into_par_iter()
+            .map(|node_id| {
+                let mut statistics = PointStatistics::default();
+                self.stream_points_for_query_in_node(
+                    query,
+                    node_id,
+                    NUM_POINTS_PER_BATCH,
+                    |batch| {
+                        statistics.add_batch(&batch);
+                        Ok(())
+                    },
+                )?;
+                Ok(statistics)
+            })
+            .collect::<Result<Vec<PointStatistics>>>()?;
+        let mut statistics = PointStatistics::default();
+        for s in &node_statistics {
+            statistics.merge(s);
+        }
+        Ok(statistics)
+    }
 
     /// Return the points matching the query in the selected node.
     /// Why only a single node? Because the nodes are distributed to several `PointStream` instances
-    /// working in parallel by the `ParallelIterator`.
+    /// working in parallel by the `ParallelIterator`. The points are not downsampled, as the
+    /// voxels span nodes.
     fn stream_points_for_query_in_node<F>(
         &self,
         query: &PointQuery,
@@ -192,23 +708,19 @@ pub trait PointCloud: Sync {
     where
         F: FnMut(PointsBatch) -> Result<()>,
     {
-        let filter_intervals = &query.filter_intervals;
-        let node_iterator = self.points_in_node(&query.attributes, node_id, batch_size)?;
-
-        dispatch_point_location!(
-            stream,
-            &query.location,
-            filter_intervals,
-            node_iterator,
-            callback
-        )
+        let node_query = NodeQuery::new(query, self.label_dictionary())?;
+        let node_iterator = self.points_in_node(&node_query.attributes, node_id, batch_size)?;
+        node_query.stream(query, node_iterator, callback)
     }
 }
 
 // TODO(nnmm): Instead of having this helper function, make stream_points_for_query_in_node
 // accept a T: PointCulling, so we can dispatch to this function directly
 fn stream<'a, T: PointCulling + Clone, F: FnMut(PointsBatch) -> Result<()>>(
+    clip_planes: &'a [HalfSpace],
     intv: &'a HashMap<&'a str, ClosedInterval<f64>>,
+    time_interval: Option<ClosedInterval<f64>>,
+    label_ids: Option<Vec<u16>>,
     itr: NodeIterator,
     callback: F,
     culling: &T,
@@ -216,7 +728,10 @@ fn stream<'a, T: PointCulling + Clone, F: FnMut(PointsBatch) -> Result<()>>(
     let culling: T = culling.clone();
     FilteredIterator {
         culling,
+        clip_planes,
         filter_intervals: intv,
+        time_interval,
+        label_ids,
         node_iterator: itr,
     }
     .try_for_each(callback)
@@ -252,18 +767,41 @@ where
     }
 
     /// compute a function while iterating on a batch of points
-    pub fn try_for_each_batch<F>(&mut self, func: F) -> Result<()>
+    pub fn try_for_each_batch<F>(&mut self, mut func: F) -> Result<()>
     where
         F: FnMut(PointsBatch) -> Result<()>,
     {
+        self.try_for_each_batch_in_layout(BatchLayout::default(), |batch| match batch {
+            LaidOutBatch::Columns(batch) => func(batch),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Like 'try_for_each_batch', but the batches are in the 'PointQuery::layout'. The threads
+    /// that read the nodes convert them, unless they are downsampled first.
+    pub fn try_for_each_laid_out_batch<F>(&mut self, func: F) -> Result<()>
+    where
+        F: FnMut(LaidOutBatch) -> Result<()>,
+    {
+        self.try_for_each_batch_in_layout(self.point_query.layout, func)
+    }
+
+    fn try_for_each_batch_in_layout<F>(&mut self, layout: BatchLayout, func: F) -> Result<()>
+    where
+        F: FnMut(LaidOutBatch) -> Result<()>,
+    {
+        // Downsampling needs the f64 positions of all batches.
+        let worker_layout = match self.point_query.downsample {
+            Some(_) => BatchLayout::default(),
+            None => layout,
+        };
         // get thread safe fifo
         let jobs = Injector::<(&C, C::Id)>::new();
         let mut number_of_jobs = 0;
         self.point_clouds
             .iter()
             .flat_map(|point_cloud| {
-                std::iter::repeat(point_cloud)
-                    .zip(point_cloud.nodes_in_location(&self.point_query.location))
+                std::iter::repeat(point_cloud).zip(point_cloud.nodes_for_query(self.point_query))
             })
             .for_each(|(node_id, point_cloud)| {
                 jobs.push((node_id, point_cloud));
@@ -272,7 +810,7 @@ where
 
         // operate on nodes with limited number of threads
         crossbeam::scope(|s| {
-            let (tx, rx) = crossbeam::channel::bounded::<PointsBatch>(self.buffer_size);
+            let (tx, rx) = crossbeam::channel::bounded::<LaidOutBatch>(self.buffer_size);
             for curr_thread in 0..self.num_threads {
                 let tx = tx.clone();
                 let point_query = &self.point_query;
@@ -281,14 +819,15 @@ where
                 let jobs = &jobs;
 
                 s.spawn(move |_| {
-                    let send_func = |batch: PointsBatch| match tx.send(batch) {
-                        Ok(_) => Ok(()),
-                        Err(e) => Err(ErrorKind::Channel(format!(
-                            "Thread {}: sending operation failed, nothing more to do {:?}",
-                            curr_thread, e,
-                        ))
-                        .into()),
-                    };
+                    let send_func =
+                        |batch: PointsBatch| match tx.send(lay_out(batch, worker_layout)) {
+                            Ok(_) => Ok(()),
+                            Err(e) => Err(ErrorKind::Channel(format!(
+                                "Thread {}: sending operation failed, nothing more to do {:?}",
+                                curr_thread, e,
+                            ))
+                            .into()),
+                        };
 
                     // One `PointStream` per thread vs one per node allows to send more full point batches
                     let mut point_stream = PointStream::new(batch_size, &send_func);
@@ -326,8 +865,34 @@ where
             // ensure to close the channel after the threads exit
             drop(tx);
 
-            // receiver collects all the messages
-            rx.iter().try_for_each(func)
+            // receiver collects all the messages, downsampling them across all nodes
+            let mut func = func;
+            let voxel_size = match self.point_query.downsample {
+                Some(voxel_size) => voxel_size,
+                None => return rx.iter().try_for_each(func),
+            };
+            let mut func = |batch| func(lay_out(batch, layout));
+            let mut downsampler = VoxelDownsampler::new(voxel_size);
+            rx.iter().try_for_each(|batch| {
+                let batch = match batch {
+                    LaidOutBatch::Columns(batch) => batch,
+                    _ => unreachable!(),
+                };
+                match downsampler.add_batch(batch) {
+                    Some(batch) => func(batch),
+                    None => Ok(()),
+                }
+            })?;
+            let mut averaged = match downsampler.finish() {
+                Some(averaged) => averaged,
+                None => return Ok(()),
+            };
+            while !averaged.position.is_empty() {
+                let at = std::cmp::min(averaged.position.len(), self.batch_size);
+                let rest = averaged.split_off(at);
+                func(std::mem::replace(&mut averaged, rest))?;
+            }
+            Ok(())
         })
         .expect("ParallelIterator: Panic in try_for_each_batch child thread")
     }
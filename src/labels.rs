@@ -0,0 +1,288 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Semantic labels of points, stored as a categorical 'classification' attribute whose values
+//! are named by a 'LabelDictionary'. Neighboring points mostly share their label, so the
+//! attribute is stored run-length encoded in the nodes.
+
+use crate::data_provider::{DataProvider, OnDiskDataProvider};
+use crate::errors::*;
+use crate::proto;
+use crate::{AttributeData, AttributeDataType};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// The attribute holding the label id of a point, as U8 or U16.
+pub const CLASSIFICATION_ATTRIBUTE: &str = "classification";
+
+/// The names of the label ids of a point cloud.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelDictionary {
+    data_type: AttributeDataType,
+    names: BTreeMap<u16, String>,
+}
+
+impl LabelDictionary {
+    /// An empty dictionary for a classification attribute of `data_type`, which is U8 or U16.
+    pub fn new(data_type: AttributeDataType) -> Result<Self> {
+        match data_type {
+            AttributeDataType::U8 | AttributeDataType::U16 => Ok(LabelDictionary {
+                data_type,
+                names: BTreeMap::new(),
+            }),
+            _ => Err(ErrorKind::InvalidInput(format!(
+                "Labels need to be U8 or U16, not {:?}.",
+                data_type
+            ))
+            .into()),
+        }
+    }
+
+    /// Names the label `id`. Ids and names need to be unique.
+    pub fn add(&mut self, id: u16, name: impl Into<String>) -> Result<()> {
+        let name = name.into();
+        if self.data_type == AttributeDataType::U8 && id > u16::from(u8::MAX) {
+            return Err(
+                ErrorKind::InvalidInput(format!("Label id {} does not fit into U8.", id)).into(),
+            );
+        }
+        if self.names.contains_key(&id) || self.id(&name).is_some() {
+            return Err(
+                ErrorKind::InvalidInput(format!("Label {} '{}' is not unique.", id, name)).into(),
+            );
+        }
+        self.names.insert(id, name);
+        Ok(())
+    }
+
+    pub fn data_type(&self) -> AttributeDataType {
+        self.data_type
+    }
+
+    pub fn name(&self, id: u16) -> Option<&str> {
+        self.names.get(&id).map(String::as_str)
+    }
+
+    pub fn id(&self, name: &str) -> Option<u16> {
+        self.names
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(id, _)| *id)
+    }
+
+    /// The labels ordered by id.
+    pub fn labels(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(id, name)| (*id, name.as_str()))
+    }
+
+    /// The sorted ids of the labels called `names`. Fails for unknown names.
+    pub fn ids(&self, names: &[&str]) -> Result<Vec<u16>> {
+        let mut ids = names
+            .iter()
+            .map(|name| {
+                self.id(name).ok_or_else(|| {
+                    ErrorKind::InvalidInput(format!("There is no label '{}'.", name)).into()
+                })
+            })
+            .collect::<Result<Vec<u16>>>()?;
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    pub fn to_proto(&self) -> proto::LabelDictionary {
+        let mut dictionary = proto::LabelDictionary::new();
+        dictionary.set_data_type(self.data_type.to_proto());
+        for (id, name) in self.labels() {
+            let mut label = proto::LabelDictionary_Label::new();
+            label.set_id(u32::from(id));
+            label.set_name(name.to_string());
+            dictionary.mut_labels().push(label);
+        }
+        dictionary
+    }
+
+    pub fn from_proto(dictionary: &proto::LabelDictionary) -> Result<Self> {
+        let mut result = Self::new(AttributeDataType::from_proto(dictionary.data_type)?)?;
+        for label in dictionary.get_labels() {
+            let id = u16::try_from(label.id).map_err(|_| {
+                ErrorKind::InvalidInput(format!("Label id {} is too large.", label.id))
+            })?;
+            result.add(id, label.get_name())?;
+        }
+        Ok(result)
+    }
+
+    /// The dictionary of a meta proto, None if the points are not labeled.
+    pub fn from_meta_proto(meta: &proto::Meta) -> Result<Option<Self>> {
+        if meta.has_label_dictionary() {
+            Self::from_proto(meta.get_label_dictionary()).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Stores `dictionary` in the meta data of the point cloud in `directory`, e.g. after building
+/// an S2 point cloud with a classification attribute.
+pub fn write_label_dictionary(
+    directory: impl AsRef<Path>,
+    dictionary: &LabelDictionary,
+) -> Result<()> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    let mut meta = data_provider.meta_proto()?;
+    meta.set_label_dictionary(dictionary.to_proto());
+    data_provider.write_meta_proto(&meta)
+}
+
+/// Writes `data`, which is U8 or U16, as runs of a little endian u32 count followed by the little
+/// endian value. Runs of consecutive writes are simply concatenated.
+pub fn run_length_encode(data: &AttributeData, writer: &mut impl Write) -> io::Result<()> {
+    fn encode<T: Copy + PartialEq>(
+        values: &[T],
+        writer: &mut impl Write,
+        write_value: impl Fn(&mut dyn Write, T) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut start = 0;
+        while start < values.len() {
+            let value = values[start];
+            let length = values[start..]
+                .iter()
+                .take(u32::MAX as usize)
+                .take_while(|v| **v == value)
+                .count();
+            writer.write_u32::<LittleEndian>(length as u32)?;
+            write_value(writer, value)?;
+            start += length;
+        }
+        Ok(())
+    }
+    match data {
+        AttributeData::U8(values) => encode(values, writer, |w, v| w.write_u8(v)),
+        AttributeData::U16(values) => encode(values, writer, |w, v| w.write_u16::<LittleEndian>(v)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Labels need to be U8 or U16.",
+        )),
+    }
+}
+
+/// Reads run-length encoded values, see 'run_length_encode', as plain little endian values.
+pub struct RunLengthDecoder<R> {
+    reader: R,
+    value: [u8; 2],
+    bytes_per_value: usize,
+    /// The bytes of the current run that have not been read yet.
+    remaining: u64,
+}
+
+impl<R: Read> RunLengthDecoder<R> {
+    pub fn new(reader: R, data_type: AttributeDataType) -> Self {
+        RunLengthDecoder {
+            reader,
+            value: [0; 2],
+            bytes_per_value: data_type.size_of(),
+            remaining: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for RunLengthDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            let length = match self.reader.read_u32::<LittleEndian>() {
+                Ok(length) => length,
+                Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(err) => return Err(err),
+            };
+            self.reader
+                .read_exact(&mut self.value[..self.bytes_per_value])?;
+            self.remaining = u64::from(length) * self.bytes_per_value as u64;
+        }
+        let num_bytes = std::cmp::min(buf.len() as u64, self.remaining) as usize;
+        // The runs consist of whole values, so the remaining bytes tell where in a value we are.
+        let offset = self.bytes_per_value - (self.remaining % self.bytes_per_value as u64) as usize;
+        for (i, byte) in buf[..num_bytes].iter_mut().enumerate() {
+            *byte = self.value[(offset + i) % self.bytes_per_value];
+        }
+        self.remaining -= num_bytes as u64;
+        Ok(num_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_length_round_trip() {
+        let values: Vec<u16> = (0..1000u16).map(|i| i / 64 * 300 + i / 32 % 2).collect();
+        let mut encoded = Vec::new();
+        // Two writes of the same node are appended to each other.
+        run_length_encode(&AttributeData::U16(values[..500].to_vec()), &mut encoded).unwrap();
+        run_length_encode(&AttributeData::U16(values[500..].to_vec()), &mut encoded).unwrap();
+        assert!(encoded.len() < values.len() * 2);
+
+        let mut decoder = RunLengthDecoder::new(&encoded[..], AttributeDataType::U16);
+        // Reads of odd sizes split values.
+        let mut decoded = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let num_bytes = decoder.read(&mut buf).unwrap();
+            if num_bytes == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..num_bytes]);
+        }
+        let decoded: Vec<u16> = decoded
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(decoded, values);
+
+        let labels = vec![1u8, 1, 1, 7, 7, 2];
+        let mut encoded = Vec::new();
+        run_length_encode(&AttributeData::U8(labels.clone()), &mut encoded).unwrap();
+        assert_eq!(encoded.len(), 3 * 5);
+        let mut decoded = Vec::new();
+        RunLengthDecoder::new(&encoded[..], AttributeDataType::U8)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, labels);
+        assert!(run_length_encode(&AttributeData::F32(vec![1.]), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_label_dictionary() {
+        let mut dictionary = LabelDictionary::new(AttributeDataType::U8).unwrap();
+        dictionary.add(2, "ground").unwrap();
+        dictionary.add(6, "building").unwrap();
+        assert!(dictionary.add(6, "tree").is_err());
+        assert!(dictionary.add(7, "ground").is_err());
+        assert!(dictionary.add(300, "tree").is_err());
+        assert_eq!(dictionary.ids(&["building", "ground"]).unwrap(), vec![2, 6]);
+        assert!(dictionary.ids(&["water"]).is_err());
+        assert_eq!(dictionary.name(6), Some("building"));
+        assert_eq!(
+            LabelDictionary::from_proto(&dictionary.to_proto()).unwrap(),
+            dictionary
+        );
+        assert!(LabelDictionary::new(AttributeDataType::F32).is_err());
+    }
+}
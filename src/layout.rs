@@ -0,0 +1,224 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Other layouts of the points of a query than the columns of f64 positions and attributes of
+//! 'PointsBatch', e.g. interleaved f32 vertices for uploading them to a GPU.
+
+use crate::{AttributeData, PointsBatch};
+use nalgebra::{Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// How the batches of points returned for a 'PointQuery' are laid out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchLayout {
+    /// The position and the attributes of each point are next to each other instead of being
+    /// stored column by column, see 'InterleavedBatch'.
+    #[serde(default)]
+    pub interleaved: bool,
+    /// The positions are f32 instead of f64. Far from the origin, they lose precision.
+    #[serde(default)]
+    pub f32_positions: bool,
+}
+
+/// Points whose position and attributes are interleaved, encoded as little endian. Every point
+/// starts with its position as three f32 or f64, followed by the attributes in the order of
+/// their names, each with the size of its 'AttributeDataType'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterleavedBatch {
+    pub num_points: usize,
+    /// The number of bytes per point.
+    pub stride: usize,
+    /// The byte offset of "position" and each attribute within a point.
+    pub offsets: Vec<(String, usize)>,
+    pub data: Vec<u8>,
+}
+
+impl InterleavedBatch {
+    pub fn offset(&self, name: &str) -> Option<usize> {
+        self.offsets
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, offset)| *offset)
+    }
+}
+
+/// A batch of points in the 'BatchLayout' of a query.
+#[derive(Debug, Clone)]
+pub enum LaidOutBatch {
+    Columns(PointsBatch),
+    ColumnsF32(PointsBatch<f32>),
+    Interleaved(InterleavedBatch),
+}
+
+impl LaidOutBatch {
+    pub fn num_points(&self) -> usize {
+        match self {
+            LaidOutBatch::Columns(batch) => batch.position.len(),
+            LaidOutBatch::ColumnsF32(batch) => batch.position.len(),
+            LaidOutBatch::Interleaved(batch) => batch.num_points,
+        }
+    }
+}
+
+/// Writes the little endian bytes of a value into the front of a slice.
+trait PutLe {
+    fn put_le(&self, out: &mut [u8]);
+}
+
+macro_rules! derive_put_le {
+    ($($scalar:ty),*) => {
+        $(impl PutLe for $scalar {
+            fn put_le(&self, out: &mut [u8]) {
+                let bytes = self.to_le_bytes();
+                out[..bytes.len()].copy_from_slice(&bytes);
+            }
+        })*
+    };
+}
+
+derive_put_le!(u8, u16, u32, u64, i8, i16, i32, i64, f32, f64);
+
+impl<T: PutLe + nalgebra::Scalar> PutLe for Vector3<T> {
+    fn put_le(&self, out: &mut [u8]) {
+        let size = std::mem::size_of::<T>();
+        for (i, value) in self.iter().enumerate() {
+            value.put_le(&mut out[i * size..]);
+        }
+    }
+}
+
+fn put_column<T: PutLe>(values: &[T], data: &mut [u8], stride: usize, offset: usize) {
+    for (value, point) in values.iter().zip(data.chunks_exact_mut(stride)) {
+        value.put_le(&mut point[offset..]);
+    }
+}
+
+fn interleave(batch: &PointsBatch, f32_positions: bool) -> InterleavedBatch {
+    let num_points = batch.position.len();
+    let mut offsets = vec![("position".to_string(), 0)];
+    let mut stride = if f32_positions { 3 * 4 } else { 3 * 8 };
+    for (name, data) in &batch.attributes {
+        offsets.push((name.clone(), stride));
+        stride += data.data_type().size_of();
+    }
+    let mut data = vec![0; num_points * stride];
+    if f32_positions {
+        let position: Vec<Vector3<f32>> = batch
+            .position
+            .iter()
+            .map(|p| p.coords.map(|c| c as f32))
+            .collect();
+        put_column(&position, &mut data, stride, 0);
+    } else {
+        let position: Vec<Vector3<f64>> = batch.position.iter().map(|p| p.coords).collect();
+        put_column(&position, &mut data, stride, 0);
+    }
+    for ((_, offset), attribute) in offsets[1..].iter().zip(batch.attributes.values()) {
+        macro_rules! rhs {
+            ($dtype:ident, $values:ident, $data:expr, $stride:expr, $offset:expr) => {
+                put_column($values, $data, $stride, $offset)
+            };
+        }
+        match_attr_data!(attribute, rhs, (&mut data), stride, (*offset))
+    }
+    InterleavedBatch {
+        num_points,
+        stride,
+        offsets,
+        data,
+    }
+}
+
+/// Converts a batch of f64 positions and attribute columns into `layout`.
+pub fn lay_out(batch: PointsBatch, layout: BatchLayout) -> LaidOutBatch {
+    match layout {
+        BatchLayout {
+            interleaved: true,
+            f32_positions,
+        } => LaidOutBatch::Interleaved(interleave(&batch, f32_positions)),
+        BatchLayout {
+            interleaved: false,
+            f32_positions: true,
+        } => LaidOutBatch::ColumnsF32(PointsBatch {
+            position: batch
+                .position
+                .iter()
+                .map(|p| Point3::from(p.coords.map(|c| c as f32)))
+                .collect(),
+            attributes: batch.attributes,
+        }),
+        BatchLayout {
+            interleaved: false,
+            f32_positions: false,
+        } => LaidOutBatch::Columns(batch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleave() {
+        let batch = PointsBatch {
+            position: vec![Point3::new(1., 2., 3.), Point3::new(4., 5., 6.)],
+            attributes: vec![
+                (
+                    "color".to_string(),
+                    AttributeData::U8Vec3(vec![Vector3::new(10, 20, 30), Vector3::new(40, 50, 60)]),
+                ),
+                ("intensity".to_string(), AttributeData::F32(vec![0.5, 1.5])),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let layout = BatchLayout {
+            interleaved: true,
+            f32_positions: true,
+        };
+        let interleaved = match lay_out(batch.clone(), layout) {
+            LaidOutBatch::Interleaved(interleaved) => interleaved,
+            other => panic!("Expected interleaved points, got {:?}.", other),
+        };
+        assert_eq!(interleaved.num_points, 2);
+        assert_eq!(interleaved.stride, 12 + 3 + 4);
+        assert_eq!(interleaved.offset("color"), Some(12));
+        assert_eq!(interleaved.offset("intensity"), Some(15));
+        let point = &interleaved.data[interleaved.stride..];
+        let f32_at = |offset: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&point[offset..offset + 4]);
+            f32::from_le_bytes(bytes)
+        };
+        assert_eq!((f32_at(0), f32_at(4), f32_at(8)), (4., 5., 6.));
+        assert_eq!(&point[12..15], &[40, 50, 60]);
+        assert_eq!(f32_at(15), 1.5);
+
+        match lay_out(batch.clone(), BatchLayout::default()) {
+            LaidOutBatch::Columns(columns) => assert_eq!(columns.position, batch.position),
+            other => panic!("Expected columns, got {:?}.", other),
+        }
+        let layout = BatchLayout {
+            interleaved: false,
+            f32_positions: true,
+        };
+        match lay_out(batch, layout) {
+            LaidOutBatch::ColumnsF32(columns) => {
+                assert_eq!(columns.position[1], Point3::new(4f32, 5., 6.));
+                assert_eq!(columns.attributes.len(), 2);
+            }
+            other => panic!("Expected f32 columns, got {:?}.", other),
+        }
+    }
+}
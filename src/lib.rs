@@ -20,20 +20,29 @@ pub mod math;
 #[macro_use]
 pub mod attributes;
 pub mod color;
+pub mod coordinates;
 pub mod data_provider;
+pub mod downsample;
 // Workaround for https://github.com/rust-lang-nursery/error-chain/issues/254
 #[allow(deprecated)]
 pub mod errors;
 pub mod geometry;
 #[macro_use]
 pub mod iterator;
+pub mod labels;
+pub mod layout;
+pub mod metadata;
 pub mod octree;
 pub mod read_write;
 pub mod s2_cells;
+pub mod segmentation;
+pub mod statistics;
+#[cfg(feature = "async")]
+pub mod stream;
 pub mod utils;
 
 use errors::Result;
-use nalgebra::Point3;
+use nalgebra::{Point3, Scalar};
 use std::collections::{BTreeMap, HashMap};
 use std::convert::{TryFrom, TryInto};
 
@@ -79,7 +88,8 @@ pub fn attribute_extension(attribute: &str) -> &str {
     }
 }
 
-trait PointCloudMeta {
+/// The attributes that the points of a point cloud have.
+pub trait PointCloudMeta {
     fn attribute_data_types(&self) -> &HashMap<String, AttributeDataType>;
     fn attribute_data_types_for(
         &self,
@@ -97,10 +107,11 @@ trait PointCloudMeta {
     }
 }
 
-/// General structure that contains points and attached feature attributes.
+/// General structure that contains points and attached feature attributes. The positions are
+/// f64, unless a query asked for another 'layout::BatchLayout'.
 #[derive(Debug, Clone)]
-pub struct PointsBatch {
-    pub position: Vec<Point3<f64>>,
+pub struct PointsBatch<S: Scalar = f64> {
+    pub position: Vec<Point3<S>>,
     // BTreeMap for deterministic iteration order.
     pub attributes: BTreeMap<String, AttributeData>,
 }
@@ -136,6 +147,19 @@ impl PointsBatch {
         }
     }
 
+    /// Returns a copy of the points in `range`.
+    pub fn slice(&self, range: std::ops::Range<usize>) -> Self {
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|(n, a)| (n.clone(), a.slice(range.clone())))
+            .collect();
+        Self {
+            position: self.position[range].to_vec(),
+            attributes,
+        }
+    }
+
     pub fn retain(&mut self, keep: &[bool]) {
         assert_eq!(self.position.len(), keep.len());
         let mut keep = keep.iter().copied().cycle();
@@ -1,9 +1,48 @@
 use crate::geometry::Aabb;
 use crate::math::sat::{CachedAxesIntersector, ConvexPolyhedron, Relation};
+use crate::math::simd::{F64x4, Mask4};
 use nalgebra::Point3;
 
 pub trait PointCulling {
     fn contains(&self, point: &Point3<f64>) -> bool;
+
+    /// Sets `inside` to whether each of the `points` is contained. The culling of large batches
+    /// is faster for the shapes that test several points at once, see 'contains_lanes'.
+    fn contains_batch(&self, points: &[Point3<f64>], inside: &mut Vec<bool>) {
+        inside.clear();
+        inside.extend(points.iter().map(|p| self.contains(p)));
+    }
+}
+
+/// The number of points that 'contains_lanes' tests at once.
+pub const LANES: usize = 4;
+
+/// Sets `inside` to the results of `lanes` for the `points`, which it is called with `LANES` at a
+/// time, as the lanes of their x, y and z coordinates. The last call is padded by repeating the
+/// last point.
+pub fn contains_lanes<F>(points: &[Point3<f64>], inside: &mut Vec<bool>, lanes: F)
+where
+    F: Fn(F64x4, F64x4, F64x4) -> Mask4,
+{
+    inside.clear();
+    inside.reserve(points.len());
+    for chunk in points.chunks(LANES) {
+        let mut x = [0.; LANES];
+        let mut y = [0.; LANES];
+        let mut z = [0.; LANES];
+        for i in 0..LANES {
+            let p = chunk[i.min(chunk.len() - 1)];
+            x[i] = p.x;
+            y[i] = p.y;
+            z[i] = p.z;
+        }
+        let mask = lanes(
+            F64x4::from_array(x),
+            F64x4::from_array(y),
+            F64x4::from_array(z),
+        );
+        inside.extend_from_slice(&mask.to_array()[..chunk.len()]);
+    }
 }
 
 /// Something that can perform an intersection test with an AABB.
@@ -26,6 +65,12 @@ pub trait HasAabbIntersector<'a> {
     fn aabb_intersector(&'a self) -> Self::Intersector;
 }
 
+impl<'a, T: IntersectAabb> IntersectAabb for &'a T {
+    fn intersect_aabb(&self, aabb: &Aabb) -> bool {
+        (**self).intersect_aabb(aabb)
+    }
+}
+
 impl IntersectAabb for CachedAxesIntersector {
     fn intersect_aabb(&self, aabb: &Aabb) -> bool {
         self.intersect(&aabb.compute_corners()) != Relation::Out
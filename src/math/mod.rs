@@ -22,6 +22,7 @@ use std::str::FromStr;
 #[macro_use]
 pub mod base;
 pub mod sat;
+pub mod simd;
 pub mod web_mercator;
 pub use base::*;
 pub use sat::*;
@@ -87,6 +88,20 @@ where
     pub fn contains(self, value: T) -> bool {
         self.lower_bound <= value && value <= self.upper_bound
     }
+
+    pub fn intersects(self, other: ClosedInterval<T>) -> bool {
+        self.lower_bound <= other.upper_bound && other.lower_bound <= self.upper_bound
+    }
+}
+
+impl<T: Copy> ClosedInterval<T> {
+    pub fn lower_bound(&self) -> T {
+        self.lower_bound
+    }
+
+    pub fn upper_bound(&self) -> T {
+        self.upper_bound
+    }
 }
 
 impl<T> FromStr for ClosedInterval<T>
@@ -185,7 +200,7 @@ pub fn local_frame_from_lat_lng(lat: f64, lon: f64) -> Isometry3<f64> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::geometry::{Aabb, Frustum, Perspective};
+    use crate::geometry::{Aabb, Frustum, Obb, Perspective};
     use nalgebra::{UnitQuaternion, Vector3};
 
     #[test]
@@ -218,4 +233,37 @@ mod tests {
         assert!(frustum.contains(&bbox_min));
         assert!(frustum.contains(&bbox_max));
     }
+
+    #[test]
+    fn test_contains_batch_matches_contains() {
+        // A grid around the shapes, with a number of points that does not fill the last lanes.
+        let points: Vec<Point3<f64>> = (0..1001)
+            .map(|i| {
+                let t = f64::from(i);
+                Point3::new(
+                    (t * 0.37) % 6. - 3.,
+                    (t * 0.61) % 6. - 3.,
+                    (t * 0.13) % 6. - 1.,
+                )
+            })
+            .collect();
+        let aabb = Aabb::new(Point3::new(-1., -2., 0.), Point3::new(1.5, 0.5, 2.));
+        let obb = Obb::new(
+            Isometry3::new(Vector3::new(0.5, 0., 1.), Vector3::new(0.3, 0.2, 0.7)),
+            Vector3::new(1., 2., 0.5),
+        );
+        let frustum = Frustum::new(
+            Isometry3::new(Vector3::new(0., 0., 5.), Vector3::new(0.1, 0., 0.)),
+            Perspective::new(-1., 1., -1., 1., 0.5, 4.),
+        );
+        let half_space = HalfSpace::new(Vector3::new(0.3, -1., 0.2), &Point3::new(0., 0.5, 1.));
+        let cullings: [&dyn PointCulling; 4] = [&aabb, &obb, &frustum, &half_space];
+        for culling in cullings.iter() {
+            let mut inside = vec![true; 3];
+            culling.contains_batch(&points, &mut inside);
+            let expected: Vec<bool> = points.iter().map(|p| culling.contains(p)).collect();
+            assert_eq!(inside, expected);
+            assert!(inside.iter().any(|i| *i) && !inside.iter().all(|i| *i));
+        }
+    }
 }
@@ -29,8 +29,12 @@
 //! }
 //! ```
 
+use crate::geometry::Aabb;
+use crate::math::base::{contains_lanes, PointCulling};
+use crate::math::simd::{splat3, F64x4};
 use arrayvec::ArrayVec;
-use nalgebra::{Point3, Unit, Vector3};
+use nalgebra::{Isometry3, Matrix3, Point3, Unit, Vector3};
+use serde::{Deserialize, Serialize};
 
 /// Spatial relation between two objects.
 /// Modeled after the collision crate.
@@ -118,12 +122,7 @@ impl Intersector {
             .collect();
         let mut dedup_axes = Vec::new();
         for ax1 in all_axes {
-            let is_dupe = dedup_axes.iter().any(|ax2: &Unit<Vector3<f64>>| {
-                let d1 = (ax1.as_ref() - ax2.as_ref()).norm_squared();
-                let d2 = (ax1.as_ref() + ax2.as_ref()).norm_squared();
-                d1.min(d2) < std::f64::EPSILON
-            });
-            if !is_dupe {
+            if !contains_parallel(&dedup_axes, &ax1) {
                 dedup_axes.push(ax1);
             }
         }
@@ -154,6 +153,275 @@ impl Intersector {
     }
 }
 
+/// Whether `axes` contains a vector parallel or antiparallel to `axis`.
+fn contains_parallel(axes: &[Unit<Vector3<f64>>], axis: &Unit<Vector3<f64>>) -> bool {
+    axes.iter().any(|other| {
+        let d1 = (axis.as_ref() - other.as_ref()).norm_squared();
+        let d2 = (axis.as_ref() + other.as_ref()).norm_squared();
+        d1.min(d2) < std::f64::EPSILON
+    })
+}
+
+/// The half-space of the points `p` with `normal.dot(p) <= offset`, i.e. the normal points out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HalfSpace {
+    pub normal: Unit<Vector3<f64>>,
+    pub offset: f64,
+}
+
+impl HalfSpace {
+    /// The half-space behind the plane through `point` with the outward `normal`.
+    pub fn new(normal: Vector3<f64>, point: &Point3<f64>) -> Self {
+        let normal = Unit::new_normalize(normal);
+        let offset = normal.dot(&point.coords);
+        HalfSpace { normal, offset }
+    }
+
+    /// The signed distance of `p` to the plane, positive outside.
+    pub fn distance(&self, p: &Point3<f64>) -> f64 {
+        self.normal.dot(&p.coords) - self.offset
+    }
+
+    pub fn contains(&self, p: &Point3<f64>) -> bool {
+        self.distance(p) <= 0.0
+    }
+
+    /// The half-space in the frame that `transform` maps into.
+    pub fn transformed(&self, transform: &Isometry3<f64>) -> Self {
+        let point = Point3::from(self.normal.into_inner() * self.offset);
+        HalfSpace::new(
+            transform.rotation * self.normal.into_inner(),
+            &(transform * point),
+        )
+    }
+
+    /// The signed distances of the corners of `aabb` farthest inside and farthest outside.
+    fn distance_range(&self, aabb: &Aabb) -> (f64, f64) {
+        let (mut inside, mut outside) = (*aabb.min(), *aabb.max());
+        for i in 0..3 {
+            if self.normal[i] < 0.0 {
+                std::mem::swap(&mut inside[i], &mut outside[i]);
+            }
+        }
+        (self.distance(&inside), self.distance(&outside))
+    }
+
+    /// Whether all of `aabb` is inside.
+    pub fn contains_aabb(&self, aabb: &Aabb) -> bool {
+        self.distance_range(aabb).1 <= 0.0
+    }
+
+    /// Whether all of `aabb` is outside.
+    pub fn excludes_aabb(&self, aabb: &Aabb) -> bool {
+        self.distance_range(aabb).0 > 0.0
+    }
+
+    fn tolerance(&self) -> f64 {
+        1e-9 * (1.0 + self.offset.abs())
+    }
+}
+
+impl PointCulling for HalfSpace {
+    fn contains(&self, p: &Point3<f64>) -> bool {
+        self.contains(p)
+    }
+
+    fn contains_batch(&self, points: &[Point3<f64>], inside: &mut Vec<bool>) {
+        let [normal_x, normal_y, normal_z] = splat3(&self.normal);
+        let offset = F64x4::splat(self.offset);
+        let zero = F64x4::splat(0.);
+        contains_lanes(points, inside, |x, y, z| {
+            (normal_x * x + normal_y * y + normal_z * z - offset).cmp_le(zero)
+        })
+    }
+}
+
+/// Collects the corners, edges and face normals of a convex polyhedron into an 'Intersector'.
+/// Edges and face normals that are parallel or antiparallel to earlier ones are skipped, so every
+/// edge and face can be added as it is, without working out which of them share an axis.
+#[derive(Clone, Debug, Default)]
+pub struct IntersectorBuilder {
+    corners: Vec<Point3<f64>>,
+    edges: Vec<Unit<Vector3<f64>>>,
+    face_normals: Vec<Unit<Vector3<f64>>>,
+}
+
+impl IntersectorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn corner(mut self, corner: Point3<f64>) -> Self {
+        self.corners.push(corner);
+        self
+    }
+
+    /// The direction of an edge. Zero vectors, e.g. of collapsed edges, are skipped.
+    pub fn edge(mut self, direction: Vector3<f64>) -> Self {
+        Self::add_axis(&mut self.edges, direction);
+        self
+    }
+
+    /// The normal of a face, in any length and orientation. Zero vectors are skipped.
+    pub fn face_normal(mut self, normal: Vector3<f64>) -> Self {
+        Self::add_axis(&mut self.face_normals, normal);
+        self
+    }
+
+    fn add_axis(axes: &mut Vec<Unit<Vector3<f64>>>, vector: Vector3<f64>) {
+        if let Some(axis) = Unit::try_new(vector, 0.0) {
+            if !contains_parallel(axes, &axis) {
+                axes.push(axis);
+            }
+        }
+    }
+
+    /// Returns None if there are no corners, or more corners, unique edges or unique face
+    /// normals than an 'Intersector' holds. Fewer than 8 corners are padded by repeating the
+    /// first one, which changes none of the projections.
+    pub fn build(self) -> Option<Intersector> {
+        let first_corner = *self.corners.first()?;
+        let mut corners = [first_corner; 8];
+        let mut edges = ArrayVec::new();
+        let mut face_normals = ArrayVec::new();
+        if self.corners.len() > corners.len()
+            || self.edges.len() > edges.capacity()
+            || self.face_normals.len() > face_normals.capacity()
+        {
+            return None;
+        }
+        corners[..self.corners.len()].copy_from_slice(&self.corners);
+        edges.extend(self.edges);
+        face_normals.extend(self.face_normals);
+        Some(Intersector {
+            corners,
+            edges,
+            face_normals,
+        })
+    }
+}
+
+/// The corners of the cube from -1 to 1, in the order of 'ConvexPolyhedron::compute_corners'
+/// of e.g. the 'Frustum': the index bits are x, y, z from the highest to the lowest.
+fn unit_cube_corner(index: usize) -> Point3<f64> {
+    let coordinate = |bit: usize| if index & bit == 0 { -1.0 } else { 1.0 };
+    Point3::new(coordinate(4), coordinate(2), coordinate(1))
+}
+
+impl Intersector {
+    /// The intersector of the image of the cube from -1 to 1 under `transform`, which needs to
+    /// keep it convex and its faces planar, like an affine or a projective transform. The corners
+    /// are in the order of 'ConvexPolyhedron::compute_corners' of e.g. the 'Frustum'.
+    pub fn from_transformed_unit_cube(transform: impl Fn(&Point3<f64>) -> Point3<f64>) -> Self {
+        let corners: Vec<Point3<f64>> = (0..8).map(|i| transform(&unit_cube_corner(i))).collect();
+        let mut builder = corners
+            .iter()
+            .fold(IntersectorBuilder::new(), |builder, corner| {
+                builder.corner(*corner)
+            });
+        for bit in &[4, 2, 1] {
+            // The four edges along this axis, from the corners without the bit to the ones
+            // with it.
+            for from in (0..8).filter(|i| i & bit == 0) {
+                builder = builder.edge(corners[from | bit] - corners[from]);
+            }
+            // The two faces across this axis, from the corners that have the same bit.
+            let others: Vec<usize> = [4, 2, 1].iter().copied().filter(|b| b != bit).collect();
+            for side in &[0, *bit] {
+                let origin = corners[*side];
+                let normal = (corners[side | others[0]] - origin)
+                    .cross(&(corners[side | others[1]] - origin));
+                builder = builder.face_normal(normal);
+            }
+        }
+        // The twelve edges and six faces of a cube always fit.
+        builder.build().unwrap()
+    }
+
+    /// The intersector of the intersection of `half_spaces`. Half-spaces that do not bound it are
+    /// ignored. Returns None if the intersection is empty or unbounded, or if it does not fit into an 'Intersector', e.g. because it has more than 8 corners.
+    pub fn from_half_spaces(half_spaces: &[HalfSpace]) -> Option<Self> {
+        let contains = |p: &Point3<f64>| {
+            half_spaces
+                .iter()
+                .all(|half_space| half_space.distance(p) <= half_space.tolerance())
+        };
+        let on_plane = |half_space: &HalfSpace, p: &Point3<f64>| {
+            half_space.distance(p).abs() <= half_space.tolerance()
+        };
+
+        // The corners are where three of the planes meet within all half-spaces.
+        let mut corners: Vec<Point3<f64>> = Vec::new();
+        for (i, a) in half_spaces.iter().enumerate() {
+            for (j, b) in half_spaces.iter().enumerate().skip(i + 1) {
+                for c in half_spaces.iter().skip(j + 1) {
+                    let normals = Matrix3::from_rows(&[
+                        a.normal.transpose(),
+                        b.normal.transpose(),
+                        c.normal.transpose(),
+                    ]);
+                    let corner = match normals.try_inverse() {
+                        Some(inverse) => {
+                            Point3::from(inverse * Vector3::new(a.offset, b.offset, c.offset))
+                        }
+                        None => continue,
+                    };
+                    let is_new = !corners
+                        .iter()
+                        .any(|other| (other - corner).norm() <= a.tolerance());
+                    if contains(&corner) && is_new {
+                        corners.push(corner);
+                    }
+                }
+            }
+        }
+        if corners.is_empty() {
+            return None;
+        }
+
+        let mut builder = corners
+            .iter()
+            .fold(IntersectorBuilder::new(), |builder, corner| {
+                builder.corner(*corner)
+            });
+        for (i, a) in half_spaces.iter().enumerate() {
+            let on_a: Vec<&Point3<f64>> = corners.iter().filter(|p| on_plane(a, p)).collect();
+            // Planes touching the polyhedron in fewer than three corners do not bound a face.
+            if on_a.len() >= 3 {
+                builder = builder.face_normal(a.normal.into_inner());
+            }
+            for b in half_spaces.iter().skip(i + 1) {
+                let direction = a.normal.cross(&b.normal);
+                if direction.norm_squared() < f64::EPSILON {
+                    continue;
+                }
+                let on_both: Vec<&&Point3<f64>> = on_a.iter().filter(|p| on_plane(b, p)).collect();
+                match on_both.len() {
+                    0 => (),
+                    1 => {
+                        // An edge leaving a single corner along the line runs to infinity, if
+                        // the planes that meet in the corner let it leave at all.
+                        let tight: Vec<&HalfSpace> = half_spaces
+                            .iter()
+                            .filter(|half_space| on_plane(half_space, on_both[0]))
+                            .collect();
+                        let leaves = |direction: Vector3<f64>| {
+                            tight
+                                .iter()
+                                .all(|half_space| half_space.normal.dot(&direction) <= 1e-12)
+                        };
+                        if leaves(direction) || leaves(-direction) {
+                            return None;
+                        }
+                    }
+                    _ => builder = builder.edge(direction),
+                }
+            }
+        }
+        builder.build()
+    }
+}
+
 /// Stores pre-computed separating axes for intersection tests.
 pub struct CachedAxesIntersector {
     pub axes: Vec<Unit<Vector3<f64>>>,
@@ -266,4 +534,121 @@ mod tests {
         assert_eq!(cube_isec_1.intersect(&cube_isec_3), Relation::In);
         assert_eq!(cube_isec_3.intersect(&cube_isec_1), Relation::Cross);
     }
+
+    fn cube(min: f64, max: f64) -> Intersector {
+        let scale = (max - min) / 2.0;
+        Intersector::from_transformed_unit_cube(|p| {
+            Point3::from(p.coords * scale + Vector3::repeat(min + scale))
+        })
+    }
+
+    #[test]
+    fn test_transformed_unit_cube() {
+        let unit_cube = cube(-1.0, 1.0);
+        assert_eq!(unit_cube.corners[0], Point3::new(-1.0, -1.0, -1.0));
+        assert_eq!(unit_cube.corners[1], Point3::new(-1.0, -1.0, 1.0));
+        assert_eq!(unit_cube.corners[4], Point3::new(1.0, -1.0, -1.0));
+        assert_eq!(
+            (unit_cube.edges.len(), unit_cube.face_normals.len()),
+            (3, 3)
+        );
+
+        // A box sheared along x, with the same z axis but tilted sides.
+        let sheared = Intersector::from_transformed_unit_cube(|p| {
+            Point3::new(0.5 * p.x + 0.3 * p.z, 0.5 * p.y, 0.5 * p.z)
+        });
+        assert_eq!((sheared.edges.len(), sheared.face_normals.len()), (3, 3));
+        assert_eq!(unit_cube.intersect(&sheared), Relation::In);
+        assert_eq!(sheared.intersect(&cube(-0.1, 0.1)), Relation::In);
+        assert_eq!(sheared.intersect(&cube(0.55, 0.9)), Relation::Out);
+        assert_eq!(sheared.intersect(&cube(0.3, 0.9)), Relation::Cross);
+    }
+
+    #[test]
+    fn test_half_spaces() {
+        // A triangular prism over the triangle between (0, 0), (1, 0) and (0, 1).
+        let origin = Point3::origin();
+        let top = Point3::new(1.0, 0.0, 1.0);
+        let mut half_spaces = vec![
+            HalfSpace::new(-Vector3::x(), &origin),
+            HalfSpace::new(-Vector3::y(), &origin),
+            HalfSpace::new(Vector3::new(1.0, 1.0, 0.0), &top),
+            HalfSpace::new(-Vector3::z(), &origin),
+            HalfSpace::new(Vector3::z(), &top),
+            // Does not touch the prism.
+            HalfSpace::new(Vector3::new(1.0, 1.0, 1.0), &Point3::new(1.0, 1.0, 1.0)),
+        ];
+        let prism = Intersector::from_half_spaces(&half_spaces).unwrap();
+        // Corners are padded with the first one.
+        assert_eq!(prism.corners[6], prism.corners[0]);
+        assert_eq!(prism.corners[7], prism.corners[0]);
+        // The three sides of the triangle and the vertical edges, and the three sides and the
+        // top and bottom.
+        assert_eq!((prism.edges.len(), prism.face_normals.len()), (4, 4));
+        assert_eq!(cube(-1.0, 2.0).intersect(&prism), Relation::In);
+        assert_eq!(prism.intersect(&cube(0.1, 0.3)), Relation::In);
+        assert_eq!(prism.intersect(&cube(0.6, 0.9)), Relation::Out);
+        assert_eq!(prism.intersect(&cube(0.4, 0.9)), Relation::Cross);
+
+        // Without the top, the prism is unbounded.
+        let without_top: Vec<HalfSpace> = half_spaces
+            .iter()
+            .filter(|half_space| half_space.normal.z <= 0.0)
+            .cloned()
+            .collect();
+        assert!(Intersector::from_half_spaces(&without_top).is_none());
+        // So is a box without a top, whose corners are all at the bottom.
+        let open_box = [
+            HalfSpace::new(-Vector3::x(), &origin),
+            HalfSpace::new(-Vector3::y(), &origin),
+            HalfSpace::new(-Vector3::z(), &origin),
+            HalfSpace::new(Vector3::x(), &top),
+            HalfSpace::new(Vector3::y(), &Point3::new(0.0, 1.0, 0.0)),
+        ];
+        assert!(Intersector::from_half_spaces(&open_box).is_none());
+        // Below its bottom, it is empty.
+        half_spaces.push(HalfSpace::new(Vector3::z(), &Point3::new(0.0, 0.0, -1.0)));
+        assert!(Intersector::from_half_spaces(&half_spaces).is_none());
+    }
+
+    #[test]
+    fn test_half_space_with_aabb() {
+        let half_space = HalfSpace::new(Vector3::new(1.0, 1.0, 0.0), &Point3::new(1.0, 0.0, 0.0));
+        let aabb =
+            |min: f64, max: f64| Aabb::new(Point3::new(min, min, 0.0), Point3::new(max, max, 1.0));
+        assert!(half_space.contains_aabb(&aabb(-1.0, 0.4)));
+        assert!(!half_space.excludes_aabb(&aabb(-1.0, 0.4)));
+        assert!(!half_space.contains_aabb(&aabb(0.0, 0.6)));
+        assert!(!half_space.excludes_aabb(&aabb(0.0, 0.6)));
+        assert!(half_space.excludes_aabb(&aabb(0.6, 1.0)));
+
+        let transform = Isometry3::new(Vector3::new(0.0, 0.0, 5.0), Vector3::z() * 0.3);
+        let transformed = half_space.transformed(&transform);
+        for p in &[Point3::new(0.4, 0.4, 0.0), Point3::new(2.0, -0.5, 3.0)] {
+            assert!(
+                (transformed.distance(&(transform * p)) - half_space.distance(p)).abs() < 1e-12
+            );
+        }
+    }
+
+    #[test]
+    fn test_pyramid_from_half_spaces() {
+        // Four sides meet in the apex, but only neighboring ones in an edge.
+        let apex = Point3::new(0.0, 0.0, 1.0);
+        let pyramid = Intersector::from_half_spaces(&[
+            HalfSpace::new(Vector3::new(1.0, 0.0, 1.0), &apex),
+            HalfSpace::new(Vector3::new(-1.0, 0.0, 1.0), &apex),
+            HalfSpace::new(Vector3::new(0.0, 1.0, 1.0), &apex),
+            HalfSpace::new(Vector3::new(0.0, -1.0, 1.0), &apex),
+            HalfSpace::new(-Vector3::z(), &Point3::origin()),
+        ])
+        .unwrap();
+        assert!(pyramid.corners.contains(&apex));
+        assert!(pyramid.corners.contains(&Point3::new(-1.0, 1.0, 0.0)));
+        // Two directions along the base and four to the apex; the sides and the base.
+        assert_eq!((pyramid.edges.len(), pyramid.face_normals.len()), (6, 5));
+        assert_eq!(pyramid.intersect(&cube(-0.2, 0.2)), Relation::Cross);
+        assert_eq!(pyramid.intersect(&cube(0.1, 0.3)), Relation::In);
+        assert_eq!(pyramid.intersect(&cube(0.6, 0.9)), Relation::Out);
+    }
 }
@@ -0,0 +1,253 @@
+//! Four f64 values that are computed on at once, for testing points against shapes in batches,
+//! see 'contains_lanes'. On x86_64, the operations are SSE2 instructions, which all of its CPUs
+//! have. Elsewhere, they are loops over the lanes, which the compiler may vectorize.
+
+use nalgebra::{Matrix4, Vector3};
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use std::arch::x86_64::*;
+    use std::ops::{Add, BitAnd, Div, Mul, Sub};
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct F64x4(__m128d, __m128d);
+
+    /// Each lane is all ones or all zeros.
+    #[derive(Clone, Copy, Debug)]
+    pub struct Mask4(__m128d, __m128d);
+
+    // SSE2 is part of x86_64, so the intrinsics are always available.
+    macro_rules! binary_op {
+        ($type:ident, $trait:ident, $method:ident, $output:ident, $intrinsic:ident) => {
+            impl $trait for $type {
+                type Output = $output;
+                #[inline]
+                #[allow(unused_unsafe)]
+                fn $method(self, other: Self) -> $output {
+                    unsafe { $output($intrinsic(self.0, other.0), $intrinsic(self.1, other.1)) }
+                }
+            }
+        };
+    }
+
+    binary_op!(F64x4, Add, add, F64x4, _mm_add_pd);
+    binary_op!(F64x4, Sub, sub, F64x4, _mm_sub_pd);
+    binary_op!(F64x4, Mul, mul, F64x4, _mm_mul_pd);
+    binary_op!(F64x4, Div, div, F64x4, _mm_div_pd);
+    binary_op!(Mask4, BitAnd, bitand, Mask4, _mm_and_pd);
+
+    #[allow(unused_unsafe)]
+    impl F64x4 {
+        #[inline]
+        pub fn splat(value: f64) -> Self {
+            unsafe { F64x4(_mm_set1_pd(value), _mm_set1_pd(value)) }
+        }
+
+        #[inline]
+        pub fn from_array(values: [f64; 4]) -> Self {
+            unsafe {
+                F64x4(
+                    _mm_setr_pd(values[0], values[1]),
+                    _mm_setr_pd(values[2], values[3]),
+                )
+            }
+        }
+
+        #[inline]
+        pub fn abs(self) -> Self {
+            // Clears the sign bits.
+            unsafe {
+                let sign = _mm_set1_pd(-0.);
+                F64x4(_mm_andnot_pd(sign, self.0), _mm_andnot_pd(sign, self.1))
+            }
+        }
+
+        #[inline]
+        pub fn cmp_lt(self, other: Self) -> Mask4 {
+            unsafe { Mask4(_mm_cmplt_pd(self.0, other.0), _mm_cmplt_pd(self.1, other.1)) }
+        }
+
+        #[inline]
+        pub fn cmp_le(self, other: Self) -> Mask4 {
+            unsafe { Mask4(_mm_cmple_pd(self.0, other.0), _mm_cmple_pd(self.1, other.1)) }
+        }
+
+        #[inline]
+        pub fn cmp_eq(self, other: Self) -> Mask4 {
+            unsafe { Mask4(_mm_cmpeq_pd(self.0, other.0), _mm_cmpeq_pd(self.1, other.1)) }
+        }
+
+        #[inline]
+        pub fn select(mask: Mask4, if_true: Self, if_false: Self) -> Self {
+            let select = |mask, if_true, if_false| unsafe {
+                _mm_or_pd(_mm_and_pd(mask, if_true), _mm_andnot_pd(mask, if_false))
+            };
+            F64x4(
+                select(mask.0, if_true.0, if_false.0),
+                select(mask.1, if_true.1, if_false.1),
+            )
+        }
+    }
+
+    impl Mask4 {
+        #[inline]
+        #[allow(unused_unsafe)]
+        pub fn to_array(self) -> [bool; 4] {
+            let bits = unsafe { _mm_movemask_pd(self.0) | (_mm_movemask_pd(self.1) << 2) };
+            [bits & 1 != 0, bits & 2 != 0, bits & 4 != 0, bits & 8 != 0]
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod imp {
+    use std::ops::{Add, BitAnd, Div, Mul, Sub};
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct F64x4([f64; 4]);
+
+    #[derive(Clone, Copy, Debug)]
+    pub struct Mask4([bool; 4]);
+
+    fn lanes<T: Copy, F: Fn(usize) -> T>(f: F) -> [T; 4] {
+        [f(0), f(1), f(2), f(3)]
+    }
+
+    macro_rules! binary_op {
+        ($type:ident, $trait:ident, $method:ident, $output:ident, $op:tt) => {
+            impl $trait for $type {
+                type Output = $output;
+                #[inline]
+                fn $method(self, other: Self) -> $output {
+                    $output(lanes(|i| self.0[i] $op other.0[i]))
+                }
+            }
+        };
+    }
+
+    binary_op!(F64x4, Add, add, F64x4, +);
+    binary_op!(F64x4, Sub, sub, F64x4, -);
+    binary_op!(F64x4, Mul, mul, F64x4, *);
+    binary_op!(F64x4, Div, div, F64x4, /);
+    binary_op!(Mask4, BitAnd, bitand, Mask4, &);
+
+    impl F64x4 {
+        #[inline]
+        pub fn splat(value: f64) -> Self {
+            F64x4([value; 4])
+        }
+
+        #[inline]
+        pub fn from_array(values: [f64; 4]) -> Self {
+            F64x4(values)
+        }
+
+        #[inline]
+        pub fn abs(self) -> Self {
+            F64x4(lanes(|i| self.0[i].abs()))
+        }
+
+        #[inline]
+        pub fn cmp_lt(self, other: Self) -> Mask4 {
+            Mask4(lanes(|i| self.0[i] < other.0[i]))
+        }
+
+        #[inline]
+        pub fn cmp_le(self, other: Self) -> Mask4 {
+            Mask4(lanes(|i| self.0[i] <= other.0[i]))
+        }
+
+        #[inline]
+        pub fn cmp_eq(self, other: Self) -> Mask4 {
+            Mask4(lanes(|i| self.0[i] == other.0[i]))
+        }
+
+        #[inline]
+        pub fn select(mask: Mask4, if_true: Self, if_false: Self) -> Self {
+            F64x4(lanes(|i| {
+                if mask.0[i] {
+                    if_true.0[i]
+                } else {
+                    if_false.0[i]
+                }
+            }))
+        }
+    }
+
+    impl Mask4 {
+        #[inline]
+        pub fn to_array(self) -> [bool; 4] {
+            self.0
+        }
+    }
+}
+
+pub use imp::{F64x4, Mask4};
+
+impl F64x4 {
+    /// The lanes where `self > other`.
+    #[inline]
+    pub fn cmp_gt(self, other: Self) -> Mask4 {
+        other.cmp_lt(self)
+    }
+}
+
+/// The lanes of the x, y and z coordinates of `v`.
+pub fn splat3(v: &Vector3<f64>) -> [F64x4; 3] {
+    [F64x4::splat(v.x), F64x4::splat(v.y), F64x4::splat(v.z)]
+}
+
+/// A row of a homogeneous transform, which computes one coordinate of the transformed points.
+#[derive(Clone, Copy, Debug)]
+pub struct AffineRow([F64x4; 4]);
+
+impl AffineRow {
+    pub fn new(transform: &Matrix4<f64>, row: usize) -> Self {
+        AffineRow([
+            F64x4::splat(transform[(row, 0)]),
+            F64x4::splat(transform[(row, 1)]),
+            F64x4::splat(transform[(row, 2)]),
+            F64x4::splat(transform[(row, 3)]),
+        ])
+    }
+
+    #[inline]
+    pub fn apply(&self, x: F64x4, y: F64x4, z: F64x4) -> F64x4 {
+        self.0[0] * x + self.0[1] * y + self.0[2] * z + self.0[3]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lanes_match_scalar_operations() {
+        let a = [1.5, -2., 0., -0.];
+        let b = [1.5, 3., -1., 2.];
+        let (x, y) = (F64x4::from_array(a), F64x4::from_array(b));
+        let lanes = |mask: Mask4| mask.to_array();
+        assert_eq!(lanes(x.cmp_lt(y)), [false, true, false, true]);
+        assert_eq!(lanes(x.cmp_le(y)), [true, true, false, true]);
+        assert_eq!(lanes(x.cmp_gt(y)), [false, false, true, false]);
+        assert_eq!(
+            lanes(x.cmp_eq(F64x4::splat(0.))),
+            [false, false, true, true]
+        );
+        assert_eq!(
+            lanes(x.cmp_le(y) & x.abs().cmp_le(F64x4::splat(1.5))),
+            [true, false, false, true]
+        );
+        let selected = F64x4::select(x.cmp_lt(y), x, (x + y) * y - x / y);
+        let expected = [
+            (a[0] + b[0]) * b[0] - a[0] / b[0],
+            a[1],
+            (a[2] + b[2]) * b[2] - a[2] / b[2],
+            a[3],
+        ];
+        assert_eq!(
+            lanes(selected.cmp_eq(F64x4::from_array(expected))),
+            [true; 4]
+        );
+    }
+}
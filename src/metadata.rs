@@ -0,0 +1,295 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where the points of a point cloud come from and how they were processed, stored in its meta
+//! data so that catalogs do not need to keep track of it separately. The coordinate system is
+//! part of the meta data itself, see 'coordinates::write_coordinate_system'.
+
+use crate::data_provider::{DataProvider, OnDiskDataProvider};
+use crate::errors::*;
+use crate::proto;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A program that produced or changed a point cloud.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessingStep {
+    /// E.g. "build_octree".
+    pub tool: String,
+    pub description: String,
+    pub seconds_since_epoch: i64,
+}
+
+impl ProcessingStep {
+    /// A step that runs at this moment.
+    pub fn now(tool: impl Into<String>, description: impl Into<String>) -> Self {
+        let seconds_since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+        ProcessingStep {
+            tool: tool.into(),
+            description: description.into(),
+            seconds_since_epoch,
+        }
+    }
+}
+
+/// The year, month and day of `days` after 1970-01-01, see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl fmt::Display for ProcessingStep {
+    /// E.g. "2020-10-14 09:30:00 UTC build_octree: Built from scan.ply."
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day) = civil_from_days(self.seconds_since_epoch.div_euclid(86_400));
+        let seconds_of_day = self.seconds_since_epoch.rem_euclid(86_400);
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC {}",
+            year,
+            month,
+            day,
+            seconds_of_day / 3600,
+            seconds_of_day / 60 % 60,
+            seconds_of_day % 60,
+            self.tool
+        )?;
+        if !self.description.is_empty() {
+            write!(f, ": {}", self.description)?;
+        }
+        Ok(())
+    }
+}
+
+/// Provenance of a point cloud. Every part of it is optional.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    /// The day the points were recorded in ISO 8601, e.g. "2020-10-14".
+    pub acquisition_date: Option<String>,
+    pub sensor_model: Option<String>,
+    /// Oldest first.
+    pub processing_history: Vec<ProcessingStep>,
+    /// Anything else a catalog wants to know, e.g. the project or license.
+    pub custom: BTreeMap<String, String>,
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    pub fn to_proto(&self) -> proto::PointCloudMetadata {
+        let mut metadata = proto::PointCloudMetadata::new();
+        if let Some(acquisition_date) = &self.acquisition_date {
+            metadata.set_acquisition_date(acquisition_date.clone());
+        }
+        if let Some(sensor_model) = &self.sensor_model {
+            metadata.set_sensor_model(sensor_model.clone());
+        }
+        for step in &self.processing_history {
+            let mut step_proto = proto::PointCloudMetadata_ProcessingStep::new();
+            step_proto.set_tool(step.tool.clone());
+            step_proto.set_description(step.description.clone());
+            step_proto.set_seconds_since_epoch(step.seconds_since_epoch);
+            metadata.mut_processing_history().push(step_proto);
+        }
+        for (key, value) in &self.custom {
+            let mut key_value = proto::PointCloudMetadata_KeyValue::new();
+            key_value.set_key(key.clone());
+            key_value.set_value(value.clone());
+            metadata.mut_custom().push(key_value);
+        }
+        metadata
+    }
+
+    pub fn from_proto(metadata: &proto::PointCloudMetadata) -> Self {
+        Metadata {
+            acquisition_date: non_empty(metadata.get_acquisition_date()),
+            sensor_model: non_empty(metadata.get_sensor_model()),
+            processing_history: metadata
+                .get_processing_history()
+                .iter()
+                .map(|step| ProcessingStep {
+                    tool: step.get_tool().to_string(),
+                    description: step.get_description().to_string(),
+                    seconds_since_epoch: step.seconds_since_epoch,
+                })
+                .collect(),
+            custom: metadata
+                .get_custom()
+                .iter()
+                .map(|key_value| {
+                    (
+                        key_value.get_key().to_string(),
+                        key_value.get_value().to_string(),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// The metadata of a meta proto, empty if it has none.
+    pub fn from_meta_proto(meta: &proto::Meta) -> Self {
+        Self::from_proto(meta.get_metadata())
+    }
+
+    /// Stores the metadata in `meta`, or removes it from there if it is empty.
+    pub fn set_in_meta_proto(&self, meta: &mut proto::Meta) {
+        if self.is_empty() {
+            meta.clear_metadata();
+        } else {
+            meta.set_metadata(self.to_proto());
+        }
+    }
+
+    /// The metadata of the point clouds that were combined into one, e.g. by a merge: the
+    /// acquisition date, sensor model and custom values that all of them that have one agree on,
+    /// and the processing history of each of them in turn.
+    pub fn combined(all: &[&Metadata]) -> Self {
+        fn agreed<'a>(mut values: impl Iterator<Item = &'a String>) -> Option<String> {
+            let first = values.next()?;
+            if values.all(|value| value == first) {
+                Some(first.clone())
+            } else {
+                None
+            }
+        }
+        let mut custom: BTreeMap<String, String> = BTreeMap::new();
+        let mut conflicting_keys = Vec::new();
+        for (key, value) in all.iter().flat_map(|metadata| &metadata.custom) {
+            match custom.get(key) {
+                Some(other) if other != value => conflicting_keys.push(key.clone()),
+                _ => {
+                    custom.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        for key in &conflicting_keys {
+            custom.remove(key);
+        }
+        Metadata {
+            acquisition_date: agreed(all.iter().filter_map(|m| m.acquisition_date.as_ref())),
+            sensor_model: agreed(all.iter().filter_map(|m| m.sensor_model.as_ref())),
+            processing_history: all
+                .iter()
+                .flat_map(|metadata| metadata.processing_history.iter().cloned())
+                .collect(),
+            custom,
+        }
+    }
+}
+
+/// Reads the metadata of the point cloud in `directory`.
+pub fn read_metadata(directory: impl AsRef<Path>) -> Result<Metadata> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    Ok(Metadata::from_meta_proto(&data_provider.meta_proto()?))
+}
+
+/// Replaces the metadata of the point cloud in `directory`.
+pub fn write_metadata(directory: impl AsRef<Path>, metadata: &Metadata) -> Result<()> {
+    let data_provider = OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    let mut meta = data_provider.meta_proto()?;
+    metadata.set_in_meta_proto(&mut meta);
+    data_provider.write_meta_proto(&meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(tool: &str, seconds_since_epoch: i64) -> ProcessingStep {
+        ProcessingStep {
+            tool: tool.to_string(),
+            description: String::new(),
+            seconds_since_epoch,
+        }
+    }
+
+    #[test]
+    fn test_metadata_survives_proto_and_combines() {
+        let metadata = Metadata {
+            acquisition_date: Some("2020-10-14".to_string()),
+            sensor_model: None,
+            processing_history: vec![ProcessingStep {
+                description: "Built from scan.ply.".to_string(),
+                ..step("build_octree", 1_602_667_800)
+            }],
+            custom: vec![("project".to_string(), "dock".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        assert_eq!(Metadata::from_proto(&metadata.to_proto()), metadata);
+        assert!(Metadata::from_meta_proto(&proto::Meta::new()).is_empty());
+        assert_eq!(
+            metadata.processing_history[0].to_string(),
+            "2020-10-14 09:30:00 UTC build_octree: Built from scan.ply."
+        );
+        assert_eq!(step("a", -1).to_string(), "1969-12-31 23:59:59 UTC a");
+
+        let other = Metadata {
+            acquisition_date: Some("2020-10-15".to_string()),
+            sensor_model: Some("VLP-16".to_string()),
+            processing_history: vec![step("build_octree", 0)],
+            custom: vec![
+                ("project".to_string(), "dock".to_string()),
+                ("license".to_string(), "CC-BY".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let combined = Metadata::combined(&[&metadata, &other]);
+        assert_eq!(combined.acquisition_date, None);
+        assert_eq!(combined.sensor_model, Some("VLP-16".to_string()));
+        assert_eq!(combined.processing_history.len(), 2);
+        assert_eq!(combined.custom, other.custom);
+        let conflicting = Metadata {
+            custom: vec![("project".to_string(), "port".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let combined = Metadata::combined(&[&metadata, &other, &conflicting]);
+        assert_eq!(combined.custom.keys().collect::<Vec<_>>(), vec!["license"]);
+    }
+}
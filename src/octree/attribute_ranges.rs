@@ -0,0 +1,204 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::math::ClosedInterval;
+use crate::proto;
+use crate::{AttributeData, PointsBatch};
+use std::collections::BTreeMap;
+
+/// The ranges of the values of the attributes with a single component of some points, e.g. of
+/// the points in a node. An attribute without a range may have any value.
+#[derive(Debug, Clone, Default)]
+pub struct AttributeRanges {
+    /// None if there are no points.
+    ranges: Option<BTreeMap<String, ClosedInterval<f64>>>,
+}
+
+fn range_of(data: &AttributeData) -> Option<ClosedInterval<f64>> {
+    macro_rules! rhs {
+        ($dtype:ident, $data:ident) => {
+            $data.iter().fold(None, |range: Option<(f64, f64)>, v| {
+                let v = *v as f64;
+                Some(range.map_or((v, v), |(min, max)| (min.min(v), max.max(v))))
+            })
+        };
+    }
+    let range = match data {
+        AttributeData::U8Vec3(_) | AttributeData::F64Vec3(_) => None,
+        _ => match_1d_attr_data!(data, rhs),
+    };
+    // NaN values cannot be compared, so they leave no valid range.
+    range
+        .filter(|(min, max)| !min.is_nan() && !max.is_nan())
+        .map(|(min, max)| ClosedInterval::new(min, max))
+}
+
+impl AttributeRanges {
+    pub fn from_batch(batch: &PointsBatch) -> Self {
+        if batch.position.is_empty() {
+            return AttributeRanges::default();
+        }
+        let ranges = batch
+            .attributes
+            .iter()
+            .filter_map(|(name, data)| range_of(data).map(|range| (name.clone(), range)))
+            .collect();
+        AttributeRanges {
+            ranges: Some(ranges),
+        }
+    }
+
+    /// Nodes written before the ranges were stored have none, so all their attributes may have
+    /// any value.
+    pub fn from_proto(protos: &[proto::AttributeRange]) -> Self {
+        let ranges = protos
+            .iter()
+            .filter(|range| range.min <= range.max)
+            .map(|range| {
+                (
+                    range.name.clone(),
+                    ClosedInterval::new(range.min, range.max),
+                )
+            })
+            .collect();
+        AttributeRanges {
+            ranges: Some(ranges),
+        }
+    }
+
+    pub fn to_proto(&self) -> Vec<proto::AttributeRange> {
+        self.ranges
+            .iter()
+            .flatten()
+            .map(|(name, range)| {
+                let mut proto = proto::AttributeRange::new();
+                proto.set_name(name.clone());
+                proto.set_min(range.lower_bound());
+                proto.set_max(range.upper_bound());
+                proto
+            })
+            .collect()
+    }
+
+    /// The range of `attribute`, None if there are no points or it may have any value.
+    pub fn get(&self, attribute: &str) -> Option<ClosedInterval<f64>> {
+        self.ranges.as_ref()?.get(attribute).copied()
+    }
+
+    /// The attributes that have a range, with their ranges.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, ClosedInterval<f64>)> {
+        self.ranges
+            .iter()
+            .flatten()
+            .map(|(name, range)| (name.as_str(), *range))
+    }
+
+    /// Grows the ranges to also contain the values of `other`.
+    pub fn merge(&mut self, other: &AttributeRanges) {
+        let other_ranges = match &other.ranges {
+            Some(other_ranges) => other_ranges,
+            None => return,
+        };
+        let ranges = match self.ranges.take() {
+            Some(ranges) => ranges,
+            None => {
+                self.ranges = Some(other_ranges.clone());
+                return;
+            }
+        };
+        // An attribute without a range in one of them may have any value in the union.
+        let merged = ranges
+            .into_iter()
+            .filter_map(|(name, range)| {
+                other_ranges.get(&name).map(|other| {
+                    let min = range.lower_bound().min(other.lower_bound());
+                    let max = range.upper_bound().max(other.upper_bound());
+                    (name, ClosedInterval::new(min, max))
+                })
+            })
+            .collect();
+        self.ranges = Some(merged);
+    }
+
+    /// Replaces the range of `attribute` with the one of `data`, which are all of its values.
+    pub fn set(&mut self, attribute: &str, data: &AttributeData) {
+        if let Some(ranges) = &mut self.ranges {
+            match range_of(data) {
+                Some(range) => ranges.insert(attribute.to_string(), range),
+                None => ranges.remove(attribute),
+            };
+        }
+    }
+
+    pub fn add_batch(&mut self, batch: &PointsBatch) {
+        self.merge(&AttributeRanges::from_batch(batch));
+    }
+
+    /// Returns false if no point can have values in all of the `intervals` of its attributes,
+    /// e.g. those of 'PointQuery::attribute_intervals'.
+    pub fn may_match(&self, intervals: &[(&str, ClosedInterval<f64>)]) -> bool {
+        let ranges = match &self.ranges {
+            Some(ranges) => ranges,
+            None => return false,
+        };
+        intervals
+            .iter()
+            .all(|(name, interval)| match ranges.get(*name) {
+                Some(range) => range.intersects(*interval),
+                None => true,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point3, Vector3};
+
+    fn batch(intensity: Vec<f32>) -> PointsBatch {
+        PointsBatch {
+            position: vec![Point3::origin(); intensity.len()],
+            attributes: vec![
+                (
+                    "color".to_string(),
+                    AttributeData::U8Vec3(vec![Vector3::zeros(); intensity.len()]),
+                ),
+                ("intensity".to_string(), AttributeData::F32(intensity)),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn test_ranges_rule_out_filters() {
+        let filter = |min, max| [("intensity", ClosedInterval::new(min, max))];
+        let mut ranges = AttributeRanges::from_batch(&batch(vec![3., 1., 2.]));
+        assert!(ranges.get("color").is_none());
+        assert!(ranges.may_match(&filter(0., 1.)));
+        assert!(!ranges.may_match(&filter(3.5, 10.)));
+        ranges.add_batch(&batch(vec![5.]));
+        ranges.add_batch(&batch(Vec::new()));
+        assert!(ranges.may_match(&filter(3.5, 10.)));
+
+        let ranges = AttributeRanges::from_proto(&ranges.to_proto());
+        assert_eq!(ranges.get("intensity").unwrap().lower_bound(), 1.);
+        assert_eq!(ranges.get("intensity").unwrap().upper_bound(), 5.);
+        assert!(!AttributeRanges::default().may_match(&[]));
+        // Without a stored range, any value is possible.
+        let mut unknown = AttributeRanges::from_proto(&[]);
+        unknown.merge(&ranges);
+        assert!(unknown.may_match(&filter(100., 200.)));
+    }
+}
@@ -0,0 +1,359 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The progress of building an octree, so that an interrupted build can be resumed. Splitting
+//! only ever writes nodes from the points of their parent, so it can be repeated for any node
+//! whose parent's points are still there. Subsampling and removing outliers rewrite nodes from
+//! their own points, so the new files are written into a separate directory and only moved into
+//! place once the checkpoint knows about them.
+
+use crate::attribute_extension;
+use crate::coordinates::CoordinateSystem;
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::geometry::Aabb;
+use crate::labels::LabelDictionary;
+use crate::metadata::Metadata;
+use crate::octree::generation::{remove_node_files, NodeSummary};
+use crate::octree::{to_meta_proto, AttributeRanges, NodeId, OctreeMeta};
+use crate::proto;
+use fnv::{FnvHashMap, FnvHashSet};
+use protobuf::Message;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub(super) use crate::proto::OctreeBuildCheckpoint_Stage as Stage;
+
+pub(super) const CHECKPOINT_FILENAME: &str = "build_checkpoint.pb";
+/// The directory next to the checkpoint for the new files of nodes that are not in place yet.
+const PENDING_DIRECTORY: &str = "build_checkpoint";
+/// While splitting, the checkpoint is saved at most this often, since it lists all nodes.
+const SPLITTING_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+struct State {
+    stage: Stage,
+    nodes_to_split: FnvHashSet<NodeId>,
+    leaf_nodes: Vec<NodeId>,
+    nodes_to_subsample: Vec<NodeId>,
+    finished_nodes: FnvHashMap<NodeId, NodeSummary>,
+    /// The nodes that have been split since the last save. Their files are removed once the
+    /// checkpoint is saved, since they would be needed to split them again.
+    split_nodes: Vec<NodeId>,
+    last_save: Option<Instant>,
+}
+
+pub(super) struct Checkpoint {
+    octree_data_provider: OnDiskDataProvider,
+    meta: proto::Meta,
+    attributes: Vec<String>,
+    state: Mutex<State>,
+}
+
+fn node_summary_to_proto(node_id: &NodeId, summary: &NodeSummary) -> proto::OctreeNode {
+    let mut proto = proto::OctreeNode::new();
+    *proto.mut_id() = node_id.to_proto();
+    proto.set_num_points(summary.num_points);
+    proto.set_attribute_ranges(summary.attribute_ranges.to_proto().into());
+    proto.set_version(summary.version);
+    proto
+}
+
+fn node_summary_from_proto(proto: &proto::OctreeNode) -> (NodeId, NodeSummary) {
+    let summary = NodeSummary {
+        num_points: proto.num_points,
+        attribute_ranges: AttributeRanges::from_proto(proto.get_attribute_ranges()),
+        version: proto.version,
+    };
+    (NodeId::from_proto(proto.get_id()), summary)
+}
+
+impl Checkpoint {
+    /// Starts the checkpoint of a new build in `directory`, replacing the one of a previous build.
+    /// Nothing is saved before the root has been split, since that reads the input.
+    pub fn new(directory: &Path, octree_meta: &OctreeMeta, attributes: &[&str]) -> Result<Self> {
+        let checkpoint = Checkpoint {
+            octree_data_provider: OnDiskDataProvider {
+                directory: directory.to_path_buf(),
+            },
+            meta: to_meta_proto(octree_meta, Vec::new()),
+            attributes: attributes.iter().map(|name| name.to_string()).collect(),
+            state: Mutex::new(State {
+                stage: Stage::SPLITTING,
+                nodes_to_split: FnvHashSet::default(),
+                leaf_nodes: Vec::new(),
+                nodes_to_subsample: Vec::new(),
+                finished_nodes: FnvHashMap::default(),
+                split_nodes: Vec::new(),
+                last_save: None,
+            }),
+        };
+        checkpoint.remove()?;
+        fs::create_dir_all(checkpoint.pending_directory())?;
+        Ok(checkpoint)
+    }
+
+    /// Loads the checkpoint of the interrupted build in `directory`, None if there is none.
+    pub fn load(directory: &Path) -> Result<Option<Self>> {
+        let path = directory.join(CHECKPOINT_FILENAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut reader = BufReader::new(File::open(&path)?);
+        let proto = protobuf::parse_from_reader::<proto::OctreeBuildCheckpoint>(&mut reader)
+            .chain_err(|| format!("Could not parse {}", path.display()))?;
+        let checkpoint = Checkpoint {
+            octree_data_provider: OnDiskDataProvider {
+                directory: directory.to_path_buf(),
+            },
+            meta: proto.get_meta().clone(),
+            attributes: proto.get_attributes().to_vec(),
+            state: Mutex::new(State {
+                stage: proto.stage,
+                nodes_to_split: proto
+                    .get_nodes_to_split()
+                    .iter()
+                    .map(NodeId::from_proto)
+                    .collect(),
+                leaf_nodes: proto
+                    .get_leaf_nodes()
+                    .iter()
+                    .map(NodeId::from_proto)
+                    .collect(),
+                nodes_to_subsample: proto
+                    .get_nodes_to_subsample()
+                    .iter()
+                    .map(NodeId::from_proto)
+                    .collect(),
+                finished_nodes: proto
+                    .get_finished_nodes()
+                    .iter()
+                    .map(node_summary_from_proto)
+                    .collect(),
+                split_nodes: Vec::new(),
+                last_save: None,
+            }),
+        };
+        fs::create_dir_all(checkpoint.pending_directory())?;
+        let nodes_to_commit: Vec<(NodeId, NodeSummary)> = proto
+            .get_nodes_to_commit()
+            .iter()
+            .map(node_summary_from_proto)
+            .collect();
+        checkpoint.commit(&nodes_to_commit)?;
+        Ok(Some(checkpoint))
+    }
+
+    /// The meta of the octree that is being built.
+    pub fn octree_meta(&self) -> Result<OctreeMeta> {
+        let mut octree_meta = OctreeMeta::new_with_standard_attributes(
+            self.meta.get_octree().resolution,
+            Aabb::from(self.meta.get_bounding_box()),
+        );
+        octree_meta.coordinate_system = CoordinateSystem::from_meta_proto(&self.meta)?;
+        octree_meta.metadata = Metadata::from_meta_proto(&self.meta);
+        if let Some(label_dictionary) = LabelDictionary::from_meta_proto(&self.meta)? {
+            octree_meta.set_label_dictionary(label_dictionary);
+        }
+        Ok(octree_meta)
+    }
+
+    pub fn attributes(&self) -> Vec<&str> {
+        self.attributes.iter().map(String::as_str).collect()
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.state.lock().unwrap().stage
+    }
+
+    pub fn nodes_to_split(&self) -> Vec<NodeId> {
+        let state = self.state.lock().unwrap();
+        state.nodes_to_split.iter().cloned().collect()
+    }
+
+    pub fn leaf_nodes(&self) -> Vec<NodeId> {
+        self.state.lock().unwrap().leaf_nodes.clone()
+    }
+
+    /// The nodes left to subsample and the summaries of the nodes written so far.
+    pub fn subsampling(&self) -> (Vec<NodeId>, FnvHashMap<NodeId, NodeSummary>) {
+        let state = self.state.lock().unwrap();
+        (
+            state.nodes_to_subsample.clone(),
+            state.finished_nodes.clone(),
+        )
+    }
+
+    /// Where the new files of `node_id` are written before they are moved into place.
+    pub fn pending_stem(&self, node_id: &NodeId) -> PathBuf {
+        self.pending_directory().join(node_id.to_string())
+    }
+
+    /// Records that `node_id` has been split into `leaf_nodes` and `split_nodes`, which still
+    /// need to be split themselves.
+    pub fn split_finished(
+        &self,
+        octree_meta: &OctreeMeta,
+        node_id: &NodeId,
+        leaf_nodes: &[NodeId],
+        split_nodes: &[NodeId],
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.nodes_to_split.remove(node_id);
+        state.nodes_to_split.extend(split_nodes.iter().cloned());
+        state.leaf_nodes.extend_from_slice(leaf_nodes);
+        state.split_nodes.push(*node_id);
+        // The first split reads the input, so it is always saved.
+        let save = state.last_save.map_or(true, |last_save| {
+            last_save.elapsed() >= SPLITTING_SAVE_INTERVAL
+        });
+        if save {
+            self.save_split_nodes(octree_meta, &mut state)?;
+        }
+        Ok(())
+    }
+
+    /// Saves that all nodes have been split and returns the leaves.
+    pub fn finish_splitting(&self, octree_meta: &OctreeMeta) -> Result<Vec<NodeId>> {
+        let mut state = self.state.lock().unwrap();
+        state.stage = Stage::REMOVING_OUTLIERS;
+        self.save_split_nodes(octree_meta, &mut state)?;
+        Ok(state.leaf_nodes.clone())
+    }
+
+    /// Moves the leaves that outliers have been removed from into place. The `nodes` only need
+    /// their number of points.
+    pub fn finish_removing_outliers(&self, nodes: &[(NodeId, NodeSummary)]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.stage = Stage::SUBSAMPLING;
+        state.nodes_to_subsample = state.leaf_nodes.clone();
+        self.save_and_commit(&mut state, nodes)
+    }
+
+    /// Moves the subsampled children of a level into place and saves what is left to do.
+    pub fn finish_level(
+        &self,
+        nodes_to_subsample: &[NodeId],
+        finished_nodes: &FnvHashMap<NodeId, NodeSummary>,
+        children: &[NodeId],
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.nodes_to_subsample = nodes_to_subsample.to_vec();
+        state.finished_nodes = finished_nodes.clone();
+        let nodes: Vec<(NodeId, NodeSummary)> = children
+            .iter()
+            .filter_map(|id| finished_nodes.get(id).map(|summary| (*id, summary.clone())))
+            .collect();
+        self.save_and_commit(&mut state, &nodes)
+    }
+
+    /// Removes the checkpoint once the build is done.
+    pub fn remove(&self) -> Result<()> {
+        let path = self
+            .octree_data_provider
+            .directory
+            .join(CHECKPOINT_FILENAME);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        if self.pending_directory().exists() {
+            fs::remove_dir_all(self.pending_directory())?;
+        }
+        Ok(())
+    }
+
+    fn pending_directory(&self) -> PathBuf {
+        self.octree_data_provider.directory.join(PENDING_DIRECTORY)
+    }
+
+    fn save_split_nodes(&self, octree_meta: &OctreeMeta, state: &mut State) -> Result<()> {
+        self.save(state, &[])?;
+        for node_id in state.split_nodes.drain(..) {
+            remove_node_files(&self.octree_data_provider, octree_meta, &node_id);
+        }
+        Ok(())
+    }
+
+    fn save_and_commit(&self, state: &mut State, nodes: &[(NodeId, NodeSummary)]) -> Result<()> {
+        self.save(state, nodes)?;
+        self.commit(nodes)?;
+        self.save(state, &[])
+    }
+
+    /// Moves the pending files of `nodes` into place. Nodes without points have none, so their
+    /// previous files are removed. Committing again after an interruption is harmless.
+    fn commit(&self, nodes: &[(NodeId, NodeSummary)]) -> Result<()> {
+        let extensions: Vec<&str> = std::iter::once("position")
+            .chain(self.attributes.iter().map(String::as_str))
+            .map(attribute_extension)
+            .collect();
+        for (node_id, summary) in nodes {
+            let stem = self.octree_data_provider.stem(&node_id.to_string());
+            let pending_stem = self.pending_stem(node_id);
+            for extension in &extensions {
+                let path = stem.with_extension(extension);
+                let pending_path = pending_stem.with_extension(extension);
+                if pending_path.exists() {
+                    fs::rename(&pending_path, &path)?;
+                } else if summary.num_points == 0 && path.exists() {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn save(&self, state: &mut State, nodes_to_commit: &[(NodeId, NodeSummary)]) -> Result<()> {
+        let mut proto = proto::OctreeBuildCheckpoint::new();
+        proto.set_stage(state.stage);
+        proto.set_meta(self.meta.clone());
+        proto.set_attributes(self.attributes.clone().into());
+        let node_ids = |ids: &mut dyn Iterator<Item = &NodeId>| {
+            ids.map(NodeId::to_proto)
+                .collect::<Vec<proto::NodeId>>()
+                .into()
+        };
+        proto.set_nodes_to_split(node_ids(&mut state.nodes_to_split.iter()));
+        proto.set_leaf_nodes(node_ids(&mut state.leaf_nodes.iter()));
+        proto.set_nodes_to_subsample(node_ids(&mut state.nodes_to_subsample.iter()));
+        let summaries = |nodes: &mut dyn Iterator<Item = (&NodeId, &NodeSummary)>| {
+            nodes
+                .map(|(id, summary)| node_summary_to_proto(id, summary))
+                .collect::<Vec<proto::OctreeNode>>()
+                .into()
+        };
+        proto.set_finished_nodes(summaries(&mut state.finished_nodes.iter()));
+        proto.set_nodes_to_commit(summaries(
+            &mut nodes_to_commit.iter().map(|(id, summary)| (id, summary)),
+        ));
+
+        let path = self
+            .octree_data_provider
+            .directory
+            .join(CHECKPOINT_FILENAME);
+        let tmp_path = path.with_extension("pb.tmp");
+        {
+            let mut buf_writer = BufWriter::new(File::create(&tmp_path)?);
+            proto
+                .write_to_writer(&mut buf_writer)
+                .chain_err(|| format!("Could not write {}", CHECKPOINT_FILENAME))?;
+            buf_writer.flush()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        state.last_save = Some(Instant::now());
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::*;
+use crate::octree::NodeData;
+use std::str::FromStr;
+
+/// How node data is compressed for the transfer to a viewer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeCompression {
+    /// The node data is sent as it is stored.
+    None,
+    /// The points are sorted along a Morton curve, so that consecutive points are close to each
+    /// other, positions and colors are delta coded and the result is deflated.
+    Delta,
+}
+
+impl FromStr for NodeCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(NodeCompression::None),
+            "delta" => Ok(NodeCompression::Delta),
+            _ => Err(ErrorKind::InvalidInput(format!("Unknown node compression '{}'.", s)).into()),
+        }
+    }
+}
+
+fn read_value(bytes: &[u8], bytes_per_value: usize) -> u16 {
+    match bytes_per_value {
+        1 => u16::from(bytes[0]),
+        _ => u16::from_le_bytes([bytes[0], bytes[1]]),
+    }
+}
+
+fn write_value(bytes: &mut [u8], bytes_per_value: usize, value: u16) {
+    match bytes_per_value {
+        1 => bytes[0] = value as u8,
+        _ => bytes[..2].copy_from_slice(&value.to_le_bytes()),
+    }
+}
+
+/// Spreads the 16 bits of `v` out so that there are two zero bits between each of them.
+fn spread_bits(v: u16) -> u64 {
+    let mut x = u64::from(v);
+    x = (x | (x << 16)) & 0x001f_0000_ff00_00ff;
+    x = (x | (x << 8)) & 0x100f_00f0_0f00_f00f;
+    x = (x | (x << 4)) & 0x10c3_0c30_c30c_30c3;
+    x = (x | (x << 2)) & 0x1249_2492_4924_9249;
+    x
+}
+
+fn morton_code(x: u16, y: u16, z: u16) -> u64 {
+    spread_bits(x) | (spread_bits(y) << 1) | (spread_bits(z) << 2)
+}
+
+fn permute(data: &[u8], bytes_per_point: usize, order: &[usize]) -> Vec<u8> {
+    let mut permuted = Vec::with_capacity(data.len());
+    for i in order {
+        permuted.extend_from_slice(&data[i * bytes_per_point..(i + 1) * bytes_per_point]);
+    }
+    permuted
+}
+
+/// Replaces every value by its difference to the same component of the previous point. The
+/// differences wrap around, so this is lossless for any input.
+fn delta_encode_components(data: &mut [u8], bytes_per_value: usize, num_components: usize) {
+    let stride = bytes_per_value * num_components;
+    let mut previous = vec![0u16; num_components];
+    for point in data.chunks_exact_mut(stride) {
+        for (component, previous) in previous.iter_mut().enumerate() {
+            let bytes = &mut point[component * bytes_per_value..];
+            let value = read_value(bytes, bytes_per_value);
+            write_value(bytes, bytes_per_value, value.wrapping_sub(*previous));
+            *previous = value;
+        }
+    }
+}
+
+fn delta_decode_components(data: &mut [u8], bytes_per_value: usize, num_components: usize) {
+    let stride = bytes_per_value * num_components;
+    let mut previous = vec![0u16; num_components];
+    for point in data.chunks_exact_mut(stride) {
+        for (component, previous) in previous.iter_mut().enumerate() {
+            let bytes = &mut point[component * bytes_per_value..];
+            let value = read_value(bytes, bytes_per_value).wrapping_add(*previous);
+            let value = if bytes_per_value == 1 {
+                value & 0xff
+            } else {
+                value
+            };
+            write_value(bytes, bytes_per_value, value);
+            *previous = value;
+        }
+    }
+}
+
+impl NodeData {
+    /// Reorders the points along a Morton curve and delta codes positions and colors in place.
+    /// The additional attributes are reordered along with the points. Only positions quantized to
+    /// 8 or 16 bits are handled, floating point positions are neither reordered nor delta coded,
+    /// their colors are delta coded nevertheless.
+    pub fn delta_encode(&mut self) {
+        let num_points = self.meta.num_points as usize;
+        let bytes_per_coordinate = self.meta.position_encoding.bytes_per_coordinate();
+        if bytes_per_coordinate <= 2 {
+            let bytes_per_point = 3 * bytes_per_coordinate;
+            let mut order: Vec<usize> = (0..num_points).collect();
+            order.sort_by_key(|i| {
+                let p = &self.position[i * bytes_per_point..];
+                morton_code(
+                    read_value(p, bytes_per_coordinate),
+                    read_value(&p[bytes_per_coordinate..], bytes_per_coordinate),
+                    read_value(&p[2 * bytes_per_coordinate..], bytes_per_coordinate),
+                )
+            });
+            self.position = permute(&self.position, bytes_per_point, &order);
+            self.color = permute(&self.color, 3, &order);
+            for attribute in &mut self.attributes {
+                attribute.data = permute(&attribute.data, attribute.data_type.size_of(), &order);
+            }
+            delta_encode_components(&mut self.position, bytes_per_coordinate, 3);
+        }
+        delta_encode_components(&mut self.color, 1, 3);
+    }
+
+    /// Undoes 'delta_encode', except for the order of the points.
+    pub fn delta_decode(&mut self) {
+        let bytes_per_coordinate = self.meta.position_encoding.bytes_per_coordinate();
+        if bytes_per_coordinate <= 2 {
+            delta_decode_components(&mut self.position, bytes_per_coordinate, 3);
+        }
+        delta_decode_components(&mut self.color, 1, 3);
+    }
+}
+
+/// Compresses `data` to the zlib format, which browsers can decompress natively.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec_zlib(data, 6)
+}
+
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    miniz_oxide::inflate::decompress_to_vec_zlib(data).map_err(|status| {
+        ErrorKind::InvalidInput(format!("Could not inflate data: {:?}", status)).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Cube;
+    use crate::octree::{AttributeRanges, NodeMeta};
+    use crate::read_write::PositionEncoding;
+    use nalgebra::Point3;
+
+    fn node_data(num_points: usize) -> NodeData {
+        let mut position = Vec::new();
+        let mut color = Vec::new();
+        for i in 0..num_points {
+            // A plane, sampled in an order that is not spatially coherent.
+            let x = ((i * 7919) % 256) as u16 * 256;
+            let y = ((i * 104_729) % 256) as u16 * 256;
+            for v in &[x, y, 1000] {
+                position.extend_from_slice(&v.to_le_bytes());
+            }
+            color.extend_from_slice(&[(x >> 8) as u8, (y >> 8) as u8, 128]);
+        }
+        NodeData {
+            meta: NodeMeta {
+                num_points: num_points as i64,
+                position_encoding: PositionEncoding::Uint16,
+                bounding_cube: Cube::new(Point3::origin(), 1.),
+                attribute_ranges: AttributeRanges::default(),
+                version: 0,
+            },
+            position,
+            color,
+            attributes: Vec::new(),
+        }
+    }
+
+    fn points(node_data: &NodeData) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut points: Vec<_> = node_data
+            .position
+            .chunks(6)
+            .zip(node_data.color.chunks(3))
+            .map(|(p, c)| (p.to_vec(), c.to_vec()))
+            .collect();
+        points.sort();
+        points
+    }
+
+    #[test]
+    fn test_morton_code() {
+        assert_eq!(morton_code(1, 0, 0), 0b001);
+        assert_eq!(morton_code(0, 1, 0), 0b010);
+        assert_eq!(morton_code(0, 0, 1), 0b100);
+        assert_eq!(morton_code(3, 0, 1), 0b001_101);
+        assert_eq!(morton_code(0xffff, 0xffff, 0xffff), (1 << 48) - 1);
+    }
+
+    #[test]
+    fn test_delta_coding_keeps_points() {
+        let original = node_data(1000);
+        let mut coded = node_data(1000);
+        coded.delta_encode();
+        assert_ne!(original.position, coded.position);
+        coded.delta_decode();
+        assert_eq!(points(&original), points(&coded));
+    }
+
+    #[test]
+    fn test_delta_coding_compresses_better() {
+        let original = node_data(10_000);
+        let mut coded = node_data(10_000);
+        coded.delta_encode();
+        let raw = [&original.position[..], &original.color[..]].concat();
+        let delta = [&coded.position[..], &coded.color[..]].concat();
+        assert!(deflate(&delta).len() < deflate(&raw).len());
+        assert_eq!(inflate(&deflate(&delta)).unwrap(), delta);
+    }
+}
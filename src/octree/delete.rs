@@ -0,0 +1,225 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::attribute_extension;
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::iterator::{PointCloud, PointLocation};
+use crate::octree::generation::{write_meta, NodeSummary};
+use crate::octree::update::read_all_points;
+use crate::octree::{subtree_attribute_ranges, ChildIndex, NodeId, Octree};
+use crate::read_write::{NodeWriter, OpenMode, RawNodeWriter};
+use crate::{AttributeDataType, PointCloudMeta};
+use fnv::FnvHashMap;
+use std::collections::HashMap;
+use std::path::Path;
+
+impl Octree {
+    /// The attributes of the meta that the node has files for. Not every node has every attribute
+    /// of the meta on disk, so we only rewrite the ones that are there.
+    pub fn attributes_on_disk(
+        &self,
+        octree_data_provider: &OnDiskDataProvider,
+        node_id: &NodeId,
+    ) -> HashMap<String, AttributeDataType> {
+        let stem = octree_data_provider.stem(&node_id.to_string());
+        self.meta
+            .attribute_data_types()
+            .iter()
+            .filter(|(name, _)| stem.with_extension(attribute_extension(name)).exists())
+            .map(|(name, data_type)| (name.clone(), *data_type))
+            .collect()
+    }
+
+    pub(super) fn children(&self, node_id: &NodeId) -> Vec<NodeId> {
+        (0..8)
+            .map(|i| node_id.get_child_id(ChildIndex::from_u8(i)))
+            .filter(|id| self.nodes.contains_key(id))
+            .collect()
+    }
+
+    /// Removes the points inside `location` from a single node. Returns the number of points
+    /// left in the node. Its attribute ranges stay valid, since they contain the remaining values.
+    fn delete_in_node(
+        &self,
+        octree_data_provider: &OnDiskDataProvider,
+        location: &PointLocation,
+        node_id: &NodeId,
+    ) -> Result<i64> {
+        let attribute_data_types = self.attributes_on_disk(octree_data_provider, node_id);
+        let mut batch = match read_all_points(
+            octree_data_provider,
+            &self.meta,
+            &attribute_data_types,
+            node_id,
+        ) {
+            Ok(batch) => batch,
+            Err(Error(ErrorKind::NodeNotFound, _)) => return Ok(0),
+            Err(err) => return Err(err),
+        };
+        let culling = location.get_point_culling();
+        let mut keep = Vec::new();
+        culling.contains_batch(&batch.position, &mut keep);
+        keep.iter_mut().for_each(|k| *k = !*k);
+        if keep.iter().all(|k| *k) {
+            return Ok(batch.position.len() as i64);
+        }
+        batch.retain(&keep);
+        // Writing an empty batch removes the node's files.
+        let mut writer = RawNodeWriter::from_data_provider(
+            octree_data_provider,
+            &self.meta,
+            node_id,
+            OpenMode::Truncate,
+        );
+        writer.write(&batch)?;
+        Ok(writer.num_written())
+    }
+
+    /// After deleting, a node can be left with far fewer points than its children, which shows
+    /// up as a hole at coarse levels of detail. To restore the ratio that 'build_octree' creates,
+    /// i.e. one point in the parent for every seven in the children, we move evenly spaced points
+    /// from the children up into the parent.
+    fn pull_up_from_children(
+        &mut self,
+        octree_data_provider: &OnDiskDataProvider,
+        node_id: &NodeId,
+    ) -> Result<()> {
+        let children = self.children(node_id);
+        let num_in_children: i64 = children.iter().map(|id| self.nodes[id].num_points).sum();
+        let num_in_parent = self.nodes[node_id].num_points;
+        if num_in_children <= 7 * num_in_parent {
+            return Ok(());
+        }
+        let num_to_move = (num_in_children - 7 * num_in_parent) / 8;
+
+        let mut parent_writer = RawNodeWriter::from_data_provider(
+            octree_data_provider,
+            &self.meta,
+            node_id,
+            OpenMode::Append,
+        );
+        for child_id in children {
+            let num_points = self.nodes[&child_id].num_points;
+            let num_to_move_from_child = num_to_move * num_points / num_in_children;
+            if num_to_move_from_child == 0 {
+                continue;
+            }
+            let step = (num_points / num_to_move_from_child) as usize;
+            let attribute_data_types = self.attributes_on_disk(octree_data_provider, &child_id);
+            let batch = read_all_points(
+                octree_data_provider,
+                &self.meta,
+                &attribute_data_types,
+                &child_id,
+            )?;
+            let (keep_parent, keep_child): (Vec<bool>, Vec<bool>) = (0..batch.position.len())
+                .map(|i| {
+                    let in_parent = i % step == 0 && i / step < num_to_move_from_child as usize;
+                    (in_parent, !in_parent)
+                })
+                .unzip();
+            let mut parent_batch = batch.clone();
+            parent_batch.retain(&keep_parent);
+            let mut child_batch = batch;
+            child_batch.retain(&keep_child);
+
+            let mut child_writer = RawNodeWriter::from_data_provider(
+                octree_data_provider,
+                &self.meta,
+                &child_id,
+                OpenMode::Truncate,
+            );
+            parent_writer.write(&parent_batch)?;
+            child_writer.write(&child_batch)?;
+            self.nodes.get_mut(&child_id).unwrap().num_points = child_writer.num_written();
+            self.nodes
+                .get_mut(node_id)
+                .unwrap()
+                .attribute_ranges
+                .add_batch(&parent_batch);
+        }
+        self.nodes.get_mut(node_id).unwrap().num_points = parent_writer.num_written();
+        Ok(())
+    }
+
+    pub(super) fn delete_in_impl(&mut self, location: &PointLocation) -> Result<usize> {
+        let directory = self
+            .data_provider
+            .directory()
+            .ok_or_else(|| {
+                ErrorKind::InvalidInput(
+                    "Points can only be deleted from octrees on disk.".to_string(),
+                )
+            })?
+            .to_path_buf();
+        let octree_data_provider = &OnDiskDataProvider {
+            directory: directory.clone(),
+        };
+
+        let mut touched = self.nodes_in_location(location);
+        // Children are handled before their parents.
+        touched.sort_by_key(|id| std::cmp::Reverse(id.level()));
+
+        let mut num_deleted = 0;
+        for node_id in &touched {
+            let num_points = self.delete_in_node(octree_data_provider, location, node_id)?;
+            let node_meta = self.nodes.get_mut(node_id).unwrap();
+            num_deleted += (node_meta.num_points - num_points) as usize;
+            node_meta.num_points = num_points;
+        }
+
+        for node_id in &touched {
+            self.pull_up_from_children(octree_data_provider, node_id)?;
+        }
+
+        // The root always stays, since traversal starts there.
+        for node_id in &touched {
+            if node_id.level() > 0
+                && self.nodes[node_id].num_points == 0
+                && self.children(node_id).is_empty()
+            {
+                self.nodes.remove(node_id);
+            }
+        }
+
+        self.write_changed_meta(&directory)?;
+        Ok(num_deleted)
+    }
+
+    /// Writes the meta file after nodes were changed on disk and drops what was derived from the
+    /// previous nodes.
+    pub(super) fn write_changed_meta(&mut self, directory: &Path) -> Result<()> {
+        let nodes: FnvHashMap<NodeId, NodeSummary> = self
+            .nodes
+            .iter()
+            .map(|(id, node_meta)| {
+                let summary = NodeSummary {
+                    num_points: node_meta.num_points,
+                    attribute_ranges: node_meta.attribute_ranges.clone(),
+                    version: node_meta.version,
+                };
+                (*id, summary)
+            })
+            .collect();
+        write_meta(directory, &self.meta, &nodes)?;
+        self.subtree_attribute_ranges = subtree_attribute_ranges(&self.nodes);
+        // The cached nodes are outdated now.
+        if let Some(node_cache) = &self.node_cache {
+            self.cache_owner = node_cache.new_owner();
+        }
+        self.node_statistics.lock().unwrap().clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,536 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exports octrees for third-party web viewers, as Cesium 3D Tiles or in the format of Potree 2.
+//! The nodes of the octree become the tiles or nodes of the export, so its levels of detail carry
+//! over without resampling. Like in the octree, the points of a node add to those of its
+//! ancestors. For PCL-based tools, all points can also be exported into a single PCD file.
+
+use crate::coordinates::{CoordinateSystem, Reprojection};
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::geometry::Aabb;
+use crate::octree::update::read_all_points;
+use crate::octree::{NodeId, Octree};
+use crate::read_write::{Encoding, NodeWriter, OpenMode, PcdNodeWriter};
+use crate::utils::create_progress_bar;
+use crate::{AttributeDataType, PointsBatch};
+use byteorder::{LittleEndian, WriteBytesExt};
+use nalgebra::{Point3, Vector3};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// How many nodes are held in memory at once.
+const NUM_NODES_PER_CHUNK: usize = 64;
+
+/// The size of a node in 'hierarchy.bin' of Potree 2.
+const POTREE_NODE_SIZE: usize = 22;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A tileset of pnts tiles. Points are reprojected to ECEF if the coordinate system of the
+    /// octree is known.
+    Tiles3d,
+    /// 'metadata.json', 'hierarchy.bin' and 'octree.bin' of Potree 2, in the coordinates of the
+    /// octree.
+    Potree,
+    /// A binary 'points.pcd' with all points, in the coordinates of the octree.
+    Pcd,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "3d-tiles" => Ok(ExportFormat::Tiles3d),
+            "potree" => Ok(ExportFormat::Potree),
+            "pcd" => Ok(ExportFormat::Pcd),
+            _ => Err(ErrorKind::InvalidInput(format!("Unknown export format '{}'.", s)).into()),
+        }
+    }
+}
+
+fn json_array(values: &[f64]) -> String {
+    let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn json_point(p: &Point3<f64>) -> String {
+    json_array(&[p.x, p.y, p.z])
+}
+
+/// The average distance between neighboring points of a node, assuming they lie on surfaces.
+fn spacing(edge_length: f64, num_points: i64) -> f64 {
+    edge_length / (num_points.max(1) as f64).sqrt()
+}
+
+/// The nodes of `octree` level by level, the children of a node in the order of their index. The
+/// root comes first, an octree without one is an error.
+fn nodes_breadth_first(octree: &Octree) -> Result<Vec<NodeId>> {
+    let root = NodeId::from_level_index(0, 0);
+    if !octree.nodes.contains_key(&root) {
+        return Err(ErrorKind::InvalidInput("The octree has no root node.".to_string()).into());
+    }
+    let mut node_ids = vec![root];
+    let mut i = 0;
+    while i < node_ids.len() {
+        let children = octree.children(&node_ids[i]);
+        node_ids.extend(children);
+        i += 1;
+    }
+    Ok(node_ids)
+}
+
+/// Reads the nodes in chunks, encoding the points of each chunk in parallel with `encode`, and
+/// hands the results to `write` in the order of `node_ids`. Nodes without points are passed to
+/// `encode` as None.
+fn for_each_encoded_node<T: Send>(
+    octree_data_provider: &OnDiskDataProvider,
+    octree: &Octree,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    node_ids: &[NodeId],
+    encode: impl Fn(&NodeId, Option<PointsBatch>) -> Result<T> + Sync,
+    mut write: impl FnMut(&NodeId, T) -> Result<()>,
+) -> Result<()> {
+    let mut progress_bar = create_progress_bar(node_ids.len(), "Exporting nodes");
+    for chunk in node_ids.chunks(NUM_NODES_PER_CHUNK) {
+        let encoded = chunk
+            .par_iter()
+            .map(|node_id| {
+                let batch = if octree.nodes[node_id].num_points > 0 {
+                    Some(read_all_points(
+                        octree_data_provider,
+                        &octree.meta,
+                        attribute_data_types,
+                        node_id,
+                    )?)
+                } else {
+                    None
+                };
+                encode(node_id, batch)
+            })
+            .collect::<Result<Vec<T>>>()?;
+        for (node_id, encoded) in chunk.iter().zip(encoded) {
+            write(node_id, encoded)?;
+            progress_bar.inc();
+        }
+    }
+    progress_bar.finish();
+    Ok(())
+}
+
+/// Pads `bytes` with `padding` so that `offset` plus its length is a multiple of 8, as 3D Tiles
+/// requires for every part of a tile.
+fn pad_to_8_bytes(bytes: &mut Vec<u8>, offset: usize, padding: u8) {
+    let padding_length = (8 - (offset + bytes.len()) % 8) % 8;
+    bytes.resize(bytes.len() + padding_length, padding);
+}
+
+/// A pnts tile of the points of `batch`, relative to the center of their bounding box. Colors
+/// become RGB and intensities, if there are any, a batch table property.
+fn encode_pnts(batch: &PointsBatch) -> Result<Vec<u8>> {
+    const HEADER_SIZE: usize = 28;
+    let num_points = batch.position.len();
+    let first = match batch.position.first() {
+        Some(first) => first,
+        None => {
+            return Err(
+                ErrorKind::InvalidInput("A tile needs at least one point.".to_string()).into(),
+            )
+        }
+    };
+    let mut bounding_box = Aabb::new(*first, *first);
+    for p in &batch.position[1..] {
+        bounding_box.grow(*p);
+    }
+    let center = bounding_box.center();
+
+    let mut feature_table_json = format!(
+        r#"{{"POINTS_LENGTH":{},"RTC_CENTER":{},"POSITION":{{"byteOffset":0}},"RGB":{{"byteOffset":{}}}}}"#,
+        num_points,
+        json_point(&center),
+        12 * num_points
+    )
+    .into_bytes();
+    pad_to_8_bytes(&mut feature_table_json, HEADER_SIZE, b' ');
+    let mut feature_table = Vec::with_capacity(15 * num_points + 8);
+    for p in &batch.position {
+        for c in (p - center).iter() {
+            feature_table.write_f32::<LittleEndian>(*c as f32)?;
+        }
+    }
+    let colors: &Vec<Vector3<u8>> = batch.get_attribute_vec("color").map_err(Error::from)?;
+    for color in colors {
+        feature_table.extend(color.iter());
+    }
+    pad_to_8_bytes(&mut feature_table, 0, 0);
+
+    let (mut batch_table_json, mut batch_table) = (Vec::new(), Vec::new());
+    if let Ok(intensities) = batch.get_attribute_vec::<f32>("intensity") {
+        batch_table_json =
+            r#"{"intensity":{"byteOffset":0,"componentType":"FLOAT","type":"SCALAR"}}"#
+                .as_bytes()
+                .to_vec();
+        pad_to_8_bytes(&mut batch_table_json, 0, b' ');
+        for intensity in intensities {
+            batch_table.write_f32::<LittleEndian>(*intensity)?;
+        }
+        pad_to_8_bytes(&mut batch_table, 0, 0);
+    }
+
+    let byte_length = HEADER_SIZE
+        + feature_table_json.len()
+        + feature_table.len()
+        + batch_table_json.len()
+        + batch_table.len();
+    let mut pnts = Vec::with_capacity(byte_length);
+    pnts.extend(b"pnts");
+    pnts.write_u32::<LittleEndian>(1)?;
+    for length in &[
+        byte_length,
+        feature_table_json.len(),
+        feature_table.len(),
+        batch_table_json.len(),
+        batch_table.len(),
+    ] {
+        pnts.write_u32::<LittleEndian>(*length as u32)?;
+    }
+    pnts.extend(feature_table_json);
+    pnts.extend(feature_table);
+    pnts.extend(batch_table_json);
+    pnts.extend(batch_table);
+    Ok(pnts)
+}
+
+/// Appends the tile of `node_id` and its descendants to `json`. Leaves are shown from any
+/// distance, inner nodes until their points are farther apart than their spacing.
+fn write_tile(
+    json: &mut String,
+    octree: &Octree,
+    node_id: &NodeId,
+    tile_box: &dyn Fn(&NodeId) -> Aabb,
+) {
+    let node = &octree.nodes[node_id];
+    let children = octree.children(node_id);
+    let geometric_error = if children.is_empty() {
+        0.
+    } else {
+        spacing(node.bounding_cube.edge_length(), node.num_points)
+    };
+    let bounding_box = tile_box(node_id);
+    let c = bounding_box.center();
+    let h = bounding_box.diag() / 2.;
+    json.push_str(&format!(
+        r#"{{"boundingVolume":{{"box":{}}},"geometricError":{}"#,
+        json_array(&[c.x, c.y, c.z, h.x, 0., 0., 0., h.y, 0., 0., 0., h.z]),
+        geometric_error
+    ));
+    if node_id.level() == 0 {
+        json.push_str(r#","refine":"ADD""#);
+    }
+    if node.num_points > 0 {
+        json.push_str(&format!(r#","content":{{"uri":"{}.pnts"}}"#, node_id));
+    }
+    if !children.is_empty() {
+        json.push_str(r#","children":["#);
+        for (i, child) in children.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write_tile(json, octree, child, tile_box);
+        }
+        json.push(']');
+    }
+    json.push('}');
+}
+
+fn export_3d_tiles(
+    octree_data_provider: &OnDiskDataProvider,
+    octree: &Octree,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    node_ids: &[NodeId],
+    output_directory: &Path,
+) -> Result<()> {
+    let reprojection = match octree.meta.coordinate_system {
+        None | Some(CoordinateSystem::Ecef) => None,
+        Some(coordinate_system) => {
+            Some(Reprojection::new(coordinate_system, CoordinateSystem::Ecef))
+        }
+    };
+    for_each_encoded_node(
+        octree_data_provider,
+        octree,
+        attribute_data_types,
+        node_ids,
+        |_, batch| match batch {
+            Some(mut batch) => {
+                if let Some(reprojection) = &reprojection {
+                    reprojection.transform_batch(&mut batch);
+                }
+                encode_pnts(&batch).map(Some)
+            }
+            None => Ok(None),
+        },
+        |node_id, pnts| match pnts {
+            Some(pnts) => {
+                fs::write(output_directory.join(format!("{}.pnts", node_id)), pnts)?;
+                Ok(())
+            }
+            None => Ok(()),
+        },
+    )?;
+
+    // The cubes of the nodes are cut down to the points, since most of them are empty for
+    // scans of the ground.
+    let data_box = &octree.meta.bounding_box;
+    let tile_box = |node_id: &NodeId| {
+        let cube = &octree.nodes[node_id].bounding_cube;
+        let min = cube.min().coords.sup(&data_box.min().coords);
+        let max = cube.max().coords.inf(&data_box.max().coords).sup(&min);
+        let bounding_box = Aabb::new(Point3::from(min), Point3::from(max));
+        match &reprojection {
+            Some(reprojection) => reprojection.transform_bounding_box(&bounding_box),
+            None => bounding_box,
+        }
+    };
+    // 'nodes_breadth_first' starts with the root.
+    let root = &node_ids[0];
+    let mut json = format!(
+        r#"{{"asset":{{"version":"1.0"}},"geometricError":{},"root":"#,
+        octree.nodes[root].bounding_cube.edge_length()
+    );
+    write_tile(&mut json, octree, root, &tile_box);
+    json.push('}');
+    fs::write(output_directory.join("tileset.json"), json)?;
+    Ok(())
+}
+
+/// The points of `batch` as Potree 2 stores them: integer positions relative to `offset` in
+/// units of `scale`, 16 bit colors and, if there are any, intensities.
+fn encode_potree_points(batch: &PointsBatch, offset: &Point3<f64>, scale: f64) -> Result<Vec<u8>> {
+    let colors: &Vec<Vector3<u8>> = batch.get_attribute_vec("color").map_err(Error::from)?;
+    let intensities: Option<&Vec<f32>> = batch.get_attribute_vec("intensity").ok();
+    let mut bytes = Vec::with_capacity(22 * batch.position.len());
+    for (i, p) in batch.position.iter().enumerate() {
+        for c in ((p - offset) / scale).iter() {
+            bytes.write_i32::<LittleEndian>(c.round() as i32)?;
+        }
+        for c in colors[i].iter() {
+            bytes.write_u16::<LittleEndian>(u16::from(*c))?;
+        }
+        if let Some(intensities) = intensities {
+            bytes.write_f32::<LittleEndian>(intensities[i])?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// The projection of `coordinate_system` as a proj4 string, which Potree shows a map for.
+fn proj4(coordinate_system: Option<CoordinateSystem>) -> String {
+    match coordinate_system {
+        Some(CoordinateSystem::Utm(zone)) => format!(
+            "+proj=utm +zone={}{} +datum=WGS84 +units=m +no_defs",
+            zone.number(),
+            if zone.north() { "" } else { " +south" }
+        ),
+        Some(CoordinateSystem::Ecef) => "+proj=geocent +datum=WGS84 +units=m +no_defs".to_string(),
+        Some(CoordinateSystem::LocalEnu(_)) | None => String::new(),
+    }
+}
+
+fn export_potree(
+    octree_data_provider: &OnDiskDataProvider,
+    octree: &Octree,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    node_ids: &[NodeId],
+    output_directory: &Path,
+) -> Result<()> {
+    // 'nodes_breadth_first' starts with the root.
+    let root = &octree.nodes[&node_ids[0]];
+    let cube = &root.bounding_cube;
+    let offset = cube.min();
+    // Positions are stored as 32 bit integers, so they need to be coarser for big octrees.
+    let scale = octree
+        .meta
+        .resolution
+        .max(cube.edge_length() / f64::from(i32::MAX));
+
+    let mut octree_bin = BufWriter::new(File::create(output_directory.join("octree.bin"))?);
+    let mut hierarchy = Vec::with_capacity(POTREE_NODE_SIZE * node_ids.len());
+    let mut byte_offset = 0;
+    for_each_encoded_node(
+        octree_data_provider,
+        octree,
+        attribute_data_types,
+        node_ids,
+        |_, batch| match batch {
+            Some(batch) => encode_potree_points(&batch, &offset, scale),
+            None => Ok(Vec::new()),
+        },
+        |node_id, points| {
+            let children = octree.children(node_id);
+            let child_mask = children
+                .iter()
+                .fold(0u8, |mask, child| mask | 1 << (child.index() & 7));
+            // The type of the node: 0 for inner nodes, 1 for leaves.
+            hierarchy.push(if children.is_empty() { 1 } else { 0 });
+            hierarchy.push(child_mask);
+            hierarchy.write_u32::<LittleEndian>(octree.nodes[node_id].num_points as u32)?;
+            hierarchy.write_u64::<LittleEndian>(byte_offset)?;
+            hierarchy.write_u64::<LittleEndian>(points.len() as u64)?;
+            octree_bin.write_all(&points)?;
+            byte_offset += points.len() as u64;
+            Ok(())
+        },
+    )?;
+    octree_bin.flush()?;
+    fs::write(output_directory.join("hierarchy.bin"), &hierarchy)?;
+
+    let num_points: i64 = octree.nodes.values().map(|node| node.num_points).sum();
+    let depth = node_ids.last().unwrap().level();
+    let data_box = &octree.meta.bounding_box;
+    let mut attributes = vec![
+        format!(
+            r#"{{"name":"position","description":"","size":12,"numElements":3,"elementSize":4,"type":"int32","min":{},"max":{}}}"#,
+            json_point(data_box.min()),
+            json_point(data_box.max())
+        ),
+        r#"{"name":"rgb","description":"","size":6,"numElements":3,"elementSize":2,"type":"uint16","min":[0,0,0],"max":[255,255,255]}"#.to_string(),
+    ];
+    if attribute_data_types.contains_key("intensity") {
+        let range = octree.attribute_ranges().get("intensity");
+        let (min, max) = range.map_or((0., 0.), |range| (range.lower_bound(), range.upper_bound()));
+        attributes.push(format!(
+            r#"{{"name":"intensity","description":"","size":4,"numElements":1,"elementSize":4,"type":"float","min":[{}],"max":[{}]}}"#,
+            min, max
+        ));
+    }
+    let name = output_directory
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let json = format!(
+        r#"{{"version":"2.0","name":"{}","description":"","points":{},"projection":"{}","hierarchy":{{"firstChunkSize":{},"stepSize":4,"depth":{}}},"offset":{},"scale":{},"spacing":{},"boundingBox":{{"min":{},"max":{}}},"encoding":"DEFAULT","attributes":[{}]}}"#,
+        name.replace('\\', "\\\\").replace('"', "\\\""),
+        num_points,
+        proj4(octree.meta.coordinate_system),
+        hierarchy.len(),
+        depth,
+        json_point(&offset),
+        json_array(&[scale, scale, scale]),
+        spacing(cube.edge_length(), root.num_points),
+        json_point(&cube.min()),
+        json_point(&cube.max()),
+        attributes.join(",")
+    );
+    fs::write(output_directory.join("metadata.json"), json)?;
+    Ok(())
+}
+
+fn export_pcd(
+    octree_data_provider: &OnDiskDataProvider,
+    octree: &Octree,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    node_ids: &[NodeId],
+    output_directory: &Path,
+) -> Result<()> {
+    // The count of points in the header is written when the writer is dropped.
+    let mut writer = PcdNodeWriter::new(
+        output_directory.join("points.pcd"),
+        Encoding::Plain,
+        OpenMode::Truncate,
+    );
+    for_each_encoded_node(
+        octree_data_provider,
+        octree,
+        attribute_data_types,
+        node_ids,
+        |_, batch| Ok(batch),
+        |_, batch| match batch {
+            Some(batch) => Ok(writer.write(&batch)?),
+            None => Ok(()),
+        },
+    )
+}
+
+/// Writes the octree in `octree_directory` to `output_directory` in `format`, with its colors
+/// and, if every node has them, its intensities.
+pub fn export_octree(
+    octree_directory: impl AsRef<Path>,
+    output_directory: impl AsRef<Path>,
+    format: ExportFormat,
+) -> Result<()> {
+    let octree_data_provider = &OnDiskDataProvider {
+        directory: octree_directory.as_ref().to_path_buf(),
+    };
+    let octree = &Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: octree_directory.as_ref().to_path_buf(),
+    }))?;
+    if octree.nodes.values().all(|node| node.num_points == 0) {
+        return Err(ErrorKind::InvalidInput("The octree has no points.".to_string()).into());
+    }
+    let mut attribute_data_types = HashMap::new();
+    for (name, data_type) in &[
+        ("color", AttributeDataType::U8Vec3),
+        ("intensity", AttributeDataType::F32),
+    ] {
+        let on_every_node = octree.nodes.iter().all(|(node_id, node)| {
+            node.num_points == 0
+                || octree
+                    .attributes_on_disk(octree_data_provider, node_id)
+                    .contains_key(*name)
+        });
+        if on_every_node && octree.meta.attribute_data_types.get(*name) == Some(data_type) {
+            attribute_data_types.insert(name.to_string(), *data_type);
+        }
+    }
+    if !attribute_data_types.contains_key("color") {
+        return Err(ErrorKind::InvalidInput(
+            "Only octrees with colors on every node can be exported.".to_string(),
+        )
+        .into());
+    }
+
+    let node_ids = &nodes_breadth_first(octree)?;
+
+    let output_directory = output_directory.as_ref();
+    fs::create_dir_all(output_directory)?;
+    match format {
+        ExportFormat::Tiles3d => export_3d_tiles(
+            octree_data_provider,
+            octree,
+            &attribute_data_types,
+            node_ids,
+            output_directory,
+        ),
+        ExportFormat::Potree => export_potree(
+            octree_data_provider,
+            octree,
+            &attribute_data_types,
+            node_ids,
+            output_directory,
+        ),
+        ExportFormat::Pcd => export_pcd(
+            octree_data_provider,
+            octree,
+            &attribute_data_types,
+            node_ids,
+            output_directory,
+        ),
+    }
+}
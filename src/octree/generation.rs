@@ -15,11 +15,17 @@
 use crate::data_provider::OnDiskDataProvider;
 use crate::errors::*;
 use crate::geometry::{Aabb, Cube};
-use crate::octree::{self, to_meta_proto, to_node_proto, ChildIndex, NodeId, OctreeMeta};
+use crate::labels::{LabelDictionary, CLASSIFICATION_ATTRIBUTE};
+use crate::octree::checkpoint::{Checkpoint, Stage};
+use crate::octree::intensity::IntensityNormalization;
+use crate::octree::outliers::{remove_outliers_in_nodes, OutlierFilter};
+use crate::octree::{
+    self, to_meta_proto, to_node_proto, AttributeRanges, ChildIndex, NodeId, OctreeMeta,
+};
 use crate::proto;
 use crate::read_write::{
-    attempt_increasing_rlimit_to_max, Encoding, NodeIterator, NodeWriter, OpenMode, PlyIterator,
-    PositionEncoding, RawNodeWriter,
+    attempt_increasing_rlimit_to_max, Encoding, InputFileIterator, NodeIterator, NodeWriter,
+    OpenMode, PositionEncoding, RawNodeWriter,
 };
 use crate::utils::create_progress_bar;
 use crate::META_FILENAME;
@@ -31,16 +37,26 @@ use rayon::Scope;
 use std::cmp;
 use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::BufWriter;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 
-const MAX_POINTS_PER_NODE: i64 = 100_000;
+pub(super) const MAX_POINTS_PER_NODE: i64 = 100_000;
+
+/// What the meta file records about a node that has been written.
+#[derive(Debug, Clone, Default)]
+pub(super) struct NodeSummary {
+    pub num_points: i64,
+    pub attribute_ranges: AttributeRanges,
+    /// See 'NodeMeta::version'.
+    pub version: u64,
+}
 
 impl RawNodeWriter {
-    fn from_data_provider(
+    pub(super) fn from_data_provider(
         octree_data_provider: &OnDiskDataProvider,
         octree_meta: &OctreeMeta,
         node_id: &NodeId,
+        open_mode: OpenMode,
     ) -> Self {
         let path = octree_data_provider.stem(&node_id.to_string());
         let bounding_cube = node_id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
@@ -49,7 +65,7 @@ impl RawNodeWriter {
         RawNodeWriter::new(
             path,
             Encoding::ScaledToCube(min, bounding_cube.edge_length(), position_encoding),
-            OpenMode::Truncate,
+            open_mode,
         )
     }
 }
@@ -60,7 +76,7 @@ fn split<P>(
     octree_meta: &octree::OctreeMeta,
     node_id: &octree::NodeId,
     stream: P,
-) -> (Vec<octree::NodeId>, Vec<octree::NodeId>)
+) -> Result<(Vec<octree::NodeId>, Vec<octree::NodeId>)>
 where
     P: Iterator<Item = PointsBatch> + NumberOfPoints,
 {
@@ -75,7 +91,7 @@ where
     );
 
     let bounding_cube = node_id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
-    stream.for_each(|batch| {
+    for batch in stream {
         let child_indices: Vec<_> = batch
             .position
             .iter()
@@ -94,18 +110,13 @@ where
                         octree_data_provider,
                         octree_meta,
                         &node_id.get_child_id(ChildIndex::from_u8(array_index as u8)),
+                        OpenMode::Truncate,
                     ));
                 }
-                child_writer.as_mut().unwrap().write(&child_batch).unwrap();
+                child_writer.as_mut().unwrap().write(&child_batch)?;
             }
         }
-    });
-
-    // Remove the node file on disk by reopening the node and immediately dropping it again without
-    // writing a point. This only saves some disk space during processing - all nodes will be
-    // rewritten by subsampling the children in the second step anyways. We also ignore file
-    // removing error. For example, we never write out the root, so it cannot be removed.
-    RawNodeWriter::from_data_provider(octree_data_provider, octree_meta, node_id);
+    }
 
     let mut leaf_nodes = Vec::new();
     let mut split_nodes = Vec::new();
@@ -122,10 +133,28 @@ where
             leaf_nodes.push(child_id);
         }
     }
-    (leaf_nodes, split_nodes)
+    Ok((leaf_nodes, split_nodes))
+}
+
+/// Removes the files of a node that has been split.
+pub(super) fn remove_node_files(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &octree::OctreeMeta,
+    node_id: &octree::NodeId,
+) {
+    // Remove the node file on disk by reopening the node and immediately dropping it again without
+    // writing a point. This only saves some disk space during processing - all nodes will be
+    // rewritten by subsampling the children in the second step anyways. We also ignore file
+    // removing error. For example, we never write out the root, so it cannot be removed.
+    RawNodeWriter::from_data_provider(
+        octree_data_provider,
+        octree_meta,
+        node_id,
+        OpenMode::Truncate,
+    );
 }
 
-fn should_split_node(
+pub(super) fn should_split_node(
     id: &octree::NodeId,
     num_points: i64,
     octree_meta: &octree::OctreeMeta,
@@ -149,58 +178,117 @@ fn should_split_node(
     true
 }
 
-fn split_node<'a, P>(
+/// Splits the node with the points of `stream` until all its descendants are small enough. With a
+/// `checkpoint`, the files of split nodes are kept until it has recorded the split. The resulting
+/// leaves are sent to `leaf_nodes_sender`, and so is every error, since splitting continues in
+/// tasks spawned on `scope`.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn split_node<'a, P>(
     scope: &Scope<'a>,
     octree_data_provider: &'a OnDiskDataProvider,
     octree_meta: &'a octree::OctreeMeta,
     attribute_data_types: &'a HashMap<String, AttributeDataType>,
     node_id: &octree::NodeId,
     stream: P,
-    leaf_nodes_sender: &crossbeam::channel::Sender<octree::NodeId>,
+    leaf_nodes_sender: &crossbeam::channel::Sender<Result<octree::NodeId>>,
+    checkpoint: Option<&'a Checkpoint>,
 ) where
     P: Iterator<Item = PointsBatch> + NumberOfPoints,
 {
-    let (leaf_nodes, split_nodes) = split(octree_data_provider, octree_meta, node_id, stream);
+    let split_result = split(octree_data_provider, octree_meta, node_id, stream).and_then(
+        |(leaf_nodes, split_nodes)| {
+            match checkpoint {
+                Some(checkpoint) => {
+                    checkpoint.split_finished(octree_meta, node_id, &leaf_nodes, &split_nodes)?
+                }
+                None => remove_node_files(octree_data_provider, octree_meta, node_id),
+            }
+            Ok((leaf_nodes, split_nodes))
+        },
+    );
+    let (leaf_nodes, split_nodes) = match split_result {
+        Ok(nodes) => nodes,
+        Err(err) => {
+            leaf_nodes_sender.send(Err(err)).unwrap();
+            return;
+        }
+    };
     for child_id in split_nodes {
         let leaf_nodes_sender_clone = leaf_nodes_sender.clone();
         scope.spawn(move |scope| {
-            let stream = NodeIterator::from_data_provider(
-                octree_data_provider,
-                attribute_data_types,
-                octree_meta.encoding_for_node(child_id),
-                &child_id,
-                octree_data_provider
-                    .number_of_points(&child_id.to_string())
-                    .unwrap() as usize,
-                NUM_POINTS_PER_BATCH,
-            )
-            .unwrap();
-            split_node(
+            split_stored_node(
                 scope,
                 octree_data_provider,
                 octree_meta,
                 attribute_data_types,
                 &child_id,
-                stream,
                 &leaf_nodes_sender_clone,
+                checkpoint,
             );
         });
     }
 
     for id in leaf_nodes {
-        leaf_nodes_sender.send(id).unwrap();
+        leaf_nodes_sender.send(Ok(id)).unwrap();
     }
 }
 
+/// Like 'split_node', but the points are read from the node's files.
+fn split_stored_node<'a>(
+    scope: &Scope<'a>,
+    octree_data_provider: &'a OnDiskDataProvider,
+    octree_meta: &'a octree::OctreeMeta,
+    attribute_data_types: &'a HashMap<String, AttributeDataType>,
+    node_id: &octree::NodeId,
+    leaf_nodes_sender: &crossbeam::channel::Sender<Result<octree::NodeId>>,
+    checkpoint: Option<&'a Checkpoint>,
+) {
+    let stream = octree_data_provider
+        .number_of_points(&node_id.to_string())
+        .and_then(|num_points| {
+            NodeIterator::from_data_provider(
+                octree_data_provider,
+                attribute_data_types,
+                octree_meta.encoding_for_node(*node_id),
+                node_id,
+                num_points as usize,
+                NUM_POINTS_PER_BATCH,
+            )
+        });
+    let stream = match stream {
+        Ok(stream) => stream,
+        Err(err) => {
+            leaf_nodes_sender.send(Err(err)).unwrap();
+            return;
+        }
+    };
+    split_node(
+        scope,
+        octree_data_provider,
+        octree_meta,
+        attribute_data_types,
+        node_id,
+        stream,
+        leaf_nodes_sender,
+        checkpoint,
+    );
+}
+
 fn subsample_children_into(
     octree_data_provider: &OnDiskDataProvider,
     octree_meta: &octree::OctreeMeta,
     attribute_data_types: &HashMap<String, AttributeDataType>,
     node_id: &octree::NodeId,
-    nodes_sender: &crossbeam::channel::Sender<(octree::NodeId, i64)>,
+    nodes_sender: &crossbeam::channel::Sender<(octree::NodeId, NodeSummary)>,
+    checkpoint: Option<&Checkpoint>,
 ) -> Result<()> {
-    let mut parent_writer =
-        RawNodeWriter::from_data_provider(octree_data_provider, octree_meta, node_id);
+    let mut parent_writer = RawNodeWriter::from_data_provider(
+        octree_data_provider,
+        octree_meta,
+        node_id,
+        OpenMode::Truncate,
+    );
+    let mut parent_ranges = AttributeRanges::default();
     for i in 0..8 {
         let child_id = node_id.get_child_id(octree::ChildIndex::from_u8(i));
         let num_points = match octree_data_provider.number_of_points(&child_id.to_string()) {
@@ -232,30 +320,183 @@ fn subsample_children_into(
         let mut child_batch = batch;
         child_batch.retain(&keep_child);
 
-        let mut child_writer =
-            RawNodeWriter::from_data_provider(octree_data_provider, octree_meta, &child_id);
+        // With a checkpoint, the child's points are kept until the whole level is done, so that
+        // it can be subsampled again after an interruption.
+        let mut child_writer = match checkpoint {
+            Some(checkpoint) => RawNodeWriter::new(
+                checkpoint.pending_stem(&child_id),
+                octree_meta.encoding_for_node(child_id),
+                OpenMode::Truncate,
+            ),
+            None => RawNodeWriter::from_data_provider(
+                octree_data_provider,
+                octree_meta,
+                &child_id,
+                OpenMode::Truncate,
+            ),
+        };
         parent_writer.write(&parent_batch)?;
         child_writer.write(&child_batch)?;
+        parent_ranges.add_batch(&parent_batch);
 
         // Update child.
-        nodes_sender
-            .send((child_id, child_writer.num_written()))
-            .unwrap();
+        let child_summary = NodeSummary {
+            num_points: child_writer.num_written(),
+            attribute_ranges: AttributeRanges::from_batch(&child_batch),
+            ..Default::default()
+        };
+        nodes_sender.send((child_id, child_summary)).unwrap();
+    }
+
+    // Make sure the top node, e.g. the root, is also tracked as an existing node. Every other
+    // parent is tracked again when its own parent is subsampled.
+    let parent_summary = NodeSummary {
+        num_points: parent_writer.num_written(),
+        attribute_ranges: parent_ranges,
+        ..Default::default()
+    };
+    nodes_sender.send((*node_id, parent_summary)).unwrap();
+    Ok(())
+}
+
+/// Builds the inner nodes of the subtree rooted at `top_level` by subsampling the given leaf nodes
+/// level by level. Returns the summary of every node that has been (re)written, including
+/// `top_level`.
+pub(super) fn subsample_up_to_level(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &octree::OctreeMeta,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    leaf_nodes: Vec<octree::NodeId>,
+    top_level: u8,
+) -> Result<FnvHashMap<octree::NodeId, NodeSummary>> {
+    subsample_levels(
+        octree_data_provider,
+        octree_meta,
+        attribute_data_types,
+        leaf_nodes,
+        FnvHashMap::default(),
+        top_level,
+        None,
+    )
+}
+
+/// Like 'subsample_up_to_level', but continues from the `nodes_to_subsample` and the
+/// `finished_nodes` so far. With a `checkpoint`, it is saved after every level.
+fn subsample_levels(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &octree::OctreeMeta,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    mut nodes_to_subsample: Vec<octree::NodeId>,
+    mut finished_nodes: FnvHashMap<octree::NodeId, NodeSummary>,
+    top_level: u8,
+    checkpoint: Option<&Checkpoint>,
+) -> Result<FnvHashMap<octree::NodeId, NodeSummary>> {
+    let deepest_level = nodes_to_subsample
+        .iter()
+        .map(|id| id.level())
+        .fold(top_level, cmp::max);
+
+    // sub sampling returns the list of finished nodes including all meta data
+    // We start on the deepest level and work our way up the tree.
+    for current_level in (top_level + 1..=deepest_level).rev() {
+        // All nodes on the same level can be subsampled in parallel.
+        let (children, rest): (Vec<_>, Vec<_>) = nodes_to_subsample
+            .into_iter()
+            .partition(|n| n.level() == current_level);
+        nodes_to_subsample = rest;
+
+        // Unwrap is safe, since we stop at current_level = top_level + 1, so the root can never
+        // appear.
+        let parent_ids: FnvHashSet<_> = children.iter().map(|id| id.parent_id().unwrap()).collect();
+        let mut progress_bar = create_progress_bar(
+            parent_ids.len(),
+            &format!("Building level {}", current_level - 1),
+        );
+
+        let (finished_nodes_sender, finished_nodes_receiver) = crossbeam::channel::unbounded();
+        let (progress_tx, progress_rx) = crossbeam::channel::unbounded();
+        let mut result: Result<()> = Ok(());
+        rayon::scope(|scope| {
+            scope.spawn(|_| {
+                for (id, summary) in finished_nodes_receiver {
+                    finished_nodes.insert(id, summary);
+                }
+            });
+
+            scope.spawn(|_| {
+                for _ in progress_rx {
+                    progress_bar.inc();
+                }
+            });
+
+            result = parent_ids.par_iter().try_for_each(|id| {
+                subsample_children_into(
+                    octree_data_provider,
+                    octree_meta,
+                    attribute_data_types,
+                    id,
+                    &finished_nodes_sender,
+                    checkpoint,
+                )?;
+                progress_tx.send(()).unwrap();
+                Ok(())
+            });
+            drop(finished_nodes_sender);
+            drop(progress_tx);
+        });
+        result?;
+        progress_bar.finish();
+
+        // The nodes that were just now created through sub-sampling will be required to create
+        // their parents.
+        nodes_to_subsample.extend(parent_ids.into_iter());
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.finish_level(&nodes_to_subsample, &finished_nodes, &children)?;
+        }
     }
+    Ok(finished_nodes)
+}
 
-    // Make sure the root node is also tracked as an existing node.
-    if node_id.level() == 0 {
-        nodes_sender
-            .send((*node_id, parent_writer.num_written()))
-            .unwrap();
+/// Writes the meta file for the given nodes. The file is first written next to its final location
+/// and then moved into place, so readers never observe a partially written meta.
+pub(super) fn write_meta(
+    output_directory: impl AsRef<Path>,
+    octree_meta: &octree::OctreeMeta,
+    nodes: &FnvHashMap<octree::NodeId, NodeSummary>,
+) -> Result<()> {
+    // Add all non-zero node meta data to meta file
+    let nodes: Vec<proto::OctreeNode> = nodes
+        .iter()
+        .map(|(id, summary)| {
+            let bounding_cube = id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
+            let position_encoding = PositionEncoding::new(&bounding_cube, octree_meta.resolution);
+            to_node_proto(
+                &id,
+                summary.num_points,
+                &position_encoding,
+                &summary.attribute_ranges,
+                summary.version,
+            )
+        })
+        .collect();
+    let meta = to_meta_proto(&octree_meta, nodes);
+
+    let meta_path = output_directory.as_ref().join(META_FILENAME);
+    let tmp_path = meta_path.with_extension("pb.tmp");
+    {
+        let mut buf_writer = BufWriter::new(File::create(&tmp_path)?);
+        meta.write_to_writer(&mut buf_writer)
+            .chain_err(|| format!("Could not write {}", META_FILENAME))?;
+        buf_writer.flush()?;
     }
+    fs::rename(&tmp_path, &meta_path)?;
     Ok(())
 }
 
 /// Returns the bounding box containing all points
 fn find_bounding_box(filename: impl AsRef<Path>) -> Aabb {
     let mut bounding_box = None;
-    let stream = PlyIterator::from_file(filename, NUM_POINTS_PER_BATCH).unwrap();
+    let stream = InputFileIterator::from_file(filename, NUM_POINTS_PER_BATCH).unwrap();
     let mut progress_bar = create_progress_bar(stream.num_points(), "Determining bounding box");
 
     stream.for_each(|batch| {
@@ -269,34 +510,111 @@ fn find_bounding_box(filename: impl AsRef<Path>) -> Aabb {
     bounding_box.unwrap_or_else(Aabb::zero)
 }
 
+/// Builds an octree out of the points in `filename`. If an `intensity_normalization` is given,
+/// it is fitted to the intensities of the file, which are then stored normalized.
 pub fn build_octree_from_file(
     output_directory: impl AsRef<Path>,
     resolution: f64,
     filename: impl AsRef<Path>,
     attributes: &[&str],
+    outlier_filters: &[OutlierFilter],
+    intensity_normalization: Option<&IntensityNormalization>,
 ) {
     let bounding_box = find_bounding_box(filename.as_ref());
-    let stream = PlyIterator::from_file(filename, NUM_POINTS_PER_BATCH).unwrap();
-    build_octree(
+    let stream = InputFileIterator::from_file(filename.as_ref(), NUM_POINTS_PER_BATCH).unwrap();
+    match intensity_normalization {
+        Some(intensity_normalization) => {
+            let mapping = intensity_normalization
+                .fit(InputFileIterator::from_file(filename, NUM_POINTS_PER_BATCH).unwrap())
+                .unwrap();
+            build_filtered_octree(
+                output_directory,
+                resolution,
+                bounding_box,
+                mapping.normalize_batches(stream),
+                attributes,
+                outlier_filters,
+            )
+        }
+        None => build_filtered_octree(
+            output_directory,
+            resolution,
+            bounding_box,
+            stream,
+            attributes,
+            outlier_filters,
+        ),
+    }
+}
+
+pub fn build_octree(
+    output_directory: impl AsRef<Path>,
+    resolution: f64,
+    bounding_box: Aabb,
+    input: impl Iterator<Item = PointsBatch> + NumberOfPoints + Send,
+    attributes: &[&str],
+) {
+    build_filtered_octree(
         output_directory,
         resolution,
         bounding_box,
-        stream,
+        input,
         attributes,
+        &[],
     )
 }
 
-pub fn build_octree(
+/// Like 'build_octree', but the `outlier_filters` are applied to the points first. How many
+/// points were removed is reported.
+pub fn build_filtered_octree(
     output_directory: impl AsRef<Path>,
     resolution: f64,
     bounding_box: Aabb,
     input: impl Iterator<Item = PointsBatch> + NumberOfPoints + Send,
     attributes: &[&str],
+    outlier_filters: &[OutlierFilter],
+) {
+    let octree_meta = octree::OctreeMeta::new_with_standard_attributes(resolution, bounding_box);
+    build_octree_with_meta(
+        output_directory,
+        octree_meta,
+        input,
+        attributes,
+        outlier_filters,
+    )
+}
+
+/// Like 'build_octree', but the points also have a classification attribute whose values are
+/// named by `label_dictionary`. It does not need to be among the `attributes`.
+pub fn build_labeled_octree(
+    output_directory: impl AsRef<Path>,
+    resolution: f64,
+    bounding_box: Aabb,
+    input: impl Iterator<Item = PointsBatch> + NumberOfPoints + Send,
+    attributes: &[&str],
+    label_dictionary: LabelDictionary,
+) {
+    let mut octree_meta =
+        octree::OctreeMeta::new_with_standard_attributes(resolution, bounding_box);
+    octree_meta.set_label_dictionary(label_dictionary);
+    let mut attributes = attributes.to_vec();
+    if !attributes.contains(&CLASSIFICATION_ATTRIBUTE) {
+        attributes.push(CLASSIFICATION_ATTRIBUTE);
+    }
+    build_octree_with_meta(output_directory, octree_meta, input, &attributes, &[])
+}
+
+pub(super) fn build_octree_with_meta(
+    output_directory: impl AsRef<Path>,
+    octree_meta: OctreeMeta,
+    input: impl Iterator<Item = PointsBatch> + NumberOfPoints + Send,
+    attributes: &[&str],
+    outlier_filters: &[OutlierFilter],
 ) {
     attempt_increasing_rlimit_to_max();
 
-    let octree_meta =
-        &octree::OctreeMeta::new_with_standard_attributes(resolution, bounding_box.clone());
+    let bounding_box = octree_meta.bounding_box.clone();
+    let octree_meta = &octree_meta;
     let attribute_data_types = &octree_meta.attribute_data_types_for(attributes).unwrap();
     let octree_data_provider = OnDiskDataProvider {
         directory: output_directory.as_ref().to_path_buf(),
@@ -305,9 +623,12 @@ pub fn build_octree(
 
     // Ignore errors, maybe directory is already there.
     let _ = fs::create_dir(output_directory.as_ref());
+    let checkpoint = Checkpoint::new(output_directory.as_ref(), octree_meta, attributes).unwrap();
+    let checkpoint = &checkpoint;
 
     eprintln!("Creating octree structure.");
 
+    // The checkpoint keeps track of the leaf nodes, so only the errors are of interest here.
     let (leaf_nodes_sender, leaf_nodes_receiver) = crossbeam::channel::unbounded();
     rayon::scope(move |scope| {
         let root_node = octree::Node::root_with_bounding_cube(Cube::bounding(&bounding_box));
@@ -319,85 +640,119 @@ pub fn build_octree(
             &root_node.id,
             input,
             &leaf_nodes_sender,
+            Some(checkpoint),
         );
     });
+    leaf_nodes_receiver
+        .try_iter()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+
+    finish_build(
+        octree_data_provider,
+        octree_meta,
+        attribute_data_types,
+        checkpoint,
+        outlier_filters,
+    )
+    .unwrap();
+}
 
-    let mut nodes_to_subsample = Vec::new();
-    let mut deepest_level = 0u8;
-    for id in leaf_nodes_receiver {
-        deepest_level = cmp::max(deepest_level, id.level());
-        nodes_to_subsample.push(id);
-    }
-    let mut finished_nodes = FnvHashMap::default();
-
-    // sub sampling returns the list of finished nodes including all meta data
-    // We start on the deepest level and work our way up the tree.
-    for current_level in (1..=deepest_level).rev() {
-        // All nodes on the same level can be subsampled in parallel.
-        let res = nodes_to_subsample
-            .into_iter()
-            .partition(|n| n.level() == current_level);
-        nodes_to_subsample = res.1;
-
-        // Unwrap is safe, since we stop at current_level = 1, so the root can never appear.
-        let parent_ids: FnvHashSet<_> = res
-            .0
-            .into_iter()
-            .map(|id| id.parent_id().unwrap())
-            .collect();
-        let mut progress_bar = create_progress_bar(
-            parent_ids.len(),
-            &format!("Building level {}", current_level - 1),
-        );
-
-        let (finished_nodes_sender, finished_nodes_receiver) = crossbeam::channel::unbounded();
-        let (progress_tx, progress_rx) = crossbeam::channel::unbounded();
-        rayon::scope(|scope| {
-            scope.spawn(|_| {
-                for (id, num_points) in finished_nodes_receiver {
-                    finished_nodes.insert(id, num_points);
-                }
-            });
+/// Continues an interrupted build of the octree in `output_directory` from its last checkpoint,
+/// which is saved while the octree is built. The `outlier_filters` need to be those the build was
+/// started with. Returns false if there is no build to resume, e.g. because it was interrupted
+/// before the input was read.
+pub fn resume_octree(
+    output_directory: impl AsRef<Path>,
+    outlier_filters: &[OutlierFilter],
+) -> Result<bool> {
+    attempt_increasing_rlimit_to_max();
 
-            scope.spawn(|_| {
-                for _ in progress_rx {
-                    progress_bar.inc();
-                }
-            });
+    let checkpoint = match Checkpoint::load(output_directory.as_ref())? {
+        Some(checkpoint) => checkpoint,
+        None => return Ok(false),
+    };
+    let checkpoint = &checkpoint;
+    let octree_meta = &checkpoint.octree_meta()?;
+    let attribute_data_types = &octree_meta.attribute_data_types_for(&checkpoint.attributes())?;
+    let octree_data_provider = &OnDiskDataProvider {
+        directory: output_directory.as_ref().to_path_buf(),
+    };
 
-            parent_ids.par_iter().for_each(|id| {
-                subsample_children_into(
-                    octree_data_provider,
-                    octree_meta,
-                    attribute_data_types,
-                    id,
-                    &finished_nodes_sender,
-                )
-                .unwrap();
-                progress_tx.send(()).unwrap();
-            });
-            drop(finished_nodes_sender);
-            drop(progress_tx);
+    if checkpoint.stage() == Stage::SPLITTING {
+        let nodes_to_split = checkpoint.nodes_to_split();
+        eprintln!("Resuming to split {} nodes.", nodes_to_split.len());
+        let (leaf_nodes_sender, leaf_nodes_receiver) = crossbeam::channel::unbounded();
+        let leaf_nodes_sender = &leaf_nodes_sender;
+        rayon::scope(move |scope| {
+            for node_id in nodes_to_split {
+                scope.spawn(move |scope| {
+                    split_stored_node(
+                        scope,
+                        octree_data_provider,
+                        octree_meta,
+                        attribute_data_types,
+                        &node_id,
+                        leaf_nodes_sender,
+                        Some(checkpoint),
+                    );
+                });
+            }
         });
-        progress_bar.finish();
-
-        // The nodes that were just now created through sub-sampling will be required to create
-        // their parents.
-        nodes_to_subsample.extend(parent_ids.into_iter());
+        leaf_nodes_receiver.try_iter().collect::<Result<Vec<_>>>()?;
     }
 
-    // Add all non-zero node meta data to meta file
-    let nodes: Vec<proto::OctreeNode> = finished_nodes
-        .iter()
-        .map(|(id, num_points)| {
-            let bounding_cube = id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box));
-            let position_encoding = PositionEncoding::new(&bounding_cube, octree_meta.resolution);
-            to_node_proto(&id, *num_points, &position_encoding)
-        })
-        .collect();
-    let meta = to_meta_proto(&octree_meta, nodes);
+    finish_build(
+        octree_data_provider,
+        octree_meta,
+        attribute_data_types,
+        checkpoint,
+        outlier_filters,
+    )?;
+    Ok(true)
+}
 
-    let mut buf_writer =
-        BufWriter::new(File::create(&output_directory.as_ref().join(META_FILENAME)).unwrap());
-    meta.write_to_writer(&mut buf_writer).unwrap();
+/// Runs the stages of the build that follow splitting, starting at the stage of the `checkpoint`,
+/// and removes it once the meta has been written.
+fn finish_build(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &OctreeMeta,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    checkpoint: &Checkpoint,
+    outlier_filters: &[OutlierFilter],
+) -> Result<()> {
+    if checkpoint.stage() == Stage::SPLITTING {
+        checkpoint.finish_splitting(octree_meta)?;
+    }
+    if checkpoint.stage() == Stage::REMOVING_OUTLIERS {
+        if outlier_filters.is_empty() {
+            checkpoint.finish_removing_outliers(&[])?;
+        } else {
+            let num_outliers = remove_outliers_in_nodes(
+                octree_data_provider,
+                octree_meta,
+                attribute_data_types,
+                &checkpoint.leaf_nodes(),
+                outlier_filters,
+                checkpoint,
+            )?;
+            eprintln!("Removed {} outliers.", num_outliers);
+        }
+    }
+    let (nodes_to_subsample, finished_nodes) = checkpoint.subsampling();
+    let finished_nodes = subsample_levels(
+        octree_data_provider,
+        octree_meta,
+        attribute_data_types,
+        nodes_to_subsample,
+        finished_nodes,
+        0,
+        Some(checkpoint),
+    )?;
+    write_meta(
+        &octree_data_provider.directory,
+        octree_meta,
+        &finished_nodes,
+    )?;
+    checkpoint.remove()
 }
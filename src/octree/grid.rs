@@ -0,0 +1,173 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::geometry::Aabb;
+use fnv::FnvHashMap;
+use nalgebra::Point3;
+
+type CellIndex = (i64, i64, i64);
+
+/// How many rings of cells around a point 'Grid::neighbors' searches at most, so that isolated
+/// points do not visit all cells of the grid.
+const MAX_NEIGHBOR_RINGS: i64 = 3;
+
+fn bounding_box(points: &[Point3<f64>]) -> Aabb {
+    let mut bounding_box = Aabb::new(points[0], points[0]);
+    for p in points {
+        bounding_box.grow(*p);
+    }
+    bounding_box
+}
+
+/// A uniform grid over the points of a node to find approximate nearest neighbors.
+pub(super) struct Grid {
+    min: Point3<f64>,
+    cell_size: f64,
+    cells: FnvHashMap<CellIndex, Vec<usize>>,
+    max_ring: i64,
+}
+
+impl Grid {
+    /// A grid with about `num_neighbors` points per cell. `points` must not be empty.
+    pub(super) fn new(points: &[Point3<f64>], num_neighbors: usize) -> Self {
+        let bounding_box = bounding_box(points);
+        let diag = bounding_box.diag();
+        let extent = diag.x.max(diag.y).max(diag.z).max(f64::EPSILON);
+        // Lidar points are mostly sampled from surfaces, so the number of occupied cells grows
+        // quadratically, not cubically, with the number of cells per axis.
+        let cells_per_axis = (points.len() as f64 / num_neighbors as f64)
+            .sqrt()
+            .ceil()
+            .max(1.);
+        let cell_size = extent / cells_per_axis;
+        let max_ring = (cells_per_axis as i64).min(MAX_NEIGHBOR_RINGS);
+        Self::with_cells(points, &bounding_box, cell_size, max_ring)
+    }
+
+    /// A grid with cells of `cell_size`, e.g. the radius of the neighborhoods to search.
+    /// `points` must not be empty.
+    pub(super) fn with_cell_size(points: &[Point3<f64>], cell_size: f64) -> Self {
+        let bounding_box = bounding_box(points);
+        let diag = bounding_box.diag();
+        let extent = diag.x.max(diag.y).max(diag.z);
+        let max_ring = (extent / cell_size).ceil() as i64;
+        Self::with_cells(points, &bounding_box, cell_size, max_ring)
+    }
+
+    fn with_cells(
+        points: &[Point3<f64>],
+        bounding_box: &Aabb,
+        cell_size: f64,
+        max_ring: i64,
+    ) -> Self {
+        let mut grid = Grid {
+            min: *bounding_box.min(),
+            cell_size,
+            cells: FnvHashMap::default(),
+            max_ring,
+        };
+        for (i, p) in points.iter().enumerate() {
+            grid.cells.entry(grid.cell_index(p)).or_default().push(i);
+        }
+        grid
+    }
+
+    fn cell_index(&self, p: &Point3<f64>) -> CellIndex {
+        let v = (p - self.min) / self.cell_size;
+        (v.x.floor() as i64, v.y.floor() as i64, v.z.floor() as i64)
+    }
+
+    /// Returns the indices of (approximately) the `num_neighbors` points closest to `p`. The
+    /// search grows ring by ring around the cell of `p` until enough candidates are found. If
+    /// fewer are returned, the other points are farther than 'search_radius'.
+    pub(super) fn neighbors(
+        &self,
+        points: &[Point3<f64>],
+        p: &Point3<f64>,
+        num_neighbors: usize,
+    ) -> Vec<usize> {
+        let (x, y, z) = self.cell_index(p);
+        let mut candidates = Vec::new();
+        let mut ring = 0;
+        while candidates.len() < num_neighbors && ring <= self.max_ring {
+            for dx in -ring..=ring {
+                for dy in -ring..=ring {
+                    for dz in -ring..=ring {
+                        // Only visit the cells on the surface of the current ring.
+                        if dx.abs() != ring && dy.abs() != ring && dz.abs() != ring {
+                            continue;
+                        }
+                        if let Some(indices) = self.cells.get(&(x + dx, y + dy, z + dz)) {
+                            candidates.extend_from_slice(indices);
+                        }
+                    }
+                }
+            }
+            ring += 1;
+        }
+        candidates.sort_by(|a, b| {
+            let da = (points[*a] - p).norm_squared();
+            let db = (points[*b] - p).norm_squared();
+            da.partial_cmp(&db).unwrap()
+        });
+        candidates.truncate(num_neighbors);
+        candidates
+    }
+
+    /// The distance from a point within which 'neighbors' finds all points if it runs out of
+    /// rings.
+    pub(super) fn search_radius(&self) -> f64 {
+        self.max_ring as f64 * self.cell_size
+    }
+
+    /// The distance to the farthest of the `neighbors` of `p` that 'neighbors' returned for
+    /// `num_neighbors`, or 'search_radius' if some of them were not found.
+    pub(super) fn reach(
+        &self,
+        points: &[Point3<f64>],
+        p: &Point3<f64>,
+        neighbors: &[usize],
+        num_neighbors: usize,
+    ) -> f64 {
+        match neighbors.last() {
+            Some(i) if neighbors.len() == num_neighbors => (points[*i] - p).norm(),
+            _ => self.search_radius(),
+        }
+    }
+
+    /// Returns the number of points within `radius` of `p`, including `p` itself if it is one of
+    /// the points.
+    pub(super) fn num_within(&self, points: &[Point3<f64>], p: &Point3<f64>, radius: f64) -> usize {
+        self.within(points, p, radius).count()
+    }
+
+    /// Returns the indices of the points within `radius` of `p`, in no particular order.
+    pub(super) fn within<'a>(
+        &'a self,
+        points: &'a [Point3<f64>],
+        p: &'a Point3<f64>,
+        radius: f64,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let (x, y, z) = self.cell_index(p);
+        let rings = (radius / self.cell_size).ceil() as i64;
+        let radius_squared = radius * radius;
+        (-rings..=rings)
+            .flat_map(move |dx| (-rings..=rings).map(move |dy| (dx, dy)))
+            .flat_map(move |(dx, dy)| (-rings..=rings).map(move |dz| (dx, dy, dz)))
+            .filter_map(move |(dx, dy, dz)| self.cells.get(&(x + dx, y + dy, z + dz)))
+            .flatten()
+            .copied()
+            .filter(move |i| (points[*i] - p).norm_squared() <= radius_squared)
+    }
+}
@@ -0,0 +1,382 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Normalization of the 'intensity' attribute while an octree is built. Intensities depend on the
+//! sensor and on how far the points are from it, so they are mapped to [0, 1] by their
+//! distribution in each input file, which makes them directly usable for coloring.
+
+use crate::errors::*;
+use crate::utils::create_progress_bar;
+use crate::{AttributeData, NumberOfPoints, PointsBatch};
+use nalgebra::Point3;
+
+/// At most this many intensities of the input are used to fit the normalization.
+const MAX_NUM_SAMPLES: usize = 1_000_000;
+
+/// The resolution of the distribution of the intensities.
+const NUM_QUANTILES: usize = 1024;
+
+/// Compensates that the returns of far points are weaker, by scaling the intensities with the
+/// squared ratio of their distance to the sensor and `reference_range`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RangeCorrection {
+    /// In the frame of the points, e.g. the position of a terrestrial scanner.
+    pub sensor_position: Point3<f64>,
+    pub reference_range: f64,
+}
+
+impl RangeCorrection {
+    fn correct(&self, intensity: f64, p: &Point3<f64>) -> f64 {
+        intensity * nalgebra::distance_squared(p, &self.sensor_position)
+            / self.reference_range.powi(2)
+    }
+}
+
+/// How the intensities of one input are normalized, see 'IntensityNormalization::fit'.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntensityNormalization {
+    /// Applied first, so that the percentiles are those of the corrected intensities.
+    pub range_correction: Option<RangeCorrection>,
+    /// The percentile of the intensities that is mapped to 0. Lower intensities are clipped.
+    pub lower_percentile: f64,
+    /// The percentile of the intensities that is mapped to 1. Higher intensities are clipped,
+    /// e.g. those of retroreflectors.
+    pub upper_percentile: f64,
+    /// Maps the intensities by their percentile instead of linearly, i.e. histogram
+    /// equalization, so that all normalized intensities are about equally frequent.
+    pub equalize: bool,
+    /// The normalized intensities are raised to this power. Below 1, dark points get brighter.
+    pub gamma: f64,
+}
+
+impl Default for IntensityNormalization {
+    fn default() -> Self {
+        IntensityNormalization {
+            range_correction: None,
+            lower_percentile: 1.,
+            upper_percentile: 99.,
+            equalize: false,
+            gamma: 1.,
+        }
+    }
+}
+
+fn intensities(batch: &PointsBatch) -> Result<&[f32]> {
+    match batch.attributes.get("intensity") {
+        Some(AttributeData::F32(intensities)) => Ok(intensities),
+        Some(other) => Err(ErrorKind::InvalidInput(format!(
+            "Intensities need to be F32, not {:?}.",
+            other.data_type()
+        ))
+        .into()),
+        None => Err(ErrorKind::InvalidInput("The points have no intensity.".to_string()).into()),
+    }
+}
+
+impl IntensityNormalization {
+    fn corrected(&self, intensity: f32, p: &Point3<f64>) -> f64 {
+        let intensity = f64::from(intensity);
+        self.range_correction
+            .map_or(intensity, |correction| correction.correct(intensity, p))
+    }
+
+    /// Fits the normalization to the intensities of `input`, of which at most 'MAX_NUM_SAMPLES'
+    /// evenly spread ones are used. Points without an intensity, i.e. NaN, are ignored.
+    pub fn fit(
+        &self,
+        input: impl Iterator<Item = PointsBatch> + NumberOfPoints,
+    ) -> Result<IntensityMapping> {
+        let valid_percentiles = 0. <= self.lower_percentile
+            && self.lower_percentile < self.upper_percentile
+            && self.upper_percentile <= 100.;
+        if !valid_percentiles {
+            return Err(ErrorKind::InvalidInput(format!(
+                "The percentiles need to be in [0, 100] and increasing, but are {} and {}.",
+                self.lower_percentile, self.upper_percentile
+            ))
+            .into());
+        }
+        if self.gamma.is_nan() || self.gamma <= 0. {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Gamma must be positive, but is {}.",
+                self.gamma
+            ))
+            .into());
+        }
+        if let Some(correction) = &self.range_correction {
+            if correction.reference_range.is_nan() || correction.reference_range <= 0. {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "The reference range must be positive, but is {}.",
+                    correction.reference_range
+                ))
+                .into());
+            }
+        }
+
+        let num_points = input.num_points();
+        let stride = (num_points / MAX_NUM_SAMPLES).max(1);
+        let mut progress_bar = create_progress_bar(num_points, "Sampling intensities");
+        let mut samples = Vec::new();
+        let mut index = 0;
+        for batch in input {
+            for (p, intensity) in batch.position.iter().zip(intensities(&batch)?) {
+                if index % stride == 0 && !intensity.is_nan() {
+                    samples.push(self.corrected(*intensity, p));
+                }
+                index += 1;
+            }
+            progress_bar.add(batch.position.len() as u64);
+        }
+        progress_bar.finish();
+        if samples.is_empty() {
+            return Err(ErrorKind::InvalidInput(
+                "There are no intensities to normalize.".to_string(),
+            )
+            .into());
+        }
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let quantiles = (0..=NUM_QUANTILES)
+            .map(|i| samples[i * (samples.len() - 1) / NUM_QUANTILES])
+            .collect();
+        let mut mapping = IntensityMapping {
+            normalization: self.clone(),
+            quantiles,
+            lower_bound: 0.,
+            upper_bound: 0.,
+        };
+        mapping.lower_bound = mapping.quantile(self.lower_percentile / 100.);
+        mapping.upper_bound = mapping.quantile(self.upper_percentile / 100.);
+        Ok(mapping)
+    }
+}
+
+/// A normalization fitted to the intensities of an input, see 'IntensityNormalization::fit'.
+#[derive(Clone, Debug)]
+pub struct IntensityMapping {
+    normalization: IntensityNormalization,
+    /// The corrected intensities below which evenly spaced fractions of the samples are.
+    quantiles: Vec<f64>,
+    /// The corrected intensities at the percentiles of the normalization.
+    lower_bound: f64,
+    upper_bound: f64,
+}
+
+impl IntensityMapping {
+    /// The corrected intensity below which `fraction` of the samples are.
+    fn quantile(&self, fraction: f64) -> f64 {
+        let x = fraction * NUM_QUANTILES as f64;
+        let i = (x.floor() as usize).min(NUM_QUANTILES - 1);
+        let t = x - i as f64;
+        self.quantiles[i] + t * (self.quantiles[i + 1] - self.quantiles[i])
+    }
+
+    /// The fraction of the samples below the corrected intensity `value`.
+    fn fraction_below(&self, value: f64) -> f64 {
+        let i = self.quantiles.partition_point(|q| *q <= value);
+        if i == 0 {
+            return 0.;
+        }
+        if i == self.quantiles.len() {
+            return 1.;
+        }
+        let (below, above) = (self.quantiles[i - 1], self.quantiles[i]);
+        ((i - 1) as f64 + (value - below) / (above - below)) / NUM_QUANTILES as f64
+    }
+
+    /// The normalized intensity in [0, 1] of a point at `p`. NaN stays NaN.
+    pub fn normalize(&self, intensity: f32, p: &Point3<f64>) -> f32 {
+        if intensity.is_nan() {
+            return intensity;
+        }
+        let value = self.normalization.corrected(intensity, p);
+        let (lower, upper, value) = if self.normalization.equalize {
+            (
+                self.normalization.lower_percentile / 100.,
+                self.normalization.upper_percentile / 100.,
+                self.fraction_below(value),
+            )
+        } else {
+            (self.lower_bound, self.upper_bound, value)
+        };
+        // All intensities between the percentiles are the same.
+        if upper <= lower {
+            return if value < lower { 0. } else { 1. };
+        }
+        let normalized = ((value - lower) / (upper - lower)).clamp(0., 1.);
+        normalized.powf(self.normalization.gamma) as f32
+    }
+
+    /// Replaces the intensities of the points in `batch` by their normalized ones.
+    pub fn apply(&self, batch: &mut PointsBatch) -> Result<()> {
+        intensities(batch)?;
+        if let Some(AttributeData::F32(intensities)) = batch.attributes.get_mut("intensity") {
+            for (intensity, p) in intensities.iter_mut().zip(&batch.position) {
+                *intensity = self.normalize(*intensity, p);
+            }
+        }
+        Ok(())
+    }
+
+    /// Normalizes the intensities of the batches of `input` one by one, e.g. for building an
+    /// octree out of them. Panics on batches without F32 intensities.
+    pub fn normalize_batches<I>(self, input: I) -> NormalizedBatches<I>
+    where
+        I: Iterator<Item = PointsBatch> + NumberOfPoints,
+    {
+        NormalizedBatches {
+            mapping: self,
+            input,
+        }
+    }
+}
+
+/// The batches of an input with normalized intensities, see 'IntensityMapping::normalize_batches'.
+pub struct NormalizedBatches<I> {
+    mapping: IntensityMapping,
+    input: I,
+}
+
+impl<I: Iterator<Item = PointsBatch>> Iterator for NormalizedBatches<I> {
+    type Item = PointsBatch;
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        let mut batch = self.input.next()?;
+        self.mapping
+            .apply(&mut batch)
+            .unwrap_or_else(|err| panic!("Could not normalize the intensities: {}", err));
+        Some(batch)
+    }
+}
+
+impl<I: NumberOfPoints> NumberOfPoints for NormalizedBatches<I> {
+    fn num_points(&self) -> usize {
+        self.input.num_points()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    struct Batches(Vec<PointsBatch>);
+
+    impl Iterator for Batches {
+        type Item = PointsBatch;
+
+        fn next(&mut self) -> Option<PointsBatch> {
+            self.0.pop()
+        }
+    }
+
+    impl NumberOfPoints for Batches {
+        fn num_points(&self) -> usize {
+            self.0.iter().map(|batch| batch.position.len()).sum()
+        }
+    }
+
+    fn batch(position: Vec<Point3<f64>>, intensity: Vec<f32>) -> PointsBatch {
+        let mut attributes = BTreeMap::new();
+        attributes.insert("intensity".to_string(), AttributeData::F32(intensity));
+        PointsBatch {
+            position,
+            attributes,
+        }
+    }
+
+    #[test]
+    fn test_percentile_clipping_and_gamma() {
+        // Intensities 0 to 1000, and a retroreflector.
+        let mut intensity: Vec<f32> = (0..=1000).map(|i| i as f32).collect();
+        intensity.push(1e6);
+        let position = vec![Point3::origin(); intensity.len()];
+        let input = Batches(vec![batch(position, intensity)]);
+        let origin = Point3::origin();
+
+        let normalization = IntensityNormalization::default();
+        let mapping = normalization.fit(input).unwrap();
+        assert_eq!(mapping.normalize(0., &origin), 0.);
+        assert!((mapping.normalize(500., &origin) - 0.5).abs() < 0.01);
+        assert_eq!(mapping.normalize(1e6, &origin), 1.);
+        assert!(mapping.normalize(f32::NAN, &origin).is_nan());
+
+        let gamma = IntensityNormalization {
+            gamma: 0.5,
+            ..normalization
+        };
+        let intensity: Vec<f32> = (0..=1000).map(|i| i as f32).collect();
+        let input = Batches(vec![batch(vec![origin; intensity.len()], intensity)]);
+        let mapping = gamma.fit(input).unwrap();
+        let normalized = mapping.normalize(250., &origin);
+        assert!((normalized.powi(2) - 0.245).abs() < 0.01, "{}", normalized);
+    }
+
+    #[test]
+    fn test_equalization_and_range_correction() {
+        // Most intensities are dark, which equalization spreads out.
+        let intensity: Vec<f32> = (0..1000)
+            .map(|i| if i < 900 { i as f32 / 900. } else { 100. })
+            .collect();
+        let origin = Point3::origin();
+        let input = Batches(vec![batch(vec![origin; intensity.len()], intensity)]);
+        let normalization = IntensityNormalization {
+            lower_percentile: 0.,
+            upper_percentile: 100.,
+            equalize: true,
+            ..Default::default()
+        };
+        let mapping = normalization.fit(input).unwrap();
+        assert!((mapping.normalize(0.5, &origin) - 0.45).abs() < 0.01);
+        assert_eq!(mapping.normalize(100., &origin), 1.);
+
+        // Two stripes of different reflectivity on a wall, whose returns weaken with the
+        // distance to the sensor.
+        let sensor_position = Point3::new(0., 0., 2.);
+        let position: Vec<Point3<f64>> = (1..=100)
+            .flat_map(|i| {
+                vec![
+                    Point3::new(f64::from(i), 0., 2.),
+                    Point3::new(f64::from(i), 0., 3.),
+                ]
+            })
+            .collect();
+        let intensity = position
+            .iter()
+            .map(|p| (p.z - 1.) / nalgebra::distance_squared(p, &sensor_position))
+            .map(|intensity| intensity as f32)
+            .collect();
+        let mut points = batch(position, intensity);
+        let normalization = IntensityNormalization {
+            range_correction: Some(RangeCorrection {
+                sensor_position,
+                reference_range: 10.,
+            }),
+            lower_percentile: 0.,
+            upper_percentile: 100.,
+            ..Default::default()
+        };
+        let mapping = normalization.fit(Batches(vec![points.clone()])).unwrap();
+        mapping.apply(&mut points).unwrap();
+        let intensity: &Vec<f32> = points.get_attribute_vec("intensity").unwrap();
+        for (p, intensity) in points.position.iter().zip(intensity) {
+            let expected = if p.z < 2.5 { 0. } else { 1. };
+            assert!(
+                (intensity - expected).abs() < 0.05,
+                "{:?}: {}",
+                p,
+                intensity
+            );
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::geometry::{Cube, Frustum};
+use crate::math::sat::{ConvexPolyhedron, Relation};
+use crate::octree::{relative_size_on_screen, ChildIndex, Node, NodeId, Octree};
+use nalgebra::Matrix4;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// The size of the image that is rendered, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Viewport {
+    pub fn new(width: u32, height: u32) -> Self {
+        Viewport { width, height }
+    }
+}
+
+/// A node selected by 'Octree::select_nodes_for_view'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodNode {
+    pub id: NodeId,
+    pub num_points: i64,
+    /// The estimated distance in pixels between neighboring points on screen, counting the points
+    /// of the selected ancestors too. Drawing the points this large closes the gaps between them.
+    pub point_size: f64,
+}
+
+struct OpenNode {
+    node: Node,
+    relation: Relation,
+    size_on_screen: f64,
+    num_points: i64,
+    /// The estimated number of points of the selected ancestors inside this node.
+    ancestor_points: f64,
+}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &OpenNode) -> Ordering {
+        self.size_on_screen
+            .partial_cmp(&other.size_on_screen)
+            .unwrap()
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &OpenNode) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for OpenNode {
+    fn eq(&self, other: &OpenNode) -> bool {
+        self.size_on_screen == other.size_on_screen
+    }
+}
+
+impl Eq for OpenNode {}
+
+impl Octree {
+    #[inline]
+    fn maybe_push_node(
+        &self,
+        v: &mut BinaryHeap<OpenNode>,
+        relation: Relation,
+        node: Node,
+        projection_matrix: &Matrix4<f64>,
+        ancestor_points: f64,
+    ) {
+        if let Some(meta) = self.nodes.get(&node.id) {
+            let size_on_screen = relative_size_on_screen(&node.bounding_cube, projection_matrix);
+            v.push(OpenNode {
+                node,
+                relation,
+                size_on_screen,
+                num_points: meta.num_points,
+                ancestor_points,
+            });
+        }
+    }
+
+    /// Returns the nodes with points in `frustum` to render, the largest on screen first, with
+    /// at most `point_budget` points in total. A node whose points do not fit into the budget is
+    /// skipped together with its subtree, since its children only refine it.
+    pub fn select_nodes_for_view(
+        &self,
+        frustum: &Frustum,
+        viewport: Viewport,
+        point_budget: usize,
+    ) -> Vec<LodNode> {
+        let projection_matrix = frustum.clip_from_query();
+        let frustum_isec = frustum.intersector().cache_separating_axes_for_aabb();
+        // The sizes on screen are fractions of the area of clip space, which is 2 x 2.
+        let pixels_per_size = f64::from(viewport.width) * f64::from(viewport.height) / 4.;
+        let mut open = BinaryHeap::new();
+        self.maybe_push_node(
+            &mut open,
+            Relation::Cross,
+            Node::root_with_bounding_cube(Cube::bounding(&self.meta.bounding_box)),
+            projection_matrix,
+            0.,
+        );
+
+        let mut selected = Vec::new();
+        let mut num_selected_points = 0;
+        while let Some(current) = open.pop() {
+            let num_points = current.num_points as usize;
+            if num_selected_points + num_points > point_budget {
+                continue;
+            }
+            num_selected_points += num_points;
+            let points_in_node = current.ancestor_points + current.num_points as f64;
+            for child_index in 0..8 {
+                let child = current.node.get_child(ChildIndex::from_u8(child_index));
+                let child_relation = match current.relation {
+                    Relation::Cross => {
+                        frustum_isec.intersect(&child.bounding_cube.to_aabb().compute_corners())
+                    }
+                    // When the parent is fully in the frustum, so are the children.
+                    Relation::In => Relation::In,
+                    // This should never happen.
+                    Relation::Out => unreachable!(),
+                };
+                if child_relation == Relation::Out {
+                    continue;
+                }
+                self.maybe_push_node(
+                    &mut open,
+                    child_relation,
+                    child,
+                    projection_matrix,
+                    points_in_node / 8.,
+                );
+            }
+            if current.num_points > 0 {
+                let pixels = current.size_on_screen * pixels_per_size;
+                selected.push(LodNode {
+                    id: current.node.id,
+                    num_points: current.num_points,
+                    point_size: (pixels / points_in_node).sqrt(),
+                });
+            }
+        }
+        selected
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::geometry::{Aabb, Cube};
+use crate::iterator::{PointCloud, PointLocation, PointQuery, TIMESTAMP_ATTRIBUTE};
+use crate::metadata::{Metadata, ProcessingStep};
+use crate::octree::generation::build_octree_with_meta;
+use crate::octree::grid::Grid;
+use crate::octree::{ChildIndex, Node, NodeId, Octree, OctreeMeta};
+use crate::{
+    AttributeData, AttributeDataType, NumberOfPoints, PointCloudMeta, PointsBatch,
+    NUM_POINTS_PER_BATCH,
+};
+use nalgebra::{Point3, Vector3};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The most points that are deduplicated at once, estimated from the nodes overlapping a tile.
+const MAX_POINTS_PER_TILE: f64 = 2_000_000.;
+
+/// Tiles are not split below this many epsilons, so that their margins stay small.
+const MIN_TILE_EDGE_LENGTH_IN_EPSILONS: f64 = 64.;
+
+/// Which of the points within 'Deduplication::epsilon' of each other is kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePreference {
+    /// The point with the highest intensity, e.g. the one scanned from closest by.
+    Intensity,
+    /// The point with the latest timestamp, e.g. to replace an old scan of a changed area.
+    Timestamp,
+}
+
+impl DuplicatePreference {
+    fn attribute(self) -> &'static str {
+        match self {
+            DuplicatePreference::Intensity => "intensity",
+            DuplicatePreference::Timestamp => TIMESTAMP_ATTRIBUTE,
+        }
+    }
+}
+
+impl FromStr for DuplicatePreference {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "intensity" => Ok(DuplicatePreference::Intensity),
+            "timestamp" => Ok(DuplicatePreference::Timestamp),
+            _ => Err(
+                ErrorKind::InvalidInput(format!("Unknown duplicate preference '{}'.", s)).into(),
+            ),
+        }
+    }
+}
+
+/// Removes points that were scanned more than once, e.g. where the octrees of a merge overlap.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Deduplication {
+    /// Points within this distance of a preferred point are removed.
+    pub epsilon: f64,
+    pub keep: DuplicatePreference,
+}
+
+impl Deduplication {
+    /// Returns for each point of `batch` whether it is kept. The points are visited from the most
+    /// to the least preferred, and each one is kept unless a kept point is within 'epsilon' of it.
+    /// Of equally preferred points, the earlier one is kept.
+    pub fn keep(&self, batch: &PointsBatch) -> Result<Vec<bool>> {
+        let points = &batch.position;
+        if points.is_empty() {
+            return Ok(Vec::new());
+        }
+        let name = self.keep.attribute();
+        let priorities: Vec<f64> = match batch.attributes.get(name) {
+            Some(AttributeData::F32(values)) => values.iter().map(|v| f64::from(*v)).collect(),
+            Some(AttributeData::F64(values)) => values.clone(),
+            _ => {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "Duplicates can only be resolved by '{}' if the points have it as floats.",
+                    name
+                ))
+                .into())
+            }
+        };
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        // The sort is stable, so earlier points win ties.
+        order.sort_by(|a, b| {
+            priorities[*b]
+                .partial_cmp(&priorities[*a])
+                .unwrap_or(Ordering::Equal)
+        });
+        let grid = Grid::with_cell_size(points, self.epsilon);
+        let mut keep = vec![false; points.len()];
+        for i in order {
+            let is_duplicate = grid
+                .within(points, &points[i], self.epsilon)
+                .any(|j| keep[j]);
+            keep[i] = !is_duplicate;
+        }
+        Ok(keep)
+    }
+}
+
+/// An octree to merge, with the attributes its points have.
+struct MergeInput {
+    octree: Octree,
+    attributes: Vec<String>,
+}
+
+impl MergeInput {
+    fn load(directory: &Path) -> Result<Self> {
+        let octree_data_provider = OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        };
+        let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        }))?;
+        // Every node of a build has the same attributes.
+        let attributes = match octree
+            .nodes
+            .iter()
+            .find(|(_, node_meta)| node_meta.num_points > 0)
+        {
+            Some((node_id, _)) => octree
+                .attributes_on_disk(&octree_data_provider, node_id)
+                .into_keys()
+                .collect(),
+            None => Vec::new(),
+        };
+        Ok(MergeInput { octree, attributes })
+    }
+
+    fn attribute_names(&self) -> Vec<&str> {
+        self.attributes.iter().map(String::as_str).collect()
+    }
+}
+
+/// Adds the attributes of `attribute_data_types` that `batch` does not have, as zeros.
+fn add_missing_attributes(
+    batch: &mut PointsBatch,
+    attribute_data_types: &BTreeMap<String, AttributeDataType>,
+) {
+    let num_points = batch.position.len();
+    for (name, data_type) in attribute_data_types {
+        batch
+            .attributes
+            .entry(name.clone())
+            .or_insert_with(|| AttributeData::zeros(*data_type, num_points));
+    }
+}
+
+/// The fraction of the volume of `cube` inside `aabb`.
+fn overlap_fraction(cube: &Cube, aabb: &Aabb) -> f64 {
+    let min = cube.min().sup(aabb.min());
+    let max = cube.max().inf(aabb.max());
+    let overlap: Vector3<f64> = (max - min).map(|extent| extent.max(0.));
+    overlap.x * overlap.y * overlap.z / cube.edge_length().powi(3).max(f64::MIN_POSITIVE)
+}
+
+/// The number of points of `inputs` in `aabb`, assuming that they are spread evenly over their
+/// nodes. None if no node overlaps it.
+fn estimated_num_points(inputs: &[MergeInput], aabb: &Aabb) -> Option<f64> {
+    let location = PointLocation::Aabb(aabb.clone());
+    let mut estimate = None;
+    for input in inputs {
+        for node_id in input.octree.nodes_in_location(&location) {
+            let node_meta = &input.octree.nodes[&node_id];
+            if node_meta.num_points > 0 {
+                *estimate.get_or_insert(0.) +=
+                    node_meta.num_points as f64 * overlap_fraction(&node_meta.bounding_cube, aabb);
+            }
+        }
+    }
+    estimate
+}
+
+/// Splits `tile` like an octree node until each tile holds at most `max_points_per_tile` points,
+/// and adds those with points to `tiles`.
+fn split_into_tiles(
+    inputs: &[MergeInput],
+    tile: Node,
+    epsilon: f64,
+    max_points_per_tile: f64,
+    tiles: &mut Vec<Node>,
+) {
+    let num_points = match estimated_num_points(inputs, &tile.bounding_cube.to_aabb()) {
+        Some(num_points) => num_points,
+        None => return,
+    };
+    let min_edge_length = MIN_TILE_EDGE_LENGTH_IN_EPSILONS * epsilon;
+    if num_points <= max_points_per_tile || tile.bounding_cube.edge_length() < min_edge_length {
+        tiles.push(tile);
+        return;
+    }
+    for child_index in 0..8 {
+        let child = tile.get_child(ChildIndex::from_u8(child_index));
+        split_into_tiles(inputs, child, epsilon, max_points_per_tile, tiles);
+    }
+}
+
+/// The id of the node at `level` below the root with `root_cube` that `p` falls into, the same
+/// way points are split into the children of a node.
+fn tile_id(root_cube: &Cube, level: u8, p: &Point3<f64>) -> NodeId {
+    let mut node = Node::root_with_bounding_cube(root_cube.clone());
+    for _ in 0..level {
+        node = node.get_child(ChildIndex::from_bounding_cube(&node.bounding_cube, p));
+    }
+    node.id
+}
+
+/// Reads the points of all `inputs` within the 'epsilon' of the `deduplication` around `tile`,
+/// so that duplicates across tile borders are found, and returns the kept points inside it.
+fn deduplicated_points_in_tile(
+    inputs: &[MergeInput],
+    attribute_data_types: &BTreeMap<String, AttributeDataType>,
+    root_cube: &Cube,
+    tile: &Node,
+    deduplication: &Deduplication,
+) -> Result<PointsBatch> {
+    let margin = Vector3::repeat(deduplication.epsilon);
+    let location = PointLocation::Aabb(Aabb::new(
+        tile.bounding_cube.min() - margin,
+        tile.bounding_cube.max() + margin,
+    ));
+    let mut batch = PointsBatch {
+        position: Vec::new(),
+        attributes: BTreeMap::new(),
+    };
+    for input in inputs {
+        let query = PointQuery {
+            attributes: input.attribute_names(),
+            location: location.clone(),
+            ..Default::default()
+        };
+        for node_id in input.octree.nodes_for_query(&query) {
+            input.octree.stream_points_for_query_in_node(
+                &query,
+                node_id,
+                NUM_POINTS_PER_BATCH,
+                |mut points| {
+                    add_missing_attributes(&mut points, attribute_data_types);
+                    batch.append(&mut points).map_err(Error::from)
+                },
+            )?;
+        }
+    }
+    let level = tile.id.level();
+    let keep: Vec<bool> = deduplication
+        .keep(&batch)?
+        .into_iter()
+        .zip(&batch.position)
+        .map(|(keep, p)| keep && tile_id(root_cube, level, p) == tile.id)
+        .collect();
+    batch.retain(&keep);
+    Ok(batch)
+}
+
+/// The points of all inputs of a merge, with the union of their attributes.
+struct MergedBatches<'a> {
+    batches: Box<dyn Iterator<Item = PointsBatch> + Send + 'a>,
+    num_points: usize,
+}
+
+impl<'a> Iterator for MergedBatches<'a> {
+    type Item = PointsBatch;
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        self.batches.next()
+    }
+}
+
+impl<'a> NumberOfPoints for MergedBatches<'a> {
+    fn num_points(&self) -> usize {
+        self.num_points
+    }
+}
+
+/// Merges the octrees in `input_directories` into a new octree in `output_directory`. Its points
+/// have the union of the attributes of the inputs, and points without one of them get zeros. The
+/// inputs need to be in the same coordinate system and have the same labels, if any. Without a
+/// `resolution`, the finest one of the inputs is used.
+///
+/// With a `deduplication`, the points within its epsilon of a preferred point are removed, e.g.
+/// where scans overlap. This also thins out the points of each input that are that close.
+pub fn merge_octrees(
+    output_directory: impl AsRef<Path>,
+    input_directories: &[impl AsRef<Path>],
+    resolution: Option<f64>,
+    deduplication: Option<&Deduplication>,
+) -> Result<()> {
+    merge_octrees_with_max_tile_points(
+        output_directory,
+        input_directories,
+        resolution,
+        deduplication,
+        MAX_POINTS_PER_TILE,
+    )
+}
+
+pub(super) fn merge_octrees_with_max_tile_points(
+    output_directory: impl AsRef<Path>,
+    input_directories: &[impl AsRef<Path>],
+    resolution: Option<f64>,
+    deduplication: Option<&Deduplication>,
+    max_points_per_tile: f64,
+) -> Result<()> {
+    let inputs = input_directories
+        .iter()
+        .map(|directory| MergeInput::load(directory.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    let first = inputs
+        .first()
+        .ok_or_else(|| ErrorKind::InvalidInput("There are no octrees to merge.".to_string()))?;
+
+    let mut coordinate_system = None;
+    let mut label_dictionary = None;
+    let mut bounding_box = first.octree.meta.bounding_box.clone();
+    for input in &inputs {
+        if let Some(system) = input.octree.coordinate_system() {
+            match coordinate_system {
+                Some(other) if other != system => {
+                    return Err(ErrorKind::InvalidInput(format!(
+                        "The octrees are in different coordinate systems, '{}' and '{}'.",
+                        other, system
+                    ))
+                    .into())
+                }
+                _ => coordinate_system = Some(system),
+            }
+        }
+        if let Some(dictionary) = input.octree.label_dictionary() {
+            match label_dictionary {
+                Some(other) if other != dictionary => {
+                    return Err(ErrorKind::InvalidInput(
+                        "The octrees have different label dictionaries.".to_string(),
+                    )
+                    .into())
+                }
+                _ => label_dictionary = Some(dictionary),
+            }
+        }
+        bounding_box.grow(*input.octree.meta.bounding_box.min());
+        bounding_box.grow(*input.octree.meta.bounding_box.max());
+    }
+    let resolution = resolution.unwrap_or_else(|| {
+        inputs
+            .iter()
+            .map(|input| input.octree.meta.resolution)
+            .fold(f64::INFINITY, f64::min)
+    });
+    let mut octree_meta = OctreeMeta::new_with_standard_attributes(resolution, bounding_box);
+    octree_meta.coordinate_system = coordinate_system;
+    let input_metadata: Vec<&Metadata> = inputs
+        .iter()
+        .map(|input| &input.octree.meta.metadata)
+        .collect();
+    octree_meta.metadata = Metadata::combined(&input_metadata);
+    let mut description = format!("Merged {} octrees", inputs.len());
+    if let Some(deduplication) = deduplication {
+        description += &format!(
+            ", keeping the point with the highest {} within {} m",
+            deduplication.keep.attribute(),
+            deduplication.epsilon
+        );
+    }
+    octree_meta
+        .metadata
+        .processing_history
+        .push(ProcessingStep::now("octree merge", description + "."));
+    if let Some(dictionary) = label_dictionary {
+        octree_meta.set_label_dictionary(dictionary.clone());
+    }
+
+    let mut attribute_data_types = BTreeMap::new();
+    for input in &inputs {
+        for (name, data_type) in input.octree.meta.attribute_data_types() {
+            if !input.attributes.contains(name) {
+                continue;
+            }
+            if octree_meta.attribute_data_types().get(name) != Some(data_type) {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "Octrees can not store the attribute '{}' of type {:?}.",
+                    name, data_type
+                ))
+                .into());
+            }
+            attribute_data_types.insert(name.clone(), *data_type);
+        }
+    }
+    if let Some(deduplication) = deduplication {
+        if deduplication.epsilon <= 0. {
+            return Err(ErrorKind::InvalidInput(
+                "The epsilon of the deduplication needs to be positive.".to_string(),
+            )
+            .into());
+        }
+        let name = deduplication.keep.attribute();
+        if !attribute_data_types.contains_key(name) {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Duplicates can only be resolved by '{}' if the points have it.",
+                name
+            ))
+            .into());
+        }
+    }
+
+    let num_points = inputs
+        .iter()
+        .flat_map(|input| input.octree.nodes.values())
+        .map(|node_meta| node_meta.num_points as usize)
+        .sum();
+    let inputs = &inputs;
+    let attribute_data_types = &attribute_data_types;
+    let batches: Box<dyn Iterator<Item = PointsBatch> + Send> = match deduplication {
+        Some(deduplication) => {
+            let root_cube = Cube::bounding(&octree_meta.bounding_box);
+            let mut tiles = Vec::new();
+            split_into_tiles(
+                inputs,
+                Node::root_with_bounding_cube(root_cube.clone()),
+                deduplication.epsilon,
+                max_points_per_tile,
+                &mut tiles,
+            );
+            eprintln!("Removing duplicates in {} tiles.", tiles.len());
+            let deduplication = *deduplication;
+            Box::new(
+                tiles
+                    .into_iter()
+                    .map(move |tile| {
+                        deduplicated_points_in_tile(
+                            inputs,
+                            attribute_data_types,
+                            &root_cube,
+                            &tile,
+                            &deduplication,
+                        )
+                        .unwrap_or_else(|err| panic!("Could not merge tile {}: {}", tile.id, err))
+                    })
+                    .filter(|batch| !batch.position.is_empty()),
+            )
+        }
+        None => Box::new(
+            inputs
+                .iter()
+                .flat_map(|input| {
+                    input
+                        .octree
+                        .nodes_in_location(&PointLocation::AllPoints)
+                        .into_iter()
+                        .filter(move |node_id| input.octree.nodes[node_id].num_points > 0)
+                        .map(move |node_id| (input, node_id))
+                })
+                .flat_map(|(input, node_id)| {
+                    input
+                        .octree
+                        .points_in_node(&input.attribute_names(), node_id, NUM_POINTS_PER_BATCH)
+                        .unwrap_or_else(|err| panic!("Could not read node {}: {}", node_id, err))
+                })
+                .map(move |mut batch| {
+                    add_missing_attributes(&mut batch, attribute_data_types);
+                    batch
+                }),
+        ),
+    };
+
+    let attribute_names: Vec<&str> = attribute_data_types.keys().map(String::as_str).collect();
+    build_octree_with_meta(
+        output_directory,
+        octree_meta,
+        MergedBatches {
+            batches,
+            num_points,
+        },
+        &attribute_names,
+        &[],
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deduplication_keeps_preferred_points() {
+        let batch = PointsBatch {
+            position: vec![
+                Point3::new(0., 0., 0.),
+                Point3::new(0.05, 0., 0.),
+                Point3::new(1., 0., 0.),
+                Point3::new(1., 0.05, 0.),
+                Point3::new(5., 0., 0.),
+            ],
+            attributes: vec![
+                (
+                    "intensity".to_string(),
+                    AttributeData::F32(vec![1., 2., 3., 3., 0.]),
+                ),
+                (
+                    "timestamp".to_string(),
+                    AttributeData::F64(vec![20., 10., 0., 5., 0.]),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let by_intensity = Deduplication {
+            epsilon: 0.1,
+            keep: DuplicatePreference::Intensity,
+        };
+        // Of the equally bright points, the first one is kept.
+        assert_eq!(
+            by_intensity.keep(&batch).unwrap(),
+            vec![false, true, true, false, true]
+        );
+        let by_timestamp = Deduplication {
+            keep: DuplicatePreference::Timestamp,
+            ..by_intensity
+        };
+        assert_eq!(
+            by_timestamp.keep(&batch).unwrap(),
+            vec![true, false, false, true, true]
+        );
+        let far_apart = Deduplication {
+            epsilon: 0.01,
+            ..by_intensity
+        };
+        assert!(far_apart.keep(&batch).unwrap().iter().all(|keep| *keep));
+    }
+}
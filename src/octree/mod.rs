@@ -11,32 +11,95 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use crate::coordinates::CoordinateSystem;
 use crate::data_provider::DataProvider;
+use crate::downsample::VoxelSize;
 use crate::errors::*;
-use crate::geometry::{Aabb, Cube, Frustum};
-use crate::iterator::{PointCloud, PointLocation};
+use crate::geometry::{Aabb, Cube, Frustum, PickRadius, Ray};
+use crate::iterator::{
+    pick_in_node, AttributeUpdate, PickHit, PointCloud, PointLocation, PointQuery,
+};
+use crate::labels::{LabelDictionary, RunLengthDecoder, CLASSIFICATION_ATTRIBUTE};
 use crate::math::base::{HasAabbIntersector, IntersectAabb};
-use crate::math::sat::{ConvexPolyhedron, Relation};
-use crate::math::AllPoints;
+use crate::math::{AllPoints, ClosedInterval};
+use crate::metadata::Metadata;
 use crate::proto;
 use crate::read_write::{Encoding, NodeIterator, PositionEncoding};
-use crate::{AttributeDataType, PointCloudMeta, CURRENT_VERSION};
+use crate::statistics::PointStatistics;
+#[cfg(feature = "async")]
+use crate::stream::NodeIteratorFuture;
+use crate::{AttributeDataType, PointCloudMeta, PointsBatch, CURRENT_VERSION};
 use fnv::FnvHashMap;
-use nalgebra::{Matrix4, Point3};
+use nalgebra::{Matrix4, Point3, Vector3};
 use num::clamp;
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BTreeMap, HashMap};
 use std::io::{BufReader, Read};
+use std::sync::{Arc, Mutex};
+
+mod attribute_ranges;
+pub use self::attribute_ranges::AttributeRanges;
+
+mod checkpoint;
+
+mod compression;
+pub use self::compression::{deflate, inflate, NodeCompression};
+
+mod delete;
+
+mod export;
+pub use self::export::{export_octree, ExportFormat};
 
 mod generation;
-pub use self::generation::{build_octree, build_octree_from_file};
+pub use self::generation::{
+    build_filtered_octree, build_labeled_octree, build_octree, build_octree_from_file,
+    resume_octree,
+};
+
+mod grid;
+
+mod intensity;
+pub use self::intensity::{
+    IntensityMapping, IntensityNormalization, NormalizedBatches, RangeCorrection,
+};
 
 mod node;
 pub use self::node::{to_node_proto, ChildIndex, Node, NodeId, NodeMeta};
 
+mod normals;
+pub use self::normals::{estimate_normals, estimate_normals_for_points, NUM_NORMAL_NEIGHBORS};
+
+mod lod;
+pub use self::lod::{LodNode, Viewport};
+
+mod merge;
+pub use self::merge::{merge_octrees, Deduplication, DuplicatePreference};
+
+mod neighborhood;
+
+mod node_cache;
+pub use self::node_cache::{NodeCache, NodeCacheStats};
+
+mod outliers;
+pub use self::outliers::{remove_outliers, OutlierFilter};
+
 mod octree_iterator;
 pub use self::octree_iterator::NodeIdsIterator;
 
+mod s2_conversion;
+pub use self::s2_conversion::{
+    build_octree_from_s2_cells, write_s2_cells_from_file, write_s2_cells_from_octree,
+};
+
+mod statistics;
+
+mod update;
+pub use self::update::update;
+
+mod update_attribute;
+
+mod validate;
+pub use self::validate::{repair_octree, validate_octree, OctreeProblem};
+
 #[cfg(test)]
 mod tests;
 
@@ -44,7 +107,11 @@ mod tests;
 pub struct OctreeMeta {
     pub resolution: f64,
     pub bounding_box: Aabb,
+    /// None if it is not known, e.g. for octrees built before it was stored.
+    pub coordinate_system: Option<CoordinateSystem>,
+    pub metadata: Metadata,
     attribute_data_types: HashMap<String, AttributeDataType>,
+    label_dictionary: Option<LabelDictionary>,
 }
 
 impl PointCloudMeta for OctreeMeta {
@@ -54,25 +121,42 @@ impl PointCloudMeta for OctreeMeta {
 }
 
 impl OctreeMeta {
-    /// An octree currently does not store its data types, instead, color and
-    /// intensity are implied. We already do have attributes as part of the
-    /// meta data structure, but not its serialized form. So the data structure
-    /// is initialized with color and intensity hardcoded until attributes are
-    /// in the meta proto.
+    /// An octree currently does not store its data types, instead, color,
+    /// intensity and normal are implied. We already do have attributes as part
+    /// of the meta data structure, but not its serialized form. So the data
+    /// structure is initialized with these hardcoded until attributes are in
+    /// the meta proto. Normals only exist if they have been estimated, timestamps only if the
+    /// input points had them.
     pub fn new_with_standard_attributes(resolution: f64, bounding_box: Aabb) -> Self {
         let attribute_data_types = vec![
             ("color".to_string(), AttributeDataType::U8Vec3),
             ("intensity".to_string(), AttributeDataType::F32),
+            ("normal".to_string(), AttributeDataType::F64Vec3),
+            ("timestamp".to_string(), AttributeDataType::F64),
         ]
         .into_iter()
         .collect();
         Self {
             resolution,
             bounding_box,
+            coordinate_system: None,
+            metadata: Metadata::default(),
             attribute_data_types,
+            label_dictionary: None,
         }
     }
 
+    /// Adds the classification attribute, with the data type and label names of `dictionary`.
+    pub fn set_label_dictionary(&mut self, dictionary: LabelDictionary) {
+        self.attribute_data_types
+            .insert(CLASSIFICATION_ATTRIBUTE.to_string(), dictionary.data_type());
+        self.label_dictionary = Some(dictionary);
+    }
+
+    pub fn label_dictionary(&self) -> Option<&LabelDictionary> {
+        self.label_dictionary.as_ref()
+    }
+
     pub fn encoding_for_node(&self, id: NodeId) -> Encoding {
         let bounding_cube = id.find_bounding_cube(&Cube::bounding(&self.bounding_box));
         let position_encoding = PositionEncoding::new(&bounding_cube, self.resolution);
@@ -95,6 +179,13 @@ pub fn to_meta_proto(octree_meta: &OctreeMeta, nodes: Vec<proto::OctreeNode>) ->
     meta.set_version(CURRENT_VERSION);
     meta.set_bounding_box(proto::AxisAlignedCuboid::from(&octree_meta.bounding_box));
     meta.set_octree(octree_proto);
+    if let Some(coordinate_system) = &octree_meta.coordinate_system {
+        meta.set_coordinate_system(coordinate_system.to_proto());
+    }
+    if let Some(label_dictionary) = &octree_meta.label_dictionary {
+        meta.set_label_dictionary(label_dictionary.to_proto());
+    }
+    octree_meta.metadata.set_in_meta_proto(&mut meta);
     meta
 }
 
@@ -142,6 +233,41 @@ pub struct Octree {
     data_provider: Box<dyn DataProvider>,
     meta: OctreeMeta,
     nodes: FnvHashMap<NodeId, NodeMeta>,
+    node_cache: Option<Arc<NodeCache>>,
+    /// Our id in 'node_cache'.
+    cache_owner: u64,
+    /// Summaries of whole nodes by node and sorted attribute names, see 'statistics_impl'.
+    node_statistics: Mutex<NodeStatisticsCache>,
+    /// The attribute ranges of all points in the subtree below each node that has points in it.
+    subtree_attribute_ranges: FnvHashMap<NodeId, AttributeRanges>,
+}
+
+type NodeStatisticsCache = FnvHashMap<(NodeId, Vec<String>), Arc<PointStatistics>>;
+
+/// Merges the attribute ranges of every node into the ranges of all its ancestors.
+fn subtree_attribute_ranges(
+    nodes: &FnvHashMap<NodeId, NodeMeta>,
+) -> FnvHashMap<NodeId, AttributeRanges> {
+    let mut subtree_ranges: FnvHashMap<NodeId, AttributeRanges> = FnvHashMap::default();
+    for (node_id, node_meta) in nodes.iter().filter(|(_, meta)| meta.num_points > 0) {
+        let mut current = Some(*node_id);
+        while let Some(id) = current {
+            subtree_ranges
+                .entry(id)
+                .or_default()
+                .merge(&node_meta.attribute_ranges);
+            current = id.parent_id();
+        }
+    }
+    subtree_ranges
+}
+
+/// The raw data of an attribute of a node, as it is stored on disk.
+#[derive(Debug)]
+pub struct NodeAttributeData {
+    pub name: String,
+    pub data_type: AttributeDataType,
+    pub data: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -149,6 +275,8 @@ pub struct NodeData {
     pub meta: NodeMeta,
     pub position: Vec<u8>,
     pub color: Vec<u8>,
+    /// Additionally requested attributes, in the order they were requested.
+    pub attributes: Vec<NodeAttributeData>,
 }
 
 impl Octree {
@@ -162,7 +290,7 @@ impl Octree {
                 meta_proto.version, CURRENT_VERSION
             );
         }
-        let (bounding_box, meta, nodes_proto) = match meta_proto.version {
+        let (bounding_box, mut meta, nodes_proto) = match meta_proto.version {
             9 | 10 | 11 => {
                 let bounding_box = Aabb::from(meta_proto.get_bounding_box());
                 (
@@ -192,6 +320,11 @@ impl Octree {
             }
             _ => return Err(ErrorKind::InvalidVersion(meta_proto.version).into()),
         };
+        meta.coordinate_system = CoordinateSystem::from_meta_proto(&meta_proto)?;
+        meta.metadata = Metadata::from_meta_proto(&meta_proto);
+        if let Some(label_dictionary) = LabelDictionary::from_meta_proto(&meta_proto)? {
+            meta.set_label_dictionary(label_dictionary);
+        }
 
         let mut nodes = FnvHashMap::default();
 
@@ -203,111 +336,162 @@ impl Octree {
                     num_points: node_proto.num_points,
                     position_encoding: PositionEncoding::from_proto(node_proto.position_encoding)?,
                     bounding_cube: node_id.find_bounding_cube(&Cube::bounding(&bounding_box)),
+                    attribute_ranges: AttributeRanges::from_proto(
+                        node_proto.get_attribute_ranges(),
+                    ),
+                    version: node_proto.version,
                 },
             );
         }
 
         Ok(Octree {
             meta,
+            subtree_attribute_ranges: subtree_attribute_ranges(&nodes),
             nodes,
             data_provider,
+            node_cache: None,
+            cache_owner: 0,
+            node_statistics: Mutex::new(FnvHashMap::default()),
         })
     }
 
+    /// Keeps the nodes decoded by 'points_in_node' in `node_cache`, so that repeated queries do
+    /// not read them again. The cache can be shared with other octrees.
+    pub fn set_node_cache(&mut self, node_cache: Arc<NodeCache>) {
+        self.cache_owner = node_cache.new_owner();
+        self.node_cache = Some(node_cache);
+    }
+
+    pub fn node_cache(&self) -> Option<&Arc<NodeCache>> {
+        self.node_cache.as_ref()
+    }
+
+    pub fn meta(&self) -> &OctreeMeta {
+        &self.meta
+    }
+
     pub fn to_meta_proto(&self) -> proto::Meta {
         let nodes: Vec<proto::OctreeNode> = self
             .nodes
             .iter()
             .map(|(id, node_meta)| {
-                to_node_proto(&id, node_meta.num_points, &node_meta.position_encoding)
+                to_node_proto(
+                    &id,
+                    node_meta.num_points,
+                    &node_meta.position_encoding,
+                    &node_meta.attribute_ranges,
+                    node_meta.version,
+                )
             })
             .collect();
         to_meta_proto(&self.meta, nodes)
     }
 
+    /// Returns the nodes that were added, removed or rewritten compared to `previous`, e.g. an
+    /// earlier version of the same octree before it was updated. Rewritten nodes changed their
+    /// number of points or their version, which e.g. 'update_attribute' increments.
+    pub fn changed_nodes(&self, previous: &Octree) -> Vec<NodeId> {
+        let mut changed: Vec<NodeId> = self
+            .nodes
+            .iter()
+            .filter(|(id, meta)| {
+                previous.nodes.get(id).map_or(true, |previous_meta| {
+                    previous_meta.num_points != meta.num_points
+                        || previous_meta.version != meta.version
+                })
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        changed.extend(
+            previous
+                .nodes
+                .keys()
+                .filter(|id| !self.nodes.contains_key(id)),
+        );
+        changed
+    }
+
+    /// The nodes with points in the frustum of `projection_matrix`, the largest on screen first.
     pub fn get_visible_nodes(&self, projection_matrix: &Matrix4<f64>) -> Vec<NodeId> {
         let frustum =
             Frustum::from_matrix4(*projection_matrix).expect("Invalid projection matrix.");
-        let frustum_isec = frustum.intersector().cache_separating_axes_for_aabb();
-        let mut open = BinaryHeap::new();
-        maybe_push_node(
-            &mut open,
-            &self.nodes,
-            Relation::Cross,
-            Node::root_with_bounding_cube(Cube::bounding(&self.meta.bounding_box)),
-            projection_matrix,
-        );
+        // Without a budget, the viewport only scales the sizes on screen, not their order.
+        self.select_nodes_for_view(&frustum, Viewport::new(2, 2), usize::MAX)
+            .into_iter()
+            .map(|lod_node| lod_node.id)
+            .collect()
+    }
 
-        let mut visible = Vec::new();
-        while let Some(current) = open.pop() {
-            match current.relation {
-                Relation::Cross => {
-                    for child_index in 0..8 {
-                        let child = current.node.get_child(ChildIndex::from_u8(child_index));
-                        let child_relation = frustum_isec
-                            .intersect(&child.bounding_cube.to_aabb().compute_corners());
-                        if child_relation == Relation::Out {
-                            continue;
-                        }
-                        maybe_push_node(
-                            &mut open,
-                            &self.nodes,
-                            child_relation,
-                            child,
-                            projection_matrix,
-                        );
-                    }
-                }
-                Relation::In => {
-                    // When the parent is fully in the frustum, so are the children.
-                    for child_index in 0..8 {
-                        maybe_push_node(
-                            &mut open,
-                            &self.nodes,
-                            Relation::In,
-                            current.node.get_child(ChildIndex::from_u8(child_index)),
-                            projection_matrix,
-                        );
-                    }
-                }
-                Relation::Out => {
-                    // This should never happen.
-                    unreachable!();
-                }
-            };
-            if !current.empty {
-                visible.push(current.node.id);
-            }
-        }
-        visible
+    /// The ranges of the attributes of all points, e.g. to scale colors by them.
+    pub fn attribute_ranges(&self) -> AttributeRanges {
+        self.subtree_attribute_ranges
+            .get(&NodeId::from_level_index(0, 0))
+            .cloned()
+            .unwrap_or_default()
     }
 
     pub fn get_node_data(&self, node_id: &NodeId) -> Result<NodeData> {
+        self.get_node_data_with_attributes(node_id, &[])
+    }
+
+    /// Like `get_node_data`, but also returns the data of `attributes`.
+    pub fn get_node_data_with_attributes(
+        &self,
+        node_id: &NodeId,
+        attributes: &[&str],
+    ) -> Result<NodeData> {
+        let attribute_data_types = self.meta.attribute_data_types_for(attributes)?;
         // TODO(hrapp): If we'd randomize the points while writing, we could just read the
         // first N points instead of reading everything and skipping over a few.
-        let mut position_color_reads = self
-            .data_provider
-            .data(&node_id.to_string(), &["position", "color"])?;
+        let mut reads = self.data_provider.data(
+            &node_id.to_string(),
+            &[&["position", "color"], attributes].concat(),
+        )?;
 
-        let mut get_data = |node_attribute: &str, err: &str| -> Result<Vec<u8>> {
-            let mut reader =
-                BufReader::new(position_color_reads.remove(node_attribute).ok_or(err)?);
+        let mut get_data = |node_attribute: &str| -> Result<Vec<u8>> {
+            let err = format!("Could not read {}", node_attribute);
+            let mut reader: Box<dyn Read> = Box::new(BufReader::new(
+                reads.remove(node_attribute).ok_or(err.as_str())?,
+            ));
+            if node_attribute == CLASSIFICATION_ATTRIBUTE {
+                reader = Box::new(RunLengthDecoder::new(
+                    reader,
+                    attribute_data_types[node_attribute],
+                ));
+            }
             let mut all_data = Vec::new();
             reader.read_to_end(&mut all_data).chain_err(|| err)?;
             Ok(all_data)
         };
-        let position = get_data("position", "Could not read position")?;
-        let color = get_data("color", "Could not read color")?;
+        let position = get_data("position")?;
+        let color = get_data("color")?;
+        let attributes = attributes
+            .iter()
+            .map(|name| {
+                Ok(NodeAttributeData {
+                    name: (*name).to_string(),
+                    data_type: attribute_data_types[*name],
+                    data: get_data(name)?,
+                })
+            })
+            .collect::<Result<_>>()?;
 
         Ok(NodeData {
             position,
             color,
+            attributes,
             meta: self.nodes[node_id].clone(),
         })
     }
 
+    /// Returns the nodes intersecting `location`. Subtrees whose attribute ranges rule out the
+    /// `attribute_intervals` are skipped, and so are nodes whose own ranges rule them out. The
+    /// children of nodes that are not larger than `voxel_size` are skipped too, since the points
+    /// of a node are spread over all of it and so fill its voxels already.
     fn nodes_in_location_impl<'a, T: HasAabbIntersector<'a>>(
         &self,
+        attribute_intervals: &[(&str, ClosedInterval<f64>)],
+        voxel_size: Option<VoxelSize>,
         location: &'a T,
     ) -> Vec<NodeId> {
         // TODO(nnmm): Once intersection tests use Relation, this function can traverse the octree
@@ -315,11 +499,27 @@ impl Octree {
         // it's a generalized version of get_visible_nodes(), and get_visible_nodes() can use this
         // function instead.
         let isec = location.aabb_intersector();
-        NodeIdsIterator::new(&self, |node_id, octree| {
-            let aabb = octree.nodes[&node_id].bounding_cube.to_aabb();
-            isec.intersect_aabb(&aabb)
-        })
-        .collect()
+        let min_parent_edge_length = voxel_size.map_or(0., |voxel_size| voxel_size.edge_length);
+        let iterator = NodeIdsIterator::new(&self, |node_id, octree| {
+            let bounding_cube = &octree.nodes[node_id].bounding_cube;
+            (node_id.level() == 0 || 2. * bounding_cube.edge_length() > min_parent_edge_length)
+                && isec.intersect_aabb(&bounding_cube.to_aabb())
+                && (attribute_intervals.is_empty()
+                    || octree
+                        .subtree_attribute_ranges
+                        .get(node_id)
+                        .map_or(false, |ranges| ranges.may_match(attribute_intervals)))
+        });
+        if attribute_intervals.is_empty() {
+            return iterator.collect();
+        }
+        iterator
+            .filter(|node_id| {
+                self.nodes[node_id]
+                    .attribute_ranges
+                    .may_match(attribute_intervals)
+            })
+            .collect()
     }
 }
 
@@ -327,7 +527,36 @@ impl PointCloud for Octree {
     type Id = NodeId;
 
     fn nodes_in_location(&self, location: &PointLocation) -> Vec<Self::Id> {
-        dispatch_point_location!(Octree::nodes_in_location_impl, location, &self)
+        dispatch_point_location!(Octree::nodes_in_location_impl, location, &self, &[], None)
+    }
+
+    fn nodes_for_query(&self, query: &PointQuery) -> Vec<Self::Id> {
+        let mut attribute_intervals = query.attribute_intervals();
+        // Unknown labels are reported when the points are streamed.
+        let label_ids = match (&query.labels, self.label_dictionary()) {
+            (Some(labels), Some(dictionary)) => dictionary.ids(labels).ok(),
+            _ => None,
+        };
+        if let Some(ids) = label_ids {
+            if let (Some(min), Some(max)) = (ids.first(), ids.last()) {
+                attribute_intervals.push((
+                    CLASSIFICATION_ATTRIBUTE,
+                    ClosedInterval::new(f64::from(*min), f64::from(*max)),
+                ));
+            }
+        }
+        let mut node_ids = dispatch_point_location!(
+            Octree::nodes_in_location_impl,
+            &query.location,
+            &self,
+            &attribute_intervals,
+            query.downsample
+        );
+        if !query.clip_planes.is_empty() {
+            node_ids
+                .retain(|node_id| !query.clips_away(&self.nodes[node_id].bounding_cube.to_aabb()));
+        }
+        node_ids
     }
 
     fn encoding_for_node(&self, id: Self::Id) -> Encoding {
@@ -340,65 +569,150 @@ impl PointCloud for Octree {
         node_id: Self::Id,
         batch_size: usize,
     ) -> Result<NodeIterator> {
-        let node_iterator = NodeIterator::from_data_provider(
-            &*self.data_provider,
-            &self.meta.attribute_data_types_for(&attributes)?,
-            self.meta.encoding_for_node(node_id),
-            &node_id,
-            self.nodes[&node_id].num_points as usize,
-            batch_size,
-        )?;
-        Ok(node_iterator)
+        let attribute_data_types = self.meta.attribute_data_types_for(&attributes)?;
+        let num_points = self.nodes[&node_id].num_points as usize;
+        let read = |batch_size| {
+            NodeIterator::from_data_provider(
+                &*self.data_provider,
+                &attribute_data_types,
+                self.meta.encoding_for_node(node_id),
+                &node_id,
+                num_points,
+                batch_size,
+            )
+        };
+        match &self.node_cache {
+            Some(node_cache) => {
+                let batch =
+                    node_cache.get_or_decode(self.cache_owner, node_id, attributes, || {
+                        Ok(read(num_points)?.next().unwrap_or_else(|| PointsBatch {
+                            position: Vec::new(),
+                            attributes: BTreeMap::new(),
+                        }))
+                    })?;
+                Ok(NodeIterator::from_batch(batch, batch_size))
+            }
+            None => read(batch_size),
+        }
+    }
+
+    /// Nodes that go through the node cache, or are empty, are read right away.
+    #[cfg(feature = "async")]
+    fn points_in_node_async(
+        &self,
+        attributes: &[&str],
+        node_id: Self::Id,
+        batch_size: usize,
+    ) -> NodeIteratorFuture<'_> {
+        let num_points = self.nodes[&node_id].num_points as usize;
+        if self.node_cache.is_some() || num_points == 0 {
+            return Box::pin(futures::future::ready(
+                self.points_in_node(attributes, node_id, batch_size),
+            ));
+        }
+        let attribute_data_types = match self.meta.attribute_data_types_for(&attributes) {
+            Ok(attribute_data_types) => attribute_data_types,
+            Err(err) => return Box::pin(futures::future::ready(Err(err))),
+        };
+        let encoding = self.meta.encoding_for_node(node_id);
+        let files_to_read = NodeIterator::files_to_read(&attribute_data_types);
+        if let Some(all_bytes) = self
+            .data_provider
+            .mapped_data(&node_id.to_string(), &files_to_read)
+        {
+            return Box::pin(futures::future::ready(all_bytes.and_then(|all_bytes| {
+                NodeIterator::from_bytes(
+                    all_bytes,
+                    &attribute_data_types,
+                    encoding,
+                    num_points,
+                    batch_size,
+                )
+            })));
+        }
+        let fetch = self
+            .data_provider
+            .data_async(&node_id.to_string(), &files_to_read);
+        Box::pin(async move {
+            NodeIterator::from_readers(
+                fetch.await?,
+                &attribute_data_types,
+                encoding,
+                num_points,
+                batch_size,
+            )
+        })
+    }
+
+    fn num_points_in_node(&self, node_id: Self::Id) -> usize {
+        self.nodes[&node_id].num_points as usize
     }
 
     /// return the bounding box saved in meta
     fn bounding_box(&self) -> &Aabb {
         &self.meta.bounding_box
     }
-}
 
-struct OpenNode {
-    node: Node,
-    relation: Relation,
-    size_on_screen: f64,
-    empty: bool,
-}
+    fn coordinate_system(&self) -> Option<CoordinateSystem> {
+        self.meta.coordinate_system
+    }
 
-impl Ord for OpenNode {
-    fn cmp(&self, other: &OpenNode) -> Ordering {
-        self.partial_cmp(other).unwrap()
+    fn metadata(&self) -> Option<&Metadata> {
+        Some(&self.meta.metadata)
     }
-}
 
-impl PartialOrd for OpenNode {
-    fn partial_cmp(&self, other: &OpenNode) -> Option<Ordering> {
-        self.size_on_screen.partial_cmp(&other.size_on_screen)
+    fn label_dictionary(&self) -> Option<&LabelDictionary> {
+        self.meta.label_dictionary()
     }
-}
 
-impl PartialEq for OpenNode {
-    fn eq(&self, other: &OpenNode) -> bool {
-        self.size_on_screen == other.size_on_screen
+    fn delete_in(&mut self, location: &PointLocation) -> Result<usize> {
+        self.delete_in_impl(location)
     }
-}
 
-impl Eq for OpenNode {}
+    fn update_attribute(
+        &mut self,
+        query: &PointQuery,
+        attribute: &str,
+        update: AttributeUpdate,
+    ) -> Result<usize> {
+        self.update_attribute_impl(query, attribute, update)
+    }
 
-#[inline]
-fn maybe_push_node(
-    v: &mut BinaryHeap<OpenNode>,
-    nodes: &FnvHashMap<NodeId, NodeMeta>,
-    relation: Relation,
-    node: Node,
-    projection_matrix: &Matrix4<f64>,
-) {
-    if let Some(meta) = nodes.get(&node.id) {
-        let size_on_screen = relative_size_on_screen(&node.bounding_cube, projection_matrix);
-        v.push(OpenNode {
-            node,
-            relation,
-            size_on_screen,
-            empty: meta.num_points == 0,
-        });
+    fn statistics(&self, query: &PointQuery) -> Result<PointStatistics> {
+        self.statistics_impl(query)
+    }
+
+    /// Visits the nodes along the ray from front to back and stops at the first node that starts
+    /// behind the closest hit so far.
+    fn pick(&self, ray: &Ray, radius: PickRadius, attributes: &[&str]) -> Result<Option<PickHit>> {
+        // A zero direction is normalized to NaN.
+        if ray.direction().iter().any(|c| !c.is_finite()) {
+            return Err(ErrorKind::InvalidInput(
+                "The direction of the ray needs to be a finite, nonzero vector.".to_string(),
+            )
+            .into());
+        }
+        let capsule = ray.bounding_capsule(radius);
+        // A pickable point is within this distance of its foot on the ray, so the ray enters a
+        // node grown by it before reaching any pickable point of the node.
+        let margin = Vector3::repeat(capsule.radius());
+        let mut nodes: Vec<(f64, NodeId)> = self
+            .nodes_in_location(&PointLocation::Capsule(capsule))
+            .into_iter()
+            .filter_map(|node_id| {
+                let aabb = self.nodes[&node_id].bounding_cube.to_aabb();
+                let grown = Aabb::new(aabb.min() - margin, aabb.max() + margin);
+                ray.entry_distance(&grown).map(|entry| (entry, node_id))
+            })
+            .collect();
+        nodes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut best: Option<PickHit> = None;
+        for (entry, node_id) in nodes {
+            if best.as_ref().is_some_and(|best| entry > best.distance) {
+                break;
+            }
+            pick_in_node(self, ray, radius, attributes, node_id, &mut best)?;
+        }
+        Ok(best)
     }
 }
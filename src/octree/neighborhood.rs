@@ -0,0 +1,116 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The points around a node, for computations on the points of a node that need the neighbors
+//! of each point, some of which lie in the adjacent nodes.
+
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::geometry::{Aabb, Cube};
+use crate::octree::update::read_all_points;
+use crate::octree::{ChildIndex, Node, NodeId, OctreeMeta};
+use fnv::FnvHashSet;
+use nalgebra::{Point3, Vector3};
+use std::collections::HashMap;
+
+/// The bounding cube of `node_id`.
+pub(super) fn bounding_cube(octree_meta: &OctreeMeta, node_id: &NodeId) -> Cube {
+    node_id.find_bounding_cube(&Cube::bounding(&octree_meta.bounding_box))
+}
+
+/// `cube` grown by `margin` on every side.
+pub(super) fn grown(cube: &Cube, margin: f64) -> Aabb {
+    let margin = Vector3::repeat(margin);
+    Aabb::new(cube.min() - margin, cube.max() + margin)
+}
+
+/// The distance of `p` to the closest face of `cube`, if it is inside.
+pub(super) fn distance_to_faces(cube: &Cube, p: &Point3<f64>) -> f64 {
+    let to_min = p - cube.min();
+    let to_max = cube.max() - p;
+    to_min.min().min(to_max.min())
+}
+
+fn overlaps(a: &Aabb, b: &Aabb) -> bool {
+    (0..3).all(|i| a.min()[i] <= b.max()[i] && b.min()[i] <= a.max()[i])
+}
+
+/// Some of the nodes of an octree, e.g. the leaves or the nodes of one level, in which to look
+/// for the nodes around a node.
+pub(super) struct NodeSet {
+    nodes: FnvHashSet<NodeId>,
+    /// The ancestors of the nodes, through which the search descends to them.
+    ancestors: FnvHashSet<NodeId>,
+    root_cube: Cube,
+}
+
+impl NodeSet {
+    pub(super) fn new(octree_meta: &OctreeMeta, nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        let nodes: FnvHashSet<NodeId> = nodes.into_iter().collect();
+        let mut ancestors = FnvHashSet::default();
+        for node_id in &nodes {
+            let mut parent_id = node_id.parent_id();
+            while let Some(id) = parent_id {
+                if !ancestors.insert(id) {
+                    break;
+                }
+                parent_id = id.parent_id();
+            }
+        }
+        NodeSet {
+            nodes,
+            ancestors,
+            root_cube: Cube::bounding(&octree_meta.bounding_box),
+        }
+    }
+
+    /// Returns the nodes of the set other than `node_id` that overlap `region`.
+    pub(super) fn nodes_within(&self, node_id: &NodeId, region: &Aabb) -> Vec<NodeId> {
+        let mut result = Vec::new();
+        let mut open = vec![Node::root_with_bounding_cube(self.root_cube.clone())];
+        while let Some(node) = open.pop() {
+            if !overlaps(&node.bounding_cube.to_aabb(), region) {
+                continue;
+            }
+            if self.nodes.contains(&node.id) {
+                if node.id != *node_id {
+                    result.push(node.id);
+                }
+            } else if self.ancestors.contains(&node.id) {
+                open.extend((0..8).map(|i| node.get_child(ChildIndex::from_u8(i))));
+            }
+        }
+        result
+    }
+}
+
+/// Returns the positions of the points of `node_ids` that lie within `region`.
+pub(super) fn points_within(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &OctreeMeta,
+    node_ids: &[NodeId],
+    region: &Aabb,
+) -> Result<Vec<Point3<f64>>> {
+    let mut points = Vec::new();
+    for node_id in node_ids {
+        let batch =
+            match read_all_points(octree_data_provider, octree_meta, &HashMap::new(), node_id) {
+                Ok(batch) => batch,
+                Err(Error(ErrorKind::NodeNotFound, _)) => continue,
+                Err(err) => return Err(err),
+            };
+        points.extend(batch.position.into_iter().filter(|p| region.contains(p)));
+    }
+    Ok(points)
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use crate::geometry::Cube;
+use crate::octree::AttributeRanges;
 use crate::proto;
 use crate::read_write::PositionEncoding;
 use nalgebra::Point3;
@@ -249,6 +250,9 @@ pub struct NodeMeta {
     pub num_points: i64,
     pub position_encoding: PositionEncoding,
     pub bounding_cube: Cube,
+    pub attribute_ranges: AttributeRanges,
+    /// How often the files of the node were rewritten since it was created.
+    pub version: u64,
 }
 
 impl NodeMeta {
@@ -261,11 +265,15 @@ pub fn to_node_proto(
     node_id: &NodeId,
     num_points: i64,
     position_encoding: &PositionEncoding,
+    attribute_ranges: &AttributeRanges,
+    version: u64,
 ) -> proto::OctreeNode {
     let mut proto = proto::OctreeNode::new();
     *proto.mut_id() = node_id.to_proto();
     proto.set_num_points(num_points);
     proto.set_position_encoding(position_encoding.to_proto());
+    proto.set_attribute_ranges(attribute_ranges.to_proto().into());
+    proto.set_version(version);
     proto
 }
 
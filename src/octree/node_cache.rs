@@ -0,0 +1,182 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::*;
+use crate::octree::NodeId;
+use crate::PointsBatch;
+use lru::LruCache;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Counters of a 'NodeCache', for tuning its size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub num_nodes: usize,
+    pub num_bytes: usize,
+}
+
+/// Identifies a node of one octree, decoded with one set of attributes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    owner: u64,
+    node_id: NodeId,
+    attributes: Vec<String>,
+}
+
+struct Entries {
+    batches: LruCache<Key, Arc<PointsBatch>>,
+    num_bytes: usize,
+}
+
+fn num_bytes(batch: &PointsBatch) -> usize {
+    batch.position.len() * std::mem::size_of::<nalgebra::Point3<f64>>()
+        + batch
+            .attributes
+            .values()
+            .map(|data| data.len() * data.data_type().size_of())
+            .sum::<usize>()
+}
+
+/// A least recently used cache of decoded nodes, bounded by the bytes of the decoded points. It
+/// can be shared by several octrees, see 'Octree::set_node_cache'.
+pub struct NodeCache {
+    max_bytes: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    next_owner: AtomicU64,
+}
+
+impl NodeCache {
+    pub fn new(max_bytes: usize) -> Self {
+        NodeCache {
+            max_bytes,
+            entries: Mutex::new(Entries {
+                batches: LruCache::unbounded(),
+                num_bytes: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            next_owner: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a new id to tell apart the nodes of different octrees. Entries of an owner that is
+    /// no longer used are evicted eventually.
+    pub(super) fn new_owner(&self) -> u64 {
+        self.next_owner.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Returns the cached node or decodes it with `decode`. Nodes are decoded outside of the lock,
+    /// so concurrent misses of the same node may decode it twice.
+    pub(super) fn get_or_decode(
+        &self,
+        owner: u64,
+        node_id: NodeId,
+        attributes: &[&str],
+        decode: impl FnOnce() -> Result<PointsBatch>,
+    ) -> Result<Arc<PointsBatch>> {
+        let mut attributes: Vec<String> = attributes.iter().map(|a| (*a).to_string()).collect();
+        attributes.sort();
+        let key = Key {
+            owner,
+            node_id,
+            attributes,
+        };
+        if let Some(batch) = self.entries.lock().unwrap().batches.get(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Arc::clone(batch));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let batch = Arc::new(decode()?);
+        let batch_bytes = num_bytes(&batch);
+        if batch_bytes > self.max_bytes {
+            return Ok(batch);
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(previous) = entries.batches.put(key, Arc::clone(&batch)) {
+            entries.num_bytes -= num_bytes(&previous);
+        }
+        entries.num_bytes += batch_bytes;
+        while entries.num_bytes > self.max_bytes {
+            match entries.batches.pop_lru() {
+                Some((_, evicted)) => entries.num_bytes -= num_bytes(&evicted),
+                None => break,
+            }
+        }
+        Ok(batch)
+    }
+
+    pub fn stats(&self) -> NodeCacheStats {
+        let entries = self.entries.lock().unwrap();
+        NodeCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            num_nodes: entries.batches.len(),
+            num_bytes: entries.num_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AttributeData;
+    use nalgebra::{Point3, Vector3};
+
+    fn batch(num_points: usize) -> PointsBatch {
+        PointsBatch {
+            position: vec![Point3::origin(); num_points],
+            attributes: vec![(
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::zeros(); num_points]),
+            )]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        // 27 bytes per point, so there is room for two nodes of 10 points.
+        let cache = NodeCache::new(600);
+        let owner = cache.new_owner();
+        let get = |node: &str| {
+            cache
+                .get_or_decode(owner, node.parse().unwrap(), &["color"], || Ok(batch(10)))
+                .unwrap()
+        };
+        get("r0");
+        get("r1");
+        get("r0");
+        get("r2");
+        assert_eq!(
+            cache.stats(),
+            NodeCacheStats {
+                hits: 1,
+                misses: 3,
+                num_nodes: 2,
+                num_bytes: 540,
+            }
+        );
+        // r1 was evicted, r0 was not.
+        get("r0");
+        get("r1");
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.stats().misses, 4);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::attribute_extension;
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::octree::grid::Grid;
+use crate::octree::neighborhood::{
+    bounding_cube, distance_to_faces, grown, points_within, NodeSet,
+};
+use crate::octree::update::read_all_points;
+use crate::octree::{NodeId, Octree};
+use crate::read_write::{DataWriter, OpenMode, WriteLE};
+use crate::utils::create_progress_bar;
+use fnv::FnvHashMap;
+use nalgebra::{Matrix3, Point3, SymmetricEigen, Vector3};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The number of neighbors used by 'build_octree --estimate-normals'.
+pub const NUM_NORMAL_NEIGHBORS: usize = 16;
+
+/// Returns the normal of the plane fitted through `neighbors`, i.e. the eigenvector of their
+/// covariance matrix with the smallest eigenvalue. Normals are oriented to point upwards.
+fn normal_from_neighbors(points: &[Point3<f64>], neighbors: &[usize]) -> Vector3<f64> {
+    if neighbors.len() < 3 {
+        return Vector3::z();
+    }
+    let centroid = neighbors
+        .iter()
+        .fold(Vector3::zeros(), |sum, i| sum + points[*i].coords)
+        / neighbors.len() as f64;
+    let covariance = neighbors.iter().fold(Matrix3::zeros(), |sum, i| {
+        let d = points[*i].coords - centroid;
+        sum + d * d.transpose()
+    });
+    let eigen = SymmetricEigen::new(covariance);
+    let normal: Vector3<f64> = eigen.eigenvectors.column(eigen.eigenvalues.imin()).into();
+    if normal.z < 0. {
+        -normal
+    } else {
+        normal
+    }
+}
+
+/// Estimates a normal for every point via principal component analysis of its `num_neighbors`
+/// nearest neighbors.
+pub fn estimate_normals_for_points(
+    points: &[Point3<f64>],
+    num_neighbors: usize,
+) -> Vec<Vector3<f64>> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+    let grid = Grid::new(points, num_neighbors);
+    points
+        .iter()
+        .map(|p| normal_from_neighbors(points, &grid.neighbors(points, p, num_neighbors)))
+        .collect()
+}
+
+/// Writes the normals of the points of `node_id`. The points whose nearest neighbors may lie
+/// outside of the node get their normals from the points in the other nodes of its level in
+/// `level_nodes` as well.
+fn estimate_normals_for_node(
+    octree_data_provider: &OnDiskDataProvider,
+    octree: &Octree,
+    level_nodes: &NodeSet,
+    node_id: &NodeId,
+    num_neighbors: usize,
+) -> Result<()> {
+    let mut points =
+        match read_all_points(octree_data_provider, &octree.meta, &HashMap::new(), node_id) {
+            Ok(batch) => batch.position,
+            Err(Error(ErrorKind::NodeNotFound, _)) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+    let num_points = points.len();
+    let mut normals = Vec::with_capacity(num_points);
+    if num_points > 0 {
+        let cube = bounding_cube(&octree.meta, node_id);
+        let grid = Grid::new(&points, num_neighbors);
+        let mut at_border = Vec::new();
+        let mut margin: f64 = 0.;
+        for (i, p) in points.iter().enumerate() {
+            let neighbors = grid.neighbors(&points, p, num_neighbors);
+            let distance = grid.reach(&points, p, &neighbors, num_neighbors);
+            if distance > distance_to_faces(&cube, p) {
+                at_border.push(i);
+                margin = margin.max(distance);
+            }
+            normals.push(normal_from_neighbors(&points, &neighbors));
+        }
+        if !at_border.is_empty() {
+            let region = grown(&cube, margin.min(cube.edge_length()));
+            let neighbor_nodes = level_nodes.nodes_within(node_id, &region);
+            points.extend(points_within(
+                octree_data_provider,
+                &octree.meta,
+                &neighbor_nodes,
+                &region,
+            )?);
+        }
+        if points.len() > num_points {
+            let grid = Grid::new(&points, num_neighbors);
+            for i in at_border {
+                let neighbors = grid.neighbors(&points, &points[i], num_neighbors);
+                normals[i] = normal_from_neighbors(&points, &neighbors);
+            }
+        }
+    }
+    let stem = octree_data_provider.stem(&node_id.to_string());
+    let mut writer = DataWriter::new(
+        stem.with_extension(attribute_extension("normal")),
+        OpenMode::Truncate,
+    )?;
+    normals.write_le(&mut writer)?;
+    Ok(())
+}
+
+/// Computes the 'normal' attribute for every node of the octree in `directory`. Neighbors are
+/// searched for among the points of the same level of detail, in the node and near its border in
+/// the adjacent nodes of its level, so the coarser levels of detail get normals of the
+/// correspondingly coarser surface. Since 'update' does not compute normals for the points it
+/// adds, this needs to be run again after updating an octree that has normals.
+pub fn estimate_normals(directory: impl AsRef<Path>, num_neighbors: usize) -> Result<()> {
+    let octree_data_provider = &OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    let octree = &Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    }))?;
+    let node_ids: Vec<NodeId> = octree.nodes.keys().cloned().collect();
+    let mut node_ids_by_level: FnvHashMap<u8, Vec<NodeId>> = FnvHashMap::default();
+    for node_id in &node_ids {
+        node_ids_by_level
+            .entry(node_id.level())
+            .or_default()
+            .push(*node_id);
+    }
+    let levels: &FnvHashMap<u8, NodeSet> = &node_ids_by_level
+        .into_iter()
+        .map(|(level, node_ids)| (level, NodeSet::new(&octree.meta, node_ids)))
+        .collect();
+    let mut progress_bar = create_progress_bar(node_ids.len(), "Estimating normals");
+    let (progress_tx, progress_rx) = crossbeam::channel::unbounded();
+    let mut result = Ok(());
+    rayon::scope(|scope| {
+        scope.spawn(|_| {
+            for _ in progress_rx {
+                progress_bar.inc();
+            }
+        });
+        result = node_ids.par_iter().try_for_each(|node_id| {
+            estimate_normals_for_node(
+                octree_data_provider,
+                octree,
+                &levels[&node_id.level()],
+                node_id,
+                num_neighbors,
+            )?;
+            progress_tx.send(()).unwrap();
+            Ok(())
+        });
+        drop(progress_tx);
+    });
+    progress_bar.finish();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normals_of_tilted_plane() {
+        let points: Vec<Point3<f64>> = (0..50)
+            .flat_map(|x| (0..50).map(move |y| (x, y)))
+            .map(|(x, y)| Point3::new(f64::from(x), f64::from(y), 0.5 * f64::from(x)))
+            .collect();
+        let expected = Vector3::new(-0.5, 0., 1.).normalize();
+        for normal in estimate_normals_for_points(&points, NUM_NORMAL_NEIGHBORS) {
+            assert!((normal - expected).norm() < 1e-6);
+        }
+    }
+}
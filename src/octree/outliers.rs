@@ -0,0 +1,285 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::geometry::Cube;
+use crate::octree::checkpoint::Checkpoint;
+use crate::octree::generation::NodeSummary;
+use crate::octree::grid::Grid;
+use crate::octree::neighborhood::{
+    bounding_cube, distance_to_faces, grown, points_within, NodeSet,
+};
+use crate::octree::update::read_all_points;
+use crate::octree::{NodeId, OctreeMeta};
+use crate::read_write::{NodeWriter, OpenMode, RawNodeWriter};
+use crate::utils::create_progress_bar;
+use crate::{AttributeDataType, PointsBatch};
+use nalgebra::Point3;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::HashMap;
+
+/// Removes isolated points, e.g. sensor noise or birds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutlierFilter {
+    /// Removes the points whose mean distance to their `num_neighbors` nearest neighbors is more
+    /// than `stddev_multiplier` standard deviations above the mean of all points.
+    Statistical {
+        num_neighbors: usize,
+        stddev_multiplier: f64,
+    },
+    /// Removes the points with fewer than `min_neighbors` other points within `radius`.
+    Radius { radius: f64, min_neighbors: usize },
+}
+
+impl OutlierFilter {
+    /// Returns for each of the `points` whether it is kept.
+    pub fn inliers(&self, points: &[Point3<f64>]) -> Vec<bool> {
+        self.inliers_among(points, points.len())
+    }
+
+    /// Returns for each of the first `num_candidates` of the `points` whether it is kept. The
+    /// other points are only searched as their neighbors.
+    fn inliers_among(&self, points: &[Point3<f64>], num_candidates: usize) -> Vec<bool> {
+        if num_candidates == 0 {
+            return Vec::new();
+        }
+        let candidates = &points[..num_candidates];
+        match *self {
+            OutlierFilter::Statistical {
+                num_neighbors,
+                stddev_multiplier,
+            } => {
+                let grid = Grid::new(points, num_neighbors);
+                // The closest point is the point itself. The neighbors that are not found are
+                // farther than the search radius, which their distance is taken to be.
+                let mean_distances: Vec<f64> = candidates
+                    .iter()
+                    .map(|p| {
+                        let neighbors = grid.neighbors(points, p, num_neighbors + 1);
+                        let distances = neighbors.iter().skip(1).map(|i| (points[*i] - p).norm());
+                        let num_missing = num_neighbors + 1 - neighbors.len();
+                        (distances.sum::<f64>() + num_missing as f64 * grid.search_radius())
+                            / num_neighbors.max(1) as f64
+                    })
+                    .collect();
+                let num_points = mean_distances.len() as f64;
+                let mean = mean_distances.iter().sum::<f64>() / num_points;
+                let variance = mean_distances
+                    .iter()
+                    .map(|d| (d - mean).powi(2))
+                    .sum::<f64>()
+                    / num_points;
+                let max_distance = mean + stddev_multiplier * variance.sqrt();
+                mean_distances.iter().map(|d| *d <= max_distance).collect()
+            }
+            OutlierFilter::Radius {
+                radius,
+                min_neighbors,
+            } => {
+                let grid = Grid::with_cell_size(points, radius);
+                candidates
+                    .iter()
+                    .map(|p| grid.num_within(points, p, radius) > min_neighbors)
+                    .collect()
+            }
+        }
+    }
+
+    /// How far outside of `cube` the neighbors of the `points` in it can be. Adding points can
+    /// only bring the nearest neighbors closer, so for the statistical filter, this is the largest
+    /// distance to the farthest of the nearest neighbors in `points` of those points that are
+    /// closer to a face of the cube. It is capped at the edge length of the cube, which only an
+    /// isolated point at the border comes close to.
+    fn search_margin(&self, points: &[Point3<f64>], cube: &Cube) -> f64 {
+        match *self {
+            OutlierFilter::Statistical { num_neighbors, .. } => {
+                if points.is_empty() {
+                    return 0.;
+                }
+                let grid = Grid::new(points, num_neighbors);
+                points
+                    .iter()
+                    .filter_map(|p| {
+                        let neighbors = grid.neighbors(points, p, num_neighbors + 1);
+                        let distance = grid.reach(points, p, &neighbors, num_neighbors + 1);
+                        if distance > distance_to_faces(cube, p) {
+                            Some(distance)
+                        } else {
+                            None
+                        }
+                    })
+                    .fold(0., f64::max)
+                    .min(cube.edge_length())
+            }
+            OutlierFilter::Radius { radius, .. } => radius,
+        }
+    }
+}
+
+/// Applies the `filters` one after the other to `batch` and returns the number of removed points.
+pub fn remove_outliers(batch: &mut PointsBatch, filters: &[OutlierFilter]) -> usize {
+    let num_points = batch.position.len();
+    for filter in filters {
+        let keep = filter.inliers(&batch.position);
+        batch.retain(&keep);
+    }
+    num_points - batch.position.len()
+}
+
+/// Like 'remove_outliers' for the points in `batch` of the leaf `node_id`, but the neighbors of
+/// its points are also searched for in the other `leaves`, as they were before any outliers were
+/// removed.
+fn remove_outliers_in_node(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &OctreeMeta,
+    leaves: &NodeSet,
+    node_id: &NodeId,
+    batch: &mut PointsBatch,
+    filters: &[OutlierFilter],
+) -> Result<usize> {
+    let num_points = batch.position.len();
+    let cube = bounding_cube(octree_meta, node_id);
+    for filter in filters {
+        let margin = filter.search_margin(&batch.position, &cube);
+        let mut points = batch.position.clone();
+        if margin > 0. {
+            let region = grown(&cube, margin);
+            let neighbor_nodes = leaves.nodes_within(node_id, &region);
+            points.extend(points_within(
+                octree_data_provider,
+                octree_meta,
+                &neighbor_nodes,
+                &region,
+            )?);
+        }
+        let keep = filter.inliers_among(&points, batch.position.len());
+        batch.retain(&keep);
+    }
+    Ok(num_points - batch.position.len())
+}
+
+/// Removes the outliers from the leaf nodes of an octree that is being built, before they are
+/// subsampled into their parents. The neighbors of the points near the border of a node are
+/// searched for in the adjacent nodes as well. The new nodes are moved into place through the
+/// `checkpoint`. Returns the number of removed points.
+pub(super) fn remove_outliers_in_nodes(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &OctreeMeta,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    node_ids: &[NodeId],
+    filters: &[OutlierFilter],
+    checkpoint: &Checkpoint,
+) -> Result<usize> {
+    let leaves = &NodeSet::new(octree_meta, node_ids.iter().copied());
+    let mut progress_bar = create_progress_bar(node_ids.len(), "Removing outliers");
+    let (progress_tx, progress_rx) = crossbeam::channel::unbounded();
+    let mut result = Ok(Vec::new());
+    rayon::scope(|scope| {
+        scope.spawn(|_| {
+            for _ in progress_rx {
+                progress_bar.inc();
+            }
+        });
+        result = node_ids
+            .par_iter()
+            .map(|node_id| {
+                let mut batch = match read_all_points(
+                    octree_data_provider,
+                    octree_meta,
+                    attribute_data_types,
+                    node_id,
+                ) {
+                    Ok(batch) => batch,
+                    Err(Error(ErrorKind::NodeNotFound, _)) => return Ok(None),
+                    Err(err) => return Err(err),
+                };
+                let num_removed = remove_outliers_in_node(
+                    octree_data_provider,
+                    octree_meta,
+                    leaves,
+                    node_id,
+                    &mut batch,
+                    filters,
+                )?;
+                progress_tx.send(()).unwrap();
+                if num_removed == 0 {
+                    return Ok(None);
+                }
+                // Writing an empty batch writes no files, so the node's files are removed.
+                let mut writer = RawNodeWriter::new(
+                    checkpoint.pending_stem(node_id),
+                    octree_meta.encoding_for_node(*node_id),
+                    OpenMode::Truncate,
+                );
+                writer.write(&batch)?;
+                let summary = NodeSummary {
+                    num_points: writer.num_written(),
+                    ..Default::default()
+                };
+                Ok(Some((*node_id, summary, num_removed)))
+            })
+            .collect::<Result<Vec<_>>>();
+        drop(progress_tx);
+    });
+    progress_bar.finish();
+    let (nodes, num_removed): (Vec<_>, Vec<usize>) = result?
+        .into_iter()
+        .flatten()
+        .map(|(node_id, summary, num_removed)| ((node_id, summary), num_removed))
+        .unzip();
+    checkpoint.finish_removing_outliers(&nodes)?;
+    Ok(num_removed.into_iter().sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_remove_isolated_points() {
+        let mut points: Vec<Point3<f64>> = (0..50)
+            .flat_map(|x| (0..50).map(move |y| (x, y)))
+            .map(|(x, y)| Point3::new(f64::from(x) * 0.1, f64::from(y) * 0.1, 0.))
+            .collect();
+        // A bird above the ground and a pair of noisy returns off to the side.
+        points.push(Point3::new(2.5, 2.5, 20.));
+        points.push(Point3::new(30., 0., 0.));
+        points.push(Point3::new(30.05, 0., 0.));
+        let num_ground = points.len() - 3;
+
+        let statistical = OutlierFilter::Statistical {
+            num_neighbors: 8,
+            stddev_multiplier: 1.,
+        };
+        let keep = statistical.inliers(&points);
+        assert!(keep[..num_ground].iter().all(|k| *k));
+        assert_eq!(&keep[num_ground..], &[false, false, false]);
+
+        let radius = OutlierFilter::Radius {
+            radius: 0.15,
+            min_neighbors: 2,
+        };
+        let keep = radius.inliers(&points);
+        assert!(keep[..num_ground].iter().all(|k| *k));
+        assert_eq!(&keep[num_ground..], &[false, false, false]);
+        // The pair are each other's neighbor.
+        let keep = OutlierFilter::Radius {
+            radius: 0.15,
+            min_neighbors: 1,
+        }
+        .inliers(&points);
+        assert_eq!(&keep[num_ground..], &[false, true, true]);
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::coordinates::{CoordinateSystem, Reprojection};
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::iterator::{PointCloud, PointLocation};
+use crate::labels::LabelDictionary;
+use crate::metadata::{Metadata, ProcessingStep};
+use crate::octree::generation::build_octree_with_meta;
+use crate::octree::{Octree, OctreeMeta};
+use crate::read_write::{
+    Encoding, InputFileIterator, NodeIterator, NodeWriter, OpenMode, RawNodeWriter, S2Splitter,
+};
+use crate::s2_cells::S2Cells;
+use crate::{NumberOfPoints, PointCloudMeta, PointsBatch, NUM_POINTS_PER_BATCH};
+use std::fs;
+use std::path::Path;
+
+/// The points of all nodes of a point cloud, one node after the other, as the input of a build.
+struct NodeBatches<'a, C: PointCloud> {
+    point_cloud: &'a C,
+    attributes: Vec<String>,
+    node_ids: std::vec::IntoIter<C::Id>,
+    current: Option<NodeIterator>,
+    num_points: usize,
+}
+
+impl<'a, C: PointCloud> NodeBatches<'a, C> {
+    fn new(point_cloud: &'a C, attributes: Vec<String>, num_points: usize) -> Self {
+        NodeBatches {
+            point_cloud,
+            attributes,
+            node_ids: point_cloud
+                .nodes_in_location(&PointLocation::AllPoints)
+                .into_iter(),
+            current: None,
+            num_points,
+        }
+    }
+}
+
+impl<'a, C: PointCloud> Iterator for NodeBatches<'a, C> {
+    type Item = PointsBatch;
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        loop {
+            if let Some(batch) = self.current.as_mut().and_then(Iterator::next) {
+                return Some(batch);
+            }
+            let node_id = self.node_ids.next()?;
+            let attributes: Vec<&str> = self.attributes.iter().map(String::as_str).collect();
+            self.current = Some(
+                self.point_cloud
+                    .points_in_node(&attributes, node_id, NUM_POINTS_PER_BATCH)
+                    .unwrap_or_else(|err| {
+                        panic!("Could not read node {}: {}", node_id.to_string(), err)
+                    }),
+            );
+        }
+    }
+}
+
+impl<'a, C: PointCloud> NumberOfPoints for NodeBatches<'a, C> {
+    fn num_points(&self) -> usize {
+        self.num_points
+    }
+}
+
+/// Builds an octree in `output_directory` out of the S2 point cloud in `s2_directory`, with all
+/// of its attributes. These need to be attributes that octrees can store, and include color.
+pub fn build_octree_from_s2_cells(
+    output_directory: impl AsRef<Path>,
+    resolution: f64,
+    s2_directory: impl AsRef<Path>,
+) -> Result<()> {
+    let s2_cells = S2Cells::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: s2_directory.as_ref().to_path_buf(),
+    }))?;
+    let mut octree_meta =
+        OctreeMeta::new_with_standard_attributes(resolution, s2_cells.bounding_box().clone());
+    octree_meta.coordinate_system = Some(CoordinateSystem::Ecef);
+    if let Some(label_dictionary) = s2_cells.label_dictionary() {
+        octree_meta.set_label_dictionary(label_dictionary.clone());
+    }
+    octree_meta.metadata = s2_cells.meta().metadata().clone();
+    octree_meta
+        .metadata
+        .processing_history
+        .push(ProcessingStep::now(
+            "octree from-s2",
+            "Built out of an S2 point cloud.",
+        ));
+
+    let s2_meta = s2_cells.meta();
+    let mut attributes: Vec<String> = s2_meta.attribute_data_types().keys().cloned().collect();
+    attributes.sort();
+    if !attributes.iter().any(|name| name == "color") {
+        return Err(ErrorKind::InvalidInput(
+            "Octrees can only be built out of points with a color.".to_string(),
+        )
+        .into());
+    }
+    for name in &attributes {
+        let data_type = s2_meta.attribute_data_types()[name];
+        if octree_meta.attribute_data_types().get(name) != Some(&data_type) {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Octrees can not store the attribute '{}' of type {:?}.",
+                name, data_type
+            ))
+            .into());
+        }
+    }
+
+    let num_points = s2_meta
+        .get_cells()
+        .values()
+        .map(|cell_meta| cell_meta.num_points as usize)
+        .sum();
+    let attribute_names: Vec<&str> = attributes.iter().map(String::as_str).collect();
+    build_octree_with_meta(
+        output_directory,
+        octree_meta,
+        NodeBatches::new(&s2_cells, attributes.clone(), num_points),
+        &attribute_names,
+        &[],
+    );
+    Ok(())
+}
+
+/// Writes the points of the octree in `octree_directory` with all of their attributes into an
+/// S2 point cloud in `output_directory`, with cells at `split_level`. S2 point clouds are in
+/// ECEF, so the points are reprojected if the octree has another coordinate system. Octrees
+/// without one are assumed to be in ECEF.
+pub fn write_s2_cells_from_octree(
+    output_directory: impl AsRef<Path>,
+    split_level: u64,
+    octree_directory: impl AsRef<Path>,
+) -> Result<()> {
+    let octree_data_provider = OnDiskDataProvider {
+        directory: octree_directory.as_ref().to_path_buf(),
+    };
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: octree_directory.as_ref().to_path_buf(),
+    }))?;
+    // Every node of a build has the same attributes.
+    let first_node = octree
+        .nodes
+        .iter()
+        .find(|(_, node_meta)| node_meta.num_points > 0)
+        .map(|(id, _)| *id)
+        .ok_or_else(|| ErrorKind::InvalidInput("The octree has no points.".to_string()))?;
+    let mut attributes: Vec<String> = octree
+        .attributes_on_disk(&octree_data_provider, &first_node)
+        .into_keys()
+        .collect();
+    attributes.sort();
+    let reprojection = match octree.coordinate_system() {
+        Some(CoordinateSystem::Ecef) | None => None,
+        Some(coordinate_system) => {
+            Some(Reprojection::new(coordinate_system, CoordinateSystem::Ecef))
+        }
+    };
+
+    let num_points = octree
+        .nodes
+        .values()
+        .map(|meta| meta.num_points as usize)
+        .sum();
+    write_s2_cells(
+        output_directory.as_ref(),
+        split_level,
+        NodeBatches::new(&octree, attributes, num_points),
+        reprojection,
+        octree.meta.label_dictionary(),
+        octree.meta.metadata.clone(),
+    )
+}
+
+/// Like 'write_s2_cells_from_octree', but for the points of a PLY, E57 or PCD `input_file`,
+/// which are assumed to be in ECEF.
+pub fn write_s2_cells_from_file(
+    output_directory: impl AsRef<Path>,
+    split_level: u64,
+    input_file: impl AsRef<Path>,
+) -> Result<()> {
+    write_s2_cells(
+        output_directory.as_ref(),
+        split_level,
+        InputFileIterator::from_file(input_file, NUM_POINTS_PER_BATCH)?,
+        None,
+        None,
+        Metadata::default(),
+    )
+}
+
+/// Writes the points of `batches` into an S2 point cloud in `output_directory`, the shared part
+/// of 'write_s2_cells_from_octree' and 'write_s2_cells_from_file'.
+fn write_s2_cells(
+    output_directory: &Path,
+    split_level: u64,
+    batches: impl Iterator<Item = PointsBatch>,
+    reprojection: Option<Reprojection>,
+    label_dictionary: Option<&LabelDictionary>,
+    mut metadata: Metadata,
+) -> Result<()> {
+    fs::create_dir_all(output_directory)?;
+    let mut s2_writer: S2Splitter<RawNodeWriter> = S2Splitter::with_split_level(
+        split_level,
+        output_directory,
+        Encoding::Plain,
+        OpenMode::Truncate,
+    );
+    for mut batch in batches {
+        if let Some(reprojection) = &reprojection {
+            reprojection.transform_batch(&mut batch);
+        }
+        s2_writer.write(&batch)?;
+    }
+    let mut s2_meta = s2_writer
+        .get_meta()
+        .ok_or_else(|| ErrorKind::InvalidInput("There are no points to write.".to_string()))?;
+    if let Some(label_dictionary) = label_dictionary {
+        s2_meta.set_label_dictionary(label_dictionary.clone());
+    }
+    metadata.processing_history.push(ProcessingStep::now(
+        "octree to-s2",
+        format!("Split into S2 cells at level {}.", split_level),
+    ));
+    s2_meta.set_metadata(metadata);
+    OnDiskDataProvider {
+        directory: output_directory.to_path_buf(),
+    }
+    .write_meta_proto(&s2_meta.to_proto())
+}
@@ -0,0 +1,89 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::*;
+use crate::iterator::{downsampled_statistics, PointCloud, PointQuery};
+use crate::octree::{NodeId, Octree};
+use crate::statistics::PointStatistics;
+use crate::NUM_POINTS_PER_BATCH;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::sync::Arc;
+
+impl Octree {
+    /// The statistics of all points of a node, computed once per set of attributes.
+    fn node_statistics(
+        &self,
+        node_id: NodeId,
+        attributes: &[&str],
+    ) -> Result<Arc<PointStatistics>> {
+        let mut key: Vec<String> = attributes.iter().map(|a| (*a).to_string()).collect();
+        key.sort();
+        if let Some(statistics) = self
+            .node_statistics
+            .lock()
+            .unwrap()
+            .get(&(node_id, key.clone()))
+        {
+            return Ok(Arc::clone(statistics));
+        }
+        let mut statistics = PointStatistics::default();
+        for batch in self.points_in_node(attributes, node_id, NUM_POINTS_PER_BATCH)? {
+            statistics.add_batch(&batch);
+        }
+        let statistics = Arc::new(statistics);
+        self.node_statistics
+            .lock()
+            .unwrap()
+            .insert((node_id, key), Arc::clone(&statistics));
+        Ok(statistics)
+    }
+
+    /// Like the default 'PointCloud::statistics', but nodes that are completely inside the
+    /// queried location are summarized once and then served from memory.
+    pub(super) fn statistics_impl(&self, query: &PointQuery) -> Result<PointStatistics> {
+        if let Some(voxel_size) = query.downsample {
+            return downsampled_statistics(self, query, voxel_size);
+        }
+        let node_statistics = self
+            .nodes_for_query(query)
+            .into_par_iter()
+            .map(|node_id| {
+                let aabb = self.nodes[&node_id].bounding_cube.to_aabb();
+                // The summaries are in the frame of the octree.
+                if !query.has_attribute_filters()
+                    && query.output_from_query.is_none()
+                    && query.contains_aabb(&aabb)
+                {
+                    return self.node_statistics(node_id, &query.attributes);
+                }
+                let mut statistics = PointStatistics::default();
+                self.stream_points_for_query_in_node(
+                    query,
+                    node_id,
+                    NUM_POINTS_PER_BATCH,
+                    |batch| {
+                        statistics.add_batch(&batch);
+                        Ok(())
+                    },
+                )?;
+                Ok(Arc::new(statistics))
+            })
+            .collect::<Result<Vec<Arc<PointStatistics>>>>()?;
+        let mut statistics = PointStatistics::default();
+        for s in &node_statistics {
+            statistics.merge(s);
+        }
+        Ok(statistics)
+    }
+}
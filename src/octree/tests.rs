@@ -1,10 +1,28 @@
+use crate::coordinates::CoordinateSystem;
 use crate::data_provider::OnDiskDataProvider;
+use crate::downsample::VoxelSize;
 use crate::errors::Result;
-use crate::geometry::Aabb;
-use crate::iterator::{ParallelIterator, PointQuery};
-use crate::octree::{build_octree, Octree};
-use crate::{AttributeData, NumberOfPoints, PointsBatch};
-use nalgebra::{Point3, Vector3};
+use crate::geometry::{Aabb, Frustum, Perspective, PickRadius, Ray};
+use crate::iterator::{AttributeUpdate, ParallelIterator, PointCloud, PointLocation, PointQuery};
+use crate::labels::{LabelDictionary, CLASSIFICATION_ATTRIBUTE};
+use crate::layout::{BatchLayout, LaidOutBatch};
+use crate::math::{ClosedInterval, HalfSpace};
+use crate::metadata::{read_metadata, write_metadata, Metadata, ProcessingStep};
+use crate::octree::checkpoint::{Checkpoint, CHECKPOINT_FILENAME};
+use crate::octree::generation::split_node;
+use crate::octree::merge::merge_octrees_with_max_tile_points;
+use crate::octree::{
+    self, build_filtered_octree, build_labeled_octree, build_octree, build_octree_from_s2_cells,
+    estimate_normals, export_octree, merge_octrees, repair_octree, resume_octree, validate_octree,
+    write_s2_cells_from_file, write_s2_cells_from_octree, Deduplication, DuplicatePreference,
+    ExportFormat, NodeId, Octree, OctreeMeta, OctreeProblem, OutlierFilter, Viewport,
+};
+use crate::read_write::PcdIterator;
+use crate::s2_cells::S2Cells;
+use crate::segmentation::{for_each_plane_mask, PlaneDetection};
+use crate::{AttributeData, AttributeDataType, NumberOfPoints, PointCloudMeta, PointsBatch};
+use nalgebra::{Isometry3, Point3, Vector3};
+use std::path::Path;
 use tempdir::TempDir;
 
 const NUM_POINTS: usize = 100_001;
@@ -15,7 +33,7 @@ impl NumberOfPoints for std::vec::IntoIter<PointsBatch> {
     }
 }
 
-fn build_test_octree() -> Octree {
+fn build_test_octree_in(directory: &Path) {
     let mut batch = PointsBatch {
         position: vec![Point3::new(0.0, 0.0, 0.0); NUM_POINTS],
         attributes: vec![(
@@ -30,15 +48,18 @@ fn build_test_octree() -> Octree {
 
     let bounding_box = Aabb::new(batch.position[0], batch.position[NUM_POINTS - 1]);
 
-    let tmp_dir = TempDir::new("octree").unwrap();
-
     build_octree(
-        &tmp_dir,
+        directory,
         1.0,
         bounding_box,
         vec![batch].into_iter(),
         &["color"],
     );
+}
+
+fn build_test_octree() -> Octree {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_test_octree_in(tmp_dir.path());
     Octree::from_data_provider(Box::new(OnDiskDataProvider {
         directory: tmp_dir.into_path(),
     }))
@@ -111,6 +132,101 @@ fn test_batch_iterator() {
     assert_eq!(c.num_received_points, 3 * batch_size);
 }
 
+#[cfg(feature = "async")]
+#[test]
+fn test_stream_batches_returns_all_points() {
+    let octree = build_test_octree();
+    let query = PointQuery {
+        attributes: vec!["color"],
+        ..Default::default()
+    };
+    let batches: Vec<PointsBatch> =
+        futures::executor::block_on_stream(octree.stream_batches(&query, 5000).unwrap())
+            .collect::<Result<_>>()
+            .unwrap();
+    assert_eq!(
+        batches.iter().map(|b| b.position.len()).sum::<usize>(),
+        NUM_POINTS
+    );
+    assert!(batches.iter().all(|b| b.position.len() <= 5000));
+}
+
+/// Reads from disk, but each of its asynchronous reads waits for one poll, and it counts how many
+/// of them are in flight at once.
+#[cfg(feature = "async")]
+struct SlowDataProvider {
+    on_disk: OnDiskDataProvider,
+    in_flight: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    max_in_flight: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(feature = "async")]
+impl crate::data_provider::DataProvider for SlowDataProvider {
+    fn meta_proto(&self) -> Result<crate::proto::Meta> {
+        self.on_disk.meta_proto()
+    }
+
+    fn data(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> Result<std::collections::HashMap<String, Box<dyn std::io::Read + Send>>> {
+        self.on_disk.data(node_id, node_attributes)
+    }
+
+    fn data_async(
+        &self,
+        node_id: &str,
+        node_attributes: &[&str],
+    ) -> crate::data_provider::NodeDataFuture<'_> {
+        use std::sync::atomic::Ordering;
+        use std::task::Poll;
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        let mut data = Some(self.on_disk.data(node_id, node_attributes));
+        let mut is_first_poll = true;
+        Box::pin(futures::future::poll_fn(move |cx| {
+            if is_first_poll {
+                is_first_poll = false;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Poll::Ready(data.take().unwrap())
+        }))
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_stream_batches_overlaps_node_fetches() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_grid_octree_in(tmp_dir.path(), 0..1100, 0., 1., false);
+    let max_in_flight = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let octree = Octree::from_data_provider(Box::new(SlowDataProvider {
+        on_disk: OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        },
+        in_flight: Default::default(),
+        max_in_flight: std::sync::Arc::clone(&max_in_flight),
+    }))
+    .unwrap();
+    let query = PointQuery {
+        attributes: vec!["intensity"],
+        ..Default::default()
+    };
+    let num_points: usize =
+        futures::executor::block_on_stream(octree.stream_batches(&query, 5000).unwrap())
+            .map(|batch| batch.unwrap().position.len())
+            .sum();
+    assert_eq!(num_points, 110_000);
+    assert!(octree.nodes.len() > 1);
+    assert_eq!(
+        max_in_flight.load(std::sync::atomic::Ordering::SeqCst),
+        octree.nodes.len()
+    );
+}
+
 #[test]
 fn test_batch_iterator_more_points() {
     let batch_size = NUM_POINTS / 2;
@@ -134,3 +250,1691 @@ fn test_batch_iterator_more_points() {
         .expect("Iterator errored even though callback should not have errored.");
     assert_eq!(c.num_received_points, NUM_POINTS);
 }
+
+#[test]
+fn test_laid_out_batches_interleave_points() {
+    let octree = build_test_octree();
+    let query = PointQuery {
+        attributes: vec!["color"],
+        layout: BatchLayout {
+            interleaved: true,
+            f32_positions: true,
+        },
+        ..Default::default()
+    };
+    let mut num_points = 0;
+    ParallelIterator::new(std::slice::from_ref(&octree), &query, 10_000, 2, 2)
+        .try_for_each_laid_out_batch(|batch| {
+            let batch = match batch {
+                LaidOutBatch::Interleaved(batch) => batch,
+                other => panic!("Expected interleaved points, got {:?}.", other),
+            };
+            assert_eq!(batch.stride, 3 * 4 + 3);
+            assert_eq!(batch.data.len(), batch.num_points * batch.stride);
+            let color = batch.offset("color").unwrap();
+            assert!(batch
+                .data
+                .chunks_exact(batch.stride)
+                .all(|point| point[color..color + 3] == [255, 0, 0]));
+            num_points += batch.num_points;
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(num_points, NUM_POINTS);
+}
+
+#[test]
+fn test_update_adds_points() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_test_octree_in(tmp_dir.path());
+
+    let num_new_points = 150_000;
+    let position: Vec<_> = (0..num_new_points)
+        .map(|i| {
+            let t = i as f64 / num_new_points as f64;
+            Point3::new(-200. * t, -40. * (1. - t), 30. * t)
+        })
+        .collect();
+    let batch = PointsBatch {
+        position,
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(0, 255, 0); num_new_points]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    // One point outside of the bounding box, which is dropped.
+    let outside = PointsBatch {
+        position: vec![Point3::new(1., 1., 1.)],
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(0, 0, 255)]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    octree::update(tmp_dir.path(), vec![batch, outside].into_iter(), &["color"]).unwrap();
+
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let num_points_in_meta: i64 = octree.nodes.values().map(|meta| meta.num_points).sum();
+    assert_eq!(num_points_in_meta as usize, NUM_POINTS + num_new_points);
+
+    let location = PointQuery {
+        attributes: vec!["color"],
+        ..Default::default()
+    };
+    let mut c = Consumer::new(usize::MAX);
+    let octree_slice: &[Octree] = std::slice::from_ref(&octree);
+    ParallelIterator::new(octree_slice, &location, 10_000, 2, 2)
+        .try_for_each_batch(|points_batch| c.consume(points_batch))
+        .unwrap();
+    assert_eq!(c.num_received_points, NUM_POINTS + num_new_points);
+}
+
+#[test]
+fn test_delete_in_removes_points() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_test_octree_in(tmp_dir.path());
+    let mut octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+
+    // All points but the one at (-200, -40, 30) are at the origin.
+    let location = PointLocation::Aabb(Aabb::new(
+        Point3::new(-1.0, -1.0, -1.0),
+        Point3::new(1.0, 1.0, 1.0),
+    ));
+    let num_deleted = octree.delete_in(&location).unwrap();
+    assert_eq!(num_deleted, NUM_POINTS - 1);
+
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let num_points_in_meta: i64 = octree.nodes.values().map(|meta| meta.num_points).sum();
+    assert_eq!(num_points_in_meta, 1);
+
+    let query = PointQuery {
+        attributes: vec!["color"],
+        ..Default::default()
+    };
+    let mut c = Consumer::new(usize::MAX);
+    let octree_slice: &[Octree] = std::slice::from_ref(&octree);
+    ParallelIterator::new(octree_slice, &query, 10_000, 2, 2)
+        .try_for_each_batch(|points_batch| c.consume(points_batch))
+        .unwrap();
+    assert_eq!(c.num_received_points, 1);
+}
+
+#[test]
+fn test_update_subsamples_split_leaves_into_ancestors() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_grid_octree_in(tmp_dir.path(), 0..1100, 0., 1., false);
+    let open = || {
+        Octree::from_data_provider(Box::new(OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        }))
+        .unwrap()
+    };
+    let before = open();
+    let root_id = NodeId::from_level_index(0, 0);
+    let num_root_points = before.nodes[&root_id].num_points;
+
+    // Enough points between the grid points at x < 100 m to split the leaf they fall into.
+    let position: Vec<_> = (0..1000)
+        .flat_map(|x| {
+            (0..60)
+                .map(move |y| Point3::new(0.1 * f64::from(x) + 0.05, 1.5 * f64::from(y) + 0.5, 0.5))
+        })
+        .collect();
+    let num_new_points = position.len();
+    let batch = PointsBatch {
+        position,
+        attributes: vec![
+            (
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(0, 255, 0); num_new_points]),
+            ),
+            (
+                "intensity".to_string(),
+                AttributeData::F32(vec![2.; num_new_points]),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    octree::update(
+        tmp_dir.path(),
+        vec![batch].into_iter(),
+        &["color", "intensity"],
+    )
+    .unwrap();
+
+    let after = open();
+    let split_leaves: Vec<&NodeId> = before
+        .nodes
+        .keys()
+        .filter(|id| before.children(id).is_empty() && !after.children(id).is_empty())
+        .collect();
+    assert_eq!(split_leaves.len(), 1);
+    assert_eq!(split_leaves[0].parent_id(), Some(root_id));
+    // Every eighth new point of the split leaf was moved up into the root.
+    assert_eq!(
+        after.nodes[&root_id].num_points,
+        num_root_points + num_new_points as i64 / 8
+    );
+    let root_intensity = after.nodes[&root_id]
+        .attribute_ranges
+        .get("intensity")
+        .unwrap();
+    assert_eq!(root_intensity.upper_bound(), 2.);
+    let num_points: i64 = after.nodes.values().map(|meta| meta.num_points).sum();
+    assert_eq!(num_points as usize, 110_000 + num_new_points);
+}
+
+#[test]
+fn test_changed_nodes_after_update() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_test_octree_in(tmp_dir.path());
+    let open = || {
+        Octree::from_data_provider(Box::new(OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        }))
+        .unwrap()
+    };
+    let before = open();
+    assert!(before.changed_nodes(&before).is_empty());
+
+    let batch = PointsBatch {
+        position: vec![Point3::new(-100., -20., 15.)],
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(0, 255, 0)]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    octree::update(tmp_dir.path(), vec![batch].into_iter(), &["color"]).unwrap();
+    let after = open();
+    let changed = after.changed_nodes(&before);
+    assert!(!changed.is_empty());
+    // Nodes that got new points are reported, and so are nodes that were rewritten without
+    // changing their number of points.
+    assert!(after.nodes.iter().all(|(id, meta)| {
+        before.nodes.get(id).map(|m| m.num_points) == Some(meta.num_points) || changed.contains(id)
+    }));
+    assert!(changed.iter().all(|id| {
+        before.nodes.get(id).map(|m| (m.num_points, m.version))
+            != after.nodes.get(id).map(|m| (m.num_points, m.version))
+    }));
+}
+
+#[test]
+fn test_changed_nodes_after_update_attribute() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_test_octree_in(tmp_dir.path());
+    let open = || {
+        Octree::from_data_provider(Box::new(OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        }))
+        .unwrap()
+    };
+    let before = open();
+    let mut octree = open();
+    let query = PointQuery {
+        location: PointLocation::Aabb(Aabb::new(
+            Point3::new(-201., -41., 29.),
+            Point3::new(-199., -39., 31.),
+        )),
+        ..Default::default()
+    };
+    let num_updated = octree
+        .update_attribute(
+            &query,
+            "color",
+            AttributeUpdate::Constant(AttributeData::U8Vec3(vec![Vector3::new(0, 255, 0)])),
+        )
+        .unwrap();
+    assert_eq!(num_updated, 1);
+
+    // The node keeps its number of points, but is reported since its colors changed.
+    let after = open();
+    let changed = after.changed_nodes(&before);
+    assert_eq!(changed.len(), 1);
+    assert_eq!(
+        after.nodes[&changed[0]].num_points,
+        before.nodes[&changed[0]].num_points
+    );
+    assert_eq!(after.nodes[&changed[0]].version, 1);
+}
+
+#[test]
+fn test_node_cache_serves_repeated_queries() {
+    let mut octree = build_test_octree();
+    let node_cache = std::sync::Arc::new(octree::NodeCache::new(100 << 20));
+    octree.set_node_cache(std::sync::Arc::clone(&node_cache));
+    let query = PointQuery {
+        attributes: vec!["color"],
+        ..Default::default()
+    };
+    let octree_slice: &[Octree] = std::slice::from_ref(&octree);
+    let num_points = || {
+        let mut c = Consumer::new(usize::MAX);
+        ParallelIterator::new(octree_slice, &query, 5000, 2, 2)
+            .try_for_each_batch(|points_batch| c.consume(points_batch))
+            .unwrap();
+        c.num_received_points
+    };
+    assert_eq!(num_points(), NUM_POINTS);
+    let stats = node_cache.stats();
+    assert_eq!(stats.hits, 0);
+    assert!(stats.misses > 0);
+
+    assert_eq!(num_points(), NUM_POINTS);
+    assert_eq!(node_cache.stats().hits, stats.misses);
+    assert_eq!(node_cache.stats().misses, stats.misses);
+}
+
+#[test]
+fn test_pick_returns_closest_point() {
+    let octree = build_test_octree();
+    let radius = PickRadius::Metric(1.);
+    let ray = Ray::new(Point3::new(-300., -40., 30.5), Vector3::x(), 1000.);
+    let hit = octree.pick(&ray, radius, &["color"]).unwrap().unwrap();
+    // Positions are quantized, so they are only approximately the original ones.
+    assert!(nalgebra::distance(&hit.position, &Point3::new(-200., -40., 30.)) < 0.5);
+    assert!((hit.distance - 100.).abs() < 0.5);
+    assert!(hit.distance_to_ray < 1.);
+    match &hit.attributes["color"] {
+        AttributeData::U8Vec3(color) => assert_eq!(color, &[Vector3::new(255, 0, 0)]),
+        _ => panic!("Unexpected color data type."),
+    }
+
+    // From the other side, the points at the origin are hit first.
+    let ray = Ray::new(Point3::new(10., 0., 0.), -Vector3::x(), 1000.);
+    let hit = octree.pick(&ray, radius, &[]).unwrap().unwrap();
+    assert!(nalgebra::distance(&hit.position, &Point3::origin()) < 0.5);
+    assert!((hit.distance - 10.).abs() < 0.5);
+
+    let ray = Ray::new(Point3::new(10., 0., 0.), Vector3::y(), 1000.);
+    assert!(octree.pick(&ray, radius, &[]).unwrap().is_none());
+    let ray = Ray::new(Point3::new(10., 0., 0.), -Vector3::x(), 5.);
+    assert!(octree.pick(&ray, radius, &[]).unwrap().is_none());
+    let ray = Ray::new(Point3::new(10., 0., 0.), Vector3::zeros(), 1000.);
+    assert!(octree.pick(&ray, radius, &[]).is_err());
+}
+
+#[test]
+fn test_statistics_match_streamed_points() {
+    let octree = build_test_octree();
+    let query = PointQuery {
+        attributes: vec!["color"],
+        ..Default::default()
+    };
+    // The first query summarizes the nodes, the second uses the summaries.
+    for _ in 0..2 {
+        let statistics = octree.statistics(&query).unwrap();
+        assert_eq!(statistics.num_points, NUM_POINTS as u64);
+        let color = &statistics.attributes["color"];
+        assert_eq!(color[0].mean(), 255.);
+        assert_eq!(color[1].max, 0.);
+        let bounding_box = statistics.bounding_box.unwrap();
+        assert!(bounding_box.min().x < -199.);
+        assert!(bounding_box.max().x > -0.5);
+    }
+    assert!(!octree.node_statistics.lock().unwrap().is_empty());
+
+    // A box around the origin only, which does not contain the nodes completely.
+    let query = PointQuery {
+        attributes: vec!["color"],
+        location: PointLocation::Aabb(Aabb::new(
+            Point3::new(-1., -1., -1.),
+            Point3::new(1., 1., 1.),
+        )),
+        ..Default::default()
+    };
+    let statistics = octree.statistics(&query).unwrap();
+    assert_eq!(statistics.num_points, NUM_POINTS as u64 - 1);
+    assert_eq!(statistics.attributes["color"][0].stddev(), 0.);
+}
+
+#[test]
+fn test_detects_planes_in_octree() {
+    // A floor at z = 1 and points scattered above it.
+    let mut position: Vec<Point3<f64>> = (0..40_000)
+        .map(|i| Point3::new(f64::from(i % 200) * 0.1, f64::from(i / 200) * 0.1, 1.))
+        .collect();
+    position.extend((0..4000).map(|i| {
+        let i = f64::from(i);
+        Point3::new((i * 0.37) % 20., (i * 0.73) % 20., 2. + (i * 0.11) % 5.)
+    }));
+    let num_points = position.len();
+    let batch = PointsBatch {
+        position,
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let bounding_box = Aabb::new(Point3::new(0., 0., 0.), Point3::new(20., 20., 8.));
+    build_octree(
+        tmp_dir.path(),
+        0.001,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color"],
+    );
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_owned(),
+    }))
+    .unwrap();
+
+    let detection = PlaneDetection {
+        min_inlier_fraction: 0.5,
+        ..Default::default()
+    };
+    let planes = detection.detect(&octree, &PointQuery::default()).unwrap();
+    assert_eq!(planes.len(), 1);
+    let floor = &planes[0].plane;
+    assert!(floor.normal().z.abs() > 0.999);
+    assert!((floor.signed_distance(&Point3::new(3., 4., 1.))).abs() < 0.01);
+    assert!((planes[0].num_inliers as i64 - 40_000).abs() < 1000);
+
+    let mut num_inliers = 0;
+    let planes = [floor.clone()];
+    for_each_plane_mask(
+        &octree,
+        &PointQuery::default(),
+        &planes,
+        0.1,
+        |batch, mask| {
+            assert_eq!(batch.position.len(), mask.len());
+            num_inliers += mask.iter().filter(|plane| plane.is_some()).count();
+            Ok(())
+        },
+    )
+    .unwrap();
+    assert_eq!(num_inliers, 40_000);
+}
+
+#[test]
+fn test_output_from_query_transforms_positions() {
+    let octree = build_test_octree();
+    // Only the far point, moved to the origin of the output frame.
+    let query = PointQuery {
+        location: PointLocation::Aabb(Aabb::new(
+            Point3::new(-201., -41., 29.),
+            Point3::new(-199., -39., 31.),
+        )),
+        output_from_query: Some(Isometry3::translation(200., 40., -30.)),
+        ..Default::default()
+    };
+    let mut positions = Vec::new();
+    ParallelIterator::new(std::slice::from_ref(&octree), &query, 1000, 2, 2)
+        .try_for_each_batch(|mut batch| {
+            positions.append(&mut batch.position);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(positions.len(), 1);
+    assert!(positions[0].coords.norm() < 1.);
+
+    // The summaries of whole nodes are in the frame of the octree, so they are not used.
+    let query = PointQuery {
+        output_from_query: Some(Isometry3::translation(200., 40., -30.)),
+        ..Default::default()
+    };
+    for _ in 0..2 {
+        let bounding_box = octree.statistics(&query).unwrap().bounding_box.unwrap();
+        assert!((bounding_box.min() - Point3::new(0., 0., -30.)).norm() < 1.);
+        assert!((bounding_box.max() - Point3::new(200., 40., 0.)).norm() < 1.);
+    }
+}
+
+#[test]
+fn test_clip_planes_cut_points() {
+    let octree = build_test_octree();
+    let count = |query: &PointQuery| {
+        let mut num_points = 0;
+        ParallelIterator::new(std::slice::from_ref(&octree), query, 1000, 2, 2)
+            .try_for_each_batch(|batch| {
+                num_points += batch.position.len();
+                Ok(())
+            })
+            .unwrap();
+        num_points
+    };
+    let cut = Point3::new(-100., 0., 0.);
+    // Only the far point is behind the plane.
+    let query = PointQuery {
+        clip_planes: vec![HalfSpace::new(Vector3::x(), &cut)],
+        ..Default::default()
+    };
+    assert_eq!(count(&query), 1);
+    assert_eq!(octree.statistics(&query).unwrap().num_points, 1);
+    // Nodes behind a plane are not read at all.
+    let query = PointQuery {
+        clip_planes: vec![HalfSpace::new(Vector3::x(), &Point3::new(-300., 0., 0.))],
+        ..Default::default()
+    };
+    assert!(octree.nodes_for_query(&query).is_empty());
+
+    let query = PointQuery {
+        clip_planes: vec![HalfSpace::new(-Vector3::x(), &cut)],
+        ..Default::default()
+    };
+    assert_eq!(count(&query), NUM_POINTS - 1);
+    // The summaries of nodes that the plane cuts are not used.
+    for _ in 0..2 {
+        assert_eq!(
+            octree.statistics(&query).unwrap().num_points,
+            NUM_POINTS as u64 - 1
+        );
+    }
+}
+
+#[test]
+fn test_attribute_filters_skip_nodes() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let mut intensity = vec![1.; NUM_POINTS];
+    intensity[NUM_POINTS - 1] = 100.;
+    let mut batch = PointsBatch {
+        position: vec![Point3::new(0.0, 0.0, 0.0); NUM_POINTS],
+        attributes: vec![
+            (
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); NUM_POINTS]),
+            ),
+            ("intensity".to_string(), AttributeData::F32(intensity)),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    batch.position[NUM_POINTS - 1] = Point3::new(-200., -40., 30.);
+    let bounding_box = Aabb::new(batch.position[0], batch.position[NUM_POINTS - 1]);
+    build_octree(
+        tmp_dir.path(),
+        1.0,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color", "intensity"],
+    );
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let root_ranges = &octree.subtree_attribute_ranges[&NodeId::from_level_index(0, 0)];
+    assert_eq!(root_ranges.get("intensity").unwrap().lower_bound(), 1.);
+    assert_eq!(root_ranges.get("intensity").unwrap().upper_bound(), 100.);
+    let ranges: Vec<(&str, f64, f64)> = root_ranges
+        .iter()
+        .map(|(name, range)| (name, range.lower_bound(), range.upper_bound()))
+        .collect();
+    assert_eq!(ranges, vec![("intensity", 1., 100.)]);
+    let intensity_range = octree.attribute_ranges().get("intensity").unwrap();
+    assert_eq!(intensity_range.upper_bound(), 100.);
+
+    let query = |min, max| PointQuery {
+        attributes: vec!["intensity"],
+        filter_intervals: vec![("intensity", ClosedInterval::new(min, max))]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    // Nodes without points never match a filter.
+    let all_nodes: Vec<NodeId> = octree
+        .nodes_in_location(&PointLocation::AllPoints)
+        .into_iter()
+        .filter(|node_id| octree.nodes[node_id].num_points > 0)
+        .collect();
+    let bright = query(50., 200.);
+    let bright_nodes = octree.nodes_for_query(&bright);
+    assert!(!bright_nodes.is_empty());
+    assert!(bright_nodes.len() < all_nodes.len());
+    assert_eq!(octree.statistics(&bright).unwrap().num_points, 1);
+    assert!(octree.nodes_for_query(&query(500., 600.)).is_empty());
+    assert_eq!(
+        octree.nodes_for_query(&query(0., 200.)).len(),
+        all_nodes.len()
+    );
+}
+
+#[test]
+fn test_time_range_filters_points() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let num_points = 20_000;
+    let position: Vec<_> = (0..num_points)
+        .map(|i| Point3::new(f64::from(i % 100), f64::from(i / 100), 0.))
+        .collect();
+    // The timestamps grow along y, so later points are in separate nodes.
+    let timestamp: Vec<f64> = position.iter().map(|p| 1000. + p.y).collect();
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(99., 199., 1.));
+    let batch = PointsBatch {
+        position,
+        attributes: vec![
+            (
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points as usize]),
+            ),
+            ("timestamp".to_string(), AttributeData::F64(timestamp)),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    build_octree(
+        tmp_dir.path(),
+        0.1,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color", "timestamp"],
+    );
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+
+    let query = PointQuery {
+        attributes: vec!["color"],
+        time_range: Some((1000., 1009.5)),
+        ..Default::default()
+    };
+    let mut num_received_points = 0;
+    let octree_slice: &[Octree] = std::slice::from_ref(&octree);
+    ParallelIterator::new(octree_slice, &query, 10_000, 2, 2)
+        .try_for_each_batch(|points_batch| {
+            assert!(points_batch.position.iter().all(|p| p.y < 9.5));
+            assert!(!points_batch.attributes.contains_key("timestamp"));
+            num_received_points += points_batch.position.len();
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(num_received_points, 1000);
+    assert!(octree.nodes_for_query(&query).len() < octree.nodes.len());
+    assert_eq!(octree.statistics(&query).unwrap().num_points, 1000);
+    let later = PointQuery {
+        time_range: Some((2000., 3000.)),
+        ..Default::default()
+    };
+    assert!(octree.nodes_for_query(&later).is_empty());
+}
+
+fn label_test_dictionary() -> LabelDictionary {
+    let mut label_dictionary = LabelDictionary::new(AttributeDataType::U8).unwrap();
+    label_dictionary.add(2, "ground").unwrap();
+    label_dictionary.add(5, "vegetation").unwrap();
+    label_dictionary.add(6, "building").unwrap();
+    label_dictionary
+}
+
+fn build_labeled_test_octree_in(directory: &Path) -> Octree {
+    // More points than fit into the root, so that it is split.
+    let num_points = 200_000;
+    let position: Vec<_> = (0..num_points)
+        .map(|i| Point3::new(f64::from(i % 400), f64::from(i / 400), 0.))
+        .collect();
+    // The lower half is ground with a few buildings, the upper half is vegetation.
+    let classification: Vec<u8> = position
+        .iter()
+        .map(|p| match (p.y < 250., p.x < 40.) {
+            (true, false) => 2,
+            (true, true) => 6,
+            (false, _) => 5,
+        })
+        .collect();
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(399., 499., 1.));
+    let batch = PointsBatch {
+        position,
+        attributes: vec![
+            (
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points as usize]),
+            ),
+            (
+                CLASSIFICATION_ATTRIBUTE.to_string(),
+                AttributeData::U8(classification),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    build_labeled_octree(
+        directory,
+        0.1,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color"],
+        label_test_dictionary(),
+    );
+    Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_labels_filter_points() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let octree = build_labeled_test_octree_in(tmp_dir.path());
+    assert_eq!(octree.label_dictionary(), Some(&label_test_dictionary()));
+
+    let query = PointQuery {
+        attributes: vec![CLASSIFICATION_ATTRIBUTE],
+        labels: Some(vec!["ground", "building"]),
+        ..Default::default()
+    };
+    let mut num_received_points = 0;
+    let octree_slice: &[Octree] = std::slice::from_ref(&octree);
+    ParallelIterator::new(octree_slice, &query, 10_000, 2, 2)
+        .try_for_each_batch(|points_batch| {
+            assert!(points_batch.position.iter().all(|p| p.y < 250.));
+            let classification: &Vec<u8> = points_batch
+                .get_attribute_vec(CLASSIFICATION_ATTRIBUTE)
+                .unwrap();
+            assert!(classification.iter().all(|c| *c == 2 || *c == 6));
+            num_received_points += points_batch.position.len();
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(num_received_points, 100_000);
+    let buildings = PointQuery {
+        labels: Some(vec!["building"]),
+        ..Default::default()
+    };
+    assert_eq!(octree.statistics(&buildings).unwrap().num_points, 10_000);
+    assert!(octree.nodes_for_query(&buildings).len() < octree.nodes.len());
+    let water = PointQuery {
+        labels: Some(vec!["water"]),
+        ..Default::default()
+    };
+    assert!(octree.statistics(&water).is_err());
+}
+
+#[test]
+fn test_update_attribute_rewrites_matching_points() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let mut octree = build_labeled_test_octree_in(tmp_dir.path());
+    let num_labeled = |octree: &Octree, label| {
+        let query = PointQuery {
+            labels: Some(vec![label]),
+            ..Default::default()
+        };
+        octree.statistics(&query).unwrap().num_points
+    };
+
+    // Some of the vegetation turns out to be buildings.
+    let location = PointLocation::Aabb(Aabb::new(
+        Point3::new(-0.5, 249.5, -1.),
+        Point3::new(39.5, 499.5, 1.),
+    ));
+    let query = PointQuery {
+        location,
+        ..Default::default()
+    };
+    let num_updated = octree
+        .update_attribute(
+            &query,
+            CLASSIFICATION_ATTRIBUTE,
+            AttributeUpdate::Constant(AttributeData::U8(vec![6])),
+        )
+        .unwrap();
+    assert_eq!(num_updated, 10_000);
+    assert_eq!(num_labeled(&octree, "building"), 20_000);
+
+    // The eastern ground is overgrown.
+    let query = PointQuery {
+        attributes: vec!["color"],
+        labels: Some(vec!["ground"]),
+        ..Default::default()
+    };
+    let classify = |batch: &PointsBatch| {
+        assert!(batch.attributes.contains_key("color"));
+        assert!(!batch.attributes.contains_key(CLASSIFICATION_ATTRIBUTE));
+        let labels = batch
+            .position
+            .iter()
+            .map(|p| if p.x < 199.5 { 2 } else { 5 })
+            .collect();
+        Ok(AttributeData::U8(labels))
+    };
+    let num_updated = octree
+        .update_attribute(
+            &query,
+            CLASSIFICATION_ATTRIBUTE,
+            AttributeUpdate::PerPoint(Box::new(classify)),
+        )
+        .unwrap();
+    assert_eq!(num_updated, 90_000);
+    assert_eq!(num_labeled(&octree, "ground"), 40_000);
+    assert_eq!(num_labeled(&octree, "vegetation"), 140_000);
+
+    // The values are persisted and the positions are untouched.
+    let reopened = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    assert_eq!(num_labeled(&reopened, "ground"), 40_000);
+    let statistics = reopened.statistics(&PointQuery::default()).unwrap();
+    assert_eq!(statistics.num_points, 200_000);
+
+    assert!(octree
+        .update_attribute(
+            &query,
+            CLASSIFICATION_ATTRIBUTE,
+            AttributeUpdate::Constant(AttributeData::F32(vec![1.])),
+        )
+        .is_err());
+    assert!(octree
+        .update_attribute(
+            &query,
+            "intensity",
+            AttributeUpdate::Constant(AttributeData::F32(vec![1.])),
+        )
+        .is_err());
+}
+
+#[test]
+fn test_update_attribute_keeps_meta_of_rewritten_nodes_on_error() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let mut octree = build_labeled_test_octree_in(tmp_dir.path());
+    let query = PointQuery {
+        labels: Some(vec!["vegetation"]),
+        ..Default::default()
+    };
+    // The vegetation of the first node gets a class that no node had before, then the classifier
+    // fails.
+    let mut num_rewritten = None;
+    let classify = |batch: &PointsBatch| match num_rewritten {
+        None => {
+            num_rewritten = Some(batch.position.len());
+            Ok(AttributeData::U8(vec![9; batch.position.len()]))
+        }
+        Some(_) => Err("Classifier failed".into()),
+    };
+    assert!(octree
+        .update_attribute(
+            &query,
+            CLASSIFICATION_ATTRIBUTE,
+            AttributeUpdate::PerPoint(Box::new(classify)),
+        )
+        .is_err());
+    let num_rewritten = num_rewritten.unwrap() as u64;
+    assert!(num_rewritten > 0 && num_rewritten < 100_000);
+
+    // The rewritten node is only read if its new range was written to the meta.
+    let reopened = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let num_in_class = |min, max| {
+        let query = PointQuery {
+            attributes: vec![CLASSIFICATION_ATTRIBUTE],
+            filter_intervals: vec![(CLASSIFICATION_ATTRIBUTE, ClosedInterval::new(min, max))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        reopened.statistics(&query).unwrap().num_points
+    };
+    assert_eq!(num_in_class(9., 9.), num_rewritten);
+    assert_eq!(num_in_class(5., 5.), 100_000 - num_rewritten);
+}
+
+#[test]
+fn test_build_filtered_octree_removes_outliers() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    // More points than fit into the root, so that it is split.
+    let mut position: Vec<_> = (0..120_000)
+        .map(|i| Point3::new(f64::from(i % 400), f64::from(i / 400), 0.))
+        .collect();
+    // A flock of birds.
+    position.extend((0..5).map(|i| Point3::new(f64::from(i) * 50., 100., 50.)));
+    let num_points = position.len();
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(399., 299., 50.));
+    let batch = PointsBatch {
+        position,
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    build_filtered_octree(
+        tmp_dir.path(),
+        0.1,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color"],
+        &[OutlierFilter::Radius {
+            radius: 1.5,
+            min_neighbors: 2,
+        }],
+    );
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let statistics = octree.statistics(&PointQuery::default()).unwrap();
+    assert_eq!(statistics.num_points, 120_000);
+    assert!(statistics.bounding_box.unwrap().max().z < 1.);
+}
+
+#[test]
+fn test_build_filtered_octree_keeps_points_at_node_borders() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    // A plane split into four leaves, whose borders are at x = 199.5 and y = 199.5.
+    let position: Vec<_> = (0..120_000)
+        .map(|i| Point3::new(f64::from(i % 400), f64::from(i / 400), 0.))
+        .collect();
+    let num_points = position.len();
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(399., 299., 50.));
+    let batch = PointsBatch {
+        position,
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    // Only the points with neighbors on all sides are kept, i.e. all but the outermost ones.
+    build_filtered_octree(
+        tmp_dir.path(),
+        0.1,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color"],
+        &[OutlierFilter::Radius {
+            radius: 1.5,
+            min_neighbors: 6,
+        }],
+    );
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    assert_eq!(octree.nodes.len(), 5);
+    let num_points_in = |mins, maxs| {
+        let query = PointQuery {
+            location: PointLocation::Aabb(Aabb::new(mins, maxs)),
+            ..Default::default()
+        };
+        octree.statistics(&query).unwrap().num_points
+    };
+    assert_eq!(
+        num_points_in(Point3::new(198.5, 0.5, -1.), Point3::new(200.5, 298.5, 1.)),
+        2 * 298
+    );
+    assert_eq!(
+        num_points_in(Point3::new(0.5, 198.5, -1.), Point3::new(398.5, 200.5, 1.)),
+        398 * 2
+    );
+    assert_eq!(
+        octree
+            .statistics(&PointQuery::default())
+            .unwrap()
+            .num_points,
+        398 * 298
+    );
+}
+
+#[test]
+fn test_estimate_normals_across_node_borders() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    // A tilted plane, of which the leaves at x > 299.5 only get the single column at x = 300.
+    // Its points lie on a line, so their normals come from the plane in the adjacent leaves.
+    let position: Vec<_> = (0..120_400)
+        .map(|i| {
+            let x = f64::from(i % 301);
+            Point3::new(x, f64::from(i / 301), 0.5 * x)
+        })
+        .collect();
+    let num_points = position.len();
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(599., 399., 150.));
+    let batch = PointsBatch {
+        position,
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    build_octree(
+        tmp_dir.path(),
+        0.1,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color"],
+    );
+    estimate_normals(tmp_dir.path(), octree::NUM_NORMAL_NEIGHBORS).unwrap();
+
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    assert_eq!(octree.nodes.len(), 5);
+    let query = PointQuery {
+        attributes: vec!["normal"],
+        ..Default::default()
+    };
+    // The positions are stored with the resolution of the octree, which tilts the normals a bit.
+    let expected = Vector3::new(-0.5, 0., 1.).normalize();
+    let mut num_received_points = 0;
+    let octree_slice: &[Octree] = std::slice::from_ref(&octree);
+    ParallelIterator::new(octree_slice, &query, 10_000, 2, 2)
+        .try_for_each_batch(|points_batch| {
+            let normal: &Vec<Vector3<f64>> = points_batch.get_attribute_vec("normal").unwrap();
+            assert!(normal.iter().all(|n| (n - expected).norm() < 1e-2));
+            num_received_points += points_batch.position.len();
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(num_received_points, num_points);
+}
+
+#[test]
+fn test_downsample_keeps_one_point_per_voxel() {
+    let tmp_dir = TempDir::new("octree").unwrap();
+    // More points than fit into the root, so that it is split.
+    let num_points = 250_000;
+    let position: Vec<_> = (0..num_points)
+        .map(|i| Point3::new(f64::from(i % 500) * 0.2, f64::from(i / 500) * 0.2, 0.))
+        .collect();
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(99.8, 99.8, 1.));
+    let batch = PointsBatch {
+        position,
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points as usize]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    build_octree(
+        tmp_dir.path(),
+        0.1,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color"],
+    );
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+
+    for average in &[false, true] {
+        let query = PointQuery {
+            attributes: vec!["color"],
+            downsample: Some(VoxelSize {
+                edge_length: 10.,
+                average: *average,
+            }),
+            ..Default::default()
+        };
+        let mut voxels = std::collections::HashSet::new();
+        let octree_slice: &[Octree] = std::slice::from_ref(&octree);
+        ParallelIterator::new(octree_slice, &query, 10_000, 2, 2)
+            .try_for_each_batch(|points_batch| {
+                let color: &Vec<Vector3<u8>> = points_batch.get_attribute_vec("color").unwrap();
+                assert!(color.iter().all(|c| *c == Vector3::new(255, 0, 0)));
+                for p in &points_batch.position {
+                    let voxel = ((p.x / 10.).floor() as i64, (p.y / 10.).floor() as i64);
+                    assert!(voxels.insert(voxel));
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(voxels.len(), 100);
+        assert_eq!(octree.statistics(&query).unwrap().num_points, 100);
+    }
+    // The children of the root are not larger than the voxels.
+    let root_id = NodeId::from_level_index(0, 0);
+    let root_edge_length = octree.nodes[&root_id].bounding_cube.edge_length();
+    assert!(octree.nodes.len() > 1);
+    let query = PointQuery {
+        downsample: Some(VoxelSize::new(root_edge_length)),
+        ..Default::default()
+    };
+    assert_eq!(octree.nodes_for_query(&query), vec![root_id]);
+}
+
+#[test]
+fn test_select_nodes_for_view_respects_budget() {
+    let octree = build_test_octree();
+    // Looking down at the whole point cloud.
+    let frustum = Frustum::new(
+        Isometry3::translation(-100., -20., 500.),
+        Perspective::new(-1., 1., -1., 1., 1., 1000.),
+    );
+    let viewport = Viewport::new(800, 600);
+    let all = octree.select_nodes_for_view(&frustum, viewport, usize::MAX);
+    assert!(all.len() > 1);
+    assert_eq!(
+        all.iter().map(|n| n.id).collect::<Vec<_>>(),
+        octree.get_visible_nodes(frustum.clip_from_query())
+    );
+    assert!(all.iter().all(|n| n.num_points > 0 && n.point_size >= 0.));
+
+    let budget = all[0].num_points as usize;
+    let selected = octree.select_nodes_for_view(&frustum, viewport, budget);
+    let num_selected_points: i64 = selected.iter().map(|n| n.num_points).sum();
+    assert!(num_selected_points as usize <= budget);
+    assert_eq!(selected[0], all[0]);
+    assert!(octree
+        .select_nodes_for_view(&frustum, viewport, 0)
+        .is_empty());
+}
+
+#[test]
+fn test_resume_octree_after_interruption() {
+    // The root's children have too many points, so that they are split as well.
+    let batch = || {
+        let num_points: u32 = 300_000;
+        PointsBatch {
+            position: (0..num_points)
+                .map(|i| Point3::new(f64::from(i % 300), f64::from(i / 300), 0.))
+                .collect(),
+            attributes: vec![(
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points as usize]),
+            )]
+            .into_iter()
+            .collect(),
+        }
+    };
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(299., 999., 1.));
+    let expected_dir = TempDir::new("octree").unwrap();
+    build_octree(
+        expected_dir.path(),
+        0.1,
+        bounding_box.clone(),
+        vec![batch()].into_iter(),
+        &["color"],
+    );
+    assert!(!expected_dir.path().join(CHECKPOINT_FILENAME).exists());
+
+    // A build that is interrupted after splitting. Only the split of the root has been saved, so
+    // the splits of its children are repeated.
+    let tmp_dir = TempDir::new("octree").unwrap();
+    let octree_meta = OctreeMeta::new_with_standard_attributes(0.1, bounding_box);
+    let attribute_data_types = octree_meta.attribute_data_types_for(&["color"]).unwrap();
+    let octree_data_provider = OnDiskDataProvider {
+        directory: tmp_dir.path().to_path_buf(),
+    };
+    let checkpoint = Checkpoint::new(tmp_dir.path(), &octree_meta, &["color"]).unwrap();
+    let (leaf_nodes_sender, _leaf_nodes_receiver) = crossbeam::channel::unbounded();
+    rayon::scope(|scope| {
+        split_node(
+            scope,
+            &octree_data_provider,
+            &octree_meta,
+            &attribute_data_types,
+            &NodeId::from_level_index(0, 0),
+            vec![batch()].into_iter(),
+            &leaf_nodes_sender,
+            Some(&checkpoint),
+        );
+    });
+    drop(checkpoint);
+    assert!(tmp_dir.path().join(CHECKPOINT_FILENAME).exists());
+
+    assert!(resume_octree(tmp_dir.path(), &[]).unwrap());
+    assert!(!tmp_dir.path().join(CHECKPOINT_FILENAME).exists());
+    assert!(!resume_octree(tmp_dir.path(), &[]).unwrap());
+
+    let load = |directory: &Path| {
+        Octree::from_data_provider(Box::new(OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        }))
+        .unwrap()
+    };
+    let expected = load(expected_dir.path());
+    let resumed = load(tmp_dir.path());
+    assert!(expected.nodes.len() > 5);
+    assert_eq!(resumed.nodes.len(), expected.nodes.len());
+    for (id, node) in &expected.nodes {
+        assert_eq!(resumed.nodes[id].num_points, node.num_points, "{}", id);
+    }
+    let statistics = resumed.statistics(&PointQuery::default()).unwrap();
+    assert_eq!(statistics.num_points, 300_000);
+}
+
+#[test]
+fn test_validate_and_repair_octree() {
+    let num_points: u32 = 300_000;
+    let batch = PointsBatch {
+        position: (0..num_points)
+            .map(|i| Point3::new(f64::from(i % 300), f64::from(i / 300), 0.))
+            .collect(),
+        attributes: vec![(
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points as usize]),
+        )]
+        .into_iter()
+        .collect(),
+    };
+    let bounding_box = Aabb::new(Point3::origin(), Point3::new(299., 999., 1.));
+    let tmp_dir = TempDir::new("octree").unwrap();
+    build_octree(
+        tmp_dir.path(),
+        0.1,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color"],
+    );
+    assert_eq!(validate_octree(tmp_dir.path()).unwrap(), vec![]);
+
+    let data_provider = || {
+        Box::new(OnDiskDataProvider {
+            directory: tmp_dir.path().to_path_buf(),
+        })
+    };
+    let mut octree = Octree::from_data_provider(data_provider()).unwrap();
+    let mut leaves: Vec<NodeId> = octree
+        .nodes
+        .iter()
+        .filter(|(id, meta)| meta.num_points > 0 && octree.children(id).is_empty())
+        .map(|(id, _)| *id)
+        .collect();
+    leaves.sort_by_key(|id| id.index());
+    let inner = leaves[0].parent_id().unwrap();
+    assert!(inner.level() > 0);
+    let (missing, truncated, without_colors) = (leaves[1], leaves[2], leaves[3]);
+    let stem = |id: &NodeId| tmp_dir.path().join(id.to_string());
+
+    // A partial copy: the meta lost a node, one node has no files, one has a position file that
+    // ends in the middle of a point and one has half a color file. There is also a node that the
+    // meta never knew about.
+    octree.nodes.remove(&inner);
+    octree.write_changed_meta(tmp_dir.path()).unwrap();
+    std::fs::remove_file(stem(&missing).with_extension("xyz")).unwrap();
+    std::fs::remove_file(stem(&missing).with_extension("rgb")).unwrap();
+    let position = std::fs::read(stem(&truncated).with_extension("xyz")).unwrap();
+    std::fs::write(
+        stem(&truncated).with_extension("xyz"),
+        &position[..position.len() - 1],
+    )
+    .unwrap();
+    let color = std::fs::read(stem(&without_colors).with_extension("rgb")).unwrap();
+    std::fs::write(
+        stem(&without_colors).with_extension("rgb"),
+        &color[..color.len() / 2],
+    )
+    .unwrap();
+    let orphan: NodeId = "r7777777".parse().unwrap();
+    std::fs::write(stem(&orphan).with_extension("rgb"), &[0, 0, 0]).unwrap();
+
+    let problems = validate_octree(tmp_dir.path()).unwrap();
+    let node_ids = |problems: &[OctreeProblem], f: fn(&OctreeProblem) -> bool| {
+        let mut node_ids: Vec<NodeId> = problems
+            .iter()
+            .filter(|p| f(p))
+            .map(|p| p.node_id())
+            .collect();
+        node_ids.sort_by_key(|id| (id.level(), id.index()));
+        node_ids
+    };
+    assert_eq!(
+        node_ids(&problems, |p| matches!(p, OctreeProblem::OrphanedNode(_))),
+        vec![inner, orphan]
+    );
+    assert_eq!(
+        node_ids(&problems, |p| matches!(p, OctreeProblem::MissingNode(_))),
+        vec![missing]
+    );
+    assert_eq!(
+        node_ids(&problems, |p| matches!(
+            p,
+            OctreeProblem::AttributeLengthMismatch { .. }
+        )),
+        vec![truncated, without_colors]
+    );
+    let attribute_of = |node_id: NodeId| {
+        problems.iter().find_map(|p| match p {
+            OctreeProblem::AttributeLengthMismatch {
+                node_id: id,
+                attribute,
+                ..
+            } if *id == node_id => Some(attribute.as_str()),
+            _ => None,
+        })
+    };
+    assert_eq!(attribute_of(truncated), Some("position"));
+    assert_eq!(attribute_of(without_colors), Some("color"));
+    assert!(problems.iter().all(|p| !matches!(
+        p,
+        OctreeProblem::WrongNumPoints { node_id, .. } if *node_id == without_colors
+    )));
+    assert!(problems.contains(&OctreeProblem::MissingParent(leaves[0])));
+    assert!(problems.iter().all(OctreeProblem::is_repairable));
+
+    assert_eq!(repair_octree(tmp_dir.path(), &problems).unwrap(), vec![]);
+    assert_eq!(validate_octree(tmp_dir.path()).unwrap(), vec![]);
+    assert!(!stem(&orphan).with_extension("rgb").exists());
+    assert!(!stem(&truncated).with_extension("xyz").exists());
+    assert!(stem(&without_colors).with_extension("xyz").exists());
+    assert!(!stem(&without_colors).with_extension("rgb").exists());
+
+    let repaired = Octree::from_data_provider(data_provider()).unwrap();
+    assert_eq!(repaired.nodes[&inner].num_points, 0);
+    assert!(!repaired.nodes.contains_key(&missing));
+    assert!(!repaired.nodes.contains_key(&truncated));
+    assert_eq!(
+        repaired.nodes[&without_colors].num_points,
+        octree.nodes[&without_colors].num_points
+    );
+    let num_points_in_meta: i64 = repaired.nodes.values().map(|meta| meta.num_points).sum();
+    let statistics = repaired.statistics(&PointQuery::default()).unwrap();
+    assert_eq!(statistics.num_points as i64, num_points_in_meta);
+}
+
+#[test]
+fn test_convert_between_octree_and_s2_cells() {
+    // ECEF points on the equator, in an area of 30 m x 100 m.
+    let num_points: u32 = 300_000;
+    let batch = PointsBatch {
+        position: (0..num_points)
+            .map(|i| {
+                Point3::new(
+                    6_378_137.,
+                    f64::from(i % 300) * 0.1,
+                    f64::from(i / 300) * 0.1,
+                )
+            })
+            .collect(),
+        attributes: vec![
+            (
+                "color".to_string(),
+                AttributeData::U8Vec3(
+                    (0..num_points)
+                        .map(|i| Vector3::new((i % 256) as u8, 0, 0))
+                        .collect(),
+                ),
+            ),
+            (
+                "intensity".to_string(),
+                AttributeData::F32((0..num_points).map(|i| (i % 100) as f32).collect()),
+            ),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    let bounding_box = Aabb::new(
+        Point3::new(6_378_136., 0., 0.),
+        Point3::new(6_378_138., 30., 100.),
+    );
+    let octree_dir = TempDir::new("octree").unwrap();
+    build_octree(
+        octree_dir.path(),
+        0.001,
+        bounding_box,
+        vec![batch].into_iter(),
+        &["color", "intensity"],
+    );
+
+    let s2_dir = TempDir::new("s2").unwrap();
+    write_s2_cells_from_octree(s2_dir.path(), 20, octree_dir.path()).unwrap();
+    let s2_cells = S2Cells::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: s2_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    assert!(s2_cells.meta().get_cells().len() > 1);
+
+    let converted_dir = TempDir::new("octree").unwrap();
+    build_octree_from_s2_cells(converted_dir.path(), 0.001, s2_dir.path()).unwrap();
+    let load = |directory: &Path| {
+        Octree::from_data_provider(Box::new(OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        }))
+        .unwrap()
+    };
+    let octree = load(octree_dir.path());
+    let converted = load(converted_dir.path());
+    assert_eq!(converted.coordinate_system(), Some(CoordinateSystem::Ecef));
+
+    let query = PointQuery {
+        attributes: vec!["color", "intensity"],
+        ..Default::default()
+    };
+    let expected = octree.statistics(&query).unwrap();
+    let in_s2_cells = s2_cells.statistics(&query).unwrap();
+    let actual = converted.statistics(&query).unwrap();
+    for statistics in &[&in_s2_cells, &actual] {
+        assert_eq!(statistics.num_points, u64::from(num_points));
+        assert_eq!(statistics.attributes.len(), 2);
+        for (name, values) in &expected.attributes {
+            let (expected_range, range) = (&values[0], &statistics.attributes[name][0]);
+            assert_eq!(
+                (range.min, range.max),
+                (expected_range.min, expected_range.max)
+            );
+            assert!(
+                (range.mean() - expected_range.mean()).abs() < 1e-6,
+                "{}",
+                name
+            );
+        }
+    }
+
+    // The same S2 point cloud can be written from the points in a PCD file.
+    let pcd_dir = TempDir::new("pcd").unwrap();
+    export_octree(octree_dir.path(), pcd_dir.path(), ExportFormat::Pcd).unwrap();
+    let s2_from_pcd_dir = TempDir::new("s2").unwrap();
+    write_s2_cells_from_file(
+        s2_from_pcd_dir.path(),
+        20,
+        pcd_dir.path().join("points.pcd"),
+    )
+    .unwrap();
+    let s2_from_pcd = S2Cells::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: s2_from_pcd_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    assert_eq!(
+        s2_from_pcd.meta().get_cells().len(),
+        s2_cells.meta().get_cells().len()
+    );
+    let from_pcd = s2_from_pcd.statistics(&query).unwrap();
+    assert_eq!(from_pcd.num_points, u64::from(num_points));
+    assert_eq!(from_pcd.attributes.len(), 2);
+}
+
+/// Builds an octree of a grid of points 1 m apart, with `x_range` and 100 m along y.
+fn build_grid_octree_in(
+    directory: &Path,
+    x_range: std::ops::Range<u32>,
+    offset: f64,
+    intensity: f32,
+    with_timestamps: bool,
+) {
+    let position: Vec<_> = x_range
+        .clone()
+        .flat_map(|x| (0..100).map(move |y| Point3::new(f64::from(x) + offset, f64::from(y), 0.)))
+        .collect();
+    let num_points = position.len();
+    let mut attributes = vec![
+        (
+            "color".to_string(),
+            AttributeData::U8Vec3(vec![Vector3::new(255, 0, 0); num_points]),
+        ),
+        (
+            "intensity".to_string(),
+            AttributeData::F32(vec![intensity; num_points]),
+        ),
+    ];
+    if with_timestamps {
+        attributes.push((
+            "timestamp".to_string(),
+            AttributeData::F64(vec![1.; num_points]),
+        ));
+    }
+    let names: Vec<String> = attributes.iter().map(|(name, _)| name.clone()).collect();
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+    let bounding_box = Aabb::new(
+        Point3::new(f64::from(x_range.start) + offset, 0., 0.),
+        Point3::new(f64::from(x_range.end - 1) + offset, 99., 1.),
+    );
+    let batch = PointsBatch {
+        position,
+        attributes: attributes.into_iter().collect(),
+    };
+    build_octree(
+        directory,
+        0.01,
+        bounding_box,
+        vec![batch].into_iter(),
+        &names,
+    );
+}
+
+#[test]
+fn test_merge_octrees_removes_duplicates() {
+    // Two scans overlapping in 50 m <= x < 100 m, where the second one is brighter.
+    let first_dir = TempDir::new("octree").unwrap();
+    build_grid_octree_in(first_dir.path(), 0..100, 0., 1., false);
+    let second_dir = TempDir::new("octree").unwrap();
+    build_grid_octree_in(second_dir.path(), 50..150, 0.01, 2., true);
+    let inputs = [first_dir.path(), second_dir.path()];
+    let load = |directory: &Path| {
+        Octree::from_data_provider(Box::new(OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        }))
+        .unwrap()
+    };
+    let query = |min: f64, max: f64| PointQuery {
+        attributes: vec!["intensity"],
+        filter_intervals: vec![("intensity", ClosedInterval::new(min, max))]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+
+    let merged_dir = TempDir::new("octree").unwrap();
+    merge_octrees(merged_dir.path(), &inputs, None, None).unwrap();
+    let merged = load(merged_dir.path());
+    assert_eq!(merged.meta.resolution, 0.01);
+    assert_eq!(
+        merged.statistics(&query(0., 3.)).unwrap().num_points,
+        20_000
+    );
+    // The points of the first scan have no timestamps, so they get zeros.
+    let statistics = merged
+        .statistics(&PointQuery {
+            attributes: vec!["timestamp"],
+            ..Default::default()
+        })
+        .unwrap();
+    let timestamps = &statistics.attributes["timestamp"][0];
+    assert_eq!((timestamps.min, timestamps.max), (0., 1.));
+
+    // Few points per tile, so that duplicates are also found across tile borders.
+    let deduplicated_dir = TempDir::new("octree").unwrap();
+    let deduplication = Deduplication {
+        epsilon: 0.1,
+        keep: DuplicatePreference::Intensity,
+    };
+    merge_octrees_with_max_tile_points(
+        deduplicated_dir.path(),
+        &inputs,
+        None,
+        Some(&deduplication),
+        1000.,
+    )
+    .unwrap();
+    let deduplicated = load(deduplicated_dir.path());
+    assert_eq!(
+        deduplicated.statistics(&query(0., 3.)).unwrap().num_points,
+        15_000
+    );
+    assert_eq!(
+        deduplicated.statistics(&query(0., 1.5)).unwrap().num_points,
+        5_000
+    );
+
+    let by_timestamp = Deduplication {
+        keep: DuplicatePreference::Timestamp,
+        ..deduplication
+    };
+    merge_octrees(deduplicated_dir.path(), &inputs, None, Some(&by_timestamp)).unwrap();
+    let deduplicated = load(deduplicated_dir.path());
+    assert_eq!(
+        deduplicated.statistics(&query(1.5, 3.)).unwrap().num_points,
+        10_000
+    );
+}
+
+#[test]
+fn test_metadata_survives_update_and_merge() {
+    let first_dir = TempDir::new("octree").unwrap();
+    build_grid_octree_in(first_dir.path(), 0..10, 0., 1., false);
+    let second_dir = TempDir::new("octree").unwrap();
+    build_grid_octree_in(second_dir.path(), 10..20, 0., 2., false);
+    assert!(read_metadata(first_dir.path()).unwrap().is_empty());
+    let first_metadata = Metadata {
+        sensor_model: Some("VLP-16".to_string()),
+        processing_history: vec![ProcessingStep::now("build_octree", "Built from a.ply.")],
+        ..Default::default()
+    };
+    write_metadata(first_dir.path(), &first_metadata).unwrap();
+    let second_metadata = Metadata {
+        sensor_model: Some("VLP-16".to_string()),
+        acquisition_date: Some("2020-10-14".to_string()),
+        ..Default::default()
+    };
+    write_metadata(second_dir.path(), &second_metadata).unwrap();
+
+    let batch = PointsBatch {
+        position: vec![Point3::new(5., 5., 0.)],
+        attributes: vec![
+            (
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(0, 0, 255)]),
+            ),
+            ("intensity".to_string(), AttributeData::F32(vec![3.])),
+        ]
+        .into_iter()
+        .collect(),
+    };
+    octree::update(
+        first_dir.path(),
+        vec![batch].into_iter(),
+        &["color", "intensity"],
+    )
+    .unwrap();
+    assert_eq!(read_metadata(first_dir.path()).unwrap(), first_metadata);
+
+    let merged_dir = TempDir::new("octree").unwrap();
+    merge_octrees(
+        merged_dir.path(),
+        &[first_dir.path(), second_dir.path()],
+        None,
+        None,
+    )
+    .unwrap();
+    let merged = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: merged_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let metadata = merged.metadata().unwrap();
+    assert_eq!(metadata.sensor_model, second_metadata.sensor_model);
+    assert_eq!(metadata.acquisition_date, second_metadata.acquisition_date);
+    let tools: Vec<&str> = metadata
+        .processing_history
+        .iter()
+        .map(|step| step.tool.as_str())
+        .collect();
+    assert_eq!(tools, vec!["build_octree", "octree merge"]);
+}
+
+#[test]
+fn test_export_keeps_the_nodes_of_the_octree() {
+    use byteorder::{ByteOrder, LittleEndian};
+
+    let octree_dir = TempDir::new("octree").unwrap();
+    build_grid_octree_in(octree_dir.path(), 0..1100, 0., 1., false);
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: octree_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let node_ids = octree.nodes_in_location(&PointLocation::AllPoints);
+    assert!(node_ids.len() > 1);
+
+    let tiles_dir = TempDir::new("tiles").unwrap();
+    export_octree(octree_dir.path(), tiles_dir.path(), ExportFormat::Tiles3d).unwrap();
+    let tileset = std::fs::read_to_string(tiles_dir.path().join("tileset.json")).unwrap();
+    assert!(tileset.contains(r#""refine":"ADD","content":{"uri":"r.pnts"}"#));
+    let mut num_points = 0;
+    for node_id in &node_ids {
+        let pnts = std::fs::read(tiles_dir.path().join(format!("{}.pnts", node_id))).unwrap();
+        assert_eq!(&pnts[..4], b"pnts");
+        assert_eq!(LittleEndian::read_u32(&pnts[8..]) as usize, pnts.len());
+        let feature_table_json_length = LittleEndian::read_u32(&pnts[12..]) as usize;
+        assert_eq!((28 + feature_table_json_length) % 8, 0);
+        let feature_table_json =
+            std::str::from_utf8(&pnts[28..28 + feature_table_json_length]).unwrap();
+        let points_length = feature_table_json
+            .trim_start_matches(r#"{"POINTS_LENGTH":"#)
+            .split(',')
+            .next()
+            .unwrap();
+        num_points += points_length.parse::<usize>().unwrap();
+        assert!(tileset.contains(&format!(r#""uri":"{}.pnts""#, node_id)));
+    }
+    assert_eq!(num_points, 110_000);
+
+    let potree_dir = TempDir::new("potree").unwrap();
+    export_octree(octree_dir.path(), potree_dir.path(), ExportFormat::Potree).unwrap();
+    let hierarchy = std::fs::read(potree_dir.path().join("hierarchy.bin")).unwrap();
+    assert_eq!(hierarchy.len(), 22 * node_ids.len());
+    // The root is an inner node and comes first.
+    assert_eq!(hierarchy[0], 0);
+    assert_ne!(hierarchy[1], 0);
+    let (num_points, num_bytes) = hierarchy.chunks(22).fold((0, 0), |(points, bytes), node| {
+        (
+            points + LittleEndian::read_u32(&node[2..]) as usize,
+            bytes + LittleEndian::read_u64(&node[14..]) as usize,
+        )
+    });
+    assert_eq!(num_points, 110_000);
+    // Positions, colors and intensities.
+    assert_eq!(num_bytes, num_points * (12 + 6 + 4));
+    let octree_bin = std::fs::read(potree_dir.path().join("octree.bin")).unwrap();
+    assert_eq!(octree_bin.len(), num_bytes);
+    let metadata = std::fs::read_to_string(potree_dir.path().join("metadata.json")).unwrap();
+    assert!(metadata.contains(r#""points":110000"#));
+    assert!(metadata.contains(r#""name":"intensity""#));
+
+    let pcd_dir = TempDir::new("pcd").unwrap();
+    export_octree(octree_dir.path(), pcd_dir.path(), ExportFormat::Pcd).unwrap();
+    let pcd = PcdIterator::from_file(pcd_dir.path().join("points.pcd"), 50_000).unwrap();
+    assert_eq!(pcd.num_points(), 110_000);
+    let mut num_points = 0;
+    for batch in pcd {
+        let colors: &Vec<Vector3<u8>> = batch.get_attribute_vec("color").unwrap();
+        assert!(colors.iter().all(|color| *color == Vector3::new(255, 0, 0)));
+        let intensities: &Vec<f32> = batch.get_attribute_vec("intensity").unwrap();
+        assert!(intensities.iter().all(|intensity| *intensity == 1.));
+        num_points += batch.position.len();
+    }
+    assert_eq!(num_points, 110_000);
+}
+
+#[test]
+fn test_export_octree_without_points_is_an_error() {
+    let octree_dir = TempDir::new("octree").unwrap();
+    build_grid_octree_in(octree_dir.path(), 0..1100, 0., 1., false);
+    let mut octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: octree_dir.path().to_path_buf(),
+    }))
+    .unwrap();
+    let output_dir = TempDir::new("export").unwrap();
+    let formats = [
+        ExportFormat::Tiles3d,
+        ExportFormat::Potree,
+        ExportFormat::Pcd,
+    ];
+
+    // Without a root there is nothing to start the export from.
+    let root = NodeId::from_level_index(0, 0);
+    let root_meta = octree.nodes.remove(&root).unwrap();
+    octree.write_changed_meta(octree_dir.path()).unwrap();
+    for format in &formats {
+        assert!(export_octree(octree_dir.path(), output_dir.path(), *format).is_err());
+    }
+
+    octree.nodes.insert(root, root_meta);
+    octree.write_changed_meta(octree_dir.path()).unwrap();
+    octree.delete_in(&PointLocation::AllPoints).unwrap();
+    for format in &formats {
+        assert!(export_octree(octree_dir.path(), output_dir.path(), *format).is_err());
+    }
+}
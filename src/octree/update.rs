@@ -0,0 +1,343 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::data_provider::{DataProvider, OnDiskDataProvider};
+use crate::errors::*;
+use crate::geometry::{Aabb, Cube};
+use crate::octree::generation::{
+    should_split_node, split_node, subsample_up_to_level, write_meta, NodeSummary,
+};
+use crate::octree::{AttributeRanges, ChildIndex, Node, NodeId, Octree, OctreeMeta};
+use crate::read_write::{
+    attempt_increasing_rlimit_to_max, NodeIterator, NodeWriter, OpenMode, RawNodeWriter,
+};
+use crate::{
+    AttributeDataType, PointCloudMeta, PointsBatch, CURRENT_VERSION, NUM_POINTS_PER_BATCH,
+};
+use fnv::{FnvHashMap, FnvHashSet};
+use nalgebra::Point3;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::path::Path;
+
+fn is_inside(bounding_box: &Aabb, p: &Point3<f64>) -> bool {
+    // In contrast to 'Aabb::contains', points on the maximum are part of the octree.
+    nalgebra::partial_le(bounding_box.min(), p) && nalgebra::partial_le(p, bounding_box.max())
+}
+
+/// Returns the node a new point should be added to: the existing leaf containing it, or a new
+/// leaf below an existing inner node that does not yet have a child in that place.
+fn find_leaf(inner_nodes: &FnvHashSet<NodeId>, root_cube: &Cube, p: &Point3<f64>) -> NodeId {
+    let mut node = Node::root_with_bounding_cube(root_cube.clone());
+    while inner_nodes.contains(&node.id) {
+        node = node.get_child(ChildIndex::from_bounding_cube(&node.bounding_cube, p));
+    }
+    node.id
+}
+
+pub(super) fn read_all_points(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &OctreeMeta,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    node_id: &NodeId,
+) -> Result<PointsBatch> {
+    let num_points = octree_data_provider.number_of_points(&node_id.to_string())?;
+    let mut node_iterator = NodeIterator::from_data_provider(
+        octree_data_provider,
+        attribute_data_types,
+        octree_meta.encoding_for_node(*node_id),
+        node_id,
+        num_points as usize,
+        num_points as usize,
+    )?;
+    node_iterator
+        .next()
+        .ok_or_else(|| ErrorKind::NodeNotFound.into())
+}
+
+/// Moves every 8th of the last `num_new_points` points of each child into its parent, i.e. the
+/// same subsampling that 'build_octree' does, but only for the newly added points. The points
+/// that were subsampled into the parent before remain untouched, so the parent's previous
+/// `parent_ranges` are grown by the added points. Returns the number of points added to the
+/// parent.
+fn subsample_new_points_into(
+    octree_data_provider: &OnDiskDataProvider,
+    octree_meta: &OctreeMeta,
+    attribute_data_types: &HashMap<String, AttributeDataType>,
+    parent_id: &NodeId,
+    mut parent_ranges: AttributeRanges,
+    children: &[(NodeId, i64)],
+    nodes_sender: &crossbeam::channel::Sender<(NodeId, NodeSummary)>,
+) -> Result<i64> {
+    let mut parent_writer = RawNodeWriter::from_data_provider(
+        octree_data_provider,
+        octree_meta,
+        parent_id,
+        OpenMode::Append,
+    );
+    let mut num_moved = 0;
+    for (child_id, num_new_points) in children {
+        let batch = match read_all_points(
+            octree_data_provider,
+            octree_meta,
+            attribute_data_types,
+            child_id,
+        ) {
+            Ok(batch) => batch,
+            Err(Error(ErrorKind::NodeNotFound, _)) => continue,
+            Err(err) => return Err(err),
+        };
+        let num_points = batch.position.len();
+        let first_new = num_points - std::cmp::min(*num_new_points as usize, num_points);
+        let (keep_parent, keep_child): (Vec<bool>, Vec<bool>) = (0..num_points)
+            .map(|i| {
+                let in_parent = i >= first_new && (i - first_new) % 8 == 0;
+                (in_parent, !in_parent)
+            })
+            .unzip();
+        let mut parent_batch = batch.clone();
+        parent_batch.retain(&keep_parent);
+        let mut child_batch = batch;
+        child_batch.retain(&keep_child);
+
+        let mut child_writer = RawNodeWriter::from_data_provider(
+            octree_data_provider,
+            octree_meta,
+            child_id,
+            OpenMode::Truncate,
+        );
+        parent_writer.write(&parent_batch)?;
+        child_writer.write(&child_batch)?;
+        num_moved += parent_batch.position.len() as i64;
+        parent_ranges.add_batch(&parent_batch);
+
+        let child_summary = NodeSummary {
+            num_points: child_writer.num_written(),
+            attribute_ranges: AttributeRanges::from_batch(&child_batch),
+            ..Default::default()
+        };
+        nodes_sender.send((*child_id, child_summary)).unwrap();
+    }
+    let parent_summary = NodeSummary {
+        num_points: parent_writer.num_written(),
+        attribute_ranges: parent_ranges,
+        ..Default::default()
+    };
+    nodes_sender.send((*parent_id, parent_summary)).unwrap();
+    Ok(num_moved)
+}
+
+/// Adds the points in `input` to the octree in `directory`. The new points are appended to the
+/// leaves they fall into, only leaves that grow too large are split, and the new points are
+/// subsampled into the inner nodes above them. Points outside of the octree's bounding box are
+/// dropped. The meta file is replaced atomically once all node files are written.
+pub fn update(
+    directory: impl AsRef<Path>,
+    input: impl Iterator<Item = PointsBatch>,
+    attributes: &[&str],
+) -> Result<()> {
+    attempt_increasing_rlimit_to_max();
+
+    let octree_data_provider = &OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    };
+    let version = octree_data_provider.meta_proto()?.version;
+    if version != CURRENT_VERSION {
+        return Err(ErrorKind::InvalidVersion(version).into());
+    }
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: directory.as_ref().to_path_buf(),
+    }))?;
+    let octree_meta = &octree.meta;
+    let attribute_data_types = &octree_meta.attribute_data_types_for(attributes)?;
+    let root_cube = Cube::bounding(&octree_meta.bounding_box);
+
+    let mut nodes: FnvHashMap<NodeId, NodeSummary> = octree
+        .nodes
+        .iter()
+        .map(|(id, node_meta)| {
+            let summary = NodeSummary {
+                num_points: node_meta.num_points,
+                attribute_ranges: node_meta.attribute_ranges.clone(),
+                version: node_meta.version,
+            };
+            (*id, summary)
+        })
+        .collect();
+    let inner_nodes: FnvHashSet<NodeId> = nodes.keys().filter_map(|id| id.parent_id()).collect();
+
+    eprintln!("Adding points to leaf nodes.");
+    let mut writers: FnvHashMap<NodeId, RawNodeWriter> = FnvHashMap::default();
+    let mut num_new_points: FnvHashMap<NodeId, i64> = FnvHashMap::default();
+    let mut num_dropped = 0;
+    for mut batch in input {
+        if batch.attributes.len() != attributes.len()
+            || attributes
+                .iter()
+                .any(|name| !batch.attributes.contains_key(*name))
+        {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Points need to have exactly the attributes {:?}.",
+                attributes
+            ))
+            .into());
+        }
+        let inside: Vec<bool> = batch
+            .position
+            .iter()
+            .map(|p| is_inside(&octree_meta.bounding_box, p))
+            .collect();
+        num_dropped += inside.iter().filter(|inside| !**inside).count();
+        batch.retain(&inside);
+
+        let leaf_ids: Vec<NodeId> = batch
+            .position
+            .iter()
+            .map(|p| find_leaf(&inner_nodes, &root_cube, p))
+            .collect();
+        let unique_leaf_ids: FnvHashSet<NodeId> = leaf_ids.iter().cloned().collect();
+        for leaf_id in unique_leaf_ids {
+            let keep: Vec<bool> = leaf_ids.iter().map(|id| *id == leaf_id).collect();
+            let mut leaf_batch = batch.clone();
+            leaf_batch.retain(&keep);
+            writers
+                .entry(leaf_id)
+                .or_insert_with(|| {
+                    RawNodeWriter::from_data_provider(
+                        octree_data_provider,
+                        octree_meta,
+                        &leaf_id,
+                        OpenMode::Append,
+                    )
+                })
+                .write(&leaf_batch)?;
+            *num_new_points.entry(leaf_id).or_insert(0) += leaf_batch.position.len() as i64;
+            // The points are appended, so the ranges of new leaves start out empty.
+            nodes
+                .entry(leaf_id)
+                .or_default()
+                .attribute_ranges
+                .add_batch(&leaf_batch);
+        }
+    }
+    if num_dropped > 0 {
+        eprintln!(
+            "Dropped {} points outside of the octree's bounding box.",
+            num_dropped
+        );
+    }
+    let leaf_ids: Vec<NodeId> = writers.keys().cloned().collect();
+    // Every node whose files are written below gets a new version.
+    let mut rewritten: FnvHashSet<NodeId> = leaf_ids.iter().cloned().collect();
+    for (leaf_id, writer) in writers {
+        nodes.get_mut(&leaf_id).unwrap().num_points = writer.num_written();
+    }
+
+    // Propagate the new points up to the root, one level at a time.
+    let deepest_level = num_new_points.keys().map(|id| id.level()).max();
+    for current_level in (1..=deepest_level.unwrap_or(0)).rev() {
+        let mut children_by_parent: FnvHashMap<NodeId, Vec<(NodeId, i64)>> = FnvHashMap::default();
+        for (id, num) in num_new_points.iter() {
+            if id.level() == current_level && *num > 0 {
+                children_by_parent
+                    .entry(id.parent_id().unwrap())
+                    .or_default()
+                    .push((*id, *num));
+            }
+        }
+
+        let (nodes_sender, nodes_receiver) = crossbeam::channel::unbounded();
+        let moved: Vec<(NodeId, i64)> = children_by_parent
+            .into_par_iter()
+            .map(|(parent_id, children)| {
+                let parent_ranges = nodes
+                    .get(&parent_id)
+                    .map(|summary| summary.attribute_ranges.clone())
+                    .unwrap_or_default();
+                subsample_new_points_into(
+                    octree_data_provider,
+                    octree_meta,
+                    attribute_data_types,
+                    &parent_id,
+                    parent_ranges,
+                    &children,
+                    &nodes_sender,
+                )
+                .map(|num_moved| (parent_id, num_moved))
+            })
+            .collect::<Result<_>>()?;
+        drop(nodes_sender);
+        for (id, summary) in nodes_receiver {
+            rewritten.insert(id);
+            nodes.insert(id, summary);
+        }
+        for (parent_id, num_moved) in moved {
+            *num_new_points.entry(parent_id).or_insert(0) += num_moved;
+        }
+    }
+
+    // Split the leaves that became too large. Their subtree is rebuilt from scratch, which is why
+    // their new points were moved up into the ancestors before: afterwards, they can no longer be
+    // told apart from the old points.
+    let leaves_to_split: Vec<NodeId> = leaf_ids
+        .iter()
+        .filter(|id| should_split_node(id, nodes[id].num_points, octree_meta))
+        .cloned()
+        .collect();
+    for leaf_id in leaves_to_split {
+        let stream = NodeIterator::from_data_provider(
+            octree_data_provider,
+            attribute_data_types,
+            octree_meta.encoding_for_node(leaf_id),
+            &leaf_id,
+            nodes[&leaf_id].num_points as usize,
+            NUM_POINTS_PER_BATCH,
+        )?;
+        let (leaf_nodes_sender, leaf_nodes_receiver) = crossbeam::channel::unbounded();
+        rayon::scope(|scope| {
+            split_node(
+                scope,
+                octree_data_provider,
+                octree_meta,
+                attribute_data_types,
+                &leaf_id,
+                stream,
+                &leaf_nodes_sender,
+                None,
+            );
+        });
+        drop(leaf_nodes_sender);
+        let leaf_nodes = leaf_nodes_receiver
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+        // This includes the split leaf itself, which now holds the subsampled points.
+        let subtree_nodes = subsample_up_to_level(
+            octree_data_provider,
+            octree_meta,
+            attribute_data_types,
+            leaf_nodes,
+            leaf_id.level(),
+        )?;
+        rewritten.extend(subtree_nodes.keys().cloned());
+        nodes.extend(subtree_nodes);
+    }
+
+    for id in rewritten {
+        nodes.get_mut(&id).unwrap().version = octree
+            .nodes
+            .get(&id)
+            .map_or(0, |node_meta| node_meta.version + 1);
+    }
+    write_meta(directory, octree_meta, &nodes)
+}
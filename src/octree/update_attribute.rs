@@ -0,0 +1,223 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::attribute_extension;
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::iterator::{
+    label_ids_for_query, matching_points, AttributeUpdate, PointCloud, PointQuery,
+    TIMESTAMP_ATTRIBUTE,
+};
+use crate::labels::{run_length_encode, CLASSIFICATION_ATTRIBUTE};
+use crate::math::PointCulling;
+use crate::octree::{NodeId, Octree};
+use crate::read_write::{DataWriter, NodeIterator, OpenMode, WriteLE};
+use crate::{AttributeData, AttributeDataType, PointCloudMeta};
+use std::fs;
+use std::io::Write;
+
+/// What 'Octree::update_attribute_in_node' needs to know about the query.
+struct NodeUpdate<'a, 'b> {
+    query: &'a PointQuery<'b>,
+    attribute: &'a str,
+    data_type: AttributeDataType,
+    /// The attributes to read, i.e. those of the query, of its filters and the updated one.
+    attributes: Vec<&'a str>,
+    culling: Box<dyn PointCulling>,
+    label_ids: Option<Vec<u16>>,
+}
+
+impl Octree {
+    /// Rewrites the file of the attribute in a single node with the new values of the matching
+    /// points. Returns the number of updated points.
+    fn update_attribute_in_node(
+        &mut self,
+        octree_data_provider: &OnDiskDataProvider,
+        node_update: &NodeUpdate,
+        update: &mut AttributeUpdate,
+        node_id: &NodeId,
+    ) -> Result<usize> {
+        let num_points = self.nodes[node_id].num_points as usize;
+        if num_points == 0 {
+            return Ok(0);
+        }
+        let mut batch = NodeIterator::from_data_provider(
+            octree_data_provider,
+            &self
+                .meta
+                .attribute_data_types_for(&node_update.attributes)?,
+            self.meta.encoding_for_node(*node_id),
+            node_id,
+            num_points,
+            num_points,
+        )?
+        .next()
+        .ok_or_else(|| Error::from(ErrorKind::NodeNotFound))?;
+        let keep = matching_points(
+            &batch,
+            &*node_update.culling,
+            &node_update.query.clip_planes,
+            &node_update.query.filter_intervals,
+            node_update.query.time_interval(),
+            node_update.label_ids.as_deref(),
+        );
+        let num_matching = keep.iter().filter(|k| **k).count();
+        if num_matching == 0 {
+            return Ok(0);
+        }
+
+        let values = match update {
+            AttributeUpdate::Constant(value) => {
+                macro_rules! rhs {
+                    ($dtype:ident, $data:ident, $num:expr) => {
+                        AttributeData::$dtype(vec![$data[0]; $num])
+                    };
+                }
+                match_attr_data!(value, rhs, num_matching)
+            }
+            AttributeUpdate::PerPoint(update) => {
+                let mut matching = batch.clone();
+                matching.retain(&keep);
+                matching
+                    .attributes
+                    .retain(|name, _| node_update.query.attributes.contains(&name.as_str()));
+                node_update.query.transform_output(&mut matching);
+                update(&matching)?
+            }
+        };
+        if values.len() != num_matching || values.data_type() != node_update.data_type {
+            return Err(ErrorKind::InvalidInput(format!(
+                "Expected {} values of type {:?} for node {}, got {} of type {:?}.",
+                num_matching,
+                node_update.data_type,
+                node_id,
+                values.len(),
+                values.data_type()
+            ))
+            .into());
+        }
+        let column = batch.attributes.get_mut(node_update.attribute).unwrap();
+        column
+            .replace_masked(&keep, &values)
+            .map_err(ErrorKind::InvalidInput)?;
+
+        // The new values are written next to the file and moved into place, so that it is never
+        // left half written.
+        let extension = attribute_extension(node_update.attribute);
+        let path = octree_data_provider
+            .stem(&node_id.to_string())
+            .with_extension(extension);
+        let tmp_path = path.with_extension(format!("{}.tmp", extension));
+        {
+            let mut writer = DataWriter::new(&tmp_path, OpenMode::Truncate)?;
+            if node_update.attribute == CLASSIFICATION_ATTRIBUTE {
+                run_length_encode(column, &mut writer)?;
+            } else {
+                column.write_le(&mut writer)?;
+            }
+            writer.flush()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+
+        let node_meta = self.nodes.get_mut(node_id).unwrap();
+        node_meta
+            .attribute_ranges
+            .set(node_update.attribute, column);
+        node_meta.version += 1;
+        Ok(num_matching)
+    }
+
+    pub(super) fn update_attribute_impl(
+        &mut self,
+        query: &PointQuery,
+        attribute: &str,
+        mut update: AttributeUpdate,
+    ) -> Result<usize> {
+        let directory = self
+            .data_provider
+            .directory()
+            .ok_or_else(|| {
+                ErrorKind::InvalidInput(
+                    "Attributes can only be updated in octrees on disk.".to_string(),
+                )
+            })?
+            .to_path_buf();
+        let octree_data_provider = &OnDiskDataProvider {
+            directory: directory.clone(),
+        };
+
+        let data_type = *self
+            .meta
+            .attribute_data_types()
+            .get(attribute)
+            .ok_or_else(|| {
+                ErrorKind::InvalidInput(format!("The octree has no attribute '{}'.", attribute))
+            })?;
+        if let AttributeUpdate::Constant(value) = &update {
+            if value.len() != 1 || value.data_type() != data_type {
+                return Err(ErrorKind::InvalidInput(format!(
+                    "The value of '{}' needs to be a single {:?}.",
+                    attribute, data_type
+                ))
+                .into());
+            }
+        }
+        if query.downsample.is_some() {
+            return Err(ErrorKind::InvalidInput(
+                "The attributes of downsampled points cannot be updated.".to_string(),
+            )
+            .into());
+        }
+
+        let label_ids = label_ids_for_query(query, self.label_dictionary())?;
+        let mut attributes = query.attributes.clone();
+        attributes.extend(query.filter_intervals.keys());
+        if query.time_range.is_some() {
+            attributes.push(TIMESTAMP_ATTRIBUTE);
+        }
+        if label_ids.is_some() {
+            attributes.push(CLASSIFICATION_ATTRIBUTE);
+        }
+        attributes.push(attribute);
+        attributes.sort_unstable();
+        attributes.dedup();
+        let node_update = NodeUpdate {
+            query,
+            attribute,
+            data_type,
+            attributes,
+            culling: query.location.get_point_culling(),
+            label_ids,
+        };
+
+        let result: Result<usize> =
+            self.nodes_for_query(query)
+                .iter()
+                .try_fold(0, |num_updated, node_id| {
+                    Ok(num_updated
+                        + self.update_attribute_in_node(
+                            octree_data_provider,
+                            &node_update,
+                            &mut update,
+                            node_id,
+                        )?)
+                });
+        // The nodes are rewritten one by one, so if one of them fails, the ranges in the meta still
+        // need to match the nodes that were rewritten before.
+        let meta_result = self.write_changed_meta(&directory);
+        let num_updated = result?;
+        meta_result?;
+        Ok(num_updated)
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::attribute_extension;
+use crate::data_provider::OnDiskDataProvider;
+use crate::errors::*;
+use crate::geometry::Cube;
+use crate::labels::CLASSIFICATION_ATTRIBUTE;
+use crate::octree::{AttributeRanges, NodeId, Octree};
+use crate::read_write::NodeIterator;
+use crate::PointCloudMeta;
+use fnv::FnvHashMap;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many points of each node are checked to be inside its bounding cube.
+const NUM_SAMPLED_POINTS: usize = 1024;
+
+/// An inconsistency between the meta of an octree and its node files, found by
+/// 'validate_octree'.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OctreeProblem {
+    /// The meta has points for a node without files on disk.
+    MissingNode(NodeId),
+    /// There are files of a node that the meta does not know about.
+    OrphanedNode(NodeId),
+    /// The meta records a different number of points than the position file of the node holds.
+    WrongNumPoints {
+        node_id: NodeId,
+        in_meta: i64,
+        on_disk: i64,
+    },
+    /// The file of an attribute does not have the size that the number of points requires.
+    AttributeLengthMismatch {
+        node_id: NodeId,
+        attribute: String,
+        expected_bytes: u64,
+        actual_bytes: u64,
+    },
+    /// Some of the sampled points of a node are outside its bounding cube.
+    PointsOutsideNode {
+        node_id: NodeId,
+        num_points_outside: usize,
+    },
+    /// The parent of a node is not in the meta, so it can not be reached from the root.
+    MissingParent(NodeId),
+}
+
+impl OctreeProblem {
+    pub fn node_id(&self) -> NodeId {
+        match self {
+            OctreeProblem::MissingNode(node_id)
+            | OctreeProblem::OrphanedNode(node_id)
+            | OctreeProblem::MissingParent(node_id)
+            | OctreeProblem::WrongNumPoints { node_id, .. }
+            | OctreeProblem::AttributeLengthMismatch { node_id, .. }
+            | OctreeProblem::PointsOutsideNode { node_id, .. } => *node_id,
+        }
+    }
+
+    /// Whether 'repair_octree' can fix this problem. Points outside of their node can not be
+    /// moved without rebuilding the octree.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, OctreeProblem::PointsOutsideNode { .. })
+    }
+}
+
+impl fmt::Display for OctreeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OctreeProblem::MissingNode(node_id) => {
+                write!(f, "Node {} is in the meta, but not on disk.", node_id)
+            }
+            OctreeProblem::OrphanedNode(node_id) => {
+                write!(f, "Node {} is on disk, but not in the meta.", node_id)
+            }
+            OctreeProblem::WrongNumPoints {
+                node_id,
+                in_meta,
+                on_disk,
+            } => write!(
+                f,
+                "Node {} has {} points on disk, but {} in the meta.",
+                node_id, on_disk, in_meta
+            ),
+            OctreeProblem::AttributeLengthMismatch {
+                node_id,
+                attribute,
+                expected_bytes,
+                actual_bytes,
+            } => write!(
+                f,
+                "Attribute '{}' of node {} has {} bytes instead of {}.",
+                attribute, node_id, actual_bytes, expected_bytes
+            ),
+            OctreeProblem::PointsOutsideNode {
+                node_id,
+                num_points_outside,
+            } => write!(
+                f,
+                "{} sampled points of node {} are outside its bounding cube.",
+                num_points_outside, node_id
+            ),
+            OctreeProblem::MissingParent(node_id) => {
+                write!(f, "The parent of node {} is not in the meta.", node_id)
+            }
+        }
+    }
+}
+
+/// Node files are named after the node, e.g. "r0172.xyz".
+fn parse_node_file_name(path: &Path) -> Option<NodeId> {
+    let stem = path.file_stem()?.to_str()?;
+    path.extension()?;
+    if !stem.starts_with('r') || !stem[1..].chars().all(|c| ('0'..='7').contains(&c)) {
+        return None;
+    }
+    stem.parse().ok()
+}
+
+/// All files of nodes in `directory`, by node.
+fn node_files_on_disk(directory: &Path) -> Result<FnvHashMap<NodeId, Vec<PathBuf>>> {
+    let mut node_files: FnvHashMap<NodeId, Vec<PathBuf>> = FnvHashMap::default();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        if let Some(node_id) = parse_node_file_name(&path) {
+            node_files.entry(node_id).or_default().push(path);
+        }
+    }
+    Ok(node_files)
+}
+
+fn sorted(mut node_ids: Vec<NodeId>) -> Vec<NodeId> {
+    node_ids.sort_by_key(|id| (id.level(), id.index()));
+    node_ids
+}
+
+impl Octree {
+    fn bytes_per_position(&self, node_id: &NodeId) -> u64 {
+        3 * self.nodes[node_id].position_encoding.bytes_per_coordinate() as u64
+    }
+
+    /// Checks the files of a node that is both in the meta and on disk.
+    fn validate_node(
+        &self,
+        octree_data_provider: &OnDiskDataProvider,
+        node_id: &NodeId,
+    ) -> Result<Vec<OctreeProblem>> {
+        let mut problems = Vec::new();
+        let in_meta = self.nodes[node_id].num_points;
+        // The positions are the reference for the number of points, so that a broken attribute
+        // file does not make the positions look broken.
+        let bytes_per_position = self.bytes_per_position(node_id);
+        let stem = octree_data_provider.stem(&node_id.to_string());
+        let position_bytes =
+            match fs::metadata(stem.with_extension(attribute_extension("position"))) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => {
+                    problems.push(OctreeProblem::MissingNode(*node_id));
+                    return Ok(problems);
+                }
+            };
+        let on_disk = (position_bytes / bytes_per_position) as i64;
+        if position_bytes % bytes_per_position != 0 {
+            // A repair removes the node, so its other files do not matter.
+            problems.push(OctreeProblem::AttributeLengthMismatch {
+                node_id: *node_id,
+                attribute: "position".to_string(),
+                expected_bytes: on_disk as u64 * bytes_per_position,
+                actual_bytes: position_bytes,
+            });
+            return Ok(problems);
+        }
+        if in_meta != on_disk {
+            problems.push(OctreeProblem::WrongNumPoints {
+                node_id: *node_id,
+                in_meta,
+                on_disk,
+            });
+        }
+
+        let encoding = self.meta.encoding_for_node(*node_id);
+        for (attribute, data_type) in self.meta.attribute_data_types() {
+            // Classifications are run length encoded.
+            if attribute == CLASSIFICATION_ATTRIBUTE {
+                continue;
+            }
+            let path = stem.with_extension(attribute_extension(attribute));
+            let actual_bytes = match fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                // Not every node has every attribute.
+                Err(_) => continue,
+            };
+            let expected_bytes = on_disk as u64 * data_type.size_of() as u64;
+            if actual_bytes != expected_bytes {
+                problems.push(OctreeProblem::AttributeLengthMismatch {
+                    node_id: *node_id,
+                    attribute: attribute.to_string(),
+                    expected_bytes,
+                    actual_bytes,
+                });
+            }
+        }
+
+        let sample = NodeIterator::from_data_provider(
+            octree_data_provider,
+            &HashMap::new(),
+            encoding,
+            node_id,
+            on_disk as usize,
+            NUM_SAMPLED_POINTS,
+        )?
+        .next();
+        // Decoded positions may be off by the resolution.
+        let bounding_cube = &self.nodes[node_id].bounding_cube;
+        let tolerance = self.meta.resolution;
+        let bounds = Cube::new(
+            bounding_cube.min() - nalgebra::Vector3::repeat(tolerance),
+            bounding_cube.edge_length() + 2. * tolerance,
+        )
+        .to_aabb();
+        let num_points_outside = sample.map_or(0, |batch| {
+            batch
+                .position
+                .iter()
+                .filter(|p| {
+                    !nalgebra::partial_le(bounds.min(), *p)
+                        || !nalgebra::partial_le(*p, bounds.max())
+                })
+                .count()
+        });
+        if num_points_outside > 0 {
+            problems.push(OctreeProblem::PointsOutsideNode {
+                node_id: *node_id,
+                num_points_outside,
+            });
+        }
+        Ok(problems)
+    }
+
+    fn validate_impl(&self, directory: &Path) -> Result<Vec<OctreeProblem>> {
+        let octree_data_provider = OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        };
+        let node_files = node_files_on_disk(directory)?;
+        let mut problems = Vec::new();
+        for node_id in sorted(node_files.keys().copied().collect()) {
+            if !self.nodes.contains_key(&node_id) {
+                problems.push(OctreeProblem::OrphanedNode(node_id));
+            }
+        }
+
+        let mut to_check = Vec::new();
+        for node_id in sorted(self.nodes.keys().copied().collect()) {
+            if let Some(parent_id) = node_id.parent_id() {
+                if !self.nodes.contains_key(&parent_id) {
+                    problems.push(OctreeProblem::MissingParent(node_id));
+                }
+            }
+            if node_files.contains_key(&node_id) {
+                to_check.push(node_id);
+            } else if self.nodes[&node_id].num_points > 0 {
+                // Nodes that lost all their points, e.g. by deleting them, have no files.
+                problems.push(OctreeProblem::MissingNode(node_id));
+            }
+        }
+        let node_problems = to_check
+            .par_iter()
+            .map(|node_id| self.validate_node(&octree_data_provider, node_id))
+            .collect::<Result<Vec<_>>>()?;
+        problems.extend(node_problems.into_iter().flatten());
+        Ok(problems)
+    }
+
+    /// Fixes the repairable `problems`. The files of nodes with missing or corrupt positions and
+    /// of orphaned nodes are removed, broken attributes of a node are dropped, the number of points and
+    /// attribute ranges are recomputed from disk, and missing parents are added without points.
+    fn repair_impl(&mut self, directory: &Path, problems: &[OctreeProblem]) -> Result<()> {
+        let octree_data_provider = OnDiskDataProvider {
+            directory: directory.to_path_buf(),
+        };
+        let mut node_files = node_files_on_disk(directory)?;
+        let mut removed = Vec::new();
+        let mut to_recompute = Vec::new();
+        let mut without_parent = Vec::new();
+        for problem in problems {
+            match problem {
+                OctreeProblem::MissingNode(node_id) | OctreeProblem::OrphanedNode(node_id) => {
+                    removed.push(*node_id)
+                }
+                OctreeProblem::AttributeLengthMismatch {
+                    node_id, attribute, ..
+                } if attribute == "position" => removed.push(*node_id),
+                OctreeProblem::AttributeLengthMismatch {
+                    node_id, attribute, ..
+                } => {
+                    let path = octree_data_provider
+                        .stem(&node_id.to_string())
+                        .with_extension(attribute_extension(attribute));
+                    fs::remove_file(&path)?;
+                    to_recompute.push(*node_id);
+                }
+                OctreeProblem::WrongNumPoints { node_id, .. } => to_recompute.push(*node_id),
+                OctreeProblem::MissingParent(node_id) => without_parent.push(*node_id),
+                OctreeProblem::PointsOutsideNode { .. } => (),
+            }
+        }
+
+        for node_id in &removed {
+            for path in node_files.remove(node_id).unwrap_or_default() {
+                fs::remove_file(&path)?;
+            }
+            // Like after deleting points, the root and inner nodes stay without points, so that
+            // their children can still be reached.
+            if node_id.level() > 0 && self.children(node_id).is_empty() {
+                self.nodes.remove(node_id);
+            } else if let Some(node_meta) = self.nodes.get_mut(node_id) {
+                node_meta.num_points = 0;
+                node_meta.attribute_ranges = AttributeRanges::default();
+                node_meta.version += 1;
+            }
+        }
+        for node_id in &to_recompute {
+            if removed.contains(node_id) {
+                continue;
+            }
+            let attribute_data_types = self.attributes_on_disk(&octree_data_provider, node_id);
+            let position_bytes = fs::metadata(
+                octree_data_provider
+                    .stem(&node_id.to_string())
+                    .with_extension(attribute_extension("position")),
+            )?
+            .len();
+            let num_points = (position_bytes / self.bytes_per_position(node_id)) as usize;
+            let batch = NodeIterator::from_data_provider(
+                &octree_data_provider,
+                &attribute_data_types,
+                self.meta.encoding_for_node(*node_id),
+                node_id,
+                num_points,
+                num_points,
+            )?
+            .next();
+            let node_meta = self.nodes.get_mut(node_id).unwrap();
+            node_meta.num_points = num_points as i64;
+            node_meta.attribute_ranges = batch
+                .as_ref()
+                .map_or_else(AttributeRanges::default, AttributeRanges::from_batch);
+            node_meta.version += 1;
+        }
+        let root_cube = Cube::bounding(&self.meta.bounding_box);
+        for node_id in &without_parent {
+            let mut current = node_id.parent_id();
+            while let Some(id) = current {
+                if self.nodes.contains_key(&id) {
+                    break;
+                }
+                let mut node_meta = self.nodes[node_id].clone();
+                node_meta.num_points = 0;
+                node_meta.bounding_cube = id.find_bounding_cube(&root_cube);
+                node_meta.attribute_ranges = AttributeRanges::default();
+                self.nodes.insert(id, node_meta);
+                current = id.parent_id();
+            }
+        }
+        self.write_changed_meta(directory)
+    }
+}
+
+/// Checks the meta of the octree in `directory` against the node files on disk. Only the first
+/// points of each node are checked to be inside its bounding cube. Returns the problems found,
+/// which is empty for a valid octree.
+pub fn validate_octree(directory: impl AsRef<Path>) -> Result<Vec<OctreeProblem>> {
+    let directory = directory.as_ref();
+    let octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    }))?;
+    octree.validate_impl(directory)
+}
+
+/// Fixes the `problems` that 'validate_octree' found in the octree in `directory` and rewrites
+/// its meta, see 'OctreeProblem::is_repairable'. Returns the problems that are left.
+pub fn repair_octree(
+    directory: impl AsRef<Path>,
+    problems: &[OctreeProblem],
+) -> Result<Vec<OctreeProblem>> {
+    let directory = directory.as_ref();
+    let mut octree = Octree::from_data_provider(Box::new(OnDiskDataProvider {
+        directory: directory.to_path_buf(),
+    }))?;
+    octree.repair_impl(directory, problems)?;
+    octree.validate_impl(directory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_node_file_name() {
+        let parse = |name: &str| parse_node_file_name(Path::new(name));
+        assert_eq!(parse("r.xyz"), Some(NodeId::from_level_index(0, 0)));
+        assert_eq!(parse("r17.rgb"), Some("r17".parse().unwrap()));
+        assert_eq!(parse("r17"), None);
+        assert_eq!(parse("r18.xyz"), None);
+        assert_eq!(parse("meta.pb"), None);
+        assert_eq!(parse("build_checkpoint.pb"), None);
+    }
+}
@@ -0,0 +1,943 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading of E57 files (ASTM E2807), the exchange format of terrestrial laser scanners. A file
+//! holds several scans, each in its own frame. The points of all scans are returned in the frame
+//! of the file, i.e. with the pose of their scan applied.
+
+use crate::errors::*;
+use crate::{AttributeData, NumberOfPoints, PointsBatch};
+use byteorder::{ByteOrder, LittleEndian};
+use nalgebra::{Isometry3, Point3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use num_integer::div_ceil;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SIGNATURE: &[u8] = b"ASTM-E57";
+const HEADER_SIZE: usize = 48;
+/// Every page ends in a CRC-32C checksum of its payload, which we do not verify.
+const CHECKSUM_SIZE: u64 = 4;
+const COMPRESSED_VECTOR_SECTION_ID: u8 = 1;
+const DATA_PACKET: u8 = 1;
+
+fn invalid(message: impl Into<String>) -> Error {
+    ErrorKind::InvalidInput(format!("Invalid E57 file: {}", message.into())).into()
+}
+
+/// Reads the logical contents of the file, i.e. without the checksums at the end of every page.
+struct PagedReader {
+    file: File,
+    page_size: u64,
+}
+
+impl PagedReader {
+    fn payload_size(&self) -> u64 {
+        self.page_size - CHECKSUM_SIZE
+    }
+
+    fn read_at(&mut self, physical_offset: u64, buf: &mut [u8]) -> Result<()> {
+        let mut offset = physical_offset;
+        let mut filled = 0;
+        while filled < buf.len() {
+            let in_page = offset % self.page_size;
+            if in_page >= self.payload_size() {
+                offset += self.page_size - in_page;
+                continue;
+            }
+            let len = std::cmp::min(self.payload_size() - in_page, (buf.len() - filled) as u64);
+            let end = filled + len as usize;
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut buf[filled..end])?;
+            filled = end;
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// The physical offset `logical_length` bytes of content after `physical_offset`.
+    fn advance(&self, physical_offset: u64, logical_length: u64) -> u64 {
+        let logical = physical_offset / self.page_size * self.payload_size()
+            + physical_offset % self.page_size
+            + logical_length;
+        logical / self.payload_size() * self.page_size + logical % self.payload_size()
+    }
+}
+
+/// An element of the XML section, which describes the scans.
+#[derive(Debug, Default)]
+struct XmlElement {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<XmlElement>,
+    text: String,
+}
+
+impl XmlElement {
+    fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    /// The value of a numeric attribute like 'minimum' or of the element's text. Missing values
+    /// are 0, like E57 defines.
+    fn number(&self, attribute: Option<&str>) -> Result<f64> {
+        let value = match attribute {
+            Some(attribute) => self.attribute(attribute).unwrap_or(""),
+            None => self.text.trim(),
+        };
+        if value.is_empty() {
+            return Ok(0.);
+        }
+        value
+            .parse()
+            .map_err(|_| invalid(format!("'{}' in '{}' is not a number", value, self.name)))
+    }
+
+    fn child_number(&self, name: &str) -> Result<Option<f64>> {
+        self.child(name).map(|child| child.number(None)).transpose()
+    }
+}
+
+/// A parser for the subset of XML that E57 files use: elements, attributes, text and CDATA.
+struct XmlParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+impl<'a> XmlParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<()> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => Err(invalid(format!("unterminated XML, expected '{}'", end))),
+        }
+    }
+
+    fn take_until(&mut self, end: &[char]) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(end).unwrap_or_else(|| rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn expect(&mut self, s: &str) -> Result<()> {
+        if !self.rest().starts_with(s) {
+            return Err(invalid(format!("expected '{}' in XML", s)));
+        }
+        self.pos += s.len();
+        Ok(())
+    }
+
+    fn parse_document(&mut self) -> Result<XmlElement> {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return self.parse_element();
+            }
+        }
+    }
+
+    fn parse_element(&mut self) -> Result<XmlElement> {
+        self.expect("<")?;
+        let mut element = XmlElement {
+            name: self
+                .take_until(&[' ', '\t', '\r', '\n', '/', '>'])
+                .to_string(),
+            ..Default::default()
+        };
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(element);
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let name = self.take_until(&['=', ' ', '\t', '\r', '\n']).to_string();
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = if self.rest().starts_with('\'') {
+                "'"
+            } else {
+                "\""
+            };
+            self.expect(quote)?;
+            let value = unescape(self.take_until(&[quote.chars().next().unwrap()]));
+            self.expect(quote)?;
+            element.attributes.insert(name, value);
+        }
+        loop {
+            if self.rest().starts_with("</") {
+                self.pos += 2;
+                let name = self.take_until(&['>']);
+                if name.trim() != element.name {
+                    return Err(invalid(format!(
+                        "'{}' is closed by '{}' in XML",
+                        element.name, name
+                    )));
+                }
+                self.expect(">")?;
+                return Ok(element);
+            } else if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<![CDATA[") {
+                self.pos += "<![CDATA[".len();
+                let start = self.pos;
+                self.skip_past("]]>")?;
+                element
+                    .text
+                    .push_str(&self.input[start..self.pos - "]]>".len()]);
+            } else if self.rest().starts_with('<') {
+                let child = self.parse_element()?;
+                element.children.push(child);
+            } else if self.rest().is_empty() {
+                return Err(invalid(format!("'{}' is not closed in XML", element.name)));
+            } else {
+                let text = unescape(self.take_until(&['<']));
+                element.text.push_str(&text);
+            }
+        }
+    }
+}
+
+/// How the values of a field of the points are packed into its bytestream.
+#[derive(Debug, Clone, Copy)]
+enum FieldCodec {
+    Float32,
+    Float64,
+    /// Integers from 'minimum' to 'maximum', stored relative to 'minimum' with the fewest bits
+    /// that can hold the range, which are then scaled and offset. Plain integers have a scale of
+    /// 1 and an offset of 0.
+    Integer {
+        minimum: i64,
+        maximum: i64,
+        bits: u32,
+        scale: f64,
+        offset: f64,
+    },
+}
+
+impl FieldCodec {
+    fn from_xml(element: &XmlElement) -> Result<Self> {
+        let integer = |scale, offset| -> Result<FieldCodec> {
+            let minimum = element.number(Some("minimum"))? as i64;
+            let maximum = element.number(Some("maximum"))? as i64;
+            if maximum < minimum {
+                return Err(invalid(format!("'{}' has an empty range", element.name)));
+            }
+            let range = maximum.wrapping_sub(minimum) as u64;
+            Ok(FieldCodec::Integer {
+                minimum,
+                maximum,
+                bits: 64 - range.leading_zeros(),
+                scale,
+                offset,
+            })
+        };
+        match element.attribute("type") {
+            Some("Float") => match element.attribute("precision") {
+                Some("single") => Ok(FieldCodec::Float32),
+                _ => Ok(FieldCodec::Float64),
+            },
+            Some("Integer") => integer(1., 0.),
+            Some("ScaledInteger") => {
+                let scale = match element.attribute("scale") {
+                    Some(_) => element.number(Some("scale"))?,
+                    None => 1.,
+                };
+                integer(scale, element.number(Some("offset"))?)
+            }
+            other => Err(invalid(format!(
+                "unsupported type {:?} of point field '{}'",
+                other, element.name
+            ))),
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            FieldCodec::Float32 => 32,
+            FieldCodec::Float64 => 64,
+            FieldCodec::Integer { bits, .. } => bits,
+        }
+    }
+
+    fn decode(self, raw: u64) -> f64 {
+        match self {
+            FieldCodec::Float32 => f64::from(f32::from_bits(raw as u32)),
+            FieldCodec::Float64 => f64::from_bits(raw),
+            FieldCodec::Integer {
+                minimum,
+                scale,
+                offset,
+                ..
+            } => minimum.wrapping_add(raw as i64) as f64 * scale + offset,
+        }
+    }
+}
+
+/// Unpacks the values of one field from the bytestream, which continues from packet to packet.
+struct FieldDecoder {
+    codec: FieldCodec,
+    bytes: Vec<u8>,
+    bit_offset: usize,
+    values: VecDeque<f64>,
+}
+
+impl FieldDecoder {
+    fn new(codec: FieldCodec) -> Self {
+        FieldDecoder {
+            codec,
+            bytes: Vec::new(),
+            bit_offset: 0,
+            values: VecDeque::new(),
+        }
+    }
+
+    /// Fields of zero bits have the same value for every point and take up no space.
+    fn is_constant(&self) -> bool {
+        self.codec.bits() == 0
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        self.bytes.drain(..self.bit_offset / 8);
+        self.bit_offset %= 8;
+        self.bytes.extend_from_slice(data);
+        let bits = self.codec.bits() as usize;
+        if bits == 0 {
+            return;
+        }
+        while self.bytes.len() * 8 - self.bit_offset >= bits {
+            let mut raw = 0u64;
+            let mut num_read = 0;
+            while num_read < bits {
+                let shift = self.bit_offset % 8;
+                let len = std::cmp::min(8 - shift, bits - num_read);
+                let byte = u64::from(self.bytes[self.bit_offset / 8] >> shift);
+                raw |= (byte & ((1 << len) - 1)) << num_read;
+                num_read += len;
+                self.bit_offset += len;
+            }
+            self.values.push_back(self.codec.decode(raw));
+        }
+    }
+
+    fn pop(&mut self) -> f64 {
+        if self.is_constant() {
+            self.codec.decode(0)
+        } else {
+            self.values.pop_front().unwrap()
+        }
+    }
+}
+
+/// The fields of a scan's points that we use, as indices into its prototype.
+#[derive(Debug, Default)]
+struct FieldIndices {
+    cartesian: Option<[usize; 3]>,
+    spherical: Option<[usize; 3]>,
+    invalid_state: Option<usize>,
+    color: Option<[usize; 3]>,
+    intensity: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Scan {
+    pose: Isometry3<f64>,
+    record_count: u64,
+    section_offset: u64,
+    codecs: Vec<FieldCodec>,
+    fields: FieldIndices,
+    /// The values of red, green and blue that map to 0 and 255.
+    color_limits: [(f64, f64); 3],
+}
+
+fn parse_pose(scan: &XmlElement) -> Result<Isometry3<f64>> {
+    let pose = match scan.child("pose") {
+        Some(pose) => pose,
+        None => return Ok(Isometry3::identity()),
+    };
+    let rotation = match pose.child("rotation") {
+        Some(r) => UnitQuaternion::from_quaternion(Quaternion::new(
+            r.child_number("w")?.unwrap_or(1.),
+            r.child_number("x")?.unwrap_or(0.),
+            r.child_number("y")?.unwrap_or(0.),
+            r.child_number("z")?.unwrap_or(0.),
+        )),
+        None => UnitQuaternion::identity(),
+    };
+    let translation = match pose.child("translation") {
+        Some(t) => Translation3::new(
+            t.child_number("x")?.unwrap_or(0.),
+            t.child_number("y")?.unwrap_or(0.),
+            t.child_number("z")?.unwrap_or(0.),
+        ),
+        None => Translation3::identity(),
+    };
+    Ok(Isometry3::from_parts(translation, rotation))
+}
+
+fn parse_scan(scan: &XmlElement) -> Result<Scan> {
+    let points = scan
+        .child("points")
+        .ok_or_else(|| invalid("a scan has no points"))?;
+    if points.attribute("type") != Some("CompressedVector") {
+        return Err(invalid("the points of a scan are not a compressed vector"));
+    }
+    let prototype = points
+        .child("prototype")
+        .ok_or_else(|| invalid("the points of a scan have no prototype"))?;
+    let codecs = prototype
+        .children
+        .iter()
+        .map(FieldCodec::from_xml)
+        .collect::<Result<Vec<_>>>()?;
+    let index = |name: &str| prototype.children.iter().position(|c| c.name == name);
+    let triple = |names: [&str; 3]| -> Option<[usize; 3]> {
+        Some([index(names[0])?, index(names[1])?, index(names[2])?])
+    };
+    let cartesian = triple(["cartesianX", "cartesianY", "cartesianZ"]);
+    let fields = FieldIndices {
+        cartesian,
+        spherical: triple(["sphericalRange", "sphericalAzimuth", "sphericalElevation"]),
+        invalid_state: if cartesian.is_some() {
+            index("cartesianInvalidState")
+        } else {
+            index("sphericalInvalidState")
+        },
+        color: triple(["colorRed", "colorGreen", "colorBlue"]),
+        intensity: index("intensity"),
+    };
+    if fields.cartesian.is_none() && fields.spherical.is_none() {
+        return Err(invalid(
+            "a scan has neither cartesian nor spherical coordinates",
+        ));
+    }
+
+    let mut color_limits = [(0., 255.); 3];
+    for (i, channel) in ["Red", "Green", "Blue"].iter().enumerate() {
+        let limits = scan.child("colorLimits");
+        let limit = |bound: &str| -> Result<Option<f64>> {
+            match limits {
+                Some(limits) => limits.child_number(&format!("color{}{}", channel, bound)),
+                None => Ok(None),
+            }
+        };
+        let field_limits = fields.color.and_then(|color| match codecs[color[i]] {
+            FieldCodec::Integer {
+                minimum,
+                maximum,
+                scale,
+                offset,
+                ..
+            } if maximum > minimum => Some((
+                minimum as f64 * scale + offset,
+                maximum as f64 * scale + offset,
+            )),
+            _ => None,
+        });
+        let (default_min, default_max) = field_limits.unwrap_or((0., 255.));
+        color_limits[i] = (
+            limit("Minimum")?.unwrap_or(default_min),
+            limit("Maximum")?.unwrap_or(default_max),
+        );
+    }
+
+    Ok(Scan {
+        pose: parse_pose(scan)?,
+        record_count: points.number(Some("recordCount"))? as u64,
+        section_offset: points.number(Some("fileOffset"))? as u64,
+        codecs,
+        fields,
+        color_limits,
+    })
+}
+
+/// The progress of reading the points of a scan.
+struct ScanReader {
+    decoders: Vec<FieldDecoder>,
+    next_packet: u64,
+    records_left: u64,
+}
+
+/// Reads the points of all scans in an E57 file, with 'color' and 'intensity' attributes if all
+/// scans have them. Points without a valid position are skipped.
+pub struct E57Iterator {
+    reader: PagedReader,
+    scans: Vec<Scan>,
+    current_scan: usize,
+    scan_reader: Option<ScanReader>,
+    has_color: bool,
+    has_intensity: bool,
+    num_total_points: u64,
+    batch_size: usize,
+}
+
+impl E57Iterator {
+    pub fn from_file<P: AsRef<Path>>(e57_file: P, batch_size: usize) -> Result<Self> {
+        let mut file = File::open(e57_file).chain_err(|| "Could not open input file.")?;
+        let mut header = [0; HEADER_SIZE];
+        file.read_exact(&mut header)
+            .map_err(|_| invalid("the header is incomplete"))?;
+        if &header[..8] != SIGNATURE {
+            return Err(invalid("the signature is missing"));
+        }
+        let xml_offset = LittleEndian::read_u64(&header[24..32]);
+        let xml_length = LittleEndian::read_u64(&header[32..40]);
+        let page_size = LittleEndian::read_u64(&header[40..48]);
+        if page_size <= CHECKSUM_SIZE {
+            return Err(invalid(format!("the page size {} is too small", page_size)));
+        }
+        let mut reader = PagedReader { file, page_size };
+
+        let mut xml = vec![0; xml_length as usize];
+        reader.read_at(xml_offset, &mut xml)?;
+        let xml = String::from_utf8(xml).map_err(|_| invalid("the XML is not UTF-8"))?;
+        let root = XmlParser {
+            input: &xml,
+            pos: 0,
+        }
+        .parse_document()?;
+        let scans = match root.child("data3D") {
+            Some(data3d) => data3d
+                .children
+                .iter()
+                .map(parse_scan)
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+
+        Ok(E57Iterator {
+            reader,
+            has_color: scans.iter().all(|scan| scan.fields.color.is_some()),
+            has_intensity: scans.iter().all(|scan| scan.fields.intensity.is_some()),
+            num_total_points: scans.iter().map(|scan| scan.record_count).sum(),
+            scans,
+            current_scan: 0,
+            scan_reader: None,
+            batch_size,
+        })
+    }
+
+    fn start_scan(&mut self, scan_index: usize) -> Result<ScanReader> {
+        let scan = &self.scans[scan_index];
+        let mut section_header = [0; 32];
+        self.reader
+            .read_at(scan.section_offset, &mut section_header)?;
+        if section_header[0] != COMPRESSED_VECTOR_SECTION_ID {
+            return Err(invalid("the points are not in a compressed vector section"));
+        }
+        Ok(ScanReader {
+            decoders: scan.codecs.iter().map(|c| FieldDecoder::new(*c)).collect(),
+            next_packet: LittleEndian::read_u64(&section_header[16..24]),
+            records_left: scan.record_count,
+        })
+    }
+
+    /// Reads packets until every field has at least one more value.
+    fn read_packets(&mut self, scan_reader: &mut ScanReader) -> Result<()> {
+        let ready = |scan_reader: &ScanReader| {
+            scan_reader
+                .decoders
+                .iter()
+                .all(|d| d.is_constant() || !d.values.is_empty())
+        };
+        while !ready(scan_reader) {
+            let mut packet_header = [0; 4];
+            self.reader
+                .read_at(scan_reader.next_packet, &mut packet_header)?;
+            let packet_length = u64::from(LittleEndian::read_u16(&packet_header[2..4])) + 1;
+            let mut packet = vec![0; packet_length as usize];
+            self.reader.read_at(scan_reader.next_packet, &mut packet)?;
+            scan_reader.next_packet = self.reader.advance(scan_reader.next_packet, packet_length);
+            if packet_header[0] != DATA_PACKET {
+                continue;
+            }
+            // Type, flags, length, bytestream count, the buffer lengths and then the buffers.
+            if packet.len() < 6 {
+                return Err(invalid("a data packet is too short"));
+            }
+            let num_bytestreams = LittleEndian::read_u16(&packet[4..6]) as usize;
+            if num_bytestreams != scan_reader.decoders.len()
+                || packet.len() < 6 + 2 * num_bytestreams
+            {
+                return Err(invalid("a data packet does not match the prototype"));
+            }
+            let mut start = 6 + 2 * num_bytestreams;
+            for (i, decoder) in scan_reader.decoders.iter_mut().enumerate() {
+                let len = LittleEndian::read_u16(&packet[6 + 2 * i..8 + 2 * i]) as usize;
+                let data = packet
+                    .get(start..start + len)
+                    .ok_or_else(|| invalid("a bytestream exceeds its data packet"))?;
+                decoder.append(data);
+                start += len;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_batch(&mut self) -> Result<Option<PointsBatch>> {
+        let mut position = Vec::with_capacity(self.batch_size);
+        let mut color = Vec::new();
+        let mut intensity = Vec::new();
+        while position.len() < self.batch_size && self.current_scan < self.scans.len() {
+            let mut scan_reader = match self.scan_reader.take() {
+                Some(scan_reader) => scan_reader,
+                None => self.start_scan(self.current_scan)?,
+            };
+            while scan_reader.records_left > 0 && position.len() < self.batch_size {
+                self.read_packets(&mut scan_reader)?;
+                scan_reader.records_left -= 1;
+                let values: Vec<f64> = scan_reader.decoders.iter_mut().map(|d| d.pop()).collect();
+                let scan = &self.scans[self.current_scan];
+                if let Some(invalid_state) = scan.fields.invalid_state {
+                    if values[invalid_state] != 0. {
+                        continue;
+                    }
+                }
+                let p = match (scan.fields.cartesian, scan.fields.spherical) {
+                    (Some([x, y, z]), _) => Point3::new(values[x], values[y], values[z]),
+                    (None, Some([range, azimuth, elevation])) => {
+                        let (r, a, e) = (values[range], values[azimuth], values[elevation]);
+                        Point3::new(r * e.cos() * a.cos(), r * e.cos() * a.sin(), r * e.sin())
+                    }
+                    (None, None) => unreachable!(),
+                };
+                position.push(scan.pose * p);
+                if self.has_color {
+                    let channels = scan.fields.color.unwrap();
+                    let mut rgb = Vector3::zeros();
+                    for i in 0..3 {
+                        let (min, max) = scan.color_limits[i];
+                        let t = if max > min {
+                            (values[channels[i]] - min) / (max - min)
+                        } else {
+                            1.
+                        };
+                        rgb[i] = (t.clamp(0., 1.) * 255.).round() as u8;
+                    }
+                    color.push(rgb);
+                }
+                if self.has_intensity {
+                    intensity.push(values[scan.fields.intensity.unwrap()] as f32);
+                }
+            }
+            if scan_reader.records_left == 0 {
+                self.current_scan += 1;
+            } else {
+                self.scan_reader = Some(scan_reader);
+            }
+        }
+        if position.is_empty() {
+            return Ok(None);
+        }
+        let mut attributes = BTreeMap::new();
+        if self.has_color {
+            attributes.insert("color".to_string(), AttributeData::U8Vec3(color));
+        }
+        if self.has_intensity {
+            attributes.insert("intensity".to_string(), AttributeData::F32(intensity));
+        }
+        Ok(Some(PointsBatch {
+            position,
+            attributes,
+        }))
+    }
+}
+
+impl NumberOfPoints for E57Iterator {
+    /// The number of records in all scans, including those without a valid position.
+    fn num_points(&self) -> usize {
+        self.num_total_points as usize
+    }
+}
+
+impl Iterator for E57Iterator {
+    type Item = PointsBatch;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let num_batches = div_ceil(self.num_total_points as usize, self.batch_size);
+        (0, Some(num_batches))
+    }
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        self.read_batch()
+            .unwrap_or_else(|e| panic!("Could not read E57 points: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    const PAGE_SIZE: u64 = 1024;
+
+    /// Packs `values` with `bits` each, least significant bit first.
+    fn pack(values: &[u64], bits: usize) -> Vec<u8> {
+        let mut bytes = vec![0; div_ceil(values.len() * bits, 8)];
+        for (i, v) in values.iter().enumerate() {
+            for b in (0..bits).filter(|b| v >> b & 1 == 1) {
+                bytes[(i * bits + b) / 8] |= 1 << ((i * bits + b) % 8);
+            }
+        }
+        bytes
+    }
+
+    fn physical(logical: u64) -> u64 {
+        logical / (PAGE_SIZE - CHECKSUM_SIZE) * PAGE_SIZE + logical % (PAGE_SIZE - CHECKSUM_SIZE)
+    }
+
+    /// A compressed vector section starting at `logical_offset`, with an empty packet and then
+    /// the bytestreams split into data packets of at most `chunk_size` bytes per stream.
+    fn section(logical_offset: u64, streams: &[Vec<u8>], chunk_size: usize) -> Vec<u8> {
+        let mut packets = vec![2, 0, 3, 0];
+        let num_packets = streams.iter().map(|s| div_ceil(s.len(), chunk_size)).max();
+        for p in 0..num_packets.unwrap() {
+            let chunks: Vec<&[u8]> = streams
+                .iter()
+                .map(|s| &s[(p * chunk_size).min(s.len())..((p + 1) * chunk_size).min(s.len())])
+                .collect();
+            let mut packet = vec![DATA_PACKET, 0, 0, 0];
+            packet.extend_from_slice(&(streams.len() as u16).to_le_bytes());
+            for chunk in &chunks {
+                packet.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            }
+            for chunk in &chunks {
+                packet.extend_from_slice(chunk);
+            }
+            while packet.len() % 4 != 0 {
+                packet.push(0);
+            }
+            let length_minus_one = (packet.len() - 1) as u16;
+            packet[2..4].copy_from_slice(&length_minus_one.to_le_bytes());
+            packets.extend(packet);
+        }
+        let mut section = vec![COMPRESSED_VECTOR_SECTION_ID, 0, 0, 0, 0, 0, 0, 0];
+        section.extend_from_slice(&(32 + packets.len() as u64).to_le_bytes());
+        section.extend_from_slice(&physical(logical_offset + 32).to_le_bytes());
+        section.extend_from_slice(&0u64.to_le_bytes());
+        section.extend(packets);
+        section
+    }
+
+    fn write_e57(path: &Path) {
+        let num_points = 300;
+        let scan_0 = [
+            // cartesianX, a scaled integer with 17 bits.
+            pack(&(0..num_points).map(|i| i * 10).collect::<Vec<_>>(), 17),
+            // cartesianY, an integer from -1000.
+            pack(&(0..num_points).map(|i| 1000 - i).collect::<Vec<_>>(), 10),
+            // cartesianZ, a double.
+            pack(&vec![0.5f64.to_bits(); num_points as usize], 64),
+            // cartesianInvalidState, with the 8th point invalid.
+            pack(
+                &(0..num_points)
+                    .map(|i| if i == 7 { 2 } else { 0 })
+                    .collect::<Vec<_>>(),
+                2,
+            ),
+            // colorRed, colorGreen and colorBlue, which has no bits.
+            pack(&(0..num_points).map(|i| i % 256).collect::<Vec<_>>(), 8),
+            pack(&vec![0; num_points as usize], 8),
+            Vec::new(),
+            // intensity, a float.
+            pack(
+                &(0..num_points)
+                    .map(|i| u64::from((i as f32 / 100.).to_bits()))
+                    .collect::<Vec<_>>(),
+                32,
+            ),
+        ];
+        let doubles = |v: f64| pack(&[v.to_bits(); 3], 64);
+        let scan_1 = [
+            doubles(2.),
+            doubles(0.),
+            doubles(0.),
+            doubles(1.),
+            doubles(0.),
+            doubles(0.5),
+            Vec::new(),
+        ];
+
+        let section_0_offset = HEADER_SIZE as u64;
+        let section_0 = section(section_0_offset, &scan_0, 50);
+        let section_1_offset = section_0_offset + section_0.len() as u64;
+        let section_1 = section(section_1_offset, &scan_1, 50);
+        let xml_offset = section_1_offset + section_1.len() as u64;
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<e57Root type="Structure" xmlns="http://www.astm.org/COMMIT/E57/2010-e57-v1.0">
+  <formatName type="String"><![CDATA[ASTM E57 3D Imaging Data File]]></formatName>
+  <!-- Two scans. -->
+  <data3D type="Vector" allowHeterogeneousChildren="1">
+    <vectorChild type="Structure">
+      <points type="CompressedVector" fileOffset="{}" recordCount="{}">
+        <prototype type="Structure">
+          <cartesianX type="ScaledInteger" minimum="0" maximum="100000" scale="0.001"/>
+          <cartesianY type="Integer" minimum="-1000" maximum="0"/>
+          <cartesianZ type="Float"/>
+          <cartesianInvalidState type="Integer" minimum="0" maximum="2"/>
+          <colorRed type="Integer" minimum="0" maximum="255"/>
+          <colorGreen type="Integer" minimum="0" maximum="255"/>
+          <colorBlue type="Integer" minimum="0" maximum="0"/>
+          <intensity type="Float" precision="single"/>
+        </prototype>
+        <codecs type="Vector" allowHeterogeneousChildren="1"/>
+      </points>
+    </vectorChild>
+    <vectorChild type="Structure">
+      <pose type="Structure">
+        <rotation type="Structure">
+          <w type="Float">0.7071067811865476</w>
+          <x type="Float">0</x>
+          <y type="Float">0</y>
+          <z type="Float">0.7071067811865476</z>
+        </rotation>
+        <translation type="Structure">
+          <x type="Float">10</x><y type="Float">0</y><z type="Float">0</z>
+        </translation>
+      </pose>
+      <colorLimits type="Structure">
+        <colorRedMinimum type="Float">0</colorRedMinimum>
+        <colorRedMaximum type="Float">1</colorRedMaximum>
+        <colorGreenMinimum type="Float">0</colorGreenMinimum>
+        <colorGreenMaximum type="Float">1</colorGreenMaximum>
+        <colorBlueMinimum type="Float">0</colorBlueMinimum>
+        <colorBlueMaximum type="Float">1</colorBlueMaximum>
+      </colorLimits>
+      <points type="CompressedVector" fileOffset="{}" recordCount="3">
+        <prototype type="Structure">
+          <sphericalRange type="Float"/>
+          <sphericalAzimuth type="Float"/>
+          <sphericalElevation type="Float"/>
+          <colorRed type="Float"/>
+          <colorGreen type="Float"/>
+          <colorBlue type="Float"/>
+          <intensity type="Integer" minimum="0" maximum="0"/>
+        </prototype>
+      </points>
+    </vectorChild>
+  </data3D>
+</e57Root>
+"#,
+            physical(section_0_offset),
+            num_points,
+            physical(section_1_offset),
+        );
+
+        let mut logical = SIGNATURE.to_vec();
+        logical.extend_from_slice(&1u32.to_le_bytes());
+        logical.extend_from_slice(&0u32.to_le_bytes());
+        let file_length = physical(xml_offset + xml.len() as u64);
+        let file_length = div_ceil(file_length, PAGE_SIZE) * PAGE_SIZE;
+        for v in &[
+            file_length,
+            physical(xml_offset),
+            xml.len() as u64,
+            PAGE_SIZE,
+        ] {
+            logical.extend_from_slice(&v.to_le_bytes());
+        }
+        logical.extend(section_0);
+        logical.extend(section_1);
+        logical.extend_from_slice(xml.as_bytes());
+
+        let mut file = File::create(path).unwrap();
+        for page in logical.chunks((PAGE_SIZE - CHECKSUM_SIZE) as usize) {
+            file.write_all(page).unwrap();
+            file.write_all(&vec![0; PAGE_SIZE as usize - page.len()])
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_scans() {
+        let tmp_dir = TempDir::new("e57").unwrap();
+        let path = tmp_dir.path().join("scans.e57");
+        write_e57(&path);
+
+        let iterator = E57Iterator::from_file(&path, 100).unwrap();
+        assert_eq!(iterator.num_points(), 303);
+        let mut position = Vec::new();
+        let mut color: Vec<Vector3<u8>> = Vec::new();
+        let mut intensity: Vec<f32> = Vec::new();
+        for batch in iterator {
+            assert!(batch.position.len() <= 100);
+            color.extend(batch.get_attribute_vec::<Vector3<u8>>("color").unwrap());
+            intensity.extend(batch.get_attribute_vec::<f32>("intensity").unwrap());
+            position.extend(batch.position);
+        }
+        // The invalid point is skipped.
+        assert_eq!(position.len(), 302);
+        assert!((position[1] - Point3::new(0.01, -1., 0.5)).norm() < 1e-9);
+        assert!((position[7] - Point3::new(0.08, -8., 0.5)).norm() < 1e-9);
+        assert!((position[298] - Point3::new(2.99, -299., 0.5)).norm() < 1e-9);
+        assert_eq!(color[1], Vector3::new(1, 0, 0));
+        assert_eq!(intensity[1], 0.01);
+        // The second scan is rotated by 90 degrees around z and moved along x.
+        assert!((position[300] - Point3::new(10., 2., 0.)).norm() < 1e-9);
+        assert_eq!(color[301], Vector3::new(255, 0, 128));
+        assert_eq!(intensity[301], 0.);
+    }
+
+    #[test]
+    fn test_short_data_packet_is_an_error() {
+        let tmp_dir = TempDir::new("e57").unwrap();
+        let path = tmp_dir.path().join("scans.e57");
+        write_e57(&path);
+        // Turn the empty packet at the start of the first section into a data packet, which is
+        // too short to hold the bytestream count.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[physical(HEADER_SIZE as u64 + 32) as usize] = DATA_PACKET;
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut iterator = E57Iterator::from_file(&path, 100).unwrap();
+        assert!(iterator.read_batch().is_err());
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::errors::*;
+use crate::read_write::{E57Iterator, PcdIterator, PlyIterator};
+use crate::{NumberOfPoints, PointsBatch};
+use std::path::Path;
+
+/// The points of an input file of any of the supported formats, which is chosen by the file's
+/// extension: '.e57' files are read as E57, '.pcd' files as PCD and all others as PLY.
+pub enum InputFileIterator {
+    Ply(PlyIterator),
+    E57(E57Iterator),
+    Pcd(PcdIterator),
+}
+
+impl InputFileIterator {
+    pub fn from_file<P: AsRef<Path>>(file: P, batch_size: usize) -> Result<Self> {
+        let extension = file
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("e57") => Ok(InputFileIterator::E57(E57Iterator::from_file(
+                file, batch_size,
+            )?)),
+            Some("pcd") => Ok(InputFileIterator::Pcd(PcdIterator::from_file(
+                file, batch_size,
+            )?)),
+            _ => Ok(InputFileIterator::Ply(PlyIterator::from_file(
+                file, batch_size,
+            )?)),
+        }
+    }
+}
+
+impl NumberOfPoints for InputFileIterator {
+    fn num_points(&self) -> usize {
+        match self {
+            InputFileIterator::Ply(iterator) => iterator.num_points(),
+            InputFileIterator::E57(iterator) => iterator.num_points(),
+            InputFileIterator::Pcd(iterator) => iterator.num_points(),
+        }
+    }
+}
+
+impl Iterator for InputFileIterator {
+    type Item = PointsBatch;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            InputFileIterator::Ply(iterator) => iterator.size_hint(),
+            InputFileIterator::E57(iterator) => iterator.size_hint(),
+            InputFileIterator::Pcd(iterator) => iterator.size_hint(),
+        }
+    }
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        match self {
+            InputFileIterator::Ply(iterator) => iterator.next(),
+            InputFileIterator::E57(iterator) => iterator.next(),
+            InputFileIterator::Pcd(iterator) => iterator.next(),
+        }
+    }
+}
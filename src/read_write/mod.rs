@@ -18,12 +18,21 @@ pub use self::codec::{
     PositionEncoding,
 };
 
+mod e57;
+pub use self::e57::E57Iterator;
+
+mod input_file;
+pub use self::input_file::InputFileIterator;
+
 mod node_iterator;
 pub use self::node_iterator::NodeIterator;
 
 mod node_writer;
 pub use self::node_writer::{DataWriter, NodeWriter, OpenMode, WriteEncoded, WriteLE, WriteLEPos};
 
+mod pcd;
+pub use self::pcd::{PcdIterator, PcdNodeWriter};
+
 mod ply;
 pub use self::ply::{PlyIterator, PlyNodeWriter};
 
@@ -12,17 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::data_provider::DataProvider;
+use crate::data_provider::{DataProvider, NodeBytes};
 use crate::errors::*;
+use crate::labels::{RunLengthDecoder, CLASSIFICATION_ATTRIBUTE};
 use crate::read_write::{AttributeReader, Encoding, RawNodeReader};
 use crate::{AttributeDataType, NumberOfPoints, PointsBatch};
 use num_integer::div_ceil;
 use std::collections::HashMap;
-use std::io::BufReader;
+use std::io::{BufReader, Cursor, Read};
+use std::sync::Arc;
 
 /// Streams points from our data provider representation.
 pub struct NodeIterator {
     reader: Option<RawNodeReader>,
+    /// Points that were decoded before, e.g. by a cache.
+    batch: Option<Arc<PointsBatch>>,
     num_points: usize,
     point_count: usize,
     batch_size: usize,
@@ -32,6 +36,7 @@ impl Default for NodeIterator {
     fn default() -> Self {
         NodeIterator {
             reader: None,
+            batch: None,
             num_points: 0,
             point_count: 0,
             batch_size: 0,
@@ -47,6 +52,23 @@ impl NodeIterator {
 
         NodeIterator {
             reader: Some(reader),
+            batch: None,
+            num_points,
+            point_count: 0,
+            batch_size,
+        }
+    }
+
+    /// Streams the points of an already decoded node.
+    pub fn from_batch(batch: Arc<PointsBatch>, batch_size: usize) -> Self {
+        let num_points = batch.position.len();
+        if num_points == 0 {
+            return NodeIterator::default();
+        }
+
+        NodeIterator {
+            reader: None,
+            batch: Some(batch),
             num_points,
             point_count: 0,
             batch_size,
@@ -65,28 +87,110 @@ impl NodeIterator {
             return Ok(NodeIterator::default());
         }
 
+        let files_to_read = Self::files_to_read(attribute_data_types);
+        if let Some(all_bytes) = data_provider.mapped_data(&id.to_string(), &files_to_read) {
+            return Self::from_bytes(
+                all_bytes?,
+                attribute_data_types,
+                encoding,
+                num_points,
+                batch_size,
+            );
+        }
+        let all_reads = data_provider.data(&id.to_string(), &files_to_read)?;
+        Self::from_readers(
+            all_reads,
+            attribute_data_types,
+            encoding,
+            num_points,
+            batch_size,
+        )
+    }
+
+    /// The attributes whose files 'from_readers' needs, i.e. 'position' and those of
+    /// `attribute_data_types`.
+    pub fn files_to_read(attribute_data_types: &HashMap<String, AttributeDataType>) -> Vec<&str> {
         let attributes: Vec<&str> = attribute_data_types.keys().map(String::as_str).collect();
-        let mut all_reads =
-            data_provider.data(&id.to_string(), &[&["position"], &attributes[..]].concat())?;
+        [&["position"], &attributes[..]].concat()
+    }
+
+    /// Streams the points of a node from the readers that a data provider returned for the
+    /// 'files_to_read'.
+    pub fn from_readers(
+        mut all_reads: HashMap<String, Box<dyn Read + Send>>,
+        attribute_data_types: &HashMap<String, AttributeDataType>,
+        encoding: Encoding,
+        num_points: usize,
+        batch_size: usize,
+    ) -> Result<Self> {
+        if num_points == 0 {
+            return Ok(NodeIterator::default());
+        }
+
         // Unwrapping all following removals is safe,
         // as the data provider would already have errored on unavailability.
         let position_reader = all_reads.remove("position").unwrap();
-        let attribute_readers = attribute_data_types
-            .iter()
-            .map(|(attribute, data_type)| {
-                let data_type = *data_type;
-                let reader = BufReader::new(all_reads.remove(attribute).unwrap());
-                let attribute_reader = AttributeReader { data_type, reader };
-                (attribute.clone(), attribute_reader)
+        Ok(Self::new(
+            RawNodeReader::new(
+                position_reader,
+                Self::attribute_readers(all_reads, attribute_data_types),
+                encoding,
+            )?,
+            num_points,
+            batch_size,
+        ))
+    }
+
+    /// Like 'from_readers', but for the files that a data provider holds in memory. The positions
+    /// are decoded straight from their bytes.
+    pub fn from_bytes(
+        mut all_bytes: HashMap<String, NodeBytes>,
+        attribute_data_types: &HashMap<String, AttributeDataType>,
+        encoding: Encoding,
+        num_points: usize,
+        batch_size: usize,
+    ) -> Result<Self> {
+        if num_points == 0 {
+            return Ok(NodeIterator::default());
+        }
+
+        let position_bytes = all_bytes.remove("position").unwrap();
+        let all_reads = all_bytes
+            .into_iter()
+            .map(|(attribute, bytes)| {
+                let reader: Box<dyn Read + Send> = Box::new(Cursor::new(bytes));
+                (attribute, reader)
             })
             .collect();
-
         Ok(Self::new(
-            RawNodeReader::new(position_reader, attribute_readers, encoding)?,
+            RawNodeReader::from_position_bytes(
+                position_bytes,
+                Self::attribute_readers(all_reads, attribute_data_types),
+                encoding,
+            )?,
             num_points,
             batch_size,
         ))
     }
+
+    fn attribute_readers(
+        mut all_reads: HashMap<String, Box<dyn Read + Send>>,
+        attribute_data_types: &HashMap<String, AttributeDataType>,
+    ) -> HashMap<String, AttributeReader> {
+        attribute_data_types
+            .iter()
+            .map(|(attribute, data_type)| {
+                let data_type = *data_type;
+                let mut reader = all_reads.remove(attribute).unwrap();
+                if attribute == CLASSIFICATION_ATTRIBUTE {
+                    reader = Box::new(RunLengthDecoder::new(BufReader::new(reader), data_type));
+                }
+                let reader = BufReader::new(reader);
+                let attribute_reader = AttributeReader { data_type, reader };
+                (attribute.clone(), attribute_reader)
+            })
+            .collect()
+    }
 }
 
 impl NumberOfPoints for NodeIterator {
@@ -103,6 +207,14 @@ impl Iterator for NodeIterator {
         (num_batches, Some(num_batches))
     }
     fn next(&mut self) -> Option<PointsBatch> {
+        if let Some(batch) = &self.batch {
+            if self.point_count < self.num_points {
+                let end = std::cmp::min(self.point_count + self.batch_size, self.num_points);
+                let res = batch.slice(self.point_count..end);
+                self.point_count = end;
+                return Some(res);
+            }
+        }
         if let Some(reader) = &mut self.reader {
             if self.point_count < self.num_points {
                 let num_points_to_read =
@@ -0,0 +1,801 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading and writing of PCD files, the format of the Point Cloud Library (PCL). Colors are
+//! stored in PCL's convention as a single 'rgb' or 'rgba' field with the channels packed into 32
+//! bits, which we convert from and to the 'color' attribute.
+
+use crate::errors::*;
+use crate::read_write::{
+    DataWriter, Encoding, NodeWriter, OpenMode, PositionEncoding, WriteEncoded, WriteLE, WriteLEPos,
+};
+use crate::{AttributeData, NumberOfPoints, Point, PointsBatch};
+use byteorder::{ByteOrder, LittleEndian};
+use nalgebra::{Point3, Vector3};
+use num_integer::div_ceil;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The fixed width of 'WIDTH' and 'POINTS' in the headers we write, so that the number of points
+/// can be updated in place.
+const NUM_POINTS_WIDTH: usize = 20;
+
+fn invalid(message: impl Into<String>) -> Error {
+    ErrorKind::InvalidInput(format!("Invalid PCD file: {}", message.into())).into()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FieldType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl FieldType {
+    fn from_header(type_str: &str, size_str: &str) -> Result<Self> {
+        use self::FieldType::*;
+        match (type_str, size_str) {
+            ("I", "1") => Ok(I8),
+            ("I", "2") => Ok(I16),
+            ("I", "4") => Ok(I32),
+            ("I", "8") => Ok(I64),
+            ("U", "1") => Ok(U8),
+            ("U", "2") => Ok(U16),
+            ("U", "4") => Ok(U32),
+            ("U", "8") => Ok(U64),
+            ("F", "4") => Ok(F32),
+            ("F", "8") => Ok(F64),
+            _ => Err(invalid(format!(
+                "unsupported field type {} of size {}",
+                type_str, size_str
+            ))),
+        }
+    }
+
+    /// The 'TYPE' and 'SIZE' of the field in the header.
+    fn to_header(self) -> (&'static str, usize) {
+        use self::FieldType::*;
+        match self {
+            I8 => ("I", 1),
+            I16 => ("I", 2),
+            I32 => ("I", 4),
+            I64 => ("I", 8),
+            U8 => ("U", 1),
+            U16 => ("U", 2),
+            U32 => ("U", 4),
+            U64 => ("U", 8),
+            F32 => ("F", 4),
+            F64 => ("F", 8),
+        }
+    }
+
+    fn size(self) -> usize {
+        self.to_header().1
+    }
+
+    fn empty_data(self) -> AttributeData {
+        use self::FieldType::*;
+        match self {
+            I8 => AttributeData::I8(Vec::new()),
+            I16 => AttributeData::I16(Vec::new()),
+            I32 => AttributeData::I32(Vec::new()),
+            I64 => AttributeData::I64(Vec::new()),
+            U8 => AttributeData::U8(Vec::new()),
+            U16 => AttributeData::U16(Vec::new()),
+            U32 => AttributeData::U32(Vec::new()),
+            U64 => AttributeData::U64(Vec::new()),
+            F32 => AttributeData::F32(Vec::new()),
+            F64 => AttributeData::F64(Vec::new()),
+        }
+    }
+
+    /// The component 'i' of a field as f64.
+    fn read_f64(self, values: &RawValues, i: usize) -> Result<f64> {
+        use self::FieldType::*;
+        let bytes = match values {
+            RawValues::Ascii(tokens) => return parse(tokens[i]),
+            RawValues::Binary(bytes) => &bytes[i * self.size()..],
+        };
+        Ok(match self {
+            I8 => f64::from(bytes[0] as i8),
+            I16 => f64::from(LittleEndian::read_i16(bytes)),
+            I32 => f64::from(LittleEndian::read_i32(bytes)),
+            I64 => LittleEndian::read_i64(bytes) as f64,
+            U8 => f64::from(bytes[0]),
+            U16 => f64::from(LittleEndian::read_u16(bytes)),
+            U32 => f64::from(LittleEndian::read_u32(bytes)),
+            U64 => LittleEndian::read_u64(bytes) as f64,
+            F32 => f64::from(LittleEndian::read_f32(bytes)),
+            F64 => LittleEndian::read_f64(bytes),
+        })
+    }
+}
+
+fn field_type_of(data: &AttributeData) -> FieldType {
+    match data {
+        AttributeData::I8(_) => FieldType::I8,
+        AttributeData::I16(_) => FieldType::I16,
+        AttributeData::I32(_) => FieldType::I32,
+        AttributeData::I64(_) => FieldType::I64,
+        AttributeData::U8(_) | AttributeData::U8Vec3(_) => FieldType::U8,
+        AttributeData::U16(_) => FieldType::U16,
+        AttributeData::U32(_) => FieldType::U32,
+        AttributeData::U64(_) => FieldType::U64,
+        AttributeData::F32(_) => FieldType::F32,
+        AttributeData::F64(_) | AttributeData::F64Vec3(_) => FieldType::F64,
+    }
+}
+
+fn parse<T: FromStr>(token: &str) -> Result<T> {
+    token
+        .parse::<T>()
+        .map_err(|_| invalid(format!("could not parse value '{}'", token)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DataFormat {
+    Ascii,
+    Binary,
+}
+
+#[derive(Debug, Clone)]
+struct FieldHeader {
+    name: String,
+    field_type: FieldType,
+    count: usize,
+}
+
+#[derive(Debug)]
+struct Header {
+    fields: Vec<FieldHeader>,
+    num_points: usize,
+    data_format: DataFormat,
+    /// The byte offsets and lengths of the values of 'WIDTH' and 'POINTS'.
+    num_points_locations: Vec<(u64, usize)>,
+}
+
+fn parse_header<R: BufRead>(reader: &mut R) -> Result<Header> {
+    let mut header_len = 0;
+    let mut line = String::new();
+    let mut names: Vec<String> = Vec::new();
+    let mut sizes: Vec<String> = Vec::new();
+    let mut types: Vec<String> = Vec::new();
+    let mut counts: Option<Vec<usize>> = None;
+    let (mut width, mut height, mut points) = (None, 1, None);
+    let mut num_points_locations = Vec::new();
+    let data_format = loop {
+        line.clear();
+        let line_len = reader.read_line(&mut line)?;
+        if line_len == 0 {
+            return Err(invalid("the header does not end in 'DATA'"));
+        }
+        let line_start = header_len;
+        header_len += line_len;
+        let entries: Vec<&str> = line.split_whitespace().collect();
+        let values = || entries[1..].iter().map(|e| e.to_string()).collect();
+        match entries.first() {
+            None => {}
+            Some(comment) if comment.starts_with('#') => {}
+            Some(&"VERSION") | Some(&"VIEWPOINT") => {}
+            Some(&"FIELDS") => names = values(),
+            Some(&"SIZE") => sizes = values(),
+            Some(&"TYPE") => types = values(),
+            Some(&"COUNT") => {
+                counts = Some(
+                    entries[1..]
+                        .iter()
+                        .map(|c| parse(c))
+                        .collect::<Result<_>>()?,
+                )
+            }
+            Some(&"WIDTH") | Some(&"POINTS") if entries.len() == 2 => {
+                let value = parse(entries[1])?;
+                if entries[0] == "WIDTH" {
+                    width = Some(value);
+                } else {
+                    points = Some(value);
+                }
+                let value_start = line_start + line.find(entries[1]).unwrap();
+                num_points_locations.push((value_start as u64, entries[1].len()));
+            }
+            Some(&"HEIGHT") if entries.len() == 2 => height = parse(entries[1])?,
+            Some(&"DATA") => match entries.get(1) {
+                Some(&"ascii") => break DataFormat::Ascii,
+                Some(&"binary") => break DataFormat::Binary,
+                _ => {
+                    return Err(invalid(format!(
+                        "unsupported data format in '{}'",
+                        line.trim()
+                    )))
+                }
+            },
+            _ => return Err(invalid(format!("invalid line '{}'", line.trim()))),
+        }
+    };
+
+    let counts = counts.unwrap_or_else(|| vec![1; names.len()]);
+    if sizes.len() != names.len() || types.len() != names.len() || counts.len() != names.len() {
+        return Err(invalid(
+            "'FIELDS', 'SIZE', 'TYPE' and 'COUNT' differ in length",
+        ));
+    }
+    let fields = names
+        .into_iter()
+        .zip(types.iter().zip(&sizes))
+        .zip(counts)
+        .map(|((name, (type_str, size_str)), count)| {
+            Ok(FieldHeader {
+                name,
+                field_type: FieldType::from_header(type_str, size_str)?,
+                count,
+            })
+        })
+        .collect::<Result<_>>()?;
+    let num_points = match (points, width) {
+        (Some(points), _) => points,
+        (None, Some(width)) => width * height,
+        (None, None) => return Err(invalid("the number of points is missing")),
+    };
+    Ok(Header {
+        fields,
+        num_points,
+        data_format,
+        num_points_locations,
+    })
+}
+
+/// The values of one field of a point.
+enum RawValues<'a> {
+    Ascii(&'a [&'a str]),
+    Binary(&'a [u8]),
+}
+
+/// What a field of the file is read into.
+enum Column {
+    Position(usize),
+    /// 'rgb' or 'rgba', read into a 'U32' as PCL packs them, even if the type is float.
+    PackedColor,
+    Scalar(AttributeData),
+    /// Fields with three components, read into 'U8Vec3' or 'F64Vec3' attributes.
+    Vector(AttributeData),
+    Skip,
+}
+
+struct Field {
+    header: FieldHeader,
+    column: Column,
+    colors: Vec<Vector3<u8>>,
+}
+
+fn read_i8(bytes: &[u8]) -> i8 {
+    bytes[0] as i8
+}
+
+fn read_u8(bytes: &[u8]) -> u8 {
+    bytes[0]
+}
+
+fn push_scalar(data: &mut AttributeData, values: &RawValues) -> Result<()> {
+    macro_rules! push {
+        ($vec:ident, $dtype:ty, $read:expr) => {{
+            let value: $dtype = match values {
+                RawValues::Ascii(tokens) => parse(tokens[0])?,
+                RawValues::Binary(bytes) => $read(&bytes[..]),
+            };
+            $vec.push(value)
+        }};
+    }
+    match data {
+        AttributeData::I8(vec) => push!(vec, i8, read_i8),
+        AttributeData::I16(vec) => push!(vec, i16, LittleEndian::read_i16),
+        AttributeData::I32(vec) => push!(vec, i32, LittleEndian::read_i32),
+        AttributeData::I64(vec) => push!(vec, i64, LittleEndian::read_i64),
+        AttributeData::U8(vec) => push!(vec, u8, read_u8),
+        AttributeData::U16(vec) => push!(vec, u16, LittleEndian::read_u16),
+        AttributeData::U32(vec) => push!(vec, u32, LittleEndian::read_u32),
+        AttributeData::U64(vec) => push!(vec, u64, LittleEndian::read_u64),
+        AttributeData::F32(vec) => push!(vec, f32, LittleEndian::read_f32),
+        AttributeData::F64(vec) => push!(vec, f64, LittleEndian::read_f64),
+        AttributeData::U8Vec3(_) | AttributeData::F64Vec3(_) => unreachable!(),
+    }
+    Ok(())
+}
+
+/// PCL packs colors as 0x00RRGGBB ('rgb') or 0xAARRGGBB ('rgba'), for 'rgb' mostly into the bits
+/// of a float.
+fn unpack_color(field_type: FieldType, values: &RawValues) -> Result<Vector3<u8>> {
+    let packed = match values {
+        RawValues::Ascii(tokens) if field_type == FieldType::F32 => {
+            parse::<f32>(tokens[0])?.to_bits()
+        }
+        RawValues::Ascii(tokens) => parse::<u32>(tokens[0])?,
+        RawValues::Binary(bytes) => LittleEndian::read_u32(bytes),
+    };
+    Ok(Vector3::new(
+        (packed >> 16) as u8,
+        (packed >> 8) as u8,
+        packed as u8,
+    ))
+}
+
+fn pack_color(color: &Vector3<u8>) -> u32 {
+    u32::from(color.x) << 16 | u32::from(color.y) << 8 | u32::from(color.z)
+}
+
+/// Reads the points of an ASCII or binary PCD file, with a 'color' attribute for an 'rgb' or
+/// 'rgba' field. Points with a NaN coordinate, which PCL uses for invalid points, are skipped.
+pub struct PcdIterator {
+    reader: BufReader<File>,
+    data_format: DataFormat,
+    fields: Vec<Field>,
+    point_size: usize,
+    num_total_points: usize,
+    point_count: usize,
+    batch_size: usize,
+}
+
+impl PcdIterator {
+    pub fn from_file<P: AsRef<Path>>(pcd_file: P, batch_size: usize) -> Result<Self> {
+        let file = File::open(pcd_file).chain_err(|| "Could not open input file.")?;
+        let mut reader = BufReader::new(file);
+        let header = parse_header(&mut reader)?;
+
+        let mut axes = [false; 3];
+        let fields = header
+            .fields
+            .into_iter()
+            .map(|header| {
+                let column = match (&header.name as &str, header.count) {
+                    ("x", 1) | ("y", 1) | ("z", 1) => {
+                        let axis = usize::from(header.name.as_bytes()[0] - b'x');
+                        axes[axis] = true;
+                        Column::Position(axis)
+                    }
+                    ("rgb", 1) | ("rgba", 1) if header.field_type.size() == 4 => {
+                        Column::PackedColor
+                    }
+                    ("_", _) => Column::Skip,
+                    (_, 1) => Column::Scalar(header.field_type.empty_data()),
+                    (_, 3) => match header.field_type {
+                        FieldType::U8 => Column::Vector(AttributeData::U8Vec3(Vec::new())),
+                        FieldType::F32 | FieldType::F64 => {
+                            Column::Vector(AttributeData::F64Vec3(Vec::new()))
+                        }
+                        _ => Column::Skip,
+                    },
+                    _ => Column::Skip,
+                };
+                if let Column::Skip = column {
+                    if header.name != "_" {
+                        eprintln!("Will ignore field '{}'.", header.name);
+                    }
+                }
+                Field {
+                    header,
+                    column,
+                    colors: Vec::new(),
+                }
+            })
+            .collect::<Vec<_>>();
+        if axes.iter().any(|seen| !seen) {
+            return Err(invalid("the fields 'x', 'y' and 'z' are required"));
+        }
+        let point_size = fields
+            .iter()
+            .map(|f| f.header.field_type.size() * f.header.count)
+            .sum();
+
+        Ok(PcdIterator {
+            reader,
+            data_format: header.data_format,
+            fields,
+            point_size,
+            num_total_points: header.num_points,
+            point_count: 0,
+            batch_size,
+        })
+    }
+
+    /// Adds the point to the columns unless one of its coordinates is NaN.
+    fn add_point(&mut self, values: &[RawValues], position: &mut Vec<Point3<f64>>) -> Result<()> {
+        let mut p = Point3::origin();
+        for (field, values) in self.fields.iter().zip(values) {
+            if let Column::Position(axis) = field.column {
+                p[axis] = field.header.field_type.read_f64(values, 0)?;
+            }
+        }
+        if p.iter().any(|c| c.is_nan()) {
+            return Ok(());
+        }
+        position.push(p);
+        for (field, values) in self.fields.iter_mut().zip(values) {
+            let field_type = field.header.field_type;
+            match &mut field.column {
+                Column::Position(_) | Column::Skip => {}
+                Column::PackedColor => field.colors.push(unpack_color(field_type, values)?),
+                Column::Scalar(data) => push_scalar(data, values)?,
+                Column::Vector(AttributeData::U8Vec3(vec)) => {
+                    let mut v = Vector3::zeros();
+                    for i in 0..3 {
+                        v[i] = field_type.read_f64(values, i)? as u8;
+                    }
+                    vec.push(v);
+                }
+                Column::Vector(AttributeData::F64Vec3(vec)) => {
+                    let mut v = Vector3::zeros();
+                    for i in 0..3 {
+                        v[i] = field_type.read_f64(values, i)?;
+                    }
+                    vec.push(v);
+                }
+                Column::Vector(_) => unreachable!(),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_batch(&mut self) -> Result<Option<PointsBatch>> {
+        let mut position = Vec::with_capacity(self.batch_size);
+        let mut line = String::new();
+        let mut record = vec![0; self.point_size];
+        while position.len() < self.batch_size && self.point_count < self.num_total_points {
+            self.point_count += 1;
+            match self.data_format {
+                DataFormat::Ascii => {
+                    line.clear();
+                    if self.reader.read_line(&mut line)? == 0 {
+                        return Err(invalid("the file ends before all points"));
+                    }
+                    let tokens: Vec<&str> = line.split_whitespace().collect();
+                    let mut start = 0;
+                    let mut values = Vec::with_capacity(self.fields.len());
+                    for field in &self.fields {
+                        let end = start + field.header.count;
+                        let field_tokens = tokens.get(start..end).ok_or_else(|| {
+                            invalid(format!("too few values in '{}'", line.trim()))
+                        })?;
+                        values.push(RawValues::Ascii(field_tokens));
+                        start = end;
+                    }
+                    self.add_point(&values, &mut position)?;
+                }
+                DataFormat::Binary => {
+                    self.reader
+                        .read_exact(&mut record)
+                        .map_err(|_| invalid("the file ends before all points"))?;
+                    let mut start = 0;
+                    let mut values = Vec::with_capacity(self.fields.len());
+                    for field in &self.fields {
+                        let end = start + field.header.field_type.size() * field.header.count;
+                        values.push(RawValues::Binary(&record[start..end]));
+                        start = end;
+                    }
+                    self.add_point(&values, &mut position)?;
+                }
+            }
+        }
+        if position.is_empty() {
+            return Ok(None);
+        }
+
+        let mut attributes = BTreeMap::new();
+        for field in &mut self.fields {
+            match &mut field.column {
+                Column::PackedColor => {
+                    attributes.insert(
+                        "color".to_string(),
+                        AttributeData::U8Vec3(field.colors.split_off(0)),
+                    );
+                }
+                Column::Scalar(data) | Column::Vector(data) => {
+                    attributes.insert(field.header.name.clone(), data.split_off(0));
+                }
+                Column::Position(_) | Column::Skip => {}
+            }
+        }
+        Ok(Some(PointsBatch {
+            position,
+            attributes,
+        }))
+    }
+}
+
+impl NumberOfPoints for PcdIterator {
+    /// The number of points in the header, including those with NaN coordinates.
+    fn num_points(&self) -> usize {
+        self.num_total_points
+    }
+}
+
+impl Iterator for PcdIterator {
+    type Item = PointsBatch;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let num_batches = div_ceil(self.num_total_points, self.batch_size);
+        (0, Some(num_batches))
+    }
+
+    fn next(&mut self) -> Option<PointsBatch> {
+        self.read_batch()
+            .unwrap_or_else(|e| panic!("Could not read PCD points: {}", e))
+    }
+}
+
+/// Writes points into a binary PCD file. The 'color' attribute is packed into an 'rgb' field.
+pub struct PcdNodeWriter {
+    writer: DataWriter,
+    point_count: usize,
+    encoding: Encoding,
+    num_points_locations: Vec<(u64, usize)>,
+}
+
+impl NodeWriter<PointsBatch> for PcdNodeWriter {
+    fn new(filename: impl Into<PathBuf>, encoding: Encoding, open_mode: OpenMode) -> Self {
+        Self::new(filename, encoding, open_mode)
+    }
+
+    fn write(&mut self, p: &PointsBatch) -> io::Result<()> {
+        if p.position.is_empty() {
+            return Ok(());
+        }
+        if self.point_count == 0 {
+            self.create_header(
+                &p.attributes
+                    .iter()
+                    .map(|(name, data)| match data {
+                        AttributeData::U8Vec3(_) if name == "color" => ("rgb", FieldType::U32, 1),
+                        _ => (&name[..], field_type_of(data), data.dim()),
+                    })
+                    .collect::<Vec<_>>(),
+            )?;
+        }
+
+        for (i, pos) in p.position.iter().enumerate() {
+            pos.write_encoded(&self.encoding, &mut self.writer)?;
+            for (name, data) in &p.attributes {
+                match data {
+                    AttributeData::U8Vec3(colors) if name == "color" => {
+                        pack_color(&colors[i]).write_le(&mut self.writer)?
+                    }
+                    _ => data.write_le_pos(i, &mut self.writer)?,
+                }
+            }
+        }
+
+        self.point_count += p.position.len();
+
+        Ok(())
+    }
+}
+
+impl NodeWriter<Point> for PcdNodeWriter {
+    fn new(filename: impl Into<PathBuf>, encoding: Encoding, open_mode: OpenMode) -> Self {
+        Self::new(filename, encoding, open_mode)
+    }
+
+    fn write(&mut self, p: &Point) -> io::Result<()> {
+        if self.point_count == 0 {
+            let mut fields = vec![("rgb", FieldType::U32, 1)];
+            if p.intensity.is_some() {
+                fields.push(("intensity", FieldType::F32, 1));
+            }
+            self.create_header(&fields)?;
+        }
+
+        p.position.write_encoded(&self.encoding, &mut self.writer)?;
+        let color = Vector3::new(p.color.red, p.color.green, p.color.blue);
+        pack_color(&color).write_le(&mut self.writer)?;
+        if let Some(i) = p.intensity {
+            i.write_le(&mut self.writer)?;
+        }
+
+        self.point_count += 1;
+
+        Ok(())
+    }
+}
+
+impl Drop for PcdNodeWriter {
+    fn drop(&mut self) {
+        for (offset, _) in &self.num_points_locations {
+            if self.writer.seek(SeekFrom::Start(*offset)).is_ok() {
+                let _res = write!(
+                    &mut self.writer,
+                    "{:0width$}",
+                    self.point_count,
+                    width = NUM_POINTS_WIDTH
+                );
+            }
+        }
+    }
+}
+
+impl PcdNodeWriter {
+    pub fn new(filename: impl Into<PathBuf>, encoding: Encoding, open_mode: OpenMode) -> Self {
+        let filename = filename.into();
+        let mut point_count = 0;
+        let mut num_points_locations = Vec::new();
+        if open_mode == OpenMode::Append {
+            if let Ok(file) = File::open(&filename) {
+                let header = parse_header(&mut BufReader::new(file)).unwrap();
+                assert!(
+                    header.data_format == DataFormat::Binary
+                        && header
+                            .num_points_locations
+                            .iter()
+                            .all(|(_, len)| *len == NUM_POINTS_WIDTH),
+                    "Can only append to PCD files written by 'PcdNodeWriter'."
+                );
+                point_count = header.num_points;
+                num_points_locations = header.num_points_locations;
+            }
+        }
+        let writer = DataWriter::new(filename, open_mode).unwrap();
+        Self {
+            writer,
+            point_count,
+            encoding,
+            num_points_locations,
+        }
+    }
+
+    fn create_header(&mut self, fields: &[(&str, FieldType, usize)]) -> io::Result<()> {
+        let position_type = match &self.encoding {
+            Encoding::Plain => FieldType::F64,
+            Encoding::ScaledToCube(_, _, pos_enc) => match pos_enc {
+                PositionEncoding::Uint8 => FieldType::U8,
+                PositionEncoding::Uint16 => FieldType::U16,
+                PositionEncoding::Float32 => FieldType::F32,
+                PositionEncoding::Float64 => FieldType::F64,
+            },
+        };
+        let fields: Vec<_> = ["x", "y", "z"]
+            .iter()
+            .map(|axis| (*axis, position_type, 1))
+            .chain(fields.iter().cloned())
+            .collect();
+        let line = |key: &str, value: &dyn Fn(&(&str, FieldType, usize)) -> String| {
+            let values: Vec<String> = fields.iter().map(value).collect();
+            format!("{} {}\n", key, values.join(" "))
+        };
+        let header = [
+            "# .PCD v0.7 - Point Cloud Data file format\nVERSION 0.7\n".to_string(),
+            line("FIELDS", &|(name, _, _)| name.to_string()),
+            line("SIZE", &|(_, field_type, _)| field_type.size().to_string()),
+            line("TYPE", &|(_, field_type, _)| {
+                field_type.to_header().0.to_string()
+            }),
+            line("COUNT", &|(_, _, count)| count.to_string()),
+        ]
+        .concat();
+        self.writer.write_all(header.as_bytes())?;
+        let num_points_placeholder = "0".repeat(NUM_POINTS_WIDTH);
+        for (key, rest) in &[
+            ("WIDTH ", "\nHEIGHT 1\nVIEWPOINT 0 0 0 1 0 0 0\n"),
+            ("POINTS ", "\n"),
+        ] {
+            self.writer.write_all(key.as_bytes())?;
+            self.num_points_locations
+                .push((self.writer.bytes_written(), NUM_POINTS_WIDTH));
+            self.writer.write_all(num_points_placeholder.as_bytes())?;
+            self.writer.write_all(rest.as_bytes())?;
+        }
+        self.writer.write_all(b"DATA binary\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const BATCH_SIZE: usize = 2;
+
+    fn read_all(path: &Path) -> Vec<PointsBatch> {
+        PcdIterator::from_file(path, BATCH_SIZE).unwrap().collect()
+    }
+
+    #[test]
+    fn test_read_ascii() {
+        let tmp_dir = TempDir::new("test_read_ascii").unwrap();
+        let path = tmp_dir.path().join("points.pcd");
+        // 4286611456 is 0xff808000, i.e. the color (128, 128, 0) with an opaque alpha.
+        std::fs::write(
+            &path,
+            "# .PCD v0.7 - Point Cloud Data file format\n\
+             VERSION 0.7\n\
+             FIELDS x y z _ intensity rgba\n\
+             SIZE 4 4 4 1 4 4\n\
+             TYPE F F F U F U\n\
+             COUNT 1 1 1 2 1 1\n\
+             WIDTH 3\n\
+             HEIGHT 1\n\
+             VIEWPOINT 0 0 0 1 0 0 0\n\
+             POINTS 3\n\
+             DATA ascii\n\
+             1 2 3 0 0 0.5 4286611456\n\
+             nan nan nan 0 0 0 0\n\
+             -1.5 0 10 0 0 2 0\n",
+        )
+        .unwrap();
+        let iterator = PcdIterator::from_file(&path, BATCH_SIZE).unwrap();
+        assert_eq!(iterator.num_points(), 3);
+        let batches: Vec<PointsBatch> = iterator.collect();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(
+            batches[0].position,
+            vec![Point3::new(1., 2., 3.), Point3::new(-1.5, 0., 10.)]
+        );
+        let color: &Vec<Vector3<u8>> = batches[0].get_attribute_vec("color").unwrap();
+        assert_eq!(color, &vec![Vector3::new(128, 128, 0), Vector3::zeros()]);
+        let intensity: &Vec<f32> = batches[0].get_attribute_vec("intensity").unwrap();
+        assert_eq!(intensity, &vec![0.5, 2.]);
+        // The float 2.3509886e-38 has the bits 0x00ffffff.
+        let white = unpack_color(FieldType::F32, &RawValues::Ascii(&["2.3509886e-38"])).unwrap();
+        assert_eq!(white, Vector3::new(255, 255, 255));
+    }
+
+    #[test]
+    fn test_pcd_read_write() {
+        let tmp_dir = TempDir::new("test_pcd_read_write").unwrap();
+        let path = tmp_dir.path().join("points.pcd");
+        let batch = PointsBatch {
+            position: vec![Point3::new(1., 2., 3.), Point3::new(-4., 5.5, 6.)],
+            attributes: vec![
+                (
+                    "color".to_string(),
+                    AttributeData::U8Vec3(vec![Vector3::new(255, 0, 10), Vector3::new(1, 2, 3)]),
+                ),
+                ("intensity".to_string(), AttributeData::F32(vec![0.25, 7.])),
+                (
+                    "normal".to_string(),
+                    AttributeData::F64Vec3(vec![Vector3::x(), Vector3::z()]),
+                ),
+                ("label".to_string(), AttributeData::U16(vec![3, 65535])),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        {
+            let mut writer = PcdNodeWriter::new(&path, Encoding::Plain, OpenMode::Truncate);
+            NodeWriter::<PointsBatch>::write(&mut writer, &batch).unwrap();
+        }
+        {
+            let mut writer = PcdNodeWriter::new(&path, Encoding::Plain, OpenMode::Append);
+            NodeWriter::<PointsBatch>::write(&mut writer, &batch).unwrap();
+        }
+        let batches = read_all(&path);
+        assert_eq!(batches.len(), 2);
+        for read in &batches {
+            assert_eq!(read.position, batch.position);
+            assert_eq!(read.attributes.len(), batch.attributes.len());
+            for (name, data) in &batch.attributes {
+                assert_eq!(
+                    format!("{:?}", read.attributes[name]),
+                    format!("{:?}", data)
+                );
+            }
+        }
+    }
+}
@@ -13,20 +13,67 @@
 // limitations under the License.
 
 use crate::color;
+use crate::data_provider::NodeBytes;
 use crate::errors::*;
+use crate::labels::{run_length_encode, CLASSIFICATION_ATTRIBUTE};
 use crate::read_write::{
     decode, fixpoint_decode, AttributeReader, DataWriter, Encoding, NodeWriter, OpenMode,
     PositionEncoding, WriteEncoded, WriteLE,
 };
 use crate::{attribute_extension, AttributeData, AttributeDataType, Point, PointsBatch};
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use nalgebra::{Point3, Vector3};
 use std::collections::{BTreeMap, HashMap};
 use std::io::{self, BufReader, ErrorKind, Read};
 use std::path::PathBuf;
 
+/// Where the positions of a node are read from.
+enum PositionSource {
+    Reader(BufReader<Box<dyn Read + Send>>),
+    /// The position file in memory and the offset of the next point in it.
+    Bytes(NodeBytes, usize),
+}
+
+impl Read for PositionSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            PositionSource::Reader(reader) => reader.read(buf),
+            PositionSource::Bytes(bytes, offset) => {
+                let num_read = (&bytes.as_ref()[*offset..]).read(buf)?;
+                *offset += num_read;
+                Ok(num_read)
+            }
+        }
+    }
+}
+
+/// Decodes `num_points` positions of `stride` bytes each from the start of `bytes` into
+/// `positions`, computing each coordinate of a point from its bytes with `coordinate`. Returns the
+/// number of decoded bytes.
+fn decode_positions<F>(
+    bytes: &[u8],
+    stride: usize,
+    num_points: usize,
+    positions: &mut Vec<Point3<f64>>,
+    coordinate: F,
+) -> io::Result<usize>
+where
+    F: Fn(&[u8], usize) -> f64,
+{
+    let len = stride * num_points;
+    let bytes = bytes
+        .get(..len)
+        .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "Position file is too short"))?;
+    positions.extend(
+        bytes
+            .chunks_exact(stride)
+            .map(|xyz| Point3::new(coordinate(xyz, 0), coordinate(xyz, 1), coordinate(xyz, 2))),
+    );
+    Ok(len)
+}
+
 pub struct RawNodeReader {
-    xyz_reader: BufReader<Box<dyn Read + Send>>,
+    xyz_reader: PositionSource,
     attribute_readers: HashMap<String, AttributeReader>,
     encoding: Encoding,
 }
@@ -130,90 +177,17 @@ impl RawNodeReader {
             attributes: BTreeMap::new(),
         };
 
-        match self.encoding {
-            Encoding::Plain => (0..num_points).try_for_each(|_| -> io::Result<()> {
-                let x = self.xyz_reader.read_f64::<LittleEndian>()?;
-                let y = self.xyz_reader.read_f64::<LittleEndian>()?;
-                let z = self.xyz_reader.read_f64::<LittleEndian>()?;
-                batch.position.push(Point3::new(x, y, z));
-                Ok(())
-            })?,
-            Encoding::ScaledToCube(min, edge_length, ref pos) => match pos {
-                PositionEncoding::Uint8 => (0..num_points).try_for_each(|_| -> io::Result<()> {
-                    let x = fixpoint_decode(self.xyz_reader.read_u8()?, min.x, edge_length);
-                    let y = fixpoint_decode(self.xyz_reader.read_u8()?, min.y, edge_length);
-                    let z = fixpoint_decode(self.xyz_reader.read_u8()?, min.z, edge_length);
-                    batch.position.push(Point3::new(x, y, z));
-                    Ok(())
-                })?,
-
-                PositionEncoding::Uint16 => {
-                    (0..num_points).try_for_each(|_| -> io::Result<()> {
-                        let x = fixpoint_decode(
-                            self.xyz_reader.read_u16::<LittleEndian>()?,
-                            min.x,
-                            edge_length,
-                        );
-                        let y = fixpoint_decode(
-                            self.xyz_reader.read_u16::<LittleEndian>()?,
-                            min.y,
-                            edge_length,
-                        );
-                        let z = fixpoint_decode(
-                            self.xyz_reader.read_u16::<LittleEndian>()?,
-                            min.z,
-                            edge_length,
-                        );
-                        batch.position.push(Point3::new(x, y, z));
-                        Ok(())
-                    })?
-                }
-
-                PositionEncoding::Float32 => {
-                    (0..num_points).try_for_each(|_| -> io::Result<()> {
-                        let x = decode(
-                            self.xyz_reader.read_f32::<LittleEndian>()?,
-                            min.x,
-                            edge_length,
-                        );
-                        let y = decode(
-                            self.xyz_reader.read_f32::<LittleEndian>()?,
-                            min.y,
-                            edge_length,
-                        );
-                        let z = decode(
-                            self.xyz_reader.read_f32::<LittleEndian>()?,
-                            min.z,
-                            edge_length,
-                        );
-                        batch.position.push(Point3::new(x, y, z));
-                        Ok(())
-                    })?
-                }
-
-                PositionEncoding::Float64 => {
-                    (0..num_points).try_for_each(|_| -> io::Result<()> {
-                        let x = decode(
-                            self.xyz_reader.read_f64::<LittleEndian>()?,
-                            min.x,
-                            edge_length,
-                        );
-                        let y = decode(
-                            self.xyz_reader.read_f64::<LittleEndian>()?,
-                            min.y,
-                            edge_length,
-                        );
-                        let z = decode(
-                            self.xyz_reader.read_f64::<LittleEndian>()?,
-                            min.z,
-                            edge_length,
-                        );
-                        batch.position.push(Point3::new(x, y, z));
-                        Ok(())
-                    })?
-                }
-            },
-        };
+        match &mut self.xyz_reader {
+            PositionSource::Bytes(bytes, offset) => {
+                *offset += Self::decode_positions_from_bytes(
+                    &bytes.as_ref()[*offset..],
+                    &self.encoding,
+                    num_points,
+                    &mut batch.position,
+                )?;
+            }
+            PositionSource::Reader(_) => self.read_positions(num_points, &mut batch.position)?,
+        }
 
         // TODO(nnmm): Implement ReadLE trait and rewrite this section with a macro
         self.attribute_readers.iter_mut().try_for_each(
@@ -343,12 +317,141 @@ impl RawNodeReader {
         }
     }
 
+    fn read_positions(
+        &mut self,
+        num_points: usize,
+        positions: &mut Vec<Point3<f64>>,
+    ) -> io::Result<()> {
+        match self.encoding {
+            Encoding::Plain => (0..num_points).try_for_each(|_| -> io::Result<()> {
+                let x = self.xyz_reader.read_f64::<LittleEndian>()?;
+                let y = self.xyz_reader.read_f64::<LittleEndian>()?;
+                let z = self.xyz_reader.read_f64::<LittleEndian>()?;
+                positions.push(Point3::new(x, y, z));
+                Ok(())
+            })?,
+            Encoding::ScaledToCube(min, edge_length, ref pos) => match pos {
+                PositionEncoding::Uint8 => (0..num_points).try_for_each(|_| -> io::Result<()> {
+                    let x = fixpoint_decode(self.xyz_reader.read_u8()?, min.x, edge_length);
+                    let y = fixpoint_decode(self.xyz_reader.read_u8()?, min.y, edge_length);
+                    let z = fixpoint_decode(self.xyz_reader.read_u8()?, min.z, edge_length);
+                    positions.push(Point3::new(x, y, z));
+                    Ok(())
+                })?,
+
+                PositionEncoding::Uint16 => {
+                    (0..num_points).try_for_each(|_| -> io::Result<()> {
+                        let x = fixpoint_decode(
+                            self.xyz_reader.read_u16::<LittleEndian>()?,
+                            min.x,
+                            edge_length,
+                        );
+                        let y = fixpoint_decode(
+                            self.xyz_reader.read_u16::<LittleEndian>()?,
+                            min.y,
+                            edge_length,
+                        );
+                        let z = fixpoint_decode(
+                            self.xyz_reader.read_u16::<LittleEndian>()?,
+                            min.z,
+                            edge_length,
+                        );
+                        positions.push(Point3::new(x, y, z));
+                        Ok(())
+                    })?
+                }
+
+                PositionEncoding::Float32 => {
+                    (0..num_points).try_for_each(|_| -> io::Result<()> {
+                        let x = decode(
+                            self.xyz_reader.read_f32::<LittleEndian>()?,
+                            min.x,
+                            edge_length,
+                        );
+                        let y = decode(
+                            self.xyz_reader.read_f32::<LittleEndian>()?,
+                            min.y,
+                            edge_length,
+                        );
+                        let z = decode(
+                            self.xyz_reader.read_f32::<LittleEndian>()?,
+                            min.z,
+                            edge_length,
+                        );
+                        positions.push(Point3::new(x, y, z));
+                        Ok(())
+                    })?
+                }
+
+                PositionEncoding::Float64 => {
+                    (0..num_points).try_for_each(|_| -> io::Result<()> {
+                        let x = decode(
+                            self.xyz_reader.read_f64::<LittleEndian>()?,
+                            min.x,
+                            edge_length,
+                        );
+                        let y = decode(
+                            self.xyz_reader.read_f64::<LittleEndian>()?,
+                            min.y,
+                            edge_length,
+                        );
+                        let z = decode(
+                            self.xyz_reader.read_f64::<LittleEndian>()?,
+                            min.z,
+                            edge_length,
+                        );
+                        positions.push(Point3::new(x, y, z));
+                        Ok(())
+                    })?
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Like 'read_positions', but from the position file in memory. Returns the number of
+    /// decoded bytes.
+    fn decode_positions_from_bytes(
+        bytes: &[u8],
+        encoding: &Encoding,
+        num_points: usize,
+        positions: &mut Vec<Point3<f64>>,
+    ) -> io::Result<usize> {
+        match *encoding {
+            Encoding::Plain => decode_positions(bytes, 24, num_points, positions, |xyz, i| {
+                LittleEndian::read_f64(&xyz[8 * i..])
+            }),
+            Encoding::ScaledToCube(min, edge_length, ref pos) => match pos {
+                PositionEncoding::Uint8 => {
+                    decode_positions(bytes, 3, num_points, positions, |xyz, i| {
+                        fixpoint_decode(xyz[i], min[i], edge_length)
+                    })
+                }
+                PositionEncoding::Uint16 => {
+                    decode_positions(bytes, 6, num_points, positions, |xyz, i| {
+                        fixpoint_decode(LittleEndian::read_u16(&xyz[2 * i..]), min[i], edge_length)
+                    })
+                }
+                PositionEncoding::Float32 => {
+                    decode_positions(bytes, 12, num_points, positions, |xyz, i| {
+                        decode(LittleEndian::read_f32(&xyz[4 * i..]), min[i], edge_length)
+                    })
+                }
+                PositionEncoding::Float64 => {
+                    decode_positions(bytes, 24, num_points, positions, |xyz, i| {
+                        decode(LittleEndian::read_f64(&xyz[8 * i..]), min[i], edge_length)
+                    })
+                }
+            },
+        }
+    }
+
     pub fn new(
         xyz_reader: Box<dyn Read + Send>,
         attribute_readers: HashMap<String, AttributeReader>,
         encoding: Encoding,
     ) -> Result<Self> {
-        let xyz_reader = BufReader::new(xyz_reader);
+        let xyz_reader = PositionSource::Reader(BufReader::new(xyz_reader));
 
         Ok(Self {
             xyz_reader,
@@ -356,6 +459,19 @@ impl RawNodeReader {
             encoding,
         })
     }
+
+    /// Like 'new', but decodes the positions straight from the `xyz_bytes` of their file.
+    pub fn from_position_bytes(
+        xyz_bytes: NodeBytes,
+        attribute_readers: HashMap<String, AttributeReader>,
+        encoding: Encoding,
+    ) -> Result<Self> {
+        Ok(Self {
+            xyz_reader: PositionSource::Bytes(xyz_bytes, 0),
+            attribute_readers,
+            encoding,
+        })
+    }
 }
 
 pub struct RawNodeWriter {
@@ -384,8 +500,12 @@ impl NodeWriter<PointsBatch> for RawNodeWriter {
             }
         }
 
-        for (i, data) in p.attributes.values().enumerate() {
-            data.write_le(&mut self.attribute_writers[i])?;
+        for (i, (name, data)) in p.attributes.iter().enumerate() {
+            if name == CLASSIFICATION_ATTRIBUTE {
+                run_length_encode(data, &mut self.attribute_writers[i])?;
+            } else {
+                data.write_le(&mut self.attribute_writers[i])?;
+            }
         }
 
         Ok(())
@@ -1,8 +1,11 @@
+use crate::coordinates::CoordinateSystem;
 use crate::data_provider::DataProvider;
 use crate::errors::*;
 use crate::geometry::Aabb;
 use crate::iterator::{PointCloud, PointLocation};
+use crate::labels::LabelDictionary;
 use crate::math::{ConvexPolyhedron, FromPoint3};
+use crate::metadata::Metadata;
 use crate::proto;
 use crate::read_write::{Encoding, NodeIterator};
 use crate::{AttributeDataType, PointCloudMeta, CURRENT_VERSION};
@@ -38,6 +41,8 @@ pub struct S2Meta {
     cells: FnvHashMap<CellID, S2CellMeta>,
     attribute_data_types: HashMap<String, AttributeDataType>,
     bounding_box: Aabb,
+    label_dictionary: Option<LabelDictionary>,
+    metadata: Metadata,
 }
 
 impl PointCloudMeta for S2Meta {
@@ -56,9 +61,24 @@ impl S2Meta {
             cells,
             attribute_data_types,
             bounding_box,
+            label_dictionary: None,
+            metadata: Metadata::default(),
         }
     }
 
+    /// Adds the names of the values of the classification attribute.
+    pub fn set_label_dictionary(&mut self, label_dictionary: LabelDictionary) {
+        self.label_dictionary = Some(label_dictionary);
+    }
+
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = metadata;
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
     pub fn iter_attr_with_xyz(&self) -> impl Iterator<Item = (&str, AttributeDataType)> {
         self.attribute_data_types
             .iter()
@@ -101,6 +121,11 @@ impl S2Meta {
             attributes_meta,
         ));
         meta.set_s2(s2_meta);
+        meta.set_coordinate_system(CoordinateSystem::Ecef.to_proto());
+        if let Some(label_dictionary) = &self.label_dictionary {
+            meta.set_label_dictionary(label_dictionary.to_proto());
+        }
+        self.metadata.set_in_meta_proto(&mut meta);
         meta
     }
 
@@ -141,10 +166,15 @@ impl S2Meta {
             attribute_data_types.insert(attr.name.to_owned(), attr_type);
         }
 
+        let label_dictionary = LabelDictionary::from_meta_proto(&meta_proto)?;
+        let metadata = Metadata::from_meta_proto(&meta_proto);
+
         Ok(S2Meta {
             cells,
             attribute_data_types,
             bounding_box,
+            label_dictionary,
+            metadata,
         })
     }
 
@@ -165,6 +195,16 @@ impl PointCloud for S2Cells {
             PointLocation::Frustum(frustum) => self.cells_in_convex_polyhedron(frustum),
             PointLocation::S2Cells(cell_union) => self.cells_intersecting_region(cell_union),
             PointLocation::WebMercatorRect(wmr) => self.cells_in_convex_polyhedron(wmr),
+            // Conservative, the points are culled exactly when streaming them.
+            PointLocation::Sphere(sphere) => {
+                self.cells_in_convex_polyhedron(&sphere.bounding_box())
+            }
+            PointLocation::Capsule(capsule) => {
+                self.cells_in_convex_polyhedron(&capsule.bounding_box())
+            }
+            PointLocation::PolygonPrism(prism) => {
+                self.cells_in_convex_polyhedron(&prism.bounding_box())
+            }
         }
     }
 
@@ -190,9 +230,26 @@ impl PointCloud for S2Cells {
         Ok(node_iterator)
     }
 
+    fn num_points_in_node(&self, node_id: Self::Id) -> usize {
+        self.meta.cells[&node_id].num_points as usize
+    }
+
     fn bounding_box(&self) -> &Aabb {
         &self.meta.bounding_box
     }
+
+    /// The cells are defined on the sphere, so the points are always in ECEF.
+    fn coordinate_system(&self) -> Option<CoordinateSystem> {
+        Some(CoordinateSystem::Ecef)
+    }
+
+    fn label_dictionary(&self) -> Option<&LabelDictionary> {
+        self.meta.label_dictionary.as_ref()
+    }
+
+    fn metadata(&self) -> Option<&Metadata> {
+        Some(&self.meta.metadata)
+    }
 }
 
 impl S2Cells {
@@ -215,6 +272,10 @@ impl S2Cells {
         self.meta.to_proto()
     }
 
+    pub fn meta(&self) -> &S2Meta {
+        &self.meta
+    }
+
     /// Returns all cells that intersect this convex polyhedron
     fn cells_in_convex_polyhedron<T>(&self, poly: &T) -> Vec<CellID>
     where
@@ -0,0 +1,392 @@
+//! Detection of planes in the points of a query with RANSAC (Fischler and Bolles, "Random sample
+//! consensus", 1981). The points are streamed once to draw a sample of each node. The candidate
+//! planes are spanned by three points of the same node, which are close to each other and so
+//! likely on the same surface, and they are scored by their inliers among the samples of all
+//! nodes. A second pass can mark the inliers of the detected planes among all points.
+
+use crate::errors::*;
+use crate::iterator::{ParallelIterator, PointCloud, PointQuery};
+use crate::{PointsBatch, NUM_POINTS_PER_BATCH};
+use nalgebra::{Matrix3, Point3, SymmetricEigen, Unit, Vector3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator as _};
+
+/// The points `p` with `normal.dot(p) == offset`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plane {
+    normal: Unit<Vector3<f64>>,
+    offset: f64,
+}
+
+impl Plane {
+    pub fn new(normal: Unit<Vector3<f64>>, offset: f64) -> Self {
+        Plane { normal, offset }
+    }
+
+    /// The plane through three points, None if they are on a line.
+    pub fn through_points(a: &Point3<f64>, b: &Point3<f64>, c: &Point3<f64>) -> Option<Self> {
+        let normal = Unit::try_new((b - a).cross(&(c - a)), f64::EPSILON)?;
+        Some(Plane::new(normal, normal.dot(&a.coords)))
+    }
+
+    /// The plane with the least squared distances to `points`, None if there are fewer than three.
+    pub fn fit(points: &[Point3<f64>]) -> Option<Self> {
+        if points.len() < 3 {
+            return None;
+        }
+        let mean = points
+            .iter()
+            .fold(Vector3::zeros(), |sum, p| sum + p.coords)
+            / points.len() as f64;
+        let covariance = points.iter().fold(Matrix3::zeros(), |sum, p| {
+            let d = p.coords - mean;
+            sum + d * d.transpose()
+        });
+        let eigen = SymmetricEigen::new(covariance);
+        let (smallest, _) = eigen
+            .eigenvalues
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())?;
+        let normal = Unit::new_normalize(eigen.eigenvectors.column(smallest).into_owned());
+        Some(Plane::new(normal, normal.dot(&mean)))
+    }
+
+    pub fn normal(&self) -> &Unit<Vector3<f64>> {
+        &self.normal
+    }
+
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// The distance of `p` from the plane, positive on the side the normal points to.
+    pub fn signed_distance(&self, p: &Point3<f64>) -> f64 {
+        self.normal.dot(&p.coords) - self.offset
+    }
+}
+
+/// A plane found by 'PlaneDetection::detect'.
+#[derive(Clone, Debug)]
+pub struct DetectedPlane {
+    /// Fitted to the sampled inliers.
+    pub plane: Plane,
+    /// The number of points within the distance threshold, estimated from the samples of each
+    /// node.
+    pub num_inliers: usize,
+}
+
+/// The points sampled from a node, see 'sample_nodes'.
+#[derive(Clone, Debug, Default)]
+pub struct NodeSample {
+    pub points: Vec<Point3<f64>>,
+    /// The number of matching points of the node, which the samples stand for.
+    pub num_points: usize,
+}
+
+/// The parameters of the detection. Distances are in the unit of the positions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlaneDetection {
+    /// Points this close to a plane are its inliers.
+    pub distance_threshold: f64,
+    /// The number of candidates tried for each plane.
+    pub num_iterations: usize,
+    /// The most points sampled from each node. Each candidate is scored against all samples.
+    pub max_samples_per_node: usize,
+    /// Planes with a smaller fraction of all points as inliers are not reported.
+    pub min_inlier_fraction: f64,
+    /// The planes are detected one after the other, each in the points left by the previous.
+    pub max_planes: usize,
+    pub seed: u64,
+}
+
+impl Default for PlaneDetection {
+    fn default() -> Self {
+        PlaneDetection {
+            distance_threshold: 0.1,
+            num_iterations: 500,
+            max_samples_per_node: 200,
+            min_inlier_fraction: 0.05,
+            max_planes: 10,
+            seed: 0,
+        }
+    }
+}
+
+/// Draws up to `max_samples_per_node` of the points matching `query` from each node, uniformly
+/// by reservoir sampling. The nodes are read in parallel. The points are in the output frame of
+/// the query.
+pub fn sample_nodes<C: PointCloud>(
+    point_cloud: &C,
+    query: &PointQuery,
+    max_samples_per_node: usize,
+    seed: u64,
+) -> Result<Vec<NodeSample>> {
+    point_cloud
+        .nodes_for_query(query)
+        .into_par_iter()
+        .enumerate()
+        .map(|(i, node_id)| {
+            // Seeded per node, so that the samples do not depend on the order of the threads.
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(i as u64));
+            let mut sample = NodeSample::default();
+            point_cloud.stream_points_for_query_in_node(
+                query,
+                node_id,
+                NUM_POINTS_PER_BATCH,
+                |batch| {
+                    for p in &batch.position {
+                        sample.num_points += 1;
+                        if sample.points.len() < max_samples_per_node {
+                            sample.points.push(*p);
+                        } else {
+                            let j = rng.gen_range(0, sample.num_points);
+                            if j < max_samples_per_node {
+                                sample.points[j] = *p;
+                            }
+                        }
+                    }
+                    Ok(())
+                },
+            )?;
+            Ok(sample)
+        })
+        .collect()
+}
+
+impl PlaneDetection {
+    /// Detects planes in the points of `point_cloud` matching `query`, in its output frame.
+    pub fn detect<C: PointCloud>(
+        &self,
+        point_cloud: &C,
+        query: &PointQuery,
+    ) -> Result<Vec<DetectedPlane>> {
+        let samples = sample_nodes(point_cloud, query, self.max_samples_per_node, self.seed)?;
+        Ok(self.detect_in_samples(samples))
+    }
+
+    /// Detects planes in the samples of the nodes, the largest first.
+    pub fn detect_in_samples(&self, mut samples: Vec<NodeSample>) -> Vec<DetectedPlane> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let total_num_points: usize = samples.iter().map(|sample| sample.num_points).sum();
+        let min_inliers = self.min_inlier_fraction * total_num_points as f64;
+        let mut planes = Vec::new();
+        while planes.len() < self.max_planes {
+            let plane = match self.best_candidate(&samples, &mut rng) {
+                Some(plane) => plane,
+                None => break,
+            };
+            // The candidate only goes through three points, its fit to all inliers is better.
+            let inliers: Vec<Point3<f64>> = samples
+                .iter()
+                .flat_map(|sample| &sample.points)
+                .filter(|p| self.is_inlier(&plane, p))
+                .cloned()
+                .collect();
+            let plane = Plane::fit(&inliers).unwrap_or(plane);
+            let num_inliers = self.num_inliers(&plane, &samples);
+            if num_inliers < min_inliers.max(1.) {
+                break;
+            }
+            for sample in &mut samples {
+                let num_sampled = sample.points.len();
+                sample.points.retain(|p| !self.is_inlier(&plane, p));
+                // The remaining samples stand for the remaining points.
+                if num_sampled > 0 {
+                    sample.num_points = (sample.num_points as f64 * sample.points.len() as f64
+                        / num_sampled as f64)
+                        .round() as usize;
+                }
+            }
+            planes.push(DetectedPlane {
+                plane,
+                num_inliers: num_inliers.round() as usize,
+            });
+        }
+        planes
+    }
+
+    fn is_inlier(&self, plane: &Plane, p: &Point3<f64>) -> bool {
+        plane.signed_distance(p).abs() <= self.distance_threshold
+    }
+
+    /// The estimated number of points that are inliers of `plane`: each sampled inlier counts
+    /// for the points of its node that it stands for.
+    fn num_inliers(&self, plane: &Plane, samples: &[NodeSample]) -> f64 {
+        samples
+            .iter()
+            .filter(|sample| !sample.points.is_empty())
+            .map(|sample| {
+                let num_sampled_inliers = sample
+                    .points
+                    .iter()
+                    .filter(|p| self.is_inlier(plane, p))
+                    .count();
+                num_sampled_inliers as f64 * sample.num_points as f64 / sample.points.len() as f64
+            })
+            .sum()
+    }
+
+    /// The candidate with the most inliers, spanned by points of the same node. The nodes are
+    /// drawn by the number of points they stand for. None if no node has three samples.
+    fn best_candidate(&self, samples: &[NodeSample], rng: &mut StdRng) -> Option<Plane> {
+        let candidates: Vec<&NodeSample> = samples
+            .iter()
+            .filter(|sample| sample.points.len() >= 3 && sample.num_points > 0)
+            .collect();
+        let total_weight: usize = candidates.iter().map(|sample| sample.num_points).sum();
+        if total_weight == 0 {
+            return None;
+        }
+        let mut best: Option<(f64, Plane)> = None;
+        for _ in 0..self.num_iterations {
+            let mut weight = rng.gen_range(0, total_weight);
+            let sample = candidates
+                .iter()
+                .find(|sample| {
+                    if weight < sample.num_points {
+                        return true;
+                    }
+                    weight -= sample.num_points;
+                    false
+                })
+                .unwrap();
+            let n = sample.points.len();
+            let (i, j, k) = (
+                rng.gen_range(0, n),
+                rng.gen_range(0, n),
+                rng.gen_range(0, n),
+            );
+            if i == j || j == k || i == k {
+                continue;
+            }
+            let plane = match Plane::through_points(
+                &sample.points[i],
+                &sample.points[j],
+                &sample.points[k],
+            ) {
+                Some(plane) => plane,
+                None => continue,
+            };
+            let num_inliers = self.num_inliers(&plane, samples);
+            if best
+                .as_ref()
+                .is_none_or(|(best_inliers, _)| num_inliers > *best_inliers)
+            {
+                best = Some((num_inliers, plane));
+            }
+        }
+        best.map(|(_, plane)| plane)
+    }
+}
+
+/// The index of the plane that `p` is closest to, if it is within `distance_threshold` of it.
+pub fn closest_plane(planes: &[Plane], p: &Point3<f64>, distance_threshold: f64) -> Option<usize> {
+    planes
+        .iter()
+        .map(|plane| plane.signed_distance(p).abs())
+        .enumerate()
+        .filter(|(_, distance)| *distance <= distance_threshold)
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+        .map(|(i, _)| i)
+}
+
+/// Calls `func` with the batches of points of `point_cloud` matching `query` and, for each
+/// point, the index of the plane whose inlier it is, see 'closest_plane'.
+pub fn for_each_plane_mask<C, F>(
+    point_cloud: &C,
+    query: &PointQuery,
+    planes: &[Plane],
+    distance_threshold: f64,
+    mut func: F,
+) -> Result<()>
+where
+    C: PointCloud,
+    F: FnMut(PointsBatch, Vec<Option<usize>>) -> Result<()>,
+{
+    let num_threads = num_cpus::get();
+    ParallelIterator::new(
+        std::slice::from_ref(point_cloud),
+        query,
+        NUM_POINTS_PER_BATCH,
+        num_threads,
+        num_threads,
+    )
+    .try_for_each_batch(|batch| {
+        let mask = batch
+            .position
+            .iter()
+            .map(|p| closest_plane(planes, p, distance_threshold))
+            .collect();
+        func(batch, mask)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_planes() {
+        // A floor of 20 m x 20 m at z = 0 and a wall at x = 5, with some clutter above the floor,
+        // split into nodes of 5 m x 5 m.
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut samples: Vec<NodeSample> = (0..16).map(|_| NodeSample::default()).collect();
+        let mut add = |p: Point3<f64>| {
+            let node = (p.x / 5.).floor().clamp(0., 3.) as usize * 4
+                + (p.y / 5.).floor().clamp(0., 3.) as usize;
+            samples[node].points.push(p);
+            samples[node].num_points += 1;
+        };
+        for _ in 0..4000 {
+            let noise = rng.gen_range(-0.02, 0.02);
+            add(Point3::new(
+                rng.gen_range(0., 20.),
+                rng.gen_range(0., 20.),
+                noise,
+            ));
+        }
+        for _ in 0..1000 {
+            let noise = rng.gen_range(-0.02, 0.02);
+            add(Point3::new(
+                5. + noise,
+                rng.gen_range(0., 20.),
+                rng.gen_range(0.5, 5.),
+            ));
+        }
+        for _ in 0..500 {
+            add(Point3::new(
+                rng.gen_range(0., 20.),
+                rng.gen_range(0., 20.),
+                rng.gen_range(0.5, 5.),
+            ));
+        }
+
+        let detection = PlaneDetection {
+            max_planes: 3,
+            min_inlier_fraction: 0.1,
+            ..Default::default()
+        };
+        let planes = detection.detect_in_samples(samples);
+        assert_eq!(planes.len(), 2);
+        let (floor, wall) = (&planes[0], &planes[1]);
+        assert!(floor.plane.normal().z.abs() > 0.999);
+        assert!(floor.plane.offset().abs() < 0.01);
+        assert!((floor.num_inliers as i64 - 4000).abs() < 100);
+        assert!(wall.plane.normal().x.abs() > 0.999);
+        assert!((wall.plane.signed_distance(&Point3::new(5., 0., 0.))).abs() < 0.01);
+        assert!((wall.num_inliers as i64 - 1000).abs() < 100);
+
+        let fitted: Vec<Plane> = planes.into_iter().map(|p| p.plane).collect();
+        assert_eq!(
+            closest_plane(&fitted, &Point3::new(1., 1., 0.), 0.1),
+            Some(0)
+        );
+        assert_eq!(
+            closest_plane(&fitted, &Point3::new(5., 1., 3.), 0.1),
+            Some(1)
+        );
+        assert_eq!(closest_plane(&fitted, &Point3::new(1., 1., 3.), 0.1), None);
+    }
+}
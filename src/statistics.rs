@@ -0,0 +1,199 @@
+//! Summaries of the points matching a query, see 'PointCloud::statistics'.
+
+use crate::geometry::Aabb;
+use crate::{AttributeData, PointsBatch};
+use std::collections::BTreeMap;
+
+/// Running statistics of a sequence of values, which can be merged, so that they can be computed
+/// in parallel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueStatistics {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    mean: f64,
+    /// The sum of squared differences from the mean.
+    m2: f64,
+}
+
+impl Default for ValueStatistics {
+    fn default() -> Self {
+        ValueStatistics {
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.,
+            m2: 0.,
+        }
+    }
+}
+
+impl ValueStatistics {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn merge(&mut self, other: &ValueStatistics) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        let (n_self, n_other) = (self.count as f64, other.count as f64);
+        let n = n_self + n_other;
+        let delta = other.mean - self.mean;
+        self.mean += delta * n_other / n;
+        self.m2 += other.m2 + delta * delta * n_self * n_other / n;
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// The mean of the values, NaN if there are none.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            f64::NAN
+        } else {
+            self.mean
+        }
+    }
+
+    /// The population standard deviation of the values, NaN if there are none.
+    pub fn stddev(&self) -> f64 {
+        (self.m2 / self.count as f64).sqrt()
+    }
+}
+
+/// Statistics of a set of points.
+#[derive(Debug, Clone, Default)]
+pub struct PointStatistics {
+    pub num_points: u64,
+    /// The bounding box of the points, None if there are none.
+    pub bounding_box: Option<Aabb>,
+    /// For every attribute the statistics of each of its components, e.g. three for colors.
+    pub attributes: BTreeMap<String, Vec<ValueStatistics>>,
+}
+
+fn add_values(statistics: &mut Vec<ValueStatistics>, data: &AttributeData) {
+    macro_rules! scalars {
+        ($data:ident) => {{
+            statistics.resize(1, ValueStatistics::default());
+            for v in $data {
+                statistics[0].add(*v as f64);
+            }
+        }};
+    }
+    macro_rules! vectors {
+        ($data:ident) => {{
+            statistics.resize(3, ValueStatistics::default());
+            for v in $data {
+                for (s, c) in statistics.iter_mut().zip(v.iter()) {
+                    s.add(f64::from(*c));
+                }
+            }
+        }};
+    }
+    match data {
+        AttributeData::U8(data) => scalars!(data),
+        AttributeData::U16(data) => scalars!(data),
+        AttributeData::U32(data) => scalars!(data),
+        AttributeData::U64(data) => scalars!(data),
+        AttributeData::I8(data) => scalars!(data),
+        AttributeData::I16(data) => scalars!(data),
+        AttributeData::I32(data) => scalars!(data),
+        AttributeData::I64(data) => scalars!(data),
+        AttributeData::F32(data) => scalars!(data),
+        AttributeData::F64(data) => scalars!(data),
+        AttributeData::U8Vec3(data) => vectors!(data),
+        AttributeData::F64Vec3(data) => vectors!(data),
+    }
+}
+
+impl PointStatistics {
+    pub fn add_batch(&mut self, batch: &PointsBatch) {
+        self.num_points += batch.position.len() as u64;
+        for p in &batch.position {
+            self.bounding_box
+                .get_or_insert_with(|| Aabb::new(*p, *p))
+                .grow(*p);
+        }
+        for (name, data) in &batch.attributes {
+            add_values(self.attributes.entry(name.clone()).or_default(), data);
+        }
+    }
+
+    pub fn merge(&mut self, other: &PointStatistics) {
+        self.num_points += other.num_points;
+        if let Some(other_box) = &other.bounding_box {
+            let bounding_box = self.bounding_box.get_or_insert_with(|| other_box.clone());
+            bounding_box.grow(*other_box.min());
+            bounding_box.grow(*other_box.max());
+        }
+        for (name, other_statistics) in &other.attributes {
+            let statistics = self.attributes.entry(name.clone()).or_default();
+            statistics.resize(other_statistics.len(), ValueStatistics::default());
+            for (s, o) in statistics.iter_mut().zip(other_statistics) {
+                s.merge(o);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Point3, Vector3};
+
+    #[test]
+    fn test_merged_statistics_equal_sequential_ones() {
+        let values: Vec<f64> = (0..100).map(|i| f64::from(i * i % 17)).collect();
+        let mut all = ValueStatistics::default();
+        values.iter().for_each(|v| all.add(*v));
+        let mut merged = ValueStatistics::default();
+        for chunk in values.chunks(30) {
+            let mut part = ValueStatistics::default();
+            chunk.iter().for_each(|v| part.add(*v));
+            merged.merge(&part);
+        }
+        let mean = values.iter().sum::<f64>() / 100.;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 100.;
+        assert_eq!(merged.count, 100);
+        assert_eq!((merged.min, merged.max), (0., 16.));
+        assert!((merged.mean() - mean).abs() < 1e-9);
+        assert!((merged.stddev() - variance.sqrt()).abs() < 1e-9);
+        assert!((all.stddev() - merged.stddev()).abs() < 1e-9);
+        assert!(ValueStatistics::default().mean().is_nan());
+    }
+
+    #[test]
+    fn test_point_statistics() {
+        let batch = PointsBatch {
+            position: vec![Point3::new(0., 1., 2.), Point3::new(-1., 3., 0.)],
+            attributes: vec![(
+                "color".to_string(),
+                AttributeData::U8Vec3(vec![Vector3::new(0, 10, 20), Vector3::new(10, 10, 40)]),
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let mut statistics = PointStatistics::default();
+        statistics.add_batch(&batch);
+        statistics.merge(&PointStatistics::default());
+        assert_eq!(statistics.num_points, 2);
+        let bounding_box = statistics.bounding_box.unwrap();
+        assert_eq!(*bounding_box.min(), Point3::new(-1., 1., 0.));
+        assert_eq!(*bounding_box.max(), Point3::new(0., 3., 2.));
+        let color = &statistics.attributes["color"];
+        assert_eq!(color.len(), 3);
+        assert_eq!(color[0].mean(), 5.);
+        assert_eq!(color[1].stddev(), 0.);
+        assert_eq!(color[2].max, 40.);
+    }
+}
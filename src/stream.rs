@@ -0,0 +1,195 @@
+// Copyright 2016 Google Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The points of a query as an asynchronous stream, for callers that run on an executor, e.g.
+//! async servers or clients of remote point clouds.
+
+use crate::downsample::VoxelDownsampler;
+use crate::errors::*;
+use crate::iterator::{NodeQuery, PointCloud, PointQuery};
+use crate::read_write::NodeIterator;
+use crate::PointsBatch;
+use futures::Stream;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The result of 'PointCloud::points_in_node_async'.
+pub type NodeIteratorFuture<'a> = Pin<Box<dyn Future<Output = Result<NodeIterator>> + Send + 'a>>;
+
+/// How many nodes 'BatchStream' fetches at the same time.
+const MAX_NODES_IN_FLIGHT: usize = 8;
+
+enum Fetch<'a> {
+    Pending(NodeIteratorFuture<'a>),
+    Done(Result<NodeIterator>),
+}
+
+/// The matching points of 'PointCloud::stream_batches'. The stream keeps a few nodes ahead of
+/// the consumer in flight, in the order of 'PointCloud::nodes_for_query', and returns their
+/// points in that order. The points of a node are decoded and filtered on the polling thread, so
+/// nothing runs in the background once the stream is dropped.
+pub struct BatchStream<'a, C: PointCloud> {
+    point_cloud: &'a C,
+    query: &'a PointQuery<'a>,
+    node_query: NodeQuery<'a>,
+    batch_size: usize,
+    node_ids: std::vec::IntoIter<C::Id>,
+    fetches: VecDeque<Fetch<'a>>,
+    downsampler: Option<VoxelDownsampler>,
+    /// Matching points that do not fill a batch yet.
+    buffer: PointsBatch,
+    /// Full batches that are ready to be returned.
+    batches: VecDeque<PointsBatch>,
+    /// Set once the last batch was returned or an error ended the stream.
+    is_done: bool,
+}
+
+// The fields are never pinned, the futures of the fetches are boxed.
+impl<'a, C: PointCloud> Unpin for BatchStream<'a, C> {}
+
+impl<'a, C: PointCloud> BatchStream<'a, C> {
+    pub(crate) fn new(
+        point_cloud: &'a C,
+        query: &'a PointQuery<'a>,
+        batch_size: usize,
+    ) -> Result<Self> {
+        let node_query = NodeQuery::new(query, point_cloud.label_dictionary())?;
+        Ok(BatchStream {
+            point_cloud,
+            query,
+            node_query,
+            batch_size,
+            node_ids: point_cloud.nodes_for_query(query).into_iter(),
+            fetches: VecDeque::new(),
+            downsampler: query.downsample.map(VoxelDownsampler::new),
+            buffer: PointsBatch {
+                position: Vec::new(),
+                attributes: BTreeMap::new(),
+            },
+            batches: VecDeque::new(),
+            is_done: false,
+        })
+    }
+
+    /// Adds the matching points of a fetched node to the batches.
+    fn add_node(&mut self, node_iterator: NodeIterator) -> Result<()> {
+        let mut matching = Vec::new();
+        self.node_query.stream(self.query, node_iterator, |batch| {
+            matching.push(batch);
+            Ok(())
+        })?;
+        for batch in matching {
+            let batch = match &mut self.downsampler {
+                Some(downsampler) => match downsampler.add_batch(batch) {
+                    Some(batch) => batch,
+                    None => continue,
+                },
+                None => batch,
+            };
+            self.push(batch)?;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, mut batch: PointsBatch) -> Result<()> {
+        self.buffer.append(&mut batch)?;
+        while self.buffer.position.len() >= self.batch_size {
+            let rest = self.buffer.split_off(self.batch_size);
+            self.batches
+                .push_back(std::mem::replace(&mut self.buffer, rest));
+        }
+        Ok(())
+    }
+
+    /// Moves the points that are left to the batches, after the last node.
+    fn finish(&mut self) -> Result<()> {
+        if let Some(batch) = self.downsampler.take().and_then(VoxelDownsampler::finish) {
+            self.push(batch)?;
+        }
+        if !self.buffer.position.is_empty() {
+            let buffer = PointsBatch {
+                position: Vec::new(),
+                attributes: BTreeMap::new(),
+            };
+            self.batches
+                .push_back(std::mem::replace(&mut self.buffer, buffer));
+        }
+        Ok(())
+    }
+
+    /// Ends the stream with `err` as its last item.
+    fn fail(&mut self, err: Error) -> Poll<Option<Result<PointsBatch>>> {
+        self.is_done = true;
+        self.fetches.clear();
+        self.batches.clear();
+        Poll::Ready(Some(Err(err)))
+    }
+}
+
+impl<'a, C: PointCloud> Stream for BatchStream<'a, C> {
+    type Item = Result<PointsBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(batch) = this.batches.pop_front() {
+                return Poll::Ready(Some(Ok(batch)));
+            }
+            if this.is_done {
+                return Poll::Ready(None);
+            }
+            while this.fetches.len() < MAX_NODES_IN_FLIGHT {
+                let node_id = match this.node_ids.next() {
+                    Some(node_id) => node_id,
+                    None => break,
+                };
+                let fetch = this.point_cloud.points_in_node_async(
+                    &this.node_query.attributes,
+                    node_id,
+                    this.batch_size,
+                );
+                this.fetches.push_back(Fetch::Pending(fetch));
+            }
+            // All fetches are polled, so that they make progress at the same time.
+            for fetch in this.fetches.iter_mut() {
+                if let Fetch::Pending(future) = fetch {
+                    if let Poll::Ready(result) = future.as_mut().poll(cx) {
+                        *fetch = Fetch::Done(result);
+                    }
+                }
+            }
+            match this.fetches.pop_front() {
+                Some(Fetch::Done(Ok(node_iterator))) => {
+                    if let Err(err) = this.add_node(node_iterator) {
+                        return this.fail(err);
+                    }
+                }
+                Some(Fetch::Done(Err(err))) => return this.fail(err),
+                Some(pending) => {
+                    // The first node is still being fetched, and it has our waker.
+                    this.fetches.push_front(pending);
+                    return Poll::Pending;
+                }
+                None => {
+                    this.is_done = true;
+                    if let Err(err) = this.finish() {
+                        return this.fail(err);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use nalgebra::Isometry3;
+use object_store_provider::register_object_stores;
 use point_viewer::data_provider::DataProviderFactory;
 use xray::build_quadtree::{run, Extension};
 
@@ -14,6 +15,6 @@ impl Extension for NullExtension {
 }
 
 pub fn main() {
-    let data_provider_factory = DataProviderFactory::new();
+    let data_provider_factory = register_object_stores(DataProviderFactory::new());
     run::<NullExtension>(data_provider_factory);
 }
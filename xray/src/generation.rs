@@ -485,6 +485,7 @@ pub fn xray_from_points(
             .iter()
             .map(|(k, v)| (&k[..], *v))
             .collect(),
+        ..Default::default()
     };
     let _ = parameters
         .point_cloud_client